@@ -43,6 +43,15 @@ class CobblerAction():
         return 'no-profile'
 
 
+    # maps each system to the mac addresses of its interfaces, as json
+    def system_macs(self):
+        return json.dumps({ system['name']: [ iface['mac_address'] for iface in system['interfaces'].values() ] for system in self.cobbler.get_systems() })
+
+
+    def remove_system(self, hostname: str):
+        self.cobbler.remove_system(hostname, self.token)
+
+
     def system_exists(self, hostname: str):
         return hostname in self.system_names()
 
@@ -873,6 +873,8 @@ pub async fn import_bookings(mut session: &Server, booking_path: PathBuf) {
                     .id,
                 cifile: Vec::new(),
                 connections: Vec::new(),
+                constraints: None,
+                kernel_args: Vec::new(),
             });
         }
 
@@ -893,6 +895,7 @@ pub async fn import_bookings(mut session: &Server, booking_path: PathBuf) {
                 .expect("Expected to find lab")
                 .expect("Expected lab to exist")
                 .id,
+            parameters: vec![],
         })
         .insert(&mut transaction)
         .await
@@ -923,6 +926,7 @@ pub async fn import_bookings(mut session: &Server, booking_path: PathBuf) {
             configuration: AggregateConfiguration {
                 ipmi_username: String::new(),
                 ipmi_password: String::new(),
+                env: Default::default(),
             },
             deleted: if old_booking.booking_meta.end < Utc::now() {
                 true
@@ -952,6 +956,13 @@ pub async fn import_bookings(mut session: &Server, booking_path: PathBuf) {
                 project: Some(old_booking.booking_meta.project.clone()),
                 start: Some(old_booking.booking_meta.start),
                 end: Some(old_booking.booking_meta.end),
+                performance_tolerance: None,
+                spread: None,
+                display_name: None,
+                favorited_by: Vec::new(),
+                telemetry: false,
+                exempt_from_expiry: false,
+                ownership_override: None,
             },
         };
 
@@ -991,6 +1002,8 @@ pub async fn import_bookings(mut session: &Server, booking_path: PathBuf) {
                     .await
                     .unwrap()],
                     connections: Vec::new(),
+                    constraints: None,
+                    kernel_args: Vec::new(),
                 },
                 network_data: NewRow::new(NetworkAssignmentMap::empty())
                     .insert(&mut transaction)
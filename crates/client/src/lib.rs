@@ -20,9 +20,9 @@ mod queries;
 
 use crate::importing::*;
 use common::prelude::{
-    anyhow, config::settings, inquire::validator::Validation, itertools::Itertools,
+    anyhow, chrono, config::settings, inquire::validator::Validation, itertools::Itertools,
 };
-use dal::{new_client, AsEasyTransaction, DBTable, EasyTransaction, FKey, Importable, ID};
+use dal::{backup, new_client, AsEasyTransaction, DBTable, EasyTransaction, FKey, Importable, ID};
 use liblaas::{
     booking::make_aggregate,
     web::api::{self, BookingMetadataBlob},
@@ -40,7 +40,7 @@ use std::{fmt::Formatter, path::PathBuf, str::FromStr, time::Duration};
 use strum::IntoEnumIterator;
 use strum_macros::{Display, EnumIter, EnumString};
 use tascii::prelude::Runtime;
-use workflows::entry::DISPATCH;
+use workflows::{entry::DISPATCH, resource_management::simulator};
 
 /// Runs the cli
 #[derive(Debug, Copy, Clone)]
@@ -54,6 +54,8 @@ pub enum LiblaasStateInstruction {
 pub enum Command {
     #[strum(serialize = "Usage Data")]
     UsageData,
+    #[strum(serialize = "Simulate Capacity")]
+    SimulateCapacity,
     #[strum(serialize = "IPA Utilities")]
     IPA,
     #[strum(serialize = "Create a Booking")]
@@ -72,6 +74,8 @@ pub enum Command {
     Export,
     #[strum(serialize = "Run Migrations")]
     Migrations,
+    #[strum(serialize = "Database Backups")]
+    Backups,
     #[strum(serialize = "Restart CLI")]
     Restart,
     #[strum(serialize = "Run Tests")]
@@ -174,6 +178,8 @@ pub async fn cli_entry(
             Command::Migrations => {
                 dal::initialize().await.unwrap();
             }
+            Command::SimulateCapacity => simulate_capacity(session).await?,
+            Command::Backups => manage_backups(session).await?,
             Command::Restart => return Ok(LiblaasStateInstruction::DoNothing),
             Command::Shutdown => {
                 areyousure(session)?;
@@ -223,6 +229,135 @@ async fn modify_templates(session: &Server) {
     transaction.commit().await.unwrap();
 }
 
+/// Replays booking history against the current inventory, with any hosts the admin wants to add
+async fn simulate_capacity(mut session: &Server) -> Result<(), anyhow::Error> {
+    let days =
+        Text::new("Replay how many days of booking history? (blank for all): ").prompt(session)?;
+    let replay_since = match days.trim() {
+        "" => None,
+        d => Some(chrono::Utc::now() - chrono::Duration::days(d.parse()?)),
+    };
+
+    let mut add_hosts = std::collections::HashMap::new();
+    let added = Text::new("Hosts to add, like `flavor=2, other-flavor=-1` (blank for none): ")
+        .prompt(session)?;
+    for pair in added.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+        let (flavor, count) = pair.split_once('=').ok_or(anyhow::Error::msg(format!(
+            "{pair} is not of the form flavor=count"
+        )))?;
+        add_hosts.insert(flavor.trim().to_owned(), count.trim().parse()?);
+    }
+
+    let max_wait_hours = match Text::new("Hours a booking will wait for hosts (blank for 24): ")
+        .prompt(session)?
+        .trim()
+    {
+        "" => 24.0,
+        h => h.parse()?,
+    };
+
+    let mut client = new_client().await?;
+    let mut transaction = client.easy_transaction().await?;
+    let report = simulator::run_request(
+        &mut transaction,
+        simulator::SimulationRequest {
+            add_hosts,
+            replay_since,
+            max_wait_hours: Some(max_wait_hours),
+            ..Default::default()
+        },
+    )
+    .await?;
+    transaction.commit().await?;
+
+    writeln!(
+        session,
+        "{} bookings over {:.1} hours: {} served, {} gave up waiting, {} asked for more hosts than exist",
+        report.bookings, report.window_hours, report.served, report.rejected, report.unsatisfiable
+    )?;
+    writeln!(
+        session,
+        "Wait hours: mean {:.1}, p95 {:.1}, max {:.1}",
+        report.mean_wait_hours, report.p95_wait_hours, report.max_wait_hours
+    )?;
+
+    let mut flavors: Vec<_> = report.flavors.into_iter().collect();
+    flavors.sort_by(|a, b| a.0.cmp(&b.0));
+    for (flavor, u) in flavors {
+        writeln!(
+            session,
+            "{flavor}: {} hosts, {:.1}% utilized, peak of {} in use",
+            u.hosts,
+            u.utilization * 100.0,
+            u.peak_in_use
+        )?;
+    }
+
+    Ok(())
+}
+
+async fn manage_backups(mut session: &Server) -> Result<(), anyhow::Error> {
+    let config = settings()
+        .backup
+        .as_ref()
+        .ok_or(anyhow::Error::msg("backups are not configured"))?;
+
+    match Select::new(
+        "select an action: ",
+        vec!["take a backup now", "list backups", "restore from a backup"],
+    )
+    .prompt(session)?
+    {
+        "take a backup now" => {
+            let mut client = new_client().await?;
+            let mut transaction = client.easy_transaction().await?;
+            let info = backup::dump(config, &mut transaction).await?;
+            transaction.commit().await?;
+
+            writeln!(session, "Wrote backup to {:?}", info.path)?;
+        }
+        "list backups" => {
+            for b in backup::list_backups(config)? {
+                writeln!(
+                    session,
+                    "{} (schema version {}): {:?}",
+                    b.created, b.schema_version, b.path
+                )?;
+            }
+        }
+        "restore from a backup" => {
+            let backups = backup::list_backups(config)?;
+            let expected = backup::expected_schema_version();
+            writeln!(session, "This build expects schema version {expected}")?;
+
+            let choice = Select::new(
+                "select a backup to restore: ",
+                backups
+                    .iter()
+                    .map(|b| format!("{} (schema version {})", b.created, b.schema_version))
+                    .collect(),
+            )
+            .prompt(session)?;
+            let chosen = backups
+                .iter()
+                .find(|b| format!("{} (schema version {})", b.created, b.schema_version) == choice)
+                .unwrap();
+
+            writeln!(
+                session,
+                "This replaces ALL current data, make sure tascii has no running tasks"
+            )?;
+            areyousure(session)?;
+
+            backup::restore(chosen).await?;
+            writeln!(session, "Restored from {:?}", chosen.path)?;
+        }
+        _ => unreachable!(),
+    }
+
+    Ok(())
+}
+
 #[derive(Debug, Clone, EnumIter, EnumString, Display)]
 pub enum YesNo {
     No,
@@ -297,8 +432,12 @@ fn select_lifecyclestate(session: &Server) -> Result<LifeCycleState, anyhow::Err
     let state = Select::new(
         "Select a state for filtering aggregates:",
         vec![
+            LifeCycleState::Queued,
+            LifeCycleState::Scheduled,
             LifeCycleState::New,
             LifeCycleState::Active,
+            LifeCycleState::TearingDown,
+            LifeCycleState::Failed,
             LifeCycleState::Done,
         ],
     )
@@ -534,7 +673,20 @@ async fn create_booking(mut session: &Server) -> Result<(), anyhow::Error> {
                     .prompt(session)?
                     .as_str(),
             )?),
+            performance_tolerance: None,
+            spread: None,
+            display_name: None,
+            telemetry: None,
         },
+        owner: None,
+        // every parameter of the template takes its default
+        parameters: Default::default(),
+        env: Default::default(),
+        tags: Vec::new(),
+        constraints: Default::default(),
+        queue: false,
+        start_date: None,
+        ownership_override: None,
     };
 
     // insert booking blob into whatever db for the extra data
@@ -1,17 +1,21 @@
 use super::{areyousure, select_aggregate, select_host, select_instance, select_lifecyclestate};
 use crate::mgmt_workflows::BootToDev;
-use crate::remote::{Select, Server, Text};
+use crate::remote::{Password, Select, Server, Text};
 use common::prelude::{
     anyhow,
+    chrono::{self, Utc},
     config::{settings, Situation},
     tracing,
 };
-use dal::{new_client, AsEasyTransaction, ExistingRow, FKey, ID};
+use dal::{new_client, AsEasyTransaction, DBTable, ExistingRow, FKey, ID};
 
 use models::{
     allocator::Allocation,
-    dashboard::{Aggregate, LifeCycleState},
-    inventory::{BootTo, Host},
+    dashboard::{
+        Aggregate, AggregateSummary, AttachmentState, ExternalAttachment, HostReplacement, Image,
+        LifeCycleState, ReplacementState,
+    },
+    inventory::{BootTo, Host, HostTicket},
 };
 use notifications::{
     email::{send_to_admins_email, send_to_admins_gchat},
@@ -25,11 +29,19 @@ use tascii::prelude::Runtime;
 use workflows::{
     deploy_booking::{
         deploy_host::DeployHost,
-        notify::Notify,
+        notify::{Notify, NotifyContext},
+        replace_host::ReplaceHost,
         set_host_power_state::{PowerState, SetPower},
     },
     entry::DISPATCH,
-    resource_management::{allocator, mailbox::Mailbox},
+    inspect_host::benchmark::BenchmarkHost,
+    resource_management::{
+        allocator,
+        external::AttachExternalNetwork,
+        images::{self, Deprecation},
+        mailbox::Mailbox,
+        orphans, reconciler,
+    },
 };
 
 #[derive(Display, Clone, EnumString, EnumIter, Debug)]
@@ -56,6 +68,20 @@ pub enum Overrides {
     SetHostPowerState,
     #[strum(serialize = "Override Endpoint Hook")]
     EndpointHook,
+    #[strum(serialize = "Benchmark Host")]
+    BenchmarkHost,
+    #[strum(serialize = "Review External Attachments")]
+    ReviewExternalAttachments,
+    #[strum(serialize = "Review Host Replacements")]
+    ReviewHostReplacements,
+    #[strum(serialize = "Resolve Host Tickets")]
+    ResolveHostTickets,
+    #[strum(serialize = "Deprecate Image")]
+    DeprecateImage,
+    #[strum(serialize = "Run Reconciler")]
+    RunReconciler,
+    #[strum(serialize = "Collect Orphaned Resources")]
+    CollectOrphans,
 }
 
 pub async fn overrides(session: &Server, tascii_rt: &'static Runtime) -> Result<(), anyhow::Error> {
@@ -75,6 +101,17 @@ pub async fn overrides(session: &Server, tascii_rt: &'static Runtime) -> Result<
         Overrides::ReleaseAggregate => handle_release_aggregate(session).await,
         Overrides::BootHost => handle_boot_host(session, tascii_rt).await,
         Overrides::SendNotification => handle_send_notification(session, tascii_rt).await,
+        Overrides::BenchmarkHost => handle_benchmark_host(session, tascii_rt).await,
+        Overrides::ReviewExternalAttachments => {
+            handle_review_external_attachments(session, tascii_rt).await
+        }
+        Overrides::ReviewHostReplacements => {
+            handle_review_host_replacements(session, tascii_rt).await
+        }
+        Overrides::ResolveHostTickets => handle_resolve_host_tickets(session).await,
+        Overrides::DeprecateImage => handle_deprecate_image(session).await,
+        Overrides::RunReconciler => handle_run_reconciler(session).await,
+        Overrides::CollectOrphans => handle_collect_orphans(session).await,
     }
 }
 
@@ -252,6 +289,7 @@ async fn handle_redeploy(
         aggregate_id: agg,
         using_instance: inst,
         distribution: None,
+        resume_from: None,
     };
 
     let id = tascii_rt.enroll(task.into());
@@ -283,14 +321,26 @@ async fn handle_aggregate(mut session: &Server) -> Result<(), anyhow::Error> {
         vec![
             LifeCycleState::New,
             LifeCycleState::Active,
+            LifeCycleState::TearingDown,
+            LifeCycleState::Failed,
             LifeCycleState::Done,
         ],
     )
     .prompt(session)
     .unwrap();
 
-    agg.state = new_state;
-    agg.update(&mut transaction).await.unwrap();
+    Aggregate::force_transition(
+        &mut agg,
+        &mut transaction,
+        new_state,
+        "set by hand from the CLI",
+        None,
+    )
+    .await
+    .unwrap();
+    AggregateSummary::refresh(&mut transaction, agg.id)
+        .await
+        .unwrap();
 
     let _ = writeln!(
         session,
@@ -316,8 +366,18 @@ async fn handle_reset_aggregate(mut session: &Server) -> Result<(), anyhow::Erro
 
     areyousure(session)?;
 
-    agg.state = LifeCycleState::New;
-    agg.update(&mut transaction).await.unwrap();
+    Aggregate::force_transition(
+        &mut agg,
+        &mut transaction,
+        LifeCycleState::New,
+        "released and rerun from the CLI",
+        None,
+    )
+    .await
+    .unwrap();
+    AggregateSummary::refresh(&mut transaction, agg.id)
+        .await
+        .unwrap();
 
     allocator::Allocator::instance()
         .deallocate_aggregate(&mut transaction, agg_id)
@@ -356,8 +416,18 @@ async fn handle_release_aggregate(mut session: &Server) -> Result<(), anyhow::Er
 
     areyousure(session)?;
 
-    agg.state = LifeCycleState::Done;
-    agg.update(&mut transaction).await.unwrap();
+    Aggregate::force_transition(
+        &mut agg,
+        &mut transaction,
+        LifeCycleState::Done,
+        "released from the CLI",
+        None,
+    )
+    .await
+    .unwrap();
+    AggregateSummary::refresh(&mut transaction, agg.id)
+        .await
+        .unwrap();
 
     allocator::Allocator::instance()
         .deallocate_aggregate(&mut transaction, agg_id)
@@ -403,6 +473,200 @@ async fn handle_boot_host(
     Ok(())
 }
 
+async fn handle_benchmark_host(
+    mut session: &Server,
+    tascii_rt: &'static Runtime,
+) -> Result<(), anyhow::Error> {
+    let mut client = new_client().await.expect("Expected to connect to db");
+    let mut transaction = client
+        .easy_transaction()
+        .await
+        .expect("Transaction creation error");
+
+    let hostname = Text::new("hostname:").prompt(session).unwrap();
+    let host = Host::get_by_name(&mut transaction, hostname)
+        .await
+        .expect("no host found by that hostname")
+        .into_inner();
+
+    let task = BenchmarkHost {
+        host_id: host.id,
+        host_address: Text::new("host address (blank for the host fqdn):")
+            .prompt(session)
+            .map(|a| if a.is_empty() { host.fqdn.clone() } else { a })?,
+        username: Text::new("ssh username:").prompt(session)?,
+        passwd: Password::new("ssh password:").prompt(session)?,
+    };
+
+    let id = tascii_rt.enroll(task.into());
+    tascii_rt.set_target(id);
+
+    writeln!(session, "Enrolled benchmark task as id {id:?}")?;
+
+    transaction.commit().await?;
+    Ok(())
+}
+
+async fn handle_review_external_attachments(
+    mut session: &Server,
+    tascii_rt: &'static Runtime,
+) -> Result<(), anyhow::Error> {
+    let mut client = new_client().await.expect("Expected to connect to db");
+    let mut transaction = client
+        .easy_transaction()
+        .await
+        .expect("Transaction creation error");
+
+    let pending = ExternalAttachment::pending(&mut transaction).await?;
+    if pending.is_empty() {
+        writeln!(session, "No external attachments are waiting on review")?;
+        return Ok(());
+    }
+
+    let mut disps = Vec::new();
+    for (i, attachment) in pending.iter().enumerate() {
+        let network = attachment.network.get(&mut transaction).await?;
+        disps.push(format!(
+            "{i}: {} wants {} of agg {:?} on feed {}, because: {}",
+            attachment.requested_by,
+            network.name,
+            attachment.aggregate.into_id(),
+            attachment.feed,
+            attachment.reason
+        ));
+    }
+
+    let choice = Select::new("Select an attachment to review:", disps).prompt(session)?;
+    let index: usize = choice.split(':').next().unwrap().parse()?;
+    let mut attachment = pending.into_iter().nth(index).unwrap();
+
+    let approve = Select::new("Decision:", vec!["Approve", "Deny"]).prompt(session)? == "Approve";
+    attachment.reviewed_by = Some(Text::new("your username:").prompt(session)?);
+    attachment.updated = Utc::now();
+    if !approve {
+        attachment.state = AttachmentState::Denied;
+    }
+    attachment.update(&mut transaction).await?;
+    transaction.commit().await?;
+
+    if approve {
+        let id = tascii_rt.enroll(
+            AttachExternalNetwork {
+                attachment: attachment.id,
+            }
+            .into(),
+        );
+        tascii_rt.set_target(id);
+
+        writeln!(session, "Approved, enrolled attach task as id {id:?}")?;
+    } else {
+        writeln!(session, "Denied attachment")?;
+    }
+
+    Ok(())
+}
+
+async fn handle_review_host_replacements(
+    mut session: &Server,
+    tascii_rt: &'static Runtime,
+) -> Result<(), anyhow::Error> {
+    let mut client = new_client().await.expect("Expected to connect to db");
+    let mut transaction = client
+        .easy_transaction()
+        .await
+        .expect("Transaction creation error");
+
+    let pending = HostReplacement::pending(&mut transaction).await?;
+    if pending.is_empty() {
+        writeln!(session, "No host replacements are waiting on review")?;
+        return Ok(());
+    }
+
+    let mut disps = Vec::new();
+    for (i, replacement) in pending.iter().enumerate() {
+        let host = replacement.faulty_host.get(&mut transaction).await?;
+        disps.push(format!(
+            "{i}: {} wants {} of agg {:?} replaced, because: {}",
+            replacement.requested_by,
+            host.server_name,
+            replacement.aggregate.into_id(),
+            replacement.reason
+        ));
+    }
+
+    let choice = Select::new("Select a replacement to review:", disps).prompt(session)?;
+    let index: usize = choice.split(':').next().unwrap().parse()?;
+    let mut replacement = pending.into_iter().nth(index).unwrap();
+
+    let approve = Select::new("Decision:", vec!["Approve", "Deny"]).prompt(session)? == "Approve";
+    replacement.reviewed_by = Some(Text::new("your username:").prompt(session)?);
+    replacement.updated = Utc::now();
+    replacement.state = match approve {
+        true => ReplacementState::Replacing,
+        false => ReplacementState::Denied,
+    };
+    replacement.update(&mut transaction).await?;
+    transaction.commit().await?;
+
+    if approve {
+        let id = tascii_rt.enroll(
+            ReplaceHost {
+                replacement: replacement.id,
+            }
+            .into(),
+        );
+        tascii_rt.set_target(id);
+
+        writeln!(session, "Approved, enrolled replace task as id {id:?}")?;
+    } else {
+        writeln!(session, "Denied replacement")?;
+    }
+
+    Ok(())
+}
+
+async fn handle_resolve_host_tickets(mut session: &Server) -> Result<(), anyhow::Error> {
+    let mut client = new_client().await.expect("Expected to connect to db");
+    let mut transaction = client
+        .easy_transaction()
+        .await
+        .expect("Transaction creation error");
+
+    let unresolved = HostTicket::unresolved(&mut transaction).await?;
+    if unresolved.is_empty() {
+        writeln!(session, "No host tickets are open")?;
+        return Ok(());
+    }
+
+    let disps = unresolved
+        .iter()
+        .enumerate()
+        .map(|(i, ticket)| {
+            format!(
+                "{i}: {} {}: {}",
+                ticket.system, ticket.external_id, ticket.summary
+            )
+        })
+        .collect();
+
+    let choice =
+        Select::new("Select a ticket whose problem has been fixed:", disps).prompt(session)?;
+    let index: usize = choice.split(':').next().unwrap().parse()?;
+    let mut ticket = unresolved.into_iter().nth(index).unwrap();
+
+    ticket.resolved = Some(Utc::now());
+    ticket.update(&mut transaction).await?;
+    transaction.commit().await?;
+
+    writeln!(
+        session,
+        "Marked {} resolved, new failures of the host will open a new ticket",
+        ticket.external_id
+    )?;
+
+    Ok(())
+}
+
 async fn handle_send_notification(
     mut session: &Server,
     tascii_rt: &'static Runtime,
@@ -440,7 +704,7 @@ async fn handle_send_notification(
     let task = Notify {
         aggregate: agg,
         situation,
-        extra_context: vec![],
+        context: NotifyContext::Booking,
     };
 
     let id = tascii_rt.enroll(task.into());
@@ -451,3 +715,135 @@ async fn handle_send_notification(
     transaction.commit().await?;
     Ok(())
 }
+
+async fn handle_deprecate_image(mut session: &Server) -> Result<(), anyhow::Error> {
+    let mut client = new_client().await.expect("Expected to connect to db");
+    let mut transaction = client
+        .easy_transaction()
+        .await
+        .expect("Transaction creation error");
+
+    let mut images: Vec<Image> = Image::select()
+        .where_field("deleted")
+        .equals(false)
+        .run(&mut transaction)
+        .await?
+        .into_iter()
+        .map(|i| i.into_inner())
+        .collect();
+    transaction.commit().await?;
+    images.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let disps = images
+        .iter()
+        .enumerate()
+        .map(|(i, image)| match image.deprecated {
+            Some(d) => format!("{i}: {} (deprecated {})", image.name, d.to_rfc2822()),
+            None => format!("{i}: {}", image.name),
+        })
+        .collect();
+    let choice = Select::new("Select an image to deprecate:", disps).prompt(session)?;
+    let index: usize = choice.split(':').next().unwrap().parse()?;
+    let image = images[index].clone();
+
+    let mut replacements = vec!["none".to_owned()];
+    replacements.extend(
+        images
+            .iter()
+            .enumerate()
+            .filter(|(_, i)| i.id != image.id && i.deprecated.is_none())
+            .map(|(i, image)| format!("{i}: {}", image.name)),
+    );
+    let choice = Select::new("Select the image replacing it:", replacements).prompt(session)?;
+    let replacement = match choice.split_once(':') {
+        Some((index, _)) => Some(images[index.parse::<usize>()?].id),
+        None => None,
+    };
+
+    let days =
+        Text::new("Days until new bookings can't use it (blank for never): ").prompt(session)?;
+    let sunset = match days.trim() {
+        "" => None,
+        d => Some(Utc::now() + chrono::Duration::days(d.parse()?)),
+    };
+
+    let deprecation = Deprecation {
+        image: image.id,
+        sunset,
+        replacement,
+        note: Text::new("Note for users (changelog entry):").prompt(session)?,
+        author: Text::new("your username:").prompt(session)?,
+    };
+
+    let notified = images::deprecate(deprecation).await?;
+    writeln!(
+        session,
+        "Deprecated {}, notified {} users: {}",
+        image.name,
+        notified.len(),
+        notified.join(", ")
+    )?;
+
+    Ok(())
+}
+
+async fn handle_run_reconciler(mut session: &Server) -> Result<(), anyhow::Error> {
+    let modes = vec![
+        "Report drift only".to_owned(),
+        "Report drift and fix what's safe to".to_owned(),
+    ];
+    let remediate =
+        Select::new("How should drift be handled?:", modes.clone()).prompt(session)? == modes[1];
+
+    writeln!(session, "Reconciling, this can take a while...")?;
+    let report = reconciler::reconcile(remediate).await?;
+
+    if report.findings.is_empty() {
+        writeln!(session, "No drift found")?;
+    }
+    for drift in report.findings {
+        let fixed = if drift.remediated { " (fixed)" } else { "" };
+        writeln!(
+            session,
+            "{}: {}, {}{fixed}",
+            drift.host, drift.kind, drift.details
+        )?;
+    }
+
+    Ok(())
+}
+
+async fn handle_collect_orphans(mut session: &Server) -> Result<(), anyhow::Error> {
+    let mut client = new_client().await.expect("Expected to connect to db");
+    let mut transaction = client
+        .easy_transaction()
+        .await
+        .expect("Transaction creation error");
+
+    let orphans = orphans::find_orphans(&mut transaction).await?;
+    transaction.commit().await?;
+
+    if orphans.is_empty() {
+        writeln!(session, "No orphaned resources found")?;
+        return Ok(());
+    }
+
+    writeln!(session, "Found {} orphaned resources:", orphans.len())?;
+    for orphan in orphans.iter() {
+        writeln!(session, "{orphan}")?;
+    }
+
+    writeln!(session, "Clean them up?")?;
+    areyousure(session)?;
+
+    DISPATCH
+        .get()
+        .unwrap()
+        .send(workflows::entry::Action::CollectOrphans { dry_run: false })?;
+    writeln!(
+        session,
+        "Garbage collection queued, admins will be sent a report"
+    )?;
+
+    Ok(())
+}
@@ -90,6 +90,15 @@ async fn handle_host_power_state_query(mut session: &Server) -> Result<(), anyho
                 PowerStateError::UnknownPowerState(err_msg) => {
                     format!("Unknown power state for host {}: {}\n", ipmi_fqdn, err_msg)
                 }
+                PowerStateError::CircuitOpen(err_msg) => {
+                    format!("Gave up on host {} for now: {}\n", ipmi_fqdn, err_msg)
+                }
+                PowerStateError::RequestFailed(err_msg) => {
+                    format!(
+                        "Redfish request failed for host {}: {}\n",
+                        ipmi_fqdn, err_msg
+                    )
+                }
                 PowerStateError::InvalidInputParameter(param) => {
                     format!(
                         "Invalid input parameter for host {}: {}\n",
@@ -209,8 +218,12 @@ async fn handle_summarize_query(session: &Server) -> Result<(), anyhow::Error> {
     let state = Select::new(
         "Get bookings in state:",
         vec![
+            LifeCycleState::Queued,
+            LifeCycleState::Scheduled,
             LifeCycleState::New,
             LifeCycleState::Active,
+            LifeCycleState::TearingDown,
+            LifeCycleState::Failed,
             LifeCycleState::Done,
         ],
     )
@@ -469,15 +482,29 @@ async fn summarize_aggregate(
         project,
         start,
         end,
+        performance_tolerance,
+        spread,
+        display_name,
+        favorited_by,
+        telemetry,
+        exempt_from_expiry,
+        ownership_override,
     } = agg.metadata.clone();
 
     writeln!(session, "Booking ID: {booking_id:?}")?;
+    writeln!(session, "Name: {display_name:?}")?;
+    writeln!(session, "Favorited by: {favorited_by:?}")?;
     writeln!(session, "Purpose: {purpose:?}")?;
     writeln!(session, "Owned by: {owner:?}")?;
     writeln!(session, "Start: {start:?}")?;
     writeln!(session, "End: {end:?}")?;
     writeln!(session, "Lab: {lab:?}")?;
     writeln!(session, "Project: {project:?}")?;
+    writeln!(session, "Performance tolerance: {performance_tolerance:?}")?;
+    writeln!(session, "Spread across: {spread:?}")?;
+    writeln!(session, "Usage telemetry: {telemetry}")?;
+    writeln!(session, "Exempt from expiry: {exempt_from_expiry}")?;
+    writeln!(session, "Ownership overridden: {ownership_override:?}")?;
 
     writeln!(session, "Collaborators:")?;
     for user in agg.users.iter() {
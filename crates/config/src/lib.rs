@@ -24,6 +24,54 @@ pub struct LibLaaSConfig {
     pub metrics: Option<MetricsConfig>,
     #[serde(default)]
     pub logging: LoggingConfig,
+    #[serde(default)]
+    pub firewall: Option<FirewallConfig>,
+    #[serde(default)]
+    pub external_feeds: HashMap<String, ExternalFeedConfig>,
+    #[serde(default)]
+    pub backup: Option<BackupConfig>,
+    #[serde(default)]
+    pub health_checks: HealthCheckConfig,
+    #[serde(default)]
+    pub bmc_audit: BmcAuditConfig,
+    #[serde(default)]
+    pub ticketing: Option<TicketingConfig>,
+    #[serde(default)]
+    pub reconciler: ReconcilerConfig,
+    #[serde(default)]
+    pub garbage_collector: GarbageCollectorConfig,
+    #[serde(default)]
+    pub device_calls: DeviceCallConfig,
+    #[serde(default)]
+    pub idle_bookings: IdleBookingConfig,
+    #[serde(default)]
+    pub host_replacement: HostReplacementConfig,
+    #[serde(default)]
+    pub failure_bundles: FailureBundleConfig,
+    #[serde(default)]
+    pub snapshots: Option<SnapshotConfig>,
+    #[serde(default)]
+    pub booking_expiry: BookingExpiryConfig,
+    #[serde(default)]
+    pub booking_queue: BookingQueueConfig,
+    #[serde(default)]
+    pub capacity_alerts: CapacityAlertConfig,
+    #[serde(default)]
+    pub host_access: Option<HostAccessConfig>,
+    #[serde(default)]
+    pub verification: Option<VerificationConfig>,
+    #[serde(default)]
+    pub trash: TrashConfig,
+    #[serde(default)]
+    pub accounting: AccountingConfig,
+    #[serde(default)]
+    pub firmware: FirmwareConfig,
+    #[serde(default)]
+    pub post_times: PostTimeConfig,
+    #[serde(default)]
+    pub fabrics: HashMap<String, FabricConfig>,
+    #[serde(default)]
+    pub branding: BrandingConfig,
 }
 
 #[derive(Debug, Clone, Copy, Default)]
@@ -96,6 +144,26 @@ impl Email {
 pub struct Dev {
     pub status: bool,
     pub hosts: Vec<String>,
+    /// Workflow steps to make fail or hang, only honored when `status` is set
+    #[serde(default)]
+    pub failure_injection: Vec<FailureInjection>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct FailureInjection {
+    /// Name of the task to inject into, like `BootToNetworkTask`
+    pub task: String,
+    pub mode: FailureMode,
+    /// Only inject into this many runs of the task, then let it succeed
+    #[serde(default)]
+    pub times: Option<u32>,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum FailureMode {
+    Fail,
+    Hang,
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -110,6 +178,203 @@ pub struct DatabaseConfig {
 pub struct WebConfig {
     pub bind_addr: HostPortPair,
     pub external_url: String,
+    #[serde(default)]
+    pub security: WebSecurityConfig,
+    #[serde(default)]
+    pub limits: WebLimitsConfig,
+    /// How long a create-booking request can be replayed by its `Idempotency-Key`
+    #[serde(default = "default_idempotency_ttl_hours")]
+    pub idempotency_ttl_hours: u64,
+    /// Endpoints and response fields clients should move off of, which responses
+    /// that use them warn about
+    #[serde(default)]
+    pub deprecations: Vec<Deprecation>,
+    #[serde(default)]
+    pub downloads: DownloadConfig,
+}
+
+/// Links to download artifacts like failure bundles and cloud-config with, which work without
+/// API access until they expire or the booking the artifact is of ends
+#[derive(Debug, Deserialize, Clone)]
+pub struct DownloadConfig {
+    /// What links are signed with. A key is made up on each start if not given, so links
+    /// stop working whenever LibLaaS restarts
+    #[serde(default)]
+    pub signing_key: Option<String>,
+    /// How long a link works for when not asked for otherwise
+    #[serde(default = "default_download_ttl_minutes")]
+    pub default_ttl_minutes: u64,
+    /// The longest a link can be asked to work for
+    #[serde(default = "default_download_max_ttl_minutes")]
+    pub max_ttl_minutes: u64,
+}
+
+fn default_download_ttl_minutes() -> u64 {
+    60
+}
+
+fn default_download_max_ttl_minutes() -> u64 {
+    7 * 24 * 60
+}
+
+impl Default for DownloadConfig {
+    fn default() -> Self {
+        Self {
+            signing_key: None,
+            default_ttl_minutes: default_download_ttl_minutes(),
+            max_ttl_minutes: default_download_max_ttl_minutes(),
+        }
+    }
+}
+
+fn default_idempotency_ttl_hours() -> u64 {
+    24
+}
+
+/// The CORS and security headers the web API answers with, so that each deployment can
+/// serve the dashboard from wherever it likes
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct WebSecurityConfig {
+    #[serde(default)]
+    pub defaults: HeaderPolicy,
+    /// Policies for requests whose path starts with the key, over `defaults`,
+    /// the longest matching prefix wins
+    #[serde(default)]
+    pub endpoints: HashMap<String, HeaderPolicy>,
+}
+
+/// Anything left out is taken from the policy this one is laid over
+#[derive(Debug, Deserialize, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct HeaderPolicy {
+    /// Origins browsers may call the API from, `*` for any, none if not given
+    pub allowed_origins: Option<Vec<String>>,
+    pub allow_credentials: Option<bool>,
+    /// Request headers browsers may send from those origins, only `Content-Type` if not given
+    pub allowed_headers: Option<Vec<String>>,
+    /// Not sent if not given
+    pub content_security_policy: Option<String>,
+    /// `DENY` if not given
+    pub frame_options: Option<String>,
+    /// `no-referrer` if not given
+    pub referrer_policy: Option<String>,
+    /// Not sent if not given, since only deployments behind TLS should send it
+    pub hsts_max_age_secs: Option<u64>,
+}
+
+impl HeaderPolicy {
+    /// This policy with anything it leaves out taken from `base`
+    pub fn over(&self, base: &HeaderPolicy) -> HeaderPolicy {
+        HeaderPolicy {
+            allowed_origins: self
+                .allowed_origins
+                .clone()
+                .or(base.allowed_origins.clone()),
+            allow_credentials: self.allow_credentials.or(base.allow_credentials),
+            allowed_headers: self
+                .allowed_headers
+                .clone()
+                .or(base.allowed_headers.clone()),
+            content_security_policy: self
+                .content_security_policy
+                .clone()
+                .or(base.content_security_policy.clone()),
+            frame_options: self.frame_options.clone().or(base.frame_options.clone()),
+            referrer_policy: self
+                .referrer_policy
+                .clone()
+                .or(base.referrer_policy.clone()),
+            hsts_max_age_secs: self.hsts_max_age_secs.or(base.hsts_max_age_secs),
+        }
+    }
+}
+
+impl WebSecurityConfig {
+    /// The policy for requests to `path`
+    pub fn for_path(&self, path: &str) -> HeaderPolicy {
+        self.endpoints
+            .iter()
+            .filter(|(prefix, _)| path.starts_with(prefix.as_str()))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(_, policy)| policy.over(&self.defaults))
+            .unwrap_or_else(|| self.defaults.clone())
+    }
+}
+
+/// An endpoint, or a field of what it answers with, that is on its way out
+///
+/// Responses from the endpoint, or only those with the field in them if one is given,
+/// carry `Deprecation` and `Sunset` headers saying so.
+#[derive(Debug, Deserialize, Clone)]
+pub struct Deprecation {
+    /// The path of the endpoint, with `:name` for path parameters, like `/booking/:agg_id/status`
+    pub path: String,
+    /// Only requests with this method are deprecated, any if not given
+    #[serde(default)]
+    pub method: Option<String>,
+    /// A field of the JSON response, with `.` between the keys it is nested under and `*` for
+    /// any key or array element, like `instances.*.assigned_host_info`. The whole endpoint
+    /// is deprecated if not given
+    #[serde(default)]
+    pub field: Option<String>,
+    /// When it was deprecated, in RFC 3339
+    pub since: String,
+    /// When it stops working, in RFC 3339, if that has been decided
+    #[serde(default)]
+    pub sunset: Option<String>,
+    /// What clients should do instead
+    #[serde(default)]
+    pub note: Option<String>,
+    /// A page about the deprecation, linked to from the response
+    #[serde(default)]
+    pub link: Option<String>,
+    /// Whether JSON object responses also get a `deprecation_warnings` field saying so,
+    /// for clients that don't look at headers
+    #[serde(default)]
+    pub warn_in_body: bool,
+}
+
+/// How much a request to the web API may send, so that no one request can take up all the memory
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct WebLimitsConfig {
+    #[serde(default)]
+    pub defaults: RequestLimits,
+    /// Limits for requests whose path starts with the key, over `defaults`,
+    /// the longest matching prefix wins
+    #[serde(default)]
+    pub endpoints: HashMap<String, RequestLimits>,
+}
+
+/// Anything left out is taken from the limits these are laid over
+#[derive(Debug, Deserialize, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct RequestLimits {
+    /// 2 MiB if not given
+    pub max_body_bytes: Option<usize>,
+    /// How deeply arrays and objects may nest in a JSON body, 32 if not given
+    pub max_json_depth: Option<usize>,
+}
+
+impl RequestLimits {
+    /// These limits with anything they leave out taken from `base`
+    pub fn over(&self, base: &RequestLimits) -> RequestLimits {
+        RequestLimits {
+            max_body_bytes: self.max_body_bytes.or(base.max_body_bytes),
+            max_json_depth: self.max_json_depth.or(base.max_json_depth),
+        }
+    }
+}
+
+impl WebLimitsConfig {
+    /// The limits for requests to `path`
+    pub fn for_path(&self, path: &str) -> RequestLimits {
+        self.endpoints
+            .iter()
+            .filter(|(prefix, _)| path.starts_with(prefix.as_str()))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(_, limits)| limits.over(&self.defaults))
+            .unwrap_or_else(|| self.defaults.clone())
+    }
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -141,6 +406,11 @@ pub struct NotificationConfig {
     pub admin_send_to_email: Option<Email>,
     pub templates_directory: String,
     pub vpn_config_path: PathBuf,
+
+    /// What the mail backend has to send in `X-LaaS-Webhook-Secret` with delivery reports,
+    /// which are refused if this isn't given
+    #[serde(default)]
+    pub delivery_webhook_secret: Option<String>,
 }
 #[derive(Debug, Deserialize, Clone)]
 pub struct CobblerConfig {
@@ -173,6 +443,713 @@ pub struct MetricsConfig {
     pub url: String,
 }
 
+/// The lab edge firewall that booking uplink policies are programmed onto
+#[derive(Debug, Deserialize, Clone)]
+pub struct FirewallConfig {
+    /// Address of the edge, which is managed over ssh using nftables
+    pub edge_address: String,
+    pub username: String,
+    pub password: String,
+    #[serde(default)]
+    pub guardrails: FirewallGuardrails,
+}
+
+/// Lab wide limits that every booking firewall policy is validated against
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct FirewallGuardrails {
+    /// The most allow rules a single booking may have
+    #[serde(default)]
+    pub max_rules: Option<usize>,
+    /// Ports that may never be opened to bookings
+    #[serde(default)]
+    pub blocked_ports: Vec<u16>,
+    /// Whether rules may allow traffic from any source (`0.0.0.0/0` or `::/0`)
+    #[serde(default)]
+    pub allow_any_source: bool,
+}
+
+/// Scheduled logical dumps of the LaaS database
+#[derive(Debug, Deserialize, Clone)]
+pub struct BackupConfig {
+    /// Where dumps and pre-teardown aggregate snapshots are written
+    pub directory: PathBuf,
+    #[serde(default = "default_backup_interval", deserialize_with = "at_least_one")]
+    pub interval_hours: u64,
+    /// How many scheduled dumps to keep before the oldest are deleted
+    #[serde(default = "default_backup_retain")]
+    pub retain: usize,
+}
+
+fn default_backup_interval() -> u64 {
+    24
+}
+
+fn default_backup_retain() -> usize {
+    7
+}
+
+/// Periodic checks of booked hosts, for tracking the availability delivered to bookings
+#[derive(Debug, Deserialize, Clone)]
+pub struct HealthCheckConfig {
+    #[serde(default = "default_health_check_enabled")]
+    pub enabled: bool,
+    #[serde(default = "default_health_check_interval", deserialize_with = "at_least_one")]
+    pub interval_minutes: u64,
+    /// How long after a power action requested through LaaS a failing check isn't held against the lab
+    #[serde(default = "default_health_check_grace")]
+    pub power_action_grace_minutes: i64,
+}
+
+impl Default for HealthCheckConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_health_check_enabled(),
+            interval_minutes: default_health_check_interval(),
+            power_action_grace_minutes: default_health_check_grace(),
+        }
+    }
+}
+
+fn default_health_check_enabled() -> bool {
+    true
+}
+
+fn default_health_check_interval() -> u64 {
+    10
+}
+
+fn default_health_check_grace() -> i64 {
+    15
+}
+
+/// Periodic checks that the BMCs of booked hosts can't be reached from booking networks
+#[derive(Debug, Deserialize, Clone)]
+pub struct BmcAuditConfig {
+    #[serde(default = "default_bmc_audit_enabled")]
+    pub enabled: bool,
+    #[serde(default = "default_bmc_audit_interval", deserialize_with = "at_least_one")]
+    pub interval_minutes: u64,
+}
+
+impl Default for BmcAuditConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_bmc_audit_enabled(),
+            interval_minutes: default_bmc_audit_interval(),
+        }
+    }
+}
+
+fn default_bmc_audit_enabled() -> bool {
+    true
+}
+
+fn default_bmc_audit_interval() -> u64 {
+    60
+}
+
+/// The nightly comparison of what the database expects against what the lab hardware reports
+#[derive(Debug, Deserialize, Clone)]
+pub struct ReconcilerConfig {
+    #[serde(default = "default_reconciler_enabled")]
+    pub enabled: bool,
+    /// Hour of the day (UTC) to run at
+    #[serde(default = "default_reconciler_hour")]
+    pub hour_utc: u32,
+    /// Whether to fix drift that is safe to fix unattended, like a free host left powered on
+    #[serde(default)]
+    pub remediate: bool,
+}
+
+impl Default for ReconcilerConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_reconciler_enabled(),
+            hour_utc: default_reconciler_hour(),
+            remediate: false,
+        }
+    }
+}
+
+fn default_reconciler_enabled() -> bool {
+    true
+}
+
+fn default_reconciler_hour() -> u32 {
+    4
+}
+
+/// Periodic cleanup of resources still held by bookings that have ended
+#[derive(Debug, Deserialize, Clone)]
+pub struct GarbageCollectorConfig {
+    #[serde(default = "default_gc_enabled")]
+    pub enabled: bool,
+    #[serde(default = "default_gc_interval", deserialize_with = "at_least_one")]
+    pub interval_hours: u64,
+    /// Only report what would be cleaned up, so admins can check the report before
+    /// letting it loose
+    #[serde(default = "default_gc_dry_run")]
+    pub dry_run: bool,
+}
+
+impl Default for GarbageCollectorConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_gc_enabled(),
+            interval_hours: default_gc_interval(),
+            dry_run: default_gc_dry_run(),
+        }
+    }
+}
+
+fn default_gc_enabled() -> bool {
+    true
+}
+
+fn default_gc_interval() -> u64 {
+    24
+}
+
+fn default_gc_dry_run() -> bool {
+    true
+}
+
+/// Ending bookings that are past their end, since owners don't always end them themselves
+#[derive(Debug, Deserialize, Clone)]
+pub struct BookingExpiryConfig {
+    #[serde(default = "default_expiry_enabled")]
+    pub enabled: bool,
+    #[serde(default = "default_expiry_interval", deserialize_with = "at_least_one")]
+    pub interval_minutes: u64,
+    /// How long past its end a booking is left running before it is ended
+    #[serde(default = "default_expiry_grace")]
+    pub grace_hours: i64,
+}
+
+impl Default for BookingExpiryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_expiry_enabled(),
+            interval_minutes: default_expiry_interval(),
+            grace_hours: default_expiry_grace(),
+        }
+    }
+}
+
+fn default_expiry_enabled() -> bool {
+    false
+}
+
+fn default_expiry_interval() -> u64 {
+    30
+}
+
+fn default_expiry_grace() -> i64 {
+    2
+}
+
+/// Bookings that asked to wait for hosts when none were free, and are started once some are
+#[derive(Debug, Deserialize, Clone)]
+pub struct BookingQueueConfig {
+    /// Whether bookings can be queued at all, they fail as if they hadn't asked otherwise
+    #[serde(default = "default_queue_enabled")]
+    pub enabled: bool,
+    #[serde(default = "default_queue_interval", deserialize_with = "at_least_one")]
+    pub interval_minutes: u64,
+    /// How long a booking waits for hosts before it is given up on
+    #[serde(default = "default_queue_max_wait")]
+    pub max_wait_hours: i64,
+}
+
+impl Default for BookingQueueConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_queue_enabled(),
+            interval_minutes: default_queue_interval(),
+            max_wait_hours: default_queue_max_wait(),
+        }
+    }
+}
+
+fn default_queue_enabled() -> bool {
+    false
+}
+
+fn default_queue_interval() -> u64 {
+    10
+}
+
+fn default_queue_max_wait() -> i64 {
+    72
+}
+
+/// Telling the admins when a flavor is running out of free hosts, or is about to
+#[derive(Debug, Deserialize, Clone)]
+pub struct CapacityAlertConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "default_capacity_interval", deserialize_with = "at_least_one")]
+    pub interval_minutes: u64,
+    /// How many days ahead queued bookings are checked against the hosts that will be free
+    #[serde(default = "default_capacity_horizon")]
+    pub horizon_days: i64,
+    /// Fewest hosts a flavor can have free before the admins are told, for flavors not
+    /// in `min_free`. Flavors are only checked for queued demand if not given
+    #[serde(default)]
+    pub default_min_free: Option<usize>,
+    /// Fewest hosts each flavor can have free before the admins are told, by flavor name
+    #[serde(default)]
+    pub min_free: HashMap<String, usize>,
+}
+
+impl Default for CapacityAlertConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            interval_minutes: default_capacity_interval(),
+            horizon_days: default_capacity_horizon(),
+            default_min_free: None,
+            min_free: HashMap::new(),
+        }
+    }
+}
+
+fn default_capacity_interval() -> u64 {
+    60
+}
+
+fn default_capacity_horizon() -> i64 {
+    7
+}
+
+/// Finding bookings whose hosts sit unused and asking their owners to keep or release them
+#[derive(Debug, Deserialize, Clone)]
+pub struct IdleBookingConfig {
+    #[serde(default = "default_idle_enabled")]
+    pub enabled: bool,
+    #[serde(default = "default_idle_interval", deserialize_with = "at_least_one")]
+    pub interval_hours: u64,
+    /// How many days a booking has to be idle for before its owner is asked about it,
+    /// and how long a "keep" holds off the next ask
+    #[serde(default = "default_idle_days")]
+    pub idle_days: i64,
+    /// Telemetry CPU use at or below this counts as idle
+    #[serde(default = "default_idle_cpu_percent")]
+    pub cpu_percent: f64,
+    /// Telemetry network traffic (in and out together) at or below this counts as idle
+    #[serde(default = "default_idle_network_bytes_per_sec")]
+    pub network_bytes_per_sec: f64,
+    /// Admins are told about bookings that are still idle after this many asks in a row
+    #[serde(default = "default_idle_flag_after")]
+    pub flag_after_nudges: i32,
+}
+
+impl Default for IdleBookingConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_idle_enabled(),
+            interval_hours: default_idle_interval(),
+            idle_days: default_idle_days(),
+            cpu_percent: default_idle_cpu_percent(),
+            network_bytes_per_sec: default_idle_network_bytes_per_sec(),
+            flag_after_nudges: default_idle_flag_after(),
+        }
+    }
+}
+
+fn default_idle_enabled() -> bool {
+    false
+}
+
+fn default_idle_interval() -> u64 {
+    6
+}
+
+fn default_idle_days() -> i64 {
+    3
+}
+
+fn default_idle_cpu_percent() -> f64 {
+    5.0
+}
+
+fn default_idle_network_bytes_per_sec() -> f64 {
+    10_000.0
+}
+
+fn default_idle_flag_after() -> i32 {
+    3
+}
+
+/// Owners swapping a faulty host of their booking out for another
+#[derive(Debug, Deserialize, Clone)]
+pub struct HostReplacementConfig {
+    /// Whether an admin has to approve each replacement before it is carried out
+    #[serde(default = "default_replacement_requires_approval")]
+    pub require_approval: bool,
+}
+
+impl Default for HostReplacementConfig {
+    fn default() -> Self {
+        Self {
+            require_approval: default_replacement_requires_approval(),
+        }
+    }
+}
+
+fn default_replacement_requires_approval() -> bool {
+    true
+}
+
+/// What goes into the diagnostic bundle put together when a host fails to provision
+#[derive(Debug, Deserialize, Clone)]
+pub struct FailureBundleConfig {
+    /// The log on the cobbler server that dhcpd writes its lease attempts to
+    #[serde(default = "default_dhcp_log")]
+    pub dhcp_log: String,
+    /// How many of the last lines of the host's serial console to keep
+    #[serde(default = "default_console_lines")]
+    pub console_lines: usize,
+}
+
+impl Default for FailureBundleConfig {
+    fn default() -> Self {
+        Self {
+            dhcp_log: default_dhcp_log(),
+            console_lines: default_console_lines(),
+        }
+    }
+}
+
+fn default_dhcp_log() -> String {
+    "/var/log/messages".to_owned()
+}
+
+fn default_console_lines() -> usize {
+    200
+}
+
+/// Disk captures for booking snapshots, taken and written back by netbooting hosts into
+/// cobbler profiles that copy their disk to and from the image store
+#[derive(Debug, Deserialize, Clone)]
+pub struct SnapshotConfig {
+    /// Copies the disk of the host to `capture_target`, then posts `ok` (or what went
+    /// wrong) to `inbox_target`
+    pub capture_profile: String,
+    /// Writes `restore_source` onto the disk of the host, then reports in to the same
+    /// targets the installer would, with cloud-init reset so the host comes up fresh
+    pub restore_profile: String,
+    /// Where disk images are kept, each is at `{store_url}/{image name}`
+    pub store_url: String,
+    #[serde(default = "default_capture_timeout_minutes")]
+    pub capture_timeout_minutes: u64,
+}
+
+fn default_capture_timeout_minutes() -> u64 {
+    120
+}
+
+/// How the lab presents itself in the notifications and reports it sends out
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct BrandingConfig {
+    #[serde(default = "default_lab_name")]
+    pub lab_name: String,
+    /// Shown at the bottom of notifications, if set
+    #[serde(default)]
+    pub logo_url: Option<String>,
+    /// Where users are told to go for help, like an email address or a ticketing page
+    #[serde(default)]
+    pub support_contact: Option<String>,
+    /// Put at the end of every notification and report
+    #[serde(default)]
+    pub footer: Option<String>,
+}
+
+impl Default for BrandingConfig {
+    fn default() -> Self {
+        Self {
+            lab_name: default_lab_name(),
+            logo_url: None,
+            support_contact: None,
+            footer: None,
+        }
+    }
+}
+
+impl BrandingConfig {
+    /// The footer of plain text reports, one line each for the lab, its support contact and
+    /// the configured footer
+    pub fn text_footer(&self) -> String {
+        let mut footer = format!("-- \n{}", self.lab_name);
+        if let Some(support) = &self.support_contact {
+            footer.push_str(&format!("\nSupport: {support}"));
+        }
+        if let Some(text) = &self.footer {
+            footer.push_str(&format!("\n{text}"));
+        }
+
+        footer
+    }
+}
+
+fn default_lab_name() -> String {
+    "LaaS".to_owned()
+}
+
+/// The account LibLaaS logs in to the hosts of bookings with, to reboot them from their
+/// OS and change who can log in to them once they're up. Every host is given it through
+/// its cloud-init
+#[derive(Debug, Deserialize, Clone)]
+pub struct HostAccessConfig {
+    pub username: String,
+    /// Authorized for `username` on every host
+    pub public_key: String,
+    /// The private half of `public_key`
+    pub private_key: PathBuf,
+}
+
+/// What usage reports of bookings are worked out with
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct AccountingConfig {
+    /// How many GB installing each image moves over the network, keyed by image name.
+    /// Images without a size here are reported with how often they were installed only
+    #[serde(default)]
+    pub image_sizes_gb: HashMap<String, f64>,
+    /// What an hour of a host of each flavor costs, keyed by flavor name, for picking the
+    /// cheapest host that meets what a booking asks for. Flavors without a cost go last
+    #[serde(default)]
+    pub flavor_hourly_costs: HashMap<String, f64>,
+}
+
+/// The firmware each model of host is expected to be running, keyed by flavor name
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct FirmwareConfig {
+    #[serde(default)]
+    pub baselines: HashMap<String, FirmwareBaseline>,
+}
+
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct FirmwareBaseline {
+    /// The NIC firmware versions that are up to date, keyed by NIC driver. NICs with a
+    /// driver not listed here can't be told to be up to date or not
+    #[serde(default)]
+    pub nics: HashMap<String, Vec<String>>,
+}
+
+/// When a host taking longer or shorter than usual to get from power on to netboot is
+/// worth looking into, often the first sign of failing DIMMs or a RAID controller
+#[derive(Debug, Deserialize, Clone)]
+pub struct PostTimeConfig {
+    /// How many of the host's latest deploys its usual POST time is taken from
+    #[serde(default = "default_post_baseline_deploys")]
+    pub baseline_deploys: usize,
+    /// Hosts with fewer deploys than this behind them aren't judged yet
+    #[serde(default = "default_post_min_baseline")]
+    pub min_baseline: usize,
+    /// How many percent off its usual time a deploy has to be to be flagged
+    #[serde(default = "default_post_drift_percent")]
+    pub drift_percent: f64,
+    /// Drifts smaller than this are never flagged, however large they are in percent
+    #[serde(default = "default_post_min_drift")]
+    pub min_drift_seconds: f64,
+}
+
+impl Default for PostTimeConfig {
+    fn default() -> Self {
+        Self {
+            baseline_deploys: default_post_baseline_deploys(),
+            min_baseline: default_post_min_baseline(),
+            drift_percent: default_post_drift_percent(),
+            min_drift_seconds: default_post_min_drift(),
+        }
+    }
+}
+
+fn default_post_baseline_deploys() -> usize {
+    20
+}
+
+fn default_post_min_baseline() -> usize {
+    5
+}
+
+fn default_post_drift_percent() -> f64 {
+    40.0
+}
+
+fn default_post_min_drift() -> f64 {
+    60.0
+}
+
+/// A fabric manager pooling PCIe or CXL devices, like GPUs and NVMe drives, that are
+/// attached to the compute sleds on the fabric as bookings need them. Keyed by fabric
+/// name in the config
+#[derive(Debug, Deserialize, Clone)]
+pub struct FabricConfig {
+    #[serde(default)]
+    pub driver: FabricDriver,
+    /// Address of the fabric manager, like `fabric1.example.com:8443`
+    pub address: String,
+    pub username: String,
+    pub password: String,
+    /// What the fabric manager knows each host on the fabric as, by server name
+    #[serde(default)]
+    pub machines: HashMap<String, String>,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum FabricDriver {
+    /// The Redfish composition service, which Liqid, GigaIO and CXL fabric managers serve.
+    /// Machines are the paths of their systems, like `/redfish/v1/Systems/sled7`
+    #[default]
+    Redfish,
+}
+
+/// How long ended bookings are kept around before their instances and logs are purged
+#[derive(Debug, Deserialize, Clone)]
+pub struct TrashConfig {
+    /// Whether bookings are purged at all once they've been in the trash for long enough,
+    /// they're kept forever otherwise
+    #[serde(default = "default_trash_purge")]
+    pub purge: bool,
+    #[serde(default = "default_trash_retention")]
+    pub retention_days: i64,
+    #[serde(default = "default_trash_interval", deserialize_with = "at_least_one")]
+    pub interval_hours: u64,
+}
+
+impl Default for TrashConfig {
+    fn default() -> Self {
+        Self {
+            purge: default_trash_purge(),
+            retention_days: default_trash_retention(),
+            interval_hours: default_trash_interval(),
+        }
+    }
+}
+
+fn default_trash_purge() -> bool {
+    false
+}
+
+fn default_trash_retention() -> i64 {
+    30
+}
+
+fn default_trash_interval() -> u64 {
+    24
+}
+
+/// Checks of hosts after their booking is cleaned up, made by netbooting them into a
+/// ramdisk before they're given back to the free pool
+#[derive(Debug, Deserialize, Clone)]
+pub struct VerificationConfig {
+    /// Checks that the disks of the host were wiped and that its BMC is set up like the
+    /// baseline at `bmc_baseline`, then posts a verification report to `inbox_target`
+    pub profile: String,
+    /// Where the ramdisk fetches the lab's BMC baseline from
+    pub bmc_baseline_url: String,
+    #[serde(default = "default_verification_timeout_minutes")]
+    pub timeout_minutes: u64,
+}
+
+fn default_verification_timeout_minutes() -> u64 {
+    45
+}
+
+/// Retries and circuit breaking for calls out to BMCs and switches
+#[derive(Debug, Deserialize, Clone)]
+pub struct DeviceCallConfig {
+    /// How many times a failed call is retried before giving up on it
+    #[serde(default = "default_device_retries")]
+    pub retries: u32,
+    /// The backoff before the first retry, doubling with every retry after
+    #[serde(default = "default_device_base_delay")]
+    pub base_delay_ms: u64,
+    #[serde(default = "default_device_max_delay")]
+    pub max_delay_ms: u64,
+    /// Consecutive failures after which calls to the device stop being made
+    #[serde(default = "default_breaker_threshold")]
+    pub breaker_threshold: u32,
+    /// How long a tripped device is left alone before calls to it are tried again
+    #[serde(default = "default_breaker_cooldown")]
+    pub breaker_cooldown_secs: u64,
+}
+
+impl Default for DeviceCallConfig {
+    fn default() -> Self {
+        Self {
+            retries: default_device_retries(),
+            base_delay_ms: default_device_base_delay(),
+            max_delay_ms: default_device_max_delay(),
+            breaker_threshold: default_breaker_threshold(),
+            breaker_cooldown_secs: default_breaker_cooldown(),
+        }
+    }
+}
+
+fn default_device_retries() -> u32 {
+    2
+}
+
+fn default_device_base_delay() -> u64 {
+    500
+}
+
+fn default_device_max_delay() -> u64 {
+    10_000
+}
+
+fn default_breaker_threshold() -> u32 {
+    6
+}
+
+fn default_breaker_cooldown() -> u64 {
+    300
+}
+
+/// The issue tracker that tickets are opened in when a host has a hardware problem
+#[derive(Debug, Deserialize, Clone)]
+pub struct TicketingConfig {
+    pub system: TicketSystem,
+    /// Base url of the tracker, like `https://jira.example.com`
+    pub url: String,
+    /// Used alongside the token for basic auth by jira and servicenow
+    #[serde(default)]
+    pub username: Option<String>,
+    pub token: String,
+    /// Jira project key, GitLab project path, or ServiceNow assignment group
+    pub project: String,
+    /// Where support requests users make about their bookings are filed instead of `project`
+    #[serde(default)]
+    pub support_project: Option<String>,
+    #[serde(default)]
+    pub labels: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, Display)]
+#[serde(rename_all = "lowercase")]
+pub enum TicketSystem {
+    #[strum(serialize = "jira")]
+    Jira,
+    #[strum(serialize = "servicenow")]
+    ServiceNow,
+    #[strum(serialize = "gitlab")]
+    GitLab,
+}
+
+/// An external carrier or partner feed that bookings can request to be trunked onto,
+/// keyed by feed name in the config
+#[derive(Debug, Deserialize, Clone)]
+pub struct ExternalFeedConfig {
+    pub description: String,
+    /// Name of the switch the feed's uplink is connected to
+    pub switch: String,
+    /// The uplink port on that switch that booking vlans are trunked onto
+    pub port: String,
+}
+
 #[derive(Debug, Deserialize, Clone)]
 pub struct ProjectConfig {
     pub vpn: VPNConfig,
@@ -185,6 +1162,95 @@ pub struct ProjectConfig {
     pub email: String,
     pub phone: String,
     pub is_dynamic: bool,
+    /// How to name hosts the user didn't give a hostname, see `workflows::deploy_booking::hostnames`
+    #[serde(default)]
+    pub hostname_template: Option<String>,
+    #[serde(default)]
+    pub image_policy: ImagePolicyConfig,
+    /// Users who can make bookings on behalf of others, like instructors setting up a class
+    #[serde(default)]
+    pub leads: Vec<String>,
+    #[serde(default)]
+    pub quotas: QuotaConfig,
+    /// When LibLaaS may tear hosts down on its own, like ending expired bookings. It may
+    /// at any time if not set
+    #[serde(default)]
+    pub working_hours: Option<WorkingHoursConfig>,
+}
+
+/// The hours someone is around at the lab to step in if an action LibLaaS takes on its own
+/// goes wrong, see `workflows::resource_management::working_hours`
+#[derive(Debug, Deserialize, Clone)]
+pub struct WorkingHoursConfig {
+    /// The IANA name of the time zone of the lab, like `America/New_York`
+    pub timezone: String,
+    /// The days of the week that are worked, like `mon` or `tuesday`
+    #[serde(default = "default_working_days")]
+    pub days: Vec<String>,
+    /// The local hour work starts at
+    #[serde(default = "default_working_start")]
+    pub start_hour: u32,
+    /// The local hour work ends at, so the last hour worked is the one before it
+    #[serde(default = "default_working_end")]
+    pub end_hour: u32,
+    /// Days that aren't worked even though they fall on a working day, as `YYYY-MM-DD`
+    #[serde(default)]
+    pub holidays: Vec<String>,
+}
+
+fn default_working_days() -> Vec<String> {
+    ["mon", "tue", "wed", "thu", "fri"]
+        .into_iter()
+        .map(str::to_owned)
+        .collect()
+}
+
+fn default_working_start() -> u32 {
+    9
+}
+
+fn default_working_end() -> u32 {
+    17
+}
+
+/// How much of the lab a booking, a user or a project can take at once. Admins can grant
+/// overrides of these for a while, see `models::dashboard::QuotaOverride`
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct QuotaConfig {
+    /// The longest a booking can be made for, bookings without an end aren't allowed if set
+    #[serde(default)]
+    pub max_length_days: Option<u64>,
+    /// The most hosts the bookings a user owns can hold between them
+    #[serde(default)]
+    pub max_hosts_per_user: Option<usize>,
+    /// The most hosts the bookings made for a project can hold between them
+    #[serde(default)]
+    pub max_hosts_per_project: Option<usize>,
+}
+
+/// Which images members of a project may book, by image name
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct ImagePolicyConfig {
+    /// If given, only these images may be used
+    #[serde(default)]
+    pub allow: Option<Vec<String>>,
+    /// Images that may not be used, even if allowed
+    #[serde(default)]
+    pub deny: Vec<String>,
+    /// The image picked for a flavor unless the user picks another, keyed by flavor name
+    #[serde(default)]
+    pub defaults: HashMap<String, String>,
+}
+
+impl ImagePolicyConfig {
+    pub fn allows(&self, image: &str) -> bool {
+        let allowed = match &self.allow {
+            Some(allow) => allow.iter().any(|a| a == image),
+            None => true,
+        };
+
+        allowed && !self.deny.iter().any(|d| d == image)
+    }
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -224,6 +1290,10 @@ pub enum Situation {
     AccountCreated,
     CollaboratorAdded(Vec<String>),
     RequestBookingExtension,
+    ImageDeprecated,
+    BookingIdle,
+    ExtensionDecided,
+    BookingQueue,
 }
 
 impl<'de> Deserialize<'de> for Situation {
@@ -242,6 +1312,10 @@ impl<'de> Deserialize<'de> for Situation {
             "account_created" => Self::AccountCreated,
             "collaborator_added" => Self::CollaboratorAdded(Vec::new()),
             "booking_extension_request" => Self::RequestBookingExtension,
+            "image_deprecated" => Self::ImageDeprecated,
+            "booking_idle" => Self::BookingIdle,
+            "booking_extension_decided" => Self::ExtensionDecided,
+            "booking_queue" => Self::BookingQueue,
             other => Err(serde::de::Error::custom(format!(
                 "Bad situation specifier {other}"
             )))?,
@@ -285,6 +1359,19 @@ impl ToString for HostPortPair {
     }
 }
 
+/// For how often background tasks run, which can't be zero since tokio panics on a zero interval
+fn at_least_one<'de, D>(deserializer: D) -> Result<u64, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    match u64::deserialize(deserializer)? {
+        0 => Err(serde::de::Error::custom(
+            "intervals have to be at least 1, got 0",
+        )),
+        n => Ok(n),
+    }
+}
+
 static CONFIG: once_cell::sync::Lazy<LibLaaSConfig> = once_cell::sync::Lazy::new(|| {
     config::Config::builder()
         .add_source(config::File::with_name("/etc/laas-reflab/config.yaml"))
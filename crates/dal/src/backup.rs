@@ -0,0 +1,244 @@
+//! Logical backups of the LaaS database, and restoring from them
+//!
+//! Every dump is written next to a small metadata file that records the
+//! schema (migration) version it was taken at, so that a dump is never
+//! restored underneath a build that expects a different schema.
+
+use std::path::{Path, PathBuf};
+
+use common::prelude::{
+    anyhow,
+    chrono::{DateTime, Utc},
+    serde_json, tokio, tracing,
+};
+use config::{settings, BackupConfig};
+use serde::{Deserialize, Serialize};
+
+use crate::{web::AnyWay, EasyTransaction};
+
+const DUMP_PREFIX: &str = "laas-";
+const DUMP_EXTENSION: &str = "dump";
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BackupInfo {
+    pub path: PathBuf,
+    pub schema_version: i64,
+    pub created: DateTime<Utc>,
+}
+
+/// The newest migration that this build of LaaS knows about
+pub fn expected_schema_version() -> i64 {
+    sqlx::migrate!("../../migrations")
+        .iter()
+        .map(|m| m.version)
+        .max()
+        .unwrap_or(0)
+}
+
+/// The newest migration that has been applied to the database
+pub async fn current_schema_version(t: &mut EasyTransaction<'_>) -> Result<i64, anyhow::Error> {
+    let row = t
+        .query_one(
+            "SELECT COALESCE(MAX(version), 0) AS version FROM _sqlx_migrations WHERE success;",
+            &[],
+        )
+        .await
+        .anyway()?;
+
+    Ok(row.try_get("version")?)
+}
+
+fn metadata_path(dump: &Path) -> PathBuf {
+    dump.with_extension(format!("{DUMP_EXTENSION}.json"))
+}
+
+fn pg_command(program: &str) -> tokio::process::Command {
+    let db = &settings().database;
+
+    let mut command = tokio::process::Command::new(program);
+    command
+        .env("PGPASSWORD", &db.password)
+        .arg("--host")
+        .arg(&db.url.host)
+        .arg("--port")
+        .arg(db.url.port.to_string())
+        .arg("--username")
+        .arg(&db.username)
+        .arg("--dbname")
+        .arg(&db.database_name);
+
+    command
+}
+
+/// Takes a dump of the whole database into the backup directory,
+/// then prunes old dumps down to the configured retention
+pub async fn dump(
+    config: &BackupConfig,
+    t: &mut EasyTransaction<'_>,
+) -> Result<BackupInfo, anyhow::Error> {
+    std::fs::create_dir_all(&config.directory)?;
+
+    let created = Utc::now();
+    let path = config.directory.join(format!(
+        "{DUMP_PREFIX}{}.{DUMP_EXTENSION}",
+        created.format("%Y%m%dT%H%M%SZ")
+    ));
+
+    let output = pg_command("pg_dump")
+        .arg("--format=custom")
+        .arg("--file")
+        .arg(&path)
+        .output()
+        .await?;
+
+    if !output.status.success() {
+        return Err(anyhow::Error::msg(format!(
+            "pg_dump failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        )));
+    }
+
+    let info = BackupInfo {
+        path: path.clone(),
+        schema_version: current_schema_version(t).await?,
+        created,
+    };
+    std::fs::write(metadata_path(&path), serde_json::to_string_pretty(&info)?)?;
+
+    tracing::info!("Wrote database backup to {path:?}");
+
+    for stale in list_backups(config)?
+        .into_iter()
+        .rev()
+        .skip(config.retain.max(1))
+    {
+        tracing::info!("Removing backup {:?} past retention", stale.path);
+        std::fs::remove_file(&stale.path)?;
+        std::fs::remove_file(metadata_path(&stale.path))?;
+    }
+
+    Ok(info)
+}
+
+/// Every dump in the backup directory that has metadata, oldest first
+pub fn list_backups(config: &BackupConfig) -> Result<Vec<BackupInfo>, anyhow::Error> {
+    let mut backups = Vec::new();
+
+    if !config.directory.exists() {
+        return Ok(backups);
+    }
+
+    for entry in std::fs::read_dir(&config.directory)? {
+        let path = entry?.path();
+        let is_dump = path.extension().is_some_and(|e| e == DUMP_EXTENSION)
+            && path
+                .file_name()
+                .is_some_and(|n| n.to_string_lossy().starts_with(DUMP_PREFIX));
+
+        if !is_dump {
+            continue;
+        }
+
+        match std::fs::read_to_string(metadata_path(&path))
+            .map_err(anyhow::Error::from)
+            .and_then(|s| Ok(serde_json::from_str::<BackupInfo>(&s)?))
+        {
+            Ok(info) => backups.push(info),
+            Err(e) => tracing::warn!("Skipping backup {path:?} with unreadable metadata: {e}"),
+        }
+    }
+
+    backups.sort_by_key(|b| b.created);
+
+    Ok(backups)
+}
+
+/// Restores the database from a dump, replacing all current contents
+///
+/// Refuses to restore a dump whose schema version differs from the one this build expects
+pub async fn restore(backup: &BackupInfo) -> Result<(), anyhow::Error> {
+    let expected = expected_schema_version();
+    if backup.schema_version != expected {
+        return Err(anyhow::Error::msg(format!(
+            "backup {:?} is at schema version {}, but this build expects {expected}",
+            backup.path, backup.schema_version
+        )));
+    }
+
+    let output = pg_command("pg_restore")
+        .arg("--clean")
+        .arg("--if-exists")
+        .arg("--single-transaction")
+        .arg(&backup.path)
+        .output()
+        .await?;
+
+    if !output.status.success() {
+        return Err(anyhow::Error::msg(format!(
+            "pg_restore failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        )));
+    }
+
+    tracing::warn!("Restored database from backup {:?}", backup.path);
+
+    Ok(())
+}
+
+/// Writes a JSON snapshot of some state into the backup `category` directory,
+/// used to keep a record of aggregates right before they're torn down
+///
+/// Does nothing if backups aren't configured
+pub fn write_snapshot<T: Serialize>(
+    category: &str,
+    name: &str,
+    value: &T,
+) -> Result<Option<PathBuf>, anyhow::Error> {
+    let config = match settings().backup.as_ref() {
+        Some(c) => c,
+        None => return Ok(None),
+    };
+
+    let directory = config.directory.join(category);
+    std::fs::create_dir_all(&directory)?;
+
+    let path = directory.join(format!(
+        "{name}-{}.json",
+        Utc::now().format("%Y%m%dT%H%M%SZ")
+    ));
+    std::fs::write(&path, serde_json::to_string_pretty(value)?)?;
+
+    Ok(Some(path))
+}
+
+/// Takes scheduled dumps forever, if backups are configured
+pub async fn entry() {
+    let config = match settings().backup.clone() {
+        Some(c) => c,
+        None => {
+            tracing::info!("No backup config, not scheduling database backups");
+            return;
+        }
+    };
+
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(
+        config.interval_hours * 60 * 60,
+    ));
+
+    loop {
+        interval.tick().await;
+
+        let res = async {
+            let mut client = crate::new_client().await?;
+            let mut transaction = crate::AsEasyTransaction::easy_transaction(&mut *client).await?;
+            let info = dump(&config, &mut transaction).await;
+            transaction.commit().await?;
+            info
+        }
+        .await;
+
+        if let Err(e) = res {
+            tracing::error!("Scheduled database backup failed: {e:?}");
+        }
+    }
+}
@@ -9,6 +9,7 @@
     trait_alias
 )]
 
+pub mod backup;
 pub mod web;
 
 use common::prelude::{
@@ -419,6 +420,8 @@ pub enum FilterOperation {
 
 pub struct SelectBuilder<T> {
     filters: Vec<Filter>,
+    order: Vec<(String, bool)>,
+    limit: Option<i64>,
     _p: PhantomData<T>,
 }
 
@@ -491,10 +494,26 @@ impl<T: DBTable> SelectBuilder<T> {
     pub fn new() -> Self {
         Self {
             filters: vec![],
+            order: vec![],
+            limit: None,
             _p: Default::default(),
         }
     }
 
+    /// Orders the results by `field_name`, after any fields it was already ordered by
+    ///
+    /// The field name goes into the query as is, so it must never come from a request
+    pub fn order_by(mut self, field_name: &str, descending: bool) -> Self {
+        self.order.push((field_name.to_owned(), descending));
+        self
+    }
+
+    /// Returns at most `count` rows
+    pub fn limit(mut self, count: i64) -> Self {
+        self.limit = Some(count);
+        self
+    }
+
     pub fn where_field(self, field_name: &str) -> WhereBuilder<T> {
         WhereBuilder {
             select: self,
@@ -534,8 +553,27 @@ impl<T: DBTable> SelectBuilder<T> {
             format!("WHERE {clauses}")
         };
 
+        let order_clause = if self.order.is_empty() {
+            String::new()
+        } else {
+            let fields = self
+                .order
+                .iter()
+                .map(|(fname, descending)| match descending {
+                    true => format!("{fname} DESC"),
+                    false => format!("{fname} ASC"),
+                })
+                .join(", ");
+            format!("ORDER BY {fields}")
+        };
+
+        let limit_clause = match self.limit {
+            Some(count) => format!("LIMIT {count}"),
+            None => String::new(),
+        };
+
         let tn = T::table_name();
-        let q = format!("SELECT * FROM {tn} {where_clauses};");
+        let q = format!("SELECT * FROM {tn} {where_clauses} {order_clause} {limit_clause};");
 
         // I'm sorry
         let params: Vec<&(dyn ToSql + Sync)> = self
@@ -1,22 +1,27 @@
-use chrono::Utc;
+use chrono::{DateTime, Utc};
 use common::prelude::chrono::Days;
-use common::prelude::*;
+use common::prelude::{itertools::Itertools, *};
 use dal::{new_client, web::*, AsEasyTransaction, EasyTransaction, FKey, NewRow};
 use metrics::prelude::*;
 
 use models::{
     allocator::{Allocation, AllocationReason},
     dashboard::{
-        Aggregate, AggregateConfiguration, BookingMetadata, HostConfig, Instance, InstanceProvData,
-        LifeCycleState, NetworkAssignmentMap, ProvEvent, StatusSentiment,
+        booking_snapshot::RESTORE_FROM, Aggregate, AggregateConfiguration, AggregateSummary,
+        AggregateTag, BookingEvent, BookingEventKind, BookingMetadata, BookingSnapshot, HostConfig,
+        Instance, InstanceProvData, LifeCycleState, NetworkAssignmentMap, ProvEvent,
+        StatusSentiment,
     },
-    inventory::Lab,
+    inventory::{Flavor, Host, HostIssue, Lab},
 };
 
-use std::collections::HashMap;
+use std::collections::{hash_map::Entry, HashMap};
+use workflows::deploy_booking::{hostnames, parameters};
 use workflows::resource_management::{
-    allocator::Allocator,
+    allocator::{funding_allows, Allocator, ConstraintMatch, MATCH_KEY},
+    estimate,
     ipmi_accounts::{generate_password, generate_username},
+    schedule,
 }; //, ResourceHandle, AggregateID, ResourceHandleInner};
 
 use axum::extract::Json;
@@ -25,22 +30,201 @@ use workflows::entry::*;
 
 use crate::web::api;
 
+use self::quota::{ExceededLimit, QuotaExceeded};
+
+pub mod quota;
+
 pub async fn make_aggregate(blob: api::BookingBlob) -> Result<FKey<Aggregate>, anyhow::Error> {
+    create_aggregate(blob, None).await
+}
+
+/// Books the hosts and networks of the snapshot again, deploying each host with the
+/// disk that was captured from it instead of a fresh install
+pub async fn restore_aggregate(
+    blob: api::BookingBlob,
+    snapshot: &BookingSnapshot,
+) -> Result<FKey<Aggregate>, anyhow::Error> {
+    create_aggregate(blob, Some(snapshot)).await
+}
+
+async fn create_aggregate(
+    blob: api::BookingBlob,
+    restore_from: Option<&BookingSnapshot>,
+) -> Result<FKey<Aggregate>, anyhow::Error> {
+    let mut client = new_client().await?;
+    let mut transaction = client.easy_transaction().await?;
+
+    let queue = blob.queue
+        && restore_from.is_none()
+        && blob.start_date.is_none()
+        && config::settings().booking_queue.enabled;
+    let staged = stage_aggregate(&mut transaction, blob, restore_from).await?;
+    if !staged.exceeded.is_empty() {
+        return Err(QuotaExceeded(staged.exceeded).into());
+    }
+
+    // a booking only waits for hosts, anything else wrong with it won't fix itself
+    let queued =
+        queue && staged.unavailable > 0 && staged.plan.problems.len() == staged.unavailable;
+    if queued {
+        let mut agg = staged.agg.get(&mut transaction).await?;
+        Aggregate::transition(
+            &mut agg,
+            &mut transaction,
+            LifeCycleState::Queued,
+            "no hosts were free, so it waits in the queue",
+            None,
+        )
+        .await?;
+
+        BookingEvent::record(
+            &mut transaction,
+            staged.agg,
+            BookingEventKind::Queued,
+            format!(
+                "no hosts were free for {} of its hosts, so it waits for some to free up",
+                staged.unavailable
+            ),
+            None,
+        )
+        .await?;
+    } else if !staged.plan.problems.is_empty() {
+        // the transaction rolls back as it is dropped, letting go of anything acquired
+        return Err(Unbookable(staged.plan).into());
+    }
+
+    AggregateSummary::refresh(&mut transaction, staged.agg).await?;
+    transaction.commit().await?;
+
+    match MetricHandler::send(staged.metric) {
+        Ok(_) => {
+            tracing::info!("Sent booking metric");
+        }
+        Err(e) => {
+            tracing::error!("Failed to send booking metric with error {}", e)
+        }
+    }
+
+    if queued || staged.scheduled {
+        // the queue starts provisioning once hosts free up, and scheduled bookings once they start
+        return Ok(staged.agg);
+    }
+
+    // Ask tascii to provision the host
+    let res = DISPATCH
+        .get()
+        .unwrap()
+        .send(Action::DeployBooking { agg_id: staged.agg });
+    if let Err(e) = res {
+        tracing::error!("Failed to send deploy task with error {:#?}", e)
+    }
+
+    Ok(staged.agg)
+}
+
+/// Goes through everything creating the booking would, without keeping any of it or
+/// starting to provision, and says what the booking would have been given
+pub async fn plan_aggregate(blob: api::BookingBlob) -> Result<api::BookingPlan, anyhow::Error> {
     let mut client = new_client().await?;
     let mut transaction = client.easy_transaction().await?;
 
-    let prov_data: Vec<InstanceProvData> = Vec::new();
+    let length = blob.metadata.length;
+    let staged = stage_aggregate(&mut transaction, blob, None).await?;
+    let estimate = estimate::estimate(&mut transaction, &staged.flavors, length).await?;
+    transaction.rollback().await?;
+
+    let mut plan = staged.plan;
+    plan.estimate = Some(estimate);
+    plan.bookable = plan.problems.is_empty();
+
+    Ok(plan)
+}
+
+/// What stood in the way of creating a booking
+#[derive(Debug)]
+pub struct Unbookable(pub api::BookingPlan);
+
+impl std::fmt::Display for Unbookable {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0.problems.join("; "))
+    }
+}
+
+impl std::error::Error for Unbookable {}
+
+/// A booking written out within a transaction that hasn't been committed yet
+struct Staged {
+    agg: FKey<Aggregate>,
+    /// Problems in here mean the transaction must not be committed
+    plan: api::BookingPlan,
+    /// The quotas the booking would go over, which are among the problems of the plan too
+    exceeded: Vec<ExceededLimit>,
+    /// The flavor of each host, once those requested by constraints have been matched
+    flavors: Vec<FKey<Flavor>>,
+    /// How many of the problems of the plan are hosts that no host was free to fill
+    unavailable: usize,
+    /// Whether the booking starts later, and only reserved its hosts
+    scheduled: bool,
+    metric: BookingMetric,
+}
+
+async fn stage_aggregate(
+    transaction: &mut EasyTransaction<'_>,
+    blob: api::BookingBlob,
+    restore_from: Option<&BookingSnapshot>,
+) -> Result<Staged, anyhow::Error> {
+    let mut plan = api::BookingPlan::default();
+
+    // a booking made for someone else is theirs, and whoever made it keeps access to it
+    let mut blob = blob;
+    let booked_by = match blob.owner.take() {
+        Some(owner) if blob.metadata.owner.as_ref() != Some(&owner) => {
+            let booked_by = blob.metadata.owner.replace(owner);
+            blob.allowed_users.extend(booked_by.clone());
+            booked_by
+        }
+        _ => None,
+    };
+
     let template = blob
         .template_id
-        .get(&mut transaction)
+        .get(transaction)
         .await
         .expect("couldn't get template for booking blob");
 
+    // what this booking is made of, once the template's parameters are filled in, or
+    // exactly what the booking the snapshot was taken of had
+    let mut expanded = match restore_from {
+        Some(snapshot) => parameters::Expanded {
+            hosts: snapshot.hosts.iter().map(|h| h.config.clone()).collect(),
+            networks: snapshot.networks.iter().map(|n| n.network).collect(),
+        },
+        None => parameters::expand(transaction, &template, &blob.parameters).await?,
+    };
+    let disks = match restore_from {
+        Some(snapshot) => snapshot
+            .hosts
+            .iter()
+            .map(|h| Some(h.disk_image.clone()))
+            .collect_vec(),
+        None => vec![None; expanded.hosts.len()],
+    };
+
+    // constraints given with the booking replace those the template gives its hosts
+    for (hostname, constraints) in std::mem::take(&mut blob.constraints) {
+        match expanded.hosts.iter_mut().find(|h| h.hostname == hostname) {
+            Some(host) => host.constraints = Some(constraints),
+            None => plan.problems.push(format!(
+                "the template has no host {hostname} to set constraints for"
+            )),
+        }
+    }
+
     let netmap = NewRow::new(NetworkAssignmentMap::empty())
-        .insert(&mut transaction)
+        .insert(transaction)
         .await?;
 
-    let _ = config::settings()
+    let project = config::settings()
         .projects
         .get(&blob.origin)
         .ok_or(anyhow::Error::msg(format!(
@@ -48,7 +232,30 @@ pub async fn make_aggregate(blob: api::BookingBlob) -> Result<FKey<Aggregate>, a
             &blob.origin
         )))?;
 
+    // make sure every notice about this booking has somewhere to go before taking it
+    let usernames = blob
+        .metadata
+        .owner
+        .iter()
+        .chain(blob.allowed_users.iter())
+        .unique()
+        .cloned()
+        .collect_vec();
+    for username in usernames {
+        let contact = notifications::contacts::verify(&username).await?;
+        if contact.unreachable() {
+            tracing::warn!(
+                "{username} is on a new booking, but mail to {} has been bouncing: {:?}",
+                contact.email,
+                contact.last_error
+            );
+        }
+    }
+
     let now = Utc::now();
+    let start = blob.start_date.filter(|start| *start > now);
+    let scheduled = start.is_some();
+    let start = start.unwrap_or(now);
 
     let booking_id: i32 = blob
         .metadata
@@ -58,10 +265,10 @@ pub async fn make_aggregate(blob: api::BookingBlob) -> Result<FKey<Aggregate>, a
         .parse()
         .unwrap_or_default();
 
-    let booking = BookingMetric {
+    let metric = BookingMetric {
         booking_id,
         booking_length_days: blob.metadata.length.unwrap_or_default() as i32,
-        num_hosts: template.hosts.len() as i32,
+        num_hosts: expanded.hosts.len() as i32,
         num_collaborators: blob
             .allowed_users
             .iter()
@@ -77,18 +284,12 @@ pub async fn make_aggregate(blob: api::BookingBlob) -> Result<FKey<Aggregate>, a
         ..Default::default()
     };
 
-    match MetricHandler::send(booking) {
-        Ok(_) => {
-            tracing::info!("Sent booking metric");
-        }
-        Err(e) => {
-            tracing::error!("Failed to send booking metric with error {}", e)
-        }
-    }
-
     let agg = NewRow::new(Aggregate {
-        state: LifeCycleState::New,
-        lab: Lab::get_by_name(&mut transaction, blob.origin.clone())
+        state: match scheduled {
+            true => LifeCycleState::Scheduled,
+            false => LifeCycleState::New,
+        },
+        lab: Lab::get_by_name(transaction, blob.origin.clone())
             .await
             .expect("Expected to find lab")
             .expect("Expected lab to exist")
@@ -101,6 +302,7 @@ pub async fn make_aggregate(blob: api::BookingBlob) -> Result<FKey<Aggregate>, a
         configuration: AggregateConfiguration {
             ipmi_username: generate_username(10),
             ipmi_password: generate_password(15),
+            env: blob.env,
         },
         metadata: BookingMetadata {
             booking_id: blob.metadata.booking_id,
@@ -108,41 +310,142 @@ pub async fn make_aggregate(blob: api::BookingBlob) -> Result<FKey<Aggregate>, a
             lab: blob.metadata.lab,
             purpose: blob.metadata.purpose,
             project: blob.metadata.project,
-            start: Some(now),
-            end: blob.metadata.length.map(|l| now + Days::new(l)),
+            start: Some(start),
+            end: blob.metadata.length.map(|l| start + Days::new(l)),
+            performance_tolerance: blob.metadata.performance_tolerance,
+            spread: blob.metadata.spread,
+            display_name: blob
+                .metadata
+                .display_name
+                .map(|n| n.trim().to_owned())
+                .filter(|n| !n.is_empty()),
+            favorited_by: Vec::new(),
+            telemetry: blob.metadata.telemetry.unwrap_or(false),
+            exempt_from_expiry: false,
+            ownership_override: blob.ownership_override.clone(),
         },
     })
-    .insert(&mut transaction)
+    .insert(transaction)
     .await
     .expect("couldn't create the aggregate")
-    .get(&mut transaction)
+    .get(transaction)
     .await
     .unwrap();
 
+    // held against the hosts the booking's owner and project already have, before it has any
+    let exceeded = quota::exceeded(
+        transaction,
+        &project.quotas,
+        &blob.origin,
+        &agg,
+        expanded.hosts.len(),
+    )
+    .await?;
+    plan.problems.extend(exceeded.iter().map(|e| e.to_string()));
+
     let allocator = Allocator::instance();
 
-    // try alloc, bailing out if this aggregate could not possibly be deployed (also letting any
-    // acquired vlans roll back as we unwind)
-    {
+    // try alloc, noting each role that could not possibly be filled (also letting any
+    // acquired hosts roll back as we unwind), and which host would fill the others
+    let mut picked = Vec::new();
+    let mut short = Vec::new();
+    let mut unavailable = 0;
+    if scheduled {
+        let constrained = expanded.hosts.iter().any(|h| h.constraints.is_some());
+        let reserved = match (agg.metadata.end, constrained) {
+            (None, _) => Err("a booking that starts later has to be given a length"),
+            (_, true) => Err(
+                "hosts requested by constraints can only be matched for bookings that start right away",
+            ),
+            (Some(end), false) => {
+                Ok(reserve_ahead(transaction, &agg, &expanded.hosts, start, end).await?)
+            }
+        };
+
+        match reserved {
+            Ok(reserved) => {
+                for (config, host) in expanded.hosts.iter().zip(reserved) {
+                    match host {
+                        Some(host) => picked.push(host),
+                        None => {
+                            plan.problems.push(format!(
+                                "no host was free to fill the role of {} over the whole booking",
+                                config.hostname
+                            ));
+                            picked.push((None, vec![], None));
+                        }
+                    }
+                }
+            }
+            Err(problem) => {
+                plan.problems.push(problem.to_owned());
+                picked.extend(expanded.hosts.iter().map(|_| (None, vec![], None)));
+            }
+        }
+    } else {
         let mut ct = transaction.easy_transaction().await?;
         let mut to_free = Vec::new();
 
-        for inst in template.hosts.iter() {
-            let hn = &inst.hostname;
-            let h = allocator
-                .allocate_host(
-                    &mut ct,
-                    inst.flavor,
-                    agg.id,
-                    AllocationReason::ForBooking,
-                    true,
-                )
-                .await
-                .map_err(|_| {
-                    anyhow::Error::msg(format!("no host was available to fill the role of {hn}"))
-                })?;
-
-            to_free.push(h);
+        for inst in expanded.hosts.iter_mut() {
+            let hn = inst.hostname.clone();
+
+            // restored hosts keep the flavor their disks were taken from
+            let allocated = match (&inst.constraints, restore_from) {
+                (Some(constraints), None) => {
+                    let image = inst.image.get(&mut ct).await?;
+                    allocator
+                        .allocate_matching(
+                            &mut ct,
+                            constraints,
+                            &image,
+                            agg.id,
+                            AllocationReason::ForBooking,
+                            true,
+                        )
+                        .await
+                        .map(|(host, handle, matched)| {
+                            inst.flavor = matched.flavor;
+                            (host, handle, Some(matched))
+                        })
+                }
+                _ => allocator
+                    .allocate_host(
+                        &mut ct,
+                        inst.flavor,
+                        agg.id,
+                        AllocationReason::ForBooking,
+                        true,
+                    )
+                    .await
+                    .map(|(host, handle)| (host, handle, None)),
+            };
+
+            match allocated {
+                Ok((host, handle, matched)) => {
+                    let issues = HostIssue::active_for_host(&mut ct, host)
+                        .await?
+                        .iter()
+                        .map(|i| i.summary.clone())
+                        .collect_vec();
+                    picked.push((
+                        Some(host.get(&mut ct).await?.server_name.clone()),
+                        issues,
+                        matched,
+                    ));
+                    to_free.push((host, handle));
+                }
+                Err(e) => {
+                    plan.problems.push(format!(
+                        "no host was available to fill the role of {hn}: {e}"
+                    ));
+                    picked.push((None, vec![], None));
+                    unavailable += 1;
+                    // there's no one flavor to tell the shortfall of for constraints
+                    if inst.constraints.is_none() || restore_from.is_some() {
+                        short.push(inst.flavor);
+                    }
+                }
+            }
         }
 
         for (host, handle) in to_free {
@@ -154,25 +457,105 @@ pub async fn make_aggregate(blob: api::BookingBlob) -> Result<FKey<Aggregate>, a
         // rollback if we can to not clutter allocation table (remember, transaction
         // is all or nothing, so we could end up with the first part but not the last part!)
         ct.rollback().await.unwrap();
-    };
+    }
 
     // release those allocations
-    for mut allocation in Allocation::all_for_aggregate(&mut transaction, agg.id).await? {
+    for mut allocation in Allocation::all_for_aggregate(transaction, agg.id).await? {
         allocation.ended = Some(Utc::now());
-        allocation.update(&mut transaction).await?;
+        allocation.update(transaction).await?;
     }
 
-    allocator
-        .allocate_vlans_for(&mut transaction, agg.id, template.networks.clone(), netmap)
-        .await?;
+    // for each flavor there weren't enough hosts of, what kept them from the booking
+    for flavor in short.into_iter().unique() {
+        let needed = expanded.hosts.iter().filter(|h| h.flavor == flavor).count();
+        plan.shortfalls.push(api::FlavorShortfall {
+            flavor: flavor.get(transaction).await?.name.clone(),
+            needed,
+            availability: allocator
+                .availability(transaction, flavor, agg.lab, agg.id, needed)
+                .await?,
+        });
+    }
+
+    if let Err(e) = allocator
+        .allocate_vlans_for(transaction, agg.id, expanded.networks.clone(), netmap)
+        .await
+    {
+        plan.problems
+            .push(format!("not enough vlans were free for the networks: {e}"));
+    }
+
+    let assigned = netmap.get(transaction).await?.into_inner().networks;
+    for network in expanded.networks.iter() {
+        let net = network.get(transaction).await?;
+        let vlan = match assigned.get(network) {
+            Some(vlan) => Some(vlan.get(transaction).await?.vlan_id),
+            None => None,
+        };
 
-    for host_config in template.hosts.clone() {
+        plan.networks.push(api::PlannedNetwork {
+            name: net.name.clone(),
+            public: net.public,
+            vlan,
+        });
+    }
+
+    for host_config in expanded.hosts.clone() {
         // create instance from config
     }
 
-    for config in template.hosts.clone() {
+    let host_count = expanded.hosts.len();
+    let flavors = expanded.hosts.iter().map(|h| h.flavor).collect_vec();
+    let mut host_configs = expanded.hosts;
+    let mut roles = Vec::new();
+    for config in host_configs.iter() {
+        roles.push((
+            config.hostname.clone(),
+            config.flavor.get(transaction).await?.name.clone(),
+        ));
+    }
+
+    let vars = hostnames::HostnameVars {
+        project: blob.origin.clone(),
+        lab: agg.metadata.lab.clone().unwrap_or_default(),
+        owner: agg.metadata.owner.clone().unwrap_or_default(),
+        booking_id: agg.metadata.booking_id.clone().unwrap_or_default(),
+        booking_short: agg.id.into_id().to_string().chars().take(8).collect(),
+    };
+    let hostname_template = project
+        .hostname_template
+        .as_deref()
+        .unwrap_or(hostnames::DEFAULT_TEMPLATE);
+    // restored hosts keep the names they had, as their disks were set up with them
+    let names = match restore_from {
+        Some(_) => roles.iter().map(|(hostname, _)| hostname.clone()).collect(),
+        None => hostnames::assign(hostname_template, &vars, &roles)?,
+    };
+    for (config, hostname) in host_configs.iter_mut().zip(names) {
+        config.hostname = hostname;
+    }
+
+    for (((config, (_, flavor)), (host, known_issues, matched)), disk) in
+        host_configs.into_iter().zip(roles).zip(picked).zip(disks)
+    {
         tracing::debug!("got config_info {config:?}");
 
+        let image = config.image.get(transaction).await?;
+        if !image.flavors.contains(&config.flavor) {
+            plan.problems.push(format!(
+                "{} can't be installed on {flavor} hosts like {}",
+                image.name, config.hostname
+            ));
+        }
+        plan.hosts.push(api::PlannedHost {
+            hostname: config.hostname.clone(),
+            flavor,
+            image: image.name.clone(),
+            host,
+            known_issues,
+            matched: matched.clone(),
+        });
+
         let mut instance = InstanceProvData {
             hostname: config.hostname.clone(),
             flavor: config.flavor,
@@ -184,15 +567,22 @@ pub async fn make_aggregate(blob: api::BookingBlob) -> Result<FKey<Aggregate>, a
 
         // Resource processing:
         // Hardware
-        hardware_conf(&mut transaction, &mut instance, config.clone()).await;
+        hardware_conf(transaction, &mut instance, config.clone()).await;
         // CI Files
-        ci_processing(&mut transaction, &mut instance, config.clone()).await;
+        ci_processing(transaction, &mut instance, config.clone()).await;
         // Finalize
         // Push prov data to vec
         let inst_id = FKey::new_id_dangling();
 
+        let mut metadata: HashMap<String, serde_json::Value> = disk
+            .map(|d| HashMap::from([(RESTORE_FROM.to_owned(), serde_json::Value::String(d))]))
+            .unwrap_or_default();
+        if let Some(matched) = matched {
+            metadata.insert(MATCH_KEY.to_owned(), serde_json::to_value(matched)?);
+        }
+
         let instance = Instance {
-            metadata: HashMap::new(),
+            metadata,
             aggregate: agg.id,
             id: inst_id,
             within_template: template.id,
@@ -201,11 +591,11 @@ pub async fn make_aggregate(blob: api::BookingBlob) -> Result<FKey<Aggregate>, a
             linked_host: None,
         };
 
-        let inst_fk = NewRow::new(instance).insert(&mut transaction).await?;
+        let inst_fk = NewRow::new(instance).insert(transaction).await?;
 
         let _ = Instance::log(
             inst_fk,
-            &mut transaction,
+            transaction,
             ProvEvent::new(
                 "Pre-Provision",
                 "Configuration has been created, host not yet selected",
@@ -215,21 +605,265 @@ pub async fn make_aggregate(blob: api::BookingBlob) -> Result<FKey<Aggregate>, a
         .await;
     }
 
+    let details = match restore_from {
+        Some(snapshot) => format!(
+            "restored from snapshot {} with {} host(s)",
+            snapshot.name, host_count
+        ),
+        None => format!(
+            "booked from template {} with {} host(s)",
+            template.name, host_count
+        ),
+    };
+    let details = match &booked_by {
+        Some(booked_by) => format!(
+            "{details}, by {booked_by} on behalf of {}",
+            agg.metadata.owner.clone().unwrap_or_default()
+        ),
+        None => details,
+    };
+    AggregateTag::retag(
+        transaction,
+        agg.id,
+        &blob.tags,
+        &[],
+        booked_by.clone().or(agg.metadata.owner.clone()),
+    )
+    .await?;
+
+    BookingEvent::record(
+        transaction,
+        agg.id,
+        BookingEventKind::Created,
+        details,
+        booked_by.clone().or(agg.metadata.owner.clone()),
+    )
+    .await?;
+
+    // whoever made the booking is an admin, as only they can override
+    if let Some(reason) = &agg.metadata.ownership_override {
+        tracing::warn!(
+            "Booking {:?} may be given hosts funded by other projects, overridden by {:?}: {reason}",
+            agg.id,
+            booked_by.as_ref().or(agg.metadata.owner.as_ref())
+        );
+        BookingEvent::record(
+            transaction,
+            agg.id,
+            BookingEventKind::OwnershipOverridden,
+            format!("may be given hosts funded by other projects: {reason}"),
+            booked_by.or(agg.metadata.owner.clone()),
+        )
+        .await?;
+    }
+
+    Ok(Staged {
+        agg: agg.id,
+        plan,
+        exceeded,
+        flavors,
+        unavailable,
+        scheduled,
+        metric,
+    })
+}
+
+/// Adds hosts configured as `configs` to a running booking and starts deploying them,
+/// failing without adding any if there aren't enough free hosts for all of them
+pub async fn expand_aggregate(
+    agg_id: FKey<Aggregate>,
+    configs: Vec<HostConfig>,
+    requested_by: Option<String>,
+) -> Result<Vec<FKey<Instance>>, anyhow::Error> {
+    let mut client = new_client().await?;
+    let mut transaction = client.easy_transaction().await?;
+
+    let agg = agg_id.get(&mut transaction).await?;
+    let lab = agg.lab.get(&mut transaction).await?.name.clone();
+    let project = config::settings()
+        .projects
+        .get(&lab)
+        .ok_or(anyhow::Error::msg(format!(
+            "no project supported by origin name {lab}"
+        )))?;
+
+    let allocator = Allocator::instance();
+
+    // the same trial allocation as for a new booking, next to the hosts it already has
+    {
+        let mut ct = transaction.easy_transaction().await?;
+        let mut to_free = Vec::new();
+
+        for config in configs.iter() {
+            let flavor = config.flavor.get(&mut ct).await?.name.clone();
+            let h = allocator
+                .allocate_host(
+                    &mut ct,
+                    config.flavor,
+                    agg.id,
+                    AllocationReason::ForBooking,
+                    true,
+                )
+                .await
+                .map_err(|e| {
+                    anyhow::Error::msg(format!("no {flavor} host was available to add: {e}"))
+                })?;
+
+            to_free.push(h);
+        }
+
+        for (_, handle) in to_free {
+            allocator.deallocate_host(&mut ct, handle, agg.id).await?;
+        }
+
+        ct.rollback().await?;
+    }
+
+    let mut existing = Vec::new();
+    for instance in agg.instances(&mut transaction).await? {
+        existing.push((
+            instance.config.hostname.clone(),
+            instance
+                .config
+                .flavor
+                .get(&mut transaction)
+                .await?
+                .name
+                .clone(),
+        ));
+    }
+    let mut roles = Vec::new();
+    for config in configs.iter() {
+        roles.push((
+            config.hostname.clone(),
+            config.flavor.get(&mut transaction).await?.name.clone(),
+        ));
+    }
+
+    let vars = hostnames::HostnameVars {
+        project: lab.clone(),
+        lab: agg.metadata.lab.clone().unwrap_or_default(),
+        owner: agg.metadata.owner.clone().unwrap_or_default(),
+        booking_id: agg.metadata.booking_id.clone().unwrap_or_default(),
+        booking_short: agg.id.into_id().to_string().chars().take(8).collect(),
+    };
+    let hostname_template = project
+        .hostname_template
+        .as_deref()
+        .unwrap_or(hostnames::DEFAULT_TEMPLATE);
+    let names = hostnames::assign_after(hostname_template, &vars, &existing, &roles)?;
+
+    let mut instances = Vec::new();
+    let mut added = Vec::new();
+    for (mut config, hostname) in configs.into_iter().zip(names) {
+        config.hostname = hostname;
+        added.push(config.hostname.clone());
+
+        let inst_fk = NewRow::new(Instance {
+            metadata: HashMap::new(),
+            aggregate: agg.id,
+            id: FKey::new_id_dangling(),
+            within_template: agg.template,
+            config,
+            network_data: agg.vlans,
+            linked_host: None,
+        })
+        .insert(&mut transaction)
+        .await?;
+
+        let _ = Instance::log(
+            inst_fk,
+            &mut transaction,
+            ProvEvent::new(
+                "Pre-Provision",
+                "Added to the running booking, host not yet selected",
+            ),
+            Some(StatusSentiment::Unknown),
+        )
+        .await;
+
+        instances.push(inst_fk);
+    }
+
+    BookingEvent::record(
+        &mut transaction,
+        agg.id,
+        BookingEventKind::Expanded,
+        format!("added {} host(s): {}", added.len(), added.join(", ")),
+        requested_by,
+    )
+    .await?;
+
+    AggregateSummary::refresh(&mut transaction, agg.id).await?;
     transaction.commit().await?;
 
-    // Ask tascii to provision the host
-    let res = DISPATCH
-        .get()
-        .unwrap()
-        .send(Action::DeployBooking { agg_id: agg.id });
-    match res {
-        Err(e) => {
-            tracing::error!("Failed to send deploy task with error {:#?}", e)
+    let res = DISPATCH.get().unwrap().send(Action::ExpandBooking {
+        agg_id: agg.id,
+        instances: instances.clone(),
+    });
+    if let Err(e) = res {
+        tracing::error!("Failed to send expand task with error {:#?}", e)
+    }
+
+    Ok(instances)
+}
+
+/// Reserves a host of its flavor for each of `hosts` from `start` until `end`, for a booking
+/// that starts later, and says which host each got along with its known issues
+async fn reserve_ahead(
+    transaction: &mut EasyTransaction<'_>,
+    agg: &Aggregate,
+    hosts: &[HostConfig],
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+) -> Result<Vec<Option<(Option<String>, Vec<String>, Option<ConstraintMatch>)>>, anyhow::Error> {
+    let allocator = Allocator::instance();
+
+    let mut free: HashMap<FKey<Flavor>, Vec<FKey<Host>>> = HashMap::new();
+    let mut reserved = Vec::new();
+    for config in hosts {
+        let candidates = match free.entry(config.flavor) {
+            Entry::Occupied(e) => e.into_mut(),
+            Entry::Vacant(e) => e.insert(
+                schedule::free_hosts(transaction, config.flavor, agg.lab, start, end).await?,
+            ),
+        };
+        let mut got = None;
+        while got.is_none() && !candidates.is_empty() {
+            let host = candidates.remove(0);
+            let funded_by = host.get(transaction).await?.funded_by.clone();
+            if agg.metadata.ownership_override.is_none()
+                && !funding_allows(funded_by.as_deref(), agg.metadata.project.as_deref())
+            {
+                continue;
+            }
+            match allocator
+                .reserve_host(transaction, host, agg.id, start, Some(end))
+                .await
+            {
+                Ok(_) => got = Some(host),
+                Err(e) => tracing::debug!("Couldn't reserve {host:?} for {:?}: {e}", agg.id),
+            }
         }
-        Ok(_) => {}
+
+        reserved.push(match got {
+            Some(host) => {
+                let issues = HostIssue::active_for_host(transaction, host)
+                    .await?
+                    .iter()
+                    .map(|i| i.summary.clone())
+                    .collect_vec();
+                Some((
+                    Some(host.get(transaction).await?.server_name.clone()),
+                    issues,
+                    None,
+                ))
+            }
+            None => None,
+        });
     }
 
-    Ok(agg.id)
+    Ok(reserved)
 }
 
 async fn hardware_conf(
@@ -271,11 +905,28 @@ pub async fn end_booking(agg_id: FKey<Aggregate>) -> Result<(), anyhow::Error> {
                 Err(_) => Err(anyhow::anyhow!("Failed to dispatch end booking job!")),
             }
         }
+        LifeCycleState::Queued | LifeCycleState::Scheduled => {
+            // nothing was deployed, so there is nothing to clean up
+            let details = match agg.state {
+                LifeCycleState::Queued => "ended while waiting in the queue",
+                _ => "ended before it started",
+            };
+            workflows::resource_management::queue::withdraw(
+                &mut transaction,
+                agg_id,
+                details.to_owned(),
+                None,
+            )
+            .await?;
+            transaction.commit().await?;
+
+            Ok(())
+        }
         LifeCycleState::New => Err(anyhow::anyhow!(
             "Cannot end booking while still provisioning!"
         )),
-        LifeCycleState::Done => {
-            // Failed bookings are set to "Done"
+        LifeCycleState::TearingDown | LifeCycleState::Failed | LifeCycleState::Done => {
+            // already being ended, or has nothing left to clean up
             Ok(())
         }
     }
@@ -0,0 +1,164 @@
+//! How much of a lab one booking, user or project may take, so that no one books all of it
+//! by accident
+//!
+//! Limits are configured per project of the lab, and admins can lift them for a user or a
+//! dashboard project for a while with a [`QuotaOverride`].
+
+use common::prelude::{anyhow, chrono::Utc, config::QuotaConfig};
+use dal::EasyTransaction;
+use models::dashboard::{Aggregate, QuotaOverride};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, JsonSchema, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum QuotaLimit {
+    LengthDays,
+    HostsPerUser,
+    HostsPerProject,
+}
+
+/// One limit a booking would have gone over
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ExceededLimit {
+    pub limit: QuotaLimit,
+    /// The user or project the limit is on, None for the length of the booking
+    pub of: Option<String>,
+    pub allowed: u64,
+    /// What the booking would have come to, counting the hosts already booked for hosts.
+    /// None for bookings without an end
+    pub requested: Option<u64>,
+}
+
+impl std::fmt::Display for ExceededLimit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let of = self.of.as_deref().unwrap_or_default();
+        let requested = self
+            .requested
+            .map(|r| r.to_string())
+            .unwrap_or("no end".to_owned());
+
+        match self.limit {
+            QuotaLimit::LengthDays => write!(
+                f,
+                "bookings can be at most {} days long, this one would be {requested}",
+                self.allowed
+            ),
+            QuotaLimit::HostsPerUser => write!(
+                f,
+                "{of} can hold at most {} hosts at once, this booking would take them to {requested}",
+                self.allowed
+            ),
+            QuotaLimit::HostsPerProject => write!(
+                f,
+                "project {of} can hold at most {} hosts at once, this booking would take it to {requested}",
+                self.allowed
+            ),
+        }
+    }
+}
+
+/// Why a booking was turned away for going over its quotas
+#[derive(Debug, Clone)]
+pub struct QuotaExceeded(pub Vec<ExceededLimit>);
+
+impl std::fmt::Display for QuotaExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let limits: Vec<String> = self.0.iter().map(|l| l.to_string()).collect();
+        write!(f, "{}", limits.join("; "))
+    }
+}
+
+impl std::error::Error for QuotaExceeded {}
+
+/// The most any of `overrides` raises a limit to, by the value `by` gives of each
+fn raised(overrides: &[&QuotaOverride], by: impl Fn(&QuotaOverride) -> Option<i64>) -> Option<u64> {
+    overrides
+        .iter()
+        .filter_map(|o| by(o))
+        .map(|v| v.max(0) as u64)
+        .max()
+}
+
+/// What the booking `agg`, which has no instances yet, would go over by taking `hosts`
+/// hosts, given the quotas of the project of the lab it's made through
+pub async fn exceeded(
+    t: &mut EasyTransaction<'_>,
+    quotas: &QuotaConfig,
+    origin: &str,
+    agg: &Aggregate,
+    hosts: usize,
+) -> Result<Vec<ExceededLimit>, anyhow::Error> {
+    let owner = agg.metadata.owner.as_deref();
+    let project = agg.metadata.project.as_deref();
+
+    let overrides = QuotaOverride::applying_to(t, origin, owner, project, Utc::now()).await?;
+    let all: Vec<&QuotaOverride> = overrides.iter().map(|o| &**o).collect();
+    let of_owner: Vec<&QuotaOverride> = all
+        .iter()
+        .copied()
+        .filter(|o| o.username.is_some() && o.username.as_deref() == owner)
+        .collect();
+    let of_project: Vec<&QuotaOverride> = all
+        .iter()
+        .copied()
+        .filter(|o| o.project.is_some() && o.project.as_deref() == project)
+        .collect();
+
+    let mut exceeded = Vec::new();
+
+    // overrides of either kind can lengthen the booking
+    if let Some(configured) = quotas.max_length_days {
+        let allowed = raised(&all, |o| o.max_length_days).map_or(configured, |r| r.max(configured));
+        let length_days = agg
+            .metadata
+            .end
+            .zip(agg.metadata.start)
+            .map(|(end, start)| (end - start).num_days() as u64);
+
+        if length_days.map_or(true, |l| l > allowed) {
+            exceeded.push(ExceededLimit {
+                limit: QuotaLimit::LengthDays,
+                of: None,
+                allowed,
+                requested: length_days,
+            });
+        }
+    }
+
+    if let (Some(owner), Some(configured)) = (owner, quotas.max_hosts_per_user) {
+        let configured = configured as u64;
+        let allowed = raised(&of_owner, |o| o.max_hosts.map(i64::from))
+            .map_or(configured, |r| r.max(configured));
+        let held = Aggregate::hosts_held(t, agg.lab, Some(owner), None).await?;
+
+        let requested = (held + hosts) as u64;
+        if requested > allowed {
+            exceeded.push(ExceededLimit {
+                limit: QuotaLimit::HostsPerUser,
+                of: Some(owner.to_owned()),
+                allowed,
+                requested: Some(requested),
+            });
+        }
+    }
+
+    if let (Some(project), Some(configured)) = (project, quotas.max_hosts_per_project) {
+        let configured = configured as u64;
+        let allowed = raised(&of_project, |o| o.max_hosts.map(i64::from))
+            .map_or(configured, |r| r.max(configured));
+        let held = Aggregate::hosts_held(t, agg.lab, None, Some(project)).await?;
+
+        let requested = (held + hosts) as u64;
+        if requested > allowed {
+            exceeded.push(ExceededLimit {
+                limit: QuotaLimit::HostsPerProject,
+                of: Some(project.to_owned()),
+                allowed,
+                requested: Some(requested),
+            });
+        }
+    }
+
+    Ok(exceeded)
+}
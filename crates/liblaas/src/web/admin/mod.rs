@@ -0,0 +1,63 @@
+//! Reports on the whole fleet, for admins planning work across it
+
+use super::{identity::Admin, AppState, WebError};
+use aide::axum::{routing::get, ApiRouter};
+use axum::{
+    extract::{Json, Query},
+    http::header,
+    response::{IntoResponse, Response},
+};
+use dal::{new_client, web::*, AsEasyTransaction};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use workflows::resource_management::firmware::{self, FirmwareReport};
+
+pub fn routes(_state: AppState) -> ApiRouter {
+    ApiRouter::new().route("/firmware-compliance", get(firmware_compliance))
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ReportFormat {
+    #[default]
+    Json,
+    /// One row per NIC of each host
+    Csv,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ReportParams {
+    #[serde(default)]
+    format: ReportFormat,
+}
+
+/// How many hosts are running the firmware configured for their flavor, and which aren't
+#[axum::debug_handler]
+async fn firmware_compliance(
+    _admin: Admin,
+    Query(params): Query<ReportParams>,
+) -> Result<Response, WebError> {
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+    let report: FirmwareReport = firmware::compliance_report(&mut transaction)
+        .await
+        .log_server_error("Unable to compare host firmware against baselines", true)?;
+
+    transaction.commit().await.log_db_client_error()?;
+
+    Ok(match params.format {
+        ReportFormat::Json => Json(report).into_response(),
+        ReportFormat::Csv => (
+            [
+                (header::CONTENT_TYPE, "text/csv"),
+                (
+                    header::CONTENT_DISPOSITION,
+                    "attachment; filename=\"firmware-compliance.csv\"",
+                ),
+            ],
+            firmware::to_csv(&report),
+        )
+            .into_response(),
+    })
+}
@@ -4,6 +4,7 @@ use common::prelude::chrono::{DateTime, Utc};
 use common::prelude::tokio_postgres;
 use models::dashboard::NetworkBlob;
 use std::borrow::{Borrow, BorrowMut};
+use std::collections::{BTreeMap, HashMap};
 use std::marker::PhantomData;
 use std::net::{Ipv4Addr, Ipv6Addr};
 use strum_macros::Display;
@@ -11,12 +12,19 @@ use tokio_postgres::types::{BorrowToSql, ToSql};
 
 use dal::*;
 use models::{
-    dashboard::{Aggregate, Image, Template},
+    dashboard::{
+        Aggregate, HostConstraints, Image, ParameterValue, Template, TemplateAccess,
+        TemplateParameter,
+    },
     inventory::{self, CardType, DataValue, Flavor},
 };
 
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use workflows::resource_management::{
+    allocator::{ConstraintMatch, HostAvailability},
+    estimate::BookingEstimate,
+};
 
 use common::prelude::*;
 
@@ -48,6 +56,13 @@ pub struct TemplateBlob {
     pub host_list: Vec<HostConfigBlob>,
     ///
     pub networks: Vec<NetworkBlob>,
+    /// What each booking of the template picks for itself
+    #[serde(default)]
+    pub parameters: Vec<TemplateParameter>,
+    /// What the user a list of templates was made for may do with this one,
+    /// ignored when creating a template
+    #[serde(default)]
+    pub access: Option<TemplateAccess>,
 }
 
 /// Lower level blob containing the configuration for a single host in a template
@@ -63,6 +78,10 @@ pub struct HostConfigBlob {
     pub cifile: Vec<String>,
     ///
     pub bondgroups: Vec<BondgroupBlob>,
+    /// Set when any host meeting the constraints will do, `flavor` is then only the
+    /// flavor the template was drawn up with
+    #[serde(default)]
+    pub constraints: Option<HostConstraints>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
@@ -73,6 +92,8 @@ pub struct HostBlob {
     pub flavor: FKey<inventory::Flavor>,
     pub ipmi_fqdn: String,
     pub allocation: Option<AllocationBlob>,
+    /// Latest standard benchmark score, if the host has been benchmarked
+    pub benchmark_score: Option<f64>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
@@ -118,6 +139,20 @@ pub struct ImageBlob {
     pub name: String,
 }
 
+/// An image as the image list shows it, with where it can be used and whether it is being retired
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+pub struct ImageDetailBlob {
+    pub image_id: FKey<Image>,
+    pub name: String,
+    /// Flavors that hosts running the image can have
+    pub flavors: Vec<FKey<Flavor>>,
+    #[schemars(with = "Option<String>")]
+    pub deprecated: Option<DateTime<Utc>>,
+    #[schemars(with = "Option<String>")]
+    pub sunset: Option<DateTime<Utc>>,
+    pub replacement: Option<FKey<Image>>,
+}
+
 /// Workflow friendly representation of a Flavor
 #[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
 pub struct FlavorBlob {
@@ -127,10 +162,16 @@ pub struct FlavorBlob {
     pub name: String,
     ///
     pub interfaces: Vec<InterfaceBlob>,
-
+    /// Images members of the project may book hosts of this flavor with
     pub images: Vec<ImageBlob>,
+    /// Which of `images` the project picks by default, if any
+    pub default_image: Option<FKey<Image>>,
 
     pub available_count: usize,
+    /// Median of the latest benchmark scores of hosts of this flavor
+    pub benchmark_median: Option<f64>,
+    /// Mean of the latest benchmark scores of hosts of this flavor
+    pub benchmark_mean: Option<f64>,
     pub cpu_count: usize,     // Max 4.294967295 Billion
     pub ram: DataValue,       // Max 4.294 Petabytes in gig
     pub root_size: DataValue, // Max 4.294 Exabytes in gig
@@ -160,9 +201,39 @@ pub struct BookingBlob {
     pub global_cifile: String,
     /// Metadata for a booking blob, differing from the ideal values will cause gaps in notification data sent to users
     pub metadata: BookingMetadataBlob,
+    /// Who the booking is for, if not the owner in `metadata`, who is then the one making it.
+    /// Only leads of the project and admins can book for someone else
+    #[serde(default)]
+    pub owner: Option<String>,
+    /// Values for the parameters of the template, by parameter name. Any left out take their default
+    #[serde(default)]
+    pub parameters: HashMap<String, ParameterValue>,
+    /// Variables for the cloud-init and recipes of the hosts, by name
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    /// Free-form labels to find the booking by, like `plugfest-2025`
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Hosts of the template to fill with any host meeting the constraints instead of one
+    /// of their flavor, by hostname. Replaces the constraints the template gives the host
+    #[serde(default)]
+    pub constraints: HashMap<String, HostConstraints>,
+    /// Waits in the queue for hosts to free up instead of failing when there aren't enough free,
+    /// and starts once they do
+    #[serde(default)]
+    pub queue: bool,
+    /// When the booking starts (RFC 3339), right away if not given or already past. Hosts are
+    /// reserved for the whole of a booking that starts later, and provisioned once it starts
+    #[serde(default)]
+    #[schemars(with = "Option<String>")]
+    pub start_date: Option<DateTime<Utc>>,
+    /// Lets the booking be given hosts funded by other projects, giving the reason why.
+    /// Only admins can override this
+    #[serde(default)]
+    pub ownership_override: Option<String>,
 }
 
-#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Default)]
 pub struct BookingMetadataBlob {
     /// The dashboard booking id
     pub booking_id: Option<String>,
@@ -176,6 +247,67 @@ pub struct BookingMetadataBlob {
     pub project: Option<String>,
     /// The length in days of a booking
     pub length: Option<u64>,
+    /// For performance sensitive bookings, only hosts within this many percent of
+    /// their flavor's median benchmark score are preferred
+    #[serde(default)]
+    pub performance_tolerance: Option<f64>,
+    /// Guarantees that no two hosts in the booking share a failure domain of this kind (`rack`, `pdu`, `switch`)
+    #[serde(default)]
+    pub spread: Option<inventory::FailureDomainKind>,
+    /// A name for the booking, shown instead of its id
+    #[serde(default)]
+    pub display_name: Option<String>,
+    /// If true, a lightweight agent installed on each host reports how busy it is,
+    /// see the booking's telemetry
+    #[serde(default)]
+    pub telemetry: Option<bool>,
+}
+
+/// What creating a booking would do, found without creating it
+#[derive(Serialize, Deserialize, Debug, JsonSchema, Clone, Default)]
+pub struct BookingPlan {
+    /// True if creating the booking right now would succeed
+    pub bookable: bool,
+    /// Everything that would stop the booking from being created
+    pub problems: Vec<String>,
+    pub hosts: Vec<PlannedHost>,
+    pub networks: Vec<PlannedNetwork>,
+    /// For each flavor that not enough hosts could be found of, what kept them
+    #[serde(default)]
+    pub shortfalls: Vec<FlavorShortfall>,
+    /// How long the booking would take to be ready and what it would come to, going by
+    /// past deploys and the configured costs. Only worked out when validating
+    #[serde(default)]
+    pub estimate: Option<BookingEstimate>,
+}
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema, Clone)]
+pub struct FlavorShortfall {
+    pub flavor: String,
+    /// How many hosts of the flavor the booking asks for
+    pub needed: usize,
+    pub availability: HostAvailability,
+}
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema, Clone)]
+pub struct PlannedHost {
+    pub hostname: String,
+    pub flavor: String,
+    pub image: String,
+    /// The host that would fill the role, None if none is free
+    pub host: Option<String>,
+    /// Problems admins know that host has
+    pub known_issues: Vec<String>,
+    /// For hosts requested by constraints, how their flavor was picked
+    pub matched: Option<ConstraintMatch>,
+}
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema, Clone)]
+pub struct PlannedNetwork {
+    pub name: String,
+    pub public: bool,
+    /// The vlan the network would get, None if none is free
+    pub vlan: Option<i16>,
 }
 
 pub mod user_management {
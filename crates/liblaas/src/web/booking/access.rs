@@ -0,0 +1,216 @@
+//! Who called the booking API about a booking, for answering "who rebooted my node?"
+//!
+//! Every call made against a booking or one of its instances is recorded by [`record`] once
+//! it has been answered. The owner of a booking, admins, and calls made without saying who
+//! for see every call made against it, while collaborators only see their own. Calls made by
+//! admins are shown to anyone else as made by an admin, without saying which one.
+
+use axum::{
+    body::Body,
+    extract::{MatchedPath, Path, RawPathParams},
+    http::{HeaderMap, Request, StatusCode},
+    middleware::Next,
+    response::Response,
+};
+use common::prelude::{chrono::Utc, tokio, tracing};
+use dal::{new_client, web::*, AsEasyTransaction, FKey, NewRow, ID};
+use models::dashboard::{Aggregate, BookingAccess, Instance};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+use super::BookingError;
+use crate::web::{
+    identity::{is_admin, requesting_user},
+    listing::{Listed, Listing},
+};
+
+/// Reading the access log isn't recorded in it
+const ACCESS_LOG_ROUTE: &str = "/access-log";
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct BookingAccessBlob {
+    pub id: FKey<BookingAccess>,
+    /// None if the call didn't say who it was for, or was made by an admin and is being
+    /// read by someone who isn't one
+    pub by: Option<String>,
+    pub by_admin: bool,
+    /// What the call did, like "changed the power state of a host"
+    pub action: String,
+    pub method: String,
+    pub route: String,
+    pub instance: Option<FKey<Instance>>,
+    pub status: u16,
+    pub time: String,
+}
+
+/// What a call to `route` with `method` does, in words an owner would recognize
+fn describe(method: &str, route: &str) -> String {
+    let action = route.rsplit('/').next().unwrap_or_default();
+    let described = match (method, action) {
+        ("GET", "powerstatus") => "viewed the power state of a host",
+        ("POST", "setpower") => "changed the power state of a host",
+        ("GET", "getfqdn") => "looked up the BMC of a host",
+        ("POST", "reimage") => "reimaged a host",
+        ("POST", "rolling-reimage") => "reimaged the hosts of the booking",
+        ("POST", "retry-from-phase") => "retried provisioning a host",
+        ("POST", "replace-host") => "asked for a host to be replaced",
+        ("DELETE", "end") => "ended the booking",
+        ("GET", "status") => "viewed the status of the booking",
+        ("GET", "config") => "viewed the configuration of the booking",
+        ("PATCH", "collaborators") => "changed the collaborators of the booking",
+        ("PUT", "permissions") => "changed what a collaborator may do",
+        ("POST", "expand") => "added hosts to the booking",
+        ("POST", "snapshot") => "took a snapshot of the booking",
+        ("PUT", "env") => "changed the environment of the booking",
+        ("PUT", "firewall") => "changed the firewall policy of the booking",
+        ("POST", "request-extension") => "asked for the booking to be extended",
+        _ => return format!("{method} {route}"),
+    };
+
+    described.to_owned()
+}
+
+/// The booking a call was made against, from the `:agg_id` or `:instance_id` it was made to
+async fn booking_of(
+    params: &[(String, String)],
+) -> Option<(FKey<Aggregate>, Option<FKey<Instance>>)> {
+    let param = |name: &str| {
+        params
+            .iter()
+            .find(|(k, _)| k == name)
+            .and_then(|(_, v)| ID::from_str(v).ok())
+    };
+
+    if let Some(agg) = param("agg_id") {
+        return Some((FKey::from_id(agg), None));
+    }
+
+    let instance: FKey<Instance> = FKey::from_id(param("instance_id")?);
+    let mut client = new_client().await.ok()?;
+    let mut transaction = client.easy_transaction().await.ok()?;
+    let agg = instance.get(&mut transaction).await.ok()?.aggregate;
+    transaction.commit().await.ok()?;
+
+    Some((agg, Some(instance)))
+}
+
+/// Records who made the call, and how it was answered, if it was made against a booking
+///
+/// Only called for requests that matched a route, so the call is recorded under the route
+/// rather than the path it was made to. Recording happens after the call is answered, and
+/// a call that couldn't be recorded is still answered the same.
+pub async fn record(
+    matched: Option<MatchedPath>,
+    params: Option<RawPathParams>,
+    request: Request<Body>,
+    next: Next<Body>,
+) -> Response {
+    let headers = request.headers().clone();
+    let method = request.method().to_string();
+    let route = matched.map(|m| m.as_str().to_owned());
+    let params: Vec<(String, String)> = params
+        .iter()
+        .flat_map(|p| p.iter())
+        .map(|(k, v)| (k.to_owned(), v.to_owned()))
+        .collect();
+
+    let response = next.run(request).await;
+
+    let route = match route {
+        Some(route) if !route.ends_with(ACCESS_LOG_ROUTE) => route,
+        _ => return response,
+    };
+    let status = response.status().as_u16() as i32;
+
+    tokio::spawn(async move {
+        let Some((aggregate, instance)) = booking_of(&params).await else {
+            return;
+        };
+
+        let access = BookingAccess {
+            id: FKey::new_id_dangling(),
+            aggregate,
+            instance,
+            username: requesting_user(&headers).map(str::to_owned),
+            admin: is_admin(&headers),
+            method,
+            route,
+            status,
+            time: Utc::now(),
+        };
+
+        let res = async {
+            let mut client = new_client().await?;
+            let mut transaction = client.easy_transaction().await?;
+            NewRow::new(access).insert(&mut transaction).await?;
+            transaction.commit().await
+        }
+        .await;
+
+        if let Err(e) = res {
+            tracing::warn!("Couldn't record a call made against booking {aggregate:?}: {e:?}");
+        }
+    });
+
+    response
+}
+
+/// Calls made against the booking, most recent first unless sorted otherwise
+#[axum::debug_handler]
+pub async fn booking_access_log(
+    headers: HeaderMap,
+    Path(agg_id): Path<FKey<Aggregate>>,
+    listing: Listing,
+) -> Result<Listed<BookingAccessBlob>, BookingError> {
+    let admin = is_admin(&headers);
+    let user = requesting_user(&headers).filter(|_| !admin);
+
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+    let agg = agg_id.get(&mut transaction).await.log_error(
+        StatusCode::NOT_FOUND,
+        "No booking with that id",
+        true,
+    )?;
+
+    // None if every call can be seen, otherwise only the calls made by that collaborator
+    let only = match user {
+        Some(user) if agg.metadata.owner.as_deref() == Some(user) => None,
+        Some(user) if agg.users.iter().any(|u| u == user) => Some(user),
+        Some(user) => {
+            return Err(BookingError::Forbidden(format!(
+                "{user} isn't the owner or a collaborator of the booking"
+            )))
+        }
+        None => None,
+    };
+
+    let log = BookingAccess::for_aggregate(&mut transaction, agg_id)
+        .await
+        .log_db_client_error()?;
+
+    transaction.commit().await.log_db_client_error()?;
+
+    let blobs = log
+        .iter()
+        .filter(|a| only.is_none() || (!a.admin && a.username.as_deref() == only))
+        .map(|a| BookingAccessBlob {
+            id: a.id,
+            by: a
+                .username
+                .clone()
+                .filter(|_| admin || user.is_none() || !a.admin),
+            by_admin: a.admin,
+            action: describe(&a.method, &a.route),
+            method: a.method.clone(),
+            route: a.route.clone(),
+            instance: a.instance,
+            status: a.status as u16,
+            time: a.time.to_rfc3339(),
+        })
+        .collect();
+
+    Ok(listing.default_sort("-time").apply(blobs)?)
+}
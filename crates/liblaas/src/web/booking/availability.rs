@@ -0,0 +1,59 @@
+//! How many hosts of each flavor are free on each day ahead, for the dashboard to draw a
+//! calendar of when a booking can be scheduled to start
+
+use axum::extract::{Json, Query};
+use common::prelude::chrono::{DateTime, Duration, Utc};
+use dal::{new_client, web::*, AsEasyTransaction, FKey};
+use models::inventory::Flavor;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use workflows::resource_management::schedule::{self, FlavorAvailability};
+
+use super::BookingError;
+
+/// The longest window one request can ask for
+const MAX_DAYS: i64 = 180;
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct AvailabilityQuery {
+    /// Only this flavor, every flavor if not given
+    flavor: Option<FKey<Flavor>>,
+    /// Start of the window (RFC 3339), now if not given
+    #[schemars(with = "Option<String>")]
+    from: Option<DateTime<Utc>>,
+    /// End of the window (RFC 3339), two weeks after its start if not given
+    #[schemars(with = "Option<String>")]
+    to: Option<DateTime<Utc>>,
+}
+
+/// The hosts of each flavor that are free on each day of the window, by date
+pub async fn flavor_availability(
+    Query(query): Query<AvailabilityQuery>,
+) -> Result<Json<Vec<FlavorAvailability>>, BookingError> {
+    let from = query.from.unwrap_or_else(Utc::now);
+    let to = query.to.unwrap_or(from + Duration::days(14));
+
+    if to <= from || to - from > Duration::days(MAX_DAYS) {
+        return Err(BookingError::BadRequest(format!(
+            "Availability can be asked for over windows of up to {MAX_DAYS} days that end after they start"
+        )));
+    }
+
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+    if let Some(flavor) = query.flavor {
+        flavor
+            .get(&mut transaction)
+            .await
+            .map_err(|_| BookingError::NotFound("Flavor does not exist".to_owned()))?;
+    }
+
+    let schedule = schedule::schedule(&mut transaction, query.flavor, from, to)
+        .await
+        .log_server_error("Unable to put together the schedule", true)?;
+
+    transaction.commit().await.log_db_client_error()?;
+
+    Ok(Json(schedule::availability(&schedule)))
+}
@@ -0,0 +1,182 @@
+//! How far along the deploy of an instance is, and picking a failed deploy back up
+//! from the last phase it got through instead of starting it over
+
+use axum::{
+    extract::{Json, Path},
+    http::{HeaderMap, StatusCode},
+};
+use dal::{new_client, web::*, AsEasyTransaction, FKey};
+use models::{
+    allocator::ResourceHandle,
+    dashboard::{
+        BookingCapability, Checkpoint, DeployCheckpoint, Instance, LeaseOperation, LifeCycleState,
+    },
+    inventory::Host,
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use workflows::{
+    entry::{Action, DISPATCH},
+    resource_management::health::{excuse_instance, REIMAGE_GRACE},
+};
+
+use super::{check_not_busy, collaborators::check_capability, BookingError};
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ReachedCheckpoint {
+    checkpoint: Checkpoint,
+    reached: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct DeployProgress {
+    /// The host the checkpoints were reached on
+    host: Option<FKey<Host>>,
+    /// Earliest first
+    checkpoints: Vec<ReachedCheckpoint>,
+    /// How far along the deploy is, between 0 and 1
+    progress: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct RetryFromPhaseBlob {
+    /// Pick the deploy back up after this checkpoint, the latest one reached if not given
+    after: Option<Checkpoint>,
+}
+
+#[axum::debug_handler]
+pub async fn deploy_progress(
+    Path(instance_id): Path<Uuid>,
+) -> Result<Json<DeployProgress>, BookingError> {
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+    let instance_id: FKey<Instance> = FKey::from_id(instance_id.into());
+    let instance = instance_id.get(&mut transaction).await.log_error(
+        StatusCode::NOT_FOUND,
+        "Instance does not exist",
+        true,
+    )?;
+
+    let checkpoints = DeployCheckpoint::for_instance(&mut transaction, instance_id)
+        .await
+        .log_db_client_error()?;
+
+    transaction.commit().await.log_db_client_error()?;
+
+    // checkpoints of a host the instance was moved off of say nothing of its deploy now
+    let checkpoints = checkpoints
+        .iter()
+        .filter(|c| Some(c.host) == instance.linked_host)
+        .collect::<Vec<_>>();
+
+    Ok(Json(DeployProgress {
+        host: instance.linked_host,
+        progress: checkpoints
+            .iter()
+            .map(|c| c.checkpoint)
+            .max()
+            .map_or(0.0, |c| c.progress()),
+        checkpoints: checkpoints
+            .iter()
+            .map(|c| ReachedCheckpoint {
+                checkpoint: c.checkpoint,
+                reached: c.reached.to_rfc2822(),
+            })
+            .collect(),
+    }))
+}
+
+/// Picks a failed deploy back up, with the same checks as reimaging the host and holding
+/// the lease of the booking while it runs
+#[axum::debug_handler]
+pub async fn retry_from_phase(
+    headers: HeaderMap,
+    Path(instance_id): Path<Uuid>,
+    Json(request): Json<RetryFromPhaseBlob>,
+) -> Result<(), BookingError> {
+    tracing::info!("API call to retry_from_phase() for {instance_id} with {request:?}");
+
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+    let instance_id: FKey<Instance> = FKey::from_id(instance_id.into());
+    let instance = instance_id.get(&mut transaction).await.log_error(
+        StatusCode::NOT_FOUND,
+        "Instance does not exist",
+        true,
+    )?;
+
+    let agg = instance
+        .aggregate
+        .get(&mut transaction)
+        .await
+        .log_db_client_error()?;
+    if agg.deleted || !matches!(agg.state, LifeCycleState::New | LifeCycleState::Active) {
+        return Err(BookingError::Conflict(
+            "Only deploys of hosts in a live booking can be picked back up".to_owned(),
+        ));
+    }
+    check_capability(&mut transaction, &headers, &agg, BookingCapability::Reimage).await?;
+    check_not_busy(&mut transaction, agg.id, LeaseOperation::Reimage).await?;
+
+    let host_id = instance.linked_host.ok_or(BookingError::Conflict(
+        "Instance has no host to deploy".to_owned(),
+    ))?;
+    let allocated = ResourceHandle::handle_for_host(&mut transaction, host_id)
+        .await
+        .log_server_error("unable to find the host of the instance", true)?
+        .currently_owned_by(&mut transaction, agg.id)
+        .await
+        .log_server_error("unable to check who the host is allocated to", true)?;
+    if !allocated {
+        return Err(BookingError::Conflict(
+            "The host of this instance isn't allocated to its booking anymore".to_owned(),
+        ));
+    }
+
+    let latest = DeployCheckpoint::for_instance(&mut transaction, instance_id)
+        .await
+        .log_db_client_error()?
+        .iter()
+        .filter(|c| c.host == host_id)
+        .map(|c| c.checkpoint)
+        .max()
+        .ok_or(BookingError::Conflict("The deploy of this instance didn't get far enough to pick it back up, reimage it instead"
+                .to_owned()))?;
+
+    let after = request.after.unwrap_or(latest);
+    if after > latest {
+        return Err(BookingError::Conflict(format!(
+            "The deploy of this instance only got as far as {latest}"
+        )));
+    }
+    if after == Checkpoint::Finished {
+        return Err(BookingError::Conflict(
+            "The deploy of this instance already finished, there is nothing to pick back up"
+                .to_owned(),
+        ));
+    }
+
+    excuse_instance(&mut transaction, instance_id, REIMAGE_GRACE)
+        .await
+        .log_db_client_error()?;
+
+    transaction.commit().await.log_db_client_error()?;
+
+    DISPATCH
+        .get()
+        .ok_or(BookingError::Internal(
+            "Unable to get dispatcher".to_owned(),
+        ))?
+        .send(Action::ResumeDeploy {
+            host_id,
+            inst_id: instance_id,
+            agg_id: instance.aggregate,
+            after,
+        })
+        .map_err(|_| BookingError::Internal("Unable to resume the deploy".to_owned()))?;
+
+    Ok(())
+}
@@ -0,0 +1,420 @@
+//! Booking a whole class at once
+//!
+//! An instructor hands over a roster and a single-host template, and every student on it
+//! gets a booking of their own, made on their behalf. The cohort keeps track of those
+//! bookings so they can be extended or ended together, and shows how each student's is
+//! doing. A booking that can't be made for one student doesn't hold up the rest.
+
+use std::collections::{BTreeMap, HashMap};
+
+use axum::{
+    extract::{Json, Path},
+    http::{HeaderMap, StatusCode},
+};
+use common::prelude::{
+    chrono::{DateTime, Utc},
+    itertools::Itertools,
+    *,
+};
+use dal::{new_client, web::*, AsEasyTransaction, FKey, NewRow};
+use models::dashboard::{
+    Aggregate, Cohort, CohortMember, LifeCycleState, ParameterKind, ParameterValue, Template,
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use workflows::cleanup_booking;
+use workflows::resource_management::jobs;
+
+use super::{
+    api, check_delegation, create_aggregate, extension, summary::BookingState, BookingError,
+};
+use crate::web::{
+    identity::User,
+    jobs::{accepted, JobStarted},
+};
+
+/// The most students a cohort can be made for at once
+const MAX_STUDENTS: usize = 200;
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct CreateCohort {
+    name: String,
+    /// The project the bookings are made in
+    origin: String,
+    template_id: FKey<Template>,
+    /// Usernames of the students, each gets a booking
+    students: Vec<String>,
+    /// Metadata for every booking, its owner is the instructor making them
+    metadata: api::BookingMetadataBlob,
+    #[serde(default)]
+    global_cifile: String,
+    #[serde(default)]
+    parameters: HashMap<String, ParameterValue>,
+    #[serde(default)]
+    env: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct MemberStatus {
+    student: String,
+    aggregate: Option<FKey<Aggregate>>,
+    state: Option<BookingState>,
+    end: Option<String>,
+    /// Why the booking for the student couldn't be made
+    error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct CohortStatus {
+    id: FKey<Cohort>,
+    name: String,
+    instructor: String,
+    origin: String,
+    template: FKey<Template>,
+    created: String,
+    members: Vec<MemberStatus>,
+    /// How many students have a booking that is still being provisioned
+    provisioning: usize,
+    active: usize,
+    expired: usize,
+    /// How many students have no booking, because it couldn't be made
+    failed: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ExtendCohort {
+    /// The end to give every booking of the cohort (RFC 3339)
+    #[schemars(with = "String")]
+    end: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct MemberFailure {
+    student: String,
+    reason: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct CohortExtended {
+    /// Students whose booking was extended
+    extended: Vec<String>,
+    failed: Vec<MemberFailure>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct EndCohort {
+    /// How many bookings to tear down at once, 4 if not given
+    #[serde(default)]
+    concurrency: Option<usize>,
+}
+
+/// The usernames of `students`, each once and in the order they were first given
+fn roster(students: &[String]) -> Vec<String> {
+    students
+        .iter()
+        .map(|s| s.trim())
+        .filter(|s| !s.is_empty())
+        .unique()
+        .map(str::to_owned)
+        .collect()
+}
+
+async fn status_of(cohort: &Cohort) -> Result<CohortStatus, BookingError> {
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+    let mut members = Vec::new();
+    for member in cohort.members.iter() {
+        let booking = match member.aggregate {
+            Some(agg) => Some(agg.get(&mut transaction).await.log_db_client_error()?),
+            None => None,
+        };
+
+        members.push(MemberStatus {
+            student: member.student.clone(),
+            aggregate: member.aggregate,
+            state: booking.as_ref().map(|b| b.state.into()),
+            end: booking
+                .as_ref()
+                .and_then(|b| b.metadata.end)
+                .map(|e| e.to_rfc2822()),
+            error: member.error.clone(),
+        });
+    }
+
+    transaction.commit().await.log_db_client_error()?;
+
+    let count = |state: Option<LifeCycleState>| {
+        members
+            .iter()
+            .filter(|m| m.state.map(LifeCycleState::from) == state)
+            .count()
+    };
+
+    Ok(CohortStatus {
+        id: cohort.id,
+        name: cohort.name.clone(),
+        instructor: cohort.instructor.clone(),
+        origin: cohort.origin.clone(),
+        template: cohort.template,
+        created: cohort.created.to_rfc2822(),
+        provisioning: count(Some(LifeCycleState::New)),
+        active: count(Some(LifeCycleState::Active)),
+        expired: count(Some(LifeCycleState::Done)),
+        failed: count(None),
+        members,
+    })
+}
+
+/// Refuses anyone but the instructor of the cohort and admins
+fn check_instructor(by: &User, cohort: &Cohort) -> Result<(), BookingError> {
+    match by.name == cohort.instructor || by.admin {
+        true => Ok(()),
+        false => Err(BookingError::Forbidden(
+            "Only the instructor of the cohort and admins can do this".to_owned(),
+        )),
+    }
+}
+
+#[axum::debug_handler]
+pub async fn create_cohort(
+    headers: HeaderMap,
+    Json(request): Json<CreateCohort>,
+) -> Result<Json<CohortStatus>, BookingError> {
+    tracing::info!(
+        "API call to create_cohort() for {} of {:?}",
+        request.name,
+        request.metadata.owner
+    );
+
+    let students = roster(&request.students);
+    if students.is_empty() {
+        return Err(BookingError::BadRequest("The roster is empty".to_owned()));
+    }
+    if students.len() > MAX_STUDENTS {
+        return Err(BookingError::BadRequest(format!(
+            "A cohort can have at most {MAX_STUDENTS} students"
+        )));
+    }
+    let instructor = request
+        .metadata
+        .owner
+        .clone()
+        .ok_or(BookingError::BadRequest(
+            "The instructor has to be given as the owner in the metadata".to_owned(),
+        ))?;
+
+    let blob_for = |student: &str| api::BookingBlob {
+        origin: request.origin.clone(),
+        template_id: request.template_id,
+        allowed_users: Vec::new(),
+        global_cifile: request.global_cifile.clone(),
+        metadata: request.metadata.clone(),
+        owner: Some(student.to_owned()),
+        parameters: request.parameters.clone(),
+        env: request.env.clone(),
+        tags: Vec::new(),
+        constraints: Default::default(),
+        queue: false,
+        start_date: None,
+        ownership_override: None,
+    };
+
+    for student in students.iter() {
+        check_delegation(&headers, &blob_for(student))?;
+    }
+
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+    let template = request.template_id.get(&mut transaction).await.log_error(
+        StatusCode::NOT_FOUND,
+        "no template by that id",
+        true,
+    )?;
+    if template.hosts.len() != 1
+        || template
+            .parameters
+            .iter()
+            .any(|p| matches!(p.kind, ParameterKind::HostCount { .. }))
+    {
+        return Err(BookingError::BadRequest(
+            "Cohorts can only be booked from templates with exactly one host".to_owned(),
+        ));
+    }
+
+    transaction.commit().await.log_db_client_error()?;
+
+    let mut members = Vec::new();
+    for student in students {
+        let member = match create_aggregate(blob_for(&student)).await {
+            Ok(agg) => CohortMember {
+                student,
+                aggregate: Some(agg),
+                error: None,
+            },
+            Err(e) => {
+                tracing::warn!("Couldn't book {student} into cohort {}: {e}", request.name);
+                CohortMember {
+                    student,
+                    aggregate: None,
+                    error: Some(e.to_string()),
+                }
+            }
+        };
+        members.push(member);
+    }
+
+    let cohort = Cohort {
+        id: FKey::new_id_dangling(),
+        name: request.name,
+        instructor,
+        origin: request.origin,
+        template: request.template_id,
+        members,
+        created: Utc::now(),
+    };
+
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+    NewRow::new(cohort.clone())
+        .insert(&mut transaction)
+        .await
+        .log_server_error("Unable to record the cohort", true)?;
+
+    transaction.commit().await.log_db_client_error()?;
+
+    Ok(Json(status_of(&cohort).await?))
+}
+
+#[axum::debug_handler]
+pub async fn list_cohorts(
+    Path(instructor): Path<String>,
+) -> Result<Json<Vec<CohortStatus>>, BookingError> {
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+    let cohorts = Cohort::for_instructor(&mut transaction, &instructor)
+        .await
+        .log_db_client_error()?;
+
+    transaction.commit().await.log_db_client_error()?;
+
+    let mut statuses = Vec::new();
+    for cohort in cohorts {
+        statuses.push(status_of(&cohort).await?);
+    }
+
+    Ok(Json(statuses))
+}
+
+#[axum::debug_handler]
+pub async fn cohort_status(
+    Path(cohort_id): Path<FKey<Cohort>>,
+) -> Result<Json<CohortStatus>, BookingError> {
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+    let cohort = cohort_id.get(&mut transaction).await.log_error(
+        StatusCode::NOT_FOUND,
+        "No cohort with that id",
+        true,
+    )?;
+
+    transaction.commit().await.log_db_client_error()?;
+
+    Ok(Json(status_of(&cohort).await?))
+}
+
+#[axum::debug_handler]
+pub async fn extend_cohort(
+    by: User,
+    Path(cohort_id): Path<FKey<Cohort>>,
+    Json(request): Json<ExtendCohort>,
+) -> Result<Json<CohortExtended>, BookingError> {
+    tracing::info!(
+        "API call to extend_cohort() of {cohort_id:?} to {}",
+        request.end
+    );
+
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+    let cohort = cohort_id
+        .get(&mut transaction)
+        .await
+        .log_error(StatusCode::NOT_FOUND, "No cohort with that id", true)?
+        .into_inner();
+    check_instructor(&by, &cohort)?;
+
+    transaction.commit().await.log_db_client_error()?;
+
+    let mut report = CohortExtended::default();
+    for member in cohort.members.iter() {
+        let Some(agg) = member.aggregate else {
+            continue;
+        };
+
+        // each booking is extended on its own, so one that can't be doesn't undo the rest
+        let mut client = new_client().await.log_db_client_error()?;
+        let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+        let mut booking = agg.get(&mut transaction).await.log_db_client_error()?;
+        match extension::extend(&mut transaction, &mut booking, request.end, &by.name).await {
+            Ok(()) => {
+                transaction.commit().await.log_db_client_error()?;
+                report.extended.push(member.student.clone());
+            }
+            Err(e) => report.failed.push(MemberFailure {
+                student: member.student.clone(),
+                reason: e.to_string(),
+            }),
+        }
+    }
+
+    Ok(Json(report))
+}
+
+#[axum::debug_handler]
+pub async fn end_cohort(
+    by: User,
+    Path(cohort_id): Path<FKey<Cohort>>,
+    Json(request): Json<EndCohort>,
+) -> Result<(StatusCode, Json<JobStarted>), BookingError> {
+    tracing::info!("API call to end_cohort() of {cohort_id:?}");
+
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+    let cohort = cohort_id
+        .get(&mut transaction)
+        .await
+        .log_error(StatusCode::NOT_FOUND, "No cohort with that id", true)?
+        .into_inner();
+    check_instructor(&by, &cohort)?;
+
+    let mut aggregates = Vec::new();
+    for agg in cohort.aggregates() {
+        if agg.get(&mut transaction).await.log_db_client_error()?.state == LifeCycleState::Active {
+            aggregates.push(agg);
+        }
+    }
+
+    transaction.commit().await.log_db_client_error()?;
+
+    if aggregates.is_empty() {
+        return Err(BookingError::Conflict(
+            "None of the cohort's bookings are running".to_owned(),
+        ));
+    }
+
+    let concurrency = request.concurrency.unwrap_or(4);
+    let job = jobs::start("end_cohort", &by.name, aggregates.len(), move |job| {
+        cleanup_booking::bulk::bulk_end(job, aggregates, concurrency)
+    })
+    .await
+    .log_server_error("Unable to start ending the cohort", true)?;
+
+    Ok(accepted(job))
+}
@@ -0,0 +1,353 @@
+//! Changing who a booking belongs to and who else can use it, after it was made
+//!
+//! Only the owner and admins can make changes. Each change is logged as an event of the
+//! booking, and carried through to its VPN groups and the logins on its hosts.
+//!
+//! Owners can also narrow down what each collaborator may do to the booking, like keeping
+//! them from reimaging its hosts or ending it.
+
+use axum::{
+    extract::{Json, Path},
+    http::{HeaderMap, StatusCode},
+};
+use common::prelude::chrono::Utc;
+use common::prelude::itertools::Itertools;
+use dal::{new_client, web::*, AsEasyTransaction, EasyTransaction, FKey, NewRow};
+use models::dashboard::{
+    Aggregate, AggregateSummary, BookingCapability, BookingEvent, BookingEventKind,
+    CollaboratorPermissions,
+};
+use notifications::contacts;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use workflows::entry::{Action, DISPATCH};
+
+use super::BookingError;
+use crate::web::identity::{is_admin, named, User};
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct CollaboratorChange {
+    #[serde(default)]
+    pub add: Vec<String>,
+    #[serde(default)]
+    pub remove: Vec<String>,
+    /// Hands the booking over to this user, who is made a collaborator if they aren't one.
+    /// The previous owner stays a collaborator unless removed
+    #[serde(default)]
+    pub owner: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Collaborators {
+    pub owner: Option<String>,
+    pub users: Vec<String>,
+}
+
+#[axum::debug_handler]
+pub async fn change_collaborators(
+    by: User,
+    Path(agg_id): Path<FKey<Aggregate>>,
+    Json(change): Json<CollaboratorChange>,
+) -> Result<Json<Collaborators>, BookingError> {
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+    let mut agg = agg_id.get(&mut transaction).await.log_error(
+        StatusCode::NOT_FOUND,
+        "No booking with that id",
+        true,
+    )?;
+
+    if agg.metadata.owner.as_deref() != Some(by.name.as_str()) && !by.admin {
+        return Err(BookingError::Forbidden(
+            "Only the owner of the booking and admins can change who is on it".to_owned(),
+        ));
+    }
+    if agg.deleted {
+        return Err(BookingError::Conflict(
+            "The booking has already ended".to_owned(),
+        ));
+    }
+
+    let new_owner = change
+        .owner
+        .clone()
+        .filter(|o| agg.metadata.owner.as_ref() != Some(o));
+    let owner = new_owner.clone().or(agg.metadata.owner.clone());
+    if let Some(owner) = owner.as_ref().filter(|o| change.remove.contains(o)) {
+        return Err(BookingError::BadRequest(format!(
+            "{owner} owns the booking, so can't be removed from it"
+        )));
+    }
+
+    let added = change
+        .add
+        .iter()
+        .chain(new_owner.iter())
+        .map(|u| u.trim().to_owned())
+        .unique()
+        .filter(|u| !agg.users.contains(u) && !change.remove.contains(u))
+        .collect_vec();
+    let removed = change
+        .remove
+        .iter()
+        .filter(|u| agg.users.contains(u))
+        .cloned()
+        .unique()
+        .collect_vec();
+
+    for user in added.iter() {
+        if user.is_empty() {
+            return Err(BookingError::BadRequest(
+                "Usernames can't be empty".to_owned(),
+            ));
+        }
+        contacts::verify(user).await.map_err(|e| {
+            tracing::info!("Not adding {user} to booking: {e:?}");
+            BookingError::BadRequest(format!("{user} doesn't have an email that can be reached"))
+        })?;
+    }
+
+    // whoever comes back later starts over with every capability
+    CollaboratorPermissions::clear(&mut transaction, agg_id, &removed)
+        .await
+        .log_db_client_error()?;
+    if let Some(owner) = new_owner.as_ref() {
+        CollaboratorPermissions::clear(&mut transaction, agg_id, &[owner.clone()])
+            .await
+            .log_db_client_error()?;
+    }
+
+    agg.users.retain(|u| !removed.contains(u));
+    agg.users.extend(added.iter().cloned());
+    let previous_owner = match new_owner.clone() {
+        Some(owner) => agg.metadata.owner.replace(owner),
+        None => None,
+    };
+    agg.update(&mut transaction)
+        .await
+        .log_server_error("Unable to change who is on the booking", true)?;
+
+    let actor = Some(by.name.clone());
+    if let Some(owner) = new_owner.as_ref() {
+        BookingEvent::record(
+            &mut transaction,
+            agg_id,
+            BookingEventKind::OwnerChanged,
+            format!(
+                "handed over from {} to {owner}",
+                previous_owner.unwrap_or("nobody".to_owned())
+            ),
+            actor.clone(),
+        )
+        .await
+        .log_db_client_error()?;
+    }
+    if !added.is_empty() {
+        BookingEvent::record(
+            &mut transaction,
+            agg_id,
+            BookingEventKind::CollaboratorAdded,
+            format!("{} added to the booking", added.join(", ")),
+            actor.clone(),
+        )
+        .await
+        .log_db_client_error()?;
+    }
+    if !removed.is_empty() {
+        BookingEvent::record(
+            &mut transaction,
+            agg_id,
+            BookingEventKind::CollaboratorRemoved,
+            format!("{} removed from the booking", removed.join(", ")),
+            actor,
+        )
+        .await
+        .log_db_client_error()?;
+    }
+
+    AggregateSummary::refresh(&mut transaction, agg_id)
+        .await
+        .log_db_client_error()?;
+
+    transaction.commit().await.log_db_client_error()?;
+
+    carry_through(agg_id, added, removed)?;
+
+    Ok(Json(Collaborators {
+        owner: agg.metadata.owner.clone(),
+        users: agg.users.clone(),
+    }))
+}
+
+/// Gives `added` and takes from `removed` the VPN groups of the booking and the logins on
+/// its hosts, once the change has been committed
+pub(super) fn carry_through(
+    agg_id: FKey<Aggregate>,
+    added: Vec<String>,
+    removed: Vec<String>,
+) -> Result<(), BookingError> {
+    if added.is_empty() && removed.is_empty() {
+        return Ok(());
+    }
+
+    DISPATCH
+        .get()
+        .ok_or(BookingError::Internal("tascii was not found".to_owned()))?
+        .send(Action::ChangeCollaborators {
+            agg_id,
+            added,
+            removed,
+        })
+        .anyway()
+        .log_server_error(
+            "Unable to carry the change through to the hosts of the booking",
+            true,
+        )?;
+
+    Ok(())
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct PermissionsChange {
+    /// Everything the collaborator may do from now on, anything left out is revoked
+    pub capabilities: Vec<BookingCapability>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct CollaboratorPermissionsBlob {
+    pub user: String,
+    pub capabilities: Vec<BookingCapability>,
+}
+
+fn describe(capability: BookingCapability) -> &'static str {
+    match capability {
+        BookingCapability::PowerControl => "power hosts on and off",
+        BookingCapability::Reimage => "reimage hosts",
+        BookingCapability::EndBooking => "end the booking",
+    }
+}
+
+/// Turns the request away unless whoever it is made for may do `capability` to `agg`
+///
+/// Admins can do anything. Requests that don't say who they are for are turned away, like
+/// the `User` extractor does.
+pub async fn check_capability(
+    transaction: &mut EasyTransaction<'_>,
+    headers: &HeaderMap,
+    agg: &Aggregate,
+    capability: BookingCapability,
+) -> Result<(), BookingError> {
+    let user = named(headers)?;
+    if is_admin(headers) {
+        return Ok(());
+    }
+
+    let capabilities = CollaboratorPermissions::of(transaction, agg, &user)
+        .await
+        .log_db_client_error()?;
+    if !capabilities.contains(&capability) {
+        return Err(BookingError::Forbidden(format!(
+            "The owner of the booking hasn't allowed {user} to {}",
+            describe(capability)
+        )));
+    }
+
+    Ok(())
+}
+
+#[axum::debug_handler]
+pub async fn set_permissions(
+    by: User,
+    Path((agg_id, user)): Path<(FKey<Aggregate>, String)>,
+    Json(change): Json<PermissionsChange>,
+) -> Result<Json<CollaboratorPermissionsBlob>, BookingError> {
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+    let agg = agg_id.get(&mut transaction).await.log_error(
+        StatusCode::NOT_FOUND,
+        "No booking with that id",
+        true,
+    )?;
+
+    if agg.metadata.owner.as_deref() != Some(by.name.as_str()) && !by.admin {
+        return Err(BookingError::Forbidden(
+            "Only the owner of the booking and admins can change what collaborators may do"
+                .to_owned(),
+        ));
+    }
+    if agg.deleted {
+        return Err(BookingError::Conflict(
+            "The booking has already ended".to_owned(),
+        ));
+    }
+    if agg.metadata.owner.as_deref() == Some(user.as_str()) {
+        return Err(BookingError::BadRequest(format!(
+            "{user} owns the booking, so can always do anything to it"
+        )));
+    }
+    if !agg.users.contains(&user) {
+        return Err(BookingError::NotFound(format!(
+            "{user} isn't a collaborator on the booking"
+        )));
+    }
+
+    let capabilities = change
+        .capabilities
+        .iter()
+        .copied()
+        .sorted()
+        .dedup()
+        .collect_vec();
+
+    match CollaboratorPermissions::find(&mut transaction, agg_id, &user)
+        .await
+        .log_db_client_error()?
+    {
+        Some(mut existing) => {
+            existing.capabilities = capabilities.clone();
+            existing.granted_by = by.name.clone();
+            existing.updated = Utc::now();
+            existing
+                .update(&mut transaction)
+                .await
+                .log_server_error("Unable to change the permissions", true)?;
+        }
+        None => {
+            NewRow::new(CollaboratorPermissions {
+                id: FKey::new_id_dangling(),
+                aggregate: agg_id,
+                username: user.clone(),
+                capabilities: capabilities.clone(),
+                granted_by: by.name.clone(),
+                updated: Utc::now(),
+            })
+            .insert(&mut transaction)
+            .await
+            .log_server_error("Unable to change the permissions", true)?;
+        }
+    }
+
+    let revoked = BookingCapability::all()
+        .into_iter()
+        .filter(|c| !capabilities.contains(c))
+        .map(describe)
+        .collect_vec();
+    BookingEvent::record(
+        &mut transaction,
+        agg_id,
+        BookingEventKind::PermissionsChanged,
+        match revoked.is_empty() {
+            true => format!("{user} can do anything to the booking"),
+            false => format!("{user} can no longer {}", revoked.join(", ")),
+        },
+        Some(by.name.clone()),
+    )
+    .await
+    .log_db_client_error()?;
+
+    transaction.commit().await.log_db_client_error()?;
+
+    Ok(Json(CollaboratorPermissionsBlob { user, capabilities }))
+}
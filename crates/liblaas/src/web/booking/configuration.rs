@@ -0,0 +1,202 @@
+//! Everything a booking was configured with, for debugging its deploy
+//!
+//! The status of a booking only shows what its users need. This also gives the image,
+//! ci files and networking each instance is provisioned with, as the deploy sees them.
+//! The BMC credentials of the booking are only shown to admins.
+
+use std::collections::{BTreeMap, HashMap};
+
+use axum::{
+    extract::{Json, Path},
+    http::{HeaderMap, StatusCode},
+};
+use common::prelude::{itertools::Itertools, *};
+use dal::{new_client, web::*, AsEasyTransaction, FKey};
+use models::{
+    dashboard::{Aggregate, Image, Instance},
+    inventory::Host,
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use super::BookingError;
+use crate::web::identity::is_admin;
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct BookingConfiguration {
+    id: FKey<Aggregate>,
+    /// None unless the request is made on behalf of an admin
+    ipmi_username: Option<String>,
+    /// None unless the request is made on behalf of an admin
+    ipmi_password: Option<String>,
+    env: BTreeMap<String, String>,
+    users: Vec<String>,
+    instances: Vec<InstanceConfiguration>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct InstanceConfiguration {
+    id: FKey<Instance>,
+    hostname: String,
+    flavor: String,
+    image_id: FKey<Image>,
+    image: String,
+    /// The host the instance is deployed on, None until one is picked
+    host: Option<String>,
+    host_id: Option<FKey<Host>>,
+    /// The ci files layered over the generated cloud-init, in order
+    cifiles: Vec<String>,
+    bondgroups: Vec<BondgroupConfiguration>,
+    metadata: HashMap<String, serde_json::Value>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct BondgroupConfiguration {
+    interfaces: Vec<String>,
+    connections: Vec<ConnectionConfiguration>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ConnectionConfiguration {
+    network: String,
+    /// The vlan the network was given, None if it hasn't been given one
+    vlan: Option<i16>,
+    tagged: bool,
+}
+
+#[axum::debug_handler]
+pub async fn booking_configuration(
+    headers: HeaderMap,
+    Path(agg_id): Path<FKey<Aggregate>>,
+) -> Result<Json<BookingConfiguration>, BookingError> {
+    let admin = is_admin(&headers);
+
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+    let agg = agg_id.get(&mut transaction).await.log_error(
+        StatusCode::NOT_FOUND,
+        "No booking with that id",
+        true,
+    )?;
+
+    let assigned = agg
+        .vlans
+        .get(&mut transaction)
+        .await
+        .log_db_client_error()?
+        .into_inner()
+        .networks;
+
+    let mut instances = Vec::new();
+    for instance in agg
+        .instances(&mut transaction)
+        .await
+        .log_db_client_error()?
+    {
+        let config = &instance.config;
+
+        let flavor = config
+            .flavor
+            .get(&mut transaction)
+            .await
+            .log_db_client_error()?
+            .name
+            .clone();
+        let image = config
+            .image
+            .get(&mut transaction)
+            .await
+            .log_db_client_error()?
+            .name
+            .clone();
+        let host = match instance.linked_host {
+            Some(host) => Some(
+                host.get(&mut transaction)
+                    .await
+                    .log_db_client_error()?
+                    .server_name
+                    .clone(),
+            ),
+            None => None,
+        };
+
+        let mut cifiles = Vec::new();
+        for cifile in config.cifile.iter() {
+            cifiles.push(
+                cifile
+                    .get(&mut transaction)
+                    .await
+                    .log_db_client_error()?
+                    .data
+                    .clone(),
+            );
+        }
+
+        let mut bondgroups = Vec::new();
+        for bondgroup in config.connections.iter() {
+            let mut connections = Vec::new();
+            for connection in bondgroup.connects_to.iter() {
+                let network = connection
+                    .network
+                    .get(&mut transaction)
+                    .await
+                    .log_db_client_error()?
+                    .name
+                    .clone();
+                let vlan = match assigned.get(&connection.network) {
+                    Some(vlan) => Some(
+                        vlan.get(&mut transaction)
+                            .await
+                            .log_db_client_error()?
+                            .vlan_id,
+                    ),
+                    None => None,
+                };
+
+                connections.push(ConnectionConfiguration {
+                    network,
+                    vlan,
+                    tagged: connection.tagged,
+                });
+            }
+
+            bondgroups.push(BondgroupConfiguration {
+                interfaces: bondgroup
+                    .member_interfaces
+                    .iter()
+                    .cloned()
+                    .sorted()
+                    .collect(),
+                connections: connections
+                    .into_iter()
+                    .sorted_by(|a, b| a.network.cmp(&b.network))
+                    .collect(),
+            });
+        }
+
+        instances.push(InstanceConfiguration {
+            id: instance.id,
+            hostname: config.hostname.clone(),
+            flavor,
+            image_id: config.image,
+            image,
+            host,
+            host_id: instance.linked_host,
+            cifiles,
+            bondgroups,
+            metadata: instance.metadata.clone(),
+        });
+    }
+
+    transaction.commit().await.log_db_client_error()?;
+
+    Ok(Json(BookingConfiguration {
+        id: agg.id,
+        ipmi_username: admin.then(|| agg.configuration.ipmi_username.clone()),
+        ipmi_password: admin.then(|| agg.configuration.ipmi_password.clone()),
+        env: agg.configuration.env.clone(),
+        users: agg.users.clone(),
+        instances,
+    }))
+}
@@ -0,0 +1,409 @@
+//! Bookings built up over the steps of the dashboard's create-booking wizard
+//!
+//! Each step patches in what it asked for, and everything given so far is checked right
+//! away so the wizard can point at the step with a problem. Nothing is allocated until the
+//! draft is submitted, and drafts stick around so an abandoned wizard can be picked back up.
+
+use axum::extract::{Json, Path};
+use common::prelude::{chrono::Utc, *};
+use dal::{new_client, web::*, AsEasyTransaction, EasyTransaction, ExistingRow, FKey, NewRow};
+use models::dashboard::{Aggregate, BookingDraft, ParameterValue, Template};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use workflows::deploy_booking::parameters;
+
+use super::{check_bookable, check_image_policy, BookingError, MAX_DISPLAY_NAME_LEN};
+use crate::{
+    booking::make_aggregate,
+    web::api::{BookingBlob, BookingMetadataBlob},
+};
+
+/// What one step of the wizard gives, any field not given is left as it is
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct DraftUpdate {
+    pub origin: Option<String>,
+    pub template_id: Option<FKey<Template>>,
+    pub allowed_users: Option<Vec<String>>,
+    pub global_cifile: Option<String>,
+    /// Only the metadata fields given are changed
+    pub metadata: Option<BookingMetadataBlob>,
+    /// Merged into the values given so far, where null takes a value back out
+    pub parameters: Option<HashMap<String, Option<ParameterValue>>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct NewDraft {
+    /// The ipa username of whoever is going through the wizard
+    pub owner: String,
+    #[serde(flatten)]
+    pub update: DraftUpdate,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct DraftBlob {
+    pub id: FKey<BookingDraft>,
+    pub owner: String,
+    pub origin: Option<String>,
+    pub template_id: Option<FKey<Template>>,
+    pub allowed_users: Vec<String>,
+    pub global_cifile: Option<String>,
+    pub metadata: BookingMetadataBlob,
+    pub parameters: HashMap<String, ParameterValue>,
+    pub created: String,
+    pub updated: String,
+    /// The booking the draft became, once submitted
+    pub submitted: Option<FKey<Aggregate>>,
+    /// What still has to be given before the draft can be submitted
+    pub missing: Vec<String>,
+}
+
+fn metadata_of(draft: &BookingDraft) -> Result<BookingMetadataBlob, BookingError> {
+    Ok(serde_json::from_value(draft.metadata.clone())
+        .anyway()
+        .log_server_error("draft has unreadable metadata", true)?)
+}
+
+fn missing(draft: &BookingDraft) -> Vec<String> {
+    let mut missing = Vec::new();
+
+    if draft.origin.is_none() {
+        missing.push("origin".to_owned());
+    }
+    if draft.template.is_none() {
+        missing.push("template_id".to_owned());
+    }
+
+    missing
+}
+
+fn to_blob(draft: &BookingDraft) -> Result<DraftBlob, BookingError> {
+    Ok(DraftBlob {
+        id: draft.id,
+        owner: draft.owner.clone(),
+        origin: draft.origin.clone(),
+        template_id: draft.template,
+        allowed_users: draft.allowed_users.clone(),
+        global_cifile: draft.global_cifile.clone(),
+        metadata: metadata_of(draft)?,
+        parameters: draft.parameters.clone(),
+        created: draft.created.to_rfc2822(),
+        updated: draft.updated.to_rfc2822(),
+        submitted: draft.submitted,
+        missing: missing(draft),
+    })
+}
+
+/// Takes `from` over any fields of `into` that it has
+fn merge_metadata(into: &mut BookingMetadataBlob, from: BookingMetadataBlob) {
+    let BookingMetadataBlob {
+        booking_id,
+        owner,
+        lab,
+        purpose,
+        project,
+        length,
+        performance_tolerance,
+        spread,
+        display_name,
+        telemetry,
+    } = from;
+
+    into.booking_id = booking_id.or(into.booking_id.take());
+    into.owner = owner.or(into.owner.take());
+    into.lab = lab.or(into.lab.take());
+    into.purpose = purpose.or(into.purpose.take());
+    into.project = project.or(into.project.take());
+    into.length = length.or(into.length.take());
+    into.performance_tolerance = performance_tolerance.or(into.performance_tolerance.take());
+    into.spread = spread.or(into.spread.take());
+    into.display_name = display_name.or(into.display_name.take());
+    into.telemetry = telemetry.or(into.telemetry.take());
+}
+
+fn apply(draft: &mut BookingDraft, update: DraftUpdate) -> Result<(), BookingError> {
+    let DraftUpdate {
+        origin,
+        template_id,
+        allowed_users,
+        global_cifile,
+        metadata,
+        parameters,
+    } = update;
+
+    if origin.is_some() {
+        draft.origin = origin;
+    }
+    if template_id.is_some() {
+        draft.template = template_id;
+    }
+    if let Some(users) = allowed_users {
+        draft.allowed_users = users.into_iter().map(|u| u.trim().to_owned()).collect();
+    }
+    if global_cifile.is_some() {
+        draft.global_cifile = global_cifile;
+    }
+
+    if let Some(metadata) = metadata {
+        let mut merged = metadata_of(draft)?;
+        merge_metadata(&mut merged, metadata);
+        draft.metadata = serde_json::to_value(merged)
+            .anyway()
+            .log_server_error("unable to store draft metadata", true)?;
+    }
+
+    for (name, value) in parameters.unwrap_or_default() {
+        match value {
+            Some(value) => draft.parameters.insert(name, value),
+            None => draft.parameters.remove(&name),
+        };
+    }
+
+    Ok(())
+}
+
+/// Checks everything given so far, as the booking would be checked if it were made now
+async fn validate(
+    transaction: &mut EasyTransaction<'_>,
+    draft: &BookingDraft,
+) -> Result<(), BookingError> {
+    let bad_request = |msg: String| Err(BookingError::BadRequest(msg));
+
+    if let Some(origin) = &draft.origin {
+        if !config::settings().projects.contains_key(origin) {
+            return bad_request(format!("No project supported by origin name {origin}"));
+        }
+    }
+
+    if draft.allowed_users.iter().any(|u| u.is_empty()) {
+        return bad_request("Users can't have empty names".to_owned());
+    }
+
+    let metadata = metadata_of(draft)?;
+    if metadata.length == Some(0) {
+        return bad_request("A booking has to last at least a day".to_owned());
+    }
+    if let Some(tolerance) = metadata.performance_tolerance {
+        if !(0.0..=100.0).contains(&tolerance) {
+            return bad_request("Performance tolerance is a percentage".to_owned());
+        }
+    }
+    if let Some(name) = &metadata.display_name {
+        if name.trim().chars().count() > MAX_DISPLAY_NAME_LEN {
+            return bad_request(format!(
+                "Display name can be at most {MAX_DISPLAY_NAME_LEN} characters"
+            ));
+        }
+    }
+
+    match draft.template {
+        Some(template_id) => {
+            let template = check_bookable(transaction, template_id, Some(&draft.owner)).await?;
+            if template.deleted {
+                return bad_request("That template has been deleted".to_owned());
+            }
+
+            parameters::validate(&template.parameters, &draft.parameters)
+                .map_err(|e| BookingError::BadRequest(e.to_string()))?;
+
+            if let Some(origin) = &draft.origin {
+                check_image_policy(transaction, origin, &template, &draft.parameters).await?;
+            }
+        }
+        None if !draft.parameters.is_empty() => {
+            return bad_request("Pick a template before giving its parameters".to_owned());
+        }
+        None => {}
+    }
+
+    Ok(())
+}
+
+async fn open_draft(
+    transaction: &mut EasyTransaction<'_>,
+    draft_id: FKey<BookingDraft>,
+) -> Result<ExistingRow<BookingDraft>, BookingError> {
+    let draft = draft_id
+        .get(transaction)
+        .await
+        .map_err(|_| BookingError::NotFound("No such draft".to_owned()))?;
+
+    match draft.submitted {
+        Some(agg) => Err(BookingError::Conflict(format!(
+            "The draft was already submitted as booking {}",
+            agg.into_id()
+        ))),
+        None => Ok(draft),
+    }
+}
+
+#[axum::debug_handler]
+pub async fn create_draft(Json(request): Json<NewDraft>) -> Result<Json<DraftBlob>, BookingError> {
+    let owner = request.owner.trim().to_owned();
+    if owner.is_empty() {
+        return Err(BookingError::BadRequest(
+            "A draft needs an owner".to_owned(),
+        ));
+    }
+
+    let now = Utc::now();
+    let mut draft = BookingDraft {
+        id: FKey::new_id_dangling(),
+        owner: owner.clone(),
+        origin: None,
+        template: None,
+        allowed_users: Vec::new(),
+        global_cifile: None,
+        metadata: serde_json::to_value(BookingMetadataBlob {
+            owner: Some(owner),
+            ..Default::default()
+        })
+        .anyway()
+        .log_server_error("unable to store draft metadata", true)?,
+        parameters: HashMap::new(),
+        created: now,
+        updated: now,
+        submitted: None,
+    };
+    apply(&mut draft, request.update)?;
+
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+    validate(&mut transaction, &draft).await?;
+
+    let blob = to_blob(&draft)?;
+    NewRow::new(draft)
+        .insert(&mut transaction)
+        .await
+        .log_db_client_error()?;
+
+    transaction.commit().await.log_db_client_error()?;
+
+    Ok(Json(blob))
+}
+
+#[axum::debug_handler]
+pub async fn get_draft(
+    Path(draft_id): Path<FKey<BookingDraft>>,
+) -> Result<Json<DraftBlob>, BookingError> {
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+    let draft = draft_id
+        .get(&mut transaction)
+        .await
+        .map_err(|_| BookingError::NotFound("No such draft".to_owned()))?;
+
+    transaction.commit().await.log_db_client_error()?;
+
+    Ok(Json(to_blob(&draft)?))
+}
+
+/// Drafts of a user that haven't been submitted yet, most recently changed first
+#[axum::debug_handler]
+pub async fn list_drafts(Path(owner): Path<String>) -> Result<Json<Vec<DraftBlob>>, BookingError> {
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+    let drafts = BookingDraft::open_for_owner(&mut transaction, &owner)
+        .await
+        .log_db_client_error()?;
+
+    transaction.commit().await.log_db_client_error()?;
+
+    Ok(Json(
+        drafts
+            .iter()
+            .map(|d| to_blob(d))
+            .collect::<Result<_, _>>()?,
+    ))
+}
+
+/// Saves one step of the wizard, refusing it (and leaving the draft as it was) if it doesn't check out
+#[axum::debug_handler]
+pub async fn update_draft(
+    Path(draft_id): Path<FKey<BookingDraft>>,
+    Json(update): Json<DraftUpdate>,
+) -> Result<Json<DraftBlob>, BookingError> {
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+    let mut draft = open_draft(&mut transaction, draft_id).await?;
+
+    apply(&mut draft, update)?;
+    validate(&mut transaction, &draft).await?;
+
+    draft.updated = Utc::now();
+    draft.update(&mut transaction).await.log_db_client_error()?;
+
+    transaction.commit().await.log_db_client_error()?;
+
+    Ok(Json(to_blob(&draft)?))
+}
+
+#[axum::debug_handler]
+pub async fn discard_draft(Path(draft_id): Path<FKey<BookingDraft>>) -> Result<(), BookingError> {
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+    let draft = draft_id
+        .get(&mut transaction)
+        .await
+        .map_err(|_| BookingError::NotFound("No such draft".to_owned()))?;
+    draft.delete(&mut transaction).await.log_db_client_error()?;
+
+    transaction.commit().await.log_db_client_error()?;
+
+    Ok(())
+}
+
+/// Makes the booking the draft describes, the same as if it had been created in one go
+#[axum::debug_handler]
+pub async fn submit_draft(
+    Path(draft_id): Path<FKey<BookingDraft>>,
+) -> Result<Json<FKey<Aggregate>>, BookingError> {
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+    let mut draft = open_draft(&mut transaction, draft_id).await?;
+
+    let missing = missing(&draft);
+    if !missing.is_empty() {
+        return Err(BookingError::BadRequest(format!(
+            "The draft still needs {}",
+            missing.join(", ")
+        )));
+    }
+    validate(&mut transaction, &draft).await?;
+
+    let blob = BookingBlob {
+        origin: draft.origin.clone().unwrap_or_default(),
+        template_id: draft.template.ok_or(BookingError::BadRequest(
+            "The draft has no template".to_owned(),
+        ))?,
+        allowed_users: draft.allowed_users.clone(),
+        global_cifile: draft.global_cifile.clone().unwrap_or_default(),
+        metadata: metadata_of(&draft)?,
+        owner: None,
+        parameters: draft.parameters.clone(),
+        env: Default::default(),
+        tags: Vec::new(),
+        constraints: Default::default(),
+        queue: false,
+        start_date: None,
+        ownership_override: None,
+    };
+
+    let agg = make_aggregate(blob)
+        .await
+        .log_server_error("unable to create the aggregate/booking", true)?;
+
+    draft.submitted = Some(agg);
+    draft.updated = Utc::now();
+    draft.update(&mut transaction).await.log_db_client_error()?;
+
+    transaction.commit().await.log_db_client_error()?;
+
+    Ok(Json(agg))
+}
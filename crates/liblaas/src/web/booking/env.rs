@@ -0,0 +1,85 @@
+//! The variables a booking hands to the cloud-init and recipes of its hosts, which can be
+//! changed until the first of its hosts has booted into its image
+
+use std::collections::BTreeMap;
+
+use axum::{
+    extract::{Json, Path},
+    http::StatusCode,
+};
+use dal::{new_client, web::*, AsEasyTransaction, FKey};
+use models::dashboard::{Aggregate, Checkpoint, DeployCheckpoint, LifeCycleState};
+use workflows::deploy_booking::booking_env;
+
+use super::BookingError;
+
+#[axum::debug_handler]
+pub async fn get_booking_env(
+    Path(agg_id): Path<FKey<Aggregate>>,
+) -> Result<Json<BTreeMap<String, String>>, BookingError> {
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+    let agg = agg_id.get(&mut transaction).await.log_error(
+        StatusCode::NOT_FOUND,
+        "No booking with that id",
+        true,
+    )?;
+
+    transaction.commit().await.log_db_client_error()?;
+
+    Ok(Json(agg.configuration.env.clone()))
+}
+
+/// Replaces the variables of the booking
+#[axum::debug_handler]
+pub async fn set_booking_env(
+    Path(agg_id): Path<FKey<Aggregate>>,
+    Json(env): Json<BTreeMap<String, String>>,
+) -> Result<Json<BTreeMap<String, String>>, BookingError> {
+    booking_env::validate(&env).map_err(|e| BookingError::BadRequest(e.to_string()))?;
+
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+    let mut agg = agg_id.get(&mut transaction).await.log_error(
+        StatusCode::NOT_FOUND,
+        "No booking with that id",
+        true,
+    )?;
+
+    let too_late = BookingError::Conflict(
+        "The variables of a booking can only be changed before its hosts boot".to_owned(),
+    );
+    if !matches!(
+        agg.state,
+        LifeCycleState::Queued | LifeCycleState::Scheduled | LifeCycleState::New
+    ) {
+        return Err(too_late);
+    }
+
+    // hosts fetch their cloud-init on first boot, so changes after that wouldn't reach them
+    for instance in agg
+        .instances(&mut transaction)
+        .await
+        .log_db_client_error()?
+    {
+        let booted = DeployCheckpoint::for_instance(&mut transaction, instance.id)
+            .await
+            .log_db_client_error()?
+            .iter()
+            .any(|c| c.checkpoint >= Checkpoint::FirstBootSeen);
+        if booted {
+            return Err(too_late);
+        }
+    }
+
+    agg.configuration.env = env;
+    agg.update(&mut transaction)
+        .await
+        .log_server_error("Unable to update the variables of the booking", true)?;
+
+    transaction.commit().await.log_db_client_error()?;
+
+    Ok(Json(agg.configuration.env.clone()))
+}
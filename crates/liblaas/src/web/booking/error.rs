@@ -0,0 +1,230 @@
+//! Errors of the booking API, answered with a code the dashboard can act on
+//!
+//! Every error is sent as an [`ErrorBody`], so callers can branch on its `code` and
+//! `retriable` instead of reading the message, which is meant for people. Errors raised
+//! before a handler runs, like bodies that couldn't be read, are given the same shape by
+//! [`structure`].
+
+use aide::OperationIo;
+use axum::{
+    body::{Body, HttpBody},
+    http::{header, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use common::prelude::tracing;
+use models::dashboard::IllegalTransition;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use thiserror::Error;
+
+use crate::booking;
+
+/// What went wrong, for callers to branch on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ErrorCode {
+    BadRequest,
+    Forbidden,
+    NotFound,
+    Conflict,
+    /// Something else is being done to the booking right now
+    Busy,
+    NoHostsAvailable,
+    /// The booking would go over the quotas of its owner or project, or be too long
+    QuotaExceeded,
+    /// An image can't be used for what was asked, like on a flavor it doesn't support
+    /// or after its sunset
+    ImageIncompatible,
+    /// The Idempotency-Key was already used for a different request
+    IdempotencyKeyReused,
+    /// The lab isn't set up for what was asked
+    NotImplemented,
+    Unavailable,
+    Internal,
+}
+
+/// How every error of the booking API is sent
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ErrorBody {
+    pub code: ErrorCode,
+    pub message: String,
+    /// Whether the same request could succeed if made again later
+    pub retriable: bool,
+    /// More about what went wrong, for the codes that have more to say
+    pub details: Option<Value>,
+}
+
+#[derive(Debug, Clone, Error, OperationIo)]
+#[aide(output_with = "Json<ErrorBody>")]
+pub enum BookingError {
+    #[error("{0}")]
+    BadRequest(String),
+    #[error("{0}")]
+    Forbidden(String),
+    #[error("{0}")]
+    NotFound(String),
+    #[error("{0}")]
+    Conflict(String),
+    #[error("{0}")]
+    Busy(String),
+    /// `plan` is what the booking would have been given, saying what stood in the way
+    #[error("{message}")]
+    NoHostsAvailable { message: String, plan: Value },
+    /// `limits` are the quotas that would have been gone over
+    #[error("{message}")]
+    QuotaExceeded { message: String, limits: Value },
+    #[error("{0}")]
+    ImageIncompatible(String),
+    #[error("{0}")]
+    IdempotencyKeyReused(String),
+    #[error("{0}")]
+    NotImplemented(String),
+    #[error("{0}")]
+    Unavailable(String),
+    #[error("{0}")]
+    Internal(String),
+}
+
+impl BookingError {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            Self::BadRequest(_) => ErrorCode::BadRequest,
+            Self::Forbidden(_) => ErrorCode::Forbidden,
+            Self::NotFound(_) => ErrorCode::NotFound,
+            Self::Conflict(_) => ErrorCode::Conflict,
+            Self::Busy(_) => ErrorCode::Busy,
+            Self::NoHostsAvailable { .. } => ErrorCode::NoHostsAvailable,
+            Self::QuotaExceeded { .. } => ErrorCode::QuotaExceeded,
+            Self::ImageIncompatible(_) => ErrorCode::ImageIncompatible,
+            Self::IdempotencyKeyReused(_) => ErrorCode::IdempotencyKeyReused,
+            Self::NotImplemented(_) => ErrorCode::NotImplemented,
+            Self::Unavailable(_) => ErrorCode::Unavailable,
+            Self::Internal(_) => ErrorCode::Internal,
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self.code() {
+            ErrorCode::BadRequest | ErrorCode::ImageIncompatible => StatusCode::BAD_REQUEST,
+            ErrorCode::Forbidden | ErrorCode::QuotaExceeded => StatusCode::FORBIDDEN,
+            ErrorCode::NotFound => StatusCode::NOT_FOUND,
+            ErrorCode::Conflict | ErrorCode::Busy | ErrorCode::NoHostsAvailable => {
+                StatusCode::CONFLICT
+            }
+            ErrorCode::IdempotencyKeyReused => StatusCode::UNPROCESSABLE_ENTITY,
+            ErrorCode::NotImplemented => StatusCode::NOT_IMPLEMENTED,
+            ErrorCode::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
+            ErrorCode::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// Whether the same request could succeed later, once whatever stood in the way of it
+    /// has moved
+    pub fn retriable(&self) -> bool {
+        matches!(
+            self.code(),
+            ErrorCode::Busy
+                | ErrorCode::NoHostsAvailable
+                | ErrorCode::Unavailable
+                | ErrorCode::Internal
+        )
+    }
+
+    pub fn details(&self) -> Option<Value> {
+        match self {
+            Self::NoHostsAvailable { plan, .. } => Some(plan.clone()),
+            Self::QuotaExceeded { limits, .. } => Some(limits.clone()),
+            _ => None,
+        }
+    }
+
+    pub fn body(&self) -> ErrorBody {
+        ErrorBody {
+            code: self.code(),
+            message: self.to_string(),
+            retriable: self.retriable(),
+            details: self.details(),
+        }
+    }
+}
+
+/// Lets what the rest of the web API and `dal::web` answer with be passed on with `?`,
+/// given the code its status stands for
+impl From<(StatusCode, String)> for BookingError {
+    fn from((status, message): (StatusCode, String)) -> Self {
+        match status {
+            StatusCode::FORBIDDEN | StatusCode::UNAUTHORIZED => Self::Forbidden(message),
+            StatusCode::NOT_FOUND => Self::NotFound(message),
+            StatusCode::CONFLICT => Self::Conflict(message),
+            StatusCode::NOT_IMPLEMENTED => Self::NotImplemented(message),
+            StatusCode::SERVICE_UNAVAILABLE => Self::Unavailable(message),
+            s if s.is_client_error() => Self::BadRequest(message),
+            _ => Self::Internal(message),
+        }
+    }
+}
+
+impl From<booking::Unbookable> for BookingError {
+    fn from(unbookable: booking::Unbookable) -> Self {
+        Self::NoHostsAvailable {
+            message: format!("Can't book this: {unbookable}"),
+            plan: serde_json::to_value(&unbookable.0).unwrap_or_default(),
+        }
+    }
+}
+
+impl From<booking::quota::QuotaExceeded> for BookingError {
+    fn from(exceeded: booking::quota::QuotaExceeded) -> Self {
+        Self::QuotaExceeded {
+            message: format!("Can't book this: {exceeded}"),
+            limits: serde_json::to_value(&exceeded.0).unwrap_or_default(),
+        }
+    }
+}
+
+impl From<IllegalTransition> for BookingError {
+    fn from(illegal: IllegalTransition) -> Self {
+        Self::Conflict(format!("Can't do this now, {illegal}"))
+    }
+}
+
+impl IntoResponse for BookingError {
+    fn into_response(self) -> Response {
+        (self.status(), Json(self.body())).into_response()
+    }
+}
+
+/// Gives errors answered with plain text, like those of extractors that turned the
+/// request away before any handler ran, the shape of an [`ErrorBody`]
+pub async fn structure(request: Request<Body>, next: Next<Body>) -> Response {
+    let response = next.run(request).await;
+
+    let status = response.status();
+    let plain = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|c| c.to_str().ok())
+        .is_some_and(|c| c.starts_with("text/plain"));
+    if !plain || !(status.is_client_error() || status.is_server_error()) {
+        return response;
+    }
+
+    let mut body = response.into_body();
+    let mut read = Vec::new();
+    while let Some(chunk) = body.data().await {
+        match chunk {
+            Ok(chunk) => read.extend_from_slice(&chunk),
+            Err(e) => {
+                tracing::warn!("Couldn't read the body of an error response: {e:?}");
+                break;
+            }
+        }
+    }
+
+    // answered with the status it was given, even where the code would have another
+    let error = BookingError::from((status, String::from_utf8_lossy(&read).into_owned()));
+    (status, Json(error.body())).into_response()
+}
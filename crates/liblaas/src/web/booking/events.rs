@@ -0,0 +1,80 @@
+//! The lifecycle log of a booking, for drawing its timeline
+
+use axum::{
+    extract::{Json, Path},
+    http::StatusCode,
+};
+use dal::{new_client, web::*, AsEasyTransaction, FKey};
+use models::dashboard::{
+    Aggregate, AggregateTransition, BookingEvent, BookingEventKind, LifeCycleState,
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use super::BookingError;
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct BookingEventBlob {
+    pub kind: BookingEventKind,
+    pub details: String,
+    pub actor: Option<String>,
+    pub time: String,
+}
+
+impl From<&BookingEvent> for BookingEventBlob {
+    fn from(e: &BookingEvent) -> Self {
+        Self {
+            kind: e.kind,
+            details: e.details.clone(),
+            actor: e.actor.clone(),
+            time: e.time.to_rfc2822(),
+        }
+    }
+}
+
+/// One change of the lifecycle state of a booking
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct StateTransitionBlob {
+    pub from: LifeCycleState,
+    pub to: LifeCycleState,
+    pub reason: String,
+    pub actor: Option<String>,
+    /// Set by an admin by hand, rather than by the booking moving along
+    pub forced: bool,
+    pub time: String,
+}
+
+impl From<&AggregateTransition> for StateTransitionBlob {
+    fn from(t: &AggregateTransition) -> Self {
+        Self {
+            from: t.from,
+            to: t.to,
+            reason: t.reason.clone(),
+            actor: t.actor.clone(),
+            forced: t.forced,
+            time: t.time.to_rfc2822(),
+        }
+    }
+}
+
+/// Oldest first
+#[axum::debug_handler]
+pub async fn booking_events(
+    Path(agg_id): Path<FKey<Aggregate>>,
+) -> Result<Json<Vec<BookingEventBlob>>, BookingError> {
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+    agg_id.get(&mut transaction).await.log_error(
+        StatusCode::NOT_FOUND,
+        "No booking with that id",
+        true,
+    )?;
+    let events = BookingEvent::for_aggregate(&mut transaction, agg_id)
+        .await
+        .log_db_client_error()?;
+
+    transaction.commit().await.log_db_client_error()?;
+
+    Ok(Json(events.iter().map(|e| (&**e).into()).collect()))
+}
@@ -0,0 +1,184 @@
+//! Growing a running booking by more hosts, instead of ending it and booking anew
+//!
+//! The added hosts connect to the networks the booking already has, by name, and are
+//! deployed alongside the hosts already in it.
+
+use std::collections::{HashMap, HashSet};
+
+use axum::{
+    extract::{Json, Path},
+    http::StatusCode,
+};
+use common::prelude::{chrono::Utc, itertools::Itertools, *};
+use dal::{new_client, web::*, AsEasyTransaction, FKey};
+use models::dashboard::{
+    Aggregate, BondGroupConfig, Cifile, HostConfig, Instance, LeaseOperation, LifeCycleState,
+    VlanConnectionConfig,
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use workflows::resource_management::images;
+
+use super::{api, check_not_busy, BookingError};
+use crate::booking;
+
+/// The most hosts that can be added to a booking at once
+const MAX_ADDED_HOSTS: usize = 16;
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ExpandBookingRequest {
+    #[serde(default)]
+    requested_by: Option<String>,
+    /// Left empty, a hostname is picked like for the rest of the booking
+    hosts: Vec<api::HostConfigBlob>,
+}
+
+#[axum::debug_handler]
+pub async fn expand_booking(
+    Path(agg_id): Path<FKey<Aggregate>>,
+    Json(request): Json<ExpandBookingRequest>,
+) -> Result<Json<Vec<FKey<Instance>>>, BookingError> {
+    tracing::info!(
+        "API call to expand_booking() for {agg_id:?} by {} host(s)",
+        request.hosts.len()
+    );
+
+    if request.hosts.is_empty() || request.hosts.len() > MAX_ADDED_HOSTS {
+        return Err(BookingError::BadRequest(format!(
+            "Between 1 and {MAX_ADDED_HOSTS} hosts can be added at once"
+        )));
+    }
+
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+    let agg = agg_id.get(&mut transaction).await.log_error(
+        StatusCode::NOT_FOUND,
+        "No booking with that id",
+        true,
+    )?;
+    if agg.deleted || agg.state != LifeCycleState::Active {
+        return Err(BookingError::Conflict(
+            "Only bookings that are up and running can have hosts added".to_owned(),
+        ));
+    }
+    check_not_busy(&mut transaction, agg_id, LeaseOperation::Expansion).await?;
+
+    let mut networks = HashMap::new();
+    for network in agg
+        .vlans
+        .get(&mut transaction)
+        .await
+        .log_db_client_error()?
+        .networks
+        .keys()
+    {
+        let name = network
+            .get(&mut transaction)
+            .await
+            .log_db_client_error()?
+            .name
+            .clone();
+        networks.insert(name, *network);
+    }
+
+    let mut taken: HashSet<String> = agg
+        .instances(&mut transaction)
+        .await
+        .log_db_client_error()?
+        .iter()
+        .map(|i| i.config.hostname.to_lowercase())
+        .collect();
+
+    let project = agg
+        .lab
+        .get(&mut transaction)
+        .await
+        .log_db_client_error()?
+        .name
+        .clone();
+
+    let mut configs = Vec::new();
+    for blob in request.hosts {
+        let hostname = blob.hostname.trim().to_owned();
+        if !hostname.is_empty() && !taken.insert(hostname.to_lowercase()) {
+            return Err(BookingError::Conflict(format!(
+                "The booking already has a host named {hostname}"
+            )));
+        }
+
+        let image = blob.image.get(&mut transaction).await.log_error(
+            StatusCode::NOT_FOUND,
+            "No image with that id",
+            true,
+        )?;
+        if image.deleted || image.sunset.is_some_and(|s| s <= Utc::now()) {
+            return Err(BookingError::ImageIncompatible(format!(
+                "{} can't be used for new hosts anymore",
+                image.name
+            )));
+        }
+        if !image.flavors.contains(&blob.flavor) {
+            return Err(BookingError::ImageIncompatible(format!(
+                "{} can't be installed on hosts of that flavor",
+                image.name
+            )));
+        }
+
+        let mut connections = Vec::new();
+        for bondgroup in blob.bondgroups {
+            let mut bgc = BondGroupConfig::default();
+            for iface in bondgroup.ifaces {
+                bgc.member_interfaces.insert(iface.name);
+            }
+            for connection in bondgroup.connections {
+                let network =
+                    networks
+                        .get(&connection.connects_to)
+                        .ok_or(BookingError::BadRequest(format!(
+                            "The booking has no network named {}",
+                            connection.connects_to
+                        )))?;
+                bgc.connects_to.insert(VlanConnectionConfig {
+                    network: *network,
+                    tagged: connection.tagged,
+                });
+            }
+            connections.push(bgc);
+        }
+
+        let cifile = Cifile::new(&mut transaction, blob.cifile)
+            .await
+            .log_server_error("unable to create CI file", true)?;
+
+        configs.push(HostConfig {
+            hostname,
+            flavor: blob.flavor,
+            image: blob.image,
+            cifile,
+            connections,
+            // hosts added to a booking are of exactly the flavor asked for
+            constraints: None,
+            kernel_args: Vec::new(),
+        });
+    }
+
+    let images = configs.iter().map(|c| c.image).collect_vec();
+    let violations = images::policy_violations(&mut transaction, &project, &images)
+        .await
+        .log_server_error("unable to check the images of the hosts", true)?;
+    if !violations.is_empty() {
+        return Err(BookingError::ImageIncompatible(format!(
+            "Can't add these hosts: {}",
+            violations.join("; ")
+        )));
+    }
+
+    transaction.commit().await.log_db_client_error()?;
+
+    let instances = booking::expand_aggregate(agg_id, configs, request.requested_by)
+        .await
+        .map_err(|e| BookingError::Conflict(format!("Can't add these hosts: {e}")))?;
+
+    Ok(Json(instances))
+}
@@ -0,0 +1,313 @@
+//! Admins deciding on requests to extend bookings
+//!
+//! Approving a request moves the end of the booking, as long as no booking scheduled
+//! after it has reserved any of its hosts by then. Either way, whoever asked is told.
+
+use axum::extract::{Json, Path};
+use common::prelude::{
+    chrono::{DateTime, Utc},
+    itertools::Itertools,
+    *,
+};
+use config::settings;
+use dal::{new_client, web::*, AsEasyTransaction, EasyTransaction, ExistingRow, FKey};
+use models::{
+    allocator::{Reservation, ResourceHandle},
+    dashboard::{
+        Aggregate, AggregateSummary, BookingEvent, BookingEventKind, ExtensionRequest,
+        ExtensionStatus, LeaseOperation,
+    },
+};
+use notifications::{booking_extension_decided, Env, ExtensionDecisionInfo};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use super::{check_not_busy, BookingError};
+use crate::web::identity::Admin;
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ExtensionRequestBlob {
+    id: FKey<ExtensionRequest>,
+    date: String,
+    reason: String,
+    requested_by: Option<String>,
+    requested: String,
+    status: ExtensionStatus,
+    decided_by: Option<String>,
+    decided: Option<String>,
+    note: Option<String>,
+    granted_end: Option<String>,
+}
+
+impl From<&ExtensionRequest> for ExtensionRequestBlob {
+    fn from(r: &ExtensionRequest) -> Self {
+        Self {
+            id: r.id,
+            date: r.date.clone(),
+            reason: r.reason.clone(),
+            requested_by: r.requested_by.clone(),
+            requested: r.requested.to_rfc2822(),
+            status: r.status,
+            decided_by: r.decided_by.clone(),
+            decided: r.decided.map(|d| d.to_rfc2822()),
+            note: r.note.clone(),
+            granted_end: r.granted_end.map(|e| e.to_rfc2822()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ApproveExtension {
+    /// The end to give the booking (RFC 3339), the one that was asked for if not given
+    #[schemars(with = "Option<String>")]
+    #[serde(default)]
+    end: Option<DateTime<Utc>>,
+    #[serde(default)]
+    note: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct DenyExtension {
+    #[serde(default)]
+    note: Option<String>,
+}
+
+#[axum::debug_handler]
+pub async fn list_extensions(
+    Path(agg_id): Path<FKey<Aggregate>>,
+) -> Result<Json<Vec<ExtensionRequestBlob>>, BookingError> {
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+    let requests = ExtensionRequest::all_for_aggregate(&mut transaction, agg_id)
+        .await
+        .log_db_client_error()?;
+
+    transaction.commit().await.log_db_client_error()?;
+
+    Ok(Json(requests.iter().map(|r| (&**r).into()).collect()))
+}
+
+/// Gets the request, which has to be of the booking and still waiting on a decision
+async fn pending_request(
+    transaction: &mut EasyTransaction<'_>,
+    agg_id: FKey<Aggregate>,
+    req_id: FKey<ExtensionRequest>,
+) -> Result<ExistingRow<ExtensionRequest>, BookingError> {
+    let request = req_id
+        .get(transaction)
+        .await
+        .ok()
+        .filter(|r| r.aggregate == agg_id)
+        .ok_or(BookingError::NotFound(
+            "The booking has no extension request by that id".to_owned(),
+        ))?;
+
+    if !request.is_pending() {
+        return Err(BookingError::Conflict(format!(
+            "The extension request was already {:?}",
+            request.status
+        )));
+    }
+
+    Ok(request)
+}
+
+/// Tells whoever asked for the extension, or the owner of the booking, how it went
+async fn notify_requester(
+    agg: &Aggregate,
+    request: &ExtensionRequest,
+) -> Result<(), anyhow::Error> {
+    let Some(username) = request.requested_by.clone().or(agg.metadata.owner.clone()) else {
+        tracing::warn!(
+            "Decided on extension request {:?}, but nobody to tell about it",
+            request.id
+        );
+        return Ok(());
+    };
+
+    let mut client = new_client().await?;
+    let mut transaction = client.easy_transaction().await?;
+    let project = agg.lab.get(&mut transaction).await?.name.clone();
+    transaction.commit().await?;
+
+    let info = ExtensionDecisionInfo {
+        booking: agg
+            .metadata
+            .display_name
+            .clone()
+            .or(agg.metadata.booking_id.clone())
+            .unwrap_or_else(|| agg.id.into_id().to_string()),
+        purpose: agg.metadata.purpose.clone().unwrap_or_default(),
+        requested_date: request.date.clone(),
+        granted_end: request.granted_end,
+        note: request.note.clone(),
+        dashboard_url: settings()
+            .projects
+            .get(project.as_str())
+            .map(|p| p.dashboard_url.clone())
+            .unwrap_or_default(),
+    };
+
+    if let Err(errors) = booking_extension_decided(&Env { project }, &username, &info).await {
+        tracing::error!("Couldn't tell {username} about their extension request: {errors:?}");
+    }
+
+    Ok(())
+}
+
+/// Moves the end of `agg` to `end`, if none of its hosts are reserved by then
+pub async fn extend(
+    transaction: &mut EasyTransaction<'_>,
+    agg: &mut ExistingRow<Aggregate>,
+    end: DateTime<Utc>,
+    by: &str,
+) -> Result<(), BookingError> {
+    if agg.deleted || agg.state.ended() {
+        return Err(BookingError::Conflict(
+            "The booking has already ended".to_owned(),
+        ));
+    }
+    check_not_busy(transaction, agg.id, LeaseOperation::Extension).await?;
+
+    if agg.metadata.end.is_some_and(|current| end <= current) || end <= Utc::now() {
+        return Err(BookingError::BadRequest(
+            "The new end has to be later than the current one".to_owned(),
+        ));
+    }
+
+    // bookings scheduled to get any of the hosts before the new end would be left without them
+    let mut handles = Vec::new();
+    for instance in agg.instances(transaction).await.log_db_client_error()? {
+        if let Some(host) = instance.linked_host {
+            handles.push(
+                ResourceHandle::handle_for_host(transaction, host)
+                    .await
+                    .log_db_client_error()?
+                    .id,
+            );
+        }
+    }
+    let conflicts = Reservation::conflicting(transaction, Some(end), Some(agg.id))
+        .await
+        .log_db_client_error()?
+        .into_iter()
+        .filter(|r| handles.contains(&r.for_resource))
+        .collect_vec();
+    if let Some(first) = conflicts.iter().map(|r| r.starts).min() {
+        return Err(BookingError::Conflict(format!(
+            "{} host(s) of the booking are reserved by other bookings from {}, \
+                so it can be extended until then at the latest",
+            conflicts.len(),
+            first.to_rfc2822()
+        )));
+    }
+
+    // a booking that hasn't started yet keeps its hosts reserved for as long as it now runs
+    for mut reservation in Reservation::all_for_aggregate(transaction, agg.id)
+        .await
+        .log_db_client_error()?
+    {
+        if !reservation.cancelled && reservation.ends.is_some_and(|e| e < end) {
+            reservation.ends = Some(end);
+            reservation
+                .update(transaction)
+                .await
+                .log_db_client_error()?;
+        }
+    }
+
+    BookingEvent::record(
+        transaction,
+        agg.id,
+        BookingEventKind::Extended,
+        format!(
+            "extended to {}{}",
+            end.to_rfc2822(),
+            agg.metadata
+                .end
+                .map(|e| format!(", it was to end {}", e.to_rfc2822()))
+                .unwrap_or_default()
+        ),
+        Some(by.to_owned()),
+    )
+    .await
+    .log_db_client_error()?;
+
+    agg.metadata.end = Some(end);
+    agg.update(transaction)
+        .await
+        .log_server_error("Unable to extend the booking", true)?;
+    AggregateSummary::refresh(transaction, agg.id)
+        .await
+        .log_db_client_error()?;
+
+    Ok(())
+}
+
+#[axum::debug_handler]
+pub async fn approve_extension(
+    admin: Admin,
+    Path((agg_id, req_id)): Path<(FKey<Aggregate>, FKey<ExtensionRequest>)>,
+    Json(decision): Json<ApproveExtension>,
+) -> Result<Json<ExtensionRequestBlob>, BookingError> {
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+    let mut request = pending_request(&mut transaction, agg_id, req_id).await?;
+    let mut agg = agg_id.get(&mut transaction).await.log_db_client_error()?;
+
+    let end = decision
+        .end
+        .or(request.requested_end())
+        .ok_or(BookingError::BadRequest(format!(
+            "Couldn't tell what end {:?} asks for, so one has to be given",
+            request.date
+        )))?;
+    extend(&mut transaction, &mut agg, end, &admin.name).await?;
+
+    request
+        .decide(Some(end), admin.name, decision.note)
+        .map_err(|e| BookingError::Conflict(e.to_string()))?;
+    request
+        .update(&mut transaction)
+        .await
+        .log_server_error("Unable to record the decision", true)?;
+
+    transaction.commit().await.log_db_client_error()?;
+
+    if let Err(e) = notify_requester(&agg, &request).await {
+        tracing::error!("Couldn't notify about extension request {req_id:?}: {e:?}");
+    }
+
+    Ok(Json((&*request).into()))
+}
+
+#[axum::debug_handler]
+pub async fn deny_extension(
+    admin: Admin,
+    Path((agg_id, req_id)): Path<(FKey<Aggregate>, FKey<ExtensionRequest>)>,
+    Json(decision): Json<DenyExtension>,
+) -> Result<Json<ExtensionRequestBlob>, BookingError> {
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+    let mut request = pending_request(&mut transaction, agg_id, req_id).await?;
+    let agg = agg_id.get(&mut transaction).await.log_db_client_error()?;
+
+    request
+        .decide(None, admin.name, decision.note)
+        .map_err(|e| BookingError::Conflict(e.to_string()))?;
+    request
+        .update(&mut transaction)
+        .await
+        .log_server_error("Unable to record the decision", true)?;
+
+    transaction.commit().await.log_db_client_error()?;
+
+    if let Err(e) = notify_requester(&agg, &request).await {
+        tracing::error!("Couldn't notify about extension request {req_id:?}: {e:?}");
+    }
+
+    Ok(Json((&*request).into()))
+}
@@ -0,0 +1,190 @@
+//! Lets users request that one of their booking networks be trunked onto
+//! an external carrier/partner feed, pending admin approval
+
+use axum::{
+    extract::{Json, Path},
+    http::StatusCode,
+};
+use common::prelude::{chrono::Utc, *};
+use dal::{new_client, web::*, AsEasyTransaction, FKey, NewRow};
+use models::dashboard::{Aggregate, AttachmentState, ExternalAttachment, Network};
+use notifications::email::send_to_admins;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::BookingError;
+use crate::web::identity::User;
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ExternalFeedBlob {
+    name: String,
+    description: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ExternalAttachmentRequest {
+    feed: String,
+    network: FKey<Network>,
+    reason: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ExternalAttachmentBlob {
+    id: FKey<ExternalAttachment>,
+    feed: String,
+    network: FKey<Network>,
+    state: AttachmentState,
+    requested_by: String,
+    reviewed_by: Option<String>,
+    requested: String,
+    updated: String,
+}
+
+impl From<&ExternalAttachment> for ExternalAttachmentBlob {
+    fn from(a: &ExternalAttachment) -> Self {
+        Self {
+            id: a.id,
+            feed: a.feed.clone(),
+            network: a.network,
+            state: a.state,
+            requested_by: a.requested_by.clone(),
+            reviewed_by: a.reviewed_by.clone(),
+            requested: a.requested.to_rfc2822(),
+            updated: a.updated.to_rfc2822(),
+        }
+    }
+}
+
+#[axum::debug_handler]
+pub async fn list_external_feeds() -> Json<Vec<ExternalFeedBlob>> {
+    let mut feeds: Vec<ExternalFeedBlob> = config::settings()
+        .external_feeds
+        .iter()
+        .map(|(name, feed)| ExternalFeedBlob {
+            name: name.clone(),
+            description: feed.description.clone(),
+        })
+        .collect();
+    feeds.sort_by(|a, b| a.name.cmp(&b.name));
+
+    Json(feeds)
+}
+
+#[axum::debug_handler]
+pub async fn list_external_attachments(
+    Path(agg_id): Path<Uuid>,
+) -> Result<Json<Vec<ExternalAttachmentBlob>>, BookingError> {
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+    let attachments =
+        ExternalAttachment::all_for_aggregate(&mut transaction, FKey::from_id(agg_id.into()))
+            .await
+            .log_db_client_error()?;
+
+    transaction.commit().await.log_db_client_error()?;
+
+    Ok(Json(attachments.iter().map(|a| (&**a).into()).collect()))
+}
+
+#[axum::debug_handler]
+pub async fn request_external_attachment(
+    by: User,
+    Path(agg_id): Path<Uuid>,
+    Json(request): Json<ExternalAttachmentRequest>,
+) -> Result<Json<ExternalAttachmentBlob>, BookingError> {
+    if !config::settings()
+        .external_feeds
+        .contains_key(&request.feed)
+    {
+        return Err(BookingError::BadRequest(format!(
+            "No external feed named {} exists",
+            request.feed
+        )));
+    }
+
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+    let agg_id: FKey<Aggregate> = FKey::from_id(agg_id.into());
+    let agg = agg_id.get(&mut transaction).await.log_error(
+        StatusCode::NOT_FOUND,
+        "Booking does not exist",
+        true,
+    )?;
+
+    if agg.state.ended() {
+        return Err(BookingError::Conflict(
+            "Booking has already ended".to_owned(),
+        ));
+    }
+
+    let netmap = agg
+        .vlans
+        .get(&mut transaction)
+        .await
+        .log_db_client_error()?;
+    if !netmap.networks.contains_key(&request.network) {
+        return Err(BookingError::BadRequest(
+            "Network is not part of this booking".to_owned(),
+        ));
+    }
+
+    let existing = ExternalAttachment::all_for_aggregate(&mut transaction, agg_id)
+        .await
+        .log_db_client_error()?;
+    if existing.iter().any(|a| {
+        a.network == request.network
+            && a.feed == request.feed
+            && matches!(
+                a.state,
+                AttachmentState::Requested | AttachmentState::Active
+            )
+    }) {
+        return Err(BookingError::Conflict(
+            "That network is already attached or awaiting approval for this feed".to_owned(),
+        ));
+    }
+
+    let network_name = request
+        .network
+        .get(&mut transaction)
+        .await
+        .log_db_client_error()?
+        .name
+        .clone();
+
+    let now = Utc::now();
+    let attachment = ExternalAttachment {
+        id: FKey::new_id_dangling(),
+        aggregate: agg_id,
+        network: request.network,
+        feed: request.feed,
+        state: AttachmentState::Requested,
+        requested_by: by.name,
+        reason: request.reason,
+        reviewed_by: None,
+        requested: now,
+        updated: now,
+    };
+
+    NewRow::new(attachment.clone())
+        .insert(&mut transaction)
+        .await
+        .log_server_error("Unable to record external attachment request", true)?;
+
+    transaction.commit().await.log_db_client_error()?;
+
+    send_to_admins(format!(
+        "{} requested that network {network_name} of booking {:?} be attached to external feed {}, \
+        because: {}. Review it from the CLI with \"Review External Attachments\".",
+        attachment.requested_by,
+        agg_id.into_id(),
+        attachment.feed,
+        attachment.reason
+    ))
+    .await;
+
+    Ok(Json((&attachment).into()))
+}
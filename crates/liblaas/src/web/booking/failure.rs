@@ -0,0 +1,91 @@
+//! The diagnostic bundles put together when hosts of an instance failed to provision
+
+use axum::{
+    extract::{Json, Path},
+    http::{header, StatusCode},
+};
+use dal::{new_client, web::*, AsEasyTransaction, FKey};
+use models::{
+    dashboard::{FailureBundle, Instance},
+    inventory::Host,
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use workflows::deploy_booking::failure_bundle;
+
+use super::BookingError;
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct FailureBundleBlob {
+    id: FKey<FailureBundle>,
+    host: FKey<Host>,
+    created: String,
+    error: String,
+}
+
+#[axum::debug_handler]
+pub async fn list_failure_bundles(
+    Path(instance_id): Path<Uuid>,
+) -> Result<Json<Vec<FailureBundleBlob>>, BookingError> {
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+    let instance_id: FKey<Instance> = FKey::from_id(instance_id.into());
+    instance_id.get(&mut transaction).await.log_error(
+        StatusCode::NOT_FOUND,
+        "Instance does not exist",
+        true,
+    )?;
+
+    let bundles = FailureBundle::for_instance(&mut transaction, instance_id)
+        .await
+        .log_db_client_error()?;
+
+    transaction.commit().await.log_db_client_error()?;
+
+    Ok(Json(
+        bundles
+            .iter()
+            .map(|b| FailureBundleBlob {
+                id: b.id,
+                host: b.host,
+                created: b.created.to_rfc2822(),
+                error: b.error.clone(),
+            })
+            .collect(),
+    ))
+}
+
+/// The bundle as the text file that was mailed to the admins
+#[axum::debug_handler]
+pub async fn download_failure_bundle(
+    Path(bundle_id): Path<Uuid>,
+) -> Result<([(header::HeaderName, String); 1], String), BookingError> {
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+    let bundle: FKey<FailureBundle> = FKey::from_id(bundle_id.into());
+    let bundle = bundle.get(&mut transaction).await.log_error(
+        StatusCode::NOT_FOUND,
+        "Failure bundle does not exist",
+        true,
+    )?;
+    let server_name = bundle
+        .host
+        .get(&mut transaction)
+        .await
+        .log_db_client_error()?
+        .server_name
+        .clone();
+
+    transaction.commit().await.log_db_client_error()?;
+
+    Ok((
+        [(
+            header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{server_name}-failure.txt\""),
+        )],
+        failure_bundle::render(&bundle, &server_name),
+    ))
+}
@@ -0,0 +1,137 @@
+//! Lets booking owners manage the inbound firewall policy of their uplinks
+
+use axum::extract::{Json, Path};
+use common::prelude::{chrono::Utc, *};
+use dal::{new_client, web::*, AsEasyTransaction, FKey, NewRow};
+use models::dashboard::{Aggregate, FirewallPolicy, FirewallRule, LeaseOperation};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use workflows::{
+    entry::{Action, DISPATCH},
+    resource_management::firewall::validate_rules,
+};
+
+use super::{check_not_busy, BookingError};
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct FirewallPolicyBlob {
+    /// Everything inbound that doesn't match one of these is denied
+    rules: Vec<FirewallRule>,
+    /// Whether the rules have been programmed onto the lab edge yet
+    applied: bool,
+    updated: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct FirewallPolicyRequest {
+    rules: Vec<FirewallRule>,
+}
+
+#[axum::debug_handler]
+pub async fn get_firewall_policy(
+    Path(agg_id): Path<Uuid>,
+) -> Result<Json<FirewallPolicyBlob>, BookingError> {
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+    let agg_id: FKey<Aggregate> = FKey::from_id(agg_id.into());
+    agg_id
+        .get(&mut transaction)
+        .await
+        .log_server_error("Booking does not exist", true)?;
+
+    let policy = FirewallPolicy::for_aggregate(&mut transaction, agg_id)
+        .await
+        .log_server_error("Unable to fetch firewall policy", true)?;
+
+    transaction.commit().await.log_db_client_error()?;
+
+    // with no policy on record the booking is default-deny
+    Ok(Json(match policy {
+        Some(p) => FirewallPolicyBlob {
+            rules: p.rules.clone(),
+            applied: p.applied,
+            updated: Some(p.updated.to_rfc2822()),
+        },
+        None => FirewallPolicyBlob {
+            rules: Vec::new(),
+            applied: true,
+            updated: None,
+        },
+    }))
+}
+
+#[axum::debug_handler]
+pub async fn set_firewall_policy(
+    Path(agg_id): Path<Uuid>,
+    Json(request): Json<FirewallPolicyRequest>,
+) -> Result<Json<FirewallPolicyBlob>, BookingError> {
+    let config = config::settings()
+        .firewall
+        .as_ref()
+        .ok_or(BookingError::NotImplemented(
+            "This lab does not manage booking firewalls".to_owned(),
+        ))?;
+
+    validate_rules(&request.rules, &config.guardrails)
+        .map_err(|problems| BookingError::BadRequest(problems.join("; ")))?;
+
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+    let agg_id: FKey<Aggregate> = FKey::from_id(agg_id.into());
+    let agg = agg_id
+        .get(&mut transaction)
+        .await
+        .log_server_error("Booking does not exist", true)?;
+
+    if agg.state.ended() {
+        return Err(BookingError::Conflict(
+            "Booking has already ended".to_owned(),
+        ));
+    }
+    check_not_busy(&mut transaction, agg_id, LeaseOperation::Network).await?;
+
+    let now = Utc::now();
+    match FirewallPolicy::for_aggregate(&mut transaction, agg_id)
+        .await
+        .log_server_error("Unable to fetch firewall policy", true)?
+    {
+        Some(mut existing) => {
+            existing.rules = request.rules.clone();
+            existing.applied = false;
+            existing.updated = now;
+            existing
+                .update(&mut transaction)
+                .await
+                .log_server_error("Unable to update firewall policy", true)?;
+        }
+        None => {
+            NewRow::new(FirewallPolicy {
+                id: FKey::new_id_dangling(),
+                aggregate: agg_id,
+                rules: request.rules.clone(),
+                applied: false,
+                updated: now,
+            })
+            .insert(&mut transaction)
+            .await
+            .log_server_error("Unable to create firewall policy", true)?;
+        }
+    }
+
+    transaction.commit().await.log_db_client_error()?;
+
+    DISPATCH
+        .get()
+        .ok_or(BookingError::Internal("Tascii was not found.".to_owned()))?
+        .send(Action::ApplyFirewall { agg_id })
+        .map_err(|_| BookingError::Internal("Unable to dispatch firewall update".to_owned()))?;
+
+    Ok(Json(FirewallPolicyBlob {
+        rules: request.rules,
+        applied: false,
+        updated: Some(now.to_rfc2822()),
+    }))
+}
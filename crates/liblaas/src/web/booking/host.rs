@@ -1,11 +1,12 @@
 use aide::OperationIo;
 use axum::{
+    body::Body,
     debug_handler,
     extract::{Json, Path},
-    http::StatusCode,
+    http::{HeaderMap, Request},
     response::{IntoResponse, Response},
 };
-use common::prelude::serde_json;
+use common::prelude::tokio;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
@@ -16,17 +17,24 @@ use uuid::Uuid;
 use dal::{new_client, AsEasyTransaction, DBTable, ExistingRow, ID};
 
 use models::{
-    dashboard::{Aggregate, Instance, LifeCycleState},
-    inventory::Host,
+    dashboard::{Aggregate, BookingCapability, Instance, LifeCycleState},
+    inventory::{BootTo, Host},
 };
 use workflows::{
+    deploy_booking::bmc::{sensor_report, Bmc, SensorReport},
+    deploy_booking::reboot::{self, RebootMode, RebootResult},
     deploy_booking::set_host_power_state::{
-        get_host_power_state, set_host_power_state, HostConfig, PowerState, PowerStateError,
-        TimeoutConfig,
+        apply_power_action, get_host_power_state, HostConfig, PowerAction, PowerActionResult,
+        PowerState, PowerStateError, TimeoutConfig,
     },
+    deploy_booking::sol::watch_console,
     entry::{Action, DISPATCH},
+    resource_management::health::excuse_power_action,
 };
 
+use super::{collaborators::check_capability, error::BookingError};
+use crate::web::websocket;
+
 /// Respective error types for the handlers. All of these error messages will be converted into an
 /// HTTP response.
 #[derive(Debug, Error, Deserialize, Serialize, JsonSchema, OperationIo)]
@@ -51,30 +59,34 @@ pub enum ApiPowerStateError {
 
     #[error("FQDN error: {0}")]
     FQDNError(String),
+
+    #[error("{0}")]
+    Forbidden(String),
 }
 
-/// Converts the errors into their respective HTTP responses.
+/// Converts the errors into their respective HTTP responses, shaped like every other error
+/// of the booking API.
 impl IntoResponse for ApiPowerStateError {
     fn into_response(self) -> Response {
-        let (status, error_message) = match self {
+        let message = self.to_string();
+        let error = match self {
             ApiPowerStateError::InvalidInstanceId
             | ApiPowerStateError::NoLinkedHosts
             | ApiPowerStateError::FQDNError(_)
-            | ApiPowerStateError::InactiveHost => (StatusCode::BAD_REQUEST, self.to_string()),
+            | ApiPowerStateError::InactiveHost => BookingError::BadRequest(message),
 
             ApiPowerStateError::DatabaseTransaction | ApiPowerStateError::DatabaseClient => {
-                (StatusCode::INTERNAL_SERVER_ERROR, self.to_string())
+                BookingError::Internal(message)
             }
 
-            ApiPowerStateError::IpmiOperationFailed(_) => {
-                (StatusCode::INTERNAL_SERVER_ERROR, self.to_string())
+            ApiPowerStateError::IpmiOperationFailed(PowerStateError::InvalidInputParameter(_)) => {
+                BookingError::BadRequest(message)
             }
+            ApiPowerStateError::IpmiOperationFailed(_) => BookingError::Internal(message),
+
+            ApiPowerStateError::Forbidden(_) => BookingError::Forbidden(message),
         };
-        (
-            status,
-            Json(serde_json::json!({ "message": error_message })),
-        )
-            .into_response()
+        error.into_response()
     }
 }
 
@@ -83,7 +95,29 @@ impl IntoResponse for ApiPowerStateError {
 pub enum PowerCommand {
     PowerOff,
     PowerOn,
+    /// The same as `Reset`
     Restart,
+    /// Powers the host off and back on
+    #[serde(alias = "cycle")]
+    Cycle,
+    /// Asks the OS to shut down cleanly through ACPI
+    #[serde(alias = "soft_shutdown")]
+    SoftShutdown,
+    /// A hard reset, without the host losing power
+    #[serde(alias = "reset")]
+    Reset,
+}
+
+impl From<&PowerCommand> for PowerAction {
+    fn from(command: &PowerCommand) -> Self {
+        match command {
+            PowerCommand::PowerOff => PowerAction::Off,
+            PowerCommand::PowerOn => PowerAction::On,
+            PowerCommand::Restart | PowerCommand::Reset => PowerAction::Reset,
+            PowerCommand::Cycle => PowerAction::Cycle,
+            PowerCommand::SoftShutdown => PowerAction::SoftShutdown,
+        }
+    }
 }
 
 /// The request payload for the power control handler, sent as JSON.
@@ -92,8 +126,21 @@ pub struct PowerCommandRequest {
     /// The power command to be executed.
     pub command: PowerCommand,
     #[serde(default)]
-    /// The timeout configuration for the IPMI command.
-    pub timeout_config: TimeoutConfig,
+    /// The timeout configuration for the IPMI command, each command has its own if not given
+    /// since a clean shutdown takes far longer than cutting power.
+    pub timeout_config: Option<TimeoutConfig>,
+}
+
+/// The longest a reboot can be waited on for
+const MAX_REBOOT_TIMEOUT_SECONDS: u64 = 1800;
+
+/// The request payload for the reboot handler, sent as JSON.
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct RebootRequest {
+    pub mode: RebootMode,
+    #[serde(default)]
+    /// How long to wait for the host to come back, ten minutes if not given.
+    pub timeout_seconds: Option<u64>,
 }
 
 /// The response payload for the power control handler, returned as JSON.
@@ -102,6 +149,35 @@ pub struct PowerStateResponse {
     pub power_state: PowerState,
 }
 
+/// What a host can be made to boot from on its next boot.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum BootDevice {
+    Pxe,
+    Disk,
+    /// The BIOS or UEFI setup of the host
+    BiosSetup,
+}
+
+/// The request payload for the boot device handler, sent as JSON.
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct BootDeviceRequest {
+    pub device: BootDevice,
+    #[serde(default)]
+    /// Whether to restart the host right away so it boots from the device, or power it on if it is off.
+    /// Otherwise the device is used whenever the host next boots.
+    pub restart: bool,
+}
+
+/// The response payload for the boot device handler, returned as JSON.
+#[derive(Debug, Serialize, Deserialize, JsonSchema, OperationIo)]
+pub struct BootDeviceResponse {
+    pub device: BootDevice,
+    /// How restarting the host went, if it was asked for
+    pub restart: Option<PowerActionResult>,
+    pub message: String,
+}
+
 #[derive(Debug, Serialize, Deserialize, JsonSchema, OperationIo)]
 pub struct IPMIFQDNResponse {
     pub ipmi_fqdn: String,
@@ -145,25 +221,103 @@ pub async fn instance_power_state(
     }
 }
 
+/// A handler that reads the sensors and event log of the host of a specific instance from its BMC.
+///
+/// This lets users tell whether a flaky host is overheating or losing a power supply,
+/// rather than misprovisioned. Reports are reused for a short while, see
+/// [`workflows::deploy_booking::bmc::SENSOR_REPORT_TTL_SECONDS`], so refreshing doesn't keep the BMC busy.
+///
+/// # Arguments
+///
+/// * `Path(instance_id)` - An [`Uuid`] representing an instance id as a [`Path`] parameter.
+///
+/// # Returns
+///
+/// This function returns a [`Result`] that wraps [`SensorReport`] as [`Json`] or an [`ApiPowerStateError`].
+pub async fn instance_sensors(
+    Path(instance_llid): Path<Uuid>,
+) -> Result<Json<SensorReport>, ApiPowerStateError> {
+    let instance = fetch_instance(&instance_llid).await?;
+
+    if !is_instance_active(&instance).await? {
+        error!("Cannot perform operation on an inactive host");
+        return Err(ApiPowerStateError::InactiveHost);
+    }
+
+    if let Some(host) = fetch_host(&instance).await? {
+        let report = sensor_report(&HostConfig::try_from(host)?).await?;
+
+        Ok(Json(report))
+    } else {
+        warn!("No host linked to instance ID: {}", instance_llid);
+        Err(ApiPowerStateError::NoLinkedHosts)
+    }
+}
+
+/// Handler to watch the serial console of an instance's host as it boots and provisions.
+///
+/// This handler switches the request over to a WebSocket, then sends everything the host
+/// prints to its console over IPMI serial-over-LAN as binary messages, as it prints it.
+/// Everyone watching a host shares the one session its BMC allows.
+///
+/// # Arguments
+///
+/// * `Path(instance_id)` - An [`Uuid`] representing an instance id as a [`Path`] parameter.
+/// * `request` - The request to switch over to a WebSocket.
+///
+/// # Returns
+///
+/// This function returns a [`Result`] that wraps the response switching protocols or an [`ApiPowerStateError`].
+pub async fn instance_console(
+    Path(instance_llid): Path<Uuid>,
+    mut request: Request<Body>,
+) -> Result<Response, ApiPowerStateError> {
+    let (response, connection) = match websocket::upgrade(&mut request) {
+        Ok(upgrade) => upgrade,
+        Err(e) => return Ok(BookingError::BadRequest(e).into_response()),
+    };
+
+    let instance = fetch_instance(&instance_llid).await?;
+
+    if !is_instance_active(&instance).await? {
+        error!("Cannot perform operation on an inactive host");
+        return Err(ApiPowerStateError::InactiveHost);
+    }
+
+    if let Some(host) = fetch_host(&instance).await? {
+        let output = watch_console(HostConfig::try_from(host)?).await?;
+        tokio::spawn(websocket::serve(connection, output));
+
+        Ok(response)
+    } else {
+        warn!("No host linked to instance ID: {}", instance_llid);
+        Err(ApiPowerStateError::NoLinkedHosts)
+    }
+}
+
 /// Handler to control the power state of an instance.
 ///
-/// This handler processes a power command (like power on, off, or restart) for a specific instance.
+/// This handler processes a power command (like power on, off, cycle, soft shutdown or reset) for a specific instance.
 /// It involves fetching the instance details, obtaining the linked host information, and then
 /// sending the appropriate IPMI command to change the power state of the host machine.
 ///
 /// # Arguments
 ///
+/// * `headers` - Who the request is made for, who has to be allowed to control the power of the booking's hosts.
 /// * `Path(instance_id)` - A [`LLID`] representing an instance id as a [`Path`] parameter.
 /// * `Json(request)` - A JSON payload that is deserialized into [`PowerCommandRequest`] representing the desired power command.
 ///
 /// # Returns
 ///
-/// This function returns a [`Result`] that wraps [`PowerStateResponse`] as [`Json`] or an [`ApiPowerStateError`].
+/// This function returns a [`Result`] that wraps [`PowerActionResult`] as [`Json`] or an [`ApiPowerStateError`].
+/// A host that didn't reach the state the command leads to in time is reported in the result
+/// rather than as an error.
 #[axum::debug_handler]
 pub async fn instance_power_control(
+    headers: HeaderMap,
     Path(instance_llid): Path<Uuid>,
     Json(request): Json<PowerCommandRequest>,
-) -> Result<Json<PowerStateResponse>, ApiPowerStateError> {
+) -> Result<Json<PowerActionResult>, ApiPowerStateError> {
     info!(
         "Attempting {:?} command for instance ID: {:?}",
         request.command, instance_llid
@@ -178,27 +332,262 @@ pub async fn instance_power_control(
     }
 
     if let Some(host) = fetch_host(&instance).await? {
-        // Determine the desired power state based on the command
-        let desired_state = match request.command {
-            PowerCommand::PowerOff => PowerState::Off,
-            PowerCommand::PowerOn => PowerState::On,
-            PowerCommand::Restart => PowerState::Reset,
-        };
+        let action = PowerAction::from(&request.command);
 
-        let power_state = set_host_power_state(
+        // the host is expected to go down, so the next health checks shouldn't count against the lab
+        let mut client = new_client()
+            .await
+            .map_err(|_| ApiPowerStateError::DatabaseClient)?;
+        let mut transaction = client
+            .easy_transaction()
+            .await
+            .map_err(|_| ApiPowerStateError::DatabaseTransaction)?;
+        let agg = instance
+            .aggregate
+            .get(&mut transaction)
+            .await
+            .map_err(|_| ApiPowerStateError::DatabaseTransaction)?;
+        check_capability(
+            &mut transaction,
+            &headers,
+            &agg,
+            BookingCapability::PowerControl,
+        )
+        .await
+        .map_err(|e| match e {
+            BookingError::Forbidden(reason) => ApiPowerStateError::Forbidden(reason),
+            _ => ApiPowerStateError::DatabaseTransaction,
+        })?;
+        excuse_power_action(&mut transaction, instance.id)
+            .await
+            .map_err(|_| ApiPowerStateError::DatabaseTransaction)?;
+        transaction
+            .commit()
+            .await
+            .map_err(|_| ApiPowerStateError::DatabaseTransaction)?;
+
+        let result = apply_power_action(
             &HostConfig::try_from(host)?,
-            request.timeout_config,
-            desired_state,
+            request
+                .timeout_config
+                .unwrap_or_else(|| action.default_timeout()),
+            action,
         )
         .await?;
+        info!(
+            "{:?} on instance ID {:?}: {}",
+            action, instance_llid, result.message
+        );
 
-        Ok(Json(PowerStateResponse { power_state }))
+        Ok(Json(result))
     } else {
         error!("No host linked to instance ID: {}", instance_llid);
         Err(ApiPowerStateError::NoLinkedHosts)
     }
 }
 
+/// Handler to set what the host of an instance boots from on its next boot.
+///
+/// This lets users debugging a failed provisioning force the host to PXE boot or go into its
+/// BIOS setup without an admin. The device only applies to the next boot, later boots go back to
+/// the boot order LibLaaS set up. Setting it takes the same capability as controlling the power
+/// of the booking's hosts.
+///
+/// # Arguments
+///
+/// * `headers` - Who the request is made for, who has to be allowed to control the power of the booking's hosts.
+/// * `Path(instance_id)` - A [`LLID`] representing an instance id as a [`Path`] parameter.
+/// * `Json(request)` - A JSON payload that is deserialized into [`BootDeviceRequest`].
+///
+/// # Returns
+///
+/// This function returns a [`Result`] that wraps [`BootDeviceResponse`] as [`Json`] or an [`ApiPowerStateError`].
+#[axum::debug_handler]
+pub async fn instance_boot_device(
+    headers: HeaderMap,
+    Path(instance_llid): Path<Uuid>,
+    Json(request): Json<BootDeviceRequest>,
+) -> Result<Json<BootDeviceResponse>, ApiPowerStateError> {
+    info!(
+        "Setting next boot device to {:?} for instance ID: {:?}",
+        request.device, instance_llid
+    );
+
+    let instance = fetch_instance(&instance_llid).await?;
+
+    if !is_instance_active(&instance).await? {
+        error!("Cannot perform operation on an inactive host");
+        return Err(ApiPowerStateError::InactiveHost);
+    }
+
+    let Some(host) = fetch_host(&instance).await? else {
+        error!("No host linked to instance ID: {}", instance_llid);
+        return Err(ApiPowerStateError::NoLinkedHosts);
+    };
+
+    let mut client = new_client()
+        .await
+        .map_err(|_| ApiPowerStateError::DatabaseClient)?;
+    let mut transaction = client
+        .easy_transaction()
+        .await
+        .map_err(|_| ApiPowerStateError::DatabaseTransaction)?;
+    let agg = instance
+        .aggregate
+        .get(&mut transaction)
+        .await
+        .map_err(|_| ApiPowerStateError::DatabaseTransaction)?;
+    check_capability(
+        &mut transaction,
+        &headers,
+        &agg,
+        BookingCapability::PowerControl,
+    )
+    .await
+    .map_err(|e| match e {
+        BookingError::Forbidden(reason) => ApiPowerStateError::Forbidden(reason),
+        _ => ApiPowerStateError::DatabaseTransaction,
+    })?;
+    if request.restart {
+        excuse_power_action(&mut transaction, instance.id)
+            .await
+            .map_err(|_| ApiPowerStateError::DatabaseTransaction)?;
+    }
+    transaction
+        .commit()
+        .await
+        .map_err(|_| ApiPowerStateError::DatabaseTransaction)?;
+
+    let config = HostConfig::try_from(host)?;
+    let bmc = config.bmc();
+    match request.device {
+        BootDevice::Pxe => bmc.set_boot_device(BootTo::Network, false).await?,
+        BootDevice::Disk => bmc.set_boot_device(BootTo::Disk, false).await?,
+        BootDevice::BiosSetup => bmc.boot_into_setup().await?,
+    }
+
+    let restart = if request.restart {
+        let action = match get_host_power_state(&config).await? {
+            PowerState::Off => PowerAction::On,
+            _ => PowerAction::Reset,
+        };
+        Some(apply_power_action(&config, action.default_timeout(), action).await?)
+    } else {
+        None
+    };
+
+    let message = match &restart {
+        Some(result) => format!(
+            "{} will boot from {:?}: {}",
+            config.fqdn, request.device, result.message
+        ),
+        None => format!(
+            "{} will boot from {:?} the next time it boots",
+            config.fqdn, request.device
+        ),
+    };
+    info!("Instance ID {:?}: {message}", instance_llid);
+
+    Ok(Json(BootDeviceResponse {
+        device: request.device,
+        restart,
+        message,
+    }))
+}
+
+/// Handler to reboot the host of an instance.
+///
+/// Unlike the power commands this waits for the OS to come back up, and can ask the OS to
+/// reboot itself over ssh instead of going through the BMC at all, so users don't have to
+/// hard reset a host that only needed a clean reboot. Rebooting takes the same capability as
+/// controlling the power of the booking's hosts.
+///
+/// # Arguments
+///
+/// * `headers` - Who the request is made for, who has to be allowed to control the power of the booking's hosts.
+/// * `Path(instance_id)` - A [`LLID`] representing an instance id as a [`Path`] parameter.
+/// * `Json(request)` - A JSON payload that is deserialized into [`RebootRequest`].
+///
+/// # Returns
+///
+/// This function returns a [`Result`] that wraps [`RebootResult`] as [`Json`] or an [`ApiPowerStateError`].
+/// A host that didn't come back in time is reported in the result rather than as an error.
+#[axum::debug_handler]
+pub async fn instance_reboot(
+    headers: HeaderMap,
+    Path(instance_llid): Path<Uuid>,
+    Json(request): Json<RebootRequest>,
+) -> Result<Json<RebootResult>, ApiPowerStateError> {
+    info!(
+        "Attempting {:?} reboot for instance ID: {:?}",
+        request.mode, instance_llid
+    );
+
+    let wait = match request.timeout_seconds {
+        Some(seconds) if seconds == 0 || seconds > MAX_REBOOT_TIMEOUT_SECONDS => {
+            return Err(ApiPowerStateError::IpmiOperationFailed(
+                PowerStateError::InvalidInputParameter(format!(
+                    "reboots can be waited on for between 1 and {MAX_REBOOT_TIMEOUT_SECONDS} seconds"
+                )),
+            ));
+        }
+        Some(seconds) => std::time::Duration::from_secs(seconds),
+        None => reboot::DEFAULT_TIMEOUT,
+    };
+
+    let instance = fetch_instance(&instance_llid).await?;
+
+    if !is_instance_active(&instance).await? {
+        error!("Cannot perform operation on an inactive host");
+        return Err(ApiPowerStateError::InactiveHost);
+    }
+
+    let Some(host) = fetch_host(&instance).await? else {
+        error!("No host linked to instance ID: {}", instance_llid);
+        return Err(ApiPowerStateError::NoLinkedHosts);
+    };
+
+    // the host is expected to go down, so the next health checks shouldn't count against the lab
+    let mut client = new_client()
+        .await
+        .map_err(|_| ApiPowerStateError::DatabaseClient)?;
+    let mut transaction = client
+        .easy_transaction()
+        .await
+        .map_err(|_| ApiPowerStateError::DatabaseTransaction)?;
+    let agg = instance
+        .aggregate
+        .get(&mut transaction)
+        .await
+        .map_err(|_| ApiPowerStateError::DatabaseTransaction)?;
+    check_capability(
+        &mut transaction,
+        &headers,
+        &agg,
+        BookingCapability::PowerControl,
+    )
+    .await
+    .map_err(|e| match e {
+        BookingError::Forbidden(reason) => ApiPowerStateError::Forbidden(reason),
+        _ => ApiPowerStateError::DatabaseTransaction,
+    })?;
+    excuse_power_action(&mut transaction, instance.id)
+        .await
+        .map_err(|_| ApiPowerStateError::DatabaseTransaction)?;
+    transaction
+        .commit()
+        .await
+        .map_err(|_| ApiPowerStateError::DatabaseTransaction)?;
+
+    let result = reboot::reboot(&host, request.mode, wait).await?;
+    info!(
+        "{:?} reboot of instance ID {:?}: {}",
+        request.mode, instance_llid, result.message
+    );
+
+    Ok(Json(result))
+}
+
 /// Fetches the an [`Instance`] from the database based on the given instance ID.
 ///
 /// # Arguments
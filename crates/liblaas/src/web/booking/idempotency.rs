@@ -0,0 +1,141 @@
+//! Making retried create-booking requests safe
+//!
+//! A client that sends an `Idempotency-Key` header with a request to create a booking
+//! can send the same request with the same key again, say after it timed out, and gets
+//! back the booking the first request made rather than allocating hosts a second time.
+//! Keys are per user, so two users picking the same key don't see each other's bookings.
+
+use axum::http::HeaderMap;
+use common::prelude::{chrono::Duration, chrono::Utc, *};
+use dal::{new_client, web::*, AsEasyTransaction, FKey};
+use models::dashboard::{Aggregate, IdempotencyKey};
+
+use super::BookingError;
+
+pub const IDEMPOTENCY_HEADER: &str = "Idempotency-Key";
+
+const MAX_KEY_LEN: usize = 255;
+
+/// A key claimed this long ago by a request that never finished can be claimed again
+const ABANDONED_AFTER_MINUTES: i64 = 10;
+
+pub enum Claim {
+    /// The request was already handled, and made this booking
+    Replay(FKey<Aggregate>),
+    /// The request is new, and is handled under the claimed key if one was sent
+    New(Option<FKey<IdempotencyKey>>),
+}
+
+/// Checks the key `username` sent with `request`, if any, and claims it for this request
+/// if it hasn't been seen before
+pub async fn claim(
+    headers: &HeaderMap,
+    username: &str,
+    request: serde_json::Value,
+) -> Result<Claim, BookingError> {
+    let Some(key) = headers.get(IDEMPOTENCY_HEADER) else {
+        return Ok(Claim::New(None));
+    };
+    let key = key
+        .to_str()
+        .ok()
+        .map(str::trim)
+        .filter(|k| !k.is_empty() && k.len() <= MAX_KEY_LEN)
+        .ok_or(BookingError::BadRequest(format!(
+            "The {IDEMPOTENCY_HEADER} header has to be 1 to {MAX_KEY_LEN} visible characters"
+        )))?
+        .to_owned();
+
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+    IdempotencyKey::purge_expired(&mut transaction)
+        .await
+        .log_db_client_error()?;
+
+    if let Some(existing) = IdempotencyKey::find(&mut transaction, username, &key)
+        .await
+        .log_db_client_error()?
+    {
+        if existing.request != request {
+            return Err(BookingError::IdempotencyKeyReused(format!(
+                "The {IDEMPOTENCY_HEADER} was already sent with a different request"
+            )));
+        }
+
+        match existing.aggregate {
+            Some(agg) => {
+                tracing::info!("Replaying create_booking for key {key}, it made {agg:?}");
+                return Ok(Claim::Replay(agg));
+            }
+            // whatever was handling it went away without settling the key
+            None if Utc::now() - existing.created > Duration::minutes(ABANDONED_AFTER_MINUTES) => {
+                existing
+                    .delete(&mut transaction)
+                    .await
+                    .log_db_client_error()?;
+            }
+            None => {
+                return Err(BookingError::Conflict(
+                    "The request with this key is still being handled, try again shortly"
+                        .to_owned(),
+                ))
+            }
+        }
+    }
+
+    let now = Utc::now();
+    let ttl = Duration::hours(config::settings().web.idempotency_ttl_hours as i64);
+
+    // the key is unique per user, so of two requests racing with it only one claims it
+    let claimed = IdempotencyKey {
+        id: FKey::new_id_dangling(),
+        username: username.to_owned(),
+        key,
+        request,
+        aggregate: None,
+        created: now,
+        expires: now + ttl,
+    }
+    .claim(&mut transaction)
+    .await
+    .log_db_client_error()?
+    .ok_or(BookingError::Conflict(
+        "The request with this key is still being handled, try again shortly".to_owned(),
+    ))?;
+
+    transaction.commit().await.log_db_client_error()?;
+
+    Ok(Claim::New(Some(claimed)))
+}
+
+/// Ties the claimed key to the booking the request made, or lets go of it if the request
+/// failed, so that it can be tried again
+pub async fn settle(
+    claimed: Option<FKey<IdempotencyKey>>,
+    result: &Result<FKey<Aggregate>, BookingError>,
+) {
+    let Some(claimed) = claimed else {
+        return;
+    };
+
+    let settled = async {
+        let mut client = new_client().await?;
+        let mut transaction = client.easy_transaction().await?;
+
+        let mut key = claimed.get(&mut transaction).await?;
+        match result {
+            Ok(agg) => {
+                key.aggregate = Some(*agg);
+                key.update(&mut transaction).await?;
+            }
+            Err(_) => key.delete(&mut transaction).await?,
+        }
+
+        transaction.commit().await
+    };
+
+    if let Err(e) = settled.await {
+        tracing::error!("Couldn't settle idempotency key {claimed:?}: {e:?}");
+    }
+}
@@ -0,0 +1,138 @@
+//! Where the keep and release links mailed to owners of idle bookings lead
+//!
+//! The links carry the token of the ask, so they work without logging in. Keeping only
+//! holds off the next ask, so following the link is enough. Releasing ends the booking,
+//! which mail scanners following every link must not be able to do, so that link shows
+//! a button to confirm with instead.
+
+use axum::{extract::Path, response::Html};
+use common::prelude::{chrono::Utc, *};
+use dal::{new_client, web::*, AsEasyTransaction, ExistingRow};
+use models::dashboard::{IdleAnswer, IdleNudge, LifeCycleState};
+
+use super::BookingError;
+use crate::booking;
+
+/// A page saying `message`, with a button posting back to the same link if `confirm`
+fn page(message: &str, confirm: bool) -> Html<String> {
+    let confirm = match confirm {
+        true => r#"<form method="post"><button type="submit">Release it</button></form>"#,
+        false => "",
+    };
+
+    Html(format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>LaaS Booking</title>
+<style>
+body {{ font-family: sans-serif; max-width: 40em; margin: 2em auto; }}
+</style>
+</head>
+<body>
+<p>{message}</p>
+{confirm}
+</body>
+</html>
+"#
+    ))
+}
+
+/// The unanswered ask `token` is for, or the page to show instead
+async fn open_nudge(
+    transaction: &mut dal::EasyTransaction<'_>,
+    token: &str,
+) -> Result<Result<ExistingRow<IdleNudge>, Html<String>>, BookingError> {
+    let Some(nudge) = IdleNudge::by_token(transaction, token)
+        .await
+        .log_db_client_error()?
+    else {
+        return Err(BookingError::NotFound("This link isn't valid".to_owned()));
+    };
+
+    if let Some(answer) = nudge.answer {
+        let answer = match answer {
+            IdleAnswer::Keep => "keep",
+            IdleAnswer::Release => "release",
+        };
+        return Ok(Err(page(
+            &format!("You already chose to {answer} this booking, thank you."),
+            false,
+        )));
+    }
+
+    let agg = nudge
+        .aggregate
+        .get(transaction)
+        .await
+        .log_db_client_error()?;
+    if !matches!(agg.state, LifeCycleState::Active) {
+        return Ok(Err(page("This booking has already ended.", false)));
+    }
+
+    Ok(Ok(nudge))
+}
+
+async fn answer(token: &str, answer: IdleAnswer) -> Result<Html<String>, BookingError> {
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+    let mut nudge = match open_nudge(&mut transaction, token).await? {
+        Ok(nudge) => nudge,
+        Err(page) => return Ok(page),
+    };
+
+    nudge.answer = Some(answer);
+    nudge.answered = Some(Utc::now());
+    nudge.update(&mut transaction).await.log_db_client_error()?;
+
+    // the answer is only kept once the booking is really ending
+    if answer == IdleAnswer::Release {
+        booking::end_booking(nudge.aggregate)
+            .await
+            .log_server_error("unable to end the booking", true)?;
+    }
+
+    transaction.commit().await.log_db_client_error()?;
+
+    tracing::info!(
+        "Owner of idle booking {:?} chose to {answer:?} it",
+        nudge.aggregate
+    );
+
+    Ok(page(
+        match answer {
+            IdleAnswer::Keep => "Thanks, we won't ask about this booking again for a while.",
+            IdleAnswer::Release => {
+                "Thanks, the booking is ending and its hosts are being cleaned up."
+            }
+        },
+        false,
+    ))
+}
+
+pub async fn keep(Path(token): Path<String>) -> Result<Html<String>, BookingError> {
+    answer(&token, IdleAnswer::Keep).await
+}
+
+pub async fn confirm_release(Path(token): Path<String>) -> Result<Html<String>, BookingError> {
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+    let page = match open_nudge(&mut transaction, &token).await? {
+        Ok(_) => page(
+            "Releasing the booking ends it and wipes its hosts, this can not be undone.",
+            true,
+        ),
+        Err(page) => page,
+    };
+
+    transaction.commit().await.log_db_client_error()?;
+
+    Ok(page)
+}
+
+pub async fn release(Path(token): Path<String>) -> Result<Html<String>, BookingError> {
+    answer(&token, IdleAnswer::Release).await
+}
@@ -0,0 +1,180 @@
+//! Reading the provision log of an instance a page at a time, and tailing it
+//!
+//! The log only grows, so every page comes back with a cursor to carry on from, even one
+//! that reached the end of what has been logged so far. Asking with `follow` holds a page
+//! that would come back empty until something is logged for the instance, or until
+//! [`FOLLOW_WAIT`] passes, so tools can tail a provision by asking again with each cursor.
+
+use std::time::Duration;
+
+use axum::extract::{Path, Query};
+use common::prelude::{
+    chrono::{DateTime, Utc},
+    tokio::{self, sync::broadcast::error::RecvError},
+};
+use dal::{new_client, web::*, AsEasyTransaction, FKey};
+use models::dashboard::{Instance, LogCursor, ProvisionLogEvent, StatusSentiment};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use workflows::utils::status_feed;
+
+use super::{BookingError, InstanceStatusUpdate};
+use crate::web::listing::{decode_cursor, encode_cursor, Listed, MAX_LIMIT};
+
+const DEFAULT_PAGE: usize = 100;
+
+/// The longest a `follow` request waits for something to be logged
+pub const FOLLOW_WAIT: Duration = Duration::from_secs(30);
+
+/// How bad the events of a log are, where each level has the events of the levels above it
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum LogSeverity {
+    /// Every event
+    Info,
+    /// Events of a degraded or failed provision
+    Warning,
+    /// Events of a failed provision
+    Error,
+}
+
+impl LogSeverity {
+    /// The sentiments of events at least this bad, or none for every event
+    fn sentiments(self) -> Vec<StatusSentiment> {
+        match self {
+            LogSeverity::Info => Vec::new(),
+            LogSeverity::Warning => vec![StatusSentiment::Degraded, StatusSentiment::Failed],
+            LogSeverity::Error => vec![StatusSentiment::Failed],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+pub struct LogQuery {
+    /// From the `x-next-cursor` header of the previous page
+    cursor: Option<String>,
+    limit: Option<usize>,
+    /// Only events logged since then (RFC 3339), for the first page
+    #[schemars(with = "Option<String>")]
+    since: Option<DateTime<Utc>>,
+    /// Only events at least this bad
+    severity: Option<LogSeverity>,
+    /// Wait for something to be logged rather than return an empty page
+    #[serde(default)]
+    follow: bool,
+}
+
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct LogEntryBlob {
+    pub id: FKey<ProvisionLogEvent>,
+    #[serde(flatten)]
+    pub update: InstanceStatusUpdate,
+}
+
+async fn read_page(
+    instance: FKey<Instance>,
+    query: &LogQuery,
+    after: Option<LogCursor>,
+    limit: usize,
+) -> Result<Vec<ProvisionLogEvent>, BookingError> {
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+    let page = ProvisionLogEvent::page(
+        &mut transaction,
+        instance,
+        query.since,
+        &query
+            .severity
+            .map(LogSeverity::sentiments)
+            .unwrap_or_default(),
+        after,
+        limit as i64,
+    )
+    .await
+    .log_db_client_error()?;
+
+    transaction.commit().await.log_db_client_error()?;
+
+    Ok(page.into_iter().map(|l| l.into_inner()).collect())
+}
+
+/// Waits until something is published to the status feed for `instance`, or until
+/// [`FOLLOW_WAIT`] passes
+async fn wait_for_log(
+    instance: FKey<Instance>,
+    feed: &mut tokio::sync::broadcast::Receiver<ProvisionLogEvent>,
+) {
+    let _ = tokio::time::timeout(FOLLOW_WAIT, async {
+        loop {
+            match feed.recv().await {
+                Ok(log) if log.instance == instance => return,
+                Ok(_) => {}
+                // whatever was missed may have been for this instance
+                Err(RecvError::Lagged(_)) => return,
+                Err(RecvError::Closed) => std::future::pending::<()>().await,
+            }
+        }
+    })
+    .await;
+}
+
+/// The provision log of an instance, a page at a time, oldest first
+#[axum::debug_handler]
+pub async fn instance_logs(
+    Path(instance_id): Path<Uuid>,
+    Query(query): Query<LogQuery>,
+) -> Result<Listed<LogEntryBlob>, BookingError> {
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE);
+    if limit == 0 || limit > MAX_LIMIT {
+        return Err(BookingError::BadRequest(format!(
+            "limit must be between 1 and {MAX_LIMIT}"
+        )));
+    }
+
+    let after = query
+        .cursor
+        .as_deref()
+        .map(decode_cursor::<LogCursor>)
+        .transpose()?;
+
+    let instance: FKey<Instance> = FKey::from_id(instance_id.into());
+    {
+        let mut client = new_client().await.log_db_client_error()?;
+        let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+        instance
+            .get(&mut transaction)
+            .await
+            .map_err(|_| BookingError::NotFound(format!("No instance {instance_id}")))?;
+        transaction.commit().await.log_db_client_error()?;
+    }
+
+    // subscribed before the log is read, so nothing logged in between is missed
+    let mut feed = query.follow.then(status_feed::subscribe);
+
+    let mut page = read_page(instance, &query, after, limit).await?;
+    if let (true, Some(feed)) = (page.is_empty(), feed.as_mut()) {
+        // events logged without being published are still found once the wait is over
+        wait_for_log(instance, feed).await;
+        page = read_page(instance, &query, after, limit).await?;
+    }
+
+    let next = match page.last() {
+        Some(last) => encode_cursor(&LogCursor {
+            time: last.time,
+            id: last.id,
+        }),
+        None => query.cursor.clone(),
+    };
+
+    let entries = page
+        .iter()
+        .map(|log| LogEntryBlob {
+            id: log.id,
+            update: InstanceStatusUpdate::from(log),
+        })
+        .collect();
+
+    Ok(Listed::page(entries, next)?)
+}
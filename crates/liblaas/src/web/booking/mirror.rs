@@ -0,0 +1,284 @@
+//! Lets users mirror the traffic of one of their booked interfaces onto
+//! another host of the same booking for capture
+
+use axum::{
+    extract::{Json, Path},
+    http::StatusCode,
+};
+use common::prelude::{chrono::Utc, *};
+use dal::{new_client, web::*, AsEasyTransaction, DBTable, EasyTransaction, FKey, NewRow};
+use models::{
+    dashboard::{Aggregate, Instance, LifeCycleState, PortMirror},
+    inventory::HostPort,
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use workflows::{
+    entry::{Action, DISPATCH},
+    resource_management::mirror::{free_session, MAX_MIRROR_MINUTES},
+};
+
+use super::BookingError;
+use crate::web::identity::User;
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct PortMirrorRequest {
+    /// The instance and interface whose traffic should be mirrored
+    source_instance: FKey<Instance>,
+    source_interface: String,
+
+    /// The instance and interface the capture will be run on
+    capture_instance: FKey<Instance>,
+    capture_interface: String,
+
+    duration_minutes: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct PortMirrorBlob {
+    id: FKey<PortMirror>,
+    source_interface: String,
+    capture_interface: String,
+    requested_by: String,
+    started: String,
+    expires: String,
+    ended: Option<String>,
+}
+
+impl PortMirrorBlob {
+    async fn from_mirror(
+        t: &mut EasyTransaction<'_>,
+        mirror: &PortMirror,
+    ) -> Result<Self, anyhow::Error> {
+        Ok(Self {
+            id: mirror.id,
+            source_interface: mirror.source.get(t).await?.name.clone(),
+            capture_interface: mirror.destination.get(t).await?.name.clone(),
+            requested_by: mirror.requested_by.clone(),
+            started: mirror.started.to_rfc2822(),
+            expires: mirror.expires.to_rfc2822(),
+            ended: mirror.ended.map(|e| e.to_rfc2822()),
+        })
+    }
+}
+
+/// Finds the named port of the host an instance of the booking is running on
+async fn booked_port(
+    t: &mut EasyTransaction<'_>,
+    agg_id: FKey<Aggregate>,
+    instance: FKey<Instance>,
+    interface: &str,
+) -> Result<HostPort, BookingError> {
+    let instance =
+        instance
+            .get(t)
+            .await
+            .log_error(StatusCode::NOT_FOUND, "Instance does not exist", true)?;
+
+    if instance.aggregate != agg_id {
+        return Err(BookingError::BadRequest(
+            "Instance is not part of this booking".to_owned(),
+        ));
+    }
+
+    let host = instance
+        .linked_host
+        .ok_or(BookingError::Conflict(
+            "Instance has not been assigned a host yet".to_owned(),
+        ))?
+        .get(t)
+        .await
+        .log_db_client_error()?;
+
+    host.ports(t)
+        .await
+        .log_db_client_error()?
+        .into_iter()
+        .find(|p| p.name == interface)
+        .ok_or(BookingError::BadRequest(format!(
+            "Host has no interface named {interface}"
+        )))
+}
+
+#[axum::debug_handler]
+pub async fn start_port_mirror(
+    by: User,
+    Path(agg_id): Path<Uuid>,
+    Json(request): Json<PortMirrorRequest>,
+) -> Result<Json<PortMirrorBlob>, BookingError> {
+    if request.duration_minutes == 0 || request.duration_minutes > MAX_MIRROR_MINUTES {
+        return Err(BookingError::BadRequest(format!(
+            "Mirrors must last between 1 and {MAX_MIRROR_MINUTES} minutes"
+        )));
+    }
+
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+    let agg_id: FKey<Aggregate> = FKey::from_id(agg_id.into());
+    let agg = agg_id.get(&mut transaction).await.log_error(
+        StatusCode::NOT_FOUND,
+        "Booking does not exist",
+        true,
+    )?;
+
+    if !matches!(agg.state, LifeCycleState::Active) {
+        return Err(BookingError::Conflict(
+            "Ports can only be mirrored while the booking is active".to_owned(),
+        ));
+    }
+
+    let source = booked_port(
+        &mut transaction,
+        agg_id,
+        request.source_instance,
+        &request.source_interface,
+    )
+    .await?;
+    let destination = booked_port(
+        &mut transaction,
+        agg_id,
+        request.capture_instance,
+        &request.capture_interface,
+    )
+    .await?;
+
+    if source.id == destination.id {
+        return Err(BookingError::BadRequest(
+            "An interface can not be mirrored onto itself".to_owned(),
+        ));
+    }
+
+    let mut switches = Vec::new();
+    for port in [&source, &destination] {
+        let switchport = port
+            .switchport
+            .ok_or(BookingError::Conflict(format!(
+                "Interface {} is not cabled to a switch",
+                port.name
+            )))?
+            .get(&mut transaction)
+            .await
+            .log_db_client_error()?;
+
+        switches.push(switchport.for_switch);
+    }
+
+    if switches[0] != switches[1] {
+        return Err(BookingError::BadRequest(
+            "Both interfaces must be connected to the same switch".to_owned(),
+        ));
+    }
+
+    let active = PortMirror::active_for_aggregate(&mut transaction, agg_id)
+        .await
+        .log_db_client_error()?;
+    if active.iter().any(|m| {
+        [m.source, m.destination]
+            .iter()
+            .any(|p| *p == source.id || *p == destination.id)
+    }) {
+        return Err(BookingError::Conflict(
+            "One of the interfaces is already part of a running mirror".to_owned(),
+        ));
+    }
+
+    let session = free_session(&mut transaction, switches[0])
+        .await
+        .log_db_client_error()?
+        .ok_or(BookingError::Unavailable(
+            "The switch has no free monitor sessions, try again later".to_owned(),
+        ))?;
+
+    let now = Utc::now();
+    let mirror = PortMirror {
+        id: FKey::new_id_dangling(),
+        aggregate: agg_id,
+        switch: switches[0],
+        session,
+        source: source.id,
+        destination: destination.id,
+        requested_by: by.name,
+        started: now,
+        expires: now + chrono::Duration::minutes(request.duration_minutes as i64),
+        ended: None,
+    };
+
+    NewRow::new(mirror.clone())
+        .insert(&mut transaction)
+        .await
+        .log_server_error("Unable to record port mirror", true)?;
+
+    let blob = PortMirrorBlob::from_mirror(&mut transaction, &mirror)
+        .await
+        .log_db_client_error()?;
+
+    transaction.commit().await.log_db_client_error()?;
+
+    DISPATCH
+        .get()
+        .ok_or(BookingError::Internal("Tascii was not found.".to_owned()))?
+        .send(Action::StartPortMirror { mirror: mirror.id })
+        .map_err(|_| BookingError::Internal("Unable to dispatch port mirror".to_owned()))?;
+
+    Ok(Json(blob))
+}
+
+#[axum::debug_handler]
+pub async fn list_port_mirrors(
+    Path(agg_id): Path<Uuid>,
+) -> Result<Json<Vec<PortMirrorBlob>>, BookingError> {
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+    let mut mirrors = PortMirror::select()
+        .where_field("aggregate")
+        .equals(FKey::<Aggregate>::from_id(agg_id.into()))
+        .run(&mut transaction)
+        .await
+        .log_db_client_error()?;
+    mirrors.sort_by_key(|m| m.started);
+
+    let mut blobs = Vec::new();
+    for mirror in mirrors {
+        blobs.push(
+            PortMirrorBlob::from_mirror(&mut transaction, &mirror)
+                .await
+                .log_db_client_error()?,
+        );
+    }
+
+    transaction.commit().await.log_db_client_error()?;
+
+    Ok(Json(blobs))
+}
+
+#[axum::debug_handler]
+pub async fn end_port_mirror(Path(mirror_id): Path<Uuid>) -> Result<(), BookingError> {
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+    let mirror: FKey<PortMirror> = FKey::from_id(mirror_id.into());
+    let existing = mirror.get(&mut transaction).await.log_error(
+        StatusCode::NOT_FOUND,
+        "Port mirror does not exist",
+        true,
+    )?;
+
+    transaction.commit().await.log_db_client_error()?;
+
+    if existing.ended.is_some() {
+        return Ok(());
+    }
+
+    DISPATCH
+        .get()
+        .ok_or(BookingError::Internal("Tascii was not found.".to_owned()))?
+        .send(Action::EndPortMirror { mirror })
+        .map_err(|_| {
+            BookingError::Internal("Unable to dispatch port mirror teardown".to_owned())
+        })?;
+
+    Ok(())
+}
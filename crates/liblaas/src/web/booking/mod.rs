@@ -1,68 +1,546 @@
 use common::prelude::{aide::axum::routing::post, itertools::Itertools, *};
-use models::dashboard::{AggregateConfiguration, Instance, StatusSentiment, Template};
+use models::dashboard::{
+    AggregateConfiguration, AggregateSummary, AggregateTag, AggregateTransition, BookingCapability,
+    BookingEvent, Instance, LeaseOperation, LifeCycleState, ParameterValue, StatusSentiment,
+    Template, TemplateAccess, TemplateShare,
+};
 
-use self::host::fetch_ipmi_fqdn;
-use super::{api, AppState, WebError};
+use self::{
+    collaborators::check_capability,
+    error::BookingError,
+    events::{BookingEventBlob, StateTransitionBlob},
+    host::fetch_ipmi_fqdn,
+};
+use super::{
+    api,
+    host::issues::HostIssueBlob,
+    identity::{is_admin, requesting_user, Admin, User},
+    input::StrictJson,
+    jobs::{accepted, JobStarted},
+    AppState,
+};
 use crate::{booking, booking::make_aggregate};
 use aide::{
     axum::{
-        routing::{delete, get},
+        routing::{delete, get, patch, put},
         ApiRouter,
     },
     OperationIo,
 };
 use axum::{
-    extract::{Json, Path},
-    http::StatusCode,
+    extract::{Json, Path, Query},
+    http::{HeaderMap, StatusCode},
 };
 use config::Situation;
-use dal::{new_client, web::*, AsEasyTransaction, DBTable, ExistingRow, FKey};
-use host::{instance_power_control, instance_power_state};
+use dal::{
+    new_client, web::*, AsEasyTransaction, DBTable, EasyTransaction, ExistingRow, FKey, NewRow,
+};
+use host::{
+    instance_boot_device, instance_console, instance_power_control, instance_power_state,
+    instance_reboot, instance_sensors,
+};
 use models::dashboard::Image;
+use models::inventory::{FailureDomain, HostIssue};
+use notifications::contacts;
 
-use models::dashboard::{self, Aggregate, ProvisionLogEvent};
+use models::dashboard::{
+    self, Aggregate, InstanceHealthCheck, IsolationAttestation, IsolationFinding, ProvisionLogEvent,
+};
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
 use std::collections::HashMap;
 use uuid::Uuid;
-use workflows::entry::DISPATCH;
+use workflows::{
+    cleanup_booking,
+    deploy_booking::{
+        booking_env,
+        nic_inventory::{self, NicInventory},
+        notify::NotifyContext,
+        parameters,
+        rolling_reimage::{self, MAX_BATCHES},
+    },
+    entry::DISPATCH,
+    resource_management::{
+        composability::{self, Composition},
+        health::{self, excuse_instance, SlaSummary, REIMAGE_GRACE},
+        images, jobs, lease,
+    },
+    utils::resilience::{self, BreakerStatus, Device},
+};
 
+pub mod access;
+pub mod availability;
+pub mod checkpoint;
+pub mod cohort;
+pub mod collaborators;
+pub mod configuration;
+pub mod draft;
+pub mod env;
+pub mod error;
+pub mod events;
+pub mod expand;
+pub mod extension;
+pub mod external;
+pub mod failure;
+pub mod firewall;
 pub mod host;
+pub mod idempotency;
+pub mod idle;
+pub mod logs;
+pub mod mirror;
+pub mod provision;
+pub mod quota;
+pub mod rebook;
+pub mod replace;
+pub mod reprovision;
+pub mod roster;
+pub mod snapshot;
+pub mod stream;
+pub mod summary;
+pub mod support;
+pub mod tags;
+pub mod telemetry;
+pub mod trash;
+pub mod usage;
 
 pub fn routes(state: AppState) -> ApiRouter {
     ApiRouter::new() // remember that in order to have the Handler trait, all inputs for
         // a handler need to implement FromRequest, and all outputs need to implement IntoResponse
+        .route("/:agg_id", patch(update_booking))
         .route("/:agg_id/status", get(booking_status))
+        .route("/:agg_id/config", get(configuration::booking_configuration))
+        .route("/:agg_id/expiry", post(set_expiry_exemption))
+        .route(
+            "/:agg_id/collaborators",
+            patch(collaborators::change_collaborators),
+        )
+        .route("/:agg_id/status/stream", get(stream::booking_status_stream))
+        .route(
+            "/:agg_id/collaborators/import",
+            post(roster::import_collaborators),
+        )
+        .route(
+            "/:agg_id/collaborators/export",
+            get(roster::export_collaborators),
+        )
+        .route(
+            "/:agg_id/collaborators/:user/permissions",
+            put(collaborators::set_permissions),
+        )
+        .route("/:agg_id/summary", get(summary::booking_summary))
+        .route(
+            "/:agg_id/support",
+            get(support::list_support_requests).post(support::request_support),
+        )
+        .route(
+            "/:agg_id/tags",
+            get(tags::get_tags).patch(tags::update_tags),
+        )
+        .route("/:agg_id/telemetry", get(telemetry::booking_telemetry))
+        .route("/:agg_id/usage", get(usage::booking_usage))
+        .route("/:agg_id/events", get(events::booking_events))
+        .route("/:agg_id/access-log", get(access::booking_access_log))
+        .route("/:agg_id/expand", post(expand::expand_booking))
+        .route("/:agg_id/snapshot", post(snapshot::take_snapshot))
+        .route("/:agg_id/rebook", post(rebook::rebook))
+        .route("/snapshot/list/:owner", get(snapshot::list_snapshots))
+        .route("/snapshot/:snapshot_id", get(snapshot::get_snapshot))
+        .route(
+            "/snapshot/:snapshot_id/restore",
+            post(snapshot::restore_snapshot),
+        )
+        .route(
+            "/:agg_id/env",
+            get(env::get_booking_env).put(env::set_booking_env),
+        )
+        .route("/status/batch", post(summary::batch_status))
+        .route("/list", get(summary::list_all_bookings))
+        .route("/availability", get(availability::flavor_availability))
+        .route("/search", get(tags::search_tags))
+        .route("/trash", get(trash::list_trash))
+        .route(
+            "/quota/overrides",
+            get(quota::list_overrides).post(quota::grant_override),
+        )
+        .route(
+            "/quota/overrides/:override_id",
+            delete(quota::revoke_override),
+        )
+        .route("/list/:username", get(summary::list_bookings))
         .route("/create", post(create_booking))
+        .route("/validate", post(validate_booking))
+        .route("/draft", post(draft::create_draft))
+        .route("/draft/list/:owner", get(draft::list_drafts))
+        .route(
+            "/draft/:draft_id",
+            get(draft::get_draft)
+                .patch(draft::update_draft)
+                .delete(draft::discard_draft),
+        )
+        .route("/draft/:draft_id/submit", post(draft::submit_draft))
         .route("/:agg_id/end", delete(end_booking))
+        .route("/:agg_id/instance/:instance_id", delete(release_instance))
+        .route("/idle/:token/keep", get(idle::keep))
+        .route(
+            "/idle/:token/release",
+            get(idle::confirm_release).post(idle::release),
+        )
         .route("/:instance_id/reimage", post(reimage_host))
+        .route(
+            "/:instance_id/reprovision",
+            post(reprovision::reprovision_host),
+        )
+        .route(
+            "/:instance_id/cancel-provision",
+            post(provision::cancel_provision),
+        )
+        .route("/:instance_id/retry", post(provision::retry_provision))
+        .route("/:instance_id/reboot", post(instance_reboot))
+        .route("/:instance_id/console", get(instance_console))
+        .route("/:instance_id/logs", get(logs::instance_logs))
+        .route(
+            "/:instance_id/checkpoints",
+            get(checkpoint::deploy_progress),
+        )
+        .route(
+            "/:instance_id/retry-from-phase",
+            post(checkpoint::retry_from_phase),
+        )
+        .route(
+            "/:instance_id/failure-bundles",
+            get(failure::list_failure_bundles),
+        )
+        .route(
+            "/failure-bundles/:bundle_id",
+            get(failure::download_failure_bundle),
+        )
+        .route(
+            "/:instance_id/replace-host",
+            get(replace::list_host_replacements).post(replace::request_host_replacement),
+        )
+        .route("/:agg_id/rolling-reimage", post(rolling_reimage))
+        .route("/reimage/bulk", post(bulk_reimage))
+        .route("/end-bulk", post(bulk_end_bookings))
+        .route("/cohort/create", post(cohort::create_cohort))
+        .route("/cohort/list/:instructor", get(cohort::list_cohorts))
+        .route("/cohort/:cohort_id", get(cohort::cohort_status))
+        .route("/cohort/:cohort_id/extend", post(cohort::extend_cohort))
+        .route("/cohort/:cohort_id/end", post(cohort::end_cohort))
         .route("/ipmi/:instance_id/powerstatus", get(instance_power_state))
         .route("/ipmi/:instance_id/setpower", post(instance_power_control))
+        .route("/ipmi/:instance_id/bootdev", post(instance_boot_device))
+        .route("/ipmi/:instance_id/sensors", get(instance_sensors))
         .route("/ipmi/:instance_id/getfqdn", get(fetch_ipmi_fqdn))
+        .route(
+            "/:agg_id/firewall",
+            get(firewall::get_firewall_policy).put(firewall::set_firewall_policy),
+        )
+        .route("/external/feeds", get(external::list_external_feeds))
+        .route(
+            "/:agg_id/external",
+            get(external::list_external_attachments).post(external::request_external_attachment),
+        )
+        .route(
+            "/:agg_id/mirror",
+            get(mirror::list_port_mirrors).post(mirror::start_port_mirror),
+        )
+        .route("/mirror/:mirror_id", delete(mirror::end_port_mirror))
         .route("/:agg_id/notify/expiring", post(notify_aggregate_expiring))
         .route(
             "/:agg_id/request-extension",
             post(request_booking_extension),
         )
+        .route("/:agg_id/extension", get(extension::list_extensions))
+        .route(
+            "/:agg_id/extension/:req_id/approve",
+            post(extension::approve_extension),
+        )
+        .route(
+            "/:agg_id/extension/:req_id/deny",
+            post(extension::deny_extension),
+        )
+        .route_layer(axum::middleware::from_fn(access::record))
+        .layer(axum::middleware::from_fn(error::structure))
 }
 
 #[axum::debug_handler]
 async fn create_booking(
-    Json(agg): Json<api::BookingBlob>,
-) -> Result<Json<FKey<dashboard::Aggregate>>, WebError> {
+    headers: HeaderMap,
+    StrictJson(agg): StrictJson<api::BookingBlob>,
+) -> Result<Json<FKey<dashboard::Aggregate>>, BookingError> {
     tracing::info!("API call to create_booking()");
-    let agg = make_aggregate(agg)
+
+    let request = serde_json::to_value(&agg)
+        .anyway()
+        .log_server_error("unable to read the booking request", true)?;
+    // keys are scoped to who is making the booking
+    let username = requesting_user(&headers)
+        .or(agg.metadata.owner.as_deref())
+        .unwrap_or_default()
+        .to_owned();
+    let claimed = match idempotency::claim(&headers, &username, request).await? {
+        idempotency::Claim::Replay(agg) => return Ok(Json(agg)),
+        idempotency::Claim::New(claimed) => claimed,
+    };
+
+    let result = match check_delegation(&headers, &agg)
+        .and_then(|()| check_ownership_override(&headers, &agg))
+    {
+        Ok(()) => create_aggregate(agg).await,
+        Err(e) => Err(e),
+    };
+    idempotency::settle(claimed, &result).await;
+
+    result.map(Json)
+}
+
+async fn create_aggregate(
+    mut agg: api::BookingBlob,
+) -> Result<FKey<dashboard::Aggregate>, BookingError> {
+    if let Some(name) = agg.metadata.display_name.take() {
+        agg.metadata.display_name = display_name(&name)?;
+    }
+
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+    booking_env::validate(&agg.env).map_err(|e| BookingError::BadRequest(e.to_string()))?;
+    for tag in agg.tags.iter() {
+        AggregateTag::normalize(tag).map_err(|e| BookingError::BadRequest(e.to_string()))?;
+    }
+
+    let template = check_bookable(
+        &mut transaction,
+        agg.template_id,
+        agg.metadata.owner.as_deref(),
+    )
+    .await?;
+    check_image_policy(&mut transaction, &agg.origin, &template, &agg.parameters).await?;
+    transaction.commit().await.log_db_client_error()?;
+
+    match make_aggregate(agg).await {
+        Ok(agg) => Ok(agg),
+        // what stood in the way is given back as the plan, so it can be acted on
+        Err(e) => match e.downcast::<booking::Unbookable>() {
+            Ok(unbookable) => Err(unbookable.into()),
+            Err(e) => match e.downcast::<booking::quota::QuotaExceeded>() {
+                Ok(exceeded) => Err(exceeded.into()),
+                Err(e) => Err(e)
+                    .log_server_error("unable to create the aggregate/booking", true)
+                    .map_err(BookingError::from),
+            },
+        },
+    }
+}
+
+/// Checks everything `create_booking` would and reports what the booking would be given,
+/// without creating it
+#[axum::debug_handler]
+async fn validate_booking(
+    headers: HeaderMap,
+    StrictJson(agg): StrictJson<api::BookingBlob>,
+) -> Result<Json<api::BookingPlan>, BookingError> {
+    tracing::info!("API call to validate_booking()");
+
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+    let mut problems = Vec::new();
+    if let Err(e) = booking_env::validate(&agg.env) {
+        problems.push(e.to_string());
+    }
+    for tag in agg.tags.iter() {
+        if let Err(e) = AggregateTag::normalize(tag) {
+            problems.push(e.to_string());
+        }
+    }
+    if let Err(e) = check_delegation(&headers, &agg) {
+        problems.push(e.to_string());
+    }
+    if let Err(e) = check_ownership_override(&headers, &agg) {
+        problems.push(e.to_string());
+    }
+    match check_bookable(
+        &mut transaction,
+        agg.template_id,
+        agg.metadata.owner.as_deref(),
+    )
+    .await
+    {
+        Ok(template) => {
+            if let Err(e) =
+                check_image_policy(&mut transaction, &agg.origin, &template, &agg.parameters).await
+            {
+                problems.push(e.to_string());
+            }
+        }
+        Err(e @ (BookingError::NotFound(_) | BookingError::Internal(_))) => return Err(e),
+        Err(e) => problems.push(e.to_string()),
+    }
+    transaction.commit().await.log_db_client_error()?;
+
+    let mut plan = booking::plan_aggregate(agg)
+        .await
+        .map_err(|e| BookingError::BadRequest(format!("Can't book this: {e}")))?;
+    problems.append(&mut plan.problems);
+    plan.problems = problems;
+    plan.bookable = plan.problems.is_empty();
+
+    Ok(Json(plan))
+}
+
+/// Refuses bookings made for someone else, unless whoever is making it leads the project
+/// or is an admin
+fn check_delegation(headers: &HeaderMap, agg: &api::BookingBlob) -> Result<(), BookingError> {
+    let Some(owner) = agg.owner.as_deref() else {
+        return Ok(());
+    };
+    let booker = agg
+        .metadata
+        .owner
+        .as_deref()
+        .ok_or(BookingError::BadRequest(
+            "Who is making a booking for someone else has to be given as the owner in its metadata"
+                .to_owned(),
+        ))?;
+    if booker == owner || is_admin(headers) {
+        return Ok(());
+    }
+
+    let leads = config::settings()
+        .projects
+        .get(&agg.origin)
+        .is_some_and(|p| p.leads.iter().any(|l| l == booker));
+    match leads {
+        true => Ok(()),
+        false => Err(BookingError::Forbidden(format!(
+            "Only leads of {} and admins can book for someone else",
+            agg.origin
+        ))),
+    }
+}
+
+/// Refuses bookings that ask for hosts funded by other projects, unless made by an admin
+fn check_ownership_override(
+    headers: &HeaderMap,
+    agg: &api::BookingBlob,
+) -> Result<(), BookingError> {
+    match agg.ownership_override.as_deref() {
+        None => Ok(()),
+        Some(_) if !is_admin(headers) => Err(BookingError::Forbidden(
+            "Only admins can book hosts funded by other projects".to_owned(),
+        )),
+        Some(reason) if reason.trim().is_empty() => Err(BookingError::BadRequest(
+            "Booking hosts funded by other projects needs a reason".to_owned(),
+        )),
+        Some(_) => Ok(()),
+    }
+}
+
+/// Refuses templates that can't be booked anymore, like ones with sunset images,
+/// or that `booker` isn't allowed to book from
+async fn check_bookable(
+    transaction: &mut EasyTransaction<'_>,
+    template_id: FKey<Template>,
+    booker: Option<&str>,
+) -> Result<ExistingRow<Template>, BookingError> {
+    let template = template_id.get(transaction).await.log_error(
+        StatusCode::NOT_FOUND,
+        "no template by that id",
+        true,
+    )?;
+
+    let access = match booker {
+        Some(booker) => {
+            let share = TemplateShare::find(transaction, template_id, booker)
+                .await
+                .log_db_client_error()?;
+            TemplateAccess::of(&template, booker, share.as_deref())
+        }
+        None => template.public.then_some(TemplateAccess::Book),
+    };
+    if !access.is_some_and(TemplateAccess::can_book) {
+        return Err(BookingError::Forbidden(
+            "This template hasn't been shared with you for booking".to_owned(),
+        ));
+    }
+    let violations = images::sunset_violations(transaction, &template)
         .await
-        .log_server_error("unable to create the aggregate/booking", true)?;
+        .log_server_error("unable to check the images of the template", true)?;
 
-    Ok(Json(agg))
+    if !violations.is_empty() {
+        return Err(BookingError::ImageIncompatible(format!(
+            "Can't book this template: {}",
+            violations.join("; ")
+        )));
+    }
+
+    Ok(template)
+}
+
+/// Turns the request away with a conflict while the booking is busy with something that
+/// `operation` can't be done alongside
+pub async fn check_not_busy(
+    transaction: &mut EasyTransaction<'_>,
+    agg_id: FKey<Aggregate>,
+    operation: LeaseOperation,
+) -> Result<(), BookingError> {
+    match lease::conflicting(transaction, agg_id, operation)
+        .await
+        .log_server_error("unable to check what the booking is busy with", true)?
+    {
+        Some(busy) => Err(BookingError::Busy(format!("Try again later, {busy}"))),
+        None => Ok(()),
+    }
+}
+
+/// Refuses bookings with hosts running images the project doesn't let its members use
+async fn check_image_policy(
+    transaction: &mut EasyTransaction<'_>,
+    project: &str,
+    template: &Template,
+    values: &HashMap<String, ParameterValue>,
+) -> Result<(), BookingError> {
+    let images = parameters::images(template, values)
+        .map_err(|e| BookingError::BadRequest(e.to_string()))?;
+    let violations = images::policy_violations(transaction, project, &images)
+        .await
+        .log_server_error("unable to check the images of the booking", true)?;
+
+    if !violations.is_empty() {
+        return Err(BookingError::ImageIncompatible(format!(
+            "Can't book this template: {}",
+            violations.join("; ")
+        )));
+    }
+
+    Ok(())
 }
 
 #[axum::debug_handler]
-async fn end_booking(Path(agg_id): Path<FKey<Aggregate>>) -> Json<EndBookingResponse> {
+async fn end_booking(
+    headers: HeaderMap,
+    Path(agg_id): Path<FKey<Aggregate>>,
+) -> Result<Json<EndBookingResponse>, BookingError> {
     tracing::info!("Received call to end booking for {:?}", agg_id);
-    match booking::end_booking(agg_id).await {
+
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+    let agg = agg_id.get(&mut transaction).await.log_error(
+        StatusCode::NOT_FOUND,
+        "No booking with that id",
+        true,
+    )?;
+    check_capability(
+        &mut transaction,
+        &headers,
+        &agg,
+        BookingCapability::EndBooking,
+    )
+    .await?;
+    transaction.commit().await.log_db_client_error()?;
+
+    Ok(match booking::end_booking(agg_id).await {
         Ok(_) => Json(EndBookingResponse {
             success: true,
             details: format!("Successfully ended booking with agg_id {:?}", agg_id),
@@ -71,7 +549,196 @@ async fn end_booking(Path(agg_id): Path<FKey<Aggregate>>) -> Json<EndBookingResp
             success: false,
             details: format!("{}", error.to_string()),
         }),
+    })
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+struct ReleaseInstanceQuery {
+    #[serde(default)]
+    released_by: Option<String>,
+}
+
+/// Tears down one host of a running booking and returns it to the free pool, keeping the
+/// rest of the booking up
+async fn release_instance(
+    headers: HeaderMap,
+    Path((agg_id, instance_id)): Path<(FKey<Aggregate>, FKey<Instance>)>,
+    Query(query): Query<ReleaseInstanceQuery>,
+) -> Result<(), BookingError> {
+    tracing::info!("API call to release_instance() for {instance_id:?} of {agg_id:?}");
+
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+    let agg = agg_id.get(&mut transaction).await.log_error(
+        StatusCode::NOT_FOUND,
+        "No booking with that id",
+        true,
+    )?;
+    check_capability(
+        &mut transaction,
+        &headers,
+        &agg,
+        BookingCapability::EndBooking,
+    )
+    .await?;
+    if agg.deleted || agg.state != models::dashboard::LifeCycleState::Active {
+        return Err(BookingError::Conflict(
+            "Only bookings that are up and running can have hosts released".to_owned(),
+        ));
+    }
+    check_not_busy(&mut transaction, agg_id, LeaseOperation::Teardown).await?;
+
+    let instances = agg
+        .instances(&mut transaction)
+        .await
+        .log_db_client_error()?;
+    if !instances.iter().any(|i| i.id == instance_id) {
+        return Err(BookingError::NotFound(format!(
+            "Instance {instance_id:?} is not part of booking {agg_id:?}"
+        )));
+    }
+    if instances.len() == 1 {
+        return Err(BookingError::Conflict(
+            "This is the last host of the booking, end the booking instead".to_owned(),
+        ));
     }
+
+    transaction.commit().await.log_db_client_error()?;
+
+    DISPATCH
+        .get()
+        .ok_or(BookingError::Internal("Tascii was not found.".to_owned()))?
+        .send(workflows::entry::Action::ReleaseInstance {
+            agg_id,
+            instance: instance_id,
+            released_by: query.released_by,
+        })
+        .map_err(|_| BookingError::Internal("Unable to start releasing the host".to_owned()))?;
+
+    Ok(())
+}
+
+/// The longest display name a booking can be given
+const MAX_DISPLAY_NAME_LEN: usize = 64;
+
+/// Trims a display name, where an empty one means the booking has none
+fn display_name(name: &str) -> Result<Option<String>, BookingError> {
+    let name = name.trim();
+    if name.chars().count() > MAX_DISPLAY_NAME_LEN {
+        return Err(BookingError::BadRequest(format!(
+            "Display name can be at most {MAX_DISPLAY_NAME_LEN} characters"
+        )));
+    }
+
+    Ok((!name.is_empty()).then(|| name.to_owned()))
+}
+
+/// Fields of a booking its users can change, any not given are left as they are
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct BookingUpdate {
+    /// An empty name clears it, only the owner and admins can rename a booking
+    pub display_name: Option<String>,
+    /// Whether the booking is a favorite of the user making the request
+    pub favorite: Option<bool>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct BookingLabels {
+    pub display_name: Option<String>,
+    pub favorite: bool,
+}
+
+#[axum::debug_handler]
+async fn update_booking(
+    by: User,
+    Path(agg_id): Path<FKey<Aggregate>>,
+    Json(update): Json<BookingUpdate>,
+) -> Result<Json<BookingLabels>, BookingError> {
+    tracing::info!(
+        "API call to update_booking() for {agg_id:?} by {} with {update:?}",
+        by.name
+    );
+
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+    let mut agg = agg_id.get(&mut transaction).await.log_error(
+        StatusCode::NOT_FOUND,
+        "No booking with that id",
+        true,
+    )?;
+
+    let owner = agg.metadata.owner.as_deref() == Some(by.name.as_str());
+    if !owner && !agg.users.contains(&by.name) && !by.admin {
+        return Err(BookingError::Forbidden(
+            "Only those on the booking and admins can change it".to_owned(),
+        ));
+    }
+
+    if let Some(name) = update.display_name {
+        if !owner && !by.admin {
+            return Err(BookingError::Forbidden(
+                "Only the owner of the booking and admins can rename it".to_owned(),
+            ));
+        }
+
+        agg.metadata.display_name = display_name(&name)?;
+    }
+
+    match update.favorite {
+        Some(true) if !agg.metadata.favorited_by.contains(&by.name) => {
+            agg.metadata.favorited_by.push(by.name.clone())
+        }
+        Some(false) => agg.metadata.favorited_by.retain(|u| *u != by.name),
+        _ => (),
+    }
+
+    agg.update(&mut transaction).await.log_db_client_error()?;
+    AggregateSummary::refresh(&mut transaction, agg.id)
+        .await
+        .log_db_client_error()?;
+    transaction.commit().await.log_db_client_error()?;
+
+    Ok(Json(BookingLabels {
+        display_name: agg.metadata.display_name.clone(),
+        favorite: agg.metadata.favorited_by.contains(&by.name),
+    }))
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ExpiryExemption {
+    /// Whether the booking is left running once it is past its end
+    pub exempt: bool,
+}
+
+/// Lets admins keep a booking from being ended automatically after its end
+#[axum::debug_handler]
+async fn set_expiry_exemption(
+    _admin: Admin,
+    Path(agg_id): Path<FKey<Aggregate>>,
+    Json(exemption): Json<ExpiryExemption>,
+) -> Result<Json<ExpiryExemption>, BookingError> {
+    tracing::info!("API call to set_expiry_exemption() for {agg_id:?} with {exemption:?}");
+
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+    let mut agg = agg_id.get(&mut transaction).await.log_error(
+        StatusCode::NOT_FOUND,
+        "No booking with that id",
+        true,
+    )?;
+
+    agg.metadata.exempt_from_expiry = exemption.exempt;
+
+    agg.update(&mut transaction).await.log_db_client_error()?;
+    AggregateSummary::refresh(&mut transaction, agg.id)
+        .await
+        .log_db_client_error()?;
+    transaction.commit().await.log_db_client_error()?;
+
+    Ok(Json(exemption))
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
@@ -81,6 +748,11 @@ pub struct AssignedHostInfo {
     serial: String,
     brand: String,
     model: String,
+    failure_domain: FailureDomain,
+    /// Whether calls to the host's BMC have been failing
+    bmc_breaker: BreakerStatus,
+    /// Problems admins know the host has
+    known_issues: Vec<HostIssueBlob>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
@@ -89,6 +761,11 @@ pub struct InstanceStatus {
     logs: Vec<InstanceStatusUpdate>,
     assigned_host_info: Option<AssignedHostInfo>,
     host_alias: String,
+    sla: SlaSummary,
+    /// What the host's nics reported once it was deployed
+    nics: Option<NicInventory>,
+    /// The devices attached to the host from a composable fabric, if its flavor is composed
+    composition: Option<Composition>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
@@ -108,12 +785,59 @@ pub struct InstanceStatusUpdate {
     pub status: String,
 }
 
+impl From<&ProvisionLogEvent> for InstanceStatusUpdate {
+    #[allow(deprecated)] // deprecated on front end, but we need to keep back-compat
+    fn from(log: &ProvisionLogEvent) -> Self {
+        Self {
+            sentiment: log.sentiment,
+
+            status: log.prov_status.to_string(),
+            status_info: StatusInfo {
+                headline: log.prov_status.event.clone(),
+                subline: log.prov_status.details.clone(),
+            },
+            time: log.time.to_rfc2822(),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 struct BookingStatus {
+    /// Where the booking is in its lifecycle
+    state: LifeCycleState,
+    display_name: Option<String>,
+    /// Whether the user asking has favorited the booking
+    favorite: bool,
     // map from <assigned hostname> to <list of status objects>
     instances: HashMap<FKey<Instance>, InstanceStatus>,
     config: AggregateConfiguration,
     template: Template,
+    isolation: Option<IsolationStatus>,
+    /// Availability of the booking's hosts while it has been active, across all instances
+    sla: SlaSummary,
+    /// Mail to the owner is bouncing, so they won't see expiry notices
+    owner_unreachable: bool,
+    /// Users on the booking whose mail is bouncing
+    unreachable_contacts: Vec<UnreachableContact>,
+    /// What happened to the booking as a whole, oldest first
+    events: Vec<BookingEventBlob>,
+    /// Every state the booking has moved through, oldest first
+    timeline: Vec<StateTransitionBlob>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+struct UnreachableContact {
+    username: String,
+    email: String,
+    last_error: Option<String>,
+}
+
+/// The outcome of the most recent network isolation audit of the booking
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+struct IsolationStatus {
+    passed: bool,
+    time: String,
+    findings: Vec<IsolationFinding>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
@@ -121,11 +845,288 @@ struct ReimageBlob {
     image_id: FKey<Image>,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+struct RollingReimageBlob {
+    /// The image to move every instance to, keeps each on its current image if not given
+    image_id: Option<FKey<Image>>,
+    /// Instances to reimage, in order, every instance of the booking if not given
+    instances: Option<Vec<FKey<Instance>>>,
+    /// How many instances to reimage at once
+    batch_size: usize,
+}
+
+/// Reimages the booking's instances a batch at a time, stopping at the first failure
+#[axum::debug_handler]
+async fn rolling_reimage(
+    headers: HeaderMap,
+    Path(agg_id): Path<FKey<Aggregate>>,
+    Json(request): Json<RollingReimageBlob>,
+) -> Result<(), BookingError> {
+    tracing::info!("API call to rolling_reimage() for {agg_id:?} with {request:?}");
+
+    if request.batch_size == 0 {
+        return Err(BookingError::BadRequest(
+            "Batch size must be at least 1".to_owned(),
+        ));
+    }
+
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+    let agg = agg_id.get(&mut transaction).await.log_error(
+        StatusCode::NOT_FOUND,
+        "No booking with that id",
+        true,
+    )?;
+    if agg.state != models::dashboard::LifeCycleState::Active {
+        return Err(BookingError::BadRequest(
+            "Only active bookings can be reimaged".to_owned(),
+        ));
+    }
+    check_capability(&mut transaction, &headers, &agg, BookingCapability::Reimage).await?;
+    check_not_busy(&mut transaction, agg_id, LeaseOperation::RollingReimage).await?;
+
+    let members = agg
+        .instances(&mut transaction)
+        .await
+        .log_db_client_error()?;
+
+    // naming an instance twice would only reimage it twice
+    let instances = match request.instances {
+        Some(instances) => instances.into_iter().unique().collect_vec(),
+        None => members.iter().map(|i| i.id).collect(),
+    };
+
+    let mut hosts = Vec::new();
+    for instance in instances.iter() {
+        match members.iter().find(|m| m.id == *instance) {
+            Some(m) if m.linked_host.is_some() => hosts.extend(m.linked_host),
+            Some(_) => {
+                return Err(BookingError::BadRequest(format!(
+                    "Instance {instance:?} has no host to reimage"
+                )))
+            }
+            None => {
+                return Err(BookingError::BadRequest(format!(
+                    "Instance {instance:?} is not part of booking {agg_id:?}"
+                )))
+            }
+        }
+    }
+
+    if let Some(image_id) = request.image_id {
+        let image = image_id.get(&mut transaction).await.log_error(
+            StatusCode::NOT_FOUND,
+            "No image with that id",
+            true,
+        )?;
+        if image.deleted || image.sunset_passed(chrono::Utc::now()) {
+            return Err(BookingError::ImageIncompatible(format!(
+                "{} can't be installed anymore",
+                image.name
+            )));
+        }
+
+        for host in hosts {
+            let host = host.get(&mut transaction).await.log_db_client_error()?;
+            if !image.flavors.contains(&host.flavor) {
+                return Err(BookingError::ImageIncompatible(format!(
+                    "{} can't be installed on {}",
+                    image.name, host.server_name
+                )));
+            }
+        }
+    }
+    transaction.commit().await.log_db_client_error()?;
+
+    if instances.len().div_ceil(request.batch_size) > MAX_BATCHES {
+        return Err(BookingError::BadRequest(format!(
+            "Batches must be large enough to reimage in at most {MAX_BATCHES} batches"
+        )));
+    }
+
+    DISPATCH
+        .get()
+        .ok_or(BookingError::Internal("Tascii was not found.".to_owned()))?
+        .send(workflows::entry::Action::RollingReimage {
+            agg_id,
+            instances,
+            image: request.image_id,
+            batch_size: request.batch_size,
+        })
+        .map_err(|_| BookingError::Internal("Unable to start rolling reimage".to_owned()))?;
+
+    Ok(())
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+struct BulkReimageBlob {
+    /// Instances to reimage, from any number of active bookings
+    instances: Vec<FKey<Instance>>,
+    /// The image to move every instance to, keeps each on its current image if not given
+    image_id: Option<FKey<Image>>,
+    /// How many instances to reimage at once
+    batch_size: usize,
+}
+
+/// Reimages instances across bookings as a job, carrying on past hosts that fail
+#[axum::debug_handler]
+async fn bulk_reimage(
+    admin: Admin,
+    Json(request): Json<BulkReimageBlob>,
+) -> Result<(StatusCode, Json<JobStarted>), BookingError> {
+    tracing::info!("API call to bulk_reimage() with {request:?}");
+
+    if request.batch_size == 0 {
+        return Err(BookingError::BadRequest(
+            "Batch size must be at least 1".to_owned(),
+        ));
+    }
+    if request.instances.is_empty() {
+        return Err(BookingError::BadRequest("No instances given".to_owned()));
+    }
+
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+    for instance in request.instances.iter() {
+        let inst = instance
+            .get(&mut transaction)
+            .await
+            .map_err(|_| BookingError::NotFound(format!("No instance has id {instance:?}")))?;
+        if inst.linked_host.is_none() {
+            return Err(BookingError::BadRequest(format!(
+                "Instance {instance:?} has no host to reimage"
+            )));
+        }
+
+        let agg = inst
+            .aggregate
+            .get(&mut transaction)
+            .await
+            .log_db_client_error()?;
+        if agg.state != models::dashboard::LifeCycleState::Active {
+            return Err(BookingError::BadRequest(format!(
+                "Instance {instance:?} isn't part of an active booking"
+            )));
+        }
+        check_not_busy(&mut transaction, agg.id, LeaseOperation::Reimage).await?;
+    }
+
+    transaction.commit().await.log_db_client_error()?;
+
+    let job = jobs::start(
+        "bulk_reimage",
+        &admin.name,
+        request.instances.len(),
+        move |job| {
+            rolling_reimage::bulk_reimage(
+                job,
+                request.instances,
+                request.image_id,
+                request.batch_size,
+            )
+        },
+    )
+    .await
+    .log_server_error("Unable to start the bulk reimage", true)?;
+
+    Ok(accepted(job))
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+struct BulkEndBlob {
+    /// Bookings to end
+    #[serde(default)]
+    aggregates: Vec<FKey<Aggregate>>,
+    /// Also ends every active booking that was to end before then (RFC 3339)
+    #[schemars(with = "Option<String>")]
+    #[serde(default)]
+    expired_before: Option<chrono::DateTime<chrono::Utc>>,
+    /// Only bookings of this project are picked by `expired_before`
+    #[serde(default)]
+    project: Option<String>,
+    /// How many bookings to tear down at once, 4 if not given
+    #[serde(default)]
+    concurrency: Option<usize>,
+}
+
+/// Ends many bookings as a job, carrying on past bookings that fail to end
+#[axum::debug_handler]
+async fn bulk_end_bookings(
+    admin: Admin,
+    Json(request): Json<BulkEndBlob>,
+) -> Result<(StatusCode, Json<JobStarted>), BookingError> {
+    tracing::info!("API call to bulk_end_bookings() with {request:?}");
+
+    let concurrency = request.concurrency.unwrap_or(4);
+    if !(1..=cleanup_booking::bulk::MAX_CONCURRENCY).contains(&concurrency) {
+        return Err(BookingError::BadRequest(format!(
+            "Concurrency must be between 1 and {}",
+            cleanup_booking::bulk::MAX_CONCURRENCY
+        )));
+    }
+
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+    for agg in request.aggregates.iter() {
+        agg.get(&mut transaction)
+            .await
+            .map_err(|_| BookingError::NotFound(format!("No booking has id {agg:?}")))?;
+    }
+
+    let mut aggregates = request.aggregates;
+    if let Some(before) = request.expired_before {
+        let filter = dashboard::AggregateFilter {
+            project: request.project,
+            state: Some(dashboard::LifeCycleState::Active),
+            ..Default::default()
+        };
+
+        let mut after = None;
+        loop {
+            let page = Aggregate::page(&mut transaction, &filter, after, 500)
+                .await
+                .log_db_client_error()?;
+            let Some(last) = page.last() else {
+                break;
+            };
+            after = Some(dashboard::AggregateCursor {
+                start: last.metadata.start,
+                id: last.id,
+            });
+
+            aggregates.extend(
+                page.iter()
+                    .filter(|agg| agg.metadata.end.is_some_and(|end| end < before))
+                    .map(|agg| agg.id),
+            );
+        }
+    }
+
+    transaction.commit().await.log_db_client_error()?;
+
+    let aggregates = aggregates.into_iter().unique().collect_vec();
+    if aggregates.is_empty() {
+        return Err(BookingError::BadRequest("No bookings to end".to_owned()));
+    }
+
+    let job = jobs::start("bulk_end", &admin.name, aggregates.len(), move |job| {
+        cleanup_booking::bulk::bulk_end(job, aggregates, concurrency)
+    })
+    .await
+    .log_server_error("Unable to start ending the bookings", true)?;
+
+    Ok(accepted(job))
+}
+
 #[axum::debug_handler]
 async fn reimage_host(
+    headers: HeaderMap,
     Path(instance_id): Path<Uuid>,
     Json(request): Json<ReimageBlob>,
-) -> Result<(), WebError> {
+) -> Result<(), BookingError> {
     tracing::info!("API call to reimage_host()");
     let image_id = request.image_id;
     let mut client = new_client().await.log_db_client_error()?;
@@ -134,37 +1135,33 @@ async fn reimage_host(
 
     let mut inst = Instance::get(&mut transaction, instance_id.into())
         .await
-        .map_err(|_| {
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                format!("Error accessing image from database."),
-            )
-        })?;
+        .map_err(|_| BookingError::Internal(format!("Error accessing image from database.")))?;
+    let agg = inst
+        .aggregate
+        .get(&mut transaction)
+        .await
+        .log_db_client_error()?;
+    check_capability(&mut transaction, &headers, &agg, BookingCapability::Reimage).await?;
+    check_not_busy(&mut transaction, inst.aggregate, LeaseOperation::Reimage).await?;
     inst.config.image = image_id;
-    inst.update(&mut transaction).await.map_err(|_| {
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            format!("Error updating instance image."),
-        )
-    })?;
-    transaction.commit().await.map_err(|_| {
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            format!("Error committing instance changes."),
-        )
-    })?;
+    inst.update(&mut transaction)
+        .await
+        .map_err(|_| BookingError::Internal(format!("Error updating instance image.")))?;
+    excuse_instance(&mut transaction, inst.id, REIMAGE_GRACE)
+        .await
+        .log_db_client_error()?;
+    transaction
+        .commit()
+        .await
+        .map_err(|_| BookingError::Internal(format!("Error committing instance changes.")))?;
 
     let res = DISPATCH
         .get()
-        .ok_or((
-            StatusCode::INTERNAL_SERVER_ERROR,
-            format!("Tascii was not found."),
-        ))?
+        .ok_or(BookingError::Internal(format!("Tascii was not found.")))?
         .send(workflows::entry::Action::Reimage {
-            host_id: inst.linked_host.ok_or((
-                StatusCode::INTERNAL_SERVER_ERROR,
-                format!("No linked host was found for instance."),
-            ))?,
+            host_id: inst.linked_host.ok_or(BookingError::Internal(format!(
+                "No linked host was found for instance."
+            )))?,
             inst_id: FKey::from_id(instance_id.into()),
             agg_id: inst.aggregate,
         });
@@ -189,7 +1186,10 @@ pub struct ExtensionRequest {
     pub reason: String,
 }
 
-async fn booking_status(Path(agg_id): Path<Uuid>) -> Result<Json<BookingStatus>, WebError> {
+async fn booking_status(
+    headers: HeaderMap,
+    Path(agg_id): Path<Uuid>,
+) -> Result<Json<BookingStatus>, BookingError> {
     tracing::debug!("API call to booking_status()");
     let mut client = new_client().await.log_db_client_error()?;
     let mut transaction = client.easy_transaction().await.log_db_client_error()?;
@@ -205,6 +1205,7 @@ async fn booking_status(Path(agg_id): Path<Uuid>) -> Result<Json<BookingStatus>,
         )?;
 
     let mut statuses = HashMap::new();
+    let mut all_checks = Vec::new();
 
     for instance in &agg
         .instances(&mut transaction)
@@ -228,13 +1229,22 @@ async fn booking_status(Path(agg_id): Path<Uuid>) -> Result<Json<BookingStatus>,
                 .into_inner();
 
             let flavor = host.flavor.get(&mut transaction).await.unwrap();
+            let known_issues = HostIssue::active_for_host(&mut transaction, host.id)
+                .await
+                .log_db_client_error()?
+                .iter()
+                .map(|i| (&**i).into())
+                .collect();
 
             let host_info = AssignedHostInfo {
                 hostname: host.server_name.clone(),
+                bmc_breaker: resilience::status(&Device::bmc(&host.ipmi_fqdn)),
                 ipmi_fqdn: host.ipmi_fqdn,
                 serial: host.serial.clone(),
                 brand: flavor.brand.clone(),
                 model: flavor.model.clone(),
+                failure_domain: host.failure_domain.clone(),
+                known_issues,
             };
 
             (Some(host.server_name), Some(host_info))
@@ -242,27 +1252,26 @@ async fn booking_status(Path(agg_id): Path<Uuid>) -> Result<Json<BookingStatus>,
             (None, None)
         };
 
-        #[allow(deprecated)] // deprecated on front end, but we need to keep back-compat
         let logs = logs_for_instance
-            .into_iter()
-            .map(|log| InstanceStatusUpdate {
-                sentiment: log.sentiment,
-
-                status: log.prov_status.to_string(),
-                status_info: StatusInfo {
-                    headline: log.prov_status.event.clone(),
-                    subline: log.prov_status.details.clone(),
-                },
-                time: log.time.to_rfc2822(),
-            })
+            .iter()
+            .map(|log| InstanceStatusUpdate::from(&**log))
             .collect_vec();
 
+        let checks = InstanceHealthCheck::all_for_instance(&mut transaction, instance.id)
+            .await
+            .log_db_client_error()?;
+        let sla = health::summarize(checks.iter().map(|c| &**c));
+        all_checks.extend(checks.into_iter().map(|c| c.into_inner()));
+
         #[allow(deprecated)] // deprecated on front end, but we need to keep back-compat
         let inst_stat = InstanceStatus {
             instance: instance.id,
             assigned_host_info,
             host_alias: inst_hn,
             logs,
+            sla,
+            nics: nic_inventory::recorded(instance),
+            composition: composability::recorded(instance),
         };
 
         statuses.insert(instance.id, inst_stat);
@@ -276,12 +1285,66 @@ async fn booking_status(Path(agg_id): Path<Uuid>) -> Result<Json<BookingStatus>,
         .into_inner()
         .clone();
 
+    let isolation = IsolationAttestation::latest_for_aggregate(&mut transaction, agg.id)
+        .await
+        .log_server_error("Failed to retrieve isolation attestation", true)?
+        .map(|a| IsolationStatus {
+            passed: a.passed,
+            time: a.time.to_rfc2822(),
+            findings: a.findings.clone(),
+        });
+
+    let usernames = agg
+        .metadata
+        .owner
+        .iter()
+        .chain(agg.users.iter())
+        .unique()
+        .cloned()
+        .collect_vec();
+    let unreachable_contacts = contacts::unreachable(&mut transaction, &usernames)
+        .await
+        .log_db_client_error()?
+        .into_iter()
+        .map(|c| UnreachableContact {
+            username: c.username,
+            email: c.email,
+            last_error: c.last_error,
+        })
+        .collect_vec();
+    let owner_unreachable = unreachable_contacts
+        .iter()
+        .any(|c| Some(&c.username) == agg.metadata.owner.as_ref());
+
+    let events = BookingEvent::for_aggregate(&mut transaction, agg.id)
+        .await
+        .log_db_client_error()?
+        .iter()
+        .map(|e| (&**e).into())
+        .collect_vec();
+    let timeline = AggregateTransition::for_aggregate(&mut transaction, agg.id)
+        .await
+        .log_db_client_error()?
+        .iter()
+        .map(|t| (&**t).into())
+        .collect_vec();
+
     transaction.commit().await.log_db_client_error()?;
 
     Ok(Json(BookingStatus {
+        state: agg.state,
+        display_name: agg.metadata.display_name.clone(),
+        favorite: requesting_user(&headers)
+            .is_some_and(|u| agg.metadata.favorited_by.iter().any(|f| f == u)),
         instances: statuses,
         config: agg.configuration.clone(),
         template,
+        isolation,
+        sla: health::summarize(&all_checks),
+        owner_unreachable,
+        unreachable_contacts,
+        events,
+        timeline,
     }))
 }
 
@@ -289,40 +1352,50 @@ async fn booking_status(Path(agg_id): Path<Uuid>) -> Result<Json<BookingStatus>,
 async fn notify_aggregate_expiring(
     Path(agg_id): Path<Uuid>,
     Json(date_string): Json<String>,
-) -> Result<(), WebError> {
+) -> Result<(), BookingError> {
     tracing::info!(
         "Call to notify_aggregate_expiring() for {agg_id} with date_string {date_string}"
     );
 
     let agg_id: FKey<Aggregate> = FKey::from_id(agg_id.into());
 
-    let dispatch = DISPATCH.get().ok_or((
-        StatusCode::INTERNAL_SERVER_ERROR,
-        format!("Unable to get dispatcher"),
-    ))?;
+    let ending = match date_string.trim() {
+        "" => None,
+        date => Some(
+            chrono::DateTime::parse_from_rfc2822(date)
+                .map_err(|e| {
+                    BookingError::BadRequest(format!(
+                        "Ending date {date:?} isn't an RFC 2822 date: {e}"
+                    ))
+                })?
+                .with_timezone(&chrono::Utc),
+        ),
+    };
+    let action = workflows::entry::Action::notify(
+        agg_id,
+        Situation::BookingExpiring,
+        NotifyContext::Expiring { ending },
+    )
+    .map_err(|e| BookingError::BadRequest(e.to_string()))?;
+
+    let dispatch = DISPATCH
+        .get()
+        .ok_or(BookingError::Internal(format!("Unable to get dispatcher")))?;
 
     dispatch
-        .send(workflows::entry::Action::NotifyTask {
-            agg_id,
-            situation: Situation::BookingExpiring,
-            context: vec![(String::from("ending_override"), date_string)],
-        })
-        .map_err(|_| {
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                format!("Unable to execute notify task!"),
-            )
-        })?;
+        .send(action)
+        .map_err(|_| BookingError::Internal(format!("Unable to execute notify task!")))?;
 
     Ok(())
 }
 
 #[axum::debug_handler]
-/// Sends an email to admins with the details of a booking extension request
+/// Records a booking extension request and sends an email to admins with its details
 async fn request_booking_extension(
+    by: User,
     Path(agg_id): Path<Uuid>,
     Json(details): Json<ExtensionRequest>,
-) -> Result<(), WebError> {
+) -> Result<(), BookingError> {
     tracing::info!(
         "Call to request_booking_extension() for {agg_id} with details {} {}",
         details.reason,
@@ -331,26 +1404,69 @@ async fn request_booking_extension(
 
     let agg_id: FKey<Aggregate> = FKey::from_id(agg_id.into());
 
-    let dispatch = DISPATCH.get().ok_or((
-        StatusCode::INTERNAL_SERVER_ERROR,
-        format!("Unable to get dispatcher"),
-    ))?;
+    let action = workflows::entry::Action::notify(
+        agg_id,
+        Situation::RequestBookingExtension,
+        NotifyContext::ExtensionRequest {
+            date: details.date.clone(),
+            reason: details.reason.clone(),
+        },
+    )
+    .map_err(|e| BookingError::BadRequest(e.to_string()))?;
+
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+    let agg = agg_id.get(&mut transaction).await.log_error(
+        StatusCode::NOT_FOUND,
+        "No booking with that id",
+        true,
+    )?;
+    if agg.metadata.owner.as_deref() != Some(by.name.as_str())
+        && !agg.users.contains(&by.name)
+        && !by.admin
+    {
+        return Err(BookingError::Forbidden(
+            "Only those on the booking and admins can ask for it to be extended".to_owned(),
+        ));
+    }
+
+    let pending = dashboard::ExtensionRequest::all_for_aggregate(&mut transaction, agg_id)
+        .await
+        .log_db_client_error()?
+        .iter()
+        .any(|r| r.is_pending());
+    if pending {
+        return Err(BookingError::Conflict(
+            "An extension of this booking is already waiting on review".to_owned(),
+        ));
+    }
+
+    NewRow::new(dashboard::ExtensionRequest {
+        id: FKey::new_id_dangling(),
+        aggregate: agg_id,
+        date: details.date.clone(),
+        reason: details.reason.clone(),
+        requested: chrono::Utc::now(),
+        requested_by: Some(by.name),
+        status: dashboard::ExtensionStatus::Pending,
+        decided_by: None,
+        decided: None,
+        note: None,
+        granted_end: None,
+    })
+    .insert(&mut transaction)
+    .await
+    .log_db_client_error()?;
+    transaction.commit().await.log_db_client_error()?;
+
+    let dispatch = DISPATCH
+        .get()
+        .ok_or(BookingError::Internal(format!("Unable to get dispatcher")))?;
 
     dispatch
-        .send(workflows::entry::Action::NotifyTask {
-            agg_id,
-            situation: Situation::RequestBookingExtension,
-            context: vec![
-                (String::from("extension_date"), details.date),
-                (String::from("extension_reason"), details.reason),
-            ],
-        })
-        .map_err(|_| {
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                format!("Unable to execute notify task!"),
-            )
-        })?;
+        .send(action)
+        .map_err(|_| BookingError::Internal(format!("Unable to execute notify task!")))?;
 
     Ok(())
 }
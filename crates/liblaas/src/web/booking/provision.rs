@@ -0,0 +1,223 @@
+//! Stepping in on the deploy of a single instance, canceling it while it is underway or
+//! trying it again once it failed
+
+use axum::{
+    extract::{Json, Path},
+    http::{HeaderMap, StatusCode},
+};
+use common::prelude::{chrono::Utc, *};
+use dal::{new_client, web::*, AsEasyTransaction, FKey};
+use models::{
+    dashboard::{
+        instance::PROVISION_CANCELED, BookingCapability, HostReplacement, Instance, LeaseOperation,
+        ProvisionLogEvent, ReplacementState, StatusSentiment,
+    },
+    inventory::{self, Host},
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use workflows::{
+    entry::{Action, DISPATCH},
+    resource_management::health::{excuse_instance, REIMAGE_GRACE},
+};
+
+use super::{check_not_busy, collaborators::check_capability, replace, BookingError};
+use crate::web::identity::requesting_user;
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct RetryProvisionBlob {
+    /// Deploy onto another host of the same flavor, for when the host the deploy failed
+    /// on is suspected to be bad
+    #[serde(default)]
+    different_host: bool,
+    /// Why the host is suspected to be bad, needed with `different_host`
+    #[serde(default)]
+    reason: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct RetryProvisionResponse {
+    /// The host the deploy is retried on, unless a replacement host is still to be picked
+    host: Option<FKey<Host>>,
+    /// The replacement of the host, when retrying on a different one
+    replacement: Option<FKey<HostReplacement>>,
+    /// True if the replacement has to be approved by an admin before the deploy is retried
+    awaiting_approval: bool,
+}
+
+/// Cancels the deploy the host of the instance is going through, leaving the host
+/// powered off and the instance failed until it is reimaged
+#[axum::debug_handler]
+pub async fn cancel_provision(
+    headers: HeaderMap,
+    Path(instance_id): Path<Uuid>,
+) -> Result<(), BookingError> {
+    tracing::info!("API call to cancel_provision() for {instance_id}");
+
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+    let instance_id: FKey<Instance> = FKey::from_id(instance_id.into());
+    let mut instance = instance_id.get(&mut transaction).await.log_error(
+        StatusCode::NOT_FOUND,
+        "Instance does not exist",
+        true,
+    )?;
+    let agg = instance
+        .aggregate
+        .get(&mut transaction)
+        .await
+        .log_db_client_error()?;
+    check_capability(&mut transaction, &headers, &agg, BookingCapability::Reimage).await?;
+
+    let host_id = instance.linked_host.ok_or(BookingError::Conflict(
+        "Instance has no host being deployed".to_owned(),
+    ))?;
+
+    let tasks = inventory::Action::get_all_incomplete_for_host(&mut transaction, host_id)
+        .await
+        .log_db_client_error()?
+        .iter()
+        .map(|a| a.in_tascii())
+        .collect::<Vec<_>>();
+    if tasks.is_empty() {
+        return Err(BookingError::Conflict(
+            "The host of this instance isn't being deployed".to_owned(),
+        ));
+    }
+
+    // the deploy stops at its next checkpoint once it sees this
+    instance.metadata.insert(
+        PROVISION_CANCELED.to_owned(),
+        serde_json::Value::String(Utc::now().to_rfc2822()),
+    );
+    instance
+        .update(&mut transaction)
+        .await
+        .log_server_error("unable to update the instance", true)?;
+    for task in &tasks {
+        inventory::Action::complete(&mut transaction, *task)
+            .await
+            .log_db_client_error()?;
+    }
+
+    // the cancel is only kept once the deploy was told to stop, otherwise the instance
+    // would be marked canceled while its deploy carries on
+    DISPATCH
+        .get()
+        .ok_or(BookingError::Internal(
+            "Unable to get dispatcher".to_owned(),
+        ))?
+        .send(Action::CancelProvision {
+            host_id,
+            inst_id: instance_id,
+            agg_id: instance.aggregate,
+            tasks,
+            canceled_by: requesting_user(&headers).map(str::to_owned),
+        })
+        .map_err(|_| BookingError::Internal("Unable to cancel the deploy".to_owned()))?;
+
+    transaction.commit().await.log_db_client_error()?;
+
+    Ok(())
+}
+
+/// Deploys an instance whose last deploy failed again, on the same host or on a
+/// replacement for it
+#[axum::debug_handler]
+pub async fn retry_provision(
+    headers: HeaderMap,
+    Path(instance_id): Path<Uuid>,
+    Json(request): Json<RetryProvisionBlob>,
+) -> Result<Json<RetryProvisionResponse>, BookingError> {
+    tracing::info!("API call to retry_provision() for {instance_id} with {request:?}");
+
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+    let instance_id: FKey<Instance> = FKey::from_id(instance_id.into());
+    let instance = instance_id.get(&mut transaction).await.log_error(
+        StatusCode::NOT_FOUND,
+        "Instance does not exist",
+        true,
+    )?;
+    let agg = instance
+        .aggregate
+        .get(&mut transaction)
+        .await
+        .log_db_client_error()?;
+    check_capability(&mut transaction, &headers, &agg, BookingCapability::Reimage).await?;
+    if agg.deleted || agg.state.ended() {
+        return Err(BookingError::Conflict(
+            "Hosts of a booking that has ended can't be deployed again".to_owned(),
+        ));
+    }
+
+    let last = ProvisionLogEvent::latest_for_instance(&mut transaction, instance_id)
+        .await
+        .log_db_client_error()?;
+    if !last.is_some_and(|e| matches!(e.sentiment, StatusSentiment::Failed)) {
+        return Err(BookingError::Conflict(
+            "Only an instance whose last deploy failed can have it retried".to_owned(),
+        ));
+    }
+
+    let host_id = instance.linked_host.ok_or(BookingError::Conflict(
+        "Instance has no host to deploy".to_owned(),
+    ))?;
+
+    if request.different_host {
+        let Some(reason) = request.reason.filter(|r| !r.trim().is_empty()) else {
+            return Err(BookingError::BadRequest(
+                "Say why the host is suspected to be bad to retry on a different one".to_owned(),
+            ));
+        };
+        let requested_by = requesting_user(&headers).unwrap_or("unknown").to_owned();
+
+        let replacement = replace::start_replacement(
+            transaction,
+            &instance,
+            requested_by,
+            format!("its deploy failed, {reason}"),
+        )
+        .await?;
+
+        return Ok(Json(RetryProvisionResponse {
+            host: replacement.replacement_host,
+            replacement: Some(replacement.id),
+            awaiting_approval: replacement.state == ReplacementState::Requested,
+        }));
+    }
+
+    check_not_busy(
+        &mut transaction,
+        instance.aggregate,
+        LeaseOperation::Reimage,
+    )
+    .await?;
+    excuse_instance(&mut transaction, instance_id, REIMAGE_GRACE)
+        .await
+        .log_db_client_error()?;
+
+    transaction.commit().await.log_db_client_error()?;
+
+    // a reimage deploys the host from the start, without picking up where it failed
+    DISPATCH
+        .get()
+        .ok_or(BookingError::Internal(
+            "Unable to get dispatcher".to_owned(),
+        ))?
+        .send(Action::Reimage {
+            host_id,
+            inst_id: instance_id,
+            agg_id: instance.aggregate,
+        })
+        .map_err(|_| BookingError::Internal("Unable to retry the deploy".to_owned()))?;
+
+    Ok(Json(RetryProvisionResponse {
+        host: Some(host_id),
+        replacement: None,
+        awaiting_approval: false,
+    }))
+}
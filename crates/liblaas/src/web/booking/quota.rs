@@ -0,0 +1,132 @@
+//! Admins lifting the booking quotas of a user or project for a while, like for a plugfest
+//! that needs more of the lab than any project normally gets
+
+use axum::{
+    extract::{Json, Path},
+    http::StatusCode,
+};
+use common::prelude::{
+    chrono::{Duration, Utc},
+    config::settings,
+};
+use dal::{new_client, web::*, AsEasyTransaction, FKey, NewRow};
+use models::dashboard::QuotaOverride;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use super::BookingError;
+use crate::web::identity::Admin;
+
+/// The longest an override can be granted for at once
+const MAX_OVERRIDE_DAYS: i64 = 90;
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct GrantOverride {
+    /// The project of the lab the override is for, as bookings give it for their origin
+    origin: String,
+    /// Exactly one of `username` and `project` is given
+    username: Option<String>,
+    project: Option<String>,
+    /// Limits left out stay as configured
+    max_hosts: Option<i32>,
+    max_length_days: Option<i64>,
+    /// How long the override lasts, at most 90 days
+    days: i64,
+    reason: String,
+}
+
+/// Overrides that haven't expired yet, soonest to expire first
+#[axum::debug_handler]
+pub async fn list_overrides(_admin: Admin) -> Result<Json<Vec<QuotaOverride>>, BookingError> {
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+    let overrides = QuotaOverride::active(&mut transaction, Utc::now())
+        .await
+        .log_db_client_error()?;
+
+    transaction.commit().await.log_db_client_error()?;
+
+    Ok(Json(
+        overrides.into_iter().map(|o| o.into_inner()).collect(),
+    ))
+}
+
+#[axum::debug_handler]
+pub async fn grant_override(
+    admin: Admin,
+    Json(grant): Json<GrantOverride>,
+) -> Result<Json<QuotaOverride>, BookingError> {
+    if grant.username.is_some() == grant.project.is_some() {
+        return Err(BookingError::BadRequest(
+            "An override is for either a username or a project".to_owned(),
+        ));
+    }
+    if !settings().projects.contains_key(&grant.origin) {
+        return Err(BookingError::NotFound(format!(
+            "No project {} is configured",
+            grant.origin
+        )));
+    }
+    if grant.days <= 0 || grant.days > MAX_OVERRIDE_DAYS {
+        return Err(BookingError::BadRequest(format!(
+            "Overrides can be granted for between 1 and {MAX_OVERRIDE_DAYS} days"
+        )));
+    }
+    if grant.max_hosts.is_some_and(|h| h < 0) || grant.max_length_days.is_some_and(|d| d < 0) {
+        return Err(BookingError::BadRequest(
+            "Limits can't be negative".to_owned(),
+        ));
+    }
+
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+    let now = Utc::now();
+    let granted = NewRow::new(QuotaOverride {
+        id: FKey::new_id_dangling(),
+        origin: grant.origin,
+        username: grant.username,
+        project: grant.project,
+        max_hosts: grant.max_hosts,
+        max_length_days: grant.max_length_days,
+        granted_by: admin.name,
+        reason: grant.reason,
+        created: now,
+        expires: now + Duration::days(grant.days),
+    })
+    .insert(&mut transaction)
+    .await
+    .log_db_client_error()?
+    .get(&mut transaction)
+    .await
+    .log_db_client_error()?;
+
+    transaction.commit().await.log_db_client_error()?;
+
+    Ok(Json(granted.into_inner()))
+}
+
+/// Ends the override now, instead of when it expires
+#[axum::debug_handler]
+pub async fn revoke_override(
+    _admin: Admin,
+    Path(override_id): Path<FKey<QuotaOverride>>,
+) -> Result<StatusCode, BookingError> {
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+    let existing = override_id.get(&mut transaction).await.log_error(
+        StatusCode::NOT_FOUND,
+        "No quota override with that id",
+        true,
+    )?;
+    existing
+        .delete(&mut transaction)
+        .await
+        .log_db_client_error()?;
+
+    transaction.commit().await.log_db_client_error()?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,468 @@
+//! Booking again what a booking that has ended was made of
+//!
+//! The new booking is made from the same template, with the users, variables, tags and
+//! length the old one had, and parameters set to what it was booked with as far as its
+//! hosts tell. What was booked is checked against the lab as it is now: hosts of flavors
+//! that have since been retired are asked for by constraints any host at least as capable
+//! meets, and images past their sunset are swapped for their replacement where the
+//! template lets the image be picked. Anything that can't be swapped is suggested instead.
+//!
+//! If not enough hosts are free, the booking waits in the queue for them when the lab has
+//! one, and says when enough of the hosts in use are due back.
+
+use std::collections::HashMap;
+
+use axum::{
+    extract::{Json, Path},
+    http::StatusCode,
+};
+use common::prelude::{
+    chrono::{DateTime, Utc},
+    itertools::Itertools,
+    *,
+};
+use dal::{new_client, web::*, AsEasyTransaction, EasyTransaction, FKey};
+use models::{
+    dashboard::{
+        Aggregate, AggregateTag, HostConfig, HostConstraints, Image, LifeCycleState, ParameterKind,
+        ParameterValue, Template,
+    },
+    inventory::{AcceleratorKind, Flavor},
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use workflows::{deploy_booking::parameters, resource_management::flavors};
+
+use super::{api, check_bookable, create_aggregate, BookingError};
+use crate::{booking, web::identity::User};
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct RebookRequest {
+    /// The length in days of the new booking, the length of the old one if not given
+    #[serde(default)]
+    pub length: Option<u64>,
+    /// Values for the parameters of the template, instead of what the old booking was
+    /// booked with
+    #[serde(default)]
+    pub parameters: Option<HashMap<String, ParameterValue>>,
+    /// Waits in the queue if not enough hosts are free, when the lab has a queue
+    #[serde(default = "default_wait")]
+    pub wait: bool,
+    /// Checks what rebooking would do without booking anything
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+fn default_wait() -> bool {
+    true
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, JsonSchema, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SubstitutionKind {
+    Flavor,
+    Image,
+}
+
+/// Something the old booking had that is no longer offered, and what stands in for it
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Substitution {
+    pub kind: SubstitutionKind,
+    /// The host of the template, or the parameter for images picked by one
+    pub target: String,
+    pub from: String,
+    /// None if nothing can stand in for it
+    pub to: Option<String>,
+    /// False for suggestions the new booking doesn't follow on its own
+    pub applied: bool,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Rebooked {
+    /// The new booking, None for dry runs
+    pub aggregate: Option<FKey<Aggregate>>,
+    /// True if the new booking is waiting in the queue for hosts to free up
+    pub queued: bool,
+    /// For bookings that have to wait, when enough hosts in use are due back to fill
+    /// them, if the bookings holding them end on time
+    #[schemars(with = "Option<String>")]
+    pub earliest_available: Option<DateTime<Utc>>,
+    pub substitutions: Vec<Substitution>,
+    pub plan: api::BookingPlan,
+}
+
+/// The values of the parameters of `template` that the hosts `booked` seem to have been
+/// booked with, leaving out any that can't be told
+fn recall_parameters(
+    template: &Template,
+    booked: &[HostConfig],
+) -> HashMap<String, ParameterValue> {
+    let mut values = HashMap::new();
+
+    for param in template.parameters.iter() {
+        match &param.kind {
+            ParameterKind::HostCount {
+                hostname, min, max, ..
+            } => {
+                let Some(host) = template.hosts.iter().find(|h| &h.hostname == hostname) else {
+                    continue;
+                };
+                // hosts of the same flavor that aren't copies of this one were booked too
+                let others = template
+                    .hosts
+                    .iter()
+                    .filter(|h| h.flavor == host.flavor && &h.hostname != hostname)
+                    .count();
+                let alike = booked.iter().filter(|b| b.flavor == host.flavor).count();
+                let count = (alike.saturating_sub(others) as u32).clamp(*min, *max);
+
+                values.insert(param.name.clone(), ParameterValue::Count(count));
+            }
+            ParameterKind::Image { choices, .. } => {
+                let picked = booked
+                    .iter()
+                    .map(|b| b.image)
+                    .filter(|i| choices.contains(i))
+                    .counts()
+                    .into_iter()
+                    .max_by_key(|(_, n)| *n)
+                    .map(|(i, _)| i);
+                if let Some(image) = picked {
+                    values.insert(param.name.clone(), ParameterValue::Image(image));
+                }
+            }
+            // networks don't say which copy of a template network they were
+            ParameterKind::VlanCount { .. } => {}
+        }
+    }
+
+    values
+}
+
+/// What a host of `flavor` has, as constraints any host at least as capable meets
+async fn constraints_of(
+    t: &mut EasyTransaction<'_>,
+    flavor: &Flavor,
+) -> Result<HostConstraints, anyhow::Error> {
+    Ok(HostConstraints {
+        min_ram_gb: flavor.ram.bytes().map(|b| b / 1_000_000_000),
+        arch: Some(flavor.arch),
+        min_nics: Some(flavor.ports(t).await?.len()),
+        gpu: Some(
+            flavor
+                .capabilities
+                .accelerators
+                .iter()
+                .any(|a| a.kind == AcceleratorKind::Gpu && a.count > 0),
+        ),
+    })
+}
+
+/// Swaps the images picked by parameters that are past their sunset for their replacement,
+/// and suggests replacements for the rest
+async fn substitute_images(
+    t: &mut EasyTransaction<'_>,
+    template: &Template,
+    values: &mut HashMap<String, ParameterValue>,
+    substitutions: &mut Vec<Substitution>,
+) -> Result<(), anyhow::Error> {
+    let now = Utc::now();
+
+    for param in template.parameters.iter() {
+        let ParameterKind::Image {
+            choices, default, ..
+        } = &param.kind
+        else {
+            continue;
+        };
+        let picked = match values.get(&param.name) {
+            Some(ParameterValue::Image(image)) => *image,
+            _ => *default,
+        };
+
+        let image = picked.get(t).await?;
+        if !image.deleted && !image.sunset_passed(now) {
+            continue;
+        }
+
+        let replacement = match image.replacement {
+            Some(r) => Some(r.get(t).await?.into_inner()),
+            None => None,
+        };
+        let applied = replacement
+            .as_ref()
+            .is_some_and(|r| choices.contains(&r.id) && !r.deleted && !r.sunset_passed(now));
+        if applied {
+            values.insert(
+                param.name.clone(),
+                ParameterValue::Image(replacement.as_ref().unwrap().id),
+            );
+        }
+
+        substitutions.push(Substitution {
+            kind: SubstitutionKind::Image,
+            target: param.name.clone(),
+            from: image.name.clone(),
+            to: replacement.as_ref().map(|r| r.name.clone()),
+            applied,
+            reason: match (applied, &replacement) {
+                (true, _) => format!("{} has been retired", image.name),
+                (false, Some(_)) => format!(
+                    "{} has been retired, and the template doesn't offer its replacement",
+                    image.name
+                ),
+                (false, None) => format!(
+                    "{} has been retired without a replacement, pick another image",
+                    image.name
+                ),
+            },
+        });
+    }
+
+    // images the template gives its hosts outright can only be changed in the template
+    for host in template.hosts.iter() {
+        let image: Image = host.image.get(t).await?.into_inner();
+        let Some(deprecated) = image.deprecated.filter(|_| !image.sunset_passed(now)) else {
+            continue;
+        };
+        let to = match image.replacement {
+            Some(r) => Some(r.get(t).await?.name.clone()),
+            None => None,
+        };
+
+        substitutions.push(Substitution {
+            kind: SubstitutionKind::Image,
+            target: host.hostname.clone(),
+            from: image.name.clone(),
+            to,
+            applied: false,
+            reason: format!(
+                "{} has been deprecated since {}, the template should move off it",
+                image.name,
+                deprecated.to_rfc2822()
+            ),
+        });
+    }
+
+    Ok(())
+}
+
+/// Asks for hosts of flavors that have since been retired by what those flavors had, giving
+/// the constraints by host and where each substituted host is among the hosts of the booking
+async fn substitute_flavors(
+    t: &mut EasyTransaction<'_>,
+    template: &Template,
+    values: &HashMap<String, ParameterValue>,
+    substitutions: &mut Vec<Substitution>,
+) -> Result<(HashMap<String, HostConstraints>, Vec<usize>), anyhow::Error> {
+    // copies of networks are made as the template is expanded, which is only looked at here
+    let mut ct = t.easy_transaction().await?;
+    let expanded = parameters::expand(&mut ct, template, values).await?;
+    ct.rollback().await?;
+
+    let mut constraints = HashMap::new();
+    let mut substituted = Vec::new();
+    let mut retired = HashMap::new();
+    for (i, host) in expanded.hosts.iter().enumerate() {
+        if host.constraints.is_some() {
+            continue;
+        }
+        let is_retired = match retired.get(&host.flavor) {
+            Some(r) => *r,
+            None => {
+                let usage = flavors::usage(t, host.flavor).await?;
+                let r = usage.hosts == usage.retired;
+                retired.insert(host.flavor, r);
+                r
+            }
+        };
+        if !is_retired {
+            continue;
+        }
+
+        let flavor = host.flavor.get(t).await?.into_inner();
+        let wanted = constraints_of(t, &flavor).await?;
+        substitutions.push(Substitution {
+            kind: SubstitutionKind::Flavor,
+            target: host.hostname.clone(),
+            from: flavor.name.clone(),
+            to: None,
+            applied: true,
+            reason: format!(
+                "{} has been retired, so any host with {} will do",
+                flavor.name,
+                wanted.describe().join(", ")
+            ),
+        });
+        constraints.insert(host.hostname.clone(), wanted);
+        substituted.push(i);
+    }
+
+    Ok((constraints, substituted))
+}
+
+#[axum::debug_handler]
+pub async fn rebook(
+    by: User,
+    Path(agg_id): Path<FKey<Aggregate>>,
+    Json(request): Json<RebookRequest>,
+) -> Result<Json<Rebooked>, BookingError> {
+    tracing::info!("API call to rebook() of {agg_id:?}");
+
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+    let agg = agg_id.get(&mut transaction).await.log_error(
+        StatusCode::NOT_FOUND,
+        "No booking with that id",
+        true,
+    )?;
+
+    if agg.metadata.owner.as_deref() != Some(by.name.as_str()) && !by.admin {
+        return Err(BookingError::Forbidden(
+            "Only the owner of the booking and admins can book it again".to_owned(),
+        ));
+    }
+    if !agg.state.ended() {
+        return Err(BookingError::Conflict(
+            "The booking hasn't ended yet, extend it instead".to_owned(),
+        ));
+    }
+
+    let template = check_bookable(
+        &mut transaction,
+        agg.template,
+        agg.metadata.owner.as_deref(),
+    )
+    .await?;
+
+    let booked = agg
+        .instances(&mut transaction)
+        .await
+        .log_db_client_error()?
+        .into_iter()
+        .map(|i| i.config.clone())
+        .collect_vec();
+
+    let mut substitutions = Vec::new();
+    let mut values = match request.parameters {
+        Some(values) => values,
+        None => recall_parameters(&template, &booked),
+    };
+    substitute_images(&mut transaction, &template, &mut values, &mut substitutions)
+        .await
+        .log_server_error("unable to check the images of the booking", true)?;
+    parameters::validate(&template.parameters, &values)
+        .map_err(|e| BookingError::BadRequest(e.to_string()))?;
+    let (constraints, substituted) =
+        substitute_flavors(&mut transaction, &template, &values, &mut substitutions)
+            .await
+            .log_server_error("unable to check the flavors of the booking", true)?;
+
+    let origin = agg
+        .lab
+        .get(&mut transaction)
+        .await
+        .log_db_client_error()?
+        .name
+        .clone();
+    let tags = AggregateTag::for_aggregate(&mut transaction, agg_id)
+        .await
+        .log_db_client_error()?;
+
+    transaction.commit().await.log_db_client_error()?;
+
+    // started bookings last until the day they end, so a partial day counts as one
+    let length = request
+        .length
+        .or(match (agg.metadata.start, agg.metadata.end) {
+            (Some(start), Some(end)) => {
+                Some(((end - start).num_hours().max(1) as u64).div_ceil(24))
+            }
+            _ => None,
+        });
+
+    let blob = api::BookingBlob {
+        origin,
+        template_id: agg.template,
+        allowed_users: agg.users.clone(),
+        global_cifile: String::new(),
+        metadata: api::BookingMetadataBlob {
+            booking_id: None,
+            owner: agg.metadata.owner.clone(),
+            lab: agg.metadata.lab.clone(),
+            purpose: agg.metadata.purpose.clone(),
+            project: agg.metadata.project.clone(),
+            length,
+            performance_tolerance: agg.metadata.performance_tolerance,
+            spread: agg.metadata.spread,
+            display_name: agg.metadata.display_name.clone(),
+            telemetry: Some(agg.metadata.telemetry),
+        },
+        owner: None,
+        parameters: values,
+        env: agg.configuration.env.clone(),
+        tags,
+        constraints,
+        queue: request.wait && config::settings().booking_queue.enabled,
+        start_date: None,
+        ownership_override: None,
+    };
+
+    let plan = booking::plan_aggregate(blob.clone())
+        .await
+        .map_err(|e| BookingError::BadRequest(format!("Can't book this again: {e}")))?;
+
+    // which flavor stands in for a retired one is only known once hosts are matched,
+    // and the plan has the hosts in the order they were expanded in
+    for (substitution, i) in substitutions
+        .iter_mut()
+        .filter(|s| s.kind == SubstitutionKind::Flavor)
+        .zip(substituted)
+    {
+        substitution.to = plan
+            .hosts
+            .get(i)
+            .and_then(|p| p.matched.as_ref())
+            .map(|m| m.flavor_name.clone());
+    }
+
+    // if every flavor that is short has hosts coming back, by when all of them do
+    let earliest_available = plan
+        .shortfalls
+        .iter()
+        .map(|s| s.availability.earliest_available)
+        .collect::<Option<Vec<_>>>()
+        .and_then(|ends| ends.into_iter().max());
+
+    if request.dry_run {
+        return Ok(Json(Rebooked {
+            aggregate: None,
+            queued: false,
+            earliest_available,
+            substitutions,
+            plan,
+        }));
+    }
+
+    let rebooked = create_aggregate(blob).await?;
+
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+    let queued = rebooked
+        .get(&mut transaction)
+        .await
+        .log_db_client_error()?
+        .state
+        == LifeCycleState::Queued;
+    transaction.commit().await.log_db_client_error()?;
+
+    Ok(Json(Rebooked {
+        aggregate: Some(rebooked),
+        queued,
+        earliest_available: earliest_available.filter(|_| queued),
+        substitutions,
+        plan,
+    }))
+}
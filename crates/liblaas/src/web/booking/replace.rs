@@ -0,0 +1,201 @@
+//! Lets owners have a faulty host of their booking swapped out for another of the same
+//! flavor, pending admin approval if the config asks for it
+
+use axum::{
+    extract::{Json, Path},
+    http::{HeaderMap, StatusCode},
+};
+use common::prelude::{chrono::Utc, *};
+use dal::{new_client, web::*, AsEasyTransaction, EasyTransaction, FKey, NewRow};
+use models::{
+    dashboard::{
+        BookingCapability, HostReplacement, Instance, LeaseOperation, LifeCycleState,
+        ReplacementState,
+    },
+    inventory::Host,
+};
+use notifications::email::send_to_admins;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use workflows::entry::{Action, DISPATCH};
+
+use super::{check_not_busy, collaborators::check_capability, BookingError};
+use crate::web::identity::named;
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct HostReplacementRequest {
+    reason: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct HostReplacementBlob {
+    id: FKey<HostReplacement>,
+    faulty_host: FKey<Host>,
+    replacement_host: Option<FKey<Host>>,
+    state: ReplacementState,
+    requested_by: String,
+    reason: String,
+    reviewed_by: Option<String>,
+    requested: String,
+    updated: String,
+}
+
+impl From<&HostReplacement> for HostReplacementBlob {
+    fn from(r: &HostReplacement) -> Self {
+        Self {
+            id: r.id,
+            faulty_host: r.faulty_host,
+            replacement_host: r.replacement_host,
+            state: r.state,
+            requested_by: r.requested_by.clone(),
+            reason: r.reason.clone(),
+            reviewed_by: r.reviewed_by.clone(),
+            requested: r.requested.to_rfc2822(),
+            updated: r.updated.to_rfc2822(),
+        }
+    }
+}
+
+#[axum::debug_handler]
+pub async fn list_host_replacements(
+    Path(instance_id): Path<Uuid>,
+) -> Result<Json<Vec<HostReplacementBlob>>, BookingError> {
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+    let replacements =
+        HostReplacement::all_for_instance(&mut transaction, FKey::from_id(instance_id.into()))
+            .await
+            .log_db_client_error()?;
+
+    transaction.commit().await.log_db_client_error()?;
+
+    Ok(Json(replacements.iter().map(|r| (&**r).into()).collect()))
+}
+
+#[axum::debug_handler]
+pub async fn request_host_replacement(
+    headers: HeaderMap,
+    Path(instance_id): Path<Uuid>,
+    Json(request): Json<HostReplacementRequest>,
+) -> Result<Json<HostReplacementBlob>, BookingError> {
+    let requested_by = named(&headers)?;
+
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+    let instance_id: FKey<Instance> = FKey::from_id(instance_id.into());
+    let instance = instance_id.get(&mut transaction).await.log_error(
+        StatusCode::NOT_FOUND,
+        "Instance does not exist",
+        true,
+    )?;
+    let agg = instance
+        .aggregate
+        .get(&mut transaction)
+        .await
+        .log_db_client_error()?;
+    check_capability(&mut transaction, &headers, &agg, BookingCapability::Reimage).await?;
+
+    let replacement =
+        start_replacement(transaction, &instance, requested_by, request.reason).await?;
+
+    Ok(Json((&replacement).into()))
+}
+
+/// Records that the host of `instance` is to be replaced and starts replacing it, or
+/// asks admins to approve it first if the config says to
+///
+/// Only hosts of bookings that are up and running and not busy deploying hosts can be
+/// replaced.
+pub(super) async fn start_replacement(
+    mut transaction: EasyTransaction<'_>,
+    instance: &Instance,
+    requested_by: String,
+    reason: String,
+) -> Result<HostReplacement, BookingError> {
+    let agg = instance
+        .aggregate
+        .get(&mut transaction)
+        .await
+        .log_db_client_error()?;
+    if !matches!(agg.state, LifeCycleState::Active) {
+        return Err(BookingError::Conflict(
+            "Hosts can only be replaced in bookings that are up and running".to_owned(),
+        ));
+    }
+    check_not_busy(&mut transaction, agg.id, LeaseOperation::Reimage).await?;
+
+    let faulty_host = instance.linked_host.ok_or(BookingError::Conflict(
+        "Instance has no host to replace".to_owned(),
+    ))?;
+
+    let existing = HostReplacement::all_for_instance(&mut transaction, instance.id)
+        .await
+        .log_db_client_error()?;
+    if existing.iter().any(|r| r.is_open()) {
+        return Err(BookingError::Conflict(
+            "The host of this instance is already being replaced or awaiting approval".to_owned(),
+        ));
+    }
+
+    let require_approval = config::settings().host_replacement.require_approval;
+
+    let now = Utc::now();
+    let replacement = HostReplacement {
+        id: FKey::new_id_dangling(),
+        aggregate: instance.aggregate,
+        instance: instance.id,
+        faulty_host,
+        replacement_host: None,
+        state: match require_approval {
+            true => ReplacementState::Requested,
+            false => ReplacementState::Replacing,
+        },
+        requested_by,
+        reason,
+        reviewed_by: None,
+        requested: now,
+        updated: now,
+    };
+
+    NewRow::new(replacement.clone())
+        .insert(&mut transaction)
+        .await
+        .log_server_error("Unable to record host replacement request", true)?;
+
+    let server_name = faulty_host
+        .get(&mut transaction)
+        .await
+        .log_db_client_error()?
+        .server_name
+        .clone();
+
+    transaction.commit().await.log_db_client_error()?;
+
+    if require_approval {
+        send_to_admins(format!(
+            "{} requested that host {server_name} of booking {:?} be replaced, because: {}. \
+            Review it from the CLI with \"Review Host Replacements\".",
+            replacement.requested_by,
+            instance.aggregate.into_id(),
+            replacement.reason
+        ))
+        .await;
+    } else {
+        DISPATCH
+            .get()
+            .ok_or(BookingError::Internal(
+                "Unable to get dispatcher".to_owned(),
+            ))?
+            .send(Action::ReplaceHost {
+                replacement: replacement.id,
+            })
+            .map_err(|_| {
+                BookingError::Internal("Unable to start the host replacement".to_owned())
+            })?;
+    }
+
+    Ok(replacement)
+}
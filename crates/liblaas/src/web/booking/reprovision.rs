@@ -0,0 +1,243 @@
+//! Redeploying a single host of a booking with a changed config, where reimaging it
+//! would only swap out its image
+//!
+//! Whatever the request leaves out stays as it was, and the host is then deployed anew
+//! from the updated config just like a reimage would.
+
+use std::collections::{HashMap, HashSet};
+
+use axum::{
+    extract::{Json, Path},
+    http::{HeaderMap, StatusCode},
+};
+use common::prelude::{chrono::Utc, *};
+use dal::{new_client, web::*, AsEasyTransaction, FKey};
+use models::dashboard::{
+    BondGroupConfig, BookingCapability, Cifile, Image, Instance, LeaseOperation,
+    VlanConnectionConfig,
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use workflows::{
+    entry::{Action, DISPATCH},
+    resource_management::{
+        health::{excuse_instance, REIMAGE_GRACE},
+        images,
+    },
+};
+
+use super::{api, check_not_busy, collaborators::check_capability, BookingError};
+
+/// What to change about the instance, left out fields are kept as they are
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ReprovisionBlob {
+    #[serde(default)]
+    hostname: Option<String>,
+    #[serde(default)]
+    image: Option<FKey<Image>>,
+    /// The contents of each ci file, applied in order, replacing the current ones
+    #[serde(default)]
+    cifile: Option<Vec<String>>,
+    /// Extra arguments the installer is booted with, replacing the current ones
+    #[serde(default)]
+    kernel_args: Option<Vec<(String, String)>>,
+    /// How the interfaces of the host are bonded and connected to the networks of the
+    /// booking, replacing the current layout
+    #[serde(default)]
+    bondgroups: Option<Vec<api::BondgroupBlob>>,
+}
+
+#[axum::debug_handler]
+pub async fn reprovision_host(
+    headers: HeaderMap,
+    Path(instance_id): Path<Uuid>,
+    Json(request): Json<ReprovisionBlob>,
+) -> Result<(), BookingError> {
+    tracing::info!("API call to reprovision_host() for {instance_id}");
+
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+    let instance_id: FKey<Instance> = FKey::from_id(instance_id.into());
+    let mut inst = instance_id.get(&mut transaction).await.log_error(
+        StatusCode::NOT_FOUND,
+        "Instance does not exist",
+        true,
+    )?;
+    let agg = inst
+        .aggregate
+        .get(&mut transaction)
+        .await
+        .log_db_client_error()?;
+    check_capability(&mut transaction, &headers, &agg, BookingCapability::Reimage).await?;
+    if agg.deleted || agg.state.ended() {
+        return Err(BookingError::Conflict(
+            "Hosts of a booking that has ended can't be reprovisioned".to_owned(),
+        ));
+    }
+    check_not_busy(&mut transaction, inst.aggregate, LeaseOperation::Reimage).await?;
+
+    let host_id = inst.linked_host.ok_or(BookingError::Conflict(
+        "Instance has no host to reprovision".to_owned(),
+    ))?;
+    let host = host_id.get(&mut transaction).await.log_db_client_error()?;
+
+    if let Some(hostname) = request.hostname {
+        let hostname = hostname.trim().to_owned();
+        if hostname.is_empty() {
+            return Err(BookingError::BadRequest(
+                "The hostname can't be empty".to_owned(),
+            ));
+        }
+        let taken = agg
+            .instances(&mut transaction)
+            .await
+            .log_db_client_error()?
+            .iter()
+            .any(|i| i.id != inst.id && i.config.hostname.eq_ignore_ascii_case(&hostname));
+        if taken {
+            return Err(BookingError::Conflict(format!(
+                "The booking already has a host named {hostname}"
+            )));
+        }
+        inst.config.hostname = hostname;
+    }
+
+    if let Some(image_id) = request.image {
+        let image = image_id.get(&mut transaction).await.log_error(
+            StatusCode::NOT_FOUND,
+            "No image with that id",
+            true,
+        )?;
+        if image.deleted || image.sunset.is_some_and(|s| s <= Utc::now()) {
+            return Err(BookingError::ImageIncompatible(format!(
+                "{} can't be installed anymore",
+                image.name
+            )));
+        }
+        if !image.flavors.contains(&host.flavor) {
+            return Err(BookingError::ImageIncompatible(format!(
+                "{} can't be installed on this host",
+                image.name
+            )));
+        }
+
+        let project = agg
+            .lab
+            .get(&mut transaction)
+            .await
+            .log_db_client_error()?
+            .name
+            .clone();
+        let violations = images::policy_violations(&mut transaction, &project, &[image_id])
+            .await
+            .log_server_error("unable to check the image of the host", true)?;
+        if !violations.is_empty() {
+            return Err(BookingError::ImageIncompatible(format!(
+                "Can't reprovision with this image: {}",
+                violations.join("; ")
+            )));
+        }
+
+        inst.config.image = image_id;
+    }
+
+    if let Some(cifile) = request.cifile {
+        inst.config.cifile = Cifile::new(&mut transaction, cifile)
+            .await
+            .log_server_error("unable to create CI file", true)?;
+    }
+
+    if let Some(kernel_args) = request.kernel_args {
+        inst.config.kernel_args = kernel_args;
+    }
+
+    if let Some(bondgroups) = request.bondgroups {
+        let mut networks = HashMap::new();
+        for network in agg
+            .vlans
+            .get(&mut transaction)
+            .await
+            .log_db_client_error()?
+            .networks
+            .keys()
+        {
+            let name = network
+                .get(&mut transaction)
+                .await
+                .log_db_client_error()?
+                .name
+                .clone();
+            networks.insert(name, *network);
+        }
+
+        let ports: HashSet<String> = host
+            .ports(&mut transaction)
+            .await
+            .log_db_client_error()?
+            .into_iter()
+            .map(|p| p.name)
+            .collect();
+
+        let mut bonded = HashSet::new();
+        let mut connections = Vec::new();
+        for bondgroup in bondgroups {
+            let mut bgc = BondGroupConfig::default();
+            for iface in bondgroup.ifaces {
+                if !ports.contains(&iface.name) {
+                    return Err(BookingError::BadRequest(format!(
+                        "The host has no interface named {}",
+                        iface.name
+                    )));
+                }
+                if !bonded.insert(iface.name.clone()) {
+                    return Err(BookingError::BadRequest(format!(
+                        "{} can only be in one bond group",
+                        iface.name
+                    )));
+                }
+                bgc.member_interfaces.insert(iface.name);
+            }
+            for connection in bondgroup.connections {
+                let network =
+                    networks
+                        .get(&connection.connects_to)
+                        .ok_or(BookingError::BadRequest(format!(
+                            "The booking has no network named {}",
+                            connection.connects_to
+                        )))?;
+                bgc.connects_to.insert(VlanConnectionConfig {
+                    network: *network,
+                    tagged: connection.tagged,
+                });
+            }
+            connections.push(bgc);
+        }
+        inst.config.connections = connections;
+    }
+
+    inst.update(&mut transaction)
+        .await
+        .log_server_error("unable to update the instance", true)?;
+    excuse_instance(&mut transaction, inst.id, REIMAGE_GRACE)
+        .await
+        .log_db_client_error()?;
+
+    transaction.commit().await.log_db_client_error()?;
+
+    // deploying the host anew goes over its image, ci files and networking alike
+    DISPATCH
+        .get()
+        .ok_or(BookingError::Internal(
+            "Unable to get dispatcher".to_owned(),
+        ))?
+        .send(Action::Reimage {
+            host_id,
+            inst_id: instance_id,
+            agg_id: inst.aggregate,
+        })
+        .map_err(|_| BookingError::Internal("Unable to start the reprovision".to_owned()))?;
+
+    Ok(())
+}
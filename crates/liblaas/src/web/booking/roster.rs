@@ -0,0 +1,390 @@
+//! Adding everyone on a course roster or project member list to a booking at once, and
+//! handing out who is on a booking as a roster
+//!
+//! Rosters are CSV, as exported from a spreadsheet or course management system, or a plain
+//! list of usernames. Every row is checked on its own, so the rows that are fine are added
+//! even when others aren't, and each row is answered with what became of it.
+
+use axum::{
+    extract::{Json, Path, Query},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use common::prelude::itertools::Itertools;
+use dal::{new_client, web::*, AsEasyTransaction, FKey};
+use models::dashboard::{
+    Aggregate, AggregateSummary, BookingCapability, BookingEvent, BookingEventKind,
+    CollaboratorPermissions,
+};
+use notifications::contacts;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use workflows::utils::csv;
+
+use super::{
+    collaborators::{carry_through, Collaborators},
+    BookingError,
+};
+use crate::web::identity::{is_admin, requesting_user, User};
+
+/// Columns of a CSV roster that usernames are taken from, the first column if none is there
+const USERNAME_COLUMNS: &[&str] = &["username", "user", "login", "ipa username"];
+
+/// The most rows one roster can have, since every new user on it is looked up in IPA
+const MAX_ROSTER_ROWS: usize = 500;
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct RosterImport {
+    /// A CSV roster with a header row
+    #[serde(default)]
+    pub csv: Option<String>,
+    /// Usernames to add, for rosters that aren't CSV
+    #[serde(default)]
+    pub users: Vec<String>,
+    /// Checks every row without adding anyone
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, JsonSchema, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RowOutcome {
+    Added,
+    /// For dry runs, the row would have been added
+    WouldAdd,
+    AlreadyOnBooking,
+    /// The username came up in an earlier row
+    Duplicate,
+    Invalid,
+    /// Mail to the user can't be delivered, so they couldn't be told about the booking
+    Unreachable,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct RowResult {
+    /// The line of the roster the row is on, or its place in `users`, counting from 1
+    pub row: usize,
+    pub username: String,
+    pub outcome: RowOutcome,
+    pub message: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct RosterImported {
+    pub added: usize,
+    pub rows: Vec<RowResult>,
+    pub collaborators: Collaborators,
+}
+
+/// The usernames of a CSV roster with where each is in it, without its header row
+fn usernames_of(text: &str) -> Result<Vec<(usize, String)>, BookingError> {
+    let rows = csv::parse(text);
+    let Some(header) = rows.first() else {
+        return Err(BookingError::BadRequest("The roster is empty".to_owned()));
+    };
+
+    let column = header
+        .iter()
+        .position(|h| USERNAME_COLUMNS.contains(&h.trim().to_lowercase().as_str()))
+        .unwrap_or(0);
+
+    Ok(rows
+        .iter()
+        .enumerate()
+        .skip(1)
+        .map(|(i, row)| (i + 1, row.get(column).cloned().unwrap_or_default()))
+        .collect())
+}
+
+/// Why `username` can't be one, if it can't
+fn invalid_username(username: &str) -> Option<&'static str> {
+    let mut chars = username.chars();
+    match chars.next() {
+        None => Some("the row has no username"),
+        Some(first) if !(first.is_ascii_lowercase() || first == '_') => {
+            Some("usernames start with a lowercase letter or an underscore")
+        }
+        _ if username.contains('@') => Some("that is an email address, not a username"),
+        _ if !chars.all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || "._-".contains(c)) => {
+            Some("usernames are made of lowercase letters, digits, '.', '_' and '-'")
+        }
+        _ => None,
+    }
+}
+
+#[axum::debug_handler]
+pub async fn import_collaborators(
+    by: User,
+    Path(agg_id): Path<FKey<Aggregate>>,
+    Json(import): Json<RosterImport>,
+) -> Result<Json<RosterImported>, BookingError> {
+    let roster = match import.csv.as_deref() {
+        Some(text) => usernames_of(text)?,
+        None => import
+            .users
+            .iter()
+            .enumerate()
+            .map(|(i, u)| (i + 1, u.clone()))
+            .collect(),
+    };
+    if roster.is_empty() {
+        return Err(BookingError::BadRequest(
+            "The roster has nobody on it".to_owned(),
+        ));
+    }
+    if roster.len() > MAX_ROSTER_ROWS {
+        return Err(BookingError::BadRequest(format!(
+            "A roster can have at most {MAX_ROSTER_ROWS} rows, split it up"
+        )));
+    }
+
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+    let agg = agg_id.get(&mut transaction).await.log_error(
+        StatusCode::NOT_FOUND,
+        "No booking with that id",
+        true,
+    )?;
+    transaction.commit().await.log_db_client_error()?;
+
+    if agg.metadata.owner.as_deref() != Some(by.name.as_str()) && !by.admin {
+        return Err(BookingError::Forbidden(
+            "Only the owner of the booking and admins can change who is on it".to_owned(),
+        ));
+    }
+    if agg.deleted {
+        return Err(BookingError::Conflict(
+            "The booking has already ended".to_owned(),
+        ));
+    }
+
+    // every new user is looked up in IPA before the booking is locked, so it isn't held
+    // locked for as long as that takes
+    let mut rows = Vec::new();
+    for (row, username) in roster {
+        let username = username.trim().to_owned();
+        let (outcome, message) = if let Some(problem) = invalid_username(&username) {
+            (RowOutcome::Invalid, Some(problem.to_owned()))
+        } else if rows.iter().any(|r: &RowResult| r.username == username) {
+            (RowOutcome::Duplicate, None)
+        } else if agg.users.contains(&username) || agg.metadata.owner.as_ref() == Some(&username) {
+            (RowOutcome::AlreadyOnBooking, None)
+        } else {
+            match contacts::verify(&username).await {
+                Ok(_) => match import.dry_run {
+                    true => (RowOutcome::WouldAdd, None),
+                    false => (RowOutcome::Added, None),
+                },
+                Err(e) => {
+                    tracing::info!("Not adding {username} to booking from a roster: {e:?}");
+                    (
+                        RowOutcome::Unreachable,
+                        Some(format!(
+                            "{username} doesn't have an email that can be reached"
+                        )),
+                    )
+                }
+            }
+        };
+
+        rows.push(RowResult {
+            row,
+            username,
+            outcome,
+            message,
+        });
+    }
+
+    if import.dry_run || !rows.iter().any(|r| r.outcome == RowOutcome::Added) {
+        return Ok(Json(RosterImported {
+            added: 0,
+            rows,
+            collaborators: Collaborators {
+                owner: agg.metadata.owner.clone(),
+                users: agg.users.clone(),
+            },
+        }));
+    }
+
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+    let mut agg = Aggregate::lock(&mut transaction, agg_id)
+        .await
+        .log_db_client_error()?;
+    if agg.deleted {
+        return Err(BookingError::Conflict(
+            "The booking has already ended".to_owned(),
+        ));
+    }
+
+    // someone may have added some of them while they were being looked up
+    let mut added: Vec<String> = Vec::new();
+    for row in rows.iter_mut().filter(|r| r.outcome == RowOutcome::Added) {
+        match agg.users.contains(&row.username)
+            || agg.metadata.owner.as_ref() == Some(&row.username)
+        {
+            true => row.outcome = RowOutcome::AlreadyOnBooking,
+            false => added.push(row.username.clone()),
+        }
+    }
+
+    if added.is_empty() {
+        transaction.commit().await.log_db_client_error()?;
+
+        return Ok(Json(RosterImported {
+            added: 0,
+            rows,
+            collaborators: Collaborators {
+                owner: agg.metadata.owner.clone(),
+                users: agg.users.clone(),
+            },
+        }));
+    }
+
+    agg.users.extend(added.iter().cloned());
+    agg.update(&mut transaction)
+        .await
+        .log_server_error("Unable to change who is on the booking", true)?;
+
+    BookingEvent::record(
+        &mut transaction,
+        agg_id,
+        BookingEventKind::CollaboratorAdded,
+        format!("{} added to the booking from a roster", added.join(", ")),
+        Some(by.name.clone()),
+    )
+    .await
+    .log_db_client_error()?;
+
+    AggregateSummary::refresh(&mut transaction, agg_id)
+        .await
+        .log_db_client_error()?;
+
+    transaction.commit().await.log_db_client_error()?;
+
+    let count = added.len();
+    carry_through(agg_id, added, Vec::new())?;
+
+    Ok(Json(RosterImported {
+        added: count,
+        rows,
+        collaborators: Collaborators {
+            owner: agg.metadata.owner.clone(),
+            users: agg.users.clone(),
+        },
+    }))
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum RosterFormat {
+    #[default]
+    Json,
+    /// With a header row, so it can be imported again as it is
+    Csv,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct RosterExportParams {
+    #[serde(default)]
+    format: RosterFormat,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct RosterEntry {
+    pub username: String,
+    pub owner: bool,
+    /// What the user may do to the booking
+    pub capabilities: Vec<BookingCapability>,
+}
+
+fn capability_name(capability: BookingCapability) -> &'static str {
+    match capability {
+        BookingCapability::PowerControl => "power_control",
+        BookingCapability::Reimage => "reimage",
+        BookingCapability::EndBooking => "end_booking",
+    }
+}
+
+/// Everyone on the booking, owner first
+///
+/// Anyone on the booking can see who else is, as can admins and calls that don't say who
+/// they are for.
+#[axum::debug_handler]
+pub async fn export_collaborators(
+    headers: HeaderMap,
+    Path(agg_id): Path<FKey<Aggregate>>,
+    Query(params): Query<RosterExportParams>,
+) -> Result<Response, BookingError> {
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+    let agg = agg_id.get(&mut transaction).await.log_error(
+        StatusCode::NOT_FOUND,
+        "No booking with that id",
+        true,
+    )?;
+
+    if let Some(user) = requesting_user(&headers).filter(|_| !is_admin(&headers)) {
+        if agg.metadata.owner.as_deref() != Some(user) && !agg.users.iter().any(|u| u == user) {
+            return Err(BookingError::Forbidden(format!(
+                "{user} isn't on the booking"
+            )));
+        }
+    }
+
+    let usernames = agg
+        .metadata
+        .owner
+        .iter()
+        .chain(agg.users.iter())
+        .unique()
+        .cloned()
+        .collect_vec();
+    let mut entries = Vec::new();
+    for username in usernames {
+        let capabilities = CollaboratorPermissions::of(&mut transaction, &agg, &username)
+            .await
+            .log_db_client_error()?;
+        entries.push(RosterEntry {
+            owner: agg.metadata.owner.as_ref() == Some(&username),
+            username,
+            capabilities,
+        });
+    }
+
+    transaction.commit().await.log_db_client_error()?;
+
+    Ok(match params.format {
+        RosterFormat::Json => Json(entries).into_response(),
+        RosterFormat::Csv => {
+            let mut out = String::from("username,role,capabilities\n");
+            for entry in entries.iter() {
+                let role = match entry.owner {
+                    true => "owner",
+                    false => "collaborator",
+                };
+                let capabilities = entry
+                    .capabilities
+                    .iter()
+                    .map(|c| capability_name(*c))
+                    .join(";");
+                let row = [entry.username.as_str(), role, capabilities.as_str()];
+                out.push_str(&row.map(csv::field).join(","));
+                out.push('\n');
+            }
+
+            (
+                [
+                    (header::CONTENT_TYPE, "text/csv"),
+                    (
+                        header::CONTENT_DISPOSITION,
+                        "attachment; filename=\"collaborators.csv\"",
+                    ),
+                ],
+                out,
+            )
+                .into_response()
+        }
+    })
+}
@@ -0,0 +1,322 @@
+//! Saving a booking to come back to later, and booking it again from what was saved
+//!
+//! Taking a snapshot captures the disk of every host of a running booking, along with
+//! its networks and settings. Restoring one books the same hosts and networks anew,
+//! with each host deployed from its captured disk rather than a fresh install.
+
+use axum::{
+    extract::{Json, Path},
+    http::StatusCode,
+};
+use common::prelude::{chrono::Utc, itertools::Itertools, *};
+use config::settings;
+use dal::{new_client, web::*, AsEasyTransaction, FKey, NewRow};
+use models::dashboard::{
+    Aggregate, BookingSnapshot, HostSnapshot, LeaseOperation, LifeCycleState, SnapshotNetwork,
+    SnapshotState,
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use workflows::{
+    deploy_booking::snapshot::disk_image_name,
+    entry::{Action, DISPATCH},
+};
+
+use super::{api, check_bookable, check_not_busy, BookingError};
+use crate::booking;
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct SnapshotHostBlob {
+    hostname: String,
+    disk_image: String,
+    captured: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct SnapshotBlob {
+    id: FKey<BookingSnapshot>,
+    name: String,
+    owner: String,
+    aggregate: Option<FKey<Aggregate>>,
+    state: SnapshotState,
+    error: Option<String>,
+    created: String,
+    captured: Option<String>,
+    hosts: Vec<SnapshotHostBlob>,
+    networks: Vec<SnapshotNetwork>,
+}
+
+impl From<&BookingSnapshot> for SnapshotBlob {
+    fn from(s: &BookingSnapshot) -> Self {
+        Self {
+            id: s.id,
+            name: s.name.clone(),
+            owner: s.owner.clone(),
+            aggregate: s.aggregate,
+            state: s.state,
+            error: s.error.clone(),
+            created: s.created.to_rfc2822(),
+            captured: s.captured.map(|c| c.to_rfc2822()),
+            hosts: s
+                .hosts
+                .iter()
+                .map(|h| SnapshotHostBlob {
+                    hostname: h.config.hostname.clone(),
+                    disk_image: h.disk_image.clone(),
+                    captured: h.captured,
+                })
+                .collect(),
+            networks: s.networks.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct TakeSnapshot {
+    name: String,
+    /// Who the snapshot is for, the owner of the booking if not given
+    #[serde(default)]
+    requested_by: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct RestoreSnapshot {
+    /// Metadata for the new booking, its owner is the owner of the snapshot if not given
+    metadata: api::BookingMetadataBlob,
+    #[serde(default)]
+    allowed_users: Vec<String>,
+}
+
+fn require_snapshots() -> Result<(), BookingError> {
+    match settings().snapshots {
+        Some(_) => Ok(()),
+        None => Err(BookingError::Unavailable(
+            "Snapshots aren't set up for this lab".to_owned(),
+        )),
+    }
+}
+
+#[axum::debug_handler]
+pub async fn take_snapshot(
+    Path(agg_id): Path<FKey<Aggregate>>,
+    Json(request): Json<TakeSnapshot>,
+) -> Result<Json<SnapshotBlob>, BookingError> {
+    tracing::info!("API call to take_snapshot() of {agg_id:?}");
+    require_snapshots()?;
+
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+    let agg = agg_id.get(&mut transaction).await.log_error(
+        StatusCode::NOT_FOUND,
+        "No booking with that id",
+        true,
+    )?;
+    if agg.deleted || agg.state != LifeCycleState::Active {
+        return Err(BookingError::Conflict(
+            "Only bookings that are up and running can be snapshotted".to_owned(),
+        ));
+    }
+    check_not_busy(&mut transaction, agg_id, LeaseOperation::Snapshot).await?;
+
+    let owner =
+        request
+            .requested_by
+            .or(agg.metadata.owner.clone())
+            .ok_or(BookingError::BadRequest(
+                "The booking has no owner, so who the snapshot is for has to be given".to_owned(),
+            ))?;
+
+    if BookingSnapshot::for_owner(&mut transaction, &owner)
+        .await
+        .log_db_client_error()?
+        .iter()
+        .any(|s| s.aggregate == Some(agg_id) && s.state == SnapshotState::Capturing)
+    {
+        return Err(BookingError::Conflict(
+            "A snapshot of the booking is already being taken".to_owned(),
+        ));
+    }
+
+    let id = FKey::new_id_dangling();
+
+    let mut hosts = Vec::new();
+    let mut disks = Vec::new();
+    for instance in agg
+        .instances(&mut transaction)
+        .await
+        .log_db_client_error()?
+    {
+        if instance.linked_host.is_none() {
+            return Err(BookingError::Conflict(format!(
+                "{} has no host yet",
+                instance.config.hostname
+            )));
+        }
+
+        let disk_image = disk_image_name(id.into_id(), &instance.config.hostname);
+        disks.push((instance.id, disk_image.clone()));
+        hosts.push(HostSnapshot {
+            config: instance.config.clone(),
+            disk_image,
+            captured: false,
+        });
+    }
+
+    let mut networks = Vec::new();
+    for network in agg
+        .vlans
+        .get(&mut transaction)
+        .await
+        .log_db_client_error()?
+        .networks
+        .keys()
+    {
+        let net = network.get(&mut transaction).await.log_db_client_error()?;
+        networks.push(SnapshotNetwork {
+            network: *network,
+            name: net.name.clone(),
+            public: net.public,
+        });
+    }
+
+    let snapshot = BookingSnapshot {
+        id,
+        name: request.name,
+        owner,
+        aggregate: Some(agg_id),
+        template: agg.template,
+        lab: agg.lab,
+        env: agg.configuration.env.clone(),
+        networks,
+        hosts,
+        state: SnapshotState::Capturing,
+        error: None,
+        created: Utc::now(),
+        captured: None,
+    };
+
+    NewRow::new(snapshot.clone())
+        .insert(&mut transaction)
+        .await
+        .log_server_error("Unable to create the snapshot", true)?;
+
+    transaction.commit().await.log_db_client_error()?;
+
+    DISPATCH
+        .get()
+        .ok_or(BookingError::Internal("Tascii was not found.".to_owned()))?
+        .send(Action::CaptureSnapshot {
+            snapshot: id,
+            disks,
+        })
+        .map_err(|_| BookingError::Internal("Unable to dispatch the snapshot".to_owned()))?;
+
+    Ok(Json((&snapshot).into()))
+}
+
+#[axum::debug_handler]
+pub async fn list_snapshots(
+    Path(owner): Path<String>,
+) -> Result<Json<Vec<SnapshotBlob>>, BookingError> {
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+    let snapshots = BookingSnapshot::for_owner(&mut transaction, &owner)
+        .await
+        .log_db_client_error()?;
+
+    transaction.commit().await.log_db_client_error()?;
+
+    Ok(Json(snapshots.iter().map(|s| (&**s).into()).collect_vec()))
+}
+
+#[axum::debug_handler]
+pub async fn get_snapshot(
+    Path(snapshot_id): Path<FKey<BookingSnapshot>>,
+) -> Result<Json<SnapshotBlob>, BookingError> {
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+    let snapshot = snapshot_id.get(&mut transaction).await.log_error(
+        StatusCode::NOT_FOUND,
+        "No snapshot with that id",
+        true,
+    )?;
+
+    transaction.commit().await.log_db_client_error()?;
+
+    Ok(Json((&*snapshot).into()))
+}
+
+#[axum::debug_handler]
+pub async fn restore_snapshot(
+    Path(snapshot_id): Path<FKey<BookingSnapshot>>,
+    Json(request): Json<RestoreSnapshot>,
+) -> Result<Json<FKey<Aggregate>>, BookingError> {
+    tracing::info!("API call to restore_snapshot() of {snapshot_id:?}");
+    require_snapshots()?;
+
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+    let snapshot = snapshot_id
+        .get(&mut transaction)
+        .await
+        .log_error(StatusCode::NOT_FOUND, "No snapshot with that id", true)?
+        .into_inner();
+    if snapshot.state != SnapshotState::Ready {
+        return Err(BookingError::Conflict(format!(
+            "The snapshot is {:?}, so it can't be restored",
+            snapshot.state
+        )));
+    }
+
+    let mut metadata = request.metadata;
+    metadata.owner = metadata.owner.or(Some(snapshot.owner.clone()));
+
+    check_bookable(
+        &mut transaction,
+        snapshot.template,
+        metadata.owner.as_deref(),
+    )
+    .await?;
+
+    let origin = snapshot
+        .lab
+        .get(&mut transaction)
+        .await
+        .log_db_client_error()?
+        .name
+        .clone();
+
+    transaction.commit().await.log_db_client_error()?;
+
+    let blob = api::BookingBlob {
+        origin,
+        template_id: snapshot.template,
+        allowed_users: request.allowed_users,
+        global_cifile: String::new(),
+        metadata,
+        owner: None,
+        parameters: Default::default(),
+        env: snapshot.env.clone(),
+        tags: Vec::new(),
+        constraints: Default::default(),
+        queue: false,
+        start_date: None,
+        ownership_override: None,
+    };
+
+    let agg = booking::restore_aggregate(blob, &snapshot)
+        .await
+        .map_err(|e| match e.downcast::<booking::Unbookable>() {
+            Ok(unbookable) => unbookable.into(),
+            Err(e) => match e.downcast::<booking::quota::QuotaExceeded>() {
+                Ok(exceeded) => exceeded.into(),
+                Err(e) => BookingError::Conflict(format!("Can't restore this: {e}")),
+            },
+        })?;
+
+    Ok(Json(agg))
+}
@@ -0,0 +1,216 @@
+//! Pushing the provisioning status of a booking to the dashboard as it happens, so it
+//! doesn't have to poll `booking_status`
+//!
+//! The stream starts with everything logged for the booking so far, or everything since
+//! the `Last-Event-ID` a reconnecting client sends, then sends on each event as tasks
+//! publish it to the status feed. Events that are logged without being published, or that
+//! the stream missed by falling behind on the feed, are caught up on by reading the log
+//! every so often.
+
+use std::{
+    collections::{HashSet, VecDeque},
+    convert::Infallible,
+    time::Duration,
+};
+
+use axum::{
+    extract::Path,
+    http::HeaderMap,
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Response,
+    },
+};
+use common::prelude::{
+    anyhow, futures,
+    tokio::{
+        self,
+        sync::broadcast::{self, error::RecvError},
+        time::{Interval, MissedTickBehavior},
+    },
+    tracing,
+};
+use dal::{new_client, web::*, AsEasyTransaction, FKey};
+use models::dashboard::{Aggregate, Instance, ProvisionLogEvent};
+use schemars::JsonSchema;
+use serde::Serialize;
+use workflows::utils::status_feed;
+
+use super::{BookingError, InstanceStatusUpdate};
+
+/// How often the log is read for events that weren't published
+const CATCH_UP_INTERVAL: Duration = Duration::from_secs(15);
+
+/// How often an idle stream is sent a comment, so proxies don't time it out
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(20);
+
+/// The data of each `status` event of the stream
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct StreamedStatusUpdate {
+    pub instance: FKey<Instance>,
+    #[serde(flatten)]
+    pub update: InstanceStatusUpdate,
+}
+
+/// What has and hasn't been sent yet of the log of a booking
+struct Watch {
+    instances: Vec<FKey<Instance>>,
+    feed: broadcast::Receiver<ProvisionLogEvent>,
+    sent: HashSet<FKey<ProvisionLogEvent>>,
+    queue: VecDeque<ProvisionLogEvent>,
+    poll: Interval,
+}
+
+impl Watch {
+    /// What was logged for the instances that hasn't been sent, oldest first
+    async fn unsent(&self) -> Result<Vec<ProvisionLogEvent>, anyhow::Error> {
+        let mut client = new_client().await?;
+        let mut transaction = client.easy_transaction().await?;
+
+        let mut unsent = Vec::new();
+        for instance in self.instances.iter() {
+            unsent.extend(
+                ProvisionLogEvent::all_for_instance(&mut transaction, *instance)
+                    .await?
+                    .into_iter()
+                    .map(|l| l.into_inner())
+                    .filter(|l| !self.sent.contains(&l.id)),
+            );
+        }
+        transaction.commit().await?;
+
+        unsent.sort_by_key(|l| l.time);
+
+        Ok(unsent)
+    }
+
+    async fn catch_up(&mut self) {
+        match self.unsent().await {
+            Ok(unsent) => {
+                for log in unsent {
+                    if !self.queue.iter().any(|q| q.id == log.id) {
+                        self.queue.push_back(log);
+                    }
+                }
+            }
+            Err(e) => tracing::warn!("Couldn't catch up on the log of a status stream: {e:?}"),
+        }
+    }
+
+    /// The next event to send, waiting for one to be logged if all have been
+    async fn next(&mut self) -> Option<ProvisionLogEvent> {
+        loop {
+            if let Some(log) = self.queue.pop_front() {
+                if self.sent.insert(log.id) {
+                    return Some(log);
+                }
+                continue;
+            }
+
+            tokio::select! {
+                received = self.feed.recv() => match received {
+                    Ok(log) if self.instances.contains(&log.instance) => self.queue.push_back(log),
+                    Ok(_) => {}
+                    Err(RecvError::Lagged(missed)) => {
+                        tracing::debug!("Status stream fell {missed} events behind the feed");
+                        self.catch_up().await;
+                    }
+                    Err(RecvError::Closed) => return None,
+                },
+                _ = self.poll.tick() => self.catch_up().await,
+            }
+        }
+    }
+}
+
+fn event_of(log: &ProvisionLogEvent) -> Option<Event> {
+    let update = StreamedStatusUpdate {
+        instance: log.instance,
+        update: InstanceStatusUpdate::from(log),
+    };
+
+    match Event::default()
+        .event("status")
+        .id(log.id.into_id().to_string())
+        .json_data(update)
+    {
+        Ok(event) => Some(event),
+        Err(e) => {
+            tracing::error!("Couldn't put status event {:?} in the stream: {e}", log.id);
+            None
+        }
+    }
+}
+
+/// Streams `status` events, each a [`StreamedStatusUpdate`], as the instances of the
+/// booking are provisioned
+pub async fn booking_status_stream(
+    Path(agg_id): Path<FKey<Aggregate>>,
+    headers: HeaderMap,
+) -> Result<Response, BookingError> {
+    // subscribed before the log is read, so nothing logged in between is missed
+    let feed = status_feed::subscribe();
+
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+    let agg = agg_id
+        .get(&mut transaction)
+        .await
+        .map_err(|_| BookingError::NotFound(format!("No booking {:?}", agg_id.into_id())))?;
+    let instances = agg
+        .instances(&mut transaction)
+        .await
+        .log_db_client_error()?
+        .iter()
+        .map(|i| i.id)
+        .collect();
+
+    transaction.commit().await.log_db_client_error()?;
+
+    let mut poll = tokio::time::interval(CATCH_UP_INTERVAL);
+    poll.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    // the first tick is right away, and the log is about to be read anyway
+    poll.reset();
+
+    let mut watch = Watch {
+        instances,
+        feed,
+        sent: HashSet::new(),
+        queue: VecDeque::new(),
+        poll,
+    };
+
+    let logged = watch
+        .unsent()
+        .await
+        .log_server_error("Unable to read the provision log", true)?;
+
+    // a reconnecting client has already been sent everything up to the last event it got
+    let resumed = headers
+        .get("last-event-id")
+        .and_then(|h| h.to_str().ok())
+        .and_then(|id| logged.iter().find(|l| l.id.into_id().to_string() == id))
+        .map(|l| l.time);
+    for log in logged {
+        match resumed {
+            Some(last) if log.time <= last => {
+                watch.sent.insert(log.id);
+            }
+            _ => watch.queue.push_back(log),
+        }
+    }
+
+    let events = futures::stream::unfold(watch, |mut watch| async move {
+        loop {
+            let log = watch.next().await?;
+            if let Some(event) = event_of(&log) {
+                return Some((Ok::<_, Infallible>(event), watch));
+            }
+        }
+    });
+
+    Ok(Sse::new(events)
+        .keep_alive(KeepAlive::new().interval(KEEP_ALIVE_INTERVAL))
+        .into_response())
+}
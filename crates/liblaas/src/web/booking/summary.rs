@@ -0,0 +1,233 @@
+//! Booking lists and status polls, served from `aggregate_summary` instead of being worked
+//! out from the instances and logs of each booking on every request
+//!
+//! Bookings made before the table existed get their summary the first time they're asked for.
+
+use axum::extract::{Json, Path, Query};
+use common::prelude::chrono::{DateTime, Utc};
+use dal::{new_client, web::*, AsEasyTransaction, EasyTransaction, FKey};
+use models::dashboard::{
+    Aggregate, AggregateCursor, AggregateFilter, AggregateSummary, LifeCycleState,
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use super::BookingError;
+use crate::web::listing::{decode_cursor, encode_cursor, Listed, Listing, MAX_LIMIT};
+
+/// The longest list of bookings a batch status request can ask about
+const MAX_BATCH: usize = 500;
+
+/// How many bookings a page of the booking list holds if not asked for otherwise
+const DEFAULT_PAGE: usize = 50;
+
+pub(super) async fn summaries(
+    transaction: &mut EasyTransaction<'_>,
+    aggregates: Vec<FKey<Aggregate>>,
+) -> Result<Vec<AggregateSummary>, BookingError> {
+    let mut found = AggregateSummary::for_aggregates(transaction, aggregates.clone())
+        .await
+        .log_db_client_error()?;
+
+    let missing: Vec<_> = aggregates
+        .iter()
+        .copied()
+        .filter(|a| !found.iter().any(|s| s.aggregate == *a))
+        .collect();
+    for aggregate in missing.iter().copied() {
+        // unknown ids are left out rather than failing the whole batch
+        if aggregate.get(transaction).await.is_err() {
+            continue;
+        }
+        AggregateSummary::refresh(transaction, aggregate)
+            .await
+            .log_db_client_error()?;
+    }
+    if !missing.is_empty() {
+        found.extend(
+            AggregateSummary::for_aggregates(transaction, missing)
+                .await
+                .log_db_client_error()?,
+        );
+    }
+
+    Ok(found.into_iter().map(|s| s.into_inner()).collect())
+}
+
+pub async fn booking_summary(
+    Path(agg_id): Path<FKey<Aggregate>>,
+) -> Result<Json<AggregateSummary>, BookingError> {
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+    let summary = summaries(&mut transaction, vec![agg_id])
+        .await?
+        .pop()
+        .ok_or((
+            axum::http::StatusCode::NOT_FOUND,
+            "No booking by that id".to_owned(),
+        ))?;
+
+    transaction.commit().await.log_db_client_error()?;
+
+    Ok(Json(summary))
+}
+
+/// Summaries of many bookings at once, leaving out any that don't exist
+pub async fn batch_status(
+    Json(aggregates): Json<Vec<FKey<Aggregate>>>,
+) -> Result<Json<Vec<AggregateSummary>>, BookingError> {
+    if aggregates.len() > MAX_BATCH {
+        return Err(BookingError::BadRequest(format!(
+            "Can ask about at most {MAX_BATCH} bookings at once"
+        )));
+    }
+
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+    let summaries = summaries(&mut transaction, aggregates).await?;
+
+    transaction.commit().await.log_db_client_error()?;
+
+    Ok(Json(summaries))
+}
+
+/// Every booking `username` owns or collaborates on, most recently started first unless sorted otherwise
+pub async fn list_bookings(
+    Path(username): Path<String>,
+    listing: Listing,
+) -> Result<Listed<AggregateSummary>, BookingError> {
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+    let aggregates = Aggregate::all_for_user(&mut transaction, &username)
+        .await
+        .log_db_client_error()?
+        .iter()
+        .map(|a| a.id)
+        .collect();
+    let summaries = summaries(&mut transaction, aggregates).await?;
+
+    transaction.commit().await.log_db_client_error()?;
+
+    Ok(listing.default_sort("-start").apply(summaries)?)
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum BookingState {
+    /// Waiting for hosts to free up
+    Queued,
+    /// Holding reservations on its hosts until it starts
+    Scheduled,
+    /// Hasn't finished provisioning yet
+    Provisioning,
+    Active,
+    /// Its hosts are being cleaned up
+    TearingDown,
+    /// Every host failed to provision, so it was released
+    Failed,
+    /// Ended and cleaned up
+    Expired,
+}
+
+impl From<LifeCycleState> for BookingState {
+    fn from(state: LifeCycleState) -> Self {
+        match state {
+            LifeCycleState::Queued => BookingState::Queued,
+            LifeCycleState::Scheduled => BookingState::Scheduled,
+            LifeCycleState::New => BookingState::Provisioning,
+            LifeCycleState::Active => BookingState::Active,
+            LifeCycleState::TearingDown => BookingState::TearingDown,
+            LifeCycleState::Failed => BookingState::Failed,
+            LifeCycleState::Done => BookingState::Expired,
+        }
+    }
+}
+
+impl From<BookingState> for LifeCycleState {
+    fn from(state: BookingState) -> Self {
+        match state {
+            BookingState::Queued => LifeCycleState::Queued,
+            BookingState::Scheduled => LifeCycleState::Scheduled,
+            BookingState::Provisioning => LifeCycleState::New,
+            BookingState::Active => LifeCycleState::Active,
+            BookingState::TearingDown => LifeCycleState::TearingDown,
+            BookingState::Failed => LifeCycleState::Failed,
+            BookingState::Expired => LifeCycleState::Done,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+pub struct BookingListQuery {
+    /// From the `x-next-cursor` header of the previous page
+    cursor: Option<String>,
+    limit: Option<usize>,
+    owner: Option<String>,
+    project: Option<String>,
+    state: Option<BookingState>,
+    /// Only bookings that haven't ended by then (RFC 3339)
+    #[schemars(with = "Option<String>")]
+    from: Option<DateTime<Utc>>,
+    /// Only bookings that start before then (RFC 3339)
+    #[schemars(with = "Option<String>")]
+    to: Option<DateTime<Utc>>,
+}
+
+/// Every booking matching the filters, a page at a time, most recently started first
+pub async fn list_all_bookings(
+    Query(query): Query<BookingListQuery>,
+) -> Result<Listed<AggregateSummary>, BookingError> {
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE);
+    if limit == 0 || limit > MAX_LIMIT {
+        return Err(BookingError::BadRequest(format!(
+            "limit must be between 1 and {MAX_LIMIT}"
+        )));
+    }
+
+    let after = query
+        .cursor
+        .as_deref()
+        .map(decode_cursor::<AggregateCursor>)
+        .transpose()?;
+
+    let filter = AggregateFilter {
+        owner: query.owner,
+        project: query.project,
+        state: query.state.map(Into::into),
+        from: query.from,
+        to: query.to,
+    };
+
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+    // one more than asked for tells whether there is a page after this one
+    let mut page = Aggregate::page(&mut transaction, &filter, after, limit as i64 + 1)
+        .await
+        .log_db_client_error()?;
+
+    let next = match page.len() > limit {
+        true => {
+            page.truncate(limit);
+            page.last().and_then(|last| {
+                encode_cursor(&AggregateCursor {
+                    start: last.metadata.start,
+                    id: last.id,
+                })
+            })
+        }
+        false => None,
+    };
+
+    let order: Vec<_> = page.iter().map(|a| a.id).collect();
+    let mut found = summaries(&mut transaction, order.clone()).await?;
+
+    transaction.commit().await.log_db_client_error()?;
+
+    found.sort_by_key(|s| order.iter().position(|a| *a == s.aggregate));
+
+    Ok(Listed::page(found, next)?)
+}
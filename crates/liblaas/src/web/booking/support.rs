@@ -0,0 +1,322 @@
+//! Users asking the lab for help with a booking, with what the admins need to know about
+//! the booking gathered for them rather than asked for over email
+//!
+//! Requests are filed in the lab's issue tracker if one is configured, and mailed to the
+//! admins if not or if filing them fails. Either way they are kept with the booking.
+
+use axum::{
+    extract::{Json, Path},
+    http::StatusCode,
+};
+use common::prelude::{chrono::Utc, *};
+use dal::{new_client, web::*, AsEasyTransaction, EasyTransaction, FKey, NewRow};
+use models::dashboard::{
+    Aggregate, BookingEvent, InstanceHealthCheck, ProvisionLogEvent, StatusSentiment,
+    SupportRequest,
+};
+use notifications::email::send_to_admins;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use workflows::resource_management::tickets::open_support_ticket;
+
+use super::BookingError;
+use crate::web::identity::User;
+
+const MAX_SUMMARY_LEN: usize = 200;
+const MAX_DESCRIPTION_LEN: usize = 10_000;
+
+/// How many of the latest entries of each provision log go with a request
+const RECENT_LOG: usize = 15;
+
+/// How many of the latest lifecycle events of the booking go with a request
+const RECENT_EVENTS: usize = 10;
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct SupportRequestBody {
+    summary: String,
+    description: String,
+}
+
+/// What a host of the booking looked like when help was asked for
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct InstanceContext {
+    hostname: String,
+    server_name: Option<String>,
+    status: Option<String>,
+    sentiment: Option<StatusSentiment>,
+    /// The latest entries of its provision log, oldest first
+    recent_log: Vec<String>,
+    /// How its latest health check went
+    health: Option<String>,
+}
+
+/// What the booking looked like when help was asked for
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct SupportContext {
+    state: String,
+    lab: String,
+    owner: Option<String>,
+    start: Option<String>,
+    end: Option<String>,
+    instances: Vec<InstanceContext>,
+    /// The latest lifecycle events of the booking, oldest first
+    recent_events: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct SupportRequestBlob {
+    id: FKey<SupportRequest>,
+    requested_by: String,
+    summary: String,
+    description: String,
+    context: serde_json::Value,
+    ticket: Option<String>,
+    ticket_url: Option<String>,
+    created: String,
+}
+
+impl From<&SupportRequest> for SupportRequestBlob {
+    fn from(r: &SupportRequest) -> Self {
+        Self {
+            id: r.id,
+            requested_by: r.requested_by.clone(),
+            summary: r.summary.clone(),
+            description: r.description.clone(),
+            context: r.context.clone(),
+            ticket: r.ticket.clone(),
+            ticket_url: r.ticket_url.clone(),
+            created: r.created.to_rfc2822(),
+        }
+    }
+}
+
+fn latest<T>(mut items: Vec<T>, n: usize) -> Vec<T> {
+    items.drain(..items.len().saturating_sub(n));
+    items
+}
+
+async fn gather_context(
+    t: &mut EasyTransaction<'_>,
+    agg: &Aggregate,
+) -> Result<SupportContext, anyhow::Error> {
+    let mut instances = Vec::new();
+    for instance in agg.instances(t).await? {
+        let server_name = match instance.linked_host {
+            Some(host) => Some(host.get(t).await?.server_name.clone()),
+            None => None,
+        };
+
+        let mut log = ProvisionLogEvent::all_for_instance(t, instance.id).await?;
+        log.sort_by_key(|l| l.time);
+        let last = log.last().map(|l| (l.prov_status.to_string(), l.sentiment));
+
+        let health = InstanceHealthCheck::latest_for_instance(t, instance.id)
+            .await?
+            .map(|c| {
+                format!(
+                    "{}: {}, {}{}",
+                    c.time.to_rfc2822(),
+                    if c.powered_on {
+                        "powered on"
+                    } else {
+                        "powered off"
+                    },
+                    if c.reachable {
+                        "reachable"
+                    } else {
+                        "unreachable"
+                    },
+                    if c.unplanned_reboot {
+                        ", rebooted unexpectedly"
+                    } else {
+                        ""
+                    }
+                )
+            });
+
+        instances.push(InstanceContext {
+            hostname: instance.config.hostname.clone(),
+            server_name,
+            status: last.as_ref().map(|(status, _)| status.clone()),
+            sentiment: last.map(|(_, sentiment)| sentiment),
+            recent_log: latest(log, RECENT_LOG)
+                .iter()
+                .map(|l| {
+                    format!(
+                        "{} [{:?}] {}",
+                        l.time.to_rfc2822(),
+                        l.sentiment,
+                        l.prov_status
+                    )
+                })
+                .collect(),
+            health,
+        });
+    }
+
+    let events = BookingEvent::for_aggregate(t, agg.id).await?;
+
+    Ok(SupportContext {
+        state: format!("{:?}", agg.state),
+        lab: agg.lab.get(t).await?.name.clone(),
+        owner: agg.metadata.owner.clone(),
+        start: agg.metadata.start.map(|s| s.to_rfc2822()),
+        end: agg.metadata.end.map(|e| e.to_rfc2822()),
+        instances,
+        recent_events: latest(events, RECENT_EVENTS)
+            .iter()
+            .map(|e| format!("{} {:?}: {}", e.time.to_rfc2822(), e.kind, e.details))
+            .collect(),
+    })
+}
+
+/// The request as it is filed, with the context written out below what the user said
+fn render(
+    agg: &Aggregate,
+    requested_by: &str,
+    description: &str,
+    context: &SupportContext,
+) -> String {
+    let mut out = format!(
+        "{description}\n\n\
+        Asked by: {requested_by}\n\
+        Booking: {} ({})\n\
+        State: {}, lab: {}, owner: {}\n\
+        From {} until {}\n",
+        agg.metadata
+            .display_name
+            .clone()
+            .or(agg.metadata.booking_id.clone())
+            .unwrap_or_default(),
+        agg.id.into_id(),
+        context.state,
+        context.lab,
+        context.owner.as_deref().unwrap_or("unknown"),
+        context.start.as_deref().unwrap_or("unknown"),
+        context.end.as_deref().unwrap_or("no end"),
+    );
+
+    for instance in context.instances.iter() {
+        out.push_str(&format!(
+            "\nHost {} on {}\n  Status: {}\n  Health: {}\n",
+            instance.hostname,
+            instance.server_name.as_deref().unwrap_or("no host"),
+            instance.status.as_deref().unwrap_or("nothing logged"),
+            instance.health.as_deref().unwrap_or("never checked"),
+        ));
+        for line in instance.recent_log.iter() {
+            out.push_str(&format!("  {line}\n"));
+        }
+    }
+
+    out.push_str("\nRecent events:\n");
+    for event in context.recent_events.iter() {
+        out.push_str(&format!("  {event}\n"));
+    }
+
+    out
+}
+
+/// Asks the lab for help with the booking
+#[axum::debug_handler]
+pub async fn request_support(
+    by: User,
+    Path(agg_id): Path<FKey<Aggregate>>,
+    Json(body): Json<SupportRequestBody>,
+) -> Result<Json<SupportRequestBlob>, BookingError> {
+    let summary = body.summary.trim().to_owned();
+    let description = body.description.trim().to_owned();
+    if summary.is_empty() || summary.len() > MAX_SUMMARY_LEN {
+        return Err(BookingError::BadRequest(format!(
+            "The summary has to be between 1 and {MAX_SUMMARY_LEN} characters"
+        )));
+    }
+    if description.is_empty() || description.len() > MAX_DESCRIPTION_LEN {
+        return Err(BookingError::BadRequest(format!(
+            "The description has to be between 1 and {MAX_DESCRIPTION_LEN} characters"
+        )));
+    }
+
+    let requested_by = by.name;
+
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+    let agg = agg_id.get(&mut transaction).await.log_error(
+        StatusCode::NOT_FOUND,
+        "No booking with that id",
+        true,
+    )?;
+    let is_user = agg.metadata.owner.as_deref() == Some(requested_by.as_str())
+        || agg.users.contains(&requested_by);
+    if !is_user && !by.admin {
+        return Err(BookingError::Forbidden(format!(
+            "{requested_by} isn't a user of this booking"
+        )));
+    }
+
+    let context = gather_context(&mut transaction, &agg)
+        .await
+        .log_server_error("Unable to gather what the booking looks like", true)?;
+    transaction.commit().await.log_db_client_error()?;
+
+    let rendered = render(&agg, &requested_by, &description, &context);
+    let ticket_summary = format!("[{}] {summary}", context.lab);
+
+    let ticket = match open_support_ticket(&ticket_summary, &rendered).await {
+        Ok(ticket) => ticket,
+        Err(e) => {
+            tracing::error!("Couldn't file support request for {agg_id:?}: {e:?}");
+            None
+        }
+    };
+    if ticket.is_none() {
+        send_to_admins(format!(
+            "{requested_by} asked for help with booking {:?}: {summary}<br><br>{}",
+            agg_id.into_id(),
+            rendered.replace('\n', "<br>")
+        ))
+        .await;
+    }
+
+    let request = SupportRequest {
+        id: FKey::new_id_dangling(),
+        aggregate: agg_id,
+        requested_by,
+        summary,
+        description,
+        context: serde_json::to_value(&context)
+            .map_err(anyhow::Error::from)
+            .log_server_error("Unable to save what the booking looks like", true)?,
+        ticket: ticket.as_ref().map(|t| t.external_id.clone()),
+        ticket_url: ticket.and_then(|t| t.url),
+        created: Utc::now(),
+    };
+
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+    NewRow::new(request.clone())
+        .insert(&mut transaction)
+        .await
+        .log_server_error("Unable to record the support request", true)?;
+    transaction.commit().await.log_db_client_error()?;
+
+    Ok(Json((&request).into()))
+}
+
+/// Every request for help made about the booking, most recent first
+#[axum::debug_handler]
+pub async fn list_support_requests(
+    Path(agg_id): Path<FKey<Aggregate>>,
+) -> Result<Json<Vec<SupportRequestBlob>>, BookingError> {
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+    let requests = SupportRequest::for_aggregate(&mut transaction, agg_id)
+        .await
+        .log_db_client_error()?;
+
+    transaction.commit().await.log_db_client_error()?;
+
+    Ok(Json(requests.iter().map(|r| (&**r).into()).collect()))
+}
@@ -0,0 +1,141 @@
+//! Free-form tags on bookings, so teams can find related bookings together, like every
+//! booking made for `plugfest-2025`
+
+use axum::{
+    extract::{Json, Path, Query},
+    http::StatusCode,
+};
+use common::prelude::itertools::Itertools;
+use dal::{new_client, web::*, AsEasyTransaction, FKey};
+use models::dashboard::{Aggregate, AggregateSummary, AggregateTag};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use super::{summary::summaries, BookingError};
+use crate::web::{
+    identity::User,
+    listing::{Listed, Listing},
+};
+
+/// The most tags one search can be for
+const MAX_SEARCH_TAGS: usize = 20;
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct TagChange {
+    /// Tags to put on the booking, any it already has are left as they are
+    #[serde(default)]
+    pub add: Vec<String>,
+    #[serde(default)]
+    pub remove: Vec<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+pub struct TagSearch {
+    /// Comma separated, like `plugfest-2025,interop`
+    pub tags: String,
+    /// Only match bookings with every one of the tags, instead of any of them
+    #[serde(default)]
+    pub all: bool,
+}
+
+#[axum::debug_handler]
+pub async fn get_tags(
+    Path(agg_id): Path<FKey<Aggregate>>,
+) -> Result<Json<Vec<String>>, BookingError> {
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+    agg_id.get(&mut transaction).await.log_error(
+        StatusCode::NOT_FOUND,
+        "No booking with that id",
+        true,
+    )?;
+    let tags = AggregateTag::for_aggregate(&mut transaction, agg_id)
+        .await
+        .log_db_client_error()?;
+
+    transaction.commit().await.log_db_client_error()?;
+
+    Ok(Json(tags))
+}
+
+/// Adds and removes tags of the booking, returning the tags it has after
+#[axum::debug_handler]
+pub async fn update_tags(
+    by: User,
+    Path(agg_id): Path<FKey<Aggregate>>,
+    Json(change): Json<TagChange>,
+) -> Result<Json<Vec<String>>, BookingError> {
+    for tag in change.add.iter().chain(change.remove.iter()) {
+        AggregateTag::normalize(tag).map_err(|e| BookingError::BadRequest(e.to_string()))?;
+    }
+
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+    let agg = agg_id.get(&mut transaction).await.log_error(
+        StatusCode::NOT_FOUND,
+        "No booking with that id",
+        true,
+    )?;
+    if agg.metadata.owner.as_deref() != Some(by.name.as_str())
+        && !agg.users.contains(&by.name)
+        && !by.admin
+    {
+        return Err(BookingError::Forbidden(
+            "Only those on the booking and admins can change its tags".to_owned(),
+        ));
+    }
+
+    AggregateTag::retag(
+        &mut transaction,
+        agg_id,
+        &change.add,
+        &change.remove,
+        Some(by.name),
+    )
+    .await
+    .log_server_error("Unable to change the tags of the booking", true)?;
+    let tags = AggregateTag::for_aggregate(&mut transaction, agg_id)
+        .await
+        .log_db_client_error()?;
+
+    transaction.commit().await.log_db_client_error()?;
+
+    Ok(Json(tags))
+}
+
+/// Bookings tagged with the tags searched for, most recently started first unless sorted otherwise
+#[axum::debug_handler]
+pub async fn search_tags(
+    Query(search): Query<TagSearch>,
+    listing: Listing,
+) -> Result<Listed<AggregateSummary>, BookingError> {
+    let tags = search
+        .tags
+        .split(',')
+        .filter(|t| !t.trim().is_empty())
+        .map(AggregateTag::normalize)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| BookingError::BadRequest(e.to_string()))?
+        .into_iter()
+        .unique()
+        .collect_vec();
+    if tags.is_empty() || tags.len() > MAX_SEARCH_TAGS {
+        return Err(BookingError::BadRequest(format!(
+            "Search for between 1 and {MAX_SEARCH_TAGS} tags"
+        )));
+    }
+
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+    let aggregates = AggregateTag::search(&mut transaction, &tags, search.all)
+        .await
+        .log_db_client_error()?;
+    let summaries = summaries(&mut transaction, aggregates).await?;
+
+    transaction.commit().await.log_db_client_error()?;
+
+    Ok(listing.default_sort("-start").apply(summaries)?)
+}
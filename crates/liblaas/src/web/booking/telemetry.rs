@@ -0,0 +1,126 @@
+//! How busy the hosts of a booking have been, as reported by the telemetry agent
+//! installed on them if the booking asked for it
+
+use axum::{
+    extract::{Json, Path, Query},
+    http::StatusCode,
+};
+use common::prelude::{
+    chrono::{DateTime, Duration, Utc},
+    itertools::Itertools,
+    *,
+};
+use dal::{new_client, web::*, AsEasyTransaction, FKey};
+use models::dashboard::{Aggregate, Instance, TelemetrySample};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use super::BookingError;
+
+/// The most history one request can ask for
+const MAX_HOURS: u32 = 24 * 30;
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct TelemetryQuery {
+    /// Only samples from this many of the last hours, 24 if not given
+    hours: Option<u32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct TelemetryPoint {
+    #[schemars(with = "String")]
+    pub time: DateTime<Utc>,
+    pub cpu_percent: f64,
+    pub memory_percent: f64,
+    pub disk_percent: f64,
+    pub network_rx_bytes_per_sec: f64,
+    pub network_tx_bytes_per_sec: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct InstanceTelemetry {
+    pub instance: FKey<Instance>,
+    pub hostname: String,
+    /// Oldest first
+    pub samples: Vec<TelemetryPoint>,
+    /// None if the host hasn't reported anything in the window
+    pub average_cpu_percent: Option<f64>,
+    pub peak_cpu_percent: Option<f64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct BookingTelemetry {
+    /// Whether the booking asked for the agent to be installed, nothing is reported otherwise
+    pub enabled: bool,
+    pub instances: Vec<InstanceTelemetry>,
+}
+
+fn point(sample: &TelemetrySample) -> TelemetryPoint {
+    TelemetryPoint {
+        time: sample.time,
+        cpu_percent: sample.cpu_percent,
+        memory_percent: sample.memory_percent,
+        disk_percent: sample.disk_percent,
+        network_rx_bytes_per_sec: sample.network_rx_bytes_per_sec,
+        network_tx_bytes_per_sec: sample.network_tx_bytes_per_sec,
+    }
+}
+
+pub async fn booking_telemetry(
+    Path(agg_id): Path<FKey<Aggregate>>,
+    Query(query): Query<TelemetryQuery>,
+) -> Result<Json<BookingTelemetry>, BookingError> {
+    let hours = query.hours.unwrap_or(24);
+    if hours == 0 || hours > MAX_HOURS {
+        return Err(BookingError::BadRequest(format!(
+            "Telemetry can be asked for over 1 to {MAX_HOURS} hours"
+        )));
+    }
+
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+    let agg = agg_id.get(&mut transaction).await.log_error(
+        StatusCode::NOT_FOUND,
+        "Booking does not exist",
+        true,
+    )?;
+
+    let mut samples = TelemetrySample::for_aggregate(
+        &mut transaction,
+        agg_id,
+        Utc::now() - Duration::hours(hours as i64),
+    )
+    .await
+    .log_db_client_error()?
+    .into_iter()
+    .map(|s| s.into_inner())
+    .into_group_map_by(|s| s.instance);
+
+    let instances = agg
+        .instances(&mut transaction)
+        .await
+        .log_db_client_error()?
+        .into_iter()
+        .map(|instance| {
+            let samples = samples.remove(&instance.id).unwrap_or_default();
+            let cpu = samples.iter().map(|s| s.cpu_percent).collect_vec();
+
+            InstanceTelemetry {
+                instance: instance.id,
+                hostname: instance.config.hostname.clone(),
+                samples: samples.iter().map(point).collect(),
+                average_cpu_percent: (!cpu.is_empty())
+                    .then(|| cpu.iter().sum::<f64>() / cpu.len() as f64),
+                peak_cpu_percent: cpu.into_iter().reduce(f64::max),
+            }
+        })
+        .collect();
+
+    transaction.commit().await.log_db_client_error()?;
+
+    Ok(Json(BookingTelemetry {
+        enabled: agg.metadata.telemetry,
+        instances,
+    }))
+}
@@ -0,0 +1,86 @@
+//! Bookings that ended recently, kept with their instances and logs until the trash is
+//! purged of them
+//!
+//! Admins, and calls made without saying who for, see the whole trash. Anyone else only
+//! sees the bookings they owned or collaborated on.
+
+use axum::http::HeaderMap;
+use common::prelude::chrono::Utc;
+use dal::{new_client, web::*, AsEasyTransaction, FKey};
+use models::dashboard::{Aggregate, TrashedBooking};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use super::BookingError;
+use crate::web::{
+    identity::{is_admin, requesting_user},
+    listing::{Listed, Listing},
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct TrashedBookingBlob {
+    pub aggregate: FKey<Aggregate>,
+    pub booking_id: Option<String>,
+    pub display_name: Option<String>,
+    pub owner: Option<String>,
+    pub collaborators: Vec<String>,
+    pub project: Option<String>,
+    pub purpose: Option<String>,
+    pub instances: usize,
+    pub ended: String,
+    /// When the instances and logs of the booking are due to be purged
+    pub purge_after: String,
+}
+
+/// Recently ended bookings, most recently ended first unless sorted otherwise
+#[axum::debug_handler]
+pub async fn list_trash(
+    headers: HeaderMap,
+    listing: Listing,
+) -> Result<Listed<TrashedBookingBlob>, BookingError> {
+    let user = requesting_user(&headers).filter(|_| !is_admin(&headers));
+
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+    let mut blobs = Vec::new();
+    for trashed in TrashedBooking::recent(&mut transaction, Utc::now())
+        .await
+        .log_db_client_error()?
+    {
+        let agg = trashed
+            .aggregate
+            .get(&mut transaction)
+            .await
+            .log_db_client_error()?;
+
+        if let Some(user) = user {
+            if agg.metadata.owner.as_deref() != Some(user) && !agg.users.iter().any(|u| u == user) {
+                continue;
+            }
+        }
+
+        let instances = agg
+            .instances(&mut transaction)
+            .await
+            .log_db_client_error()?
+            .len();
+
+        blobs.push(TrashedBookingBlob {
+            aggregate: agg.id,
+            booking_id: agg.metadata.booking_id.clone(),
+            display_name: agg.metadata.display_name.clone(),
+            owner: agg.metadata.owner.clone(),
+            collaborators: agg.users.clone(),
+            project: agg.metadata.project.clone(),
+            purpose: agg.metadata.purpose.clone(),
+            instances,
+            ended: trashed.ended.to_rfc2822(),
+            purge_after: trashed.purge_after.to_rfc2822(),
+        });
+    }
+
+    transaction.commit().await.log_db_client_error()?;
+
+    Ok(listing.default_sort("-ended").apply(blobs)?)
+}
@@ -0,0 +1,34 @@
+//! What a booking used, for billing projects or reporting on them
+
+use axum::{
+    extract::{Json, Path},
+    http::StatusCode,
+};
+use dal::{new_client, web::*, AsEasyTransaction, FKey};
+use models::dashboard::Aggregate;
+use workflows::resource_management::usage::{self, BookingUsage};
+
+use super::BookingError;
+
+/// Host-hours by flavor, power-on time and image bandwidth of the booking, counted up to now
+/// if it's still running
+#[axum::debug_handler]
+pub async fn booking_usage(
+    Path(agg_id): Path<FKey<Aggregate>>,
+) -> Result<Json<BookingUsage>, BookingError> {
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+    agg_id.get(&mut transaction).await.log_error(
+        StatusCode::NOT_FOUND,
+        "No booking with that id",
+        true,
+    )?;
+    let usage = usage::booking_usage(&mut transaction, agg_id)
+        .await
+        .log_server_error("Unable to work out the usage of the booking", true)?;
+
+    transaction.commit().await.log_db_client_error()?;
+
+    Ok(Json(usage))
+}
@@ -0,0 +1,50 @@
+//! Capacity planning, so that hardware purchases can be backed by data
+
+use super::{identity::Admin, AppState, WebError};
+use aide::axum::{routing::post, ApiRouter};
+use axum::{extract::Json, http::StatusCode};
+use dal::{new_client, web::*, AsEasyTransaction};
+use workflows::resource_management::simulator::{run_request, SimulationReport, SimulationRequest};
+
+/// The longest a simulated booking can last or wait, a year
+const MAX_HOURS: f64 = 366.0 * 24.0;
+
+pub fn routes(_state: AppState) -> ApiRouter {
+    ApiRouter::new().route("/simulate", post(simulate))
+}
+
+#[axum::debug_handler]
+async fn simulate(
+    _admin: Admin,
+    Json(request): Json<SimulationRequest>,
+) -> Result<Json<SimulationReport>, WebError> {
+    // written so that NaN fails too
+    let bad_wait = request
+        .max_wait_hours
+        .is_some_and(|h| !(0.0..=MAX_HOURS).contains(&h));
+    let bad_duration = request
+        .bookings
+        .iter()
+        .flatten()
+        .any(|b| !(b.duration_hours > 0.0 && b.duration_hours <= MAX_HOURS));
+    if bad_wait || bad_duration {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!(
+                "Durations have to be more than 0 hours and waits can not be negative, \
+                and neither can be more than {MAX_HOURS} hours"
+            ),
+        ));
+    }
+
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+    let report = run_request(&mut transaction, request)
+        .await
+        .log_server_error("Unable to run capacity simulation", true)?;
+
+    transaction.commit().await.log_db_client_error()?;
+
+    Ok(Json(report))
+}
@@ -0,0 +1,237 @@
+//! What changed in the API since the deployment before this one
+//!
+//! Every deployment records the OpenAPI spec it serves when it starts, if it differs from
+//! the one recorded last. The changes are then worked out against the latest spec that
+//! isn't the one being served, so dashboard developers can see exactly which endpoints
+//! and schemas a deployment added, removed or changed.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::Arc,
+};
+
+use aide::{
+    axum::{routing::get, ApiRouter},
+    openapi::OpenApi,
+};
+use axum::{extract::Json, Extension};
+use common::prelude::{
+    chrono::{DateTime, Utc},
+    *,
+};
+use dal::{new_client, web::*, AsEasyTransaction, FKey, NewRow};
+use models::dashboard::ApiSpec;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::{AppState, WebError};
+
+const METHODS: [&str; 8] = [
+    "get", "put", "post", "delete", "options", "head", "patch", "trace",
+];
+
+pub fn routes(_state: AppState) -> ApiRouter {
+    ApiRouter::new().route("/changes", get(api_changes))
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct ApiChanges {
+    /// The version of liblaas the changes are since, None if no other spec was ever recorded
+    pub since_version: Option<String>,
+    /// When the spec the changes are since was first served
+    #[schemars(with = "Option<String>")]
+    pub since: Option<DateTime<Utc>>,
+    pub version: String,
+    /// Like `POST /booking/create`
+    pub added_endpoints: Vec<String>,
+    pub removed_endpoints: Vec<String>,
+    /// Endpoints that take or return something different, or are documented differently
+    pub changed_endpoints: Vec<String>,
+    pub added_schemas: Vec<String>,
+    pub removed_schemas: Vec<String>,
+    pub changed_schemas: Vec<SchemaChange>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct SchemaChange {
+    pub name: String,
+    pub added_fields: Vec<String>,
+    pub removed_fields: Vec<String>,
+    /// Fields that are there in both, but are a different type or documented differently
+    pub changed_fields: Vec<String>,
+    pub newly_required: Vec<String>,
+    pub no_longer_required: Vec<String>,
+}
+
+/// Records `spec` as what this deployment serves, unless that's what was recorded last
+pub async fn record(spec: Value) -> Result<(), anyhow::Error> {
+    let mut client = new_client().await?;
+    let mut transaction = client.easy_transaction().await?;
+
+    let latest = ApiSpec::latest(&mut transaction).await?;
+    if latest.is_some_and(|l| l.spec == spec) {
+        return Ok(());
+    }
+
+    NewRow::new(ApiSpec {
+        id: FKey::new_id_dangling(),
+        version: env!("CARGO_PKG_VERSION").to_owned(),
+        spec,
+        recorded: Utc::now(),
+    })
+    .insert(&mut transaction)
+    .await?;
+
+    transaction.commit().await?;
+
+    Ok(())
+}
+
+/// Each operation in `spec`, by method and path
+fn endpoints(spec: &Value) -> BTreeMap<String, &Value> {
+    let mut found = BTreeMap::new();
+    let Some(paths) = spec.get("paths").and_then(Value::as_object) else {
+        return found;
+    };
+    for (path, item) in paths {
+        for method in METHODS {
+            if let Some(op) = item.get(method) {
+                found.insert(format!("{} {path}", method.to_uppercase()), op);
+            }
+        }
+    }
+
+    found
+}
+
+fn schemas(spec: &Value) -> BTreeMap<String, &Value> {
+    spec.pointer("/components/schemas")
+        .and_then(Value::as_object)
+        .map(|s| s.iter().map(|(k, v)| (k.clone(), v)).collect())
+        .unwrap_or_default()
+}
+
+fn required(schema: &Value) -> BTreeSet<String> {
+    schema
+        .get("required")
+        .and_then(Value::as_array)
+        .map(|r| {
+            r.iter()
+                .filter_map(|f| f.as_str().map(str::to_owned))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// How the fields of a schema changed
+fn schema_change(name: &str, old: &Value, new: &Value) -> SchemaChange {
+    let no_fields = serde_json::Map::new();
+    let old_fields = old
+        .get("properties")
+        .and_then(Value::as_object)
+        .unwrap_or(&no_fields);
+    let new_fields = new
+        .get("properties")
+        .and_then(Value::as_object)
+        .unwrap_or(&no_fields);
+    let (old_required, new_required) = (required(old), required(new));
+
+    SchemaChange {
+        name: name.to_owned(),
+        added_fields: new_fields
+            .keys()
+            .filter(|f| !old_fields.contains_key(*f))
+            .cloned()
+            .collect(),
+        removed_fields: old_fields
+            .keys()
+            .filter(|f| !new_fields.contains_key(*f))
+            .cloned()
+            .collect(),
+        changed_fields: new_fields
+            .iter()
+            .filter(|(f, v)| old_fields.get(*f).is_some_and(|o| o != *v))
+            .map(|(f, _)| f.clone())
+            .collect(),
+        newly_required: new_required.difference(&old_required).cloned().collect(),
+        no_longer_required: old_required.difference(&new_required).cloned().collect(),
+    }
+}
+
+/// What changed going from the spec `old` to `new`
+fn diff(old: &Value, new: &Value) -> ApiChanges {
+    let (old_endpoints, new_endpoints) = (endpoints(old), endpoints(new));
+    let (old_schemas, new_schemas) = (schemas(old), schemas(new));
+
+    ApiChanges {
+        added_endpoints: new_endpoints
+            .keys()
+            .filter(|e| !old_endpoints.contains_key(*e))
+            .cloned()
+            .collect(),
+        removed_endpoints: old_endpoints
+            .keys()
+            .filter(|e| !new_endpoints.contains_key(*e))
+            .cloned()
+            .collect(),
+        changed_endpoints: new_endpoints
+            .iter()
+            .filter(|(e, op)| old_endpoints.get(*e).is_some_and(|o| o != *op))
+            .map(|(e, _)| e.clone())
+            .collect(),
+        added_schemas: new_schemas
+            .keys()
+            .filter(|s| !old_schemas.contains_key(*s))
+            .cloned()
+            .collect(),
+        removed_schemas: old_schemas
+            .keys()
+            .filter(|s| !new_schemas.contains_key(*s))
+            .cloned()
+            .collect(),
+        changed_schemas: new_schemas
+            .iter()
+            .filter_map(|(name, schema)| {
+                let old = old_schemas.get(name).filter(|o| *o != schema)?;
+                Some(schema_change(name, old, schema))
+            })
+            .collect(),
+        ..Default::default()
+    }
+}
+
+/// What changed in the API since the spec served before this one
+#[axum::debug_handler]
+pub async fn api_changes(
+    Extension(api): Extension<Arc<OpenApi>>,
+) -> Result<Json<ApiChanges>, WebError> {
+    let current = serde_json::to_value(api.as_ref())
+        .anyway()
+        .log_server_error("unable to write out the current spec", true)?;
+
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+    let before = ApiSpec::before(&mut transaction, &current)
+        .await
+        .log_db_client_error()?;
+
+    transaction.commit().await.log_db_client_error()?;
+
+    let version = env!("CARGO_PKG_VERSION").to_owned();
+    let changes = match before {
+        Some(before) => ApiChanges {
+            since_version: Some(before.version.clone()),
+            since: Some(before.recorded),
+            version,
+            ..diff(&before.spec, &current)
+        },
+        None => ApiChanges {
+            version,
+            ..Default::default()
+        },
+    };
+
+    Ok(Json(changes))
+}
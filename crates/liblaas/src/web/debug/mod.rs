@@ -0,0 +1,94 @@
+//! Dev-only endpoints for testing recovery paths, only mounted when dev mode is on
+
+use super::{AppState, WebError};
+use aide::axum::{
+    routing::{delete, get},
+    ApiRouter,
+};
+use axum::{
+    extract::{Json, Path},
+    http::StatusCode,
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tascii::faults::{self, FaultMode, InjectedFault};
+
+pub fn routes(_state: AppState) -> ApiRouter {
+    ApiRouter::new()
+        .route(
+            "/faults",
+            get(list_faults).put(inject_fault).delete(clear_faults),
+        )
+        .route("/faults/:task", delete(clear_fault))
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum FaultModeBlob {
+    Fail,
+    Hang,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct FaultBlob {
+    /// Name of the task, like `BootToNetworkTask`
+    task: String,
+    mode: FaultModeBlob,
+    /// How many runs of the task to inject into, all of them if not given
+    remaining: Option<u32>,
+}
+
+impl From<InjectedFault> for FaultBlob {
+    fn from(f: InjectedFault) -> Self {
+        Self {
+            task: f.task,
+            mode: match f.mode {
+                FaultMode::Fail => FaultModeBlob::Fail,
+                FaultMode::Hang => FaultModeBlob::Hang,
+            },
+            remaining: f.remaining,
+        }
+    }
+}
+
+#[axum::debug_handler]
+async fn list_faults() -> Json<Vec<FaultBlob>> {
+    Json(faults::active().into_iter().map(FaultBlob::from).collect())
+}
+
+#[axum::debug_handler]
+async fn inject_fault(Json(fault): Json<FaultBlob>) -> Result<Json<FaultBlob>, WebError> {
+    if fault.remaining == Some(0) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "A fault has to apply to at least one run".to_owned(),
+        ));
+    }
+
+    faults::inject(InjectedFault {
+        task: fault.task.clone(),
+        mode: match fault.mode {
+            FaultModeBlob::Fail => FaultMode::Fail,
+            FaultModeBlob::Hang => FaultMode::Hang,
+        },
+        remaining: fault.remaining,
+    });
+
+    Ok(Json(fault))
+}
+
+#[axum::debug_handler]
+async fn clear_fault(Path(task): Path<String>) -> Result<(), WebError> {
+    match faults::clear(&task) {
+        true => Ok(()),
+        false => Err((
+            StatusCode::NOT_FOUND,
+            format!("No fault is injected for {task}"),
+        )),
+    }
+}
+
+#[axum::debug_handler]
+async fn clear_faults() {
+    faults::clear_all();
+}
@@ -0,0 +1,235 @@
+//! Warning clients off endpoints and response fields that are on their way out
+//!
+//! What is deprecated comes from `web.deprecations` in the config. Responses that use
+//! something deprecated carry a `Deprecation` header (RFC 9745) with when it was
+//! deprecated, a `Sunset` header (RFC 8594) with when it stops working and a `Link` to
+//! any page about it, so clients find out while there is still time to move. Responses
+//! are only read through when a deprecated field has to be looked for in them.
+
+use axum::{
+    body::{self, Body, Bytes, HttpBody},
+    http::{header, HeaderValue, Method, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use common::prelude::{
+    chrono::{DateTime, Utc},
+    config::{self, Deprecation},
+    tracing,
+};
+use serde_json::Value;
+
+pub const DEPRECATION_HEADER: &str = "deprecation";
+pub const SUNSET_HEADER: &str = "sunset";
+
+/// The field of JSON object responses that warnings go in, for deprecations that ask for it
+const WARNINGS_FIELD: &str = "deprecation_warnings";
+
+/// Whether `path` is of the endpoint `template`, where `:name` segments match any segment
+fn path_matches(template: &str, path: &str) -> bool {
+    let template: Vec<&str> = template.trim_matches('/').split('/').collect();
+    let path: Vec<&str> = path.trim_matches('/').split('/').collect();
+
+    template.len() == path.len()
+        && template
+            .iter()
+            .zip(&path)
+            .all(|(t, p)| t.starts_with(':') || t == p)
+}
+
+/// Whether `value` has the field at `path`, where `*` is any key or array element
+fn has_field(value: &Value, path: &[&str]) -> bool {
+    let Some((key, rest)) = path.split_first() else {
+        return true;
+    };
+
+    let children: Vec<&Value> = match (value, *key) {
+        (Value::Object(o), "*") => o.values().collect(),
+        (Value::Array(a), "*") => a.iter().collect(),
+        (Value::Object(o), key) => o.get(key).into_iter().collect(),
+        (Value::Array(a), index) => index
+            .parse::<usize>()
+            .ok()
+            .and_then(|i| a.get(i))
+            .into_iter()
+            .collect(),
+        _ => Vec::new(),
+    };
+
+    children.into_iter().any(|c| has_field(c, rest))
+}
+
+fn parse_date(date: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(date)
+        .map(|d| d.with_timezone(&Utc))
+        .ok()
+}
+
+/// A deprecation that a response uses, with its dates read
+struct Hit<'a> {
+    deprecation: &'a Deprecation,
+    since: DateTime<Utc>,
+    sunset: Option<DateTime<Utc>>,
+}
+
+impl<'a> Hit<'a> {
+    fn of(deprecation: &'a Deprecation) -> Option<Self> {
+        let Some(since) = parse_date(&deprecation.since) else {
+            tracing::warn!(
+                "Deprecation of {} has a since of {} that isn't RFC 3339, so it is ignored",
+                deprecation.path,
+                deprecation.since
+            );
+            return None;
+        };
+
+        Some(Self {
+            deprecation,
+            since,
+            sunset: deprecation.sunset.as_deref().and_then(parse_date),
+        })
+    }
+
+    fn warning(&self, method: &Method) -> String {
+        let d = self.deprecation;
+        let what = match &d.field {
+            Some(field) => format!("The {field} field of {method} {}", d.path),
+            None => format!("{method} {}", d.path),
+        };
+
+        let mut warning = format!("{what} is deprecated since {}", self.since.format("%F"));
+        if let Some(sunset) = self.sunset {
+            warning.push_str(&format!(" and stops working on {}", sunset.format("%F")));
+        }
+        if let Some(note) = &d.note {
+            warning.push_str(&format!(", {note}"));
+        }
+
+        warning
+    }
+}
+
+/// A date as HTTP headers give them, like `Sun, 06 Nov 1994 08:49:37 GMT`
+fn http_date(date: DateTime<Utc>) -> String {
+    date.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+fn is_json(headers: &header::HeaderMap) -> bool {
+    headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|c| c.to_str().ok())
+        .is_some_and(|c| c.starts_with("application/json") || c.contains("+json"))
+}
+
+/// Adds deprecation headers, and warnings if asked for, to responses that use something
+/// deprecated
+pub async fn warnings(request: Request<Body>, next: Next<Body>) -> Response {
+    let method = request.method().clone();
+    let path = request.uri().path().to_owned();
+
+    let deprecations: Vec<&Deprecation> = config::settings()
+        .web
+        .deprecations
+        .iter()
+        .filter(|d| path_matches(&d.path, &path))
+        .filter(|d| {
+            d.method
+                .as_ref()
+                .map_or(true, |m| m.eq_ignore_ascii_case(method.as_str()))
+        })
+        .collect();
+
+    let response = next.run(request).await;
+    if deprecations.is_empty() {
+        return response;
+    }
+
+    let (mut parts, mut body) = response.into_parts();
+
+    let read_through = is_json(&parts.headers)
+        && deprecations
+            .iter()
+            .any(|d| d.field.is_some() || d.warn_in_body);
+    let (bytes, json) = match read_through {
+        true => {
+            let mut read = Vec::new();
+            while let Some(chunk) = body.data().await {
+                match chunk {
+                    Ok(chunk) => read.extend_from_slice(&chunk),
+                    Err(e) => {
+                        tracing::error!("Couldn't read the response to {method} {path}: {e}");
+                        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+                    }
+                }
+            }
+
+            let json = serde_json::from_slice::<Value>(&read).ok();
+            (Some(Bytes::from(read)), json)
+        }
+        false => (None, None),
+    };
+
+    let hits: Vec<Hit> = deprecations
+        .into_iter()
+        .filter(|d| match &d.field {
+            None => true,
+            Some(field) => json
+                .as_ref()
+                .is_some_and(|json| has_field(json, &field.split('.').collect::<Vec<_>>())),
+        })
+        .filter_map(Hit::of)
+        .collect();
+
+    let rebuilt = |parts, bytes: Option<Bytes>, body| match bytes {
+        Some(bytes) => Response::from_parts(parts, body::boxed(Body::from(bytes))),
+        None => Response::from_parts(parts, body),
+    };
+    if hits.is_empty() {
+        return rebuilt(parts, bytes, body);
+    }
+
+    let warnings: Vec<String> = hits.iter().map(|h| h.warning(&method)).collect();
+    tracing::info!(
+        "{method} {path} used something deprecated: {}",
+        warnings.join("; ")
+    );
+
+    let headers = &mut parts.headers;
+    if let Some(since) = hits.iter().map(|h| h.since).min() {
+        if let Ok(value) = HeaderValue::from_str(&format!("@{}", since.timestamp())) {
+            headers.insert(DEPRECATION_HEADER, value);
+        }
+    }
+    if let Some(sunset) = hits.iter().filter_map(|h| h.sunset).min() {
+        if let Ok(value) = HeaderValue::from_str(&http_date(sunset)) {
+            headers.insert(SUNSET_HEADER, value);
+        }
+    }
+    for link in hits.iter().filter_map(|h| h.deprecation.link.as_ref()) {
+        if let Ok(value) = HeaderValue::from_str(&format!(
+            "<{link}>; rel=\"deprecation\"; type=\"text/html\""
+        )) {
+            headers.append(header::LINK, value);
+        }
+    }
+
+    let in_body: Vec<Value> = hits
+        .iter()
+        .zip(&warnings)
+        .filter(|(h, _)| h.deprecation.warn_in_body)
+        .map(|(_, w)| Value::String(w.clone()))
+        .collect();
+    if let (false, Some(Value::Object(mut object))) = (in_body.is_empty(), json) {
+        object.insert(WARNINGS_FIELD.to_owned(), Value::Array(in_body));
+
+        match serde_json::to_vec(&object) {
+            Ok(warned) => {
+                parts.headers.remove(header::CONTENT_LENGTH);
+                return rebuilt(parts, Some(Bytes::from(warned)), body);
+            }
+            Err(e) => tracing::error!("Couldn't add deprecation warnings to {path}: {e}"),
+        }
+    }
+
+    rebuilt(parts, bytes, body)
+}
@@ -0,0 +1,299 @@
+//! Signed links to download generated artifacts with, for instances and users that have
+//! no access to the rest of the API
+//!
+//! Only the owner of the booking an artifact is of, its collaborators and admins can ask for
+//! a link to it. A link names the artifact, when it was issued and when it expires, all
+//! signed with `web.downloads.signing_key` so none of it can be changed. Nothing is kept
+//! about a link, so on its own it works until it expires, but no link to an artifact of a
+//! booking works once the booking has ended.
+
+use aide::axum::{
+    routing::{get, post},
+    ApiRouter,
+};
+use axum::{
+    extract::{Json, Path, Query},
+    http::{header, StatusCode},
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use common::prelude::{
+    chrono::{DateTime, Duration, Utc},
+    config::settings,
+    lazy_static::lazy_static,
+    rand, tracing,
+};
+use dal::{new_client, web::*, AsEasyTransaction, EasyTransaction, FKey};
+use hmac::{Hmac, Mac};
+use models::dashboard::{Aggregate, BookingEvent, BookingEventKind, FailureBundle, Instance};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use uuid::Uuid;
+use workflows::deploy_booking::{failure_bundle, generate_cloud_config};
+
+use super::{identity::User, AppState, WebError};
+
+lazy_static! {
+    static ref SIGNING_KEY: Vec<u8> = match &settings().web.downloads.signing_key {
+        Some(key) => key.as_bytes().to_vec(),
+        None => {
+            tracing::warn!(
+                "No signing key is configured for download links, so links won't survive a restart"
+            );
+            rand::random::<[u8; 32]>().to_vec()
+        }
+    };
+}
+
+pub fn routes(_state: AppState) -> ApiRouter {
+    ApiRouter::new()
+        .route("/sign", post(sign_link))
+        .route("/:kind/:id", get(download))
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, JsonSchema, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ArtifactKind {
+    /// What was gathered about a failed deploy, as mailed to the admins, by bundle id
+    FailureBundle,
+    /// The cloud-config generated for an instance, by instance id
+    CloudConfig,
+}
+
+impl ArtifactKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            ArtifactKind::FailureBundle => "failure-bundle",
+            ArtifactKind::CloudConfig => "cloud-config",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct SignRequest {
+    kind: ArtifactKind,
+    id: Uuid,
+    /// How long the link works for, `web.downloads.default_ttl_minutes` if not given
+    ttl_minutes: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct SignedLink {
+    url: String,
+    #[schemars(with = "String")]
+    expires: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct DownloadQuery {
+    /// When the link was issued, in seconds since the epoch
+    issued: i64,
+    /// When the link expires, in seconds since the epoch
+    expires: i64,
+    signature: String,
+}
+
+fn mac(kind: ArtifactKind, id: Uuid, issued: i64, expires: i64) -> Hmac<Sha256> {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(&SIGNING_KEY).expect("HMAC takes keys of any length");
+    mac.update(format!("{}/{id}/{issued}/{expires}", kind.as_str()).as_bytes());
+
+    mac
+}
+
+fn not_found(what: &str) -> WebError {
+    (StatusCode::NOT_FOUND, format!("{what} does not exist"))
+}
+
+/// The booking the artifact is of
+async fn aggregate_of(
+    t: &mut EasyTransaction<'_>,
+    kind: ArtifactKind,
+    id: Uuid,
+) -> Result<FKey<Aggregate>, WebError> {
+    match kind {
+        ArtifactKind::FailureBundle => FKey::<FailureBundle>::from_id(id.into())
+            .get(t)
+            .await
+            .map(|b| b.aggregate)
+            .map_err(|_| not_found("Failure bundle")),
+        ArtifactKind::CloudConfig => FKey::<Instance>::from_id(id.into())
+            .get(t)
+            .await
+            .map(|i| i.aggregate)
+            .map_err(|_| not_found("Instance")),
+    }
+}
+
+/// Whether the booking has ended, after which no link to its artifacts works
+async fn has_ended(t: &mut EasyTransaction<'_>, agg: FKey<Aggregate>) -> Result<bool, WebError> {
+    Ok(BookingEvent::for_aggregate(t, agg)
+        .await
+        .log_db_client_error()?
+        .iter()
+        .any(|e| e.kind == BookingEventKind::Ended))
+}
+
+fn booking_ended() -> WebError {
+    (
+        StatusCode::GONE,
+        "Links to this stopped working when the booking ended".to_owned(),
+    )
+}
+
+/// The file name and content of the artifact
+async fn render(
+    t: &mut EasyTransaction<'_>,
+    kind: ArtifactKind,
+    id: Uuid,
+) -> Result<(String, String), WebError> {
+    match kind {
+        ArtifactKind::FailureBundle => {
+            let bundle = FKey::<FailureBundle>::from_id(id.into())
+                .get(t)
+                .await
+                .map_err(|_| not_found("Failure bundle"))?;
+            let server_name = bundle
+                .host
+                .get(t)
+                .await
+                .log_db_client_error()?
+                .server_name
+                .clone();
+
+            Ok((
+                format!("{server_name}-failure.txt"),
+                failure_bundle::render(&bundle, &server_name),
+            ))
+        }
+        ArtifactKind::CloudConfig => {
+            let instance = FKey::<Instance>::from_id(id.into())
+                .get(t)
+                .await
+                .map_err(|_| not_found("Instance"))?;
+            let host = instance.linked_host.ok_or((
+                StatusCode::NOT_FOUND,
+                "The instance hasn't been given a host yet".to_owned(),
+            ))?;
+
+            let cloud_config = generate_cloud_config(
+                instance.config.clone(),
+                host,
+                instance.id,
+                instance.aggregate,
+                t,
+            )
+            .await
+            .log_server_error("Unable to generate the cloud-config", true)?;
+
+            Ok((
+                format!("{}-cloud-config.yaml", instance.config.hostname),
+                cloud_config,
+            ))
+        }
+    }
+}
+
+/// A link to download the artifact with, that works without API access until it expires
+/// or the booking ends
+async fn sign_link(
+    by: User,
+    Json(request): Json<SignRequest>,
+) -> Result<Json<SignedLink>, WebError> {
+    let config = &settings().web.downloads;
+    let ttl = request.ttl_minutes.unwrap_or(config.default_ttl_minutes);
+    if ttl == 0 || ttl > config.max_ttl_minutes {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!(
+                "Links can work for between 1 and {} minutes",
+                config.max_ttl_minutes
+            ),
+        ));
+    }
+
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+    let agg = aggregate_of(&mut transaction, request.kind, request.id)
+        .await?
+        .get(&mut transaction)
+        .await
+        .log_db_client_error()?;
+
+    if agg.metadata.owner.as_deref() != Some(by.name.as_str())
+        && !agg.users.contains(&by.name)
+        && !by.admin
+    {
+        return Err((
+            StatusCode::FORBIDDEN,
+            "Only those on the booking and admins can get links to its artifacts".to_owned(),
+        ));
+    }
+
+    if has_ended(&mut transaction, agg.id).await? {
+        return Err(booking_ended());
+    }
+
+    transaction.commit().await.log_db_client_error()?;
+
+    let issued_at = Utc::now();
+    let expires_at = issued_at + Duration::minutes(ttl as i64);
+    let (issued, expires) = (issued_at.timestamp(), expires_at.timestamp());
+
+    let signature = URL_SAFE_NO_PAD.encode(
+        mac(request.kind, request.id, issued, expires)
+            .finalize()
+            .into_bytes(),
+    );
+
+    Ok(Json(SignedLink {
+        url: format!(
+            "{}/download/{}/{}?issued={issued}&expires={expires}&signature={signature}",
+            settings().web.external_url.trim_end_matches('/'),
+            request.kind.as_str(),
+            request.id
+        ),
+        expires: expires_at,
+    }))
+}
+
+/// The artifact a signed link is for
+async fn download(
+    Path((kind, id)): Path<(ArtifactKind, Uuid)>,
+    Query(query): Query<DownloadQuery>,
+) -> Result<([(header::HeaderName, String); 2], String), WebError> {
+    let invalid = (StatusCode::FORBIDDEN, "This link isn't valid".to_owned());
+    let signature = URL_SAFE_NO_PAD
+        .decode(&query.signature)
+        .map_err(|_| invalid.clone())?;
+    mac(kind, id, query.issued, query.expires)
+        .verify_slice(&signature)
+        .map_err(|_| invalid)?;
+
+    if Utc::now().timestamp() >= query.expires {
+        return Err((StatusCode::GONE, "This link has expired".to_owned()));
+    }
+
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+    let agg = aggregate_of(&mut transaction, kind, id).await?;
+    if has_ended(&mut transaction, agg).await? {
+        return Err(booking_ended());
+    }
+
+    let (filename, content) = render(&mut transaction, kind, id).await?;
+
+    transaction.commit().await.log_db_client_error()?;
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, "text/plain; charset=utf-8".to_owned()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{filename}\""),
+            ),
+        ],
+        content,
+    ))
+}
@@ -0,0 +1,287 @@
+//! Exports booking and inventory history for the lab's data warehouse
+//!
+//! Snapshots are JSONL: the first line is a header describing the export,
+//! and every line after it is one `{"table": ..., "row": ...}` record.
+//! Credentials and user access lists are never exported. Only admins can take
+//! snapshots, and they are streamed out as the rows are read rather than built up
+//! in memory first.
+
+use super::{identity::Admin, AppState, WebError};
+use aide::axum::{routing::get, ApiRouter};
+use axum::{
+    body::StreamBody,
+    extract::Query,
+    http::header,
+    response::{IntoResponse, Response},
+};
+use common::prelude::{
+    chrono::{DateTime, Utc},
+    tokio::sync::mpsc,
+    *,
+};
+use dal::{web::*, *};
+use models::{
+    allocator::Allocation,
+    dashboard::{Aggregate, BookingEvent, Instance, IsolationAttestation, ProvisionLogEvent},
+    inventory::{Flavor, Host, HostBenchmark, Lab},
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+/// Bumped whenever the shape of exported rows changes incompatibly
+pub const EXPORT_VERSION: u32 = 1;
+
+pub fn routes(_state: AppState) -> ApiRouter {
+    ApiRouter::new().route("/snapshot", get(export_snapshot))
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct SnapshotParams {
+    /// Only export history rows that changed at or after this time,
+    /// inventory tables are always exported in full (RFC 3339)
+    #[schemars(with = "Option<String>")]
+    since: Option<DateTime<Utc>>,
+}
+
+/// Tables whose rows are filtered by `since`, and the expression compared against it
+const INCREMENTAL: [(&str, &str); 5] = [
+    ("allocations", "COALESCE(ended, started)"),
+    ("provision_log_events", "time"),
+    ("booking_events", "time"),
+    ("host_benchmarks", "recorded"),
+    ("isolation_attestations", "time"),
+];
+
+async fn rows_since<T: DBTable>(
+    t: &mut EasyTransaction<'_>,
+    since: Option<DateTime<Utc>>,
+) -> Result<Vec<ExistingRow<T>>, anyhow::Error> {
+    let tn = T::table_name();
+    let column = INCREMENTAL
+        .iter()
+        .find(|(table, _)| *table == tn)
+        .map(|(_, column)| *column);
+
+    let rows = match (since, column) {
+        (Some(since), Some(column)) => {
+            let q = format!("SELECT * FROM {tn} WHERE {column} >= $1;");
+            t.query(&q, &[&since]).await.anyway()?
+        }
+        _ => {
+            let q = format!("SELECT * FROM {tn};");
+            t.query(&q, &[]).await.anyway()?
+        }
+    };
+
+    T::from_rows(rows)
+}
+
+/// How many lines can be read ahead of what has been sent to the client
+const LINES_BUFFERED: usize = 256;
+
+/// Where the lines of a snapshot go as they are made
+struct Snapshot {
+    lines: mpsc::Sender<Result<String, std::io::Error>>,
+    rows: usize,
+}
+
+impl Snapshot {
+    async fn send(&mut self, line: String) -> Result<(), anyhow::Error> {
+        self.lines
+            .send(Ok(line + "\n"))
+            .await
+            .map_err(|_| anyhow::Error::msg("the client stopped reading the snapshot"))
+    }
+
+    async fn push(&mut self, table: &str, row: Value) -> Result<(), anyhow::Error> {
+        self.rows += 1;
+        self.send(json!({ "table": table, "row": row }).to_string())
+            .await
+    }
+}
+
+async fn build_snapshot(
+    t: &mut EasyTransaction<'_>,
+    since: Option<DateTime<Utc>>,
+    snapshot: &mut Snapshot,
+) -> Result<(), anyhow::Error> {
+    // every table has to be read from the same point in time for the export to be consistent
+    t.execute(
+        "SET TRANSACTION ISOLATION LEVEL REPEATABLE READ, READ ONLY;",
+        &[],
+    )
+    .await
+    .anyway()?;
+
+    let header = json!({
+        "export_version": EXPORT_VERSION,
+        "generated": Utc::now(),
+        "since": since,
+        "incremental_tables": INCREMENTAL.iter().map(|(t, _)| *t).collect::<Vec<_>>(),
+    });
+
+    snapshot.send(header.to_string()).await?;
+
+    for lab in rows_since::<Lab>(t, since).await? {
+        snapshot
+            .push(
+                Lab::table_name(),
+                json!({ "id": lab.id, "name": lab.name, "location": lab.location }),
+            )
+            .await?;
+    }
+
+    for flavor in rows_since::<Flavor>(t, since).await? {
+        snapshot
+            .push(Flavor::table_name(), serde_json::to_value(&*flavor)?)
+            .await?;
+    }
+
+    for host in rows_since::<Host>(t, since).await? {
+        snapshot
+            .push(
+                Host::table_name(),
+                json!({
+                    "id": host.id,
+                    "server_name": host.server_name,
+                    "arch": host.arch,
+                    "flavor": host.flavor,
+                    "serial": host.serial,
+                    "projects": host.projects,
+                    "failure_domain": host.failure_domain,
+                }),
+            )
+            .await?;
+    }
+
+    for agg in rows_since::<Aggregate>(t, since).await? {
+        let m = &agg.metadata;
+        snapshot
+            .push(
+                Aggregate::table_name(),
+                json!({
+                    "id": agg.id,
+                    "deleted": agg.deleted,
+                    "state": agg.state,
+                    "lab": agg.lab,
+                    "template": agg.template,
+                    "booking_id": m.booking_id,
+                    "owner": m.owner,
+                    "purpose": m.purpose,
+                    "project": m.project,
+                    "start": m.start,
+                    "end": m.end,
+                }),
+            )
+            .await?;
+    }
+
+    for inst in rows_since::<Instance>(t, since).await? {
+        snapshot
+            .push(
+                Instance::table_name(),
+                json!({
+                    "id": inst.id,
+                    "aggregate": inst.aggregate,
+                    "within_template": inst.within_template,
+                    "linked_host": inst.linked_host,
+                    "hostname": inst.config.hostname,
+                    "flavor": inst.config.flavor,
+                    "image": inst.config.image,
+                }),
+            )
+            .await?;
+    }
+
+    for allocation in rows_since::<Allocation>(t, since).await? {
+        snapshot
+            .push(
+                Allocation::table_name(),
+                serde_json::to_value(&*allocation)?,
+            )
+            .await?;
+    }
+
+    for event in rows_since::<ProvisionLogEvent>(t, since).await? {
+        snapshot
+            .push(
+                ProvisionLogEvent::table_name(),
+                serde_json::to_value(&*event)?,
+            )
+            .await?;
+    }
+
+    for event in rows_since::<BookingEvent>(t, since).await? {
+        snapshot
+            .push(BookingEvent::table_name(), serde_json::to_value(&*event)?)
+            .await?;
+    }
+
+    for benchmark in rows_since::<HostBenchmark>(t, since).await? {
+        snapshot
+            .push(
+                HostBenchmark::table_name(),
+                serde_json::to_value(&*benchmark)?,
+            )
+            .await?;
+    }
+
+    for attestation in rows_since::<IsolationAttestation>(t, since).await? {
+        snapshot
+            .push(
+                IsolationAttestation::table_name(),
+                serde_json::to_value(&*attestation)?,
+            )
+            .await?;
+    }
+
+    Ok(())
+}
+
+#[axum::debug_handler]
+async fn export_snapshot(
+    _admin: Admin,
+    Query(params): Query<SnapshotParams>,
+) -> Result<Response, WebError> {
+    let mut client = new_client().await.log_db_client_error()?;
+    let (lines, received) = mpsc::channel(LINES_BUFFERED);
+
+    tokio::spawn(async move {
+        let mut snapshot = Snapshot {
+            lines: lines.clone(),
+            rows: 0,
+        };
+        let exported = async {
+            let mut transaction = client.easy_transaction().await?;
+            build_snapshot(&mut transaction, params.since, &mut snapshot).await?;
+            transaction.commit().await?;
+
+            Ok::<_, anyhow::Error>(())
+        };
+
+        match exported.await {
+            Ok(()) => tracing::info!("Exported snapshot of {} rows", snapshot.rows),
+            Err(e) => {
+                tracing::error!("Unable to build export snapshot: {e:?}");
+                // cuts the response off, so the client can't take what it got for the whole export
+                let _ = lines
+                    .send(Err(std::io::Error::new(
+                        std::io::ErrorKind::Other,
+                        "the snapshot couldn't be finished",
+                    )))
+                    .await;
+            }
+        }
+    });
+
+    let body = futures::stream::unfold(received, |mut received| async move {
+        received.recv().await.map(|line| (line, received))
+    });
+
+    Ok((
+        [(header::CONTENT_TYPE, "application/x-ndjson")],
+        StreamBody::new(body),
+    )
+        .into_response())
+}
@@ -7,11 +7,16 @@ use super::{
     AppState,
 };
 use crate::web::{
-    api::{AggregateDescription, AllocationBlob, HostBlob, ImageBlob},
+    api::{AggregateDescription, AllocationBlob, HostBlob, ImageBlob, ImageDetailBlob},
+    identity::Admin,
+    listing::{Listed, Listing},
     WebError,
 };
 use aide::{
-    axum::{routing::get, ApiRouter},
+    axum::{
+        routing::{get, post},
+        ApiRouter,
+    },
     transform::TransformOperation,
 };
 use axum::{
@@ -22,15 +27,25 @@ use common::prelude::{itertools::Itertools, *};
 use dal::{web::*, *};
 use models::{
     allocator::{Allocation, AllocationReason, ResourceHandle},
-    dashboard::Image,
-    inventory::{Flavor, Host, Lab},
+    dashboard::{Image, Template},
+    inventory::{
+        host::benchmark, Arch, DataValue, ExtraFlavorInfo, Flavor, FlavorCapabilities, Host,
+        HostBenchmark, HostPort, InterfaceFlavor, Lab,
+    },
 };
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
-use std::collections::HashMap;
+use std::{
+    collections::{HashMap, HashSet},
+    str::FromStr,
+};
 use uuid::Uuid;
-use workflows::resource_management::allocator;
+use workflows::resource_management::{
+    allocator,
+    flavors::{self, CompatibilityMatrix, FlavorUsage},
+    images,
+};
 
 #[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
 pub struct FlavorResponse {
@@ -137,14 +152,15 @@ async fn build_flavor_blobs(
                 })
                 .collect();
 
-            let images: Vec<_> = Image::images_for_flavor(transaction, f.id, None)
+            let (images, default_image) = images::project_images(transaction, &lab_name, &f)
                 .await
                 .map_err(|e| {
                     (
                         StatusCode::INTERNAL_SERVER_ERROR,
                         format!("Failed to get images: {e}"),
                     )
-                })?
+                })?;
+            let images: Vec<_> = images
                 .into_iter()
                 .map(|img| ImageBlob {
                     image_id: img.id,
@@ -152,12 +168,28 @@ async fn build_flavor_blobs(
                 })
                 .collect();
 
+            let scores = HostBenchmark::latest_for_flavor(transaction, f.id)
+                .await
+                .map_err(|e| {
+                    (
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        format!("Failed to get benchmarks: {e}"),
+                    )
+                })?
+                .into_iter()
+                .map(|b| b.score)
+                .collect_vec();
+
             fbs.push(FlavorBlob {
                 flavor_id: f.id,
                 name: f.name,
                 interfaces,
                 images,
+                default_image,
                 available_count: available_count.get(&f.id).copied().unwrap_or(0),
+                benchmark_median: benchmark::median(&scores),
+                benchmark_mean: (!scores.is_empty())
+                    .then(|| scores.iter().sum::<f64>() / scores.len() as f64),
                 cpu_count: f.cpu_count,
                 ram: f.ram,
                 root_size: f.root_size,
@@ -213,7 +245,8 @@ pub struct ListFlavorsRequest {
 /// List hosts, filtering to only hosts for the given project (dashboard)
 pub async fn list_hosts(
     Path(lab_name): Path<String>,
-) -> Result<Json<Vec<api::HostBlob>>, WebError> {
+    listing: Listing,
+) -> Result<Listed<api::HostBlob>, WebError> {
     tracing::info!("API call to list_hosts()");
     let mut client = new_client().await.log_db_client_error()?;
     let mut transaction = client.easy_transaction().await.log_db_client_error()?;
@@ -295,6 +328,11 @@ pub async fn list_hosts(
             None
         };
 
+        let benchmark_score = HostBenchmark::latest_for_host(&mut transaction, host.id)
+            .await
+            .log_server_error("Failed to retrieve host benchmark", true)?
+            .map(|b| b.score);
+
         let hb = HostBlob {
             id: Some(host.id),
             name: host.server_name,
@@ -302,6 +340,7 @@ pub async fn list_hosts(
             flavor: host.flavor,
             ipmi_fqdn: host.ipmi_fqdn,
             allocation,
+            benchmark_score,
         };
 
         blobs.push(hb);
@@ -309,11 +348,477 @@ pub async fn list_hosts(
 
     transaction.commit().await.log_db_client_error()?;
 
-    Ok(Json(blobs))
+    listing.apply(blobs)
+}
+
+/// List the images members of the project may book with
+pub async fn list_images(
+    Path(lab_name): Path<String>,
+    listing: Listing,
+) -> Result<Listed<ImageDetailBlob>, WebError> {
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+    let images = images::allowed_images(&mut transaction, &lab_name)
+        .await
+        .log_db_client_error()?;
+
+    transaction.commit().await.log_db_client_error()?;
+
+    listing.apply(
+        images
+            .into_iter()
+            .map(|i| ImageDetailBlob {
+                image_id: i.id,
+                name: i.name,
+                flavors: i.flavors,
+                deprecated: i.deprecated,
+                sunset: i.sunset,
+                replacement: i.replacement,
+            })
+            .collect(),
+    )
+}
+
+/// A flavor as admins create or update it
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
+pub struct FlavorRequest {
+    pub name: String,
+    pub arch: String,
+    pub public: bool,
+    pub cpu_count: usize,
+    pub ram: DataValue,
+    pub root_size: DataValue,
+    pub disk_size: DataValue,
+    pub swap_size: DataValue,
+    pub brand: String,
+    pub model: String,
+    #[serde(default)]
+    pub capabilities: FlavorCapabilities,
+    /// Interfaces are matched by name, so renaming one replaces it
+    #[serde(default)]
+    pub interfaces: Vec<InterfaceBlob>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
+pub struct FlavorRecord {
+    pub id: FKey<Flavor>,
+    pub name: String,
+    pub arch: String,
+    pub public: bool,
+    pub cpu_count: usize,
+    pub ram: DataValue,
+    pub root_size: DataValue,
+    pub disk_size: DataValue,
+    pub swap_size: DataValue,
+    pub brand: String,
+    pub model: String,
+    pub capabilities: FlavorCapabilities,
+    pub interfaces: Vec<InterfaceBlob>,
+    pub usage: FlavorUsage,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
+pub struct FlavorUsageEntry {
+    pub flavor: FKey<Flavor>,
+    pub name: String,
+    pub usage: FlavorUsage,
+}
+
+fn check_request(request: &FlavorRequest) -> Result<Arch, WebError> {
+    if request.name.trim().is_empty() {
+        return Err((StatusCode::BAD_REQUEST, "A flavor needs a name".to_owned()));
+    }
+
+    let arch = Arch::from_str(&request.arch).map_err(|_| {
+        (
+            StatusCode::BAD_REQUEST,
+            format!("{} is not an architecture", request.arch),
+        )
+    })?;
+
+    let mut names = HashSet::new();
+    for iface in request.interfaces.iter() {
+        if iface.name.trim().is_empty() {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                "Every interface needs a name".to_owned(),
+            ));
+        }
+        if !names.insert(iface.name.as_str()) {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                format!("Interface {} is given more than once", iface.name),
+            ));
+        }
+    }
+
+    Ok(arch)
+}
+
+async fn check_name_free(
+    transaction: &mut EasyTransaction<'_>,
+    name: &str,
+    except: Option<FKey<Flavor>>,
+) -> Result<(), WebError> {
+    let taken = Flavor::select()
+        .where_field("name")
+        .equals(name.to_owned())
+        .run(transaction)
+        .await
+        .log_db_client_error()?
+        .into_iter()
+        .any(|f| Some(f.id) != except);
+
+    match taken {
+        true => Err((
+            StatusCode::CONFLICT,
+            format!("A flavor named {name} already exists"),
+        )),
+        false => Ok(()),
+    }
+}
+
+/// Makes the interfaces of the flavor match `wanted`, refusing to remove any that hosts still have
+async fn sync_interfaces(
+    transaction: &mut EasyTransaction<'_>,
+    flavor: FKey<Flavor>,
+    wanted: &[InterfaceBlob],
+) -> Result<(), WebError> {
+    let existing = InterfaceFlavor::all_for_flavor(transaction, flavor)
+        .await
+        .log_db_client_error()?;
+
+    for mut iface in existing {
+        match wanted.iter().find(|w| w.name == iface.name) {
+            Some(w) => {
+                iface.speed = w.speed;
+                iface.cardtype = w.cardtype;
+                iface.update(transaction).await.log_db_client_error()?;
+            }
+            None => {
+                let in_use = !HostPort::select()
+                    .where_field("is_a")
+                    .equals(iface.id)
+                    .run(transaction)
+                    .await
+                    .log_db_client_error()?
+                    .is_empty();
+
+                if in_use {
+                    return Err((
+                        StatusCode::CONFLICT,
+                        format!("Interface {} is still on hosts of this flavor", iface.name),
+                    ));
+                }
+
+                iface.delete(transaction).await.log_db_client_error()?;
+            }
+        }
+    }
+
+    let existing: HashSet<String> = InterfaceFlavor::all_for_flavor(transaction, flavor)
+        .await
+        .log_db_client_error()?
+        .into_iter()
+        .map(|i| i.name.clone())
+        .collect();
+
+    for w in wanted.iter().filter(|w| !existing.contains(&w.name)) {
+        NewRow::new(InterfaceFlavor {
+            id: FKey::new_id_dangling(),
+            on_flavor: flavor,
+            name: w.name.clone(),
+            speed: w.speed,
+            cardtype: w.cardtype,
+        })
+        .insert(transaction)
+        .await
+        .log_db_client_error()?;
+    }
+
+    Ok(())
+}
+
+async fn flavor_record(
+    transaction: &mut EasyTransaction<'_>,
+    flavor: Flavor,
+) -> Result<FlavorRecord, WebError> {
+    let interfaces = flavor
+        .ports(transaction)
+        .await
+        .log_db_client_error()?
+        .into_iter()
+        .map(|er| {
+            let iface = er.into_inner();
+            InterfaceBlob {
+                name: iface.name,
+                speed: iface.speed,
+                cardtype: iface.cardtype,
+            }
+        })
+        .collect();
+
+    let usage = flavors::usage(transaction, flavor.id)
+        .await
+        .log_server_error("Unable to work out flavor usage", true)?;
+
+    Ok(FlavorRecord {
+        id: flavor.id,
+        name: flavor.name,
+        arch: flavor.arch.to_string(),
+        public: flavor.public,
+        cpu_count: flavor.cpu_count,
+        ram: flavor.ram,
+        root_size: flavor.root_size,
+        disk_size: flavor.disk_size,
+        swap_size: flavor.swap_size,
+        brand: flavor.brand,
+        model: flavor.model,
+        capabilities: flavor.capabilities,
+        interfaces,
+        usage,
+    })
+}
+
+async fn create_flavor(
+    _admin: Admin,
+    Json(request): Json<FlavorRequest>,
+) -> Result<Json<FlavorRecord>, WebError> {
+    let arch = check_request(&request)?;
+
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+    check_name_free(&mut transaction, &request.name, None).await?;
+
+    let id = NewRow::new(Flavor {
+        id: FKey::new_id_dangling(),
+        arch,
+        name: request.name,
+        public: request.public,
+        cpu_count: request.cpu_count,
+        ram: request.ram,
+        root_size: request.root_size,
+        disk_size: request.disk_size,
+        swap_size: request.swap_size,
+        brand: request.brand,
+        model: request.model,
+        capabilities: request.capabilities,
+    })
+    .insert(&mut transaction)
+    .await
+    .log_db_client_error()?;
+
+    sync_interfaces(&mut transaction, id, &request.interfaces).await?;
+
+    let flavor = id
+        .get(&mut transaction)
+        .await
+        .log_db_client_error()?
+        .into_inner();
+    let record = flavor_record(&mut transaction, flavor).await?;
+
+    transaction.commit().await.log_db_client_error()?;
+
+    Ok(Json(record))
+}
+
+async fn get_flavor(Path(flavor_id): Path<FKey<Flavor>>) -> Result<Json<FlavorRecord>, WebError> {
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+    let flavor = flavor_id
+        .get(&mut transaction)
+        .await
+        .map_err(|_| (StatusCode::NOT_FOUND, "No such flavor".to_owned()))?
+        .into_inner();
+    let record = flavor_record(&mut transaction, flavor).await?;
+
+    transaction.commit().await.log_db_client_error()?;
+
+    Ok(Json(record))
+}
+
+async fn update_flavor(
+    _admin: Admin,
+    Path(flavor_id): Path<FKey<Flavor>>,
+    Json(request): Json<FlavorRequest>,
+) -> Result<Json<FlavorRecord>, WebError> {
+    let arch = check_request(&request)?;
+
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+    let mut flavor = flavor_id
+        .get(&mut transaction)
+        .await
+        .map_err(|_| (StatusCode::NOT_FOUND, "No such flavor".to_owned()))?;
+
+    check_name_free(&mut transaction, &request.name, Some(flavor_id)).await?;
+
+    flavor.arch = arch;
+    flavor.name = request.name;
+    flavor.public = request.public;
+    flavor.cpu_count = request.cpu_count;
+    flavor.ram = request.ram;
+    flavor.root_size = request.root_size;
+    flavor.disk_size = request.disk_size;
+    flavor.swap_size = request.swap_size;
+    flavor.brand = request.brand;
+    flavor.model = request.model;
+    flavor.capabilities = request.capabilities;
+    flavor
+        .update(&mut transaction)
+        .await
+        .log_db_client_error()?;
+
+    sync_interfaces(&mut transaction, flavor_id, &request.interfaces).await?;
+
+    let record = flavor_record(&mut transaction, flavor.into_inner()).await?;
+
+    transaction.commit().await.log_db_client_error()?;
+
+    Ok(Json(record))
+}
+
+/// Only flavors that no host or live template uses can be deleted
+async fn delete_flavor(_admin: Admin, Path(flavor_id): Path<FKey<Flavor>>) -> Result<(), WebError> {
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+    let flavor = flavor_id
+        .get(&mut transaction)
+        .await
+        .map_err(|_| (StatusCode::NOT_FOUND, "No such flavor".to_owned()))?;
+
+    let hosts = Host::select()
+        .where_field("flavor")
+        .equals(flavor_id)
+        .run(&mut transaction)
+        .await
+        .log_db_client_error()?;
+    if !hosts.is_empty() {
+        return Err((
+            StatusCode::CONFLICT,
+            format!(
+                "{} is still the flavor of {} host(s), retire or move them first",
+                flavor.name,
+                hosts.len()
+            ),
+        ));
+    }
+
+    let templates = Template::select()
+        .where_field("deleted")
+        .equals(false)
+        .run(&mut transaction)
+        .await
+        .log_db_client_error()?
+        .into_iter()
+        .filter(|t| t.hosts.iter().any(|h| h.flavor == flavor_id))
+        .map(|t| t.name.clone())
+        .collect_vec();
+    if !templates.is_empty() {
+        return Err((
+            StatusCode::CONFLICT,
+            format!(
+                "{} is still used by templates {}",
+                flavor.name,
+                templates.join(", ")
+            ),
+        ));
+    }
+
+    for mut image in Image::select()
+        .run(&mut transaction)
+        .await
+        .log_db_client_error()?
+    {
+        if image.flavors.contains(&flavor_id) {
+            image.flavors.retain(|f| *f != flavor_id);
+            image.update(&mut transaction).await.log_db_client_error()?;
+        }
+    }
+
+    for info in ExtraFlavorInfo::select()
+        .where_field("for_flavor")
+        .equals(flavor_id)
+        .run(&mut transaction)
+        .await
+        .log_db_client_error()?
+    {
+        info.delete(&mut transaction).await.log_db_client_error()?;
+    }
+
+    for iface in InterfaceFlavor::all_for_flavor(&mut transaction, flavor_id)
+        .await
+        .log_db_client_error()?
+    {
+        iface.delete(&mut transaction).await.log_db_client_error()?;
+    }
+
+    flavor
+        .delete(&mut transaction)
+        .await
+        .log_db_client_error()?;
+
+    transaction.commit().await.log_db_client_error()?;
+
+    Ok(())
+}
+
+/// Which images run on which flavors
+async fn flavor_matrix() -> Result<Json<CompatibilityMatrix>, WebError> {
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+    let matrix = flavors::compatibility(&mut transaction)
+        .await
+        .log_server_error("Unable to build the compatibility matrix", true)?;
+
+    transaction.commit().await.log_db_client_error()?;
+
+    Ok(Json(matrix))
+}
+
+/// How much every flavor has been used, least used first
+async fn flavor_usage() -> Result<Json<Vec<FlavorUsageEntry>>, WebError> {
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+    let mut entries = Vec::new();
+    for flavor in Flavor::select()
+        .run(&mut transaction)
+        .await
+        .log_db_client_error()?
+    {
+        entries.push(FlavorUsageEntry {
+            flavor: flavor.id,
+            name: flavor.name.clone(),
+            usage: flavors::usage(&mut transaction, flavor.id)
+                .await
+                .log_server_error("Unable to work out flavor usage", true)?,
+        });
+    }
+
+    transaction.commit().await.log_db_client_error()?;
+
+    entries.sort_by(|a, b| a.usage.utilization.total_cmp(&b.usage.utilization));
+
+    Ok(Json(entries))
 }
 
 pub fn routes(_state: AppState) -> ApiRouter {
     return ApiRouter::new()
+        .route("/", post(create_flavor))
+        .route("/matrix", get(flavor_matrix))
+        .route("/usage", get(flavor_usage))
+        .route(
+            "/detail/:flavor_id",
+            get(get_flavor).put(update_flavor).delete(delete_flavor),
+        )
         .api_route("/:lab_name", get(list_flavors))
-        .api_route("/:lab_name/hosts", get(list_hosts));
+        .api_route("/:lab_name/hosts", get(list_hosts))
+        .api_route("/:lab_name/images", get(list_images));
 }
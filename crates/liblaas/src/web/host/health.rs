@@ -0,0 +1,69 @@
+//! How a host's hardware has been holding up, for catching failing parts before a booking
+//! runs into them
+
+use axum::{
+    extract::{Json, Path},
+    http::StatusCode,
+};
+use dal::{new_client, web::*, AsEasyTransaction, FKey};
+use models::inventory::{Host, HostIssue};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use workflows::resource_management::post_times::{self, PostTimeReport};
+
+use super::{issues::HostIssueBlob, WebError};
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct HostHealth {
+    pub id: FKey<Host>,
+    pub server_name: String,
+    /// Everything about the host that looks off, empty if nothing does
+    pub anomalies: Vec<String>,
+    /// How long the host has taken to get from power on to netboot on its latest deploys
+    pub post_times: PostTimeReport,
+    /// Issues flagged on the host that haven't been cleared
+    pub known_issues: Vec<HostIssueBlob>,
+}
+
+#[axum::debug_handler]
+pub async fn host_health(Path(host_id): Path<FKey<Host>>) -> Result<Json<HostHealth>, WebError> {
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+    let host = host_id
+        .get(&mut transaction)
+        .await
+        .map_err(|_| (StatusCode::NOT_FOUND, format!("No host has id {host_id:?}")))?
+        .into_inner();
+
+    let post_times = post_times::report(&mut transaction, host.id)
+        .await
+        .log_db_client_error()?;
+
+    let known_issues = HostIssue::active_for_host(&mut transaction, host.id)
+        .await
+        .log_db_client_error()?
+        .iter()
+        .map(|i| (&**i).into())
+        .collect();
+
+    transaction.commit().await.log_db_client_error()?;
+
+    let mut anomalies = Vec::new();
+    if let (Some(latest), Some(drift)) = (post_times.latest_seconds, post_times.drift) {
+        if drift.anomalous {
+            anomalies.push(format!(
+                "took {latest:.0} seconds to netboot on its latest deploy, where it usually takes {:.0} ({:+.0}%)",
+                drift.baseline_seconds, drift.drift_percent
+            ));
+        }
+    }
+
+    Ok(Json(HostHealth {
+        id: host.id,
+        server_name: host.server_name,
+        anomalies,
+        post_times,
+        known_issues,
+    }))
+}
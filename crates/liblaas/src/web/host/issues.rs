@@ -0,0 +1,149 @@
+//! Known issues admins flag on hosts, which keep bookings that rely on the affected
+//! part off the host and are shown on the bookings the host ends up in
+
+use axum::{
+    extract::{Json, Path},
+    http::StatusCode,
+};
+use common::prelude::chrono::Utc;
+use dal::{new_client, web::*, AsEasyTransaction, FKey, NewRow};
+use models::inventory::{Host, HostIssue, IssueArea};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use super::WebError;
+use crate::web::identity::Admin;
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct HostIssueBlob {
+    pub id: FKey<HostIssue>,
+    pub area: IssueArea,
+    pub summary: String,
+    pub flagged_by: String,
+    pub flagged: String,
+    pub cleared_by: Option<String>,
+    pub cleared: Option<String>,
+}
+
+impl From<&HostIssue> for HostIssueBlob {
+    fn from(i: &HostIssue) -> Self {
+        Self {
+            id: i.id,
+            area: i.area,
+            summary: i.summary.clone(),
+            flagged_by: i.flagged_by.clone(),
+            flagged: i.flagged.to_rfc2822(),
+            cleared_by: i.cleared_by.clone(),
+            cleared: i.cleared.map(|c| c.to_rfc2822()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct FlagIssue {
+    pub area: IssueArea,
+    /// Like `NIC2 unreliable`
+    pub summary: String,
+}
+
+/// Every issue the host has had, oldest first
+#[axum::debug_handler]
+pub async fn list_issues(
+    Path(host_id): Path<FKey<Host>>,
+) -> Result<Json<Vec<HostIssueBlob>>, WebError> {
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+    let issues = HostIssue::all_for_host(&mut transaction, host_id)
+        .await
+        .log_db_client_error()?;
+
+    transaction.commit().await.log_db_client_error()?;
+
+    Ok(Json(issues.iter().map(|i| (&**i).into()).collect()))
+}
+
+#[axum::debug_handler]
+pub async fn flag_issue(
+    admin: Admin,
+    Path(host_id): Path<FKey<Host>>,
+    Json(request): Json<FlagIssue>,
+) -> Result<Json<HostIssueBlob>, WebError> {
+    tracing::info!(
+        "{} flagged {:?} issue on host {host_id:?}: {}",
+        admin.name,
+        request.area,
+        request.summary
+    );
+
+    if request.summary.trim().is_empty() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "The issue needs a summary".to_owned(),
+        ));
+    }
+
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+    host_id
+        .get(&mut transaction)
+        .await
+        .map_err(|_| (StatusCode::NOT_FOUND, format!("No host has id {host_id:?}")))?;
+
+    let issue = HostIssue {
+        id: FKey::new_id_dangling(),
+        host: host_id,
+        area: request.area,
+        summary: request.summary.trim().to_owned(),
+        flagged_by: admin.name,
+        flagged: Utc::now(),
+        cleared_by: None,
+        cleared: None,
+    };
+    NewRow::new(issue.clone())
+        .insert(&mut transaction)
+        .await
+        .log_server_error("Unable to flag the issue", true)?;
+
+    transaction.commit().await.log_db_client_error()?;
+
+    Ok(Json((&issue).into()))
+}
+
+#[axum::debug_handler]
+pub async fn clear_issue(
+    admin: Admin,
+    Path((host_id, issue_id)): Path<(FKey<Host>, FKey<HostIssue>)>,
+) -> Result<Json<HostIssueBlob>, WebError> {
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+    let mut issue = issue_id
+        .get(&mut transaction)
+        .await
+        .ok()
+        .filter(|i| i.host == host_id)
+        .ok_or((
+            StatusCode::NOT_FOUND,
+            "The host has no issue by that id".to_owned(),
+        ))?;
+
+    if issue.cleared.is_some() {
+        return Err((
+            StatusCode::CONFLICT,
+            "The issue was already cleared".to_owned(),
+        ));
+    }
+
+    issue.cleared_by = Some(admin.name);
+    issue.cleared = Some(Utc::now());
+    issue
+        .update(&mut transaction)
+        .await
+        .log_server_error("Unable to clear the issue", true)?;
+
+    transaction.commit().await.log_db_client_error()?;
+
+    Ok(Json((&*issue).into()))
+}
@@ -0,0 +1,387 @@
+//! Everything recorded about a single host, for admins tracking down a problem with it
+
+use super::{
+    identity::{is_admin, Admin},
+    jobs::{accepted, JobStarted},
+    AppState, WebError,
+};
+use aide::axum::{
+    routing::{get, post},
+    ApiRouter,
+};
+use axum::{
+    extract::{Json, Path},
+    http::{HeaderMap, StatusCode},
+};
+use common::prelude::*;
+use dal::{new_client, web::*, AsEasyTransaction, DBTable, FKey};
+use issues::HostIssueBlob;
+use models::{
+    allocator::{Allocation, AllocationReason, ResourceHandle},
+    dashboard::{Aggregate, Instance},
+    inventory::{
+        BmcProtocol, DataValue, FailureDomain, Flavor, Host, HostBenchmark, HostIssue, HostTicket,
+        HostVerification, TicketKind,
+    },
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use workflows::{
+    resource_management::{ipmi_accounts, jobs},
+    utils::resilience::{self, BreakerStatus, Device},
+};
+
+pub mod health;
+pub mod issues;
+
+pub fn routes(_state: AppState) -> ApiRouter {
+    ApiRouter::new()
+        .route("/:host_id", get(host_detail))
+        .route("/:host_id/health", get(health::host_health))
+        .route(
+            "/:host_id/issues",
+            get(issues::list_issues).post(issues::flag_issue),
+        )
+        .route(
+            "/:host_id/issues/:issue_id/clear",
+            post(issues::clear_issue),
+        )
+        .route("/credentials/rotate", post(rotate_credentials))
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct HostDetail {
+    pub id: FKey<Host>,
+    pub server_name: String,
+    pub fqdn: String,
+    pub arch: String,
+    pub serial: String,
+    pub iol_id: String,
+    pub lab: Option<String>,
+    pub projects: Vec<String>,
+    /// The dashboard project whose bookings are the only ones given the host
+    pub funded_by: Option<String>,
+    pub sda_uefi_device: Option<String>,
+    pub flavor: FlavorSummary,
+    pub location: FailureDomain,
+    pub interfaces: Vec<InterfaceDetail>,
+    pub bmc: BmcDetail,
+    pub state: HostState,
+    pub active_booking: Option<ActiveBooking>,
+    pub open_tickets: Vec<TicketSummary>,
+    /// Issues flagged on the host that haven't been cleared
+    pub known_issues: Vec<HostIssueBlob>,
+    /// How the host was last checked over after a booking's cleanup
+    pub last_verification: Option<VerificationSummary>,
+    /// How the host last scored on the standard benchmark, if it was ever benchmarked
+    pub last_benchmark: Option<BenchmarkSummary>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct FlavorSummary {
+    pub id: FKey<Flavor>,
+    pub name: String,
+    pub brand: String,
+    pub model: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct InterfaceDetail {
+    pub name: String,
+    pub mac: String,
+    pub speed: DataValue,
+    pub bus_addr: String,
+    pub switch: String,
+    /// The switch port the interface is cabled to, if known
+    pub switchport: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct BmcDetail {
+    pub fqdn: String,
+    pub protocol: BmcProtocol,
+    pub mac: String,
+    /// Only given to admins
+    pub user: Option<String>,
+    /// Only given to admins
+    pub password: Option<String>,
+    pub breaker: BreakerStatus,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, JsonSchema, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum HostState {
+    Free,
+    Booked,
+    Maintenance,
+    Retired,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ActiveBooking {
+    pub aggregate: FKey<Aggregate>,
+    pub booking_id: Option<String>,
+    pub owner: Option<String>,
+    pub project: Option<String>,
+    pub purpose: Option<String>,
+    /// The name the host goes by within the booking
+    pub hostname: Option<String>,
+    pub end: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct TicketSummary {
+    pub kind: TicketKind,
+    pub external_id: String,
+    pub url: Option<String>,
+    pub summary: String,
+    pub opened: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct VerificationSummary {
+    pub aggregate: FKey<Aggregate>,
+    pub passed: bool,
+    pub disks_wiped: bool,
+    pub bmc_matches_baseline: bool,
+    pub findings: Vec<String>,
+    pub recorded: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct BenchmarkSummary {
+    pub suite: String,
+    /// Higher is better, comparable between hosts of the same flavor
+    pub score: f64,
+    pub recorded: String,
+}
+
+#[axum::debug_handler]
+async fn host_detail(
+    headers: HeaderMap,
+    Path(host_id): Path<FKey<Host>>,
+) -> Result<Json<HostDetail>, WebError> {
+    let admin = is_admin(&headers);
+
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+    let host = host_id
+        .get(&mut transaction)
+        .await
+        .map_err(|_| (StatusCode::NOT_FOUND, format!("No host has id {host_id:?}")))?
+        .into_inner();
+
+    let flavor = host
+        .flavor
+        .get(&mut transaction)
+        .await
+        .log_db_client_error()?
+        .into_inner();
+
+    let mut interfaces = Vec::new();
+    for port in host.ports(&mut transaction).await.log_db_client_error()? {
+        let switchport = match port.switchport {
+            Some(sp) => Some(
+                sp.get(&mut transaction)
+                    .await
+                    .log_db_client_error()?
+                    .name
+                    .clone(),
+            ),
+            None => None,
+        };
+
+        interfaces.push(InterfaceDetail {
+            name: port.name,
+            mac: port.mac.to_string(),
+            speed: port.speed,
+            bus_addr: port.bus_addr,
+            switch: port.switch,
+            switchport,
+        });
+    }
+
+    let handle = ResourceHandle::handle_for_host(&mut transaction, host.id)
+        .await
+        .log_server_error("Host has no resource handle", true)?;
+
+    let lab = match handle.lab {
+        Some(lab) => Some(
+            lab.get(&mut transaction)
+                .await
+                .log_db_client_error()?
+                .name
+                .clone(),
+        ),
+        None => None,
+    };
+
+    let allocation = Allocation::find(&mut transaction, handle.id, false)
+        .await
+        .log_db_client_error()?
+        .into_iter()
+        .next()
+        .map(|a| a.into_inner());
+
+    let state = match allocation.as_ref().map(|a| a.reason_started) {
+        None => HostState::Free,
+        Some(AllocationReason::ForBooking) => HostState::Booked,
+        Some(AllocationReason::ForMaintenance) => HostState::Maintenance,
+        Some(AllocationReason::ForRetiry) => HostState::Retired,
+    };
+
+    let active_booking = match allocation.and_then(|a| a.for_aggregate) {
+        Some(agg_id) => {
+            let agg = agg_id
+                .get(&mut transaction)
+                .await
+                .log_db_client_error()?
+                .into_inner();
+
+            let hostname = Instance::select()
+                .where_field("aggregate")
+                .equals(agg_id)
+                .run(&mut transaction)
+                .await
+                .log_db_client_error()?
+                .into_iter()
+                .find(|i| i.linked_host == Some(host.id))
+                .map(|i| i.config.hostname.clone());
+
+            Some(ActiveBooking {
+                aggregate: agg_id,
+                booking_id: agg.metadata.booking_id,
+                owner: agg.metadata.owner,
+                project: agg.metadata.project,
+                purpose: agg.metadata.purpose,
+                hostname,
+                end: agg.metadata.end.map(|e| e.to_rfc2822()),
+            })
+        }
+        None => None,
+    };
+
+    let open_tickets = HostTicket::all_for_host(&mut transaction, host.id)
+        .await
+        .log_db_client_error()?
+        .into_iter()
+        .filter(|t| t.resolved.is_none())
+        .map(|t| TicketSummary {
+            kind: t.kind,
+            external_id: t.external_id.clone(),
+            url: t.url.clone(),
+            summary: t.summary.clone(),
+            opened: t.opened.to_rfc2822(),
+        })
+        .collect();
+
+    let known_issues = HostIssue::active_for_host(&mut transaction, host.id)
+        .await
+        .log_db_client_error()?
+        .iter()
+        .map(|i| (&**i).into())
+        .collect();
+
+    let last_verification = HostVerification::latest_for_host(&mut transaction, host.id)
+        .await
+        .log_db_client_error()?
+        .map(|v| VerificationSummary {
+            aggregate: v.aggregate,
+            passed: v.passed,
+            disks_wiped: v.disks_wiped,
+            bmc_matches_baseline: v.bmc_matches_baseline,
+            findings: v.findings.clone(),
+            recorded: v.recorded.to_rfc2822(),
+        });
+
+    let last_benchmark = HostBenchmark::latest_for_host(&mut transaction, host.id)
+        .await
+        .log_db_client_error()?
+        .map(|b| BenchmarkSummary {
+            suite: b.suite.clone(),
+            score: b.score,
+            recorded: b.recorded.to_rfc2822(),
+        });
+
+    transaction.commit().await.log_db_client_error()?;
+
+    Ok(Json(HostDetail {
+        id: host.id,
+        server_name: host.server_name,
+        fqdn: host.fqdn,
+        arch: host.arch.to_string(),
+        serial: host.serial,
+        iol_id: host.iol_id,
+        lab,
+        projects: host.projects,
+        funded_by: host.funded_by,
+        sda_uefi_device: host.sda_uefi_device,
+        flavor: FlavorSummary {
+            id: flavor.id,
+            name: flavor.name,
+            brand: flavor.brand,
+            model: flavor.model,
+        },
+        location: host.failure_domain,
+        interfaces,
+        bmc: BmcDetail {
+            breaker: resilience::status(&Device::bmc(&host.ipmi_fqdn)),
+            fqdn: host.ipmi_fqdn,
+            protocol: host.bmc_protocol,
+            mac: host.ipmi_mac.to_hex_string(),
+            user: admin.then_some(host.ipmi_user),
+            password: admin.then_some(host.ipmi_pass),
+        },
+        state,
+        active_booking,
+        open_tickets,
+        known_issues,
+        last_verification,
+        last_benchmark,
+    }))
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct CredentialRotationRequest {
+    /// Every host in the inventory if not given
+    pub hosts: Option<Vec<FKey<Host>>>,
+}
+
+/// Gives the BMC admin account of each host a new password, as a job
+async fn rotate_credentials(
+    admin: Admin,
+    Json(request): Json<CredentialRotationRequest>,
+) -> Result<(StatusCode, Json<JobStarted>), WebError> {
+    tracing::info!("API call to rotate_credentials() for {:?}", request.hosts);
+
+    let hosts = match request.hosts {
+        Some(hosts) => hosts,
+        None => {
+            let mut client = new_client().await.log_db_client_error()?;
+            let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+            let hosts = Host::all_hosts(&mut transaction)
+                .await
+                .log_db_client_error()?
+                .into_iter()
+                .map(|h| h.id)
+                .collect();
+
+            transaction.commit().await.log_db_client_error()?;
+
+            hosts
+        }
+    };
+
+    let job = jobs::start(
+        "credential_rotation",
+        &admin.name,
+        hosts.len(),
+        move |job| ipmi_accounts::rotate_admin_credentials(job, hosts),
+    )
+    .await
+    .log_server_error("Unable to start the credential rotation", true)?;
+
+    Ok(accepted(job))
+}
@@ -0,0 +1,97 @@
+//! Who a request is made on behalf of
+//!
+//! The dashboard authenticates users itself, and passes on who a request is for and their
+//! role in headers. Every endpoint reads them through here, so none of them take who is
+//! asking from the body of the request instead.
+
+use aide::OperationInput;
+use axum::{
+    async_trait,
+    extract::FromRequestParts,
+    http::{request::Parts, HeaderMap, StatusCode},
+};
+
+use super::WebError;
+
+/// The dashboard passes on who a request is made for
+pub const USER_HEADER: &str = "X-LaaS-User";
+
+/// The dashboard passes on the role of whoever a request is made on behalf of
+pub const ROLE_HEADER: &str = "X-LaaS-Role";
+
+/// The role that may administer the lab, like being shown BMC credentials
+const ADMIN_ROLE: &str = "admin";
+
+/// Who the request is made for, if the dashboard said
+pub fn requesting_user(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(USER_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .map(str::trim)
+        .filter(|u| !u.is_empty())
+}
+
+/// Whether the request is made on behalf of an admin
+pub fn is_admin(headers: &HeaderMap) -> bool {
+    headers
+        .get(ROLE_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|role| role.trim().eq_ignore_ascii_case(ADMIN_ROLE))
+}
+
+fn forbidden() -> WebError {
+    (StatusCode::FORBIDDEN, "Only admins can do this".to_owned())
+}
+
+/// Who the request is made for, turning it away if the dashboard didn't say
+pub fn named(headers: &HeaderMap) -> Result<String, WebError> {
+    requesting_user(headers).map(str::to_owned).ok_or((
+        StatusCode::BAD_REQUEST,
+        format!("No {USER_HEADER} header to say who the request is for"),
+    ))
+}
+
+/// Who the request is made for, for endpoints that can't do anything without knowing
+#[derive(Debug, Clone)]
+pub struct User {
+    pub name: String,
+    pub admin: bool,
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for User {
+    type Rejection = WebError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(Self {
+            name: named(&parts.headers)?,
+            admin: is_admin(&parts.headers),
+        })
+    }
+}
+
+impl OperationInput for User {}
+
+/// A request made on behalf of an admin, turned away otherwise
+#[derive(Debug, Clone)]
+pub struct Admin {
+    /// Which admin, so what they do can be put down to them
+    pub name: String,
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Admin {
+    type Rejection = WebError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        if !is_admin(&parts.headers) {
+            return Err(forbidden());
+        }
+
+        Ok(Self {
+            name: named(&parts.headers)?,
+        })
+    }
+}
+
+impl OperationInput for Admin {}
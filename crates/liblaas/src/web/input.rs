@@ -0,0 +1,212 @@
+//! Limits on what requests to the web API may send
+//!
+//! Every request body is read up to the size allowed by `web.limits` in the config for its
+//! path, and JSON bodies nesting deeper than allowed are turned away, before any handler
+//! sees them. Handlers that would rather refuse fields they don't know about than quietly
+//! drop them take their body as a [`StrictJson`].
+
+use aide::{gen::GenContext, openapi::Operation, OperationInput};
+use axum::{
+    async_trait,
+    body::{Body, Bytes, HttpBody},
+    extract::FromRequest,
+    http::{header, Request, StatusCode},
+    middleware::Next,
+    response::Response,
+    Json,
+};
+use common::prelude::config;
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+
+use super::WebError;
+
+/// What bodies may be if the config doesn't say, the same as axum allows by default
+const DEFAULT_MAX_BODY_BYTES: usize = 2 * 1024 * 1024;
+const DEFAULT_MAX_JSON_DEPTH: usize = 32;
+
+fn too_large(max: usize) -> WebError {
+    (
+        StatusCode::PAYLOAD_TOO_LARGE,
+        format!("The request body is larger than the {max} bytes this endpoint accepts"),
+    )
+}
+
+/// How deeply arrays and objects nest in `json`, without parsing it
+///
+/// Brackets within strings don't count. Malformed JSON is left for the handler to refuse.
+fn json_depth(json: &[u8]) -> usize {
+    let (mut depth, mut deepest) = (0usize, 0usize);
+    let (mut in_string, mut escaped) = (false, false);
+
+    for &b in json {
+        match (in_string, b) {
+            (true, _) if escaped => escaped = false,
+            (true, b'\\') => escaped = true,
+            (true, b'"') => in_string = false,
+            (true, _) => (),
+            (false, b'"') => in_string = true,
+            (false, b'{' | b'[') => {
+                depth += 1;
+                deepest = deepest.max(depth);
+            }
+            (false, b'}' | b']') => depth = depth.saturating_sub(1),
+            (false, _) => (),
+        }
+    }
+
+    deepest
+}
+
+fn is_json(request: &Request<Body>) -> bool {
+    request
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|c| c.to_str().ok())
+        .is_some_and(|c| c.starts_with("application/json") || c.contains("+json"))
+}
+
+/// Reads the body of `request` up to its limit before passing it on
+pub async fn limits(request: Request<Body>, next: Next<Body>) -> Result<Response, WebError> {
+    let limits = config::settings().web.limits.for_path(request.uri().path());
+    let max_bytes = limits.max_body_bytes.unwrap_or(DEFAULT_MAX_BODY_BYTES);
+    let max_depth = limits.max_json_depth.unwrap_or(DEFAULT_MAX_JSON_DEPTH);
+
+    // turn away what says up front that it is too big without reading any of it
+    let declared = request
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|l| l.to_str().ok())
+        .and_then(|l| l.parse::<usize>().ok());
+    if declared.is_some_and(|l| l > max_bytes) {
+        return Err(too_large(max_bytes));
+    }
+
+    let json = is_json(&request);
+    let (parts, mut body) = request.into_parts();
+
+    let mut read = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(|e| {
+            (
+                StatusCode::BAD_REQUEST,
+                format!("Couldn't read the request body: {e}"),
+            )
+        })?;
+
+        if read.len() + chunk.len() > max_bytes {
+            return Err(too_large(max_bytes));
+        }
+        read.extend_from_slice(&chunk);
+    }
+
+    if json {
+        let depth = json_depth(&read);
+        if depth > max_depth {
+            return Err((
+                StatusCode::UNPROCESSABLE_ENTITY,
+                format!("The request body nests {depth} levels deep, this endpoint accepts at most {max_depth}"),
+            ));
+        }
+    }
+
+    Ok(next
+        .run(Request::from_parts(parts, Body::from(Bytes::from(read))))
+        .await)
+}
+
+/// Paths of the fields in `given` that didn't make it into `kept`, the same value after
+/// a round trip through the type it was read as
+///
+/// Fields that were given as null or empty are let through, since types often leave
+/// those out when they are written back out.
+fn unknown_fields(given: &Value, kept: &Value, path: &str, unknown: &mut Vec<String>) {
+    let field_path = |field: &str| match path.is_empty() {
+        true => field.to_owned(),
+        false => format!("{path}.{field}"),
+    };
+
+    match (given, kept) {
+        (Value::Object(given), Value::Object(kept)) => {
+            for (field, value) in given {
+                match kept.get(field) {
+                    Some(k) => unknown_fields(value, k, &field_path(field), unknown),
+                    None if is_empty(value) => (),
+                    None => unknown.push(field_path(field)),
+                }
+            }
+        }
+        (Value::Array(given), Value::Array(kept)) => {
+            for (i, (value, k)) in given.iter().zip(kept).enumerate() {
+                unknown_fields(value, k, &format!("{path}[{i}]"), unknown);
+            }
+        }
+        _ => (),
+    }
+}
+
+fn is_empty(value: &Value) -> bool {
+    match value {
+        Value::Null => true,
+        Value::Array(a) => a.is_empty(),
+        Value::Object(o) => o.is_empty(),
+        _ => false,
+    }
+}
+
+/// A JSON body that is refused with a 422 if it has any fields `T` doesn't know about
+///
+/// Worth it for endpoints where a misspelled field quietly taking its default would
+/// go unnoticed, like options on a booking.
+pub struct StrictJson<T>(pub T);
+
+#[async_trait]
+impl<T, S, B> FromRequest<S, B> for StrictJson<T>
+where
+    T: DeserializeOwned + Serialize,
+    Json<Value>: FromRequest<S, B, Rejection = axum::extract::rejection::JsonRejection>,
+    S: Send + Sync,
+    B: Send + 'static,
+{
+    type Rejection = WebError;
+
+    async fn from_request(request: Request<B>, state: &S) -> Result<Self, Self::Rejection> {
+        let Json(given) = Json::<Value>::from_request(request, state)
+            .await
+            .map_err(|e| (e.status(), e.body_text()))?;
+
+        let value = T::deserialize(&given).map_err(|e| {
+            (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                format!("Failed to deserialize the JSON body into the target type: {e}"),
+            )
+        })?;
+
+        let kept = serde_json::to_value(&value).map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Couldn't check the request body for unknown fields: {e}"),
+            )
+        })?;
+
+        let mut unknown = Vec::new();
+        unknown_fields(&given, &kept, "", &mut unknown);
+        if !unknown.is_empty() {
+            return Err((
+                StatusCode::UNPROCESSABLE_ENTITY,
+                format!(
+                    "The request body has fields this endpoint doesn't know about: {}",
+                    unknown.join(", ")
+                ),
+            ));
+        }
+
+        Ok(StrictJson(value))
+    }
+}
+
+impl<T: schemars::JsonSchema> OperationInput for StrictJson<T> {
+    fn operation_input(ctx: &mut GenContext, operation: &mut Operation) {
+        Json::<T>::operation_input(ctx, operation)
+    }
+}
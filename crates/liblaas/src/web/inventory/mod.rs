@@ -0,0 +1,77 @@
+//! Changes to the lab inventory
+
+use super::{
+    identity::Admin,
+    jobs::{self, JobStarted},
+    AppState, WebError,
+};
+use aide::axum::{routing::post, ApiRouter};
+use axum::{extract::Json, http::StatusCode};
+use dal::{new_client, web::*, AsEasyTransaction};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use workflows::resource_management::{
+    inventory::{self, BulkHostReport, BulkHostRequest},
+    jobs as job_runner,
+};
+
+pub fn routes(_state: AppState) -> ApiRouter {
+    ApiRouter::new()
+        .route("/hosts/bulk", post(bulk_hosts))
+        .route("/hosts/import", post(import_hosts))
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+struct HostImportRequest {
+    #[serde(flatten)]
+    hosts: BulkHostRequest,
+}
+
+/// Applies every operation or none of them, answering with 422 and why each
+/// operation failed if any of them did
+#[axum::debug_handler]
+async fn bulk_hosts(
+    _admin: Admin,
+    Json(request): Json<BulkHostRequest>,
+) -> Result<(StatusCode, Json<BulkHostReport>), WebError> {
+    if request.operations.is_empty() {
+        return Err((StatusCode::BAD_REQUEST, "No operations given".to_owned()));
+    }
+
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+    let report = inventory::apply(&mut transaction, request, None)
+        .await
+        .log_server_error("Unable to apply inventory operations", true)?;
+
+    transaction.commit().await.log_db_client_error()?;
+
+    let status = match report.valid() {
+        true => StatusCode::OK,
+        false => StatusCode::UNPROCESSABLE_ENTITY,
+    };
+
+    Ok((status, Json(report)))
+}
+
+/// Applies the operations like `/hosts/bulk` does, but as a job, for imports too large to wait on
+async fn import_hosts(
+    admin: Admin,
+    Json(request): Json<HostImportRequest>,
+) -> Result<(StatusCode, Json<JobStarted>), WebError> {
+    if request.hosts.operations.is_empty() {
+        return Err((StatusCode::BAD_REQUEST, "No operations given".to_owned()));
+    }
+
+    let job = job_runner::start(
+        "inventory_import",
+        &admin.name,
+        request.hosts.operations.len(),
+        move |job| inventory::import(job, request.hosts),
+    )
+    .await
+    .log_server_error("Unable to start the import", true)?;
+
+    Ok(jobs::accepted(job))
+}
@@ -0,0 +1,132 @@
+//! Following, cancelling and fetching the outcome of long running admin operations
+//!
+//! Endpoints that start one answer with a [`JobStarted`] right away, see
+//! `workflows::resource_management::jobs`. Admins can see every job, anyone else only the
+//! jobs they started, like instructors ending a cohort.
+
+use super::{
+    identity::User,
+    listing::{Listed, Listing},
+    AppState, WebError,
+};
+use aide::axum::{
+    routing::{get, post},
+    ApiRouter,
+};
+use axum::{
+    extract::{Json, Path},
+    http::StatusCode,
+};
+use common::prelude::*;
+use dal::{new_client, web::*, AsEasyTransaction, DBTable, EasyTransaction, ExistingRow, FKey};
+use models::dashboard::Job;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+pub fn routes(_state: AppState) -> ApiRouter {
+    ApiRouter::new()
+        .route("/", get(list_jobs))
+        .route("/:job_id", get(job_status))
+        .route("/:job_id/result", get(job_result))
+        .route("/:job_id/cancel", post(cancel_job))
+}
+
+/// What endpoints starting a job answer with
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct JobStarted {
+    pub job_id: FKey<Job>,
+}
+
+/// The answer to a request that started `job`
+pub fn accepted(job: FKey<Job>) -> (StatusCode, Json<JobStarted>) {
+    (StatusCode::ACCEPTED, Json(JobStarted { job_id: job }))
+}
+
+/// The job, unless it isn't one `by` can see
+async fn visible_job(
+    transaction: &mut EasyTransaction<'_>,
+    by: &User,
+    job_id: FKey<Job>,
+) -> Result<ExistingRow<Job>, WebError> {
+    job_id
+        .get(transaction)
+        .await
+        .ok()
+        .filter(|j| by.admin || j.requested_by == by.name)
+        .ok_or((StatusCode::NOT_FOUND, "No job has that id".to_owned()))
+}
+
+/// Every job, most recently started first unless sorted otherwise
+async fn list_jobs(by: User, listing: Listing) -> Result<Listed<Job>, WebError> {
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+    let jobs = Job::select()
+        .order_by("created", true)
+        .run(&mut transaction)
+        .await
+        .log_db_client_error()?
+        .into_iter()
+        .map(|j| j.into_inner())
+        .filter(|j: &Job| by.admin || j.requested_by == by.name)
+        .collect();
+
+    transaction.commit().await.log_db_client_error()?;
+
+    listing.default_sort("-created").apply(jobs)
+}
+
+async fn job_status(by: User, Path(job_id): Path<FKey<Job>>) -> Result<Json<Job>, WebError> {
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+    let job = visible_job(&mut transaction, &by, job_id)
+        .await?
+        .into_inner();
+
+    transaction.commit().await.log_db_client_error()?;
+
+    Ok(Json(job))
+}
+
+/// The report of a finished job, null if it failed before it could report anything (its error says why)
+async fn job_result(
+    by: User,
+    Path(job_id): Path<FKey<Job>>,
+) -> Result<Json<Option<serde_json::Value>>, WebError> {
+    let Json(job) = job_status(by, Path(job_id)).await?;
+
+    if !job.status.finished() {
+        return Err((
+            StatusCode::CONFLICT,
+            format!("The job is still {:?}", job.status),
+        ));
+    }
+
+    Ok(Json(job.result))
+}
+
+/// Asks the job to stop, which it does at its next step
+async fn cancel_job(by: User, Path(job_id): Path<FKey<Job>>) -> Result<Json<Job>, WebError> {
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+    visible_job(&mut transaction, &by, job_id).await?;
+    let mut job = Job::lock(&mut transaction, job_id)
+        .await
+        .log_db_client_error()?;
+
+    if job.status.finished() {
+        return Err((
+            StatusCode::CONFLICT,
+            format!("The job already finished as {:?}", job.status),
+        ));
+    }
+
+    job.cancel_requested = true;
+    job.update(&mut transaction).await.log_db_client_error()?;
+
+    transaction.commit().await.log_db_client_error()?;
+
+    Ok(Json(job.into_inner()))
+}
@@ -0,0 +1,377 @@
+//! How every list endpoint pages, sorts and trims what it returns
+//!
+//! Lists take `limit` for the most items to return, `sort` for a comma separated list of
+//! fields to order by (a leading `-` sorts that field descending) and `fields` for a comma
+//! separated list of the only fields each item should have. A page that stops short of the
+//! end of the list comes back with an `x-next-cursor` header, which is passed back as
+//! `cursor` for the page after it. A list asked for without any of these comes back whole,
+//! the way it always has.
+//!
+//! Lists too long to load whole are paged by the database instead, and only take `cursor`
+//! and `limit`.
+
+use std::{cmp::Ordering, marker::PhantomData};
+
+use aide::{
+    gen::GenContext,
+    openapi::{Operation, Response},
+    OperationInput, OperationOutput,
+};
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, Query},
+    http::{request::Parts, HeaderValue, StatusCode},
+    response::IntoResponse,
+    Json,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use dal::web::*;
+use models::dashboard::{AggregateSummary, Job};
+use schemars::JsonSchema;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+
+use super::{
+    api::{HostBlob, ImageDetailBlob, TemplateBlob},
+    booking::{access::BookingAccessBlob, logs::LogEntryBlob, trash::TrashedBookingBlob},
+    WebError,
+};
+
+/// Where the cursor for the next page is returned
+pub const NEXT_CURSOR_HEADER: &str = "x-next-cursor";
+
+/// The most items one page can hold
+pub const MAX_LIMIT: usize = 1000;
+
+#[derive(Debug, Clone, Default, Deserialize, JsonSchema)]
+pub struct ListParams {
+    /// From the `x-next-cursor` header of the previous page
+    pub cursor: Option<String>,
+    pub limit: Option<usize>,
+    /// Like `-end,name`
+    pub sort: Option<String>,
+    /// Like `id,name`
+    pub fields: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SortKey {
+    pub field: String,
+    pub descending: bool,
+}
+
+/// Where the previous page ended, under the sort it was made with
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Cursor {
+    sort: String,
+    after: Vec<Value>,
+}
+
+/// Something list endpoints return many of
+pub trait Listable: Serialize {
+    /// The field that tells items apart, which breaks ties between items that sort the same
+    const KEY: &'static str;
+}
+
+impl Listable for AggregateSummary {
+    const KEY: &'static str = "aggregate";
+}
+
+impl Listable for HostBlob {
+    const KEY: &'static str = "id";
+}
+
+impl Listable for Job {
+    const KEY: &'static str = "id";
+}
+
+impl Listable for ImageDetailBlob {
+    const KEY: &'static str = "image_id";
+}
+
+impl Listable for TemplateBlob {
+    const KEY: &'static str = "id";
+}
+
+impl Listable for BookingAccessBlob {
+    const KEY: &'static str = "id";
+}
+
+impl Listable for TrashedBookingBlob {
+    const KEY: &'static str = "aggregate";
+}
+
+impl Listable for LogEntryBlob {
+    const KEY: &'static str = "id";
+}
+
+/// The paging, sorting and fields a list was asked for
+#[derive(Debug, Clone, Default)]
+pub struct Listing {
+    limit: Option<usize>,
+    sort: Vec<SortKey>,
+    fields: Option<Vec<String>>,
+    cursor: Option<Cursor>,
+}
+
+/// A page of a list, as the client asked for it
+pub struct Listed<T> {
+    items: Vec<Value>,
+    next: Option<String>,
+    _p: PhantomData<T>,
+}
+
+fn bad_request(message: String) -> WebError {
+    (StatusCode::BAD_REQUEST, message)
+}
+
+fn split(list: &str) -> Vec<String> {
+    list.split(',')
+        .map(str::trim)
+        .filter(|f| !f.is_empty())
+        .map(str::to_owned)
+        .collect()
+}
+
+fn split_sort(spec: &str) -> Vec<SortKey> {
+    split(spec)
+        .into_iter()
+        .map(|f| match f.strip_prefix('-') {
+            Some(field) => SortKey {
+                field: field.to_owned(),
+                descending: true,
+            },
+            None => SortKey {
+                field: f,
+                descending: false,
+            },
+        })
+        .collect()
+}
+
+/// Orders JSON values the way a client would expect, with nulls first
+fn compare(a: &Value, b: &Value) -> Ordering {
+    match (a, b) {
+        (Value::Null, Value::Null) => Ordering::Equal,
+        (Value::Null, _) => Ordering::Less,
+        (_, Value::Null) => Ordering::Greater,
+        (Value::Bool(a), Value::Bool(b)) => a.cmp(b),
+        (Value::Number(a), Value::Number(b)) => a
+            .as_f64()
+            .unwrap_or_default()
+            .total_cmp(&b.as_f64().unwrap_or_default()),
+        (Value::String(a), Value::String(b)) => a.cmp(b),
+        (a, b) => a.to_string().cmp(&b.to_string()),
+    }
+}
+
+impl Listing {
+    pub fn parse(params: ListParams) -> Result<Self, WebError> {
+        let limit = match params.limit {
+            Some(0) => return Err(bad_request("limit must be at least 1".to_owned())),
+            Some(l) if l > MAX_LIMIT => {
+                return Err(bad_request(format!("limit can be at most {MAX_LIMIT}")))
+            }
+            limit => limit,
+        };
+
+        let sort = split_sort(params.sort.as_deref().unwrap_or_default());
+
+        let cursor = params.cursor.as_deref().map(decode_cursor).transpose()?;
+
+        Ok(Self {
+            limit,
+            sort,
+            fields: params.fields.as_deref().map(split),
+            cursor,
+        })
+    }
+
+    /// Sorts by `spec`, written like the `sort` parameter, if the client didn't pick a sort
+    pub fn default_sort(mut self, spec: &str) -> Self {
+        if self.sort.is_empty() {
+            self.sort = split_sort(spec);
+        }
+
+        self
+    }
+
+    /// The sort as the client gave it, which a cursor only works under
+    fn sort_spec(&self) -> String {
+        self.sort
+            .iter()
+            .map(|k| match k.descending {
+                true => format!("-{}", k.field),
+                false => k.field.clone(),
+            })
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+
+    /// The requested sort, then the key of `T` so that the order is total
+    fn order<T: Listable>(&self) -> Vec<SortKey> {
+        let mut order = self.sort.clone();
+        order.push(SortKey {
+            field: T::KEY.to_owned(),
+            descending: false,
+        });
+
+        order
+    }
+
+    pub fn apply<T: Listable>(&self, items: Vec<T>) -> Result<Listed<T>, WebError> {
+        let mut items: Vec<Value> = items
+            .iter()
+            .map(serde_json::to_value)
+            .collect::<Result<_, _>>()
+            .anyway()
+            .log_server_error("unable to serialize the listed items", true)?;
+
+        if let Some(first) = items.first() {
+            let unknown = self
+                .sort
+                .iter()
+                .map(|k| &k.field)
+                .chain(self.fields.iter().flatten())
+                .find(|f| first.get(f.as_str()).is_none());
+            if let Some(field) = unknown {
+                return Err(bad_request(format!("Items have no field {field}")));
+            }
+        }
+
+        let order = self.order::<T>();
+        let values = |item: &Value| -> Vec<Value> {
+            order
+                .iter()
+                .map(|k| item.get(&k.field).cloned().unwrap_or(Value::Null))
+                .collect()
+        };
+        let cmp = |a: &[Value], b: &[Value]| {
+            order
+                .iter()
+                .zip(a.iter().zip(b.iter()))
+                .map(|(k, (a, b))| match k.descending {
+                    true => compare(b, a),
+                    false => compare(a, b),
+                })
+                .find(|o| o.is_ne())
+                .unwrap_or(Ordering::Equal)
+        };
+
+        items.sort_by(|a, b| cmp(&values(a), &values(b)));
+
+        if let Some(cursor) = &self.cursor {
+            if cursor.sort != self.sort_spec() {
+                return Err(bad_request(
+                    "That cursor is for a different sort, start over without it".to_owned(),
+                ));
+            }
+            items.retain(|i| cmp(&values(i), &cursor.after).is_gt());
+        }
+
+        let next = match self.limit {
+            Some(limit) if items.len() > limit => {
+                items.truncate(limit);
+                let cursor = Cursor {
+                    sort: self.sort_spec(),
+                    after: items.last().map(values).unwrap_or_default(),
+                };
+                encode_cursor(&cursor)
+            }
+            _ => None,
+        };
+
+        if let Some(fields) = &self.fields {
+            for item in items.iter_mut() {
+                if let Value::Object(map) = item {
+                    map.retain(|k, _| fields.contains(k));
+                }
+            }
+        }
+
+        Ok(Listed {
+            items,
+            next,
+            _p: PhantomData,
+        })
+    }
+}
+
+/// A cursor for lists that the database pages instead of [`Listing::apply`]
+pub fn encode_cursor<C: Serialize>(cursor: &C) -> Option<String> {
+    serde_json::to_vec(cursor)
+        .ok()
+        .map(|c| URL_SAFE_NO_PAD.encode(c))
+}
+
+pub fn decode_cursor<C: DeserializeOwned>(cursor: &str) -> Result<C, WebError> {
+    URL_SAFE_NO_PAD
+        .decode(cursor)
+        .ok()
+        .and_then(|c| serde_json::from_slice(&c).ok())
+        .ok_or(bad_request("That cursor isn't valid".to_owned()))
+}
+
+impl<T: Listable> Listed<T> {
+    /// A page that was already cut out of the list, followed by the page at `next`
+    pub fn page(items: Vec<T>, next: Option<String>) -> Result<Self, WebError> {
+        let items = items
+            .iter()
+            .map(serde_json::to_value)
+            .collect::<Result<_, _>>()
+            .anyway()
+            .log_server_error("unable to serialize the listed items", true)?;
+
+        Ok(Self {
+            items,
+            next,
+            _p: PhantomData,
+        })
+    }
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Listing {
+    type Rejection = WebError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Query(params) = Query::<ListParams>::from_request_parts(parts, state)
+            .await
+            .map_err(|e| bad_request(e.to_string()))?;
+
+        Self::parse(params)
+    }
+}
+
+impl OperationInput for Listing {
+    fn operation_input(ctx: &mut GenContext, operation: &mut Operation) {
+        Query::<ListParams>::operation_input(ctx, operation)
+    }
+}
+
+impl<T> IntoResponse for Listed<T> {
+    fn into_response(self) -> axum::response::Response {
+        let mut response = Json(self.items).into_response();
+
+        if let Some(next) = self.next.and_then(|n| HeaderValue::from_str(&n).ok()) {
+            response.headers_mut().insert(NEXT_CURSOR_HEADER, next);
+        }
+
+        response
+    }
+}
+
+impl<T: JsonSchema> OperationOutput for Listed<T> {
+    type Inner = Vec<T>;
+
+    fn operation_response(ctx: &mut GenContext, operation: &mut Operation) -> Option<Response> {
+        Json::<Vec<T>>::operation_response(ctx, operation)
+    }
+
+    fn inferred_responses(
+        ctx: &mut GenContext,
+        operation: &mut Operation,
+    ) -> Vec<(Option<u16>, Response)> {
+        Json::<Vec<T>>::inferred_responses(ctx, operation)
+    }
+}
@@ -4,7 +4,11 @@
 use common::prelude::*;
 
 use aide::transform::TransformOpenApi;
-use axum::{extract::Json, http::StatusCode, Extension, ServiceExt};
+use axum::{
+    extract::{DefaultBodyLimit, Json},
+    http::StatusCode,
+    Extension,
+};
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
@@ -20,13 +24,30 @@ use dal::web::ApiError;
 use docs::docs_routes;
 use std::{str::FromStr, sync::Arc};
 
+mod admin;
 pub mod api;
 pub mod booking;
+mod capacity;
+mod changes;
+mod debug;
+mod deprecation;
 mod docs;
+mod download;
+mod export;
 mod flavor;
+mod host;
+mod identity;
+mod input;
+mod inventory;
+mod jobs;
+pub mod listing;
 mod metrics;
+mod schedule;
+mod security;
+mod status;
 pub mod template;
 pub mod users;
+mod websocket;
 
 pub type WebError = (StatusCode, String);
 
@@ -66,12 +87,47 @@ pub async fn entry(rt: &'static Runtime) {
     let app = ApiRouter::new()
         .nest_api_service("/booking", booking::routes(state.clone()))
         .nest_api_service("/flavor", flavor::routes(state.clone()))
+        .nest_api_service("/host", host::routes(state.clone()))
         .nest_api_service("/template", template::routes(state.clone()))
         .nest_api_service("/user", users::routes(state.clone()))
         .nest_api_service("/docs", docs_routes(state.clone()))
         .nest_api_service("/metrics", metrics::routes(state.clone()))
-        .finish_api_with(&mut api, api_docs)
+        .nest_api_service("/export", export::routes(state.clone()))
+        .nest_api_service("/capacity", capacity::routes(state.clone()))
+        .nest_api_service("/inventory", inventory::routes(state.clone()))
+        .nest_api_service("/jobs", jobs::routes(state.clone()))
+        .nest_api_service("/status", status::routes(state.clone()))
+        .nest_api_service("/schedule", schedule::routes(state.clone()))
+        .nest_api_service("/download", download::routes(state.clone()))
+        .nest_api_service("/api", changes::routes(state.clone()))
+        .nest_api_service("/admin", admin::routes(state.clone()));
+
+    // failure injection must never be reachable on a production deployment
+    let app = match config::settings().dev.status {
+        true => app.nest_api_service("/debug", debug::routes(state.clone())),
+        false => app,
+    };
+
+    let app = app.finish_api_with(&mut api, api_docs);
+
+    match serde_json::to_value(&api) {
+        Ok(spec) => {
+            tokio::spawn(async move {
+                if let Err(e) = changes::record(spec).await {
+                    tracing::error!("Couldn't record the spec of this deployment: {e:?}");
+                }
+            });
+        }
+        Err(e) => tracing::error!("Couldn't write out the spec of this deployment: {e:?}"),
+    }
+
+    let app = app
         .layer(Extension(Arc::new(api)))
+        // bodies are limited by input::limits instead, per endpoint
+        .layer(DefaultBodyLimit::disable())
+        .layer(axum::middleware::from_fn(input::limits))
+        .layer(axum::middleware::from_fn(deprecation::warnings))
+        .layer(axum::middleware::from_fn(security::headers))
         .with_state(state);
 
     let api = OpenApi {
@@ -0,0 +1,64 @@
+//! Occupancy of the lab over time, for the dashboard to draw a calendar of bookings with
+
+use super::{AppState, WebError};
+use aide::axum::{routing::get, ApiRouter};
+use axum::{
+    extract::{Json, Query},
+    http::StatusCode,
+};
+use common::prelude::chrono::{DateTime, Duration, Utc};
+use dal::{new_client, web::*, AsEasyTransaction, FKey};
+use models::inventory::Flavor;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use workflows::resource_management::schedule::{self, Schedule};
+
+/// The longest window one request can ask for
+const MAX_DAYS: i64 = 180;
+
+pub fn routes(_state: AppState) -> ApiRouter {
+    ApiRouter::new().route("/", get(lab_schedule))
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ScheduleQuery {
+    /// Only hosts of this flavor, every flavor if not given
+    flavor: Option<FKey<Flavor>>,
+    /// Start of the window (RFC 3339), now if not given
+    #[schemars(with = "Option<String>")]
+    from: Option<DateTime<Utc>>,
+    /// End of the window (RFC 3339), two weeks after its start if not given
+    #[schemars(with = "Option<String>")]
+    to: Option<DateTime<Utc>>,
+}
+
+async fn lab_schedule(Query(query): Query<ScheduleQuery>) -> Result<Json<Schedule>, WebError> {
+    let from = query.from.unwrap_or_else(Utc::now);
+    let to = query.to.unwrap_or(from + Duration::days(14));
+
+    if to <= from || to - from > Duration::days(MAX_DAYS) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("The schedule can be asked for over windows of up to {MAX_DAYS} days that end after they start"),
+        ));
+    }
+
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+    if let Some(flavor) = query.flavor {
+        flavor.get(&mut transaction).await.log_error(
+            StatusCode::NOT_FOUND,
+            "Flavor does not exist",
+            true,
+        )?;
+    }
+
+    let schedule = schedule::schedule(&mut transaction, query.flavor, from, to)
+        .await
+        .log_server_error("Unable to put together the schedule", true)?;
+
+    transaction.commit().await.log_db_client_error()?;
+
+    Ok(Json(schedule))
+}
@@ -0,0 +1,166 @@
+//! CORS and security headers on every response of the web API
+//!
+//! What is sent comes from `web.security` in the config, with the policy of the longest
+//! configured path prefix laid over the defaults, so deployments can serve the dashboard
+//! from their own origins and loosen individual endpoints (like the status page) without
+//! code changes. Nothing CORS related is sent unless origins are configured.
+
+use axum::{
+    http::{header, HeaderMap, HeaderValue, Method, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use common::prelude::config::{self, HeaderPolicy};
+
+use super::{
+    deprecation::{DEPRECATION_HEADER, SUNSET_HEADER},
+    identity::{ROLE_HEADER, USER_HEADER},
+    listing::NEXT_CURSOR_HEADER,
+};
+
+/// How long browsers may cache the answer to a preflight request
+const PREFLIGHT_MAX_AGE_SECS: u64 = 600;
+
+const ALLOWED_METHODS: &str = "GET, POST, PUT, PATCH, DELETE, OPTIONS";
+
+/// Say who a request is made for, so only the dashboard's server may send them, never a browser
+const IDENTITY_HEADERS: [&str; 2] = [USER_HEADER, ROLE_HEADER];
+
+/// What `Access-Control-Allow-Headers` should say, the configured headers without the
+/// identity ones
+fn allowed_headers(policy: &HeaderPolicy) -> Option<HeaderValue> {
+    let allowed = policy
+        .allowed_headers
+        .clone()
+        .unwrap_or_else(|| vec![header::CONTENT_TYPE.to_string()])
+        .into_iter()
+        .filter(|h| {
+            !IDENTITY_HEADERS
+                .iter()
+                .any(|i| i.eq_ignore_ascii_case(h.trim()))
+        })
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    HeaderValue::from_str(&allowed).ok()
+}
+
+/// What `Access-Control-Allow-Origin` should say to a request from `origin`, if it is allowed
+fn allowed_origin(policy: &HeaderPolicy, origin: &str) -> Option<HeaderValue> {
+    let origins = policy.allowed_origins.as_ref()?;
+    let credentials = policy.allow_credentials.unwrap_or(false);
+
+    // browsers refuse a wildcard on requests with credentials, so those get the origin back
+    if origins.iter().any(|o| o == "*") && !credentials {
+        return Some(HeaderValue::from_static("*"));
+    }
+
+    origins
+        .iter()
+        .any(|o| o.trim_end_matches('/').eq_ignore_ascii_case(origin))
+        .then(|| HeaderValue::from_str(origin).ok())
+        .flatten()
+}
+
+fn insert_cors(headers: &mut HeaderMap, policy: &HeaderPolicy, origin: HeaderValue) {
+    headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, origin);
+    headers.append(header::VARY, HeaderValue::from_static("Origin"));
+    // the dashboard can't see deprecation warnings unless they are exposed
+    let exposed = [
+        NEXT_CURSOR_HEADER,
+        DEPRECATION_HEADER,
+        SUNSET_HEADER,
+        header::LINK.as_str(),
+    ]
+    .join(", ");
+    if let Ok(exposed) = HeaderValue::from_str(&exposed) {
+        headers.insert(header::ACCESS_CONTROL_EXPOSE_HEADERS, exposed);
+    }
+    if policy.allow_credentials.unwrap_or(false) {
+        headers.insert(
+            header::ACCESS_CONTROL_ALLOW_CREDENTIALS,
+            HeaderValue::from_static("true"),
+        );
+    }
+}
+
+/// Adds whatever security headers the handler didn't set itself
+fn insert_security(headers: &mut HeaderMap, policy: &HeaderPolicy) {
+    let mut set = |name, value: Option<String>| {
+        if let Some(value) = value.and_then(|v| HeaderValue::from_str(&v).ok()) {
+            headers.entry(name).or_insert(value);
+        }
+    };
+
+    set(header::X_CONTENT_TYPE_OPTIONS, Some("nosniff".to_owned()));
+    set(
+        header::X_FRAME_OPTIONS,
+        Some(policy.frame_options.clone().unwrap_or("DENY".to_owned())),
+    );
+    set(
+        header::REFERRER_POLICY,
+        Some(
+            policy
+                .referrer_policy
+                .clone()
+                .unwrap_or("no-referrer".to_owned()),
+        ),
+    );
+    set(
+        header::CONTENT_SECURITY_POLICY,
+        policy.content_security_policy.clone(),
+    );
+    set(
+        header::STRICT_TRANSPORT_SECURITY,
+        policy
+            .hsts_max_age_secs
+            .map(|age| format!("max-age={age}; includeSubDomains")),
+    );
+}
+
+pub async fn headers<B>(request: Request<B>, next: Next<B>) -> Response {
+    let policy = config::settings()
+        .web
+        .security
+        .for_path(request.uri().path());
+
+    let origin = request
+        .headers()
+        .get(header::ORIGIN)
+        .and_then(|o| o.to_str().ok())
+        .and_then(|o| allowed_origin(&policy, o));
+
+    let preflight = request.method() == Method::OPTIONS
+        && request
+            .headers()
+            .contains_key(header::ACCESS_CONTROL_REQUEST_METHOD);
+
+    let mut response = match preflight {
+        true => {
+            let mut response = StatusCode::NO_CONTENT.into_response();
+            if origin.is_some() {
+                let headers = response.headers_mut();
+                headers.insert(
+                    header::ACCESS_CONTROL_ALLOW_METHODS,
+                    HeaderValue::from_static(ALLOWED_METHODS),
+                );
+                if let Some(allowed) = allowed_headers(&policy) {
+                    headers.insert(header::ACCESS_CONTROL_ALLOW_HEADERS, allowed);
+                }
+                headers.insert(
+                    header::ACCESS_CONTROL_MAX_AGE,
+                    HeaderValue::from(PREFLIGHT_MAX_AGE_SECS),
+                );
+            }
+            response
+        }
+        false => next.run(request).await,
+    };
+
+    if let Some(origin) = origin {
+        insert_cors(response.headers_mut(), &policy, origin);
+    }
+    insert_security(response.headers_mut(), &policy);
+
+    response
+}
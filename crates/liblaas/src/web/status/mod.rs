@@ -0,0 +1,354 @@
+//! A public summary of whether each part of LibLaaS is working
+//!
+//! Users check this before filing a ticket about the lab being broken, so it needs no
+//! credentials and is served both as JSON and as a plain page. Checking every component
+//! takes a few round trips, so the summary is only worked out again once it is
+//! [`CACHE_FOR`] old.
+
+use std::{
+    fmt::Write,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use super::{AppState, WebError};
+use aide::axum::{routing::get, ApiRouter};
+use axum::{extract::Json, response::Html};
+use common::prelude::{
+    chrono::{DateTime, Utc},
+    *,
+};
+use dal::{new_client, AsEasyTransaction};
+use models::dashboard::ProvisionLogEvent;
+use notifications::email::mail_server_reachable;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use workflows::entry::DISPATCH;
+
+/// How long a summary is served before the components are checked again
+const CACHE_FOR: Duration = Duration::from_secs(30);
+
+/// The longest any one check may take before its component counts as down
+const CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Database round trips slower than this mean the database is struggling
+const SLOW_DATABASE: Duration = Duration::from_secs(1);
+
+/// Provisioning is degraded when fewer than this share of the last day's deploys went through...
+const PROVISIONING_DEGRADED_BELOW: f64 = 0.9;
+/// ...and in an outage when fewer than this share did
+const PROVISIONING_OUTAGE_BELOW: f64 = 0.5;
+
+static CACHE: Mutex<Option<(Instant, StatusSummary)>> = Mutex::new(None);
+
+pub fn routes(_state: AppState) -> ApiRouter {
+    ApiRouter::new()
+        .route("/", get(status))
+        .route("/page", get(status_page))
+}
+
+/// Ordered from best to worst, so the state of the whole service is the greatest of its components'
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize, JsonSchema,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum ComponentState {
+    Operational,
+    Degraded,
+    Outage,
+}
+
+impl ComponentState {
+    fn describe(&self) -> &'static str {
+        match self {
+            Self::Operational => "Operational",
+            Self::Degraded => "Degraded",
+            Self::Outage => "Outage",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ComponentStatus {
+    pub name: String,
+    pub state: ComponentState,
+    pub detail: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct StatusSummary {
+    /// The worst state of any component
+    pub state: ComponentState,
+    pub components: Vec<ComponentStatus>,
+    /// Share of the instances deployed over the last day that finished provisioning, None if there were none
+    pub provisioning_success_rate: Option<f64>,
+    #[schemars(with = "String")]
+    pub generated: DateTime<Utc>,
+}
+
+fn component(name: &str, state: ComponentState, detail: String) -> ComponentStatus {
+    ComponentStatus {
+        name: name.to_owned(),
+        state,
+        detail,
+    }
+}
+
+async fn check_database() -> ComponentStatus {
+    let started = Instant::now();
+    let result = tokio::time::timeout(CHECK_TIMEOUT, async {
+        let client = new_client().await?;
+        client.query_one("SELECT 1;", &[]).await?;
+
+        Ok::<_, anyhow::Error>(())
+    })
+    .await;
+
+    let elapsed = started.elapsed();
+    match result {
+        Ok(Ok(())) if elapsed > SLOW_DATABASE => component(
+            "Database",
+            ComponentState::Degraded,
+            format!("answering slowly, in {}ms", elapsed.as_millis()),
+        ),
+        Ok(Ok(())) => component(
+            "Database",
+            ComponentState::Operational,
+            format!("answering in {}ms", elapsed.as_millis()),
+        ),
+        Ok(Err(e)) => {
+            tracing::warn!("Status check couldn't reach the database: {e:?}");
+            component("Database", ComponentState::Outage, "unreachable".to_owned())
+        }
+        Err(_) => component(
+            "Database",
+            ComponentState::Outage,
+            "didn't answer in time".to_owned(),
+        ),
+    }
+}
+
+fn check_dispatcher() -> ComponentStatus {
+    match DISPATCH.get() {
+        Some(_) => component(
+            "Dispatcher",
+            ComponentState::Operational,
+            "accepting bookings to provision and clean up".to_owned(),
+        ),
+        None => component(
+            "Dispatcher",
+            ComponentState::Outage,
+            "not running, bookings can't be provisioned or cleaned up".to_owned(),
+        ),
+    }
+}
+
+async fn check_mail() -> ComponentStatus {
+    let result = tokio::time::timeout(
+        CHECK_TIMEOUT,
+        tokio::task::spawn_blocking(mail_server_reachable),
+    )
+    .await;
+
+    match result {
+        Ok(Ok(Ok(true))) => component(
+            "Mail",
+            ComponentState::Operational,
+            "the mail relay is answering".to_owned(),
+        ),
+        Ok(Ok(Ok(false))) => component(
+            "Mail",
+            ComponentState::Degraded,
+            "the mail relay is reachable but not answering, notifications may be delayed"
+                .to_owned(),
+        ),
+        Ok(Ok(Err(e))) => {
+            tracing::warn!("Status check couldn't reach the mail relay: {e:?}");
+            component(
+                "Mail",
+                ComponentState::Outage,
+                "the mail relay is unreachable, notifications aren't being sent".to_owned(),
+            )
+        }
+        Ok(Err(_)) | Err(_) => component(
+            "Mail",
+            ComponentState::Outage,
+            "the mail relay didn't answer in time".to_owned(),
+        ),
+    }
+}
+
+async fn check_provisioning() -> (ComponentStatus, Option<f64>) {
+    let outcomes = tokio::time::timeout(CHECK_TIMEOUT, async {
+        let mut client = new_client().await?;
+        let mut transaction = client.easy_transaction().await?;
+
+        let outcomes = ProvisionLogEvent::outcomes_since(
+            &mut transaction,
+            Utc::now() - chrono::Duration::hours(24),
+        )
+        .await?;
+
+        transaction.commit().await?;
+
+        Ok::<_, anyhow::Error>(outcomes)
+    })
+    .await;
+
+    let (succeeded, failed) = match outcomes {
+        Ok(Ok(outcomes)) => outcomes,
+        Ok(Err(e)) => {
+            tracing::warn!("Status check couldn't count provisioning outcomes: {e:?}");
+            return (
+                component(
+                    "Provisioning",
+                    ComponentState::Degraded,
+                    "couldn't work out how recent deploys went".to_owned(),
+                ),
+                None,
+            );
+        }
+        Err(_) => {
+            return (
+                component(
+                    "Provisioning",
+                    ComponentState::Degraded,
+                    "couldn't work out how recent deploys went in time".to_owned(),
+                ),
+                None,
+            )
+        }
+    };
+
+    if succeeded + failed == 0 {
+        return (
+            component(
+                "Provisioning",
+                ComponentState::Operational,
+                "no deploys in the last 24 hours".to_owned(),
+            ),
+            None,
+        );
+    }
+
+    let rate = succeeded as f64 / (succeeded + failed) as f64;
+    let state = if rate < PROVISIONING_OUTAGE_BELOW {
+        ComponentState::Outage
+    } else if rate < PROVISIONING_DEGRADED_BELOW {
+        ComponentState::Degraded
+    } else {
+        ComponentState::Operational
+    };
+
+    (
+        component(
+            "Provisioning",
+            state,
+            format!(
+                "{succeeded} of {} deploys in the last 24 hours succeeded",
+                succeeded + failed
+            ),
+        ),
+        Some(rate),
+    )
+}
+
+async fn summarize() -> StatusSummary {
+    let (database, mail, (provisioning, rate)) =
+        tokio::join!(check_database(), check_mail(), check_provisioning());
+
+    let components = vec![
+        // if this is being served, the API is up
+        component(
+            "API",
+            ComponentState::Operational,
+            "answering requests".to_owned(),
+        ),
+        check_dispatcher(),
+        database,
+        mail,
+        provisioning,
+    ];
+
+    StatusSummary {
+        state: components
+            .iter()
+            .map(|c| c.state)
+            .max()
+            .unwrap_or(ComponentState::Operational),
+        components,
+        provisioning_success_rate: rate,
+        generated: Utc::now(),
+    }
+}
+
+/// The cached summary if it is fresh enough, otherwise a new one
+async fn current() -> StatusSummary {
+    if let Some((at, summary)) = CACHE.lock().unwrap().as_ref() {
+        if at.elapsed() < CACHE_FOR {
+            return summary.clone();
+        }
+    }
+
+    let summary = summarize().await;
+    *CACHE.lock().unwrap() = Some((Instant::now(), summary.clone()));
+
+    summary
+}
+
+async fn status() -> Result<Json<StatusSummary>, WebError> {
+    Ok(Json(current().await))
+}
+
+/// The summary as a page, for people rather than monitoring
+async fn status_page() -> Html<String> {
+    let summary = current().await;
+
+    let rows = summary
+        .components
+        .iter()
+        .fold(String::new(), |mut rows, c| {
+            let _ = writeln!(
+                rows,
+                "<tr class=\"{:?}\"><td>{}</td><td>{}</td><td>{}</td></tr>",
+                c.state,
+                c.name,
+                c.state.describe(),
+                c.detail
+            );
+            rows
+        });
+
+    Html(format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<meta http-equiv="refresh" content="{refresh}">
+<title>LaaS Status</title>
+<style>
+body {{ font-family: sans-serif; max-width: 50em; margin: 2em auto; }}
+table {{ border-collapse: collapse; width: 100%; }}
+td {{ padding: 0.5em; border-bottom: 1px solid #ddd; }}
+.Operational td:nth-child(2) {{ color: #1a7f37; }}
+.Degraded td:nth-child(2) {{ color: #9a6700; }}
+.Outage td:nth-child(2) {{ color: #cf222e; }}
+</style>
+</head>
+<body>
+<h1>LaaS is {state}</h1>
+<table>
+{rows}</table>
+<p>Checked {generated}</p>
+</body>
+</html>
+"#,
+        refresh = CACHE_FOR.as_secs(),
+        state = match summary.state {
+            ComponentState::Operational => "operational",
+            ComponentState::Degraded => "degraded",
+            ComponentState::Outage => "having an outage",
+        },
+        generated = summary.generated.to_rfc2822(),
+    ))
+}
@@ -1,7 +1,7 @@
 //! Copyright (c) 2023 University of New Hampshire
 //! SPDX-License-Identifier: MIT
 
-use std::collections::HashMap;
+use std::collections::{hash_map::Entry, HashMap};
 
 use common::prelude::{itertools::Itertools, *};
 
@@ -14,23 +14,30 @@ use dal::{web::*, *};
 
 use models::{
     dashboard::{
-        self, BondGroupConfig, HostConfig, Network, NetworkBlob, Template, VlanConnectionConfig,
+        self, BondGroupConfig, HostConfig, Network, NetworkBlob, Template, TemplateAccess,
+        TemplateShare, VlanConnectionConfig,
     },
     inventory::{DataUnit, DataValue, Lab},
 };
 
 use axum::http::StatusCode;
+use workflows::deploy_booking::parameters as template_parameters;
 
 use crate::web::api::{self};
 
 use super::{
     api::{BondgroupBlob, ConnectionBlob, HostConfigBlob, InterfaceBlob, TemplateBlob},
+    listing::{Listed, Listing},
     AppState, WebError,
 };
 
+pub mod portable;
+pub mod share;
+
 pub async fn list_templates(
     Path((request_origin, username)): Path<(String, String)>,
-) -> Result<Json<Vec<TemplateBlob>>, WebError> {
+    listing: Listing,
+) -> Result<Listed<TemplateBlob>, WebError> {
     // Lists all templates available to a given user
     tracing::info!("API call to list_templates()");
 
@@ -46,7 +53,7 @@ pub async fn list_templates(
         .map(|t| (t.id.into_id(), t))
         .collect();
     tracing::debug!("public templates: {templates:?}");
-    let user_templates = Template::owned_by(t, username)
+    let user_templates = Template::owned_by(t, username.clone())
         .await
         .log_server_error("unable to get templates owned by user", true)?; // should we handle the error case?
     tracing::debug!("user templates: {user_templates:?}");
@@ -54,10 +61,32 @@ pub async fn list_templates(
         templates.insert(temp.id(), temp);
     }
 
+    let shares: HashMap<ID, TemplateShare> = TemplateShare::for_user(t, &username)
+        .await
+        .log_server_error("unable to get templates shared with user", true)?
+        .into_iter()
+        .map(|s| (s.template.into_id(), s.into_inner()))
+        .collect();
+    for share in shares.values() {
+        if let Entry::Vacant(e) = templates.entry(share.template.into_id()) {
+            e.insert(
+                share
+                    .template
+                    .get(t)
+                    .await
+                    .log_db_client_error()?
+                    .into_inner(),
+            );
+        }
+    }
+
     let mut template_blobs = Vec::new();
 
     for pair in templates {
         let template = pair.1;
+        let Some(access) = TemplateAccess::of(&template, &username, shares.get(&pair.0)) else {
+            continue;
+        };
         let Template {
             id,
             name,
@@ -68,6 +97,7 @@ pub async fn list_templates(
             networks,
             hosts,
             lab,
+            parameters,
         } = template;
 
         if !template.deleted {
@@ -81,6 +111,8 @@ pub async fn list_templates(
                     image,
                     cifile,
                     connections,
+                    constraints,
+                    kernel_args: _,
                 } = hc;
                 let port_profiles = flavor
                     .get(t)
@@ -152,6 +184,7 @@ pub async fn list_templates(
                     image,
                     cifile: cifiles,
                     bondgroups: bg_blobs,
+                    constraints,
                 };
                 host_blobs.push(hcb);
             }
@@ -174,6 +207,8 @@ pub async fn list_templates(
                 host_list: host_blobs,
                 networks: network_blobs,
                 lab_name: lab.name.clone(),
+                parameters,
+                access: Some(access),
             };
 
             tracing::debug!("Trying to add template: {name}");
@@ -189,7 +224,7 @@ pub async fn list_templates(
     }
 
     transaction.commit().await.log_db_client_error()?;
-    Ok(Json(template_blobs))
+    listing.apply(template_blobs)
 }
 
 #[axum::debug_handler]
@@ -227,6 +262,8 @@ pub async fn make_template(
         host_list,
         networks,
         lab_name,
+        parameters,
+        access: _,
     } = blob;
 
     // discard the id field, since it's meaningless in this context
@@ -277,6 +314,7 @@ pub async fn make_template(
             image,
             cifile,
             bondgroups,
+            constraints,
         } = blob;
 
         let mut bg_configs = Vec::new();
@@ -326,11 +364,17 @@ pub async fn make_template(
             image,
             cifile,
             connections: bg_configs,
+            constraints,
+            kernel_args: Vec::new(),
         };
 
         db_host_configs.push(host);
     }
 
+    let network_names = net_ids.keys().cloned().collect_vec();
+    template_parameters::check(&parameters, &db_host_configs, &network_names)
+        .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
+
     let template = NewRow::new(Template {
         id: FKey::new_id_dangling(),
         name: pod_name,
@@ -345,6 +389,7 @@ pub async fn make_template(
             .expect("Expected to find lab")
             .expect("Expected that lab exists")
             .id,
+        parameters,
     });
 
     let template_fk = template
@@ -361,5 +406,15 @@ pub fn routes(state: AppState) -> ApiRouter {
     return ApiRouter::new()
         .route("/list/:lab_name/:user_id", get(list_templates))
         .route("/:template_id", delete(delete_template))
+        .route("/:template_id/export", get(portable::export_template))
+        .route("/import", post(portable::import_template))
+        .route(
+            "/:template_id/share",
+            get(share::list_shares).post(share::share_template),
+        )
+        .route(
+            "/:template_id/share/:username",
+            delete(share::unshare_template),
+        )
         .route("/:lab_name/create", post(make_template));
 }
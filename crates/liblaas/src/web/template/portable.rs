@@ -0,0 +1,497 @@
+//! Sharing templates between LaaS deployments as YAML
+//!
+//! An exported template names its flavors, images and networks rather than giving ids,
+//! so it can be imported into any lab that has flavors and images of the same names, or
+//! kept in git alongside the rest of a lab's configuration. Who owns the template and
+//! which lab it is for are given when importing, not kept in the file. A file looks like
+//!
+//! ```yaml
+//! version: 1
+//! name: two-node-k8s
+//! description: A control plane and a worker on one network
+//! public: false
+//! networks:
+//!   - name: cluster
+//!     public: false
+//! hosts:
+//!   - hostname: control
+//!     flavor: hpe-gen10
+//!     image: ubuntu-22.04
+//!     cifiles: []
+//!     bondgroups:
+//!       - interfaces: [ens1f0]
+//!         connections:
+//!           - network: cluster
+//!             tagged: false
+//!   - hostname: worker
+//!     flavor: hpe-gen10
+//!     image: ubuntu-22.04
+//!     bondgroups:
+//!       - interfaces: [ens1f0]
+//!         connections:
+//!           - network: cluster
+//!             tagged: false
+//! parameters:
+//!   - name: workers
+//!     description: How many workers to book
+//!     kind:
+//!       type: host_count
+//!       hostname: worker
+//!       min: 1
+//!       max: 4
+//!       default: 1
+//! ```
+//!
+//! `version` is bumped whenever the format changes incompatibly, and files of a version
+//! this deployment doesn't know are refused rather than half understood.
+
+use std::collections::HashMap;
+
+use axum::{
+    extract::{Path, Query},
+    http::{header, StatusCode},
+    Json,
+};
+use common::prelude::{itertools::Itertools, *};
+use dal::{web::*, *};
+use models::{
+    dashboard::{
+        BondGroupConfig, Cifile, HostConfig, HostConstraints, Image, Network, NetworkBlob,
+        ParameterKind, Template, TemplateParameter, VlanConnectionConfig,
+    },
+    inventory::{Flavor, Lab},
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use workflows::deploy_booking::parameters as template_parameters;
+
+use crate::web::WebError;
+
+/// The version of the format templates are exported in
+pub const PORTABLE_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct PortableTemplate {
+    pub version: u32,
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub public: bool,
+    #[serde(default)]
+    pub networks: Vec<NetworkBlob>,
+    pub hosts: Vec<PortableHost>,
+    #[serde(default)]
+    pub parameters: Vec<PortableParameter>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct PortableHost {
+    pub hostname: String,
+    /// The name of the flavor the host is booked as
+    pub flavor: String,
+    /// The name of the image the host is provisioned with
+    pub image: String,
+    /// The contents of each ci file, applied in order
+    #[serde(default)]
+    pub cifiles: Vec<String>,
+    #[serde(default)]
+    pub bondgroups: Vec<PortableBondGroup>,
+    /// What any host filling the role has to have, if it needn't be of `flavor`
+    #[serde(default)]
+    pub constraints: Option<HostConstraints>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct PortableBondGroup {
+    /// Names of the flavor's interfaces that are bonded together
+    pub interfaces: Vec<String>,
+    pub connections: Vec<PortableConnection>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct PortableConnection {
+    /// The name of one of the template's networks
+    pub network: String,
+    pub tagged: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct PortableParameter {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    pub kind: PortableParameterKind,
+}
+
+/// Like [`ParameterKind`], with images given by name
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PortableParameterKind {
+    HostCount {
+        hostname: String,
+        min: u32,
+        max: u32,
+        default: u32,
+    },
+    Image {
+        #[serde(default)]
+        hostnames: Vec<String>,
+        choices: Vec<String>,
+        default: String,
+    },
+    VlanCount {
+        network: String,
+        min: u32,
+        max: u32,
+        default: u32,
+    },
+}
+
+async fn image_name(t: &mut EasyTransaction<'_>, image: FKey<Image>) -> Result<String, WebError> {
+    Ok(image.get(t).await.log_db_client_error()?.name.clone())
+}
+
+async fn image_named(t: &mut EasyTransaction<'_>, name: &str) -> Result<FKey<Image>, WebError> {
+    Image::lookup(t, vec![name.to_owned()])
+        .await
+        .map(|i| i.id)
+        .map_err(|_| {
+            (
+                StatusCode::BAD_REQUEST,
+                format!("There is no image named {name} here"),
+            )
+        })
+}
+
+async fn to_portable(
+    t: &mut EasyTransaction<'_>,
+    template: &Template,
+) -> Result<PortableTemplate, WebError> {
+    let mut networks = Vec::new();
+    let mut network_names = HashMap::new();
+    for network in template.networks.iter() {
+        let net = network.get(t).await.log_db_client_error()?.into_inner();
+        network_names.insert(net.id, net.name.clone());
+        networks.push(NetworkBlob {
+            name: net.name,
+            public: net.public,
+        });
+    }
+
+    let mut hosts = Vec::new();
+    for host in template.hosts.iter() {
+        let flavor = host.flavor.get(t).await.log_db_client_error()?.name.clone();
+
+        let mut cifiles = Vec::new();
+        for cifile in host.cifile.iter() {
+            cifiles.push(cifile.get(t).await.log_db_client_error()?.data.clone());
+        }
+
+        let bondgroups = host
+            .connections
+            .iter()
+            .map(|bg| PortableBondGroup {
+                interfaces: bg.member_interfaces.iter().cloned().sorted().collect(),
+                connections: bg
+                    .connects_to
+                    .iter()
+                    .map(|c| PortableConnection {
+                        network: network_names.get(&c.network).cloned().unwrap_or_default(),
+                        tagged: c.tagged,
+                    })
+                    .sorted_by(|a, b| a.network.cmp(&b.network))
+                    .collect(),
+            })
+            .collect();
+
+        hosts.push(PortableHost {
+            hostname: host.hostname.clone(),
+            flavor,
+            image: image_name(t, host.image).await?,
+            cifiles,
+            bondgroups,
+            constraints: host.constraints.clone(),
+        });
+    }
+
+    let mut parameters = Vec::new();
+    for parameter in template.parameters.iter() {
+        let kind = match &parameter.kind {
+            ParameterKind::HostCount {
+                hostname,
+                min,
+                max,
+                default,
+            } => PortableParameterKind::HostCount {
+                hostname: hostname.clone(),
+                min: *min,
+                max: *max,
+                default: *default,
+            },
+            ParameterKind::Image {
+                hostnames,
+                choices,
+                default,
+            } => {
+                let mut names = Vec::new();
+                for choice in choices {
+                    names.push(image_name(t, *choice).await?);
+                }
+
+                PortableParameterKind::Image {
+                    hostnames: hostnames.clone(),
+                    choices: names,
+                    default: image_name(t, *default).await?,
+                }
+            }
+            ParameterKind::VlanCount {
+                network,
+                min,
+                max,
+                default,
+            } => PortableParameterKind::VlanCount {
+                network: network.clone(),
+                min: *min,
+                max: *max,
+                default: *default,
+            },
+        };
+
+        parameters.push(PortableParameter {
+            name: parameter.name.clone(),
+            description: parameter.description.clone(),
+            kind,
+        });
+    }
+
+    Ok(PortableTemplate {
+        version: PORTABLE_VERSION,
+        name: template.name.clone(),
+        description: template.description.clone(),
+        public: template.public,
+        networks,
+        hosts,
+        parameters,
+    })
+}
+
+#[axum::debug_handler]
+pub async fn export_template(
+    Path(template_id): Path<FKey<Template>>,
+) -> Result<([(header::HeaderName, &'static str); 1], String), WebError> {
+    tracing::info!("API call to export_template() for {template_id:?}");
+
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+    let template = template_id.get(&mut transaction).await.log_error(
+        StatusCode::NOT_FOUND,
+        "No template with that id",
+        true,
+    )?;
+    if template.deleted {
+        return Err((StatusCode::NOT_FOUND, "The template was deleted".to_owned()));
+    }
+
+    let portable = to_portable(&mut transaction, &template).await?;
+
+    transaction.commit().await.log_db_client_error()?;
+
+    let yaml = serde_yaml::to_string(&portable)
+        .anyway()
+        .log_server_error("Unable to write out the template", true)?;
+
+    Ok(([(header::CONTENT_TYPE, "application/yaml")], yaml))
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ImportParams {
+    /// The lab the template is imported into
+    pub lab: String,
+    pub owner: String,
+}
+
+#[axum::debug_handler]
+pub async fn import_template(
+    Query(params): Query<ImportParams>,
+    body: String,
+) -> Result<Json<FKey<Template>>, WebError> {
+    tracing::info!("API call to import_template() into {}", params.lab);
+
+    let portable: PortableTemplate = serde_yaml::from_str(&body).map_err(|e| {
+        (
+            StatusCode::BAD_REQUEST,
+            format!("The template isn't valid: {e}"),
+        )
+    })?;
+    if portable.version != PORTABLE_VERSION {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!(
+                "The template is version {}, only version {PORTABLE_VERSION} can be imported",
+                portable.version
+            ),
+        ));
+    }
+
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+    let lab = Lab::get_by_name(&mut transaction, params.lab.clone())
+        .await
+        .log_db_client_error()?
+        .ok_or((
+            StatusCode::NOT_FOUND,
+            format!("There is no lab named {}", params.lab),
+        ))?
+        .id;
+
+    let mut networks = Vec::new();
+    let mut net_ids = HashMap::new();
+    for NetworkBlob { name, public } in portable.networks {
+        if net_ids.contains_key(&name) {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                format!("There is more than one network named {name}"),
+            ));
+        }
+
+        let id = NewRow::new(Network {
+            id: FKey::new_id_dangling(),
+            name: name.clone(),
+            public,
+        })
+        .insert(&mut transaction)
+        .await
+        .log_server_error("unable to insert network into db", true)?;
+
+        net_ids.insert(name, id);
+        networks.push(id);
+    }
+
+    let mut hosts = Vec::new();
+    for host in portable.hosts {
+        let flavor = Flavor::lookup(&mut transaction, vec![host.flavor.clone()])
+            .await
+            .map_err(|_| {
+                (
+                    StatusCode::BAD_REQUEST,
+                    format!("There is no flavor named {} here", host.flavor),
+                )
+            })?
+            .id;
+        let image = image_named(&mut transaction, &host.image).await?;
+
+        let mut connections = Vec::new();
+        for bondgroup in host.bondgroups {
+            let mut bgc = BondGroupConfig::default();
+            bgc.member_interfaces.extend(bondgroup.interfaces);
+
+            for connection in bondgroup.connections {
+                let network = *net_ids.get(&connection.network).ok_or((
+                    StatusCode::BAD_REQUEST,
+                    format!(
+                        "{} connects to {}, which isn't one of the template's networks",
+                        host.hostname, connection.network
+                    ),
+                ))?;
+
+                bgc.connects_to.insert(VlanConnectionConfig {
+                    network,
+                    tagged: connection.tagged,
+                });
+            }
+
+            connections.push(bgc);
+        }
+
+        let cifile = Cifile::new(&mut transaction, host.cifiles)
+            .await
+            .log_server_error("unable to create CI file", true)?;
+
+        hosts.push(HostConfig {
+            hostname: host.hostname,
+            flavor,
+            image,
+            cifile,
+            connections,
+            constraints: host.constraints,
+            kernel_args: Vec::new(),
+        });
+    }
+
+    let mut parameters = Vec::new();
+    for parameter in portable.parameters {
+        let kind = match parameter.kind {
+            PortableParameterKind::HostCount {
+                hostname,
+                min,
+                max,
+                default,
+            } => ParameterKind::HostCount {
+                hostname,
+                min,
+                max,
+                default,
+            },
+            PortableParameterKind::Image {
+                hostnames,
+                choices,
+                default,
+            } => {
+                let mut images = Vec::new();
+                for choice in choices.iter() {
+                    images.push(image_named(&mut transaction, choice).await?);
+                }
+
+                ParameterKind::Image {
+                    hostnames,
+                    choices: images,
+                    default: image_named(&mut transaction, &default).await?,
+                }
+            }
+            PortableParameterKind::VlanCount {
+                network,
+                min,
+                max,
+                default,
+            } => ParameterKind::VlanCount {
+                network,
+                min,
+                max,
+                default,
+            },
+        };
+
+        parameters.push(TemplateParameter {
+            name: parameter.name,
+            description: parameter.description,
+            kind,
+        });
+    }
+
+    let network_names = net_ids.keys().cloned().collect_vec();
+    template_parameters::check(&parameters, &hosts, &network_names)
+        .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
+
+    let template = NewRow::new(Template {
+        id: FKey::new_id_dangling(),
+        name: portable.name,
+        deleted: false,
+        description: portable.description,
+        owner: Some(params.owner),
+        public: portable.public,
+        networks,
+        hosts,
+        lab,
+        parameters,
+    })
+    .insert(&mut transaction)
+    .await
+    .log_db_client_error()?;
+
+    transaction.commit().await.log_db_client_error()?;
+
+    Ok(Json(template))
+}
@@ -0,0 +1,182 @@
+//! Lets owners share a template with specific users, to only see it or also book from it
+
+use axum::{
+    extract::{Json, Path},
+    http::StatusCode,
+};
+use common::prelude::chrono::{DateTime, Utc};
+use dal::{new_client, web::*, AsEasyTransaction, EasyTransaction, ExistingRow, FKey, NewRow};
+use models::dashboard::{SharePermission, Template, TemplateShare};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::web::{identity::User, WebError};
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ShareTemplateRequest {
+    users: Vec<String>,
+    permission: SharePermission,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct TemplateShareBlob {
+    username: String,
+    permission: SharePermission,
+    shared_by: String,
+    #[schemars(with = "String")]
+    shared: DateTime<Utc>,
+}
+
+impl From<&TemplateShare> for TemplateShareBlob {
+    fn from(s: &TemplateShare) -> Self {
+        Self {
+            username: s.username.clone(),
+            permission: s.permission,
+            shared_by: s.shared_by.clone(),
+            shared: s.shared,
+        }
+    }
+}
+
+/// Gets the template, refusing anyone but its owner
+async fn owned_template(
+    transaction: &mut EasyTransaction<'_>,
+    template_id: FKey<Template>,
+    by: &str,
+) -> Result<ExistingRow<Template>, WebError> {
+    let template = template_id.get(transaction).await.log_error(
+        StatusCode::NOT_FOUND,
+        "Template does not exist",
+        true,
+    )?;
+
+    if template.owner.as_deref() != Some(by) {
+        return Err((
+            StatusCode::FORBIDDEN,
+            "Only the owner of a template can change who it is shared with".to_owned(),
+        ));
+    }
+
+    Ok(template)
+}
+
+async fn shares_of(
+    transaction: &mut EasyTransaction<'_>,
+    template_id: FKey<Template>,
+) -> Result<Vec<TemplateShareBlob>, WebError> {
+    Ok(TemplateShare::for_template(transaction, template_id)
+        .await
+        .log_db_client_error()?
+        .iter()
+        .map(|s| (&**s).into())
+        .collect())
+}
+
+#[axum::debug_handler]
+pub async fn list_shares(
+    Path(template_id): Path<FKey<Template>>,
+) -> Result<Json<Vec<TemplateShareBlob>>, WebError> {
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+    template_id.get(&mut transaction).await.log_error(
+        StatusCode::NOT_FOUND,
+        "Template does not exist",
+        true,
+    )?;
+    let shares = shares_of(&mut transaction, template_id).await?;
+
+    transaction.commit().await.log_db_client_error()?;
+
+    Ok(Json(shares))
+}
+
+/// Shares the template with each of the users, replacing what they were allowed before
+#[axum::debug_handler]
+pub async fn share_template(
+    by: User,
+    Path(template_id): Path<FKey<Template>>,
+    Json(request): Json<ShareTemplateRequest>,
+) -> Result<Json<Vec<TemplateShareBlob>>, WebError> {
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+    let template = owned_template(&mut transaction, template_id, &by.name).await?;
+    if template.deleted {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "That template has been deleted".to_owned(),
+        ));
+    }
+
+    let now = Utc::now();
+    for username in request.users {
+        // the owner can already do everything with it
+        if template.owner.as_deref() == Some(username.as_str()) {
+            continue;
+        }
+
+        match TemplateShare::find(&mut transaction, template_id, &username)
+            .await
+            .log_db_client_error()?
+        {
+            Some(mut share) => {
+                share.permission = request.permission;
+                share.shared_by = by.name.clone();
+                share.shared = now;
+                share
+                    .update(&mut transaction)
+                    .await
+                    .log_server_error("Unable to update template share", true)?;
+            }
+            None => {
+                NewRow::new(TemplateShare {
+                    id: FKey::new_id_dangling(),
+                    template: template_id,
+                    username,
+                    permission: request.permission,
+                    shared_by: by.name.clone(),
+                    shared: now,
+                })
+                .insert(&mut transaction)
+                .await
+                .log_server_error("Unable to share template", true)?;
+            }
+        }
+    }
+
+    let shares = shares_of(&mut transaction, template_id).await?;
+
+    transaction.commit().await.log_db_client_error()?;
+
+    Ok(Json(shares))
+}
+
+#[axum::debug_handler]
+pub async fn unshare_template(
+    by: User,
+    Path((template_id, username)): Path<(FKey<Template>, String)>,
+) -> Result<Json<Vec<TemplateShareBlob>>, WebError> {
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+    owned_template(&mut transaction, template_id, &by.name).await?;
+
+    let share = TemplateShare::find(&mut transaction, template_id, &username)
+        .await
+        .log_db_client_error()?
+        .ok_or((
+            StatusCode::NOT_FOUND,
+            "The template isn't shared with that user".to_owned(),
+        ))?;
+    share
+        .delete(&mut transaction)
+        .await
+        .log_server_error("Unable to unshare template", true)?;
+
+    let shares = shares_of(&mut transaction, template_id).await?;
+
+    transaction.commit().await.log_db_client_error()?;
+
+    Ok(Json(shares))
+}
@@ -15,16 +15,19 @@ use axum::{
 };
 use axum_macros::debug_handler;
 use dal::{new_client, web::*, AsEasyTransaction, DBTable, ExistingRow, FKey, ID};
-use models::dashboard::{Aggregate, LifeCycleState};
+use models::dashboard::{Aggregate, BookingEvent, BookingEventKind, LifeCycleState};
+use notifications::contacts::{self, DeliveryOutcome};
 use schemars::JsonSchema;
 use thiserror::Error;
 
-use axum::http::StatusCode;
+use axum::http::{HeaderMap, StatusCode};
 use uuid::Uuid;
 use workflows::entry::DISPATCH;
 
 use super::{AppState, WebError};
 
+mod summary;
+
 // check ipa Acct
 // create ipa acct
 // set ssh key
@@ -52,6 +55,10 @@ pub async fn create_user(Json(user): Json<User>) -> Result<(), WebError> {
 
     match res {
         Ok(user) => {
+            if let Err(e) = contacts::verify(&user.uid).await {
+                tracing::warn!("New user {} can't be emailed: {e:?}", user.uid);
+            }
+
             let _ = notifications::send_new_account_notification(
                 &notifications::Env {
                     project: "anuket".to_owned(), // IPA is project independent. Any valid project name works here.
@@ -122,15 +129,93 @@ pub async fn set_email(
     Path(username): Path<String>,
     Json(email): Json<String>,
 ) -> Result<(), WebError> {
+    let email = email.trim().to_owned();
+    contacts::check_address(&email).log_error(
+        StatusCode::BAD_REQUEST,
+        "Email address is not valid",
+        true,
+    )?;
+
     let mut ipa = IPA::init()
         .await
         .log_server_error("Failed to connect to IPA", true)?;
-    ipa.update_user(username, vec![], vec![UserData::mail(Some(email))], false)
-        .await
-        .log_server_error("Failed to find user", true)?;
+    ipa.update_user(
+        username.clone(),
+        vec![],
+        vec![UserData::mail(Some(email))],
+        false,
+    )
+    .await
+    .log_server_error("Failed to find user", true)?;
+
+    // starts the bounce history over for the new address
+    contacts::verify(&username).await.log_error(
+        StatusCode::BAD_REQUEST,
+        "Email address is not valid",
+        true,
+    )?;
+
     Ok(())
 }
 
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+pub struct DeliveryEvent {
+    pub email: String,
+    pub outcome: DeliveryOutcome,
+    /// The bounce message from the receiving server, if any
+    pub reason: Option<String>,
+}
+
+pub const WEBHOOK_SECRET_HEADER: &str = "X-LaaS-Webhook-Secret";
+
+/// Whether `headers` carry the configured webhook secret, compared without short circuiting
+/// so how long it takes says nothing about how much of it was right
+fn has_webhook_secret(headers: &HeaderMap) -> bool {
+    let Some(secret) = config::settings()
+        .notifications
+        .delivery_webhook_secret
+        .as_deref()
+        .filter(|s| !s.is_empty())
+    else {
+        return false;
+    };
+    let Some(sent) = headers.get(WEBHOOK_SECRET_HEADER) else {
+        return false;
+    };
+
+    let (secret, sent) = (secret.as_bytes(), sent.as_bytes());
+    secret.len() == sent.len() && secret.iter().zip(sent).fold(0, |d, (a, b)| d | (a ^ b)) == 0
+}
+
+/// Takes delivery and bounce reports from the mail backend's webhook, which has to send
+/// the configured secret along with them
+#[debug_handler]
+pub async fn report_delivery_events(
+    headers: HeaderMap,
+    Json(events): Json<Vec<DeliveryEvent>>,
+) -> Result<Json<Vec<String>>, WebError> {
+    if !has_webhook_secret(&headers) {
+        return Err((
+            StatusCode::UNAUTHORIZED,
+            format!("Delivery reports have to carry the configured {WEBHOOK_SECRET_HEADER}"),
+        ));
+    }
+
+    let mut affected = Vec::new();
+
+    for event in events {
+        tracing::info!("Mail to {} was reported {:?}", event.email, event.outcome);
+
+        affected.extend(
+            contacts::record_event(&event.email, event.outcome, event.reason)
+                .await
+                .log_db_client_error()?,
+        );
+    }
+
+    Ok(Json(affected))
+}
+
 pub async fn request_password_reset(Path(username): Path<String>) -> Result<(), WebError> {
     todo!("password resets")
 }
@@ -148,6 +233,8 @@ pub enum UserApiError {
     EmptyUser,
     #[error("Aggregate has not finished provisioning.")]
     AggregateNotReady,
+    #[error("User has no valid email address.")]
+    InvalidEmail,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize, JsonSchema, OperationIo)]
@@ -158,9 +245,10 @@ pub struct AddUserRequestResponse {
 impl IntoResponse for UserApiError {
     fn into_response(self) -> Response {
         let (status, err_msg) = match self {
-            UserApiError::EmptyUser | UserApiError::InvalidId | UserApiError::AggregateNotReady => {
-                (StatusCode::BAD_REQUEST, self.to_string())
-            }
+            UserApiError::EmptyUser
+            | UserApiError::InvalidId
+            | UserApiError::AggregateNotReady
+            | UserApiError::InvalidEmail => (StatusCode::BAD_REQUEST, self.to_string()),
             UserApiError::DatabaseClient
             | UserApiError::Dispatch
             | UserApiError::DatabaseTransaction => {
@@ -203,6 +291,11 @@ pub async fn add_users_to_booking(
 
     for item in request.users {
         if !aggregate_row.users.contains(&item) {
+            contacts::verify(&item).await.map_err(|e| {
+                tracing::info!("Not adding {item} to booking: {e:?}");
+                UserApiError::InvalidEmail
+            })?;
+
             aggregate_row.users.push(item.clone());
             new_users.push(item);
         }
@@ -213,6 +306,18 @@ pub async fn add_users_to_booking(
         .await
         .map_err(|_| UserApiError::DatabaseTransaction)?;
 
+    if !new_users.is_empty() {
+        BookingEvent::record(
+            &mut transaction,
+            FKey::from_id(aggregate_row.id()),
+            BookingEventKind::CollaboratorAdded,
+            format!("{} added to the booking", new_users.join(", ")),
+            None,
+        )
+        .await
+        .map_err(|_| UserApiError::DatabaseTransaction)?;
+    }
+
     transaction
         .commit()
         .await
@@ -290,10 +395,12 @@ async fn get_many_users(Json(usernames): Json<Vec<String>>) -> Result<Json<Vec<U
 pub fn routes(state: AppState) -> ApiRouter {
     ApiRouter::new()
         .route("/:username", get(get_user))
+        .route("/me/summary", get(summary::get_my_summary))
         .route("/create", post(create_user))
         .route("/:username/ssh", post(set_ssh))
         .route("/:username/company", post(set_company))
         .route("/:username/email", post(set_email))
+        .route("/email/events", post(report_delivery_events))
         .route("/:aggregate_id/addusers", post(add_users_to_booking))
         .route("/many", post(get_many_users))
 }
@@ -0,0 +1,198 @@
+//! Everything the dashboard landing page shows about a user, in one call
+
+use std::collections::HashMap;
+
+use common::prelude::{
+    chrono::{Duration, Utc},
+    *,
+};
+
+use axum::Json;
+use dal::{new_client, web::*, AsEasyTransaction};
+use models::dashboard::{
+    Aggregate, ExtensionRequest, LifeCycleState, ProvisionLogEvent, StatusSentiment,
+};
+use schemars::JsonSchema;
+
+use crate::web::{identity::User, WebError};
+
+/// How far back failures are shown
+const RECENT_FAILURE_DAYS: i64 = 7;
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct BookingSummary {
+    pub aggregate: dal::FKey<Aggregate>,
+    pub booking_id: Option<String>,
+    pub display_name: Option<String>,
+    pub favorite: bool,
+    pub purpose: Option<String>,
+    pub project: Option<String>,
+    pub lab: Option<String>,
+    /// Whether the user owns the booking, as opposed to collaborating on it
+    pub owner: bool,
+    pub provisioning: bool,
+    pub hosts: usize,
+    pub start: Option<String>,
+    pub end: Option<String>,
+    pub hours_until_expiry: Option<f64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct PendingExtension {
+    pub aggregate: dal::FKey<Aggregate>,
+    pub booking_id: Option<String>,
+    pub date: String,
+    pub reason: String,
+    pub requested: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct Usage {
+    pub active_bookings: usize,
+    pub owned_bookings: usize,
+    pub hosts: usize,
+    /// Hosts in use, by the project of the booking they're in
+    pub hosts_by_project: HashMap<String, usize>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct RecentFailure {
+    pub aggregate: dal::FKey<Aggregate>,
+    pub booking_id: Option<String>,
+    pub hostname: String,
+    pub time: String,
+    pub headline: String,
+    pub details: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct UserSummary {
+    pub username: String,
+    pub active_bookings: Vec<BookingSummary>,
+    /// Extension requests made on bookings that are still active
+    pub pending_extensions: Vec<PendingExtension>,
+    pub usage: Usage,
+    pub recent_failures: Vec<RecentFailure>,
+}
+
+pub async fn get_my_summary(user: User) -> Result<Json<UserSummary>, WebError> {
+    let username = user.name;
+
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+    let now = Utc::now();
+    let failure_cutoff = now - Duration::days(RECENT_FAILURE_DAYS);
+
+    let mut summary = UserSummary {
+        username: username.clone(),
+        active_bookings: Vec::new(),
+        pending_extensions: Vec::new(),
+        usage: Usage::default(),
+        recent_failures: Vec::new(),
+    };
+    let mut failures = Vec::new();
+
+    for agg in Aggregate::all_for_user(&mut transaction, &username)
+        .await
+        .log_db_client_error()?
+    {
+        let active = !agg.state.ended();
+        let recently_ended = agg.metadata.end.is_some_and(|end| end > failure_cutoff);
+        if !active && !recently_ended {
+            continue;
+        }
+
+        let instances = agg
+            .instances(&mut transaction)
+            .await
+            .log_db_client_error()?;
+
+        for instance in instances.iter() {
+            for event in ProvisionLogEvent::all_for_instance(&mut transaction, instance.id)
+                .await
+                .log_db_client_error()?
+            {
+                if matches!(event.sentiment, StatusSentiment::Failed) && event.time > failure_cutoff
+                {
+                    failures.push((
+                        event.time,
+                        RecentFailure {
+                            aggregate: agg.id,
+                            booking_id: agg.metadata.booking_id.clone(),
+                            hostname: instance.config.hostname.clone(),
+                            time: event.time.to_rfc2822(),
+                            headline: event.prov_status.event.clone(),
+                            details: event.prov_status.details.clone(),
+                        },
+                    ));
+                }
+            }
+        }
+
+        if !active {
+            continue;
+        }
+
+        let owner = agg.metadata.owner.as_deref() == Some(username.as_str());
+
+        summary.usage.active_bookings += 1;
+        summary.usage.hosts += instances.len();
+        if owner {
+            summary.usage.owned_bookings += 1;
+        }
+        *summary
+            .usage
+            .hosts_by_project
+            .entry(agg.metadata.project.clone().unwrap_or("None".to_owned()))
+            .or_insert(0) += instances.len();
+
+        for request in ExtensionRequest::all_for_aggregate(&mut transaction, agg.id)
+            .await
+            .log_db_client_error()?
+            .into_iter()
+            .filter(|r| r.is_pending())
+        {
+            summary.pending_extensions.push(PendingExtension {
+                aggregate: agg.id,
+                booking_id: agg.metadata.booking_id.clone(),
+                date: request.date.clone(),
+                reason: request.reason.clone(),
+                requested: request.requested.to_rfc2822(),
+            });
+        }
+
+        summary.active_bookings.push(BookingSummary {
+            aggregate: agg.id,
+            booking_id: agg.metadata.booking_id.clone(),
+            display_name: agg.metadata.display_name.clone(),
+            favorite: agg.metadata.favorited_by.contains(&username),
+            purpose: agg.metadata.purpose.clone(),
+            project: agg.metadata.project.clone(),
+            lab: agg.metadata.lab.clone(),
+            owner,
+            provisioning: agg.state == LifeCycleState::New,
+            hosts: instances.len(),
+            start: agg.metadata.start.map(|s| s.to_rfc2822()),
+            end: agg.metadata.end.map(|e| e.to_rfc2822()),
+            hours_until_expiry: agg
+                .metadata
+                .end
+                .map(|e| (e - now).num_minutes() as f64 / 60.0),
+        });
+    }
+
+    transaction.commit().await.log_db_client_error()?;
+
+    // favorites first, then soonest to expire
+    summary.active_bookings.sort_by(|a, b| {
+        let expiry = |s: &BookingSummary| s.hours_until_expiry.unwrap_or(f64::INFINITY);
+        b.favorite
+            .cmp(&a.favorite)
+            .then(expiry(a).total_cmp(&expiry(b)))
+    });
+    failures.sort_by_key(|(time, _)| std::cmp::Reverse(*time));
+    summary.recent_failures = failures.into_iter().map(|(_, f)| f).collect();
+
+    Ok(Json(summary))
+}
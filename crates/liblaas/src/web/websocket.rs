@@ -0,0 +1,200 @@
+//! Just enough of WebSockets (RFC 6455) for the server to push a stream of bytes to a client
+//!
+//! The server sends binary messages, answers pings and closes when asked to. Anything else
+//! clients send is read and dropped, nothing takes input over these yet.
+
+use axum::{
+    body::Body,
+    http::{header, HeaderMap, HeaderValue, Request, StatusCode},
+    response::{IntoResponse, Response},
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use common::prelude::{
+    hyper::upgrade::{self, OnUpgrade},
+    tokio::{
+        self,
+        io::{self, AsyncRead, AsyncReadExt, AsyncWriteExt},
+        sync::{
+            broadcast::{self, error::RecvError},
+            mpsc,
+        },
+    },
+    tracing,
+};
+use sha1::{Digest, Sha1};
+
+/// What the key a client sends is hashed with to prove the server speaks WebSockets
+const ACCEPT_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// The largest frame clients may send, who have nothing to send but control frames
+const MAX_CLIENT_FRAME: u64 = 64 * 1024;
+
+pub const OPCODE_BINARY: u8 = 0x2;
+pub const OPCODE_CLOSE: u8 = 0x8;
+pub const OPCODE_PING: u8 = 0x9;
+pub const OPCODE_PONG: u8 = 0xA;
+
+/// The `Sec-WebSocket-Accept` the server answers the client's `Sec-WebSocket-Key` with
+pub fn accept_key(key: &str) -> String {
+    let mut hasher = Sha1::new();
+    hasher.update(key.as_bytes());
+    hasher.update(ACCEPT_GUID.as_bytes());
+
+    STANDARD.encode(hasher.finalize())
+}
+
+fn header_has(headers: &HeaderMap, name: header::HeaderName, token: &str) -> bool {
+    headers
+        .get_all(name)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .any(|v| v.trim().eq_ignore_ascii_case(token))
+}
+
+/// The response switching `request` over to a WebSocket, and the connection to speak it
+/// on once the client has switched, or why the request can't be switched
+pub fn upgrade(request: &mut Request<Body>) -> Result<(Response, OnUpgrade), String> {
+    let headers = request.headers();
+    if !header_has(headers, header::CONNECTION, "upgrade")
+        || !header_has(headers, header::UPGRADE, "websocket")
+    {
+        return Err("This endpoint has to be opened as a WebSocket".to_owned());
+    }
+    if !header_has(headers, header::SEC_WEBSOCKET_VERSION, "13") {
+        return Err("Only version 13 of WebSockets is spoken".to_owned());
+    }
+    let key = headers
+        .get(header::SEC_WEBSOCKET_KEY)
+        .and_then(|k| k.to_str().ok())
+        .ok_or("The WebSocket was opened without a key")?;
+    let accept = HeaderValue::from_str(&accept_key(key)).map_err(|e| e.to_string())?;
+
+    let on_upgrade = upgrade::on(request);
+
+    let response = (
+        StatusCode::SWITCHING_PROTOCOLS,
+        [
+            (header::CONNECTION, HeaderValue::from_static("upgrade")),
+            (header::UPGRADE, HeaderValue::from_static("websocket")),
+            (header::SEC_WEBSOCKET_ACCEPT, accept),
+        ],
+    )
+        .into_response();
+
+    Ok((response, on_upgrade))
+}
+
+/// A single unfragmented frame from the server, which never masks what it sends
+pub fn frame(opcode: u8, payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(payload.len() + 10);
+    frame.push(0x80 | opcode);
+
+    match payload.len() {
+        len @ 0..=125 => frame.push(len as u8),
+        len @ 126..=0xFFFF => {
+            frame.push(126);
+            frame.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            frame.push(127);
+            frame.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    frame.extend_from_slice(payload);
+
+    frame
+}
+
+/// A frame from a client, with its payload unmasked
+pub struct ClientFrame {
+    pub opcode: u8,
+    pub payload: Vec<u8>,
+}
+
+/// Reads the next frame the client sends
+pub async fn read_frame<R: AsyncRead + Unpin>(reader: &mut R) -> io::Result<ClientFrame> {
+    let mut head = [0u8; 2];
+    reader.read_exact(&mut head).await?;
+
+    let opcode = head[0] & 0x0F;
+    let masked = head[1] & 0x80 != 0;
+    let len = match head[1] & 0x7F {
+        126 => reader.read_u16().await? as u64,
+        127 => reader.read_u64().await?,
+        len => len as u64,
+    };
+    if len > MAX_CLIENT_FRAME {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("client sent a frame of {len} bytes"),
+        ));
+    }
+
+    let mut mask = [0u8; 4];
+    if masked {
+        reader.read_exact(&mut mask).await?;
+    }
+
+    let mut payload = vec![0u8; len as usize];
+    reader.read_exact(&mut payload).await?;
+    if masked {
+        for (i, byte) in payload.iter_mut().enumerate() {
+            *byte ^= mask[i % 4];
+        }
+    }
+
+    Ok(ClientFrame { opcode, payload })
+}
+
+/// Sends the client everything received on `output` once it has switched to the WebSocket,
+/// until either of them closes
+pub async fn serve(connection: OnUpgrade, mut output: broadcast::Receiver<Vec<u8>>) {
+    let socket = match connection.await {
+        Ok(socket) => socket,
+        Err(e) => {
+            tracing::warn!("Client never switched to the WebSocket: {e}");
+            return;
+        }
+    };
+    let (mut reader, mut writer) = io::split(socket);
+
+    // frames are read on their own, as reading one isn't safe to give up on halfway
+    let (control, mut controls) = mpsc::channel::<ClientFrame>(8);
+    let reading = tokio::spawn(async move {
+        while let Ok(frame) = read_frame(&mut reader).await {
+            let closing = frame.opcode == OPCODE_CLOSE;
+            if control.send(frame).await.is_err() || closing {
+                break;
+            }
+        }
+    });
+
+    loop {
+        let sent = tokio::select! {
+            received = output.recv() => match received {
+                Ok(bytes) => writer.write_all(&frame(OPCODE_BINARY, &bytes)).await,
+                Err(RecvError::Lagged(missed)) => {
+                    tracing::debug!("WebSocket client fell {missed} messages behind");
+                    Ok(())
+                }
+                Err(RecvError::Closed) => break,
+            },
+            control = controls.recv() => match control {
+                Some(ClientFrame { opcode: OPCODE_PING, payload }) => {
+                    writer.write_all(&frame(OPCODE_PONG, &payload)).await
+                }
+                Some(ClientFrame { opcode: OPCODE_CLOSE, .. }) | None => break,
+                Some(_) => Ok(()),
+            },
+        };
+
+        if sent.is_err() {
+            break;
+        }
+    }
+
+    let _ = writer.write_all(&frame(OPCODE_CLOSE, &[])).await;
+    let _ = writer.shutdown().await;
+    reading.abort();
+}
@@ -54,5 +54,7 @@ pub enum MetricMessage {
     Booking(BookingMetric),
     Provision(ProvisionMetric),
     BookingExpired(BookingExpiredMetric),
+    Orphan(OrphanMetric),
+    DeviceBreaker(DeviceBreakerMetric),
     // ...add additional metrics defined in the metrics module here
 }
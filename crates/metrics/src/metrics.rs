@@ -337,3 +337,88 @@ impl Timestampable for BookingExpiredMetric {
         self.ts = ts;
     }
 }
+
+/// Represents resources found still held by bookings that have ended, once per kind of
+/// resource each time the garbage collector runs
+#[derive(Metric, Default, Debug, Serialize, Deserialize, Clone)]
+#[measurement = "orphaned_resources"]
+pub struct OrphanMetric {
+    #[telegraf(timestamp)]
+    #[serde(default)]
+    pub ts: Timestamp,
+
+    /// **Tag:** What kind of resource leaked. ie. "vlan_allocation"
+    #[telegraf(tag)]
+    #[serde(default)]
+    pub kind: String,
+
+    /// **Tag:** Whether the run only reported orphans without cleaning them up.
+    #[telegraf(tag)]
+    #[serde(default)]
+    pub dry_run: bool,
+
+    /// **Field:** How many orphans of this kind were found.
+    #[telegraf(field)]
+    #[serde(default)]
+    pub found: i32,
+
+    /// **Field:** How many of them were cleaned up.
+    #[telegraf(field)]
+    #[serde(default)]
+    pub cleaned: i32,
+
+    #[telegraf(tag)]
+    #[serde(default)]
+    pub mock: bool,
+}
+
+impl Timestampable for OrphanMetric {
+    fn update(mut self) {
+        self.ts = Timestamp::now();
+    }
+    fn set(mut self, ts: Timestamp) {
+        self.ts = ts;
+    }
+}
+
+/// Represents a circuit breaker on calls to a BMC or switch tripping or closing again
+#[derive(Metric, Default, Debug, Serialize, Deserialize, Clone)]
+#[measurement = "device_breaker"]
+pub struct DeviceBreakerMetric {
+    #[telegraf(timestamp)]
+    #[serde(default)]
+    pub ts: Timestamp,
+
+    /// **Tag:** What kind of device is called. ie. "bmc" or "switch"
+    #[telegraf(tag)]
+    #[serde(default)]
+    pub kind: String,
+
+    /// **Tag:** The address of the device.
+    #[telegraf(tag)]
+    #[serde(default)]
+    pub device: String,
+
+    /// **Tag:** Whether calls to the device are now being refused.
+    #[telegraf(tag)]
+    #[serde(default)]
+    pub open: bool,
+
+    /// **Field:** How many calls to the device had failed in a row.
+    #[telegraf(field)]
+    #[serde(default)]
+    pub consecutive_failures: i32,
+
+    #[telegraf(tag)]
+    #[serde(default)]
+    pub mock: bool,
+}
+
+impl Timestampable for DeviceBreakerMetric {
+    fn update(mut self) {
+        self.ts = Timestamp::now();
+    }
+    fn set(mut self, ts: Timestamp) {
+        self.ts = ts;
+    }
+}
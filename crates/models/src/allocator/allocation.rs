@@ -127,6 +127,18 @@ impl Allocation {
         Self::from_rows(rows)
     }
 
+    /// Every allocation that hasn't ended yet, for any resource
+    pub async fn all_live(
+        t: &mut EasyTransaction<'_>,
+    ) -> Result<Vec<ExistingRow<Allocation>>, anyhow::Error> {
+        let tn = Self::table_name();
+
+        let q = format!("SELECT * FROM {tn} WHERE ended IS NULL;");
+
+        let rows = t.query(&q, &[]).await.anyway()?;
+        Self::from_rows(rows)
+    }
+
     /// selects for the given aggregate and host
     pub async fn find_for_aggregate_and_host(
         t: &mut EasyTransaction<'_>,
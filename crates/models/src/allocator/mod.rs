@@ -1,11 +1,13 @@
 use std::sync::Mutex;
 
 pub mod allocation;
+pub mod reservation;
 pub mod resource_handle;
 pub mod types;
 pub mod vpn_token;
 
 pub use allocation::{Allocation, AllocationOperation, AllocationReason, AllocationStatus};
+pub use reservation::Reservation;
 pub use resource_handle::{ResourceHandle, ResourceHandleInner};
 pub use types::{ResourceClass, ResourceRequestInner};
 pub use vpn_token::VPNToken;
@@ -0,0 +1,122 @@
+use dal::{web::*, *};
+use tokio_postgres::types::ToSql;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::{allocator::ResourceHandle, dashboard::Aggregate};
+
+/// A claim on a resource for some window in the future
+///
+/// Reservations are not allocations: a reserved resource is still free
+/// until the booking that holds the reservation actually starts, but it
+/// may only be handed out to bookings that will be done with it before
+/// the reservation begins
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Reservation {
+    pub id: FKey<Reservation>,
+    pub for_resource: FKey<ResourceHandle>,
+    pub for_aggregate: FKey<Aggregate>,
+
+    pub starts: DateTime<Utc>,
+
+    /// If None, the reservation is open ended
+    pub ends: Option<DateTime<Utc>>,
+
+    pub cancelled: bool,
+}
+
+impl DBTable for Reservation {
+    fn id(&self) -> ID {
+        self.id.into_id()
+    }
+
+    fn table_name() -> &'static str {
+        "reservations"
+    }
+
+    fn from_row(row: tokio_postgres::Row) -> Result<ExistingRow<Self>, anyhow::Error> {
+        Ok(ExistingRow::from_existing(Self {
+            id: row.try_get("id")?,
+            for_resource: row.try_get("for_resource")?,
+            for_aggregate: row.try_get("for_aggregate")?,
+            starts: row.try_get("starts")?,
+            ends: row.try_get("ends")?,
+            cancelled: row.try_get("cancelled")?,
+        }))
+    }
+
+    fn to_rowlike(&self) -> Result<HashMap<&str, Box<dyn ToSql + Sync + Send>>, anyhow::Error> {
+        let c: [(&str, Box<dyn tokio_postgres::types::ToSql + Sync + Send>); _] = [
+            ("id", Box::new(self.id)),
+            ("for_resource", Box::new(self.for_resource)),
+            ("for_aggregate", Box::new(self.for_aggregate)),
+            ("starts", Box::new(self.starts)),
+            ("ends", Box::new(self.ends)),
+            ("cancelled", Box::new(self.cancelled)),
+        ];
+
+        Ok(c.into_iter().collect())
+    }
+}
+
+impl Reservation {
+    /// Whether this reservation overlaps the window `[from, until)`,
+    /// where an `until` of None means the window never closes
+    pub fn overlaps(&self, from: DateTime<Utc>, until: Option<DateTime<Utc>>) -> bool {
+        let starts_before_window_ends = match until {
+            Some(until) => self.starts < until,
+            None => true,
+        };
+
+        let ends_after_window_starts = match self.ends {
+            Some(ends) => ends > from,
+            None => true,
+        };
+
+        !self.cancelled && starts_before_window_ends && ends_after_window_starts
+    }
+
+    /// Selects every live (not cancelled, not yet over) reservation
+    pub async fn upcoming(
+        t: &mut EasyTransaction<'_>,
+    ) -> Result<Vec<ExistingRow<Reservation>>, anyhow::Error> {
+        let tn = Self::table_name();
+        let q = format!(
+            "SELECT * FROM {tn} WHERE cancelled = false AND (ends IS NULL OR ends > $1) ORDER BY starts ASC;"
+        );
+
+        let rows = t.query(&q, &[&Utc::now()]).await.anyway()?;
+
+        Self::from_rows(rows)
+    }
+
+    /// Selects the live reservations that would conflict with using a resource
+    /// from now until `until`, ignoring any held by `except_aggregate`
+    pub async fn conflicting(
+        t: &mut EasyTransaction<'_>,
+        until: Option<DateTime<Utc>>,
+        except_aggregate: Option<FKey<Aggregate>>,
+    ) -> Result<Vec<ExistingRow<Reservation>>, anyhow::Error> {
+        let now = Utc::now();
+
+        Ok(Self::upcoming(t)
+            .await?
+            .into_iter()
+            .filter(|r| Some(r.for_aggregate) != except_aggregate && r.overlaps(now, until))
+            .collect())
+    }
+
+    pub async fn all_for_aggregate(
+        t: &mut EasyTransaction<'_>,
+        agg: FKey<Aggregate>,
+    ) -> Result<Vec<ExistingRow<Reservation>>, anyhow::Error> {
+        let tn = Self::table_name();
+
+        let q = format!("SELECT * FROM {tn} WHERE for_aggregate = $1;");
+
+        let rows = t.query(&q, &[&agg]).await.anyway()?;
+        Self::from_rows(rows)
+    }
+}
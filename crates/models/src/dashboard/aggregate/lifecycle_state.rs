@@ -1,19 +1,63 @@
 use tokio_postgres::types::ToSql;
 
 use common::prelude::*;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use serde_json::to_value;
 use serde_json::Value;
 use tokio_postgres::types::{private::BytesMut, IsNull, Type};
 
-#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, JsonSchema)]
 pub enum LifeCycleState {
-    New,    // signals this booking has not yet been fully provisioned
-    Active, // signals this booking is actively being used and has already been provisioned
+    Queued,    // signals this booking is waiting for hosts to free up, and has none yet
+    Scheduled, // signals this booking starts later, and has only reserved its hosts until then
+    New,       // signals this booking has not yet been fully provisioned
+    Active,    // signals this booking is actively being used and has already been provisioned
     // (ready for cleanup, if it's time)
-    Done, // signals this booking has been cleaned up and released
+    TearingDown, // signals the hosts of this booking are being cleaned up
+    Failed,      // signals every host of this booking failed to provision, and they were released
+    Done,        // signals this booking has been cleaned up and released
 }
 
+impl LifeCycleState {
+    /// Whether an aggregate in this state may be moved to `next`
+    ///
+    /// Bookings only move forward, from waiting through provisioning and use to being torn
+    /// down, with `Failed` and `Done` being where they end up for good.
+    pub fn can_become(self, next: LifeCycleState) -> bool {
+        use LifeCycleState::*;
+
+        matches!(
+            (self, next),
+            (Queued, New | Done)
+                | (Scheduled, New | Done)
+                | (New, Queued | Active | Failed)
+                | (Active, TearingDown)
+                | (TearingDown, Done)
+        )
+    }
+
+    /// Whether the booking is over, and holds no hosts anymore
+    pub fn ended(self) -> bool {
+        matches!(self, LifeCycleState::Failed | LifeCycleState::Done)
+    }
+}
+
+/// A change of lifecycle state that the state machine of [`LifeCycleState`] doesn't allow
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IllegalTransition {
+    pub from: LifeCycleState,
+    pub to: LifeCycleState,
+}
+
+impl std::fmt::Display for IllegalTransition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "a booking can't go from {} to {}", self.from, self.to)
+    }
+}
+
+impl std::error::Error for IllegalTransition {}
+
 type BoxedError = Box<dyn std::error::Error + Sync + Send>;
 
 impl ToSql for LifeCycleState {
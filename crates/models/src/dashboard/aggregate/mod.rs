@@ -2,18 +2,19 @@ use common::prelude::{
     chrono::{DateTime, Utc},
     *,
 };
-use dal::*;
+use dal::{web::*, *};
 
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use tokio_postgres::types::ToSql;
 
 mod lifecycle_state;
-pub use lifecycle_state::LifeCycleState;
+pub use lifecycle_state::{IllegalTransition, LifeCycleState};
 
 use crate::{
-    dashboard::{Instance, NetworkAssignmentMap, Template},
-    inventory::Lab,
+    dashboard::{AggregateTransition, Instance, NetworkAssignmentMap, Template},
+    inventory::{FailureDomainKind, Lab},
 };
 
 #[derive(Serialize, Deserialize, Debug, Clone, Default)]
@@ -32,12 +33,36 @@ pub struct BookingMetadata {
     pub start: Option<DateTime<Utc>>,
     /// DateTime<Utc> that contains the end of a booking
     pub end: Option<DateTime<Utc>>,
+    /// If set, prefer hosts whose benchmark score is within this many percent of their flavor median
+    #[serde(default)]
+    pub performance_tolerance: Option<f64>,
+    /// If set, no two hosts in the booking may share a failure domain of this kind
+    #[serde(default)]
+    pub spread: Option<FailureDomainKind>,
+    /// A name the user picked to tell the booking apart from their others
+    #[serde(default)]
+    pub display_name: Option<String>,
+    /// Users who favorited the booking, which is listed before their others
+    #[serde(default)]
+    pub favorited_by: Vec<String>,
+    /// Install the usage telemetry agent on the hosts of the booking
+    #[serde(default)]
+    pub telemetry: bool,
+    /// Set by admins to keep the booking from being ended automatically once it is past its end
+    #[serde(default)]
+    pub exempt_from_expiry: bool,
+    /// Why an admin let the booking be given hosts funded by other projects, if they did
+    #[serde(default)]
+    pub ownership_override: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
 pub struct AggregateConfiguration {
     pub ipmi_username: String,
     pub ipmi_password: String,
+    /// Variables handed to the cloud-init and recipes of the hosts of the booking
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -62,6 +87,25 @@ pub struct Aggregate {
     pub lab: FKey<Lab>,
 }
 
+/// Which aggregates [`Aggregate::page`] lists, every one that isn't deleted by default
+#[derive(Debug, Clone, Default)]
+pub struct AggregateFilter {
+    pub owner: Option<String>,
+    pub project: Option<String>,
+    pub state: Option<LifeCycleState>,
+    /// Only aggregates that haven't ended by then
+    pub from: Option<DateTime<Utc>>,
+    /// Only aggregates that start before then
+    pub to: Option<DateTime<Utc>>,
+}
+
+/// The last aggregate of a page, which the next page starts after
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct AggregateCursor {
+    pub start: Option<DateTime<Utc>>,
+    pub id: FKey<Aggregate>,
+}
+
 impl std::fmt::Display for Aggregate {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}", self.id.into_id())
@@ -90,6 +134,7 @@ impl DBTable for Aggregate {
                 AggregateConfiguration {
                     ipmi_username: String::new(),
                     ipmi_password: String::new(),
+                    env: BTreeMap::new(),
                 },
             ),
             lab: row.try_get("lab")?,
@@ -118,6 +163,78 @@ impl DBTable for Aggregate {
 }
 
 impl Aggregate {
+    /// Moves `agg` to the state `to`, saving it along with anything else changed on it and
+    /// recording why, or fails with an [`IllegalTransition`] if it can't go there from
+    /// where it is
+    pub async fn transition(
+        agg: &mut ExistingRow<Aggregate>,
+        t: &mut EasyTransaction<'_>,
+        to: LifeCycleState,
+        reason: impl Into<String>,
+        actor: Option<String>,
+    ) -> Result<(), anyhow::Error> {
+        let from = agg.state;
+        if !from.can_become(to) {
+            return Err(IllegalTransition { from, to }.into());
+        }
+
+        Self::record_transition(agg, t, to, reason.into(), actor, false).await
+    }
+
+    /// Moves `agg` to the state `to` whether or not it could get there on its own, for
+    /// admins fixing up bookings by hand
+    pub async fn force_transition(
+        agg: &mut ExistingRow<Aggregate>,
+        t: &mut EasyTransaction<'_>,
+        to: LifeCycleState,
+        reason: impl Into<String>,
+        actor: Option<String>,
+    ) -> Result<(), anyhow::Error> {
+        Self::record_transition(agg, t, to, reason.into(), actor, true).await
+    }
+
+    async fn record_transition(
+        agg: &mut ExistingRow<Aggregate>,
+        t: &mut EasyTransaction<'_>,
+        to: LifeCycleState,
+        reason: String,
+        actor: Option<String>,
+        forced: bool,
+    ) -> Result<(), anyhow::Error> {
+        let from = agg.state;
+        agg.state = to;
+        agg.update(t).await?;
+
+        NewRow::new(AggregateTransition {
+            id: FKey::new_id_dangling(),
+            aggregate: agg.id,
+            from,
+            to,
+            reason,
+            actor,
+            forced,
+            time: Utc::now(),
+        })
+        .insert(t)
+        .await?;
+
+        Ok(())
+    }
+
+    /// The aggregate, locked until the transaction ends so that nothing else changes it
+    /// between reading and writing it back
+    pub async fn lock(
+        t: &mut EasyTransaction<'_>,
+        id: FKey<Aggregate>,
+    ) -> Result<ExistingRow<Aggregate>, anyhow::Error> {
+        let tn = <Self as DBTable>::table_name();
+        let q = format!("SELECT * FROM {tn} WHERE id = $1 FOR UPDATE;");
+
+        let row = t.query_one(&q, &[&id]).await.anyway()?;
+
+        Self::from_row(row)
+    }
+
     pub async fn instances(
         &self,
         t: &mut EasyTransaction<'_>,
@@ -128,4 +245,117 @@ impl Aggregate {
             .run(t)
             .await
     }
+
+    /// Up to `limit` aggregates matching `filter` that come after `after`, most recently
+    /// started first
+    ///
+    /// The conditions and the order are all covered by indexes on the table, so this doesn't
+    /// scan every aggregate however deep into the list the page is.
+    pub async fn page(
+        t: &mut EasyTransaction<'_>,
+        filter: &AggregateFilter,
+        after: Option<AggregateCursor>,
+        limit: i64,
+    ) -> Result<Vec<ExistingRow<Aggregate>>, anyhow::Error> {
+        let tn = <Self as DBTable>::table_name();
+
+        let mut params: Vec<Box<dyn ToSqlObject>> = Vec::new();
+        let mut bind = |value: Box<dyn ToSqlObject>| {
+            params.push(value);
+            format!("${}", params.len())
+        };
+
+        let mut conditions = vec!["NOT deleted".to_owned()];
+        if let Some(owner) = &filter.owner {
+            conditions.push(format!(
+                "metadata->>'owner' = {}",
+                bind(Box::new(owner.clone()))
+            ));
+        }
+        if let Some(project) = &filter.project {
+            conditions.push(format!(
+                "metadata->>'project' = {}",
+                bind(Box::new(project.clone()))
+            ));
+        }
+        if let Some(state) = filter.state {
+            conditions.push(format!("lifecycle_state = {}", bind(Box::new(state))));
+        }
+        if let Some(from) = filter.from {
+            conditions.push(format!("booking_end(metadata) > {}", bind(Box::new(from))));
+        }
+        if let Some(to) = filter.to {
+            conditions.push(format!("booking_start(metadata) < {}", bind(Box::new(to))));
+        }
+        if let Some(after) = after {
+            conditions.push(format!(
+                "(booking_start(metadata), id) < (COALESCE({}::timestamptz, '-infinity'), {})",
+                bind(Box::new(after.start)),
+                bind(Box::new(after.id))
+            ));
+        }
+        let limit = bind(Box::new(limit));
+
+        let q = format!(
+            "SELECT * FROM {tn} WHERE {} ORDER BY booking_start(metadata) DESC, id DESC LIMIT {limit};",
+            conditions.join(" AND ")
+        );
+
+        let params: Vec<&(dyn ToSql + Sync)> =
+            params.iter().map(|p| &**p as &(dyn ToSql + Sync)).collect();
+        let rows = t.query(&q, &params).await.anyway()?;
+
+        Self::from_rows(rows)
+    }
+
+    /// How many hosts the bookings in `lab` that haven't ended hold between them, counting
+    /// only those owned by `owner` and made for `project` where given
+    pub async fn hosts_held(
+        t: &mut EasyTransaction<'_>,
+        lab: FKey<Lab>,
+        owner: Option<&str>,
+        project: Option<&str>,
+    ) -> Result<usize, anyhow::Error> {
+        let tn = <Self as DBTable>::table_name();
+        let instance_tn = <Instance as DBTable>::table_name();
+        let q = format!(
+            "SELECT COUNT(*) FROM {instance_tn} i JOIN {tn} a ON i.aggregate = a.id
+                WHERE NOT a.deleted AND a.lab = $1 AND a.lifecycle_state <> $2
+                AND a.lifecycle_state <> $5
+                AND ($3::varchar IS NULL OR a.metadata->>'owner' = $3)
+                AND ($4::varchar IS NULL OR a.metadata->>'project' = $4);"
+        );
+
+        let row = t
+            .query_one(
+                &q,
+                &[
+                    &lab,
+                    &LifeCycleState::Done,
+                    &owner,
+                    &project,
+                    &LifeCycleState::Failed,
+                ],
+            )
+            .await
+            .anyway()?;
+        let held: i64 = row.try_get(0)?;
+
+        Ok(held as usize)
+    }
+
+    /// Every aggregate `username` owns or was added to as a collaborator
+    pub async fn all_for_user(
+        t: &mut EasyTransaction<'_>,
+        username: &str,
+    ) -> Result<Vec<ExistingRow<Aggregate>>, anyhow::Error> {
+        let tn = <Self as DBTable>::table_name();
+        let q = format!(
+            "SELECT * FROM {tn} WHERE NOT deleted AND ($1 = ANY(users) OR metadata->>'owner' = $1);"
+        );
+
+        let rows = t.query(&q, &[&username]).await.anyway()?;
+
+        Self::from_rows(rows)
+    }
 }
@@ -0,0 +1,109 @@
+use chrono::{DateTime, Utc};
+use dal::{web::*, *};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::dashboard::Aggregate;
+
+/// Something being done to a booking that others could get in the way of
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum LeaseOperation {
+    Reimage,
+    RollingReimage,
+    /// Changing the firewall policy of the booking
+    Network,
+    Extension,
+    Expansion,
+    Snapshot,
+    Teardown,
+}
+
+impl LeaseOperation {
+    /// How long a lease for the operation is held for at most, in case whatever took it
+    /// never gets to give it back
+    pub fn lasts(self) -> chrono::Duration {
+        match self {
+            Self::Extension | Self::Network => chrono::Duration::minutes(15),
+            Self::Reimage | Self::Expansion | Self::Teardown => chrono::Duration::hours(3),
+            Self::Snapshot => chrono::Duration::hours(4),
+            Self::RollingReimage => chrono::Duration::hours(24),
+        }
+    }
+}
+
+/// A claim on a booking for an operation, which other operations that conflict with it
+/// have to wait for or give up on
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AggregateLease {
+    pub id: FKey<AggregateLease>,
+    pub aggregate: FKey<Aggregate>,
+    pub operation: LeaseOperation,
+    /// Who asked for the operation, None for what LibLaaS does on its own
+    pub holder: Option<String>,
+    pub acquired: DateTime<Utc>,
+    /// After this the lease no longer counts, even if it wasn't given back
+    pub expires: DateTime<Utc>,
+}
+
+impl DBTable for AggregateLease {
+    fn table_name() -> &'static str {
+        "aggregate_leases"
+    }
+
+    fn id(&self) -> ID {
+        self.id.into_id()
+    }
+
+    fn from_row(row: tokio_postgres::Row) -> Result<ExistingRow<Self>, anyhow::Error> {
+        Ok(ExistingRow::from_existing(Self {
+            id: row.try_get("id")?,
+            aggregate: row.try_get("aggregate")?,
+            operation: serde_json::from_value(row.try_get("operation")?)?,
+            holder: row.try_get("holder")?,
+            acquired: row.try_get("acquired")?,
+            expires: row.try_get("expires")?,
+        }))
+    }
+
+    fn to_rowlike(&self) -> Result<HashMap<&str, Box<dyn ToSqlObject>>, anyhow::Error> {
+        let clone = self.clone();
+        let c: [(&str, Box<dyn ToSqlObject>); _] = [
+            ("id", Box::new(clone.id)),
+            ("aggregate", Box::new(clone.aggregate)),
+            (
+                "operation",
+                Box::new(serde_json::to_value(clone.operation)?),
+            ),
+            ("holder", Box::new(clone.holder)),
+            ("acquired", Box::new(clone.acquired)),
+            ("expires", Box::new(clone.expires)),
+        ];
+
+        Ok(c.into_iter().collect())
+    }
+}
+
+impl AggregateLease {
+    /// The leases on `aggregate` that haven't expired, oldest first
+    ///
+    /// Locks the aggregate until the transaction ends, so that no other lease can be taken
+    /// on it in the meantime.
+    pub async fn held_on(
+        t: &mut EasyTransaction<'_>,
+        aggregate: FKey<Aggregate>,
+    ) -> Result<Vec<ExistingRow<AggregateLease>>, anyhow::Error> {
+        let at = <Aggregate as DBTable>::table_name();
+        let lock = format!("SELECT id FROM {at} WHERE id = $1 FOR UPDATE;");
+        t.query(&lock, &[&aggregate]).await.anyway()?;
+
+        let tn = <Self as DBTable>::table_name();
+        let q =
+            format!("SELECT * FROM {tn} WHERE aggregate = $1 AND expires > $2 ORDER BY acquired;");
+
+        let rows = t.query(&q, &[&aggregate, &Utc::now()]).await.anyway()?;
+
+        Self::from_rows(rows)
+    }
+}
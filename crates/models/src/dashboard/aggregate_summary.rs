@@ -0,0 +1,198 @@
+use chrono::{DateTime, Utc};
+use dal::{web::*, *};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::dashboard::{Aggregate, LifeCycleState, ProvisionLogEvent, StatusSentiment};
+
+/// What booking lists and status polls show about an aggregate, kept up to date as
+/// workflows change it so that reading it takes a single row
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+pub struct AggregateSummary {
+    pub id: FKey<AggregateSummary>,
+    pub aggregate: FKey<Aggregate>,
+
+    pub owner: Option<String>,
+    pub users: Vec<String>,
+    pub booking_id: Option<String>,
+    pub display_name: Option<String>,
+    pub project: Option<String>,
+
+    #[schemars(with = "String")]
+    pub state: LifeCycleState,
+    pub hosts: i32,
+    /// Hosts whose latest log entry says they are done provisioning
+    pub hosts_provisioned: i32,
+    /// Hosts whose latest log entry is a failure
+    pub hosts_failed: i32,
+    /// Headline of the most recent log entry of any host
+    pub latest_status: Option<String>,
+    pub latest_sentiment: Option<StatusSentiment>,
+
+    #[schemars(with = "Option<String>")]
+    pub start: Option<DateTime<Utc>>,
+    #[schemars(with = "Option<String>")]
+    pub end: Option<DateTime<Utc>>,
+    #[schemars(with = "String")]
+    pub updated: DateTime<Utc>,
+}
+
+impl AggregateSummary {
+    pub async fn for_aggregate(
+        t: &mut EasyTransaction<'_>,
+        aggregate: FKey<Aggregate>,
+    ) -> Result<Option<ExistingRow<AggregateSummary>>, anyhow::Error> {
+        Ok(Self::select()
+            .where_field("aggregate")
+            .equals(aggregate)
+            .run(t)
+            .await?
+            .pop())
+    }
+
+    /// Summaries of those of `aggregates` that have one, in no particular order
+    pub async fn for_aggregates(
+        t: &mut EasyTransaction<'_>,
+        aggregates: Vec<FKey<Aggregate>>,
+    ) -> Result<Vec<ExistingRow<AggregateSummary>>, anyhow::Error> {
+        let tn = <Self as DBTable>::table_name();
+        let q = format!("SELECT * FROM {tn} WHERE aggregate = ANY($1);");
+
+        let rows = t.query(&q, &[&aggregates]).await.anyway()?;
+
+        Self::from_rows(rows)
+    }
+
+    /// Recomputes the summary of `aggregate` from it, its instances and their logs
+    ///
+    /// This is what workflows call after changing any of those, deleted aggregates lose their summary
+    pub async fn refresh(
+        t: &mut EasyTransaction<'_>,
+        aggregate: FKey<Aggregate>,
+    ) -> Result<(), anyhow::Error> {
+        let agg = aggregate.get(t).await?;
+        let existing = Self::for_aggregate(t, aggregate).await?;
+
+        if agg.deleted {
+            if let Some(existing) = existing {
+                existing.delete(t).await?;
+            }
+            return Ok(());
+        }
+
+        let instances = agg.instances(t).await?;
+
+        let mut latest: Option<ExistingRow<ProvisionLogEvent>> = None;
+        let (mut provisioned, mut failed) = (0, 0);
+        for instance in instances.iter() {
+            let Some(event) = ProvisionLogEvent::latest_for_instance(t, instance.id).await? else {
+                continue;
+            };
+
+            match event.sentiment {
+                StatusSentiment::Succeeded => provisioned += 1,
+                StatusSentiment::Failed => failed += 1,
+                _ => {}
+            }
+
+            if latest.as_ref().map_or(true, |l| l.time < event.time) {
+                latest = Some(event);
+            }
+        }
+
+        let summary = AggregateSummary {
+            id: existing
+                .as_ref()
+                .map(|e| e.id)
+                .unwrap_or(FKey::new_id_dangling()),
+            aggregate,
+            owner: agg.metadata.owner.clone(),
+            users: agg.users.clone(),
+            booking_id: agg.metadata.booking_id.clone(),
+            display_name: agg.metadata.display_name.clone(),
+            project: agg.metadata.project.clone(),
+            state: agg.state,
+            hosts: instances.len() as i32,
+            hosts_provisioned: provisioned,
+            hosts_failed: failed,
+            latest_status: latest.as_ref().map(|l| l.prov_status.event.clone()),
+            latest_sentiment: latest.as_ref().map(|l| l.sentiment),
+            start: agg.metadata.start,
+            end: agg.metadata.end,
+            updated: Utc::now(),
+        };
+
+        match existing {
+            Some(mut existing) => {
+                *existing = summary;
+                existing.update(t).await?;
+            }
+            None => {
+                NewRow::new(summary).insert(t).await?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl DBTable for AggregateSummary {
+    fn table_name() -> &'static str {
+        "aggregate_summary"
+    }
+
+    fn id(&self) -> ID {
+        self.id.into_id()
+    }
+
+    fn from_row(row: tokio_postgres::Row) -> Result<ExistingRow<Self>, anyhow::Error> {
+        Ok(ExistingRow::from_existing(Self {
+            id: row.try_get("id")?,
+            aggregate: row.try_get("aggregate")?,
+            owner: row.try_get("owner")?,
+            users: row.try_get("users")?,
+            booking_id: row.try_get("booking_id")?,
+            display_name: row.try_get("display_name")?,
+            project: row.try_get("project")?,
+            state: serde_json::from_value(row.try_get("lifecycle_state")?)?,
+            hosts: row.try_get("hosts")?,
+            hosts_provisioned: row.try_get("hosts_provisioned")?,
+            hosts_failed: row.try_get("hosts_failed")?,
+            latest_status: row.try_get("latest_status")?,
+            latest_sentiment: row
+                .try_get::<_, Option<SqlAsJson<StatusSentiment>>>("latest_sentiment")?
+                .map(|s| s.extract()),
+            start: row.try_get("booking_start")?,
+            end: row.try_get("booking_end")?,
+            updated: row.try_get("updated")?,
+        }))
+    }
+
+    fn to_rowlike(&self) -> Result<HashMap<&str, Box<dyn ToSqlObject>>, anyhow::Error> {
+        let clone = self.clone();
+        let c: [(&str, Box<dyn ToSqlObject>); _] = [
+            ("id", Box::new(clone.id)),
+            ("aggregate", Box::new(clone.aggregate)),
+            ("owner", Box::new(clone.owner)),
+            ("users", Box::new(clone.users)),
+            ("booking_id", Box::new(clone.booking_id)),
+            ("display_name", Box::new(clone.display_name)),
+            ("project", Box::new(clone.project)),
+            ("lifecycle_state", Box::new(clone.state)),
+            ("hosts", Box::new(clone.hosts)),
+            ("hosts_provisioned", Box::new(clone.hosts_provisioned)),
+            ("hosts_failed", Box::new(clone.hosts_failed)),
+            ("latest_status", Box::new(clone.latest_status)),
+            (
+                "latest_sentiment",
+                Box::new(clone.latest_sentiment.map(SqlAsJson::of)),
+            ),
+            ("booking_start", Box::new(clone.start)),
+            ("booking_end", Box::new(clone.end)),
+            ("updated", Box::new(clone.updated)),
+        ];
+
+        Ok(c.into_iter().collect())
+    }
+}
@@ -0,0 +1,164 @@
+use chrono::{DateTime, Utc};
+use dal::{web::*, *};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::dashboard::Aggregate;
+
+/// The longest a tag can be
+pub const MAX_TAG_LENGTH: usize = 64;
+
+/// A free-form label on a booking, so that related bookings can be found together
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AggregateTag {
+    pub id: FKey<AggregateTag>,
+    pub aggregate: FKey<Aggregate>,
+    pub tag: String,
+
+    pub tagged_by: Option<String>,
+    pub tagged: DateTime<Utc>,
+}
+
+impl DBTable for AggregateTag {
+    fn table_name() -> &'static str {
+        "aggregate_tags"
+    }
+
+    fn id(&self) -> ID {
+        self.id.into_id()
+    }
+
+    fn from_row(row: tokio_postgres::Row) -> Result<ExistingRow<Self>, anyhow::Error> {
+        Ok(ExistingRow::from_existing(Self {
+            id: row.try_get("id")?,
+            aggregate: row.try_get("aggregate")?,
+            tag: row.try_get("tag")?,
+            tagged_by: row.try_get("tagged_by")?,
+            tagged: row.try_get("tagged")?,
+        }))
+    }
+
+    fn to_rowlike(&self) -> Result<HashMap<&str, Box<dyn ToSqlObject>>, anyhow::Error> {
+        let clone = self.clone();
+        let c: [(&str, Box<dyn ToSqlObject>); _] = [
+            ("id", Box::new(clone.id)),
+            ("aggregate", Box::new(clone.aggregate)),
+            ("tag", Box::new(clone.tag)),
+            ("tagged_by", Box::new(clone.tagged_by)),
+            ("tagged", Box::new(clone.tagged)),
+        ];
+
+        Ok(c.into_iter().collect())
+    }
+}
+
+impl AggregateTag {
+    /// The form `tag` is stored and searched in, folded to lower case
+    ///
+    /// Tags are made of letters, digits, `-`, `_`, `.` and `:`, like `plugfest-2025`.
+    pub fn normalize(tag: &str) -> Result<String, anyhow::Error> {
+        let tag = tag.trim().to_lowercase();
+
+        if tag.is_empty() || tag.len() > MAX_TAG_LENGTH {
+            return Err(anyhow::Error::msg(format!(
+                "tags have to be between 1 and {MAX_TAG_LENGTH} characters long"
+            )));
+        }
+        if let Some(c) = tag
+            .chars()
+            .find(|c| !(c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | ':')))
+        {
+            return Err(anyhow::Error::msg(format!(
+                "tag {tag} has {c:?} in it, only letters, digits, '-', '_', '.' and ':' are allowed"
+            )));
+        }
+
+        Ok(tag)
+    }
+
+    /// The tags of `aggregate`, in alphabetical order
+    pub async fn for_aggregate(
+        t: &mut EasyTransaction<'_>,
+        aggregate: FKey<Aggregate>,
+    ) -> Result<Vec<String>, anyhow::Error> {
+        let tn = <Self as DBTable>::table_name();
+        let q = format!("SELECT * FROM {tn} WHERE aggregate = $1 ORDER BY tag;");
+
+        let rows = t.query(&q, &[&aggregate]).await.anyway()?;
+
+        Ok(Self::from_rows(rows)?
+            .into_iter()
+            .map(|t| t.into_inner().tag)
+            .collect())
+    }
+
+    /// Adds each of `tags` that `aggregate` doesn't have yet, then takes off each of `untag`
+    ///
+    /// Both are normalized first, failing without changing anything if any can't be.
+    pub async fn retag(
+        t: &mut EasyTransaction<'_>,
+        aggregate: FKey<Aggregate>,
+        tags: &[String],
+        untag: &[String],
+        by: Option<String>,
+    ) -> Result<(), anyhow::Error> {
+        let tags = tags
+            .iter()
+            .map(|t| Self::normalize(t))
+            .collect::<Result<Vec<_>, _>>()?;
+        let untag = untag
+            .iter()
+            .map(|t| Self::normalize(t))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let existing = Self::for_aggregate(t, aggregate).await?;
+        let now = Utc::now();
+        let mut added = Vec::new();
+        for tag in tags {
+            if existing.contains(&tag) || added.contains(&tag) {
+                continue;
+            }
+            NewRow::new(Self {
+                id: FKey::new_id_dangling(),
+                aggregate,
+                tag: tag.clone(),
+                tagged_by: by.clone(),
+                tagged: now,
+            })
+            .insert(t)
+            .await?;
+            added.push(tag);
+        }
+
+        let tn = <Self as DBTable>::table_name();
+        let q = format!("DELETE FROM {tn} WHERE aggregate = $1 AND tag = ANY($2);");
+        t.execute(&q, &[&aggregate, &untag]).await.anyway()?;
+
+        Ok(())
+    }
+
+    /// The bookings tagged with every one of `tags` if `all`, otherwise with any of them,
+    /// in no particular order
+    ///
+    /// `tags` have to be normalized already.
+    pub async fn search(
+        t: &mut EasyTransaction<'_>,
+        tags: &[String],
+        all: bool,
+    ) -> Result<Vec<FKey<Aggregate>>, anyhow::Error> {
+        let tn = <Self as DBTable>::table_name();
+        let wanted: i64 = match all {
+            true => tags.len() as i64,
+            false => 1,
+        };
+        let q = format!(
+            "SELECT aggregate FROM {tn} WHERE tag = ANY($1) GROUP BY aggregate HAVING COUNT(*) >= $2;"
+        );
+
+        let rows = t.query(&q, &[&tags, &wanted]).await.anyway()?;
+
+        rows.into_iter()
+            .map(|r| r.try_get("aggregate").anyway())
+            .collect()
+    }
+}
@@ -0,0 +1,75 @@
+use chrono::{DateTime, Utc};
+use dal::{web::*, *};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::dashboard::{Aggregate, LifeCycleState};
+
+/// One change of the lifecycle state of an aggregate, as made by [`Aggregate::transition`]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AggregateTransition {
+    pub id: FKey<AggregateTransition>,
+    pub aggregate: FKey<Aggregate>,
+    pub from: LifeCycleState,
+    pub to: LifeCycleState,
+    pub reason: String,
+    /// The user who caused it, None for what LibLaaS did on its own
+    pub actor: Option<String>,
+    /// Set when an admin put the aggregate into a state the state machine doesn't allow
+    pub forced: bool,
+    pub time: DateTime<Utc>,
+}
+
+impl DBTable for AggregateTransition {
+    fn table_name() -> &'static str {
+        "aggregate_transitions"
+    }
+
+    fn id(&self) -> ID {
+        self.id.into_id()
+    }
+
+    fn from_row(row: tokio_postgres::Row) -> Result<ExistingRow<Self>, anyhow::Error> {
+        Ok(ExistingRow::from_existing(Self {
+            id: row.try_get("id")?,
+            aggregate: row.try_get("aggregate")?,
+            from: serde_json::from_value(row.try_get("from_state")?)?,
+            to: serde_json::from_value(row.try_get("to_state")?)?,
+            reason: row.try_get("reason")?,
+            actor: row.try_get("actor")?,
+            forced: row.try_get("forced")?,
+            time: row.try_get("time")?,
+        }))
+    }
+
+    fn to_rowlike(&self) -> Result<HashMap<&str, Box<dyn ToSqlObject>>, anyhow::Error> {
+        let clone = self.clone();
+        let c: [(&str, Box<dyn ToSqlObject>); _] = [
+            ("id", Box::new(clone.id)),
+            ("aggregate", Box::new(clone.aggregate)),
+            ("from_state", Box::new(clone.from)),
+            ("to_state", Box::new(clone.to)),
+            ("reason", Box::new(clone.reason)),
+            ("actor", Box::new(clone.actor)),
+            ("forced", Box::new(clone.forced)),
+            ("time", Box::new(clone.time)),
+        ];
+
+        Ok(c.into_iter().collect())
+    }
+}
+
+impl AggregateTransition {
+    /// Every state the aggregate has been through, oldest first
+    pub async fn for_aggregate(
+        t: &mut EasyTransaction<'_>,
+        aggregate: FKey<Aggregate>,
+    ) -> Result<Vec<ExistingRow<AggregateTransition>>, anyhow::Error> {
+        let tn = <Self as DBTable>::table_name();
+        let q = format!("SELECT * FROM {tn} WHERE aggregate = $1 ORDER BY time;");
+
+        let rows = t.query(&q, &[&aggregate]).await.anyway()?;
+
+        Self::from_rows(rows)
+    }
+}
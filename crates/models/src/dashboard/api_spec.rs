@@ -0,0 +1,74 @@
+use chrono::{DateTime, Utc};
+use dal::{web::*, *};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// The OpenAPI spec liblaas served from some deployment on, kept so the next deployment
+/// can tell what it changed
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ApiSpec {
+    pub id: FKey<ApiSpec>,
+    /// The version of liblaas that served it
+    pub version: String,
+    pub spec: serde_json::Value,
+    /// When it was first served
+    pub recorded: DateTime<Utc>,
+}
+
+impl DBTable for ApiSpec {
+    fn table_name() -> &'static str {
+        "api_specs"
+    }
+
+    fn id(&self) -> ID {
+        self.id.into_id()
+    }
+
+    fn from_row(row: tokio_postgres::Row) -> Result<ExistingRow<Self>, anyhow::Error> {
+        Ok(ExistingRow::from_existing(Self {
+            id: row.try_get("id")?,
+            version: row.try_get("version")?,
+            spec: row.try_get("spec")?,
+            recorded: row.try_get("recorded")?,
+        }))
+    }
+
+    fn to_rowlike(&self) -> Result<HashMap<&str, Box<dyn ToSqlObject>>, anyhow::Error> {
+        let clone = self.clone();
+        let c: [(&str, Box<dyn ToSqlObject>); _] = [
+            ("id", Box::new(clone.id)),
+            ("version", Box::new(clone.version)),
+            ("spec", Box::new(clone.spec)),
+            ("recorded", Box::new(clone.recorded)),
+        ];
+
+        Ok(c.into_iter().collect())
+    }
+}
+
+impl ApiSpec {
+    /// The spec recorded last
+    pub async fn latest(
+        t: &mut EasyTransaction<'_>,
+    ) -> Result<Option<ExistingRow<ApiSpec>>, anyhow::Error> {
+        let tn = <Self as DBTable>::table_name();
+        let q = format!("SELECT * FROM {tn} ORDER BY recorded DESC LIMIT 1;");
+
+        let rows = t.query(&q, &[]).await.anyway()?;
+
+        Ok(Self::from_rows(rows)?.into_iter().next())
+    }
+
+    /// The spec recorded last that isn't `spec`, which is what was served before it
+    pub async fn before(
+        t: &mut EasyTransaction<'_>,
+        spec: &serde_json::Value,
+    ) -> Result<Option<ExistingRow<ApiSpec>>, anyhow::Error> {
+        let tn = <Self as DBTable>::table_name();
+        let q = format!("SELECT * FROM {tn} WHERE spec <> $1 ORDER BY recorded DESC LIMIT 1;");
+
+        let rows = t.query(&q, &[spec]).await.anyway()?;
+
+        Ok(Self::from_rows(rows)?.into_iter().next())
+    }
+}
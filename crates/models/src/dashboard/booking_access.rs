@@ -0,0 +1,80 @@
+use chrono::{DateTime, Utc};
+use dal::{web::*, *};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::dashboard::{Aggregate, Instance};
+
+/// One call made to the booking API against a booking, so its owner can find out who did
+/// what to it
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BookingAccess {
+    pub id: FKey<BookingAccess>,
+    pub aggregate: FKey<Aggregate>,
+    /// The instance the call was made against, for calls about one host of the booking
+    pub instance: Option<FKey<Instance>>,
+    /// Who the dashboard said the call was made for, None if it didn't say
+    pub username: Option<String>,
+    pub admin: bool,
+    pub method: String,
+    /// The route that was called, like `/ipmi/:instance_id/setpower`
+    pub route: String,
+    pub status: i32,
+    pub time: DateTime<Utc>,
+}
+
+impl DBTable for BookingAccess {
+    fn table_name() -> &'static str {
+        "booking_access_log"
+    }
+
+    fn id(&self) -> ID {
+        self.id.into_id()
+    }
+
+    fn from_row(row: tokio_postgres::Row) -> Result<ExistingRow<Self>, anyhow::Error> {
+        Ok(ExistingRow::from_existing(Self {
+            id: row.try_get("id")?,
+            aggregate: row.try_get("aggregate")?,
+            instance: row.try_get("instance")?,
+            username: row.try_get("username")?,
+            admin: row.try_get("admin")?,
+            method: row.try_get("method")?,
+            route: row.try_get("route")?,
+            status: row.try_get("status")?,
+            time: row.try_get("time")?,
+        }))
+    }
+
+    fn to_rowlike(&self) -> Result<HashMap<&str, Box<dyn ToSqlObject>>, anyhow::Error> {
+        let clone = self.clone();
+        let c: [(&str, Box<dyn ToSqlObject>); _] = [
+            ("id", Box::new(clone.id)),
+            ("aggregate", Box::new(clone.aggregate)),
+            ("instance", Box::new(clone.instance)),
+            ("username", Box::new(clone.username)),
+            ("admin", Box::new(clone.admin)),
+            ("method", Box::new(clone.method)),
+            ("route", Box::new(clone.route)),
+            ("status", Box::new(clone.status)),
+            ("time", Box::new(clone.time)),
+        ];
+
+        Ok(c.into_iter().collect())
+    }
+}
+
+impl BookingAccess {
+    /// Every call made against `aggregate`, most recent first
+    pub async fn for_aggregate(
+        t: &mut EasyTransaction<'_>,
+        aggregate: FKey<Aggregate>,
+    ) -> Result<Vec<ExistingRow<BookingAccess>>, anyhow::Error> {
+        let tn = Self::table_name();
+        let q = format!("SELECT * FROM {tn} WHERE aggregate = $1 ORDER BY time DESC;");
+
+        let rows = t.query(&q, &[&aggregate]).await.anyway()?;
+
+        Self::from_rows(rows)
+    }
+}
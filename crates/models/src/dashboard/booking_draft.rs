@@ -0,0 +1,91 @@
+use chrono::{DateTime, Utc};
+use dal::{web::*, *};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::dashboard::{Aggregate, ParameterValue, Template};
+
+/// A booking that the dashboard's create-booking wizard is still filling in,
+/// which is only provisioned once it is submitted
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BookingDraft {
+    pub id: FKey<BookingDraft>,
+    /// Who started the draft, which the dashboard finds their drafts by
+    pub owner: String,
+    pub origin: Option<String>,
+    pub template: Option<FKey<Template>>,
+    pub allowed_users: Vec<String>,
+    pub global_cifile: Option<String>,
+    /// The booking metadata given so far, in the shape the web API takes it in
+    pub metadata: serde_json::Value,
+    pub parameters: HashMap<String, ParameterValue>,
+    pub created: DateTime<Utc>,
+    pub updated: DateTime<Utc>,
+    /// The booking the draft became, once submitted
+    pub submitted: Option<FKey<Aggregate>>,
+}
+
+impl BookingDraft {
+    /// Drafts of `owner` that haven't been submitted yet, most recently changed first
+    pub async fn open_for_owner(
+        t: &mut EasyTransaction<'_>,
+        owner: &str,
+    ) -> Result<Vec<ExistingRow<BookingDraft>>, anyhow::Error> {
+        let tn = <Self as DBTable>::table_name();
+        let q = format!(
+            "SELECT * FROM {tn} WHERE owner = $1 AND submitted IS NULL ORDER BY updated DESC;"
+        );
+
+        let rows = t.query(&q, &[&owner]).await.anyway()?;
+
+        Self::from_rows(rows)
+    }
+}
+
+impl DBTable for BookingDraft {
+    fn table_name() -> &'static str {
+        "booking_drafts"
+    }
+
+    fn id(&self) -> ID {
+        self.id.into_id()
+    }
+
+    fn from_row(row: tokio_postgres::Row) -> Result<ExistingRow<Self>, anyhow::Error> {
+        Ok(ExistingRow::from_existing(Self {
+            id: row.try_get("id")?,
+            owner: row.try_get("owner")?,
+            origin: row.try_get("origin")?,
+            template: row.try_get("template")?,
+            allowed_users: row.try_get("allowed_users")?,
+            global_cifile: row.try_get("global_cifile")?,
+            metadata: row.try_get("metadata")?,
+            parameters: serde_json::from_value(row.try_get("parameters")?)?,
+            created: row.try_get("created")?,
+            updated: row.try_get("updated")?,
+            submitted: row.try_get("submitted")?,
+        }))
+    }
+
+    fn to_rowlike(&self) -> Result<HashMap<&str, Box<dyn ToSqlObject>>, anyhow::Error> {
+        let clone = self.clone();
+        let c: [(&str, Box<dyn ToSqlObject>); _] = [
+            ("id", Box::new(clone.id)),
+            ("owner", Box::new(clone.owner)),
+            ("origin", Box::new(clone.origin)),
+            ("template", Box::new(clone.template)),
+            ("allowed_users", Box::new(clone.allowed_users)),
+            ("global_cifile", Box::new(clone.global_cifile)),
+            ("metadata", Box::new(clone.metadata)),
+            (
+                "parameters",
+                Box::new(serde_json::to_value(clone.parameters)?),
+            ),
+            ("created", Box::new(clone.created)),
+            ("updated", Box::new(clone.updated)),
+            ("submitted", Box::new(clone.submitted)),
+        ];
+
+        Ok(c.into_iter().collect())
+    }
+}
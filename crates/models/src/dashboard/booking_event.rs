@@ -0,0 +1,139 @@
+use chrono::{DateTime, Utc};
+use dal::{web::*, *};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::dashboard::Aggregate;
+
+/// Something that happened to a booking as a whole, as opposed to the provisioning
+/// logs of each of its instances
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum BookingEventKind {
+    Created,
+    /// No hosts were free for the booking, so it is waiting for some to free up
+    Queued,
+    /// Hosts freed up for a queued booking, so it started
+    Dequeued,
+    /// Hosts were reserved for the booking ahead of when it starts
+    Scheduled,
+    ProvisioningStarted,
+    Provisioned,
+    ProvisioningFailed,
+    Extended,
+    /// Hosts were added to the booking while it was running
+    Expanded,
+    /// A host was given back while the rest of the booking kept running
+    InstanceReleased,
+    CollaboratorAdded,
+    CollaboratorRemoved,
+    /// The booking was handed over to someone else
+    OwnerChanged,
+    /// What a collaborator is allowed to do to the booking was changed
+    PermissionsChanged,
+    /// An admin let the booking be given hosts funded by other projects
+    OwnershipOverridden,
+    /// The disks of the booking were captured to a snapshot
+    Snapshotted,
+    /// The booking was past its end, so LibLaaS started ending it
+    Expired,
+    Ended,
+}
+
+/// One entry of the lifecycle log of a booking
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BookingEvent {
+    pub id: FKey<BookingEvent>,
+    pub aggregate: FKey<Aggregate>,
+    pub kind: BookingEventKind,
+    pub details: String,
+    /// The user who caused it, None for what LibLaaS did on its own
+    pub actor: Option<String>,
+    pub time: DateTime<Utc>,
+}
+
+impl DBTable for BookingEvent {
+    fn table_name() -> &'static str {
+        "booking_events"
+    }
+
+    fn id(&self) -> ID {
+        self.id.into_id()
+    }
+
+    fn from_row(row: tokio_postgres::Row) -> Result<ExistingRow<Self>, anyhow::Error> {
+        Ok(ExistingRow::from_existing(Self {
+            id: row.try_get("id")?,
+            aggregate: row.try_get("aggregate")?,
+            kind: serde_json::from_value(row.try_get("kind")?)?,
+            details: row.try_get("details")?,
+            actor: row.try_get("actor")?,
+            time: row.try_get("time")?,
+        }))
+    }
+
+    fn to_rowlike(&self) -> Result<HashMap<&str, Box<dyn ToSqlObject>>, anyhow::Error> {
+        let clone = self.clone();
+        let c: [(&str, Box<dyn ToSqlObject>); _] = [
+            ("id", Box::new(clone.id)),
+            ("aggregate", Box::new(clone.aggregate)),
+            ("kind", Box::new(serde_json::to_value(clone.kind)?)),
+            ("details", Box::new(clone.details)),
+            ("actor", Box::new(clone.actor)),
+            ("time", Box::new(clone.time)),
+        ];
+
+        Ok(c.into_iter().collect())
+    }
+}
+
+impl BookingEvent {
+    pub async fn record(
+        t: &mut EasyTransaction<'_>,
+        aggregate: FKey<Aggregate>,
+        kind: BookingEventKind,
+        details: impl Into<String>,
+        actor: Option<String>,
+    ) -> Result<FKey<BookingEvent>, anyhow::Error> {
+        NewRow::new(BookingEvent {
+            id: FKey::new_id_dangling(),
+            aggregate,
+            kind,
+            details: details.into(),
+            actor,
+            time: Utc::now(),
+        })
+        .insert(t)
+        .await
+    }
+
+    /// Records the event in a transaction of its own, so it shows up right away
+    pub async fn record_committing(
+        aggregate: FKey<Aggregate>,
+        kind: BookingEventKind,
+        details: impl Into<String>,
+        actor: Option<String>,
+    ) -> Result<(), anyhow::Error> {
+        let mut client = new_client().await?;
+        let mut transaction = client.easy_transaction().await?;
+
+        Self::record(&mut transaction, aggregate, kind, details, actor).await?;
+        transaction.commit().await?;
+
+        Ok(())
+    }
+
+    /// The lifecycle log of the booking, oldest first
+    pub async fn for_aggregate(
+        t: &mut EasyTransaction<'_>,
+        aggregate: FKey<Aggregate>,
+    ) -> Result<Vec<ExistingRow<BookingEvent>>, anyhow::Error> {
+        let tn = <Self as DBTable>::table_name();
+        let q = format!("SELECT * FROM {tn} WHERE aggregate = $1 ORDER BY time;");
+
+        let rows = t.query(&q, &[&aggregate]).await.anyway()?;
+
+        Self::from_rows(rows)
+    }
+}
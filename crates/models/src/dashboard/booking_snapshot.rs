@@ -0,0 +1,127 @@
+use chrono::{DateTime, Utc};
+use dal::{web::*, *};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+use crate::{
+    dashboard::{Aggregate, HostConfig, Network, Template},
+    inventory::Lab,
+};
+
+/// The instance metadata key under which an instance being restored from a snapshot
+/// keeps the name of the disk image to write onto its host
+pub const RESTORE_FROM: &str = "restore_from";
+
+/// Everything needed to bring a booking back later on a fresh allocation: the disk of
+/// each of its hosts, captured to the image store, and how they were networked
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BookingSnapshot {
+    pub id: FKey<BookingSnapshot>,
+    pub name: String,
+    pub owner: String,
+    /// The booking it was taken of, None once that booking is gone
+    pub aggregate: Option<FKey<Aggregate>>,
+    pub template: FKey<Template>,
+    pub lab: FKey<Lab>,
+
+    pub env: BTreeMap<String, String>,
+    pub networks: Vec<SnapshotNetwork>,
+    pub hosts: Vec<HostSnapshot>,
+
+    pub state: SnapshotState,
+    /// What went wrong, if capturing failed
+    pub error: Option<String>,
+    pub created: DateTime<Utc>,
+    /// When the last disk was captured
+    pub captured: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, JsonSchema)]
+pub struct SnapshotNetwork {
+    pub network: FKey<Network>,
+    pub name: String,
+    pub public: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+pub struct HostSnapshot {
+    /// The host as it was configured in the booking, including its connections
+    pub config: HostConfig,
+    /// The name of the disk image in the image store
+    pub disk_image: String,
+    pub captured: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, JsonSchema)]
+pub enum SnapshotState {
+    /// The disks are still being copied off the hosts
+    Capturing,
+    /// Every disk was captured, so the snapshot can be restored
+    Ready,
+    Failed,
+}
+
+impl DBTable for BookingSnapshot {
+    fn table_name() -> &'static str {
+        "booking_snapshots"
+    }
+
+    fn id(&self) -> ID {
+        self.id.into_id()
+    }
+
+    fn from_row(row: tokio_postgres::Row) -> Result<ExistingRow<Self>, anyhow::Error> {
+        Ok(ExistingRow::from_existing(Self {
+            id: row.try_get("id")?,
+            name: row.try_get("name")?,
+            owner: row.try_get("owner")?,
+            aggregate: row.try_get("aggregate")?,
+            template: row.try_get("template")?,
+            lab: row.try_get("lab")?,
+            env: serde_json::from_value(row.try_get("env")?)?,
+            networks: serde_json::from_value(row.try_get("networks")?)?,
+            hosts: serde_json::from_value(row.try_get("hosts")?)?,
+            state: serde_json::from_value(row.try_get("state")?)?,
+            error: row.try_get("error")?,
+            created: row.try_get("created")?,
+            captured: row.try_get("captured")?,
+        }))
+    }
+
+    fn to_rowlike(&self) -> Result<HashMap<&str, Box<dyn ToSqlObject>>, anyhow::Error> {
+        let clone = self.clone();
+        let c: [(&str, Box<dyn ToSqlObject>); _] = [
+            ("id", Box::new(clone.id)),
+            ("name", Box::new(clone.name)),
+            ("owner", Box::new(clone.owner)),
+            ("aggregate", Box::new(clone.aggregate)),
+            ("template", Box::new(clone.template)),
+            ("lab", Box::new(clone.lab)),
+            ("env", Box::new(serde_json::to_value(clone.env)?)),
+            ("networks", Box::new(serde_json::to_value(clone.networks)?)),
+            ("hosts", Box::new(serde_json::to_value(clone.hosts)?)),
+            ("state", Box::new(serde_json::to_value(clone.state)?)),
+            ("error", Box::new(clone.error)),
+            ("created", Box::new(clone.created)),
+            ("captured", Box::new(clone.captured)),
+        ];
+
+        Ok(c.into_iter().collect())
+    }
+}
+
+impl BookingSnapshot {
+    /// The snapshots a user has taken, newest first
+    pub async fn for_owner(
+        t: &mut EasyTransaction<'_>,
+        owner: &str,
+    ) -> Result<Vec<ExistingRow<BookingSnapshot>>, anyhow::Error> {
+        let tn = <Self as DBTable>::table_name();
+        let q = format!("SELECT * FROM {tn} WHERE owner = $1 ORDER BY created DESC;");
+
+        let rows = t.query(&q, &[&owner]).await.anyway()?;
+
+        Self::from_rows(rows)
+    }
+}
@@ -0,0 +1,89 @@
+use chrono::{DateTime, Utc};
+use dal::{web::*, *};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::dashboard::{Aggregate, Template};
+
+/// A class of students that were each given a booking of the same template at once,
+/// so that the instructor can look after all of them together
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Cohort {
+    pub id: FKey<Cohort>,
+    pub name: String,
+    /// Who made the bookings, on behalf of the students
+    pub instructor: String,
+    /// The project the bookings were made in
+    pub origin: String,
+    pub template: FKey<Template>,
+    /// One for each student on the roster, in roster order
+    pub members: Vec<CohortMember>,
+    pub created: DateTime<Utc>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+pub struct CohortMember {
+    pub student: String,
+    /// None if the booking for the student couldn't be made
+    pub aggregate: Option<FKey<Aggregate>>,
+    /// Why the booking for the student couldn't be made
+    pub error: Option<String>,
+}
+
+impl DBTable for Cohort {
+    fn table_name() -> &'static str {
+        "cohorts"
+    }
+
+    fn id(&self) -> ID {
+        self.id.into_id()
+    }
+
+    fn from_row(row: tokio_postgres::Row) -> Result<ExistingRow<Self>, anyhow::Error> {
+        Ok(ExistingRow::from_existing(Self {
+            id: row.try_get("id")?,
+            name: row.try_get("name")?,
+            instructor: row.try_get("instructor")?,
+            origin: row.try_get("origin")?,
+            template: row.try_get("template")?,
+            members: serde_json::from_value(row.try_get("members")?)?,
+            created: row.try_get("created")?,
+        }))
+    }
+
+    fn to_rowlike(&self) -> Result<HashMap<&str, Box<dyn ToSqlObject>>, anyhow::Error> {
+        let clone = self.clone();
+        let c: [(&str, Box<dyn ToSqlObject>); _] = [
+            ("id", Box::new(clone.id)),
+            ("name", Box::new(clone.name)),
+            ("instructor", Box::new(clone.instructor)),
+            ("origin", Box::new(clone.origin)),
+            ("template", Box::new(clone.template)),
+            ("members", Box::new(serde_json::to_value(clone.members)?)),
+            ("created", Box::new(clone.created)),
+        ];
+
+        Ok(c.into_iter().collect())
+    }
+}
+
+impl Cohort {
+    /// The cohorts an instructor has set up, newest first
+    pub async fn for_instructor(
+        t: &mut EasyTransaction<'_>,
+        instructor: &str,
+    ) -> Result<Vec<ExistingRow<Cohort>>, anyhow::Error> {
+        let tn = <Self as DBTable>::table_name();
+        let q = format!("SELECT * FROM {tn} WHERE instructor = $1 ORDER BY created DESC;");
+
+        let rows = t.query(&q, &[&instructor]).await.anyway()?;
+
+        Self::from_rows(rows)
+    }
+
+    /// The bookings that were made for the cohort
+    pub fn aggregates(&self) -> Vec<FKey<Aggregate>> {
+        self.members.iter().filter_map(|m| m.aggregate).collect()
+    }
+}
@@ -0,0 +1,126 @@
+use chrono::{DateTime, Utc};
+use dal::{web::*, *};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::dashboard::Aggregate;
+
+/// Something a collaborator can be kept from doing to a booking
+#[derive(
+    Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, JsonSchema,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum BookingCapability {
+    /// Turning hosts of the booking on and off
+    PowerControl,
+    Reimage,
+    EndBooking,
+}
+
+impl BookingCapability {
+    pub fn all() -> Vec<Self> {
+        vec![Self::PowerControl, Self::Reimage, Self::EndBooking]
+    }
+}
+
+/// What the owner of a booking allows one of its collaborators to do
+///
+/// Collaborators without one can do everything, like before permissions could be narrowed.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CollaboratorPermissions {
+    pub id: FKey<CollaboratorPermissions>,
+    pub aggregate: FKey<Aggregate>,
+    pub username: String,
+    pub capabilities: Vec<BookingCapability>,
+
+    pub granted_by: String,
+    pub updated: DateTime<Utc>,
+}
+
+impl DBTable for CollaboratorPermissions {
+    fn table_name() -> &'static str {
+        "collaborator_permissions"
+    }
+
+    fn id(&self) -> ID {
+        self.id.into_id()
+    }
+
+    fn from_row(row: tokio_postgres::Row) -> Result<ExistingRow<Self>, anyhow::Error> {
+        Ok(ExistingRow::from_existing(Self {
+            id: row.try_get("id")?,
+            aggregate: row.try_get("aggregate")?,
+            username: row.try_get("username")?,
+            capabilities: serde_json::from_value(row.try_get("capabilities")?)?,
+            granted_by: row.try_get("granted_by")?,
+            updated: row.try_get("updated")?,
+        }))
+    }
+
+    fn to_rowlike(&self) -> Result<HashMap<&str, Box<dyn ToSqlObject>>, anyhow::Error> {
+        let clone = self.clone();
+        let c: [(&str, Box<dyn ToSqlObject>); _] = [
+            ("id", Box::new(clone.id)),
+            ("aggregate", Box::new(clone.aggregate)),
+            ("username", Box::new(clone.username)),
+            (
+                "capabilities",
+                Box::new(serde_json::to_value(clone.capabilities)?),
+            ),
+            ("granted_by", Box::new(clone.granted_by)),
+            ("updated", Box::new(clone.updated)),
+        ];
+
+        Ok(c.into_iter().collect())
+    }
+}
+
+impl CollaboratorPermissions {
+    pub async fn find(
+        t: &mut EasyTransaction<'_>,
+        aggregate: FKey<Aggregate>,
+        username: &str,
+    ) -> Result<Option<ExistingRow<CollaboratorPermissions>>, anyhow::Error> {
+        let tn = <Self as DBTable>::table_name();
+        let q = format!("SELECT * FROM {tn} WHERE aggregate = $1 AND username = $2;");
+
+        let rows = t.query(&q, &[&aggregate, &username]).await.anyway()?;
+
+        Ok(Self::from_rows(rows)?.into_iter().next())
+    }
+
+    /// What `username` can do to `agg`, every capability for its owner and for
+    /// collaborators whose permissions were never narrowed, and none for anyone else
+    pub async fn of(
+        t: &mut EasyTransaction<'_>,
+        agg: &Aggregate,
+        username: &str,
+    ) -> Result<Vec<BookingCapability>, anyhow::Error> {
+        if agg.metadata.owner.as_deref() == Some(username) {
+            return Ok(BookingCapability::all());
+        }
+        if !agg.users.iter().any(|u| u == username) {
+            return Ok(Vec::new());
+        }
+
+        Ok(match Self::find(t, agg.id, username).await? {
+            Some(permissions) => permissions.into_inner().capabilities,
+            None => BookingCapability::all(),
+        })
+    }
+
+    /// Forgets what was granted to each of `usernames`, for when they leave the booking
+    pub async fn clear(
+        t: &mut EasyTransaction<'_>,
+        aggregate: FKey<Aggregate>,
+        usernames: &[String],
+    ) -> Result<(), anyhow::Error> {
+        let tn = <Self as DBTable>::table_name();
+        let q = format!("DELETE FROM {tn} WHERE aggregate = $1 AND username = ANY($2);");
+
+        t.execute(&q, &[&aggregate, &usernames]).await.anyway()?;
+
+        Ok(())
+    }
+}
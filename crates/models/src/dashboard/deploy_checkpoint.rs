@@ -0,0 +1,133 @@
+use chrono::{DateTime, Utc};
+use dal::{web::*, *};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::{dashboard::Instance, inventory::Host};
+
+/// A phase of deploying a host, in the order they are reached
+#[derive(
+    Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, JsonSchema,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum Checkpoint {
+    /// The host was set to netboot into the installer, and powered on
+    NetbootConfigured,
+    /// The installer finished writing the image to disk
+    ImageWritten,
+    /// The installed OS booted and phoned home
+    FirstBootSeen,
+    /// The host was put on its booking networks and finished configuring itself
+    PostConfigDone,
+    /// Inventory and IPMI accounts were set up, nothing is left to do
+    Finished,
+}
+
+impl Checkpoint {
+    pub const ALL: [Checkpoint; 5] = [
+        Checkpoint::NetbootConfigured,
+        Checkpoint::ImageWritten,
+        Checkpoint::FirstBootSeen,
+        Checkpoint::PostConfigDone,
+        Checkpoint::Finished,
+    ];
+
+    /// How far along a deploy that reached this checkpoint is, between 0 and 1
+    pub fn progress(&self) -> f64 {
+        let position = Self::ALL.iter().position(|c| c == self).unwrap_or(0) + 1;
+
+        position as f64 / Self::ALL.len() as f64
+    }
+}
+
+impl std::fmt::Display for Checkpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            Checkpoint::NetbootConfigured => "netboot was configured",
+            Checkpoint::ImageWritten => "the image was written",
+            Checkpoint::FirstBootSeen => "the host first booted",
+            Checkpoint::PostConfigDone => "the host finished configuring itself",
+            Checkpoint::Finished => "the provision finished",
+        };
+
+        write!(f, "{s}")
+    }
+}
+
+/// Records that the deploy of an instance onto a host got through a [`Checkpoint`]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DeployCheckpoint {
+    pub id: FKey<DeployCheckpoint>,
+    pub instance: FKey<Instance>,
+    pub host: FKey<Host>,
+    pub checkpoint: Checkpoint,
+    pub reached: DateTime<Utc>,
+}
+
+impl DBTable for DeployCheckpoint {
+    fn table_name() -> &'static str {
+        "deploy_checkpoints"
+    }
+
+    fn id(&self) -> ID {
+        self.id.into_id()
+    }
+
+    fn from_row(row: tokio_postgres::Row) -> Result<ExistingRow<Self>, anyhow::Error> {
+        Ok(ExistingRow::from_existing(Self {
+            id: row.try_get("id")?,
+            instance: row.try_get("instance")?,
+            host: row.try_get("host")?,
+            checkpoint: serde_json::from_value(row.try_get("checkpoint")?)?,
+            reached: row.try_get("reached")?,
+        }))
+    }
+
+    fn to_rowlike(&self) -> Result<HashMap<&str, Box<dyn ToSqlObject>>, anyhow::Error> {
+        let clone = self.clone();
+        let c: [(&str, Box<dyn ToSqlObject>); _] = [
+            ("id", Box::new(clone.id)),
+            ("instance", Box::new(clone.instance)),
+            ("host", Box::new(clone.host)),
+            (
+                "checkpoint",
+                Box::new(serde_json::to_value(clone.checkpoint)?),
+            ),
+            ("reached", Box::new(clone.reached)),
+        ];
+
+        Ok(c.into_iter().collect())
+    }
+}
+
+impl DeployCheckpoint {
+    /// The checkpoints the current deploy of `instance` got through, earliest first
+    pub async fn for_instance(
+        t: &mut EasyTransaction<'_>,
+        instance: FKey<Instance>,
+    ) -> Result<Vec<ExistingRow<DeployCheckpoint>>, anyhow::Error> {
+        let tn = <Self as DBTable>::table_name();
+        let q = format!("SELECT * FROM {tn} WHERE instance = $1 ORDER BY reached;");
+
+        let rows = t.query(&q, &[&instance]).await.anyway()?;
+
+        Self::from_rows(rows)
+    }
+
+    /// Forgets the checkpoints of `instance` that come after `keep`, or all of them if
+    /// `keep` is None, so that a deploy starting over can record them anew
+    pub async fn clear_after(
+        t: &mut EasyTransaction<'_>,
+        instance: FKey<Instance>,
+        keep: Option<Checkpoint>,
+    ) -> Result<(), anyhow::Error> {
+        for row in Self::for_instance(t, instance).await? {
+            if keep.map_or(true, |keep| row.checkpoint > keep) {
+                row.delete(t).await?;
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,106 @@
+use chrono::{DateTime, Utc};
+use dal::{web::*, *};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Soft bounces (full mailbox, greylisting, ...) in a row before a contact is
+/// considered unreachable
+pub const SOFT_BOUNCE_LIMIT: i32 = 3;
+
+/// What LaaS knows about whether mail to a user actually arrives
+///
+/// There is one of these per username, keyed on the address from IPA. When the
+/// address in IPA changes, the bounce history is reset along with it.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct EmailContact {
+    pub id: FKey<EmailContact>,
+    pub username: String,
+    pub email: String,
+
+    /// Last time the mail backend reported mail to this address as delivered
+    pub verified: Option<DateTime<Utc>>,
+    /// Soft bounces since the last delivered message
+    pub soft_bounces: i32,
+    /// When the address permanently bounced, if it has
+    pub hard_bounced: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
+}
+
+impl EmailContact {
+    pub fn new(username: String, email: String) -> Self {
+        Self {
+            id: FKey::new_id_dangling(),
+            username,
+            email,
+            verified: None,
+            soft_bounces: 0,
+            hard_bounced: None,
+            last_error: None,
+        }
+    }
+
+    pub fn unreachable(&self) -> bool {
+        self.hard_bounced.is_some() || self.soft_bounces >= SOFT_BOUNCE_LIMIT
+    }
+
+    pub async fn for_username(
+        t: &mut EasyTransaction<'_>,
+        username: &str,
+    ) -> Result<Option<ExistingRow<EmailContact>>, anyhow::Error> {
+        let tn = <Self as DBTable>::table_name();
+        let q = format!("SELECT * FROM {tn} WHERE username = $1;");
+
+        let row = t.query_opt(&q, &[&username]).await.anyway()?;
+
+        row.map(Self::from_row).transpose()
+    }
+
+    pub async fn all_for_email(
+        t: &mut EasyTransaction<'_>,
+        email: &str,
+    ) -> Result<Vec<ExistingRow<EmailContact>>, anyhow::Error> {
+        let tn = <Self as DBTable>::table_name();
+        let q = format!("SELECT * FROM {tn} WHERE lower(email) = lower($1);");
+
+        let rows = t.query(&q, &[&email]).await.anyway()?;
+
+        Self::from_rows(rows)
+    }
+}
+
+impl DBTable for EmailContact {
+    fn table_name() -> &'static str {
+        "email_contacts"
+    }
+
+    fn id(&self) -> ID {
+        self.id.into_id()
+    }
+
+    fn from_row(row: tokio_postgres::Row) -> Result<ExistingRow<Self>, anyhow::Error> {
+        Ok(ExistingRow::from_existing(Self {
+            id: row.try_get("id")?,
+            username: row.try_get("username")?,
+            email: row.try_get("email")?,
+            verified: row.try_get("verified")?,
+            soft_bounces: row.try_get("soft_bounces")?,
+            hard_bounced: row.try_get("hard_bounced")?,
+            last_error: row.try_get("last_error")?,
+        }))
+    }
+
+    fn to_rowlike(&self) -> Result<HashMap<&str, Box<dyn ToSqlObject>>, anyhow::Error> {
+        let clone = self.clone();
+        let c: [(&str, Box<dyn ToSqlObject>); _] = [
+            ("id", Box::new(clone.id)),
+            ("username", Box::new(clone.username)),
+            ("email", Box::new(clone.email)),
+            ("verified", Box::new(clone.verified)),
+            ("soft_bounces", Box::new(clone.soft_bounces)),
+            ("hard_bounced", Box::new(clone.hard_bounced)),
+            ("last_error", Box::new(clone.last_error)),
+        ];
+
+        Ok(c.into_iter().collect())
+    }
+}
@@ -0,0 +1,147 @@
+use chrono::{DateTime, NaiveDate, Utc};
+use dal::{web::*, *};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::dashboard::Aggregate;
+
+/// A request from a user to have their booking extended, which is sent on to
+/// the lab admins to decide on
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ExtensionRequest {
+    pub id: FKey<ExtensionRequest>,
+    pub aggregate: FKey<Aggregate>,
+    /// The end date asked for, as the user gave it
+    pub date: String,
+    pub reason: String,
+    pub requested: DateTime<Utc>,
+    /// Who asked, if the dashboard said
+    pub requested_by: Option<String>,
+
+    pub status: ExtensionStatus,
+    pub decided_by: Option<String>,
+    pub decided: Option<DateTime<Utc>>,
+    /// What the admin had to say about their decision
+    pub note: Option<String>,
+    /// The end the booking was given, once approved
+    pub granted_end: Option<DateTime<Utc>>,
+}
+
+/// A request starts out pending, and is then either approved or denied once and for all
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, JsonSchema)]
+pub enum ExtensionStatus {
+    Pending,
+    Approved,
+    Denied,
+}
+
+impl ExtensionRequest {
+    pub async fn all_for_aggregate(
+        t: &mut EasyTransaction<'_>,
+        aggregate: FKey<Aggregate>,
+    ) -> Result<Vec<ExistingRow<ExtensionRequest>>, anyhow::Error> {
+        let tn = <Self as DBTable>::table_name();
+        let q = format!("SELECT * FROM {tn} WHERE aggregate = $1 ORDER BY requested;");
+
+        let rows = t.query(&q, &[&aggregate]).await.anyway()?;
+
+        Self::from_rows(rows)
+    }
+
+    pub fn is_pending(&self) -> bool {
+        self.status == ExtensionStatus::Pending
+    }
+
+    /// The end date asked for, if it is in a form we understand
+    ///
+    /// Plain dates are taken as the very end of that day, in UTC.
+    pub fn requested_end(&self) -> Option<DateTime<Utc>> {
+        let date = self.date.trim();
+
+        DateTime::parse_from_rfc3339(date)
+            .or_else(|_| DateTime::parse_from_rfc2822(date))
+            .map(|d| d.with_timezone(&Utc))
+            .ok()
+            .or_else(|| {
+                NaiveDate::parse_from_str(date, "%Y-%m-%d")
+                    .ok()
+                    .and_then(|d| d.and_hms_opt(23, 59, 59))
+                    .map(|d| d.and_utc())
+            })
+    }
+
+    /// Moves the request out of pending, approving it with the end the booking was given
+    /// or denying it if there is none
+    ///
+    /// Fails if the request was already decided on.
+    pub fn decide(
+        &mut self,
+        granted_end: Option<DateTime<Utc>>,
+        decided_by: String,
+        note: Option<String>,
+    ) -> Result<(), anyhow::Error> {
+        if !self.is_pending() {
+            return Err(anyhow::Error::msg(format!(
+                "the extension request was already {:?}",
+                self.status
+            )));
+        }
+
+        self.status = match granted_end {
+            Some(_) => ExtensionStatus::Approved,
+            None => ExtensionStatus::Denied,
+        };
+        self.granted_end = granted_end;
+        self.decided_by = Some(decided_by);
+        self.decided = Some(Utc::now());
+        self.note = note;
+
+        Ok(())
+    }
+}
+
+impl DBTable for ExtensionRequest {
+    fn table_name() -> &'static str {
+        "extension_requests"
+    }
+
+    fn id(&self) -> ID {
+        self.id.into_id()
+    }
+
+    fn from_row(row: tokio_postgres::Row) -> Result<ExistingRow<Self>, anyhow::Error> {
+        Ok(ExistingRow::from_existing(Self {
+            id: row.try_get("id")?,
+            aggregate: row.try_get("aggregate")?,
+            date: row.try_get("date")?,
+            reason: row.try_get("reason")?,
+            requested: row.try_get("requested")?,
+            requested_by: row.try_get("requested_by")?,
+            status: serde_json::from_value(row.try_get("status")?)?,
+            decided_by: row.try_get("decided_by")?,
+            decided: row.try_get("decided")?,
+            note: row.try_get("note")?,
+            granted_end: row.try_get("granted_end")?,
+        }))
+    }
+
+    fn to_rowlike(&self) -> Result<HashMap<&str, Box<dyn ToSqlObject>>, anyhow::Error> {
+        let clone = self.clone();
+        let c: [(&str, Box<dyn ToSqlObject>); _] = [
+            ("id", Box::new(clone.id)),
+            ("aggregate", Box::new(clone.aggregate)),
+            ("date", Box::new(clone.date)),
+            ("reason", Box::new(clone.reason)),
+            ("requested", Box::new(clone.requested)),
+            ("requested_by", Box::new(clone.requested_by)),
+            ("status", Box::new(serde_json::to_value(clone.status)?)),
+            ("decided_by", Box::new(clone.decided_by)),
+            ("decided", Box::new(clone.decided)),
+            ("note", Box::new(clone.note)),
+            ("granted_end", Box::new(clone.granted_end)),
+        ];
+
+        Ok(c.into_iter().collect())
+    }
+}
@@ -0,0 +1,113 @@
+use chrono::{DateTime, Utc};
+use dal::{web::*, *};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::dashboard::{Aggregate, Network};
+
+/// A request for one of a booking's networks to be trunked onto the uplink
+/// of an external feed, which an admin has to approve before it is programmed
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ExternalAttachment {
+    pub id: FKey<ExternalAttachment>,
+    pub aggregate: FKey<Aggregate>,
+    pub network: FKey<Network>,
+
+    /// Name of the feed in the `external_feeds` config
+    pub feed: String,
+    pub state: AttachmentState,
+
+    pub requested_by: String,
+    pub reason: String,
+    /// The admin who approved or denied the request
+    pub reviewed_by: Option<String>,
+
+    pub requested: DateTime<Utc>,
+    pub updated: DateTime<Utc>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, JsonSchema)]
+pub enum AttachmentState {
+    /// Waiting on an admin to review it
+    Requested,
+    Denied,
+    /// Approved, and the vlan is trunked onto the feed uplink
+    Active,
+    /// Approved, but programming the uplink failed
+    Failed,
+    /// The vlan has been taken back off of the feed uplink
+    Removed,
+}
+
+impl DBTable for ExternalAttachment {
+    fn table_name() -> &'static str {
+        "external_attachments"
+    }
+
+    fn id(&self) -> ID {
+        self.id.into_id()
+    }
+
+    fn from_row(row: tokio_postgres::Row) -> Result<ExistingRow<Self>, anyhow::Error> {
+        Ok(ExistingRow::from_existing(Self {
+            id: row.try_get("id")?,
+            aggregate: row.try_get("aggregate")?,
+            network: row.try_get("network")?,
+            feed: row.try_get("feed")?,
+            state: serde_json::from_value(row.try_get("state")?)?,
+            requested_by: row.try_get("requested_by")?,
+            reason: row.try_get("reason")?,
+            reviewed_by: row.try_get("reviewed_by")?,
+            requested: row.try_get("requested")?,
+            updated: row.try_get("updated")?,
+        }))
+    }
+
+    fn to_rowlike(&self) -> Result<HashMap<&str, Box<dyn ToSqlObject>>, anyhow::Error> {
+        let clone = self.clone();
+        let c: [(&str, Box<dyn ToSqlObject>); _] = [
+            ("id", Box::new(clone.id)),
+            ("aggregate", Box::new(clone.aggregate)),
+            ("network", Box::new(clone.network)),
+            ("feed", Box::new(clone.feed)),
+            ("state", Box::new(serde_json::to_value(clone.state)?)),
+            ("requested_by", Box::new(clone.requested_by)),
+            ("reason", Box::new(clone.reason)),
+            ("reviewed_by", Box::new(clone.reviewed_by)),
+            ("requested", Box::new(clone.requested)),
+            ("updated", Box::new(clone.updated)),
+        ];
+
+        Ok(c.into_iter().collect())
+    }
+}
+
+impl ExternalAttachment {
+    pub async fn all_for_aggregate(
+        t: &mut EasyTransaction<'_>,
+        aggregate: FKey<Aggregate>,
+    ) -> Result<Vec<ExistingRow<ExternalAttachment>>, anyhow::Error> {
+        let tn = <Self as DBTable>::table_name();
+        let q = format!("SELECT * FROM {tn} WHERE aggregate = $1 ORDER BY requested;");
+
+        let rows = t.query(&q, &[&aggregate]).await.anyway()?;
+
+        Self::from_rows(rows)
+    }
+
+    /// Every attachment, across all bookings, that is waiting on review
+    pub async fn pending(
+        t: &mut EasyTransaction<'_>,
+    ) -> Result<Vec<ExistingRow<ExternalAttachment>>, anyhow::Error> {
+        let tn = <Self as DBTable>::table_name();
+        let q = format!("SELECT * FROM {tn} WHERE state = $1 ORDER BY requested;");
+
+        let rows = t
+            .query(&q, &[&serde_json::to_value(AttachmentState::Requested)?])
+            .await
+            .anyway()?;
+
+        Self::from_rows(rows)
+    }
+}
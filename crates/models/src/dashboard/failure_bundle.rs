@@ -0,0 +1,108 @@
+use chrono::{DateTime, Utc};
+use dal::{web::*, *};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::{
+    dashboard::{Aggregate, Instance, StatusSentiment},
+    inventory::Host,
+};
+
+/// What could be found out about a host that failed to provision, gathered once the
+/// deploy gave up so that admins don't have to go digging for it
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FailureBundle {
+    pub id: FKey<FailureBundle>,
+    pub instance: FKey<Instance>,
+    pub aggregate: FKey<Aggregate>,
+    pub host: FKey<Host>,
+    pub created: DateTime<Utc>,
+
+    /// What the last attempt at deploying failed with
+    pub error: String,
+    /// The provision log of the instance, earliest first
+    pub events: Vec<BundleEvent>,
+    /// The tail of the host's serial console, if it was being captured
+    pub console: Option<String>,
+    /// The lines dhcpd logged about the host's MACs
+    pub dhcp: Vec<String>,
+    pub switch_ports: Vec<PortState>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BundleEvent {
+    pub time: DateTime<Utc>,
+    pub sentiment: StatusSentiment,
+    pub event: String,
+    pub details: String,
+}
+
+/// How a switch port the host is cabled to was configured when the bundle was made
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PortState {
+    pub switch: String,
+    pub port: String,
+    /// The vlans the port carries, or why they couldn't be read
+    pub state: String,
+}
+
+impl DBTable for FailureBundle {
+    fn table_name() -> &'static str {
+        "failure_bundles"
+    }
+
+    fn id(&self) -> ID {
+        self.id.into_id()
+    }
+
+    fn from_row(row: tokio_postgres::Row) -> Result<ExistingRow<Self>, anyhow::Error> {
+        Ok(ExistingRow::from_existing(Self {
+            id: row.try_get("id")?,
+            instance: row.try_get("instance")?,
+            aggregate: row.try_get("aggregate")?,
+            host: row.try_get("host")?,
+            created: row.try_get("created")?,
+            error: row.try_get("error")?,
+            events: serde_json::from_value(row.try_get("events")?)?,
+            console: row.try_get("console")?,
+            dhcp: serde_json::from_value(row.try_get("dhcp")?)?,
+            switch_ports: serde_json::from_value(row.try_get("switch_ports")?)?,
+        }))
+    }
+
+    fn to_rowlike(&self) -> Result<HashMap<&str, Box<dyn ToSqlObject>>, anyhow::Error> {
+        let clone = self.clone();
+        let c: [(&str, Box<dyn ToSqlObject>); _] = [
+            ("id", Box::new(clone.id)),
+            ("instance", Box::new(clone.instance)),
+            ("aggregate", Box::new(clone.aggregate)),
+            ("host", Box::new(clone.host)),
+            ("created", Box::new(clone.created)),
+            ("error", Box::new(clone.error)),
+            ("events", Box::new(serde_json::to_value(clone.events)?)),
+            ("console", Box::new(clone.console)),
+            ("dhcp", Box::new(serde_json::to_value(clone.dhcp)?)),
+            (
+                "switch_ports",
+                Box::new(serde_json::to_value(clone.switch_ports)?),
+            ),
+        ];
+
+        Ok(c.into_iter().collect())
+    }
+}
+
+impl FailureBundle {
+    /// Newest first
+    pub async fn for_instance(
+        t: &mut EasyTransaction<'_>,
+        instance: FKey<Instance>,
+    ) -> Result<Vec<ExistingRow<FailureBundle>>, anyhow::Error> {
+        let tn = <Self as DBTable>::table_name();
+        let q = format!("SELECT * FROM {tn} WHERE instance = $1 ORDER BY created DESC;");
+
+        let rows = t.query(&q, &[&instance]).await.anyway()?;
+
+        Self::from_rows(rows)
+    }
+}
@@ -0,0 +1,94 @@
+use chrono::{DateTime, Utc};
+use dal::{web::*, *};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::dashboard::Aggregate;
+
+/// The inbound firewall policy for the uplinks of a booking
+///
+/// Everything inbound is denied except for traffic matching one of the `rules`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FirewallPolicy {
+    pub id: FKey<FirewallPolicy>,
+    pub aggregate: FKey<Aggregate>,
+    pub rules: Vec<FirewallRule>,
+
+    /// Whether the current rules have been programmed onto the lab edge
+    pub applied: bool,
+    pub updated: DateTime<Utc>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum FirewallProtocol {
+    Tcp,
+    Udp,
+    Icmp,
+}
+
+/// An inclusive range of ports
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, JsonSchema)]
+pub struct PortRange {
+    pub start: u16,
+    pub end: u16,
+}
+
+/// Allows inbound traffic from `source` to the booking
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash, JsonSchema)]
+pub struct FirewallRule {
+    pub protocol: FirewallProtocol,
+    /// Must be None for icmp, if None for tcp or udp then all ports are allowed
+    pub ports: Option<PortRange>,
+    /// The CIDR that traffic may originate from
+    pub source: String,
+    pub description: Option<String>,
+}
+
+impl DBTable for FirewallPolicy {
+    fn table_name() -> &'static str {
+        "firewall_policies"
+    }
+
+    fn id(&self) -> ID {
+        self.id.into_id()
+    }
+
+    fn from_row(row: tokio_postgres::Row) -> Result<ExistingRow<Self>, anyhow::Error> {
+        Ok(ExistingRow::from_existing(Self {
+            id: row.try_get("id")?,
+            aggregate: row.try_get("aggregate")?,
+            rules: serde_json::from_value(row.try_get("rules")?)?,
+            applied: row.try_get("applied")?,
+            updated: row.try_get("updated")?,
+        }))
+    }
+
+    fn to_rowlike(&self) -> Result<HashMap<&str, Box<dyn ToSqlObject>>, anyhow::Error> {
+        let clone = self.clone();
+        let c: [(&str, Box<dyn ToSqlObject>); _] = [
+            ("id", Box::new(clone.id)),
+            ("aggregate", Box::new(clone.aggregate)),
+            ("rules", Box::new(serde_json::to_value(clone.rules)?)),
+            ("applied", Box::new(clone.applied)),
+            ("updated", Box::new(clone.updated)),
+        ];
+
+        Ok(c.into_iter().collect())
+    }
+}
+
+impl FirewallPolicy {
+    pub async fn for_aggregate(
+        t: &mut EasyTransaction<'_>,
+        aggregate: FKey<Aggregate>,
+    ) -> Result<Option<ExistingRow<FirewallPolicy>>, anyhow::Error> {
+        let tn = <Self as DBTable>::table_name();
+        let q = format!("SELECT * FROM {tn} WHERE aggregate = $1;");
+
+        let row = t.query_opt(&q, &[&aggregate]).await.anyway()?;
+
+        row.map(Self::from_row).transpose()
+    }
+}
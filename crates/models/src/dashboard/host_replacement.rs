@@ -0,0 +1,128 @@
+use chrono::{DateTime, Utc};
+use dal::{web::*, *};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::{
+    dashboard::{Aggregate, Instance},
+    inventory::Host,
+};
+
+/// A request from the owner of a booking to swap a faulty host out for another of the
+/// same flavor, which admins may have to approve before it is carried out
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct HostReplacement {
+    pub id: FKey<HostReplacement>,
+    pub aggregate: FKey<Aggregate>,
+    pub instance: FKey<Instance>,
+
+    /// The host the instance was on when the replacement was asked for
+    pub faulty_host: FKey<Host>,
+    /// The host that was allocated in its place, once there is one
+    pub replacement_host: Option<FKey<Host>>,
+    pub state: ReplacementState,
+
+    pub requested_by: String,
+    pub reason: String,
+    /// The admin who approved or denied the request, if it needed review
+    pub reviewed_by: Option<String>,
+
+    pub requested: DateTime<Utc>,
+    pub updated: DateTime<Utc>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, JsonSchema)]
+pub enum ReplacementState {
+    /// Waiting on an admin to review it
+    Requested,
+    Denied,
+    /// A replacement is being allocated and provisioned
+    Replacing,
+    /// The instance runs on the replacement, and the faulty host is in maintenance
+    Replaced,
+    /// No replacement could be provisioned, so the instance was left on the faulty host
+    Failed,
+}
+
+impl DBTable for HostReplacement {
+    fn table_name() -> &'static str {
+        "host_replacements"
+    }
+
+    fn id(&self) -> ID {
+        self.id.into_id()
+    }
+
+    fn from_row(row: tokio_postgres::Row) -> Result<ExistingRow<Self>, anyhow::Error> {
+        Ok(ExistingRow::from_existing(Self {
+            id: row.try_get("id")?,
+            aggregate: row.try_get("aggregate")?,
+            instance: row.try_get("instance")?,
+            faulty_host: row.try_get("faulty_host")?,
+            replacement_host: row.try_get("replacement_host")?,
+            state: serde_json::from_value(row.try_get("state")?)?,
+            requested_by: row.try_get("requested_by")?,
+            reason: row.try_get("reason")?,
+            reviewed_by: row.try_get("reviewed_by")?,
+            requested: row.try_get("requested")?,
+            updated: row.try_get("updated")?,
+        }))
+    }
+
+    fn to_rowlike(&self) -> Result<HashMap<&str, Box<dyn ToSqlObject>>, anyhow::Error> {
+        let clone = self.clone();
+        let c: [(&str, Box<dyn ToSqlObject>); _] = [
+            ("id", Box::new(clone.id)),
+            ("aggregate", Box::new(clone.aggregate)),
+            ("instance", Box::new(clone.instance)),
+            ("faulty_host", Box::new(clone.faulty_host)),
+            ("replacement_host", Box::new(clone.replacement_host)),
+            ("state", Box::new(serde_json::to_value(clone.state)?)),
+            ("requested_by", Box::new(clone.requested_by)),
+            ("reason", Box::new(clone.reason)),
+            ("reviewed_by", Box::new(clone.reviewed_by)),
+            ("requested", Box::new(clone.requested)),
+            ("updated", Box::new(clone.updated)),
+        ];
+
+        Ok(c.into_iter().collect())
+    }
+}
+
+impl HostReplacement {
+    /// Whether the replacement is still to be carried out or being carried out
+    pub fn is_open(&self) -> bool {
+        matches!(
+            self.state,
+            ReplacementState::Requested | ReplacementState::Replacing
+        )
+    }
+
+    pub async fn all_for_instance(
+        t: &mut EasyTransaction<'_>,
+        instance: FKey<Instance>,
+    ) -> Result<Vec<ExistingRow<HostReplacement>>, anyhow::Error> {
+        let tn = <Self as DBTable>::table_name();
+        let q = format!("SELECT * FROM {tn} WHERE instance = $1 ORDER BY requested;");
+
+        let rows = t.query(&q, &[&instance]).await.anyway()?;
+
+        Self::from_rows(rows)
+    }
+
+    /// Every replacement, across all bookings, that is waiting on review
+    pub async fn pending(
+        t: &mut EasyTransaction<'_>,
+    ) -> Result<Vec<ExistingRow<HostReplacement>>, anyhow::Error> {
+        let tn = <Self as DBTable>::table_name();
+        let q = format!("SELECT * FROM {tn} WHERE state = $1 ORDER BY requested;");
+
+        let rows = t
+            .query(&q, &[&serde_json::to_value(ReplacementState::Requested)?])
+            .await
+            .anyway()?;
+
+        Self::from_rows(rows)
+    }
+}
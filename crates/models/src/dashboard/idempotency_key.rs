@@ -0,0 +1,118 @@
+use chrono::{DateTime, Utc};
+use dal::{web::*, *};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::dashboard::Aggregate;
+
+/// A key a client sent along with a request to create a booking, so that sending the
+/// same request again gets back the booking the first one made instead of a second one
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct IdempotencyKey {
+    pub id: FKey<IdempotencyKey>,
+    /// Who sent the key, keys only have to be unique between requests of the same user
+    pub username: String,
+    pub key: String,
+    /// The request the key was first sent with, which replays have to match
+    pub request: serde_json::Value,
+    /// The booking the request made, None while it is still being made
+    pub aggregate: Option<FKey<Aggregate>>,
+    pub created: DateTime<Utc>,
+    /// After this the key no longer counts, and may be used for a new request
+    pub expires: DateTime<Utc>,
+}
+
+impl DBTable for IdempotencyKey {
+    fn table_name() -> &'static str {
+        "idempotency_keys"
+    }
+
+    fn id(&self) -> ID {
+        self.id.into_id()
+    }
+
+    fn from_row(row: tokio_postgres::Row) -> Result<ExistingRow<Self>, anyhow::Error> {
+        Ok(ExistingRow::from_existing(Self {
+            id: row.try_get("id")?,
+            username: row.try_get("username")?,
+            key: row.try_get("key")?,
+            request: row.try_get("request")?,
+            aggregate: row.try_get("aggregate")?,
+            created: row.try_get("created")?,
+            expires: row.try_get("expires")?,
+        }))
+    }
+
+    fn to_rowlike(&self) -> Result<HashMap<&str, Box<dyn ToSqlObject>>, anyhow::Error> {
+        let clone = self.clone();
+        let c: [(&str, Box<dyn ToSqlObject>); _] = [
+            ("id", Box::new(clone.id)),
+            ("username", Box::new(clone.username)),
+            ("key", Box::new(clone.key)),
+            ("request", Box::new(clone.request)),
+            ("aggregate", Box::new(clone.aggregate)),
+            ("created", Box::new(clone.created)),
+            ("expires", Box::new(clone.expires)),
+        ];
+
+        Ok(c.into_iter().collect())
+    }
+}
+
+impl IdempotencyKey {
+    /// The key, if `username` sent it before and it hasn't expired yet
+    pub async fn find(
+        t: &mut EasyTransaction<'_>,
+        username: &str,
+        key: &str,
+    ) -> Result<Option<ExistingRow<IdempotencyKey>>, anyhow::Error> {
+        let tn = <Self as DBTable>::table_name();
+        let q = format!("SELECT * FROM {tn} WHERE username = $1 AND key = $2 AND expires > $3;");
+
+        let rows = t
+            .query(&q, &[&username, &key, &Utc::now()])
+            .await
+            .anyway()?;
+
+        Ok(Self::from_rows(rows)?.into_iter().next())
+    }
+
+    /// Inserts the key, or returns None if the user already has a row for it
+    pub async fn claim(
+        &self,
+        t: &mut EasyTransaction<'_>,
+    ) -> Result<Option<FKey<IdempotencyKey>>, anyhow::Error> {
+        let tn = <Self as DBTable>::table_name();
+        let q = format!(
+            "INSERT INTO {tn} (id, username, key, request, aggregate, created, expires)
+                VALUES ($1, $2, $3, $4, $5, $6, $7)
+                ON CONFLICT (username, key) DO NOTHING;"
+        );
+
+        let inserted = t
+            .execute(
+                &q,
+                &[
+                    &self.id,
+                    &self.username,
+                    &self.key,
+                    &self.request,
+                    &self.aggregate,
+                    &self.created,
+                    &self.expires,
+                ],
+            )
+            .await
+            .anyway()?;
+
+        Ok((inserted == 1).then_some(self.id))
+    }
+
+    /// Forgets every key that has expired, returning how many there were
+    pub async fn purge_expired(t: &mut EasyTransaction<'_>) -> Result<u64, anyhow::Error> {
+        let tn = <Self as DBTable>::table_name();
+        let q = format!("DELETE FROM {tn} WHERE expires <= $1;");
+
+        t.execute(&q, &[&Utc::now()]).await.anyway()
+    }
+}
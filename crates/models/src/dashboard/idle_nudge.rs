@@ -0,0 +1,101 @@
+use chrono::{DateTime, Utc};
+use dal::{web::*, *};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::dashboard::Aggregate;
+
+/// What the owner of an idle booking answered when asked about it
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, JsonSchema)]
+pub enum IdleAnswer {
+    Keep,
+    Release,
+}
+
+/// One time the owner of a booking whose hosts sat idle was asked whether they still need it
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct IdleNudge {
+    pub id: FKey<IdleNudge>,
+    pub aggregate: FKey<Aggregate>,
+    /// Put in the keep and release links, so the owner can answer without logging in
+    pub token: String,
+    pub sent: DateTime<Utc>,
+    /// How many asks in a row, this one included, found the booking idle
+    pub streak: i32,
+    /// Whether the admins were told about the booking with this ask
+    pub flagged: bool,
+    pub answer: Option<IdleAnswer>,
+    pub answered: Option<DateTime<Utc>>,
+}
+
+impl IdleNudge {
+    pub async fn latest_for_aggregate(
+        t: &mut EasyTransaction<'_>,
+        aggregate: FKey<Aggregate>,
+    ) -> Result<Option<ExistingRow<IdleNudge>>, anyhow::Error> {
+        let tn = <Self as DBTable>::table_name();
+        let q = format!("SELECT * FROM {tn} WHERE aggregate = $1 ORDER BY sent DESC LIMIT 1;");
+
+        let row = t.query_opt(&q, &[&aggregate]).await.anyway()?;
+
+        row.map(Self::from_row).transpose()
+    }
+
+    pub async fn by_token(
+        t: &mut EasyTransaction<'_>,
+        token: &str,
+    ) -> Result<Option<ExistingRow<IdleNudge>>, anyhow::Error> {
+        let tn = <Self as DBTable>::table_name();
+        let q = format!("SELECT * FROM {tn} WHERE token = $1;");
+
+        let row = t.query_opt(&q, &[&token]).await.anyway()?;
+
+        row.map(Self::from_row).transpose()
+    }
+}
+
+impl DBTable for IdleNudge {
+    fn table_name() -> &'static str {
+        "idle_nudges"
+    }
+
+    fn id(&self) -> ID {
+        self.id.into_id()
+    }
+
+    fn from_row(row: tokio_postgres::Row) -> Result<ExistingRow<Self>, anyhow::Error> {
+        Ok(ExistingRow::from_existing(Self {
+            id: row.try_get("id")?,
+            aggregate: row.try_get("aggregate")?,
+            token: row.try_get("token")?,
+            sent: row.try_get("sent")?,
+            streak: row.try_get("streak")?,
+            flagged: row.try_get("flagged")?,
+            answer: row
+                .try_get::<_, Option<serde_json::Value>>("answer")?
+                .map(serde_json::from_value)
+                .transpose()?,
+            answered: row.try_get("answered")?,
+        }))
+    }
+
+    fn to_rowlike(&self) -> Result<HashMap<&str, Box<dyn ToSqlObject>>, anyhow::Error> {
+        let clone = self.clone();
+        let c: [(&str, Box<dyn ToSqlObject>); _] = [
+            ("id", Box::new(clone.id)),
+            ("aggregate", Box::new(clone.aggregate)),
+            ("token", Box::new(clone.token)),
+            ("sent", Box::new(clone.sent)),
+            ("streak", Box::new(clone.streak)),
+            ("flagged", Box::new(clone.flagged)),
+            (
+                "answer",
+                Box::new(clone.answer.map(serde_json::to_value).transpose()?),
+            ),
+            ("answered", Box::new(clone.answered)),
+        ];
+
+        Ok(c.into_iter().collect())
+    }
+}
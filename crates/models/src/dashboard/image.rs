@@ -2,6 +2,7 @@ use common::prelude::reqwest::StatusCode;
 use dal::{web::*, *};
 use std::{fs::File, io::Write, path::PathBuf};
 
+use chrono::{DateTime, Utc};
 use common::prelude::*;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
@@ -18,6 +19,22 @@ pub struct Image {
     pub cobbler_name: String,
     pub public: bool,
     pub flavors: Vec<FKey<Flavor>>, // vector of compatible flavor IDs
+
+    /// When admins stopped recommending this image, it can still be booked until `sunset`
+    pub deprecated: Option<DateTime<Utc>>,
+    /// After this no new bookings can use the image
+    pub sunset: Option<DateTime<Utc>>,
+    /// What users should switch to instead
+    pub replacement: Option<FKey<Image>>,
+    pub changelog: Vec<ImageChange>,
+}
+
+/// A note admins left about a change to an image, oldest first in `Image::changelog`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ImageChange {
+    pub time: DateTime<Utc>,
+    pub author: String,
+    pub note: String,
 }
 
 impl Named for Image {
@@ -68,6 +85,10 @@ impl ImportImage {
             cobbler_name: clone.cobbler_name,
             public: clone.public,
             flavors,
+            deprecated: None,
+            sunset: None,
+            replacement: None,
+            changelog: Vec::new(),
         }
     }
 
@@ -113,6 +134,11 @@ impl Importable for Image {
 
         if let Ok(mut orig_image) = Image::lookup(transaction, Image::name_parts(&image)).await {
             image.id = orig_image.id;
+            // deprecation is managed from the admin CLI, not the import files
+            image.deprecated = orig_image.deprecated;
+            image.sunset = orig_image.sunset;
+            image.replacement = orig_image.replacement;
+            image.changelog = orig_image.changelog.clone();
 
             orig_image.mass_update(image).unwrap();
 
@@ -174,6 +200,10 @@ impl DBTable for Image {
             cobbler_name: row.try_get("cobbler_name")?,
             public: row.try_get("public")?,
             flavors: row.try_get("flavors")?,
+            deprecated: row.try_get("deprecated")?,
+            sunset: row.try_get("sunset")?,
+            replacement: row.try_get("replacement")?,
+            changelog: serde_json::from_value(row.try_get("changelog")?)?,
         }))
     }
 
@@ -187,6 +217,13 @@ impl DBTable for Image {
             ("cobbler_name", Box::new(clone.cobbler_name)),
             ("public", Box::new(clone.public)),
             ("flavors", Box::new(clone.flavors)),
+            ("deprecated", Box::new(clone.deprecated)),
+            ("sunset", Box::new(clone.sunset)),
+            ("replacement", Box::new(clone.replacement)),
+            (
+                "changelog",
+                Box::new(serde_json::to_value(clone.changelog)?),
+            ),
         ];
 
         Ok(c.into_iter().collect())
@@ -194,6 +231,11 @@ impl DBTable for Image {
 }
 
 impl Image {
+    /// Whether the sunset date has passed, so the image can't be used for new bookings
+    pub fn sunset_passed(&self, now: DateTime<Utc>) -> bool {
+        self.sunset.is_some_and(|s| s <= now)
+    }
+
     pub async fn get_by_name(
         t: &mut EasyTransaction<'_>,
         name: String,
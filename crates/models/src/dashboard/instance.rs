@@ -11,9 +11,14 @@ use crate::dashboard::types::ProvEvent;
 use crate::EasyLog;
 
 use crate::dashboard::{
-    Aggregate, HostConfig, NetworkAssignmentMap, ProvisionLogEvent, StatusSentiment, Template,
+    Aggregate, AggregateSummary, HostConfig, NetworkAssignmentMap, ProvisionLogEvent,
+    StatusSentiment, Template,
 };
 
+/// The instance metadata key set when the deploy of an instance was canceled, so that the
+/// deploy stops the next time it checks instead of carrying on with the host
+pub const PROVISION_CANCELED: &str = "provision_canceled";
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Instance {
     pub id: FKey<Instance>, // Instance id which exists when the host is being provisioned
@@ -76,12 +81,13 @@ impl DBTable for Instance {
 }
 
 impl Instance {
+    /// Adds the event to the provision log of `inst`, giving what was logged
     pub async fn log(
         inst: FKey<Instance>,
         transaction: &mut EasyTransaction<'_>,
         event: ProvEvent,
         sentiment: Option<StatusSentiment>,
-    ) -> Result<(), anyhow::Error> {
+    ) -> Result<ProvisionLogEvent, anyhow::Error> {
         let ple = ProvisionLogEvent {
             id: FKey::new_id_dangling(),
             sentiment: sentiment.unwrap_or(StatusSentiment::Unknown),
@@ -90,18 +96,22 @@ impl Instance {
             prov_status: event,
         };
 
-        let nr = NewRow::new(ple);
+        let nr = NewRow::new(ple.clone());
 
         nr.insert(transaction).await?;
 
-        Ok(())
+        // booking lists show the latest progress without reading the logs
+        let aggregate = inst.get(transaction).await?.aggregate;
+        AggregateSummary::refresh(transaction, aggregate).await?;
+
+        Ok(ple)
     }
 
     pub async fn log_committing(
         inst: FKey<Instance>,
         event: ProvEvent,
         sentiment: Option<StatusSentiment>,
-    ) -> Result<(), anyhow::Error> {
+    ) -> Result<ProvisionLogEvent, anyhow::Error> {
         let mut client = new_client().await.log_db_client_error().unwrap();
         let mut transaction = client
             .easy_transaction()
@@ -109,10 +119,10 @@ impl Instance {
             .log_db_client_error()
             .unwrap();
 
-        Instance::log(inst, &mut transaction, event, sentiment).await?;
+        let logged = Instance::log(inst, &mut transaction, event, sentiment).await?;
         transaction.commit().await?;
 
-        Ok(())
+        Ok(logged)
     }
 }
 
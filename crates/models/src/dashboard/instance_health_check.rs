@@ -0,0 +1,109 @@
+use chrono::{DateTime, Utc};
+use dal::{web::*, *};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::dashboard::{Aggregate, Instance};
+
+/// The outcome of one periodic check of a booked host while its booking is active,
+/// used to work out the availability the lab delivered
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct InstanceHealthCheck {
+    pub id: FKey<InstanceHealthCheck>,
+    pub instance: FKey<Instance>,
+    pub aggregate: FKey<Aggregate>,
+    pub time: DateTime<Utc>,
+
+    /// The BMC reported chassis power as on
+    pub powered_on: bool,
+    /// The host answered a ping
+    pub reachable: bool,
+    /// The host had a power action requested through LaaS shortly before this check,
+    /// so a failure here is expected and doesn't count against availability
+    pub excused: bool,
+    /// The host went down since the previous check without anyone asking LaaS to reboot it
+    pub unplanned_reboot: bool,
+}
+
+impl InstanceHealthCheck {
+    pub fn passed(&self) -> bool {
+        self.powered_on && self.reachable
+    }
+
+    pub async fn all_for_instance(
+        t: &mut EasyTransaction<'_>,
+        instance: FKey<Instance>,
+    ) -> Result<Vec<ExistingRow<InstanceHealthCheck>>, anyhow::Error> {
+        let tn = <Self as DBTable>::table_name();
+        let q = format!("SELECT * FROM {tn} WHERE instance = $1 ORDER BY time;");
+
+        let rows = t.query(&q, &[&instance]).await.anyway()?;
+
+        Self::from_rows(rows)
+    }
+
+    /// The checks of `instance` since `since`, oldest first
+    pub async fn for_instance_since(
+        t: &mut EasyTransaction<'_>,
+        instance: FKey<Instance>,
+        since: DateTime<Utc>,
+    ) -> Result<Vec<ExistingRow<InstanceHealthCheck>>, anyhow::Error> {
+        let tn = <Self as DBTable>::table_name();
+        let q = format!("SELECT * FROM {tn} WHERE instance = $1 AND time >= $2 ORDER BY time;");
+
+        let rows = t.query(&q, &[&instance, &since]).await.anyway()?;
+
+        Self::from_rows(rows)
+    }
+
+    pub async fn latest_for_instance(
+        t: &mut EasyTransaction<'_>,
+        instance: FKey<Instance>,
+    ) -> Result<Option<ExistingRow<InstanceHealthCheck>>, anyhow::Error> {
+        let tn = <Self as DBTable>::table_name();
+        let q = format!("SELECT * FROM {tn} WHERE instance = $1 ORDER BY time DESC LIMIT 1;");
+
+        let row = t.query_opt(&q, &[&instance]).await.anyway()?;
+
+        row.map(Self::from_row).transpose()
+    }
+}
+
+impl DBTable for InstanceHealthCheck {
+    fn table_name() -> &'static str {
+        "instance_health_checks"
+    }
+
+    fn id(&self) -> ID {
+        self.id.into_id()
+    }
+
+    fn from_row(row: tokio_postgres::Row) -> Result<ExistingRow<Self>, anyhow::Error> {
+        Ok(ExistingRow::from_existing(Self {
+            id: row.try_get("id")?,
+            instance: row.try_get("instance")?,
+            aggregate: row.try_get("aggregate")?,
+            time: row.try_get("time")?,
+            powered_on: row.try_get("powered_on")?,
+            reachable: row.try_get("reachable")?,
+            excused: row.try_get("excused")?,
+            unplanned_reboot: row.try_get("unplanned_reboot")?,
+        }))
+    }
+
+    fn to_rowlike(&self) -> Result<HashMap<&str, Box<dyn ToSqlObject>>, anyhow::Error> {
+        let clone = self.clone();
+        let c: [(&str, Box<dyn ToSqlObject>); _] = [
+            ("id", Box::new(clone.id)),
+            ("instance", Box::new(clone.instance)),
+            ("aggregate", Box::new(clone.aggregate)),
+            ("time", Box::new(clone.time)),
+            ("powered_on", Box::new(clone.powered_on)),
+            ("reachable", Box::new(clone.reachable)),
+            ("excused", Box::new(clone.excused)),
+            ("unplanned_reboot", Box::new(clone.unplanned_reboot)),
+        ];
+
+        Ok(c.into_iter().collect())
+    }
+}
@@ -0,0 +1,76 @@
+use chrono::{DateTime, Utc};
+use dal::{web::*, *};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::dashboard::Aggregate;
+
+/// The recorded outcome of auditing that a booking's networks are isolated
+/// from those of every other booking
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct IsolationAttestation {
+    pub id: FKey<IsolationAttestation>,
+    pub aggregate: FKey<Aggregate>,
+    pub time: DateTime<Utc>,
+
+    /// True only if no findings were made
+    pub passed: bool,
+    pub findings: Vec<IsolationFinding>,
+}
+
+/// A single way in which a booking was found to not be isolated
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+pub struct IsolationFinding {
+    /// The name of the network within the booking that is affected
+    pub network: String,
+    pub vlan_id: Option<i16>,
+    pub detail: String,
+}
+
+impl DBTable for IsolationAttestation {
+    fn table_name() -> &'static str {
+        "isolation_attestations"
+    }
+
+    fn id(&self) -> ID {
+        self.id.into_id()
+    }
+
+    fn from_row(row: tokio_postgres::Row) -> Result<ExistingRow<Self>, anyhow::Error> {
+        Ok(ExistingRow::from_existing(Self {
+            id: row.try_get("id")?,
+            aggregate: row.try_get("aggregate")?,
+            time: row.try_get("time")?,
+            passed: row.try_get("passed")?,
+            findings: serde_json::from_value(row.try_get("findings")?)?,
+        }))
+    }
+
+    fn to_rowlike(&self) -> Result<HashMap<&str, Box<dyn ToSqlObject>>, anyhow::Error> {
+        let clone = self.clone();
+        let c: [(&str, Box<dyn ToSqlObject>); _] = [
+            ("id", Box::new(clone.id)),
+            ("aggregate", Box::new(clone.aggregate)),
+            ("time", Box::new(clone.time)),
+            ("passed", Box::new(clone.passed)),
+            ("findings", Box::new(serde_json::to_value(clone.findings)?)),
+        ];
+
+        Ok(c.into_iter().collect())
+    }
+}
+
+impl IsolationAttestation {
+    pub async fn latest_for_aggregate(
+        t: &mut EasyTransaction<'_>,
+        aggregate: FKey<Aggregate>,
+    ) -> Result<Option<ExistingRow<IsolationAttestation>>, anyhow::Error> {
+        let tn = <Self as DBTable>::table_name();
+        let q = format!("SELECT * FROM {tn} WHERE aggregate = $1 ORDER BY time DESC LIMIT 1;");
+
+        let row = t.query_opt(&q, &[&aggregate]).await.anyway()?;
+
+        row.map(Self::from_row).transpose()
+    }
+}
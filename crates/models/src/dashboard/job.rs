@@ -0,0 +1,160 @@
+use chrono::{DateTime, Utc};
+use dal::{web::*, *};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// A long running admin operation, which the endpoint starting it answers with the id of
+/// right away so that the caller can follow it, cancel it and fetch what it came up with
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+pub struct Job {
+    pub id: FKey<Job>,
+    /// Which operation this is, like `credential_rotation`
+    pub kind: String,
+    pub requested_by: String,
+    pub status: JobStatus,
+
+    /// How many of `total` steps are done
+    pub done: i32,
+    pub total: i32,
+    /// What the job is doing right now
+    pub message: Option<String>,
+
+    /// Set when someone asks for the job to stop, the job stops at its next step
+    pub cancel_requested: bool,
+    /// Whatever the operation reports back, present once it finishes
+    pub result: Option<serde_json::Value>,
+    /// Why the job couldn't finish
+    pub error: Option<String>,
+
+    #[schemars(with = "String")]
+    pub created: DateTime<Utc>,
+    #[schemars(with = "Option<String>")]
+    pub started: Option<DateTime<Utc>>,
+    #[schemars(with = "Option<String>")]
+    pub finished: Option<DateTime<Utc>>,
+    #[schemars(with = "String")]
+    pub updated: DateTime<Utc>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, JsonSchema)]
+pub enum JobStatus {
+    Queued,
+    Running,
+    /// Finished, and everything it was asked to do went through
+    Succeeded,
+    /// Finished, but an error stopped it or part of what it was asked to do didn't go through
+    Failed,
+    /// Stopped early because it was asked to
+    Cancelled,
+}
+
+impl JobStatus {
+    pub fn finished(&self) -> bool {
+        matches!(self, Self::Succeeded | Self::Failed | Self::Cancelled)
+    }
+}
+
+impl Job {
+    pub fn new(kind: &str, requested_by: &str, total: usize) -> Self {
+        let now = Utc::now();
+
+        Self {
+            id: FKey::new_id_dangling(),
+            kind: kind.to_owned(),
+            requested_by: requested_by.to_owned(),
+            status: JobStatus::Queued,
+            done: 0,
+            total: total as i32,
+            message: None,
+            cancel_requested: false,
+            result: None,
+            error: None,
+            created: now,
+            started: None,
+            finished: None,
+            updated: now,
+        }
+    }
+
+    /// The job, locked until the transaction ends so that nothing else changes it
+    /// between reading and writing it back
+    pub async fn lock(
+        t: &mut EasyTransaction<'_>,
+        id: FKey<Job>,
+    ) -> Result<ExistingRow<Job>, anyhow::Error> {
+        let tn = <Self as DBTable>::table_name();
+        let q = format!("SELECT * FROM {tn} WHERE id = $1 FOR UPDATE;");
+
+        let row = t.query_one(&q, &[&id]).await.anyway()?;
+
+        Self::from_row(row)
+    }
+
+    /// Jobs that haven't finished, oldest first
+    pub async fn unfinished(
+        t: &mut EasyTransaction<'_>,
+    ) -> Result<Vec<ExistingRow<Job>>, anyhow::Error> {
+        let tn = <Self as DBTable>::table_name();
+        let q = format!("SELECT * FROM {tn} WHERE status = ANY($1) ORDER BY created;");
+
+        let statuses = vec![
+            serde_json::to_value(JobStatus::Queued)?,
+            serde_json::to_value(JobStatus::Running)?,
+        ];
+        let rows = t.query(&q, &[&statuses]).await.anyway()?;
+
+        Self::from_rows(rows)
+    }
+}
+
+impl DBTable for Job {
+    fn table_name() -> &'static str {
+        "jobs"
+    }
+
+    fn id(&self) -> ID {
+        self.id.into_id()
+    }
+
+    fn from_row(row: tokio_postgres::Row) -> Result<ExistingRow<Self>, anyhow::Error> {
+        Ok(ExistingRow::from_existing(Self {
+            id: row.try_get("id")?,
+            kind: row.try_get("kind")?,
+            requested_by: row.try_get("requested_by")?,
+            status: serde_json::from_value(row.try_get("status")?)?,
+            done: row.try_get("done")?,
+            total: row.try_get("total")?,
+            message: row.try_get("message")?,
+            cancel_requested: row.try_get("cancel_requested")?,
+            result: row.try_get("result")?,
+            error: row.try_get("error")?,
+            created: row.try_get("created")?,
+            started: row.try_get("started")?,
+            finished: row.try_get("finished")?,
+            updated: row.try_get("updated")?,
+        }))
+    }
+
+    fn to_rowlike(&self) -> Result<HashMap<&str, Box<dyn ToSqlObject>>, anyhow::Error> {
+        let clone = self.clone();
+        let c: [(&str, Box<dyn ToSqlObject>); _] = [
+            ("id", Box::new(clone.id)),
+            ("kind", Box::new(clone.kind)),
+            ("requested_by", Box::new(clone.requested_by)),
+            ("status", Box::new(serde_json::to_value(clone.status)?)),
+            ("done", Box::new(clone.done)),
+            ("total", Box::new(clone.total)),
+            ("message", Box::new(clone.message)),
+            ("cancel_requested", Box::new(clone.cancel_requested)),
+            ("result", Box::new(clone.result)),
+            ("error", Box::new(clone.error)),
+            ("created", Box::new(clone.created)),
+            ("started", Box::new(clone.started)),
+            ("finished", Box::new(clone.finished)),
+            ("updated", Box::new(clone.updated)),
+        ];
+
+        Ok(c.into_iter().collect())
+    }
+}
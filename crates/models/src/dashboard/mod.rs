@@ -1,21 +1,82 @@
 pub mod aggregate;
+pub mod aggregate_lease;
+pub mod aggregate_summary;
+pub mod aggregate_tag;
+pub mod aggregate_transition;
+pub mod api_spec;
+pub mod booking_access;
+pub mod booking_draft;
+pub mod booking_event;
+pub mod booking_snapshot;
 pub mod ci_file;
+pub mod cohort;
+pub mod collaborator_permissions;
+pub mod deploy_checkpoint;
+pub mod email_contact;
+pub mod extension_request;
+pub mod external_attachment;
+pub mod failure_bundle;
+pub mod firewall_policy;
+pub mod host_replacement;
+pub mod idempotency_key;
+pub mod idle_nudge;
 pub mod image;
 pub mod instance;
+pub mod instance_health_check;
+pub mod isolation_attestation;
+pub mod job;
 pub mod network;
 pub mod network_assignment_map;
+pub mod port_mirror;
 pub mod provision_log_event;
+pub mod quota_override;
+pub mod support_request;
+pub mod telemetry_sample;
 pub mod template;
+pub mod template_share;
+pub mod trashed_booking;
 pub mod types;
 
-pub use aggregate::{Aggregate, AggregateConfiguration, BookingMetadata, LifeCycleState};
+pub use aggregate::{
+    Aggregate, AggregateConfiguration, AggregateCursor, AggregateFilter, BookingMetadata,
+    IllegalTransition, LifeCycleState,
+};
+pub use aggregate_lease::{AggregateLease, LeaseOperation};
+pub use aggregate_summary::AggregateSummary;
+pub use aggregate_tag::AggregateTag;
+pub use aggregate_transition::AggregateTransition;
+pub use api_spec::ApiSpec;
+pub use booking_access::BookingAccess;
+pub use booking_draft::BookingDraft;
+pub use booking_event::{BookingEvent, BookingEventKind};
+pub use booking_snapshot::{BookingSnapshot, HostSnapshot, SnapshotNetwork, SnapshotState};
 pub use ci_file::Cifile;
-pub use image::Image;
+pub use cohort::{Cohort, CohortMember};
+pub use collaborator_permissions::{BookingCapability, CollaboratorPermissions};
+pub use deploy_checkpoint::{Checkpoint, DeployCheckpoint};
+pub use email_contact::EmailContact;
+pub use extension_request::{ExtensionRequest, ExtensionStatus};
+pub use external_attachment::{AttachmentState, ExternalAttachment};
+pub use failure_bundle::{BundleEvent, FailureBundle, PortState};
+pub use firewall_policy::{FirewallPolicy, FirewallProtocol, FirewallRule, PortRange};
+pub use host_replacement::{HostReplacement, ReplacementState};
+pub use idempotency_key::IdempotencyKey;
+pub use idle_nudge::{IdleAnswer, IdleNudge};
+pub use image::{Image, ImageChange};
 pub use instance::Instance;
+pub use instance_health_check::InstanceHealthCheck;
+pub use isolation_attestation::{IsolationAttestation, IsolationFinding};
+pub use job::{Job, JobStatus};
 pub use network::{import_net, Network, NetworkBlob};
 pub use network_assignment_map::NetworkAssignmentMap;
-pub use provision_log_event::ProvisionLogEvent;
+pub use port_mirror::PortMirror;
+pub use provision_log_event::{LogCursor, ProvisionLogEvent};
+pub use quota_override::QuotaOverride;
+pub use support_request::SupportRequest;
+pub use telemetry_sample::TelemetrySample;
 pub use template::Template;
+pub use template_share::{SharePermission, TemplateAccess, TemplateShare};
+pub use trashed_booking::TrashedBooking;
 pub use types::*;
 
 // #[derive(Debug, Display, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
@@ -0,0 +1,105 @@
+use chrono::{DateTime, Utc};
+use dal::{web::*, *};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::{
+    dashboard::Aggregate,
+    inventory::{HostPort, Switch},
+};
+
+/// A time limited mirror of all traffic on one booked host port onto
+/// another port of the same booking, so that it can be captured
+///
+/// Rows are kept after the mirror ends as an audit record of who mirrored what
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PortMirror {
+    pub id: FKey<PortMirror>,
+    pub aggregate: FKey<Aggregate>,
+
+    /// The switch both ports are connected to, which does the mirroring
+    pub switch: FKey<Switch>,
+    /// The monitor session number used on the switch
+    pub session: i16,
+
+    pub source: FKey<HostPort>,
+    pub destination: FKey<HostPort>,
+
+    pub requested_by: String,
+    pub started: DateTime<Utc>,
+    pub expires: DateTime<Utc>,
+
+    /// Set once the mirror has been torn down on the switch
+    pub ended: Option<DateTime<Utc>>,
+}
+
+impl DBTable for PortMirror {
+    fn table_name() -> &'static str {
+        "port_mirrors"
+    }
+
+    fn id(&self) -> ID {
+        self.id.into_id()
+    }
+
+    fn from_row(row: tokio_postgres::Row) -> Result<ExistingRow<Self>, anyhow::Error> {
+        Ok(ExistingRow::from_existing(Self {
+            id: row.try_get("id")?,
+            aggregate: row.try_get("aggregate")?,
+            switch: row.try_get("switch")?,
+            session: row.try_get("session")?,
+            source: row.try_get("source")?,
+            destination: row.try_get("destination")?,
+            requested_by: row.try_get("requested_by")?,
+            started: row.try_get("started")?,
+            expires: row.try_get("expires")?,
+            ended: row.try_get("ended")?,
+        }))
+    }
+
+    fn to_rowlike(&self) -> Result<HashMap<&str, Box<dyn ToSqlObject>>, anyhow::Error> {
+        let clone = self.clone();
+        let c: [(&str, Box<dyn ToSqlObject>); _] = [
+            ("id", Box::new(clone.id)),
+            ("aggregate", Box::new(clone.aggregate)),
+            ("switch", Box::new(clone.switch)),
+            ("session", Box::new(clone.session)),
+            ("source", Box::new(clone.source)),
+            ("destination", Box::new(clone.destination)),
+            ("requested_by", Box::new(clone.requested_by)),
+            ("started", Box::new(clone.started)),
+            ("expires", Box::new(clone.expires)),
+            ("ended", Box::new(clone.ended)),
+        ];
+
+        Ok(c.into_iter().collect())
+    }
+}
+
+impl PortMirror {
+    /// Every mirror of the aggregate that has not been torn down yet
+    pub async fn active_for_aggregate(
+        t: &mut EasyTransaction<'_>,
+        aggregate: FKey<Aggregate>,
+    ) -> Result<Vec<ExistingRow<PortMirror>>, anyhow::Error> {
+        let tn = <Self as DBTable>::table_name();
+        let q = format!("SELECT * FROM {tn} WHERE aggregate = $1 AND ended IS NULL;");
+
+        let rows = t.query(&q, &[&aggregate]).await.anyway()?;
+
+        Self::from_rows(rows)
+    }
+
+    /// Every mirror on the switch that has not been torn down yet, for any aggregate
+    pub async fn active_on_switch(
+        t: &mut EasyTransaction<'_>,
+        switch: FKey<Switch>,
+    ) -> Result<Vec<ExistingRow<PortMirror>>, anyhow::Error> {
+        let tn = <Self as DBTable>::table_name();
+        let q = format!("SELECT * FROM {tn} WHERE switch = $1 AND ended IS NULL;");
+
+        let rows = t.query(&q, &[&switch]).await.anyway()?;
+
+        Self::from_rows(rows)
+    }
+}
@@ -1,10 +1,14 @@
 use anyhow::Result;
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, Duration, Utc};
 use dal::{web::*, *};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use tokio_postgres::types::ToSql;
 
-use crate::dashboard::{Instance, ProvEvent, StatusSentiment};
+use crate::{
+    dashboard::{Instance, ProvEvent, StatusSentiment},
+    inventory::Flavor,
+};
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct ProvisionLogEvent {
@@ -54,6 +58,13 @@ impl DBTable for ProvisionLogEvent {
     }
 }
 
+/// The last event of a page of a log, which the next page starts after
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct LogCursor {
+    pub time: DateTime<Utc>,
+    pub id: FKey<ProvisionLogEvent>,
+}
+
 impl ProvisionLogEvent {
     pub async fn all_for_instance(
         t: &mut EasyTransaction<'_>,
@@ -68,4 +79,134 @@ impl ProvisionLogEvent {
             .anyway()
             .flatten()
     }
+
+    /// How many instances finished provisioning since `since`, and how many failed to without
+    /// having finished since
+    pub async fn outcomes_since(
+        t: &mut EasyTransaction<'_>,
+        since: DateTime<Utc>,
+    ) -> Result<(i64, i64), anyhow::Error> {
+        let tn = <Self as DBTable>::table_name();
+        let q = format!(
+            "SELECT COUNT(*) FILTER (WHERE succeeded) AS succeeded, \
+             COUNT(*) FILTER (WHERE failed AND NOT succeeded) AS failed \
+             FROM (SELECT COALESCE(bool_or(sentiment = $2), false) AS succeeded, \
+             COALESCE(bool_or(sentiment = $3), false) AS failed \
+             FROM {tn} WHERE time >= $1 GROUP BY instance) AS outcomes;"
+        );
+
+        let row = t
+            .query_one(
+                &q,
+                &[
+                    &since,
+                    &SqlAsJson::of(StatusSentiment::Succeeded),
+                    &SqlAsJson::of(StatusSentiment::Failed),
+                ],
+            )
+            .await
+            .anyway()?;
+
+        Ok((row.try_get("succeeded")?, row.try_get("failed")?))
+    }
+
+    /// How long the `limit` instances of `flavor` that finished provisioning most recently
+    /// took to, from when they were first logged to when they first succeeded
+    pub async fn provisioning_times(
+        t: &mut EasyTransaction<'_>,
+        flavor: FKey<Flavor>,
+        limit: i64,
+    ) -> Result<Vec<Duration>, anyhow::Error> {
+        let tn = <Self as DBTable>::table_name();
+        let instances = <Instance as DBTable>::table_name();
+        let q = format!(
+            "SELECT EXTRACT(EPOCH FROM MIN(l.time) FILTER (WHERE l.sentiment = $2) - MIN(l.time))::float8 \
+             AS seconds \
+             FROM {tn} l JOIN {instances} i ON i.id = l.instance \
+             WHERE i.config->>'flavor' = $1 \
+             GROUP BY l.instance HAVING bool_or(l.sentiment = $2) \
+             ORDER BY MIN(l.time) FILTER (WHERE l.sentiment = $2) DESC LIMIT $3;"
+        );
+
+        let rows = t
+            .query(
+                &q,
+                &[
+                    &flavor.into_id().to_string(),
+                    &SqlAsJson::of(StatusSentiment::Succeeded),
+                    &limit,
+                ],
+            )
+            .await
+            .anyway()?;
+
+        rows.into_iter()
+            .map(|row| {
+                let seconds: f64 = row.try_get("seconds")?;
+                Ok(Duration::milliseconds((seconds * 1000.0) as i64))
+            })
+            .collect()
+    }
+
+    pub async fn latest_for_instance(
+        t: &mut EasyTransaction<'_>,
+        instance: FKey<Instance>,
+    ) -> Result<Option<ExistingRow<ProvisionLogEvent>>, anyhow::Error> {
+        let tn = <Self as DBTable>::table_name();
+        let q = format!("SELECT * FROM {tn} WHERE instance = $1 ORDER BY time DESC LIMIT 1;");
+
+        let row = t.query_opt(&q, &[&instance]).await.anyway()?;
+
+        row.map(Self::from_row).transpose()
+    }
+
+    /// Up to `limit` events of the log of `instance`, oldest first, starting after `after`
+    /// or at `since` for the first page, and only those of `sentiments` if any are given
+    pub async fn page(
+        t: &mut EasyTransaction<'_>,
+        instance: FKey<Instance>,
+        since: Option<DateTime<Utc>>,
+        sentiments: &[StatusSentiment],
+        after: Option<LogCursor>,
+        limit: i64,
+    ) -> Result<Vec<ExistingRow<ProvisionLogEvent>>, anyhow::Error> {
+        let tn = <Self as DBTable>::table_name();
+
+        let mut params: Vec<Box<dyn ToSqlObject>> = Vec::new();
+        let mut bind = |value: Box<dyn ToSqlObject>| {
+            params.push(value);
+            format!("${}", params.len())
+        };
+
+        let mut conditions = vec![format!("instance = {}", bind(Box::new(instance)))];
+        if let Some(since) = since {
+            conditions.push(format!("time >= {}", bind(Box::new(since))));
+        }
+        if !sentiments.is_empty() {
+            let any_of: Vec<String> = sentiments
+                .iter()
+                .map(|s| bind(Box::new(SqlAsJson::of(*s))))
+                .collect();
+            conditions.push(format!("sentiment IN ({})", any_of.join(", ")));
+        }
+        if let Some(after) = after {
+            conditions.push(format!(
+                "(time, id) > ({}, {})",
+                bind(Box::new(after.time)),
+                bind(Box::new(after.id))
+            ));
+        }
+        let limit = bind(Box::new(limit));
+
+        let q = format!(
+            "SELECT * FROM {tn} WHERE {} ORDER BY time ASC, id ASC LIMIT {limit};",
+            conditions.join(" AND ")
+        );
+
+        let params: Vec<&(dyn ToSql + Sync)> =
+            params.iter().map(|p| &**p as &(dyn ToSql + Sync)).collect();
+        let rows = t.query(&q, &params).await.anyway()?;
+
+        Self::from_rows(rows)
+    }
 }
@@ -0,0 +1,109 @@
+use chrono::{DateTime, Utc};
+use dal::{web::*, *};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Quotas of a project, configured per lab, that an admin lifted for one user or one
+/// dashboard project until `expires`
+///
+/// Limits left as None stay as configured.
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+pub struct QuotaOverride {
+    pub id: FKey<QuotaOverride>,
+    /// The project of the lab, as bookings give it for their origin
+    pub origin: String,
+    /// Set for overrides of the quotas of a user, who can then book up to `max_hosts`
+    pub username: Option<String>,
+    /// Set for overrides of the quotas of a dashboard project, whose bookings can then
+    /// hold up to `max_hosts` between them
+    pub project: Option<String>,
+    pub max_hosts: Option<i32>,
+    pub max_length_days: Option<i64>,
+    pub granted_by: String,
+    pub reason: String,
+    #[schemars(with = "String")]
+    pub created: DateTime<Utc>,
+    #[schemars(with = "String")]
+    pub expires: DateTime<Utc>,
+}
+
+impl DBTable for QuotaOverride {
+    fn table_name() -> &'static str {
+        "quota_overrides"
+    }
+
+    fn id(&self) -> ID {
+        self.id.into_id()
+    }
+
+    fn from_row(row: tokio_postgres::Row) -> Result<ExistingRow<Self>, anyhow::Error> {
+        Ok(ExistingRow::from_existing(Self {
+            id: row.try_get("id")?,
+            origin: row.try_get("origin")?,
+            username: row.try_get("username")?,
+            project: row.try_get("project")?,
+            max_hosts: row.try_get("max_hosts")?,
+            max_length_days: row.try_get("max_length_days")?,
+            granted_by: row.try_get("granted_by")?,
+            reason: row.try_get("reason")?,
+            created: row.try_get("created")?,
+            expires: row.try_get("expires")?,
+        }))
+    }
+
+    fn to_rowlike(&self) -> Result<HashMap<&str, Box<dyn ToSqlObject>>, anyhow::Error> {
+        let clone = self.clone();
+        let c: [(&str, Box<dyn ToSqlObject>); _] = [
+            ("id", Box::new(clone.id)),
+            ("origin", Box::new(clone.origin)),
+            ("username", Box::new(clone.username)),
+            ("project", Box::new(clone.project)),
+            ("max_hosts", Box::new(clone.max_hosts)),
+            ("max_length_days", Box::new(clone.max_length_days)),
+            ("granted_by", Box::new(clone.granted_by)),
+            ("reason", Box::new(clone.reason)),
+            ("created", Box::new(clone.created)),
+            ("expires", Box::new(clone.expires)),
+        ];
+
+        Ok(c.into_iter().collect())
+    }
+}
+
+impl QuotaOverride {
+    /// Every override that hasn't expired by `now`, soonest to expire first
+    pub async fn active(
+        t: &mut EasyTransaction<'_>,
+        now: DateTime<Utc>,
+    ) -> Result<Vec<ExistingRow<QuotaOverride>>, anyhow::Error> {
+        let tn = Self::table_name();
+        let q = format!("SELECT * FROM {tn} WHERE expires > $1 ORDER BY expires;");
+
+        let rows = t.query(&q, &[&now]).await.anyway()?;
+
+        Self::from_rows(rows)
+    }
+
+    /// The overrides of `origin` that apply at `now` to `username` or to `project`
+    pub async fn applying_to(
+        t: &mut EasyTransaction<'_>,
+        origin: &str,
+        username: Option<&str>,
+        project: Option<&str>,
+        now: DateTime<Utc>,
+    ) -> Result<Vec<ExistingRow<QuotaOverride>>, anyhow::Error> {
+        let tn = Self::table_name();
+        let q = format!(
+            "SELECT * FROM {tn} WHERE origin = $1 AND expires > $2
+                AND (username = $3 OR project = $4);"
+        );
+
+        let rows = t
+            .query(&q, &[&origin, &now, &username, &project])
+            .await
+            .anyway()?;
+
+        Self::from_rows(rows)
+    }
+}
@@ -0,0 +1,80 @@
+use chrono::{DateTime, Utc};
+use dal::{web::*, *};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::dashboard::Aggregate;
+
+/// A problem a user of a booking asked the lab for help with, along with what the booking
+/// looked like when they asked
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SupportRequest {
+    pub id: FKey<SupportRequest>,
+    pub aggregate: FKey<Aggregate>,
+    pub requested_by: String,
+    pub summary: String,
+    /// The problem, in the words of whoever asked
+    pub description: String,
+    /// The statuses, recent logs and health of the hosts of the booking when it was asked
+    pub context: serde_json::Value,
+    /// The ticket it was filed as, None if it was only mailed to the admins
+    pub ticket: Option<String>,
+    pub ticket_url: Option<String>,
+    pub created: DateTime<Utc>,
+}
+
+impl DBTable for SupportRequest {
+    fn table_name() -> &'static str {
+        "support_requests"
+    }
+
+    fn id(&self) -> ID {
+        self.id.into_id()
+    }
+
+    fn from_row(row: tokio_postgres::Row) -> Result<ExistingRow<Self>, anyhow::Error> {
+        Ok(ExistingRow::from_existing(Self {
+            id: row.try_get("id")?,
+            aggregate: row.try_get("aggregate")?,
+            requested_by: row.try_get("requested_by")?,
+            summary: row.try_get("summary")?,
+            description: row.try_get("description")?,
+            context: row.try_get("context")?,
+            ticket: row.try_get("ticket")?,
+            ticket_url: row.try_get("ticket_url")?,
+            created: row.try_get("created")?,
+        }))
+    }
+
+    fn to_rowlike(&self) -> Result<HashMap<&str, Box<dyn ToSqlObject>>, anyhow::Error> {
+        let clone = self.clone();
+        let c: [(&str, Box<dyn ToSqlObject>); _] = [
+            ("id", Box::new(clone.id)),
+            ("aggregate", Box::new(clone.aggregate)),
+            ("requested_by", Box::new(clone.requested_by)),
+            ("summary", Box::new(clone.summary)),
+            ("description", Box::new(clone.description)),
+            ("context", Box::new(clone.context)),
+            ("ticket", Box::new(clone.ticket)),
+            ("ticket_url", Box::new(clone.ticket_url)),
+            ("created", Box::new(clone.created)),
+        ];
+
+        Ok(c.into_iter().collect())
+    }
+}
+
+impl SupportRequest {
+    /// Every support request made for `aggregate`, most recent first
+    pub async fn for_aggregate(
+        t: &mut EasyTransaction<'_>,
+        aggregate: FKey<Aggregate>,
+    ) -> Result<Vec<ExistingRow<SupportRequest>>, anyhow::Error> {
+        let tn = Self::table_name();
+        let q = format!("SELECT * FROM {tn} WHERE aggregate = $1 ORDER BY created DESC;");
+
+        let rows = t.query(&q, &[&aggregate]).await.anyway()?;
+
+        Self::from_rows(rows)
+    }
+}
@@ -0,0 +1,87 @@
+use chrono::{DateTime, Utc};
+use dal::{web::*, *};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::dashboard::{Aggregate, Instance};
+
+/// How busy a booked host was at one point, as reported by the telemetry agent
+/// installed on it for bookings that asked for one
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TelemetrySample {
+    pub id: FKey<TelemetrySample>,
+    pub instance: FKey<Instance>,
+    pub aggregate: FKey<Aggregate>,
+    pub time: DateTime<Utc>,
+
+    pub cpu_percent: f64,
+    pub memory_percent: f64,
+    /// How full the root filesystem is
+    pub disk_percent: f64,
+    /// Summed over every interface but loopback
+    pub network_rx_bytes_per_sec: f64,
+    pub network_tx_bytes_per_sec: f64,
+}
+
+impl TelemetrySample {
+    /// Every sample reported for the instances of `aggregate` since `since`, oldest first
+    pub async fn for_aggregate(
+        t: &mut EasyTransaction<'_>,
+        aggregate: FKey<Aggregate>,
+        since: DateTime<Utc>,
+    ) -> Result<Vec<ExistingRow<TelemetrySample>>, anyhow::Error> {
+        let tn = <Self as DBTable>::table_name();
+        let q = format!("SELECT * FROM {tn} WHERE aggregate = $1 AND time >= $2 ORDER BY time;");
+
+        let rows = t.query(&q, &[&aggregate, &since]).await.anyway()?;
+
+        Self::from_rows(rows)
+    }
+}
+
+impl DBTable for TelemetrySample {
+    fn table_name() -> &'static str {
+        "telemetry_samples"
+    }
+
+    fn id(&self) -> ID {
+        self.id.into_id()
+    }
+
+    fn from_row(row: tokio_postgres::Row) -> Result<ExistingRow<Self>, anyhow::Error> {
+        Ok(ExistingRow::from_existing(Self {
+            id: row.try_get("id")?,
+            instance: row.try_get("instance")?,
+            aggregate: row.try_get("aggregate")?,
+            time: row.try_get("time")?,
+            cpu_percent: row.try_get("cpu_percent")?,
+            memory_percent: row.try_get("memory_percent")?,
+            disk_percent: row.try_get("disk_percent")?,
+            network_rx_bytes_per_sec: row.try_get("network_rx_bytes_per_sec")?,
+            network_tx_bytes_per_sec: row.try_get("network_tx_bytes_per_sec")?,
+        }))
+    }
+
+    fn to_rowlike(&self) -> Result<HashMap<&str, Box<dyn ToSqlObject>>, anyhow::Error> {
+        let clone = self.clone();
+        let c: [(&str, Box<dyn ToSqlObject>); _] = [
+            ("id", Box::new(clone.id)),
+            ("instance", Box::new(clone.instance)),
+            ("aggregate", Box::new(clone.aggregate)),
+            ("time", Box::new(clone.time)),
+            ("cpu_percent", Box::new(clone.cpu_percent)),
+            ("memory_percent", Box::new(clone.memory_percent)),
+            ("disk_percent", Box::new(clone.disk_percent)),
+            (
+                "network_rx_bytes_per_sec",
+                Box::new(clone.network_rx_bytes_per_sec),
+            ),
+            (
+                "network_tx_bytes_per_sec",
+                Box::new(clone.network_tx_bytes_per_sec),
+            ),
+        ];
+
+        Ok(c.into_iter().collect())
+    }
+}
@@ -8,7 +8,9 @@ use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
 use crate::{
-    dashboard::{import_net, HostConfig, ImportHostConfig, Network, NetworkBlob},
+    dashboard::{
+        import_net, HostConfig, ImportHostConfig, Network, NetworkBlob, TemplateParameter,
+    },
     inventory::Lab,
 };
 
@@ -23,6 +25,8 @@ pub struct Template {
     pub networks: Vec<FKey<Network>>, // User defined network
     pub hosts: Vec<HostConfig>,
     pub lab: FKey<Lab>,
+    /// What each booking of the template picks for itself
+    pub parameters: Vec<TemplateParameter>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
@@ -186,6 +190,7 @@ impl DBTable for Template {
             networks: row.try_get("networks")?,
             hosts: serde_json::from_value(row.try_get("hosts")?)?,
             lab: row.try_get("lab")?,
+            parameters: serde_json::from_value(row.try_get("parameters")?)?,
         }))
     }
 
@@ -201,6 +206,10 @@ impl DBTable for Template {
             ("networks", Box::new(clone.networks)),
             ("hosts", Box::new(serde_json::to_value(clone.hosts)?)),
             ("lab", Box::new(clone.lab)),
+            (
+                "parameters",
+                Box::new(serde_json::to_value(clone.parameters)?),
+            ),
         ];
 
         Ok(c.into_iter().collect())
@@ -331,6 +340,8 @@ impl ImportTemplate {
             networks: nets,
             hosts,
             lab: lab.id,
+            // images are only known by name in import files, so imported templates aren't parameterized
+            parameters: Vec::new(),
         }
     }
 
@@ -0,0 +1,154 @@
+use chrono::{DateTime, Utc};
+use dal::{web::*, *};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::dashboard::Template;
+
+/// What a user a template was shared with may do with it
+#[derive(
+    Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, JsonSchema,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum SharePermission {
+    /// See the template in their lists
+    View,
+    /// Also book from it
+    Book,
+}
+
+/// What a user may do with a template, all things considered
+///
+/// Each level allows everything the ones before it do.
+#[derive(
+    Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, JsonSchema,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum TemplateAccess {
+    View,
+    Book,
+    /// Also share it with others and delete it
+    Owner,
+}
+
+impl From<SharePermission> for TemplateAccess {
+    fn from(p: SharePermission) -> Self {
+        match p {
+            SharePermission::View => Self::View,
+            SharePermission::Book => Self::Book,
+        }
+    }
+}
+
+impl TemplateAccess {
+    /// The access `username` has to `template`, given the share of it with them if there is one
+    ///
+    /// Public templates can be booked by anyone. None if the user can't see the template at all.
+    pub fn of(template: &Template, username: &str, share: Option<&TemplateShare>) -> Option<Self> {
+        if template.owner.as_deref() == Some(username) {
+            return Some(Self::Owner);
+        }
+
+        let shared = share
+            .filter(|s| s.template == template.id && s.username == username)
+            .map(|s| s.permission.into());
+        let public = template.public.then_some(Self::Book);
+
+        shared.max(public)
+    }
+
+    pub fn can_book(self) -> bool {
+        self >= Self::Book
+    }
+}
+
+/// A template that was explicitly shared with one user, beyond who can see it anyway
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TemplateShare {
+    pub id: FKey<TemplateShare>,
+    pub template: FKey<Template>,
+    pub username: String,
+    pub permission: SharePermission,
+
+    pub shared_by: String,
+    pub shared: DateTime<Utc>,
+}
+
+impl DBTable for TemplateShare {
+    fn table_name() -> &'static str {
+        "template_shares"
+    }
+
+    fn id(&self) -> ID {
+        self.id.into_id()
+    }
+
+    fn from_row(row: tokio_postgres::Row) -> Result<ExistingRow<Self>, anyhow::Error> {
+        Ok(ExistingRow::from_existing(Self {
+            id: row.try_get("id")?,
+            template: row.try_get("template")?,
+            username: row.try_get("username")?,
+            permission: serde_json::from_value(row.try_get("permission")?)?,
+            shared_by: row.try_get("shared_by")?,
+            shared: row.try_get("shared")?,
+        }))
+    }
+
+    fn to_rowlike(&self) -> Result<HashMap<&str, Box<dyn ToSqlObject>>, anyhow::Error> {
+        let clone = self.clone();
+        let c: [(&str, Box<dyn ToSqlObject>); _] = [
+            ("id", Box::new(clone.id)),
+            ("template", Box::new(clone.template)),
+            ("username", Box::new(clone.username)),
+            (
+                "permission",
+                Box::new(serde_json::to_value(clone.permission)?),
+            ),
+            ("shared_by", Box::new(clone.shared_by)),
+            ("shared", Box::new(clone.shared)),
+        ];
+
+        Ok(c.into_iter().collect())
+    }
+}
+
+impl TemplateShare {
+    pub async fn for_template(
+        t: &mut EasyTransaction<'_>,
+        template: FKey<Template>,
+    ) -> Result<Vec<ExistingRow<TemplateShare>>, anyhow::Error> {
+        let tn = <Self as DBTable>::table_name();
+        let q = format!("SELECT * FROM {tn} WHERE template = $1 ORDER BY username;");
+
+        let rows = t.query(&q, &[&template]).await.anyway()?;
+
+        Self::from_rows(rows)
+    }
+
+    /// Every template shared with `username`
+    pub async fn for_user(
+        t: &mut EasyTransaction<'_>,
+        username: &str,
+    ) -> Result<Vec<ExistingRow<TemplateShare>>, anyhow::Error> {
+        let tn = <Self as DBTable>::table_name();
+        let q = format!("SELECT * FROM {tn} WHERE username = $1;");
+
+        let rows = t.query(&q, &[&username]).await.anyway()?;
+
+        Self::from_rows(rows)
+    }
+
+    pub async fn find(
+        t: &mut EasyTransaction<'_>,
+        template: FKey<Template>,
+        username: &str,
+    ) -> Result<Option<ExistingRow<TemplateShare>>, anyhow::Error> {
+        let tn = <Self as DBTable>::table_name();
+        let q = format!("SELECT * FROM {tn} WHERE template = $1 AND username = $2;");
+
+        let rows = t.query(&q, &[&template, &username]).await.anyway()?;
+
+        Ok(Self::from_rows(rows)?.into_iter().next())
+    }
+}
@@ -0,0 +1,130 @@
+use chrono::{DateTime, Duration, Utc};
+use dal::{web::*, *};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::dashboard::{Aggregate, BookingAccess, Instance};
+
+/// A booking that ended recently, whose instances and logs are kept until `purge_after`
+/// in case anything about it has to be looked up again
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TrashedBooking {
+    pub id: FKey<TrashedBooking>,
+    pub aggregate: FKey<Aggregate>,
+    pub ended: DateTime<Utc>,
+    pub purge_after: DateTime<Utc>,
+}
+
+impl DBTable for TrashedBooking {
+    fn table_name() -> &'static str {
+        "booking_trash"
+    }
+
+    fn id(&self) -> ID {
+        self.id.into_id()
+    }
+
+    fn from_row(row: tokio_postgres::Row) -> Result<ExistingRow<Self>, anyhow::Error> {
+        Ok(ExistingRow::from_existing(Self {
+            id: row.try_get("id")?,
+            aggregate: row.try_get("aggregate")?,
+            ended: row.try_get("ended")?,
+            purge_after: row.try_get("purge_after")?,
+        }))
+    }
+
+    fn to_rowlike(&self) -> Result<HashMap<&str, Box<dyn ToSqlObject>>, anyhow::Error> {
+        let c: [(&str, Box<dyn ToSqlObject>); _] = [
+            ("id", Box::new(self.id)),
+            ("aggregate", Box::new(self.aggregate)),
+            ("ended", Box::new(self.ended)),
+            ("purge_after", Box::new(self.purge_after)),
+        ];
+
+        Ok(c.into_iter().collect())
+    }
+}
+
+impl TrashedBooking {
+    /// Puts `aggregate`, which just ended, in the trash for `retention`
+    pub async fn record(
+        t: &mut EasyTransaction<'_>,
+        aggregate: FKey<Aggregate>,
+        retention: Duration,
+    ) -> Result<(), anyhow::Error> {
+        let tn = Self::table_name();
+        let ended = Utc::now();
+        let q = format!(
+            "INSERT INTO {tn} (id, aggregate, ended, purge_after) VALUES ($1, $2, $3, $4)
+                ON CONFLICT (aggregate) DO UPDATE SET ended = $3, purge_after = $4;"
+        );
+
+        t.execute(
+            &q,
+            &[
+                &FKey::<TrashedBooking>::new_id_dangling(),
+                &aggregate,
+                &ended,
+                &(ended + retention),
+            ],
+        )
+        .await
+        .anyway()?;
+
+        Ok(())
+    }
+
+    /// Every booking in the trash that isn't due to be purged by `now`, most recently
+    /// ended first
+    pub async fn recent(
+        t: &mut EasyTransaction<'_>,
+        now: DateTime<Utc>,
+    ) -> Result<Vec<ExistingRow<TrashedBooking>>, anyhow::Error> {
+        let tn = Self::table_name();
+        let q = format!("SELECT * FROM {tn} WHERE purge_after > $1 ORDER BY ended DESC;");
+
+        let rows = t.query(&q, &[&now]).await.anyway()?;
+
+        Self::from_rows(rows)
+    }
+
+    /// Every booking in the trash that is due to be purged by `now`
+    pub async fn due(
+        t: &mut EasyTransaction<'_>,
+        now: DateTime<Utc>,
+    ) -> Result<Vec<ExistingRow<TrashedBooking>>, anyhow::Error> {
+        let tn = Self::table_name();
+        let q = format!("SELECT * FROM {tn} WHERE purge_after <= $1;");
+
+        let rows = t.query(&q, &[&now]).await.anyway()?;
+
+        Self::from_rows(rows)
+    }
+
+    /// Deletes the instances of the booking, along with their logs and everything else
+    /// recorded about them, and takes the booking out of the trash
+    ///
+    /// The aggregate itself is only marked deleted, since the allocation history of the
+    /// lab refers to it.
+    pub async fn purge(self, t: &mut EasyTransaction<'_>) -> Result<(), anyhow::Error> {
+        let instance_tn = <Instance as DBTable>::table_name();
+        let q = format!("DELETE FROM {instance_tn} WHERE aggregate = $1;");
+        t.execute(&q, &[&self.aggregate]).await.anyway()?;
+
+        let access_tn = <BookingAccess as DBTable>::table_name();
+        let q = format!("DELETE FROM {access_tn} WHERE aggregate = $1;");
+        t.execute(&q, &[&self.aggregate]).await.anyway()?;
+
+        let mut agg = self.aggregate.get(t).await?;
+        agg.deleted = true;
+        agg.configuration.ipmi_username = String::new();
+        agg.configuration.ipmi_password = String::new();
+        agg.update(t).await?;
+
+        let tn = Self::table_name();
+        let q = format!("DELETE FROM {tn} WHERE id = $1;");
+        t.execute(&q, &[&self.id]).await.anyway()?;
+
+        Ok(())
+    }
+}
@@ -4,7 +4,7 @@ use common::prelude::*;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-use crate::dashboard::types::{BondGroupConfig, ImportBondGroupConfig};
+use crate::dashboard::types::{BondGroupConfig, HostConstraints, ImportBondGroupConfig};
 use crate::dashboard::{ci_file::Cifile, image::Image};
 use crate::inventory::Flavor;
 
@@ -17,6 +17,15 @@ pub struct HostConfig {
     pub cifile: Vec<FKey<Cifile>>, // A vector of C-I Files. order is determined by order of the Vec
 
     pub connections: Vec<BondGroupConfig>,
+
+    /// If given, any flavor whose hosts meet these and can be installed with `image` can
+    /// fill the role instead of `flavor`, the cheapest one with a free host being picked
+    #[serde(default)]
+    pub constraints: Option<HostConstraints>,
+
+    /// Extra arguments the installer is booted with, after the ones LibLaaS passes itself
+    #[serde(default)]
+    pub kernel_args: Vec<(String, String)>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
@@ -26,6 +35,10 @@ pub struct ImportHostConfig {
     pub flavor: String,
     pub cifile: Vec<Cifile>,
     pub connections: Vec<ImportBondGroupConfig>,
+    #[serde(default)]
+    pub constraints: Option<HostConstraints>,
+    #[serde(default)]
+    pub kernel_args: Vec<(String, String)>,
 }
 
 impl ImportHostConfig {
@@ -59,6 +72,8 @@ impl ImportHostConfig {
             image,
             cifile,
             connections,
+            constraints: clone.constraints,
+            kernel_args: clone.kernel_args,
         }
     }
 
@@ -104,6 +119,8 @@ impl ImportHostConfig {
             flavor,
             cifile,
             connections,
+            constraints: clone.constraints,
+            kernel_args: clone.kernel_args,
         }
     }
 }
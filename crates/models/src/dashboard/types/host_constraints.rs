@@ -0,0 +1,82 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::inventory::{AcceleratorKind, Arch, ComposableKind, Flavor};
+
+/// What a host has to have when any host that has it will do, rather than one of a
+/// particular flavor
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, JsonSchema)]
+#[serde(default)]
+pub struct HostConstraints {
+    pub min_ram_gb: Option<u64>,
+    #[schemars(with = "Option<String>")]
+    pub arch: Option<Arch>,
+    pub min_nics: Option<usize>,
+    /// Whether the host must have a GPU, or must not
+    pub gpu: Option<bool>,
+}
+
+impl HostConstraints {
+    /// Each constraint, in words
+    pub fn describe(&self) -> Vec<String> {
+        let mut described = Vec::new();
+
+        if let Some(gb) = self.min_ram_gb {
+            described.push(format!("at least {gb} GB of RAM"));
+        }
+        if let Some(arch) = self.arch {
+            described.push(format!("a {arch} CPU"));
+        }
+        if let Some(nics) = self.min_nics {
+            described.push(format!("at least {nics} NICs"));
+        }
+        match self.gpu {
+            Some(true) => described.push("a GPU".to_owned()),
+            Some(false) => described.push("no GPU".to_owned()),
+            None => {}
+        }
+
+        described
+    }
+
+    /// The constraints, in words, that hosts of `flavor` with `nics` NICs don't meet
+    pub fn unmet_by(&self, flavor: &Flavor, nics: usize) -> Vec<String> {
+        let mut unmet = Vec::new();
+
+        if let Some(gb) = self.min_ram_gb {
+            let has = flavor.ram.bytes().unwrap_or_default();
+            if has < gb * 1_000_000_000 {
+                unmet.push(format!("at least {gb} GB of RAM"));
+            }
+        }
+        if let Some(arch) = self.arch {
+            if flavor.arch != arch {
+                unmet.push(format!("a {arch} CPU"));
+            }
+        }
+        if let Some(min) = self.min_nics {
+            if nics < min {
+                unmet.push(format!("at least {min} NICs"));
+            }
+        }
+        if let Some(gpu) = self.gpu {
+            let has = flavor
+                .capabilities
+                .accelerators
+                .iter()
+                .any(|a| a.kind == AcceleratorKind::Gpu && a.count > 0)
+                || flavor
+                    .capabilities
+                    .composed
+                    .iter()
+                    .any(|c| c.kind == ComposableKind::Gpu && c.count > 0);
+            match (gpu, has) {
+                (true, false) => unmet.push("a GPU".to_owned()),
+                (false, true) => unmet.push("no GPU".to_owned()),
+                _ => {}
+            }
+        }
+
+        unmet
+    }
+}
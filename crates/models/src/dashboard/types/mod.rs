@@ -1,11 +1,15 @@
 mod bond_group_config;
 mod host_config;
+mod host_constraints;
 mod provision_data;
 mod status_sentiment;
+mod template_parameter;
 mod vlan_connection_config;
 
 pub use bond_group_config::{BondGroupConfig, ImportBondGroupConfig};
 pub use host_config::{HostConfig, ImportHostConfig};
+pub use host_constraints::HostConstraints;
 pub use provision_data::{InstanceProvData, NetworkProvData, ProvEvent};
 pub use status_sentiment::StatusSentiment;
+pub use template_parameter::{ParameterKind, ParameterValue, TemplateParameter};
 pub use vlan_connection_config::{ImportVlanConnectionConfig, VlanConnectionConfig};
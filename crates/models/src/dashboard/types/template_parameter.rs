@@ -0,0 +1,49 @@
+use dal::*;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::dashboard::Image;
+
+/// Something about a template that each booking picks, instead of the template fixing it
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, JsonSchema)]
+pub struct TemplateParameter {
+    /// What bookings call the parameter when giving it a value
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    pub kind: ParameterKind,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, JsonSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ParameterKind {
+    /// How many copies of the host named `hostname` the booking gets
+    HostCount {
+        hostname: String,
+        min: u32,
+        max: u32,
+        default: u32,
+    },
+    /// The image the hosts named in `hostnames` are provisioned with, or every host if none are named
+    Image {
+        #[serde(default)]
+        hostnames: Vec<String>,
+        choices: Vec<FKey<Image>>,
+        default: FKey<Image>,
+    },
+    /// How many copies of the network named `network` the booking gets
+    VlanCount {
+        network: String,
+        min: u32,
+        max: u32,
+        default: u32,
+    },
+}
+
+/// What a booking gives for a parameter
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, JsonSchema)]
+#[serde(untagged)]
+pub enum ParameterValue {
+    Count(u32),
+    Image(FKey<Image>),
+}
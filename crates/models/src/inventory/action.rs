@@ -75,4 +75,19 @@ impl Action {
 
         action.insert(t).await
     }
+
+    /// The tascii task this action tracks
+    pub fn in_tascii(&self) -> ID {
+        self.in_tascii
+    }
+
+    /// Marks every action tracking the tascii task `in_tascii` as complete
+    pub async fn complete(t: &mut EasyTransaction<'_>, in_tascii: ID) -> Result<(), anyhow::Error> {
+        let tn = <Self as DBTable>::table_name();
+        let q = format!("UPDATE {tn} SET is_complete = $1 WHERE in_tascii = $2;");
+
+        t.execute(&q, &[&true, &in_tascii]).await.anyway()?;
+
+        Ok(())
+    }
 }
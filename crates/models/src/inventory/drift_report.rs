@@ -0,0 +1,96 @@
+use common::prelude::chrono::{DateTime, Utc};
+use dal::{web::AnyWay, *};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// What the reconciler found when comparing the database against the lab hardware
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DriftReport {
+    pub id: FKey<DriftReport>,
+    pub time: DateTime<Utc>,
+    pub findings: Vec<Drift>,
+}
+
+/// One place where the database and the lab disagree
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
+pub struct Drift {
+    /// Server name of the host the drift is on
+    pub host: String,
+    pub kind: DriftKind,
+    pub details: String,
+    /// Whether the reconciler put things back the way the database expects
+    pub remediated: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum DriftKind {
+    /// The host is still allocated to a booking that has ended, or that doesn't use it
+    StaleAllocation,
+    /// A booking is using the host without holding an allocation for it
+    MissingAllocation,
+    /// The BMC reports a power state the host shouldn't be in
+    UnexpectedPower,
+    /// A switch port carries different vlans than the host's state calls for
+    VlanMismatch,
+    /// Cobbler has no system for the host, so it can't get a DHCP lease to provision
+    DhcpMissing,
+    /// The MACs cobbler serves leases to don't match the host's ports
+    DhcpMismatch,
+}
+
+impl std::fmt::Display for DriftKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::StaleAllocation => write!(f, "stale allocation"),
+            Self::MissingAllocation => write!(f, "missing allocation"),
+            Self::UnexpectedPower => write!(f, "unexpected power state"),
+            Self::VlanMismatch => write!(f, "vlan mismatch"),
+            Self::DhcpMissing => write!(f, "missing dhcp reservation"),
+            Self::DhcpMismatch => write!(f, "dhcp reservation mismatch"),
+        }
+    }
+}
+
+impl DBTable for DriftReport {
+    fn table_name() -> &'static str {
+        "drift_reports"
+    }
+
+    fn id(&self) -> ID {
+        self.id.into_id()
+    }
+
+    fn from_row(row: tokio_postgres::Row) -> Result<ExistingRow<Self>, anyhow::Error> {
+        Ok(ExistingRow::from_existing(Self {
+            id: row.try_get("id")?,
+            time: row.try_get("time")?,
+            findings: serde_json::from_value(row.try_get("findings")?)?,
+        }))
+    }
+
+    fn to_rowlike(&self) -> Result<HashMap<&str, Box<dyn ToSqlObject>>, anyhow::Error> {
+        let clone = self.clone();
+        let c: [(&str, Box<dyn ToSqlObject>); _] = [
+            ("id", Box::new(clone.id)),
+            ("time", Box::new(clone.time)),
+            ("findings", Box::new(serde_json::to_value(clone.findings)?)),
+        ];
+
+        Ok(c.into_iter().collect())
+    }
+}
+
+impl DriftReport {
+    pub async fn latest(
+        t: &mut EasyTransaction<'_>,
+    ) -> Result<Option<ExistingRow<DriftReport>>, anyhow::Error> {
+        let tn = Self::table_name();
+        let q = format!("SELECT * FROM {tn} ORDER BY time DESC LIMIT 1;");
+
+        let row = t.query_opt(&q, &[]).await.anyway()?;
+
+        row.map(Self::from_row).transpose()
+    }
+}
@@ -0,0 +1,70 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::inventory::DataValue;
+
+/// What the hardware of a flavor has beyond its core counts and sizes
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq, JsonSchema)]
+#[serde(default)]
+pub struct FlavorCapabilities {
+    /// Like `Xeon Gold 6338`
+    pub cpu_model: Option<String>,
+    /// Like `Ice Lake`
+    pub cpu_generation: Option<String>,
+    pub cpu_sockets: Option<u32>,
+    pub disks: Vec<DiskCapability>,
+    pub accelerators: Vec<Accelerator>,
+    /// Devices attached to hosts of the flavor from a composable PCIe or CXL fabric for
+    /// each booking, on top of what is built into the hosts
+    pub composed: Vec<ComposedDevices>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, JsonSchema)]
+pub struct DiskCapability {
+    pub kind: DiskKind,
+    pub size: DataValue,
+    pub count: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum DiskKind {
+    Hdd,
+    Ssd,
+    Nvme,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, JsonSchema)]
+pub struct Accelerator {
+    pub kind: AcceleratorKind,
+    /// Like `A100 40GB`
+    pub model: String,
+    pub count: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum AcceleratorKind {
+    Gpu,
+    Fpga,
+    SmartNic,
+    Other,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, JsonSchema)]
+pub struct ComposedDevices {
+    pub kind: ComposableKind,
+    /// Like `A100 40GB`, any model of the kind will do if not given
+    pub model: Option<String>,
+    pub count: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ComposableKind {
+    Gpu,
+    Nvme,
+    Fpga,
+    /// Pooled CXL memory
+    Memory,
+}
@@ -5,9 +5,14 @@ use std::{collections::HashMap, fs::File, io::Write, path::PathBuf};
 
 use crate::inventory::{Arch, DataValue};
 
+mod capabilities;
 mod extra_info;
 mod interface;
 
+pub use capabilities::{
+    Accelerator, AcceleratorKind, ComposableKind, ComposedDevices, DiskCapability, DiskKind,
+    FlavorCapabilities,
+};
 pub use extra_info::ExtraFlavorInfo;
 pub use interface::{CardType, InterfaceFlavor};
 
@@ -25,6 +30,7 @@ pub struct Flavor {
     pub swap_size: DataValue,
     pub brand: String,
     pub model: String,
+    pub capabilities: FlavorCapabilities,
 }
 
 impl DBTable for Flavor {
@@ -52,6 +58,10 @@ impl DBTable for Flavor {
             ("swap_size", self.swap_size.to_sqlval()?),
             ("brand", Box::new(self.brand.clone())),
             ("model", Box::new(self.model.clone())),
+            (
+                "capabilities",
+                Box::new(serde_json::to_value(&self.capabilities)?),
+            ),
         ];
         Ok(c.into_iter().collect())
     }
@@ -62,13 +72,14 @@ impl DBTable for Flavor {
             arch: Arch::from_str(row.try_get("arch")?)?,
             name: row.try_get("name")?,
             public: row.try_get("public")?,
-            cpu_count: serde_json::from_value::<i64>(row.try_get("cpu_count")?)?.min(0) as usize,
+            cpu_count: serde_json::from_value::<i64>(row.try_get("cpu_count")?)?.max(0) as usize,
             ram: DataValue::from_sqlval(row.try_get("ram")?)?,
             root_size: DataValue::from_sqlval(row.try_get("root_size")?)?,
             disk_size: DataValue::from_sqlval(row.try_get("disk_size")?)?,
             swap_size: DataValue::from_sqlval(row.try_get("swap_size")?)?,
             brand: row.try_get("brand")?,
             model: row.try_get("model")?,
+            capabilities: serde_json::from_value(row.try_get("capabilities")?)?,
         }))
     }
 }
@@ -97,6 +108,8 @@ pub struct ImportFlavor {
     pub swap_size: DataValue,
     pub brand: String,
     pub model: String,
+    #[serde(default)]
+    pub capabilities: FlavorCapabilities,
 }
 
 impl ImportFlavor {
@@ -115,6 +128,7 @@ impl ImportFlavor {
             swap_size: clone.swap_size,
             brand: clone.brand,
             model: clone.model,
+            capabilities: clone.capabilities,
         }
     }
 
@@ -130,6 +144,7 @@ impl ImportFlavor {
             swap_size: flavor.swap_size,
             brand: flavor.brand.clone(),
             model: flavor.model.clone(),
+            capabilities: flavor.capabilities.clone(),
         }
     }
 }
@@ -0,0 +1,114 @@
+use common::prelude::chrono::{DateTime, Utc};
+use dal::{web::AnyWay, *};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::inventory::{Flavor, Host};
+
+/// The benchmark suite that burn-in and re-runs record scores for
+pub const STANDARD_BENCHMARK: &str = "sysbench-cpu";
+
+/// A single recorded benchmark run for a host
+///
+/// Higher scores are better, so that results from different hosts
+/// of the same flavor can be compared directly
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct HostBenchmark {
+    pub id: FKey<HostBenchmark>,
+    pub host: FKey<Host>,
+    pub suite: String,
+    pub score: f64,
+    pub recorded: DateTime<Utc>,
+}
+
+impl DBTable for HostBenchmark {
+    fn table_name() -> &'static str {
+        "host_benchmarks"
+    }
+
+    fn id(&self) -> ID {
+        self.id.into_id()
+    }
+
+    fn from_row(row: tokio_postgres::Row) -> Result<ExistingRow<Self>, anyhow::Error> {
+        Ok(ExistingRow::from_existing(Self {
+            id: row.try_get("id")?,
+            host: row.try_get("host")?,
+            suite: row.try_get("suite")?,
+            score: row.try_get("score")?,
+            recorded: row.try_get("recorded")?,
+        }))
+    }
+
+    fn to_rowlike(&self) -> Result<HashMap<&str, Box<dyn ToSqlObject>>, anyhow::Error> {
+        let c: [(&str, Box<dyn ToSqlObject>); _] = [
+            ("id", Box::new(self.id)),
+            ("host", Box::new(self.host)),
+            ("suite", Box::new(self.suite.clone())),
+            ("score", Box::new(self.score)),
+            ("recorded", Box::new(self.recorded)),
+        ];
+
+        Ok(c.into_iter().collect())
+    }
+}
+
+impl HostBenchmark {
+    /// The most recent result of the standard suite for the given host, if it was ever benchmarked
+    pub async fn latest_for_host(
+        t: &mut EasyTransaction<'_>,
+        host: FKey<Host>,
+    ) -> Result<Option<ExistingRow<HostBenchmark>>, anyhow::Error> {
+        let tn = Self::table_name();
+        let q = format!(
+            "SELECT * FROM {tn} WHERE host = $1 AND suite = $2 ORDER BY recorded DESC LIMIT 1;"
+        );
+
+        let row = t
+            .query_opt(&q, &[&host, &STANDARD_BENCHMARK])
+            .await
+            .anyway()?;
+
+        row.map(Self::from_row).transpose()
+    }
+
+    /// The most recent standard suite result for every benchmarked host of the given flavor
+    pub async fn latest_for_flavor(
+        t: &mut EasyTransaction<'_>,
+        flavor: FKey<Flavor>,
+    ) -> Result<Vec<ExistingRow<HostBenchmark>>, anyhow::Error> {
+        let tn = Self::table_name();
+        let host_tn = Host::table_name();
+        let q = format!(
+            "SELECT DISTINCT ON ({tn}.host) {tn}.* FROM {tn}
+                INNER JOIN {host_tn} ON {host_tn}.id = {tn}.host
+                WHERE {host_tn}.flavor = $1 AND {tn}.suite = $2
+                ORDER BY {tn}.host, {tn}.recorded DESC;"
+        );
+
+        let rows = t
+            .query(&q, &[&flavor, &STANDARD_BENCHMARK])
+            .await
+            .anyway()?;
+
+        Self::from_rows(rows)
+    }
+}
+
+/// Returns the median of the given scores, or None if there are none
+pub fn median(scores: &[f64]) -> Option<f64> {
+    let mut sorted = scores.to_vec();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+
+    let mid = sorted.len() / 2;
+    match sorted.len() {
+        0 => None,
+        n if n % 2 == 0 => Some((sorted[mid - 1] + sorted[mid]) / 2.0),
+        _ => Some(sorted[mid]),
+    }
+}
+
+/// Whether `score` is within `percent` percent of `median`
+pub fn within_percent_of(score: f64, median: f64, percent: f64) -> bool {
+    (score - median).abs() <= median.abs() * (percent / 100.0)
+}
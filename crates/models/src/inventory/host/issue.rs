@@ -0,0 +1,115 @@
+use common::prelude::chrono::{DateTime, Utc};
+use dal::{web::AnyWay, *};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::inventory::Host;
+
+/// A known problem with part of a host that admins flagged, like `NIC2 unreliable`,
+/// for hosts that are still worth lending out
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct HostIssue {
+    pub id: FKey<HostIssue>,
+    pub host: FKey<Host>,
+    pub area: IssueArea,
+    pub summary: String,
+
+    pub flagged_by: String,
+    pub flagged: DateTime<Utc>,
+    /// Set once the problem is fixed, the flag is kept for the history of the host
+    pub cleared_by: Option<String>,
+    pub cleared: Option<DateTime<Utc>>,
+}
+
+/// Which part of the host an issue is with, so bookings that rely on that part can
+/// be kept off the host
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum IssueArea {
+    Network,
+    Storage,
+    Accelerator,
+    Performance,
+    /// The BMC, which every booking needs to provision the host
+    Management,
+    Other,
+}
+
+impl DBTable for HostIssue {
+    fn table_name() -> &'static str {
+        "host_issues"
+    }
+
+    fn id(&self) -> ID {
+        self.id.into_id()
+    }
+
+    fn from_row(row: tokio_postgres::Row) -> Result<ExistingRow<Self>, anyhow::Error> {
+        Ok(ExistingRow::from_existing(Self {
+            id: row.try_get("id")?,
+            host: row.try_get("host")?,
+            area: serde_json::from_value(row.try_get("area")?)?,
+            summary: row.try_get("summary")?,
+            flagged_by: row.try_get("flagged_by")?,
+            flagged: row.try_get("flagged")?,
+            cleared_by: row.try_get("cleared_by")?,
+            cleared: row.try_get("cleared")?,
+        }))
+    }
+
+    fn to_rowlike(&self) -> Result<HashMap<&str, Box<dyn ToSqlObject>>, anyhow::Error> {
+        let clone = self.clone();
+        let c: [(&str, Box<dyn ToSqlObject>); _] = [
+            ("id", Box::new(clone.id)),
+            ("host", Box::new(clone.host)),
+            ("area", Box::new(serde_json::to_value(clone.area)?)),
+            ("summary", Box::new(clone.summary)),
+            ("flagged_by", Box::new(clone.flagged_by)),
+            ("flagged", Box::new(clone.flagged)),
+            ("cleared_by", Box::new(clone.cleared_by)),
+            ("cleared", Box::new(clone.cleared)),
+        ];
+
+        Ok(c.into_iter().collect())
+    }
+}
+
+impl HostIssue {
+    pub async fn all_for_host(
+        t: &mut EasyTransaction<'_>,
+        host: FKey<Host>,
+    ) -> Result<Vec<ExistingRow<HostIssue>>, anyhow::Error> {
+        let tn = Self::table_name();
+        let q = format!("SELECT * FROM {tn} WHERE host = $1 ORDER BY flagged;");
+
+        let rows = t.query(&q, &[&host]).await.anyway()?;
+
+        Self::from_rows(rows)
+    }
+
+    /// The issues of the host that haven't been cleared
+    pub async fn active_for_host(
+        t: &mut EasyTransaction<'_>,
+        host: FKey<Host>,
+    ) -> Result<Vec<ExistingRow<HostIssue>>, anyhow::Error> {
+        let tn = Self::table_name();
+        let q = format!("SELECT * FROM {tn} WHERE host = $1 AND cleared IS NULL ORDER BY flagged;");
+
+        let rows = t.query(&q, &[&host]).await.anyway()?;
+
+        Self::from_rows(rows)
+    }
+
+    /// Every issue that hasn't been cleared, across all hosts
+    pub async fn active(
+        t: &mut EasyTransaction<'_>,
+    ) -> Result<Vec<ExistingRow<HostIssue>>, anyhow::Error> {
+        let tn = Self::table_name();
+        let q = format!("SELECT * FROM {tn} WHERE cleared IS NULL ORDER BY flagged;");
+
+        let rows = t.query(&q, &[]).await.anyway()?;
+
+        Self::from_rows(rows)
+    }
+}
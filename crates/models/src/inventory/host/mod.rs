@@ -1,11 +1,22 @@
 use dal::{web::AnyWay, *};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::{collections::HashMap, fs::File, io::Write, path::PathBuf, str::FromStr};
 
+pub mod benchmark;
+pub mod issue;
 mod port;
+pub mod post_time;
+pub mod ticket;
+pub mod verification;
 
+pub use benchmark::HostBenchmark;
+pub use issue::{HostIssue, IssueArea};
 pub use port::HostPort;
+pub use post_time::HostPostTime;
+pub use ticket::{HostTicket, TicketKind};
+pub use verification::{HostVerification, VerificationReport};
 
 use crate::inventory::{Arch, Flavor, Lab};
 
@@ -26,6 +37,51 @@ pub struct Host {
     pub fqdn: String,
     pub projects: Vec<String>,
     pub sda_uefi_device: Option<String>,
+    pub failure_domain: FailureDomain,
+    /// What the BMC at `ipmi_fqdn` is managed over
+    pub bmc_protocol: BmcProtocol,
+    /// The dashboard project that paid for the host, whose bookings are then the only ones
+    /// it is given to unless an admin overrides it
+    pub funded_by: Option<String>,
+}
+
+/// How LibLaaS talks to the BMC of a host
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, Hash, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum BmcProtocol {
+    #[default]
+    Ipmi,
+    /// For newer BMCs that only handle Redfish reliably, `ipmi_user` and `ipmi_pass`
+    /// are then the Redfish account
+    Redfish,
+}
+
+/// Where a host physically sits, so that hosts within one booking
+/// can be kept from sharing a single point of failure
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq, Hash, JsonSchema)]
+pub struct FailureDomain {
+    pub rack: Option<String>,
+    pub pdu: Option<String>,
+    pub switch: Option<String>,
+}
+
+/// The kinds of failure domain that allocations can be spread across
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum FailureDomainKind {
+    Rack,
+    Pdu,
+    Switch,
+}
+
+impl FailureDomain {
+    pub fn get(&self, kind: FailureDomainKind) -> Option<&str> {
+        match kind {
+            FailureDomainKind::Rack => self.rack.as_deref(),
+            FailureDomainKind::Pdu => self.pdu.as_deref(),
+            FailureDomainKind::Switch => self.switch.as_deref(),
+        }
+    }
 }
 
 impl Named for Host {
@@ -54,6 +110,12 @@ pub struct ImportHost {
     pub fqdn: String,
     pub projects: Vec<String>,
     pub sda_uefi_device: Option<String>,
+    #[serde(default)]
+    pub failure_domain: FailureDomain,
+    #[serde(default)]
+    pub bmc_protocol: BmcProtocol,
+    #[serde(default)]
+    pub funded_by: Option<String>,
 }
 
 impl ImportHost {
@@ -88,6 +150,9 @@ impl ImportHost {
             fqdn: self.fqdn.clone(),
             projects: self.projects.clone(),
             sda_uefi_device: self.sda_uefi_device.clone(),
+            failure_domain: self.failure_domain.clone(),
+            bmc_protocol: self.bmc_protocol,
+            funded_by: self.funded_by.clone(),
         }
     }
 
@@ -111,6 +176,9 @@ impl ImportHost {
             fqdn: clone.fqdn,
             projects: clone.projects,
             sda_uefi_device: clone.sda_uefi_device,
+            failure_domain: clone.failure_domain,
+            bmc_protocol: clone.bmc_protocol,
+            funded_by: clone.funded_by,
         }
     }
 }
@@ -294,6 +362,9 @@ impl DBTable for Host {
             fqdn: row.try_get("fqdn")?,
             projects: serde_json::from_value(row.try_get("projects")?)?,
             sda_uefi_device: row.try_get("sda_uefi_device")?,
+            failure_domain: serde_json::from_value(row.try_get("failure_domain")?)?,
+            bmc_protocol: serde_json::from_value(row.try_get("bmc_protocol")?)?,
+            funded_by: row.try_get("funded_by")?,
         }))
     }
 
@@ -313,6 +384,15 @@ impl DBTable for Host {
             ("fqdn", Box::new(clone.fqdn)),
             ("projects", Box::new(serde_json::to_value(clone.projects)?)),
             ("sda_uefi_device", Box::new(clone.sda_uefi_device)),
+            (
+                "failure_domain",
+                Box::new(serde_json::to_value(clone.failure_domain)?),
+            ),
+            (
+                "bmc_protocol",
+                Box::new(serde_json::to_value(clone.bmc_protocol)?),
+            ),
+            ("funded_by", Box::new(clone.funded_by)),
         ];
 
         Ok(c.into_iter().collect())
@@ -0,0 +1,74 @@
+use common::prelude::chrono::{DateTime, Utc};
+use dal::{web::AnyWay, *};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::{dashboard::Instance, inventory::Host};
+
+/// How long a host took from being powered on to netbooting into the installer on one deploy
+///
+/// Most of that is the host going through POST, so it stays about the same from deploy
+/// to deploy until something in the host starts failing
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct HostPostTime {
+    pub id: FKey<HostPostTime>,
+    pub host: FKey<Host>,
+    pub instance: Option<FKey<Instance>>,
+    pub powered_on: DateTime<Utc>,
+    pub netbooted: DateTime<Utc>,
+    pub seconds: f64,
+    /// Whether the time was far enough off the host's usual to be flagged when recorded
+    pub anomalous: bool,
+}
+
+impl DBTable for HostPostTime {
+    fn table_name() -> &'static str {
+        "host_post_times"
+    }
+
+    fn id(&self) -> ID {
+        self.id.into_id()
+    }
+
+    fn from_row(row: tokio_postgres::Row) -> Result<ExistingRow<Self>, anyhow::Error> {
+        Ok(ExistingRow::from_existing(Self {
+            id: row.try_get("id")?,
+            host: row.try_get("host")?,
+            instance: row.try_get("instance")?,
+            powered_on: row.try_get("powered_on")?,
+            netbooted: row.try_get("netbooted")?,
+            seconds: row.try_get("seconds")?,
+            anomalous: row.try_get("anomalous")?,
+        }))
+    }
+
+    fn to_rowlike(&self) -> Result<HashMap<&str, Box<dyn ToSqlObject>>, anyhow::Error> {
+        let c: [(&str, Box<dyn ToSqlObject>); _] = [
+            ("id", Box::new(self.id)),
+            ("host", Box::new(self.host)),
+            ("instance", Box::new(self.instance)),
+            ("powered_on", Box::new(self.powered_on)),
+            ("netbooted", Box::new(self.netbooted)),
+            ("seconds", Box::new(self.seconds)),
+            ("anomalous", Box::new(self.anomalous)),
+        ];
+
+        Ok(c.into_iter().collect())
+    }
+}
+
+impl HostPostTime {
+    /// The latest `limit` times recorded for the host, newest first
+    pub async fn latest_for_host(
+        t: &mut EasyTransaction<'_>,
+        host: FKey<Host>,
+        limit: usize,
+    ) -> Result<Vec<ExistingRow<HostPostTime>>, anyhow::Error> {
+        let tn = Self::table_name();
+        let q = format!("SELECT * FROM {tn} WHERE host = $1 ORDER BY netbooted DESC LIMIT $2;");
+
+        let rows = t.query(&q, &[&host, &(limit as i64)]).await.anyway()?;
+
+        Self::from_rows(rows)
+    }
+}
@@ -0,0 +1,123 @@
+use common::prelude::chrono::{DateTime, Utc};
+use dal::{web::AnyWay, *};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::inventory::Host;
+
+/// A ticket opened in the lab's issue tracker for a hardware problem with a host
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct HostTicket {
+    pub id: FKey<HostTicket>,
+    pub host: FKey<Host>,
+    pub kind: TicketKind,
+
+    /// Which tracker the ticket lives in, like `jira`
+    pub system: String,
+    /// The id the tracker gave the ticket, like `LAB-123`
+    pub external_id: String,
+    pub url: Option<String>,
+    pub summary: String,
+
+    pub opened: DateTime<Utc>,
+    /// Set by an admin once the problem is fixed, so new failures open a new ticket
+    pub resolved: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum TicketKind {
+    BurnInFailure,
+    DeployFailures,
+    SensorThreshold,
+    BmcExposed,
+    ReportedFaulty,
+    FailedVerification,
+    PostTimeDrift,
+    DecompositionFailed,
+}
+
+impl std::fmt::Display for TicketKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::BurnInFailure => write!(f, "failed burn-in"),
+            Self::DeployFailures => write!(f, "repeatedly failed to deploy"),
+            Self::SensorThreshold => write!(f, "tripped a sensor threshold"),
+            Self::BmcExposed => write!(f, "has a BMC reachable outside the management network"),
+            Self::ReportedFaulty => write!(f, "was reported faulty by the owner of a booking"),
+            Self::FailedVerification => write!(f, "failed verification after cleanup"),
+            Self::PostTimeDrift => write!(f, "is taking unusually long or short to POST"),
+            Self::DecompositionFailed => {
+                write!(f, "still has devices attached from its composable fabric")
+            }
+        }
+    }
+}
+
+impl DBTable for HostTicket {
+    fn table_name() -> &'static str {
+        "host_tickets"
+    }
+
+    fn id(&self) -> ID {
+        self.id.into_id()
+    }
+
+    fn from_row(row: tokio_postgres::Row) -> Result<ExistingRow<Self>, anyhow::Error> {
+        Ok(ExistingRow::from_existing(Self {
+            id: row.try_get("id")?,
+            host: row.try_get("host")?,
+            kind: serde_json::from_value(row.try_get("kind")?)?,
+            system: row.try_get("system")?,
+            external_id: row.try_get("external_id")?,
+            url: row.try_get("url")?,
+            summary: row.try_get("summary")?,
+            opened: row.try_get("opened")?,
+            resolved: row.try_get("resolved")?,
+        }))
+    }
+
+    fn to_rowlike(&self) -> Result<HashMap<&str, Box<dyn ToSqlObject>>, anyhow::Error> {
+        let clone = self.clone();
+        let c: [(&str, Box<dyn ToSqlObject>); _] = [
+            ("id", Box::new(clone.id)),
+            ("host", Box::new(clone.host)),
+            ("kind", Box::new(serde_json::to_value(clone.kind)?)),
+            ("system", Box::new(clone.system)),
+            ("external_id", Box::new(clone.external_id)),
+            ("url", Box::new(clone.url)),
+            ("summary", Box::new(clone.summary)),
+            ("opened", Box::new(clone.opened)),
+            ("resolved", Box::new(clone.resolved)),
+        ];
+
+        Ok(c.into_iter().collect())
+    }
+}
+
+impl HostTicket {
+    pub async fn all_for_host(
+        t: &mut EasyTransaction<'_>,
+        host: FKey<Host>,
+    ) -> Result<Vec<ExistingRow<HostTicket>>, anyhow::Error> {
+        let tn = Self::table_name();
+        let q = format!("SELECT * FROM {tn} WHERE host = $1 ORDER BY opened;");
+
+        let rows = t.query(&q, &[&host]).await.anyway()?;
+
+        Self::from_rows(rows)
+    }
+
+    /// Every ticket that an admin hasn't marked resolved yet, across all hosts
+    pub async fn unresolved(
+        t: &mut EasyTransaction<'_>,
+    ) -> Result<Vec<ExistingRow<HostTicket>>, anyhow::Error> {
+        let tn = Self::table_name();
+        let q = format!("SELECT * FROM {tn} WHERE resolved IS NULL ORDER BY opened;");
+
+        let rows = t.query(&q, &[]).await.anyway()?;
+
+        Self::from_rows(rows)
+    }
+}
@@ -0,0 +1,108 @@
+use common::prelude::chrono::{DateTime, Utc};
+use dal::{web::AnyWay, *};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::{dashboard::Aggregate, inventory::Host};
+
+/// What the verification ramdisk posts back once it has looked a host over
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct VerificationReport {
+    /// Whether nothing written by the last booking could be found on any disk
+    pub disks_wiped: bool,
+    /// Whether the BMC settings match the lab's baseline
+    pub bmc_matches_baseline: bool,
+    /// What the ramdisk found wrong, meant for people
+    #[serde(default)]
+    pub findings: Vec<String>,
+}
+
+impl VerificationReport {
+    pub fn passed(&self) -> bool {
+        self.disks_wiped && self.bmc_matches_baseline
+    }
+}
+
+/// A check of a host made after a booking's cleanup, deciding whether it's fit to go back
+/// to the free pool
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct HostVerification {
+    pub id: FKey<HostVerification>,
+    pub host: FKey<Host>,
+    /// The booking the host was cleaned up from
+    pub aggregate: FKey<Aggregate>,
+    pub passed: bool,
+    pub disks_wiped: bool,
+    pub bmc_matches_baseline: bool,
+    /// What was found wrong, including why the ramdisk couldn't report if it never did
+    pub findings: Vec<String>,
+    pub recorded: DateTime<Utc>,
+}
+
+impl DBTable for HostVerification {
+    fn table_name() -> &'static str {
+        "host_verifications"
+    }
+
+    fn id(&self) -> ID {
+        self.id.into_id()
+    }
+
+    fn from_row(row: tokio_postgres::Row) -> Result<ExistingRow<Self>, anyhow::Error> {
+        Ok(ExistingRow::from_existing(Self {
+            id: row.try_get("id")?,
+            host: row.try_get("host")?,
+            aggregate: row.try_get("aggregate")?,
+            passed: row.try_get("passed")?,
+            disks_wiped: row.try_get("disks_wiped")?,
+            bmc_matches_baseline: row.try_get("bmc_matches_baseline")?,
+            findings: serde_json::from_value(row.try_get("findings")?)?,
+            recorded: row.try_get("recorded")?,
+        }))
+    }
+
+    fn to_rowlike(&self) -> Result<HashMap<&str, Box<dyn ToSqlObject>>, anyhow::Error> {
+        let c: [(&str, Box<dyn ToSqlObject>); _] = [
+            ("id", Box::new(self.id)),
+            ("host", Box::new(self.host)),
+            ("aggregate", Box::new(self.aggregate)),
+            ("passed", Box::new(self.passed)),
+            ("disks_wiped", Box::new(self.disks_wiped)),
+            ("bmc_matches_baseline", Box::new(self.bmc_matches_baseline)),
+            ("findings", Box::new(serde_json::to_value(&self.findings)?)),
+            ("recorded", Box::new(self.recorded)),
+        ];
+
+        Ok(c.into_iter().collect())
+    }
+}
+
+impl HostVerification {
+    /// Records how verifying `host` after its time on `aggregate` went
+    pub fn new(host: FKey<Host>, aggregate: FKey<Aggregate>, report: VerificationReport) -> Self {
+        Self {
+            id: FKey::new_id_dangling(),
+            host,
+            aggregate,
+            passed: report.passed(),
+            disks_wiped: report.disks_wiped,
+            bmc_matches_baseline: report.bmc_matches_baseline,
+            findings: report.findings,
+            recorded: Utc::now(),
+        }
+    }
+
+    /// The most recent verification of `host`, if it was ever verified
+    pub async fn latest_for_host(
+        t: &mut EasyTransaction<'_>,
+        host: FKey<Host>,
+    ) -> Result<Option<ExistingRow<HostVerification>>, anyhow::Error> {
+        let tn = Self::table_name();
+        let q = format!("SELECT * FROM {tn} WHERE host = $1 ORDER BY recorded DESC LIMIT 1;");
+
+        let row = t.query_opt(&q, &[&host]).await.anyway()?;
+
+        row.map(Self::from_row).transpose()
+    }
+}
@@ -1,14 +1,23 @@
 mod action;
+mod drift_report;
 mod flavor;
-mod host;
+pub mod host;
 mod lab;
 mod switch;
 mod types;
 mod vlan;
 
 pub use action::Action;
-pub use flavor::{CardType, ExtraFlavorInfo, Flavor, ImportFlavor, InterfaceFlavor};
-pub use host::{Host, HostPort, ImportHost};
+pub use drift_report::{Drift, DriftKind, DriftReport};
+pub use flavor::{
+    Accelerator, AcceleratorKind, CardType, ComposableKind, ComposedDevices, DiskCapability,
+    DiskKind, ExtraFlavorInfo, Flavor, FlavorCapabilities, ImportFlavor, InterfaceFlavor,
+};
+pub use host::{
+    BmcProtocol, FailureDomain, FailureDomainKind, Host, HostBenchmark, HostIssue, HostPort,
+    HostPostTime, HostTicket, HostVerification, ImportHost, IssueArea, TicketKind,
+    VerificationReport,
+};
 pub use lab::Lab;
 pub use switch::{Switch, SwitchOS, SwitchPort};
 pub use types::{
@@ -1,7 +1,7 @@
 use serde::{Deserialize, Serialize};
 use strum_macros::{Display, EnumString};
 
-#[derive(Serialize, Deserialize, Clone, Debug, Hash, Copy, EnumString, Display)]
+#[derive(Serialize, Deserialize, Clone, Debug, Hash, Copy, PartialEq, Eq, EnumString, Display)]
 pub enum Arch {
     #[strum(serialize = "x86")]
     X86,
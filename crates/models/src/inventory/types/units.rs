@@ -39,6 +39,20 @@ impl DataValue {
             .ok()
     }
 
+    /// How many bytes this is, None for values that aren't sizes
+    pub fn bytes(&self) -> Option<u64> {
+        let multiplier: u64 = match self.unit {
+            DataUnit::Bytes => 1,
+            DataUnit::KiloBytes => 1_000,
+            DataUnit::MegaBytes => 1_000_000,
+            DataUnit::GigaBytes => 1_000_000_000,
+            DataUnit::TeraBytes => 1_000_000_000_000,
+            _ => return None,
+        };
+
+        Some(self.value.saturating_mul(multiplier))
+    }
+
     pub fn to_sqlval(&self) -> Result<Box<serde_json::Value>, anyhow::Error> {
         serde_json::to_value(self).map(Box::new).anyway()
     }
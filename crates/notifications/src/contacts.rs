@@ -0,0 +1,175 @@
+//! Tracks whether mail to each user actually gets through, so that notices about a
+//! booking (expiry especially) don't silently go nowhere
+//!
+//! Addresses are checked when a user is onboarded or added to a booking. After that,
+//! bounces are learned from the SMTP relay rejecting a message outright, and from
+//! delivery events the mail backend reports through the LaaS API.
+
+use common::prelude::{anyhow, chrono::Utc, tracing};
+use dal::{new_client, AsEasyTransaction, DBTable, EasyTransaction, ExistingRow, NewRow};
+use lettre::Address;
+use models::dashboard::EmailContact;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use users::ipa::IPA;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum DeliveryOutcome {
+    Delivered,
+    /// A temporary failure, like a full mailbox
+    SoftBounce,
+    /// The address doesn't exist or refuses our mail
+    HardBounce,
+}
+
+/// Gets the contact for `username` at `email`, making one or starting it over if the
+/// address changed since it was last seen
+async fn contact_for(
+    t: &mut EasyTransaction<'_>,
+    username: &str,
+    email: &str,
+) -> Result<ExistingRow<EmailContact>, anyhow::Error> {
+    match EmailContact::for_username(t, username).await? {
+        Some(c) if c.email.eq_ignore_ascii_case(email) => Ok(c),
+        Some(mut c) => {
+            tracing::info!(
+                "Email of {username} changed, forgetting bounces to {}",
+                c.email
+            );
+            let id = c.id;
+            *c = EmailContact {
+                id,
+                ..EmailContact::new(username.to_owned(), email.to_owned())
+            };
+            c.update(t).await?;
+            Ok(c)
+        }
+        None => {
+            let id = NewRow::new(EmailContact::new(username.to_owned(), email.to_owned()))
+                .insert(t)
+                .await?;
+            id.get(t).await
+        }
+    }
+}
+
+/// Whether mail can be sent to `email` at all, before it is given to anyone
+pub fn check_address(email: &str) -> Result<(), anyhow::Error> {
+    email
+        .trim()
+        .parse::<Address>()
+        .map(|_| ())
+        .map_err(|e| anyhow::Error::msg(format!("{email:?}: {e}")))
+}
+
+/// Checks that `username` has a usable email address in IPA, and returns what is
+/// known about mail to it
+///
+/// Errors if the user has no address or it can't be parsed. A returned contact
+/// may still be `unreachable()` because of earlier bounces.
+pub async fn verify(username: &str) -> Result<EmailContact, anyhow::Error> {
+    let mut ipa = IPA::init().await?;
+    let user = ipa
+        .find_matching_user(username.to_owned(), false, false)
+        .await?;
+
+    let email = user.mail.trim();
+    check_address(email).map_err(|e| {
+        anyhow::Error::msg(format!("user {username} has no valid email address ({e})"))
+    })?;
+
+    let mut client = new_client().await?;
+    let mut transaction = client.easy_transaction().await?;
+    let contact = contact_for(&mut transaction, username, email).await?;
+    transaction.commit().await?;
+
+    Ok(contact.into_inner())
+}
+
+fn apply(contact: &mut EmailContact, outcome: DeliveryOutcome, reason: Option<String>) {
+    match outcome {
+        DeliveryOutcome::Delivered => {
+            contact.verified = Some(Utc::now());
+            contact.soft_bounces = 0;
+            contact.hard_bounced = None;
+        }
+        DeliveryOutcome::SoftBounce => {
+            contact.soft_bounces += 1;
+            contact.last_error = reason;
+        }
+        DeliveryOutcome::HardBounce => {
+            contact.hard_bounced = Some(Utc::now());
+            contact.last_error = reason;
+        }
+    }
+}
+
+/// Records the outcome of mail sent to `username` at `email`
+pub async fn record_for_user(
+    username: &str,
+    email: &str,
+    outcome: DeliveryOutcome,
+    reason: Option<String>,
+) -> Result<(), anyhow::Error> {
+    let mut client = new_client().await?;
+    let mut transaction = client.easy_transaction().await?;
+
+    let mut contact = contact_for(&mut transaction, username, email).await?;
+    apply(&mut contact, outcome, reason);
+    contact.update(&mut transaction).await?;
+
+    transaction.commit().await?;
+
+    Ok(())
+}
+
+/// Records a delivery event reported by the mail backend for `email`, returning the
+/// users it applied to
+///
+/// Events for addresses no user is known to have are ignored.
+pub async fn record_event(
+    email: &str,
+    outcome: DeliveryOutcome,
+    reason: Option<String>,
+) -> Result<Vec<String>, anyhow::Error> {
+    let mut client = new_client().await?;
+    let mut transaction = client.easy_transaction().await?;
+
+    let mut usernames = Vec::new();
+    for mut contact in EmailContact::all_for_email(&mut transaction, email).await? {
+        apply(&mut contact, outcome, reason.clone());
+        contact.update(&mut transaction).await?;
+
+        if contact.unreachable() {
+            tracing::warn!(
+                "{} is now unreachable at {email}: {:?}",
+                contact.username,
+                contact.last_error
+            );
+        }
+        usernames.push(contact.username.clone());
+    }
+
+    transaction.commit().await?;
+
+    Ok(usernames)
+}
+
+/// The contacts of `usernames` that mail is known not to reach
+pub async fn unreachable(
+    t: &mut EasyTransaction<'_>,
+    usernames: &[String],
+) -> Result<Vec<EmailContact>, anyhow::Error> {
+    let mut unreachable = Vec::new();
+
+    for username in usernames {
+        if let Some(contact) = EmailContact::for_username(t, username).await? {
+            if contact.unreachable() {
+                unreachable.push(contact.into_inner());
+            }
+        }
+    }
+
+    Ok(unreachable)
+}
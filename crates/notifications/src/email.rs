@@ -1,7 +1,10 @@
 //! Copyright (c) 2023 University of New Hampshire
 //! SPDX-License-Identifier: MIT
 
-use crate::{render, Env, Notification};
+use crate::{
+    contacts::{self, DeliveryOutcome},
+    render, Env, Notification,
+};
 use common::prelude::{
     config::*,
     reqwest::RequestBuilder,
@@ -25,6 +28,7 @@ pub async fn send(env: &Env, notification: Notification) -> Result<(), anyhow::E
         .find_matching_user(notification.send_to.clone(), false, false)
         .await
         .unwrap();
+    let recipient = user.mail.clone();
     let email = user.mail;
     let addr: Address = email.parse().expect("Couldn't parse addr");
     let from_addr: Email = email_config
@@ -78,10 +82,47 @@ pub async fn send(env: &Env, notification: Notification) -> Result<(), anyhow::E
 
     match mailer.send(&email) {
         Ok(_) => Ok(()),
-        Err(e) => Err(anyhow::Error::msg(e.to_string())),
+        Err(e) => {
+            // only the relay refusing the recipient says anything about the address,
+            // not being able to reach the relay at all doesn't
+            let outcome = if e.is_permanent() {
+                Some(DeliveryOutcome::HardBounce)
+            } else if e.is_transient() {
+                Some(DeliveryOutcome::SoftBounce)
+            } else {
+                None
+            };
+
+            if let Some(outcome) = outcome {
+                if let Err(re) = contacts::record_for_user(
+                    &notification.send_to,
+                    &recipient,
+                    outcome,
+                    Some(e.to_string()),
+                )
+                .await
+                {
+                    tracing::error!("Couldn't record bounce to {recipient}: {re:?}");
+                }
+            }
+
+            Err(anyhow::Error::msg(e.to_string()))
+        }
     }
 }
 
+/// Whether the mail relay answers, without sending anything through it
+pub fn mail_server_reachable() -> Result<bool, anyhow::Error> {
+    let mail_server = settings().notifications.mail_server.clone();
+    let mailer = SmtpTransport::relay(mail_server.host.as_str())?
+        .port(mail_server.port)
+        .tls(transport::smtp::client::Tls::None)
+        .timeout(Some(std::time::Duration::from_secs(5)))
+        .build();
+
+    Ok(mailer.test_connection()?)
+}
+
 pub async fn send_to_admins(error: String) {
     if let Some(ec) = &config::settings().notifications.admin_mail_server {
         send_to_admins_email(error.clone()).await;
@@ -92,6 +133,19 @@ pub async fn send_to_admins(error: String) {
     }
 }
 
+/// Like [`send_to_admins`], with a text file attached to the email. Chat only gets the message.
+pub async fn send_to_admins_with_attachment(error: String, name: String, contents: String) {
+    if let Some(ec) = &config::settings().notifications.admin_mail_server {
+        if let Err(e) = send_to_admins_email_with_attachment(error.clone(), name, contents).await {
+            tracing::error!("Couldn't email admins with attachment: {e:?}");
+        }
+    }
+
+    if let Some(gc) = &config::settings().notifications.admin_gchat_webhook {
+        send_to_admins_gchat(error).await;
+    }
+}
+
 pub async fn send_to_admins_gchat(error: String) {
     let client = reqwest::Client::new();
     let response = client
@@ -130,7 +184,10 @@ pub async fn send_to_admins_email(error: String) {
             None,
             Address::new(to_addr.username.clone(), to_addr.domain.clone()).unwrap(),
         ))
-        .subject("LibLaaS Error Encountered")
+        .subject(format!(
+            "{} Error Encountered",
+            settings().branding.lab_name
+        ))
         .header(ContentType::TEXT_HTML)
         .body(error)
         .expect("Expected to create email");
@@ -149,6 +206,59 @@ pub async fn send_to_admins_email(error: String) {
     .unwrap();
 }
 
+pub async fn send_to_admins_email_with_attachment(
+    error: String,
+    name: String,
+    contents: String,
+) -> Result<(), anyhow::Error> {
+    let email_config = config::settings().notifications.clone();
+    let from_addr = email_config
+        .admin_send_from_email
+        .ok_or(anyhow::Error::msg(
+            "no admin from email address is configured",
+        ))?;
+    let to_addr = email_config.admin_send_to_email.ok_or(anyhow::Error::msg(
+        "no admin to email address is configured",
+    ))?;
+    let email = Message::builder()
+        .from(Mailbox::new(
+            Some(from_addr.username.clone()),
+            Address::new(from_addr.username.clone(), from_addr.domain.clone())?,
+        ))
+        .to(Mailbox::new(
+            None,
+            Address::new(to_addr.username.clone(), to_addr.domain.clone())?,
+        ))
+        .subject(format!(
+            "{} Error Encountered",
+            settings().branding.lab_name
+        ))
+        .multipart(
+            MultiPart::mixed()
+                .singlepart(
+                    SinglePart::builder()
+                        .header(ContentType::TEXT_HTML)
+                        .body(error),
+                )
+                .singlepart(Attachment::new(name).body(contents, ContentType::TEXT_PLAIN)),
+        )?;
+
+    let mail_server = settings()
+        .notifications
+        .admin_mail_server
+        .clone()
+        .ok_or(anyhow::Error::msg("no admin mail server is configured"))?;
+    let mailer = SmtpTransport::relay(mail_server.host.as_str())?
+        .port(mail_server.port)
+        .tls(transport::smtp::client::Tls::None)
+        .build();
+
+    match mailer.send(&email) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(anyhow::Error::msg(e.to_string())),
+    }
+}
+
 /// Sends an email to admins using an HTML template instead of plaintext
 pub async fn send_to_admins_email_template(
     env: &Env,
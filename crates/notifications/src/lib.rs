@@ -14,6 +14,7 @@ use std::{
     path::{Path, PathBuf},
 };
 use tera::Tera;
+pub mod contacts;
 pub mod email;
 
 use common::prelude::{
@@ -60,7 +61,11 @@ fn render(notification: &Notification, target: RenderTarget) -> Result<String, a
     )
     .expect("no template found matching query");
 
-    let rendered = TERA.render(&template_name, &notification.context)?;
+    // every template can brand itself with the lab it is sent from
+    let mut context = notification.context.clone();
+    context.insert("branding", &settings().branding);
+
+    let rendered = TERA.render(&template_name, &context)?;
 
     Ok(rendered)
 }
@@ -88,6 +93,57 @@ pub struct BookingInfo {
     pub configuration: AggregateConfiguration,
 }
 
+/// What a user needs to know about an image being deprecated that they are using
+pub struct ImageDeprecationInfo {
+    pub image: String,
+    pub image_id: String,
+    pub note: String,
+    pub sunset: Option<chrono::DateTime<chrono::Utc>>,
+    /// Name and id of the image to move to
+    pub replacement: Option<(String, String)>,
+    /// Names of the user's templates that use the image
+    pub templates: Vec<String>,
+    /// Booking ids of the user's bookings that have hosts running the image
+    pub bookings: Vec<String>,
+    pub dashboard_url: String,
+}
+
+/// What the owner of a booking that has sat idle needs to keep or release it
+pub struct IdleBookingInfo {
+    /// The display name of the booking, or its dashboard id if it has none
+    pub booking: String,
+    pub purpose: String,
+    pub idle_days: i64,
+    pub hosts: Vec<String>,
+    pub keep_url: String,
+    pub release_url: String,
+    pub dashboard_url: String,
+}
+
+/// What the owner of a booking that waited in the queue needs to know about how it ended up
+pub struct QueuedBookingInfo {
+    /// The display name of the booking, or its dashboard id if it has none
+    pub booking: String,
+    pub purpose: String,
+    pub waited_hours: i64,
+    /// True if hosts freed up and the booking is being provisioned, false if it was given up on
+    pub started: bool,
+    pub dashboard_url: String,
+}
+
+/// What the user who asked for a booking to be extended needs to know about the decision
+pub struct ExtensionDecisionInfo {
+    /// The display name of the booking, or its dashboard id if it has none
+    pub booking: String,
+    pub purpose: String,
+    /// The end date they asked for, as they gave it
+    pub requested_date: String,
+    /// The end the booking was given, None if the request was denied
+    pub granted_end: Option<chrono::DateTime<chrono::Utc>>,
+    pub note: Option<String>,
+    pub dashboard_url: String,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct IPAInfo {
     pub username: String,
@@ -221,6 +277,7 @@ pub async fn send_test_email(
         configuration: AggregateConfiguration {
             ipmi_username: "fedora_the_explorer".to_owned(),
             ipmi_password: "youwillneverguessthis".to_owned(),
+            env: Default::default(),
         },
     };
 
@@ -435,6 +492,221 @@ pub async fn request_booking_extension(
     }
 }
 
+pub async fn image_deprecated(
+    env: &Env,
+    username: &Username,
+    info: &ImageDeprecationInfo,
+) -> Result<(), Vec<anyhow::Error>> {
+    let styles = read_styles(
+        settings()
+            .projects
+            .get(env.project.clone().as_str())
+            .unwrap()
+            .styles_path
+            .as_str(),
+    )
+    .expect("Failed to read styles");
+
+    let styles_json: serde_json::Value =
+        serde_json::from_str(&styles).expect("Failed to parse JSON");
+
+    let mut context = tera::Context::new();
+    context.insert("styles", &styles_json);
+    context.insert(
+        "image",
+        &json!({
+            "name": info.image,
+            "id": info.image_id,
+            "note": info.note,
+            "sunset": info.sunset.map(|s| s.to_rfc2822()),
+            "replacement_name": info.replacement.as_ref().map(|(name, _)| name),
+            "replacement_id": info.replacement.as_ref().map(|(_, id)| id),
+        }),
+    );
+    context.insert("templates", &info.templates);
+    context.insert("bookings", &info.bookings);
+    context.insert("dashboard_url", &info.dashboard_url);
+
+    let notification = Notification {
+        title: format!("The Image {} Is Being Retired", info.image),
+        send_to: username.clone(),
+        by_methods: preferred_methods(username),
+        situation: Situation::ImageDeprecated,
+        project: env.project.clone(),
+        context,
+        attachment: None,
+    };
+
+    match send(env, notification).await {
+        Ok(_) => Ok(()),
+        Err(e) => {
+            tracing::error!("Failed to send email to {username} with error {e:#?}");
+            Err(vec![e])
+        }
+    }
+}
+
+pub async fn booking_idle(
+    env: &Env,
+    username: &Username,
+    info: &IdleBookingInfo,
+) -> Result<(), Vec<anyhow::Error>> {
+    let styles = read_styles(
+        settings()
+            .projects
+            .get(env.project.clone().as_str())
+            .unwrap()
+            .styles_path
+            .as_str(),
+    )
+    .expect("Failed to read styles");
+
+    let styles_json: serde_json::Value =
+        serde_json::from_str(&styles).expect("Failed to parse JSON");
+
+    let mut context = tera::Context::new();
+    context.insert("styles", &styles_json);
+    context.insert(
+        "booking",
+        &json!({
+            "name": info.booking,
+            "purpose": info.purpose,
+            "hosts": info.hosts,
+        }),
+    );
+    context.insert("idle_days", &info.idle_days);
+    context.insert("keep_url", &info.keep_url);
+    context.insert("release_url", &info.release_url);
+    context.insert("dashboard_url", &info.dashboard_url);
+
+    let notification = Notification {
+        title: format!("Is Your Booking {} Still Needed?", info.booking),
+        send_to: username.clone(),
+        by_methods: preferred_methods(username),
+        situation: Situation::BookingIdle,
+        project: env.project.clone(),
+        context,
+        attachment: None,
+    };
+
+    match send(env, notification).await {
+        Ok(_) => Ok(()),
+        Err(e) => {
+            tracing::error!("Failed to send email to {username} with error {e:#?}");
+            Err(vec![e])
+        }
+    }
+}
+
+pub async fn booking_queue(
+    env: &Env,
+    username: &Username,
+    info: &QueuedBookingInfo,
+) -> Result<(), Vec<anyhow::Error>> {
+    let styles = read_styles(
+        settings()
+            .projects
+            .get(env.project.clone().as_str())
+            .unwrap()
+            .styles_path
+            .as_str(),
+    )
+    .expect("Failed to read styles");
+
+    let styles_json: serde_json::Value =
+        serde_json::from_str(&styles).expect("Failed to parse JSON");
+
+    let mut context = tera::Context::new();
+    context.insert("styles", &styles_json);
+    context.insert(
+        "booking",
+        &json!({
+            "name": info.booking,
+            "purpose": info.purpose,
+        }),
+    );
+    context.insert("waited_hours", &info.waited_hours);
+    context.insert("started", &info.started);
+    context.insert("dashboard_url", &info.dashboard_url);
+
+    let title = match info.started {
+        true => format!("Hosts Are Free For Your Booking {}", info.booking),
+        false => format!("No Hosts Freed Up For Your Booking {}", info.booking),
+    };
+    let notification = Notification {
+        title,
+        send_to: username.clone(),
+        by_methods: preferred_methods(username),
+        situation: Situation::BookingQueue,
+        project: env.project.clone(),
+        context,
+        attachment: None,
+    };
+
+    match send(env, notification).await {
+        Ok(_) => Ok(()),
+        Err(e) => {
+            tracing::error!("Failed to send email to {username} with error {e:#?}");
+            Err(vec![e])
+        }
+    }
+}
+
+pub async fn booking_extension_decided(
+    env: &Env,
+    username: &Username,
+    info: &ExtensionDecisionInfo,
+) -> Result<(), Vec<anyhow::Error>> {
+    let styles = read_styles(
+        settings()
+            .projects
+            .get(env.project.clone().as_str())
+            .unwrap()
+            .styles_path
+            .as_str(),
+    )
+    .expect("Failed to read styles");
+
+    let styles_json: serde_json::Value =
+        serde_json::from_str(&styles).expect("Failed to parse JSON");
+
+    let mut context = tera::Context::new();
+    context.insert("styles", &styles_json);
+    context.insert(
+        "booking",
+        &json!({
+            "name": info.booking,
+            "purpose": info.purpose,
+        }),
+    );
+    context.insert("approved", &info.granted_end.is_some());
+    context.insert("requested_date", &info.requested_date);
+    context.insert("granted_end", &info.granted_end.map(|e| e.to_rfc2822()));
+    context.insert("note", &info.note);
+    context.insert("dashboard_url", &info.dashboard_url);
+
+    let notification = Notification {
+        title: match info.granted_end {
+            Some(_) => format!("Your Booking {} Has Been Extended", info.booking),
+            None => format!("Your Booking {} Was Not Extended", info.booking),
+        },
+        send_to: username.clone(),
+        by_methods: preferred_methods(username),
+        situation: Situation::ExtensionDecided,
+        project: env.project.clone(),
+        context,
+        attachment: None,
+    };
+
+    match send(env, notification).await {
+        Ok(_) => Ok(()),
+        Err(e) => {
+            tracing::error!("Failed to send email to {username} with error {e:#?}");
+            Err(vec![e])
+        }
+    }
+}
+
 /// Send email containing ipa username, temp password, openvpn config, and instructions
 pub async fn send_new_account_notification(
     env: &Env,
@@ -0,0 +1,99 @@
+//! Failure injection for exercising recovery paths (retries, substitution, watchdogs)
+//!
+//! Faults are registered against a task by the name in its `TaskIdentifier`,
+//! and are checked right before each run of a matching task. This is meant for
+//! dev and CI deployments only, nothing registers faults unless dev mode is on.
+
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+use crate::{task_trait::TaskIdentifier, workflows::TaskError};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FaultMode {
+    /// The run fails immediately with a `TaskError::Reason`
+    Fail,
+    /// The run never completes, so it is cut off by the task timeout
+    Hang,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InjectedFault {
+    /// Name of the task, as given to `TaskIdentifier::named`
+    pub task: String,
+    pub mode: FaultMode,
+    /// How many more runs the fault applies to, forever if `None`
+    pub remaining: Option<u32>,
+}
+
+static FAULTS: Mutex<Vec<InjectedFault>> = Mutex::new(Vec::new());
+
+/// Registers a fault, replacing any fault already registered for the same task.
+/// A fault for zero runs never applies, so it only clears the one already registered
+pub fn inject(fault: InjectedFault) {
+    let mut faults = FAULTS.lock();
+    faults.retain(|f| f.task != fault.task);
+
+    if fault.remaining == Some(0) {
+        tracing::warn!("Not injecting fault {fault:?}, it applies to no runs");
+        return;
+    }
+
+    tracing::warn!("Injecting fault {fault:?}");
+    faults.push(fault);
+}
+
+/// Removes the fault for `task`, returning whether there was one
+pub fn clear(task: &str) -> bool {
+    let mut faults = FAULTS.lock();
+    let before = faults.len();
+    faults.retain(|f| f.task != task);
+
+    before != faults.len()
+}
+
+pub fn clear_all() {
+    FAULTS.lock().clear();
+}
+
+pub fn active() -> Vec<InjectedFault> {
+    FAULTS.lock().clone()
+}
+
+/// Consumes one run of the fault registered for `ident`, if any
+fn take(ident: &TaskIdentifier) -> Option<FaultMode> {
+    let mut faults = FAULTS.lock();
+    let idx = faults.iter().position(|f| f.task == ident.name)?;
+
+    let fault = &mut faults[idx];
+    let mode = fault.mode;
+    match &mut fault.remaining {
+        Some(0 | 1) => {
+            faults.remove(idx);
+        }
+        Some(n) => *n -= 1,
+        None => (),
+    }
+
+    Some(mode)
+}
+
+/// Called before each task run, returns the error the run should fail with instead,
+/// or never returns at all for a hang
+pub(crate) async fn check(ident: &TaskIdentifier) -> Result<(), TaskError> {
+    match take(ident) {
+        None => Ok(()),
+        Some(FaultMode::Fail) => {
+            tracing::warn!("Failing run of {ident:?} due to an injected fault");
+            Err(TaskError::Reason(format!(
+                "injected failure for task {}",
+                ident.name
+            )))
+        }
+        Some(FaultMode::Hang) => {
+            tracing::warn!("Hanging run of {ident:?} due to an injected fault");
+            std::future::pending().await
+        }
+    }
+}
@@ -15,6 +15,7 @@
 )]
 
 pub mod executors;
+pub mod faults;
 pub mod task_trait;
 
 mod oneshot;
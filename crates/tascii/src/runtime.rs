@@ -114,6 +114,23 @@ impl Runtime {
         self.tx.send(msg)
     }
 
+    /// Stops the task with the given id if it hasn't completed yet, so anything
+    /// joining on it sees it fail
+    ///
+    /// The task itself isn't interrupted, so it has to notice on its own
+    /// that it should stop doing work
+    pub fn cancel(&'static self, id: ID) {
+        // the orchestrator expects any task it is told to stop to exist
+        if let Err(e) = self.get_task(id) {
+            warn!("Asked to cancel task {id}, which couldn't be found: {e:?}");
+            return;
+        }
+
+        if let Err(e) = self.tx.send(TaskMessage::Cancel(id)) {
+            tracing::error!("Couldn't send a cancel message for {id}, error was {e:?} as channel closed");
+        }
+    }
+
     pub fn unset_target(&self, id: ID) {
         self.targets.remove(&id);
 
@@ -12,7 +12,7 @@ use std::sync::Arc;
 use tracing::{debug, info};
 
 use crate::{
-    executors,
+    executors, faults,
     oneshot::{OneShot, OneShotRegistry, SimpleOneshotHandle, StrongUntypedOneshotHandle},
     runtime::Runtime,
     scheduler,
@@ -145,7 +145,10 @@ where
 
         let res = std::panic::catch_unwind(AssertUnwindSafe(|| {
             tokio_rt.block_on(async move {
-                let r = slef.v.run(&context).await;
+                let r = match faults::check(&R::identifier()).await {
+                    Ok(()) => slef.v.run(&context).await,
+                    Err(e) => Err(e),
+                };
 
                 let _summary = slef.summarize(run_id);
 
@@ -164,7 +164,7 @@ impl<
 #[derive(PartialEq, Eq, Hash, Clone, Serialize, Deserialize, Debug)]
 pub struct TaskIdentifier {
     version: usize,
-    name: String,
+    pub(crate) name: String,
 }
 
 impl TaskIdentifier {
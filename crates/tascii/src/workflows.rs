@@ -62,8 +62,8 @@ impl TaskError {
 }
 
 impl From<anyhow::Error> for TaskError {
-    fn from(_value: anyhow::Error) -> Self {
-        todo!()
+    fn from(value: anyhow::Error) -> Self {
+        Self::Reason(format!("{value:?}"))
     }
 }
 
@@ -163,6 +163,11 @@ impl Context {
         }
     }
 
+    /// The id of the task this context belongs to
+    pub fn id(&self) -> ID {
+        self.inner.lock().tid
+    }
+
     /// If no prior run with this context existed, this
     /// creates a new runtime task from the provided runnable
     /// and returns a handle that can be joined on to block until
@@ -0,0 +1,144 @@
+//! Ending many bookings at once as a job
+//!
+//! Bookings are torn down a few at a time, so a big sweep doesn't flood the switches
+//! and BMCs with cleanup work. A booking that fails to end doesn't stop the rest.
+
+use common::prelude::{
+    anyhow,
+    tokio::time::{sleep, Duration, Instant},
+    tracing,
+};
+use dal::{new_client, AsEasyTransaction, FKey};
+use models::dashboard::{Aggregate, LifeCycleState};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    entry::{Action, DISPATCH},
+    resource_management::jobs::{JobHandle, JobReport},
+};
+
+/// The most bookings that may be torn down at the same time
+pub const MAX_CONCURRENCY: usize = 16;
+
+/// How long one booking gets to finish tearing down
+const END_DEADLINE: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct BulkEndReport {
+    /// Bookings whose hosts were cleaned up and released
+    pub ended: Vec<FKey<Aggregate>>,
+    pub failed: Vec<EndFailure>,
+    /// Bookings that weren't ended because the job was cancelled first
+    pub skipped: Vec<FKey<Aggregate>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct EndFailure {
+    pub aggregate: FKey<Aggregate>,
+    pub reason: String,
+}
+
+impl JobReport for BulkEndReport {
+    fn succeeded(&self) -> bool {
+        self.failed.is_empty() && self.skipped.is_empty()
+    }
+}
+
+/// Queues the teardown of `agg`, which has to be up and running
+async fn start_end(agg: FKey<Aggregate>) -> Result<(), anyhow::Error> {
+    let mut client = new_client().await?;
+    let mut transaction = client.easy_transaction().await?;
+    let state = agg.get(&mut transaction).await?.state;
+    transaction.commit().await?;
+
+    if state != LifeCycleState::Active {
+        return Err(anyhow::Error::msg(format!(
+            "the booking is {state:?}, only active bookings can be ended"
+        )));
+    }
+
+    DISPATCH
+        .get()
+        .ok_or(anyhow::Error::msg("tascii was not found"))?
+        .send(Action::CleanupBooking { agg_id: agg })
+        .map_err(|_| anyhow::Error::msg("couldn't dispatch the teardown"))
+}
+
+async fn has_ended(agg: FKey<Aggregate>) -> Result<bool, anyhow::Error> {
+    let mut client = new_client().await?;
+    let mut transaction = client.easy_transaction().await?;
+    let state = agg.get(&mut transaction).await?.state;
+    transaction.commit().await?;
+
+    Ok(state.ended())
+}
+
+/// Ends each of `aggregates`, `concurrency` of them at a time
+pub async fn bulk_end(
+    job: JobHandle,
+    aggregates: Vec<FKey<Aggregate>>,
+    concurrency: usize,
+) -> Result<BulkEndReport, anyhow::Error> {
+    let mut report = BulkEndReport::default();
+    let batches: Vec<&[FKey<Aggregate>]> = aggregates
+        .chunks(concurrency.clamp(1, MAX_CONCURRENCY))
+        .collect();
+    let count = batches.len();
+
+    for (n, batch) in batches.iter().enumerate() {
+        let done = report.ended.len() + report.failed.len();
+        if !report.skipped.is_empty()
+            || !job
+                .step(done, format!("ending batch {} of {count}", n + 1))
+                .await?
+        {
+            report.skipped.extend(batch.iter());
+            continue;
+        }
+
+        let mut pending = Vec::new();
+        for &aggregate in batch.iter() {
+            match start_end(aggregate).await {
+                Ok(()) => pending.push(aggregate),
+                Err(e) => report.failed.push(EndFailure {
+                    aggregate,
+                    reason: format!("couldn't start ending it: {e}"),
+                }),
+            }
+        }
+
+        let deadline = Instant::now() + END_DEADLINE;
+        while !pending.is_empty() {
+            if Instant::now() > deadline {
+                for aggregate in pending.drain(..) {
+                    report.failed.push(EndFailure {
+                        aggregate,
+                        reason: "didn't finish tearing down in time".to_owned(),
+                    });
+                }
+                break;
+            }
+
+            sleep(Duration::from_secs(15)).await;
+
+            let mut still_pending = Vec::new();
+            for aggregate in pending {
+                match has_ended(aggregate).await {
+                    Ok(true) => report.ended.push(aggregate),
+                    Ok(false) => still_pending.push(aggregate),
+                    Err(e) => {
+                        tracing::warn!("Couldn't check on the end of {aggregate:?}: {e:?}");
+                        still_pending.push(aggregate);
+                    }
+                }
+            }
+            pending = still_pending;
+        }
+    }
+
+    job.step(report.ended.len() + report.failed.len(), "done".to_owned())
+        .await?;
+
+    Ok(report)
+}
@@ -6,8 +6,7 @@ use config::settings;
 use dal::{new_client, AsEasyTransaction, FKey};
 use models::{
     dashboard::{Aggregate, Instance, StatusSentiment},
-    inventory::Host,
-    EasyLog,
+    inventory::{Host, TicketKind},
 };
 use notifications::email::{send_to_admins_email, send_to_admins_gchat};
 use serde::{self, Deserialize, Serialize};
@@ -18,8 +17,11 @@ use crate::{
         configure_networking::ConfigureNetworking, manage_eve_nodes::DeleteEveNode,
         net_config::empty_network_config, set_host_power_state::SetPower,
     },
-    resource_management::ipmi_accounts::DeleteIPMIAccount,
+    resource_management::{
+        composability, ipmi_accounts::DeleteIPMIAccount, tickets::report_host_failure,
+    },
     retry_for,
+    utils::status_feed::PublishedLog,
 };
 
 tascii::mark_task!(CleanupHost);
@@ -47,6 +49,32 @@ impl AsyncRunnable for CleanupHost {
 
         retry_for(SetPower::off(self.host_id), context, 10, 10).expect("couldn't power down host");
 
+        // the devices go back to the fabric for the next booking that is composed with them
+        match composability::decompose(self.instance).await {
+            Ok(Some(composition)) => {
+                self.instance
+                    .log(
+                        "Host Decomposed",
+                        &format!(
+                            "devices attached from fabric {} were detached",
+                            composition.fabric
+                        ),
+                        StatusSentiment::InProgress,
+                    )
+                    .await;
+            }
+            Ok(None) => {}
+            Err(e) => {
+                tracing::error!("Failed to decompose host {:?}: {e:?}", self.host_id);
+                report_host_failure(
+                    self.host_id,
+                    TicketKind::DecompositionFailed,
+                    format!("Detaching the devices composed onto the host at cleanup failed: {e}"),
+                )
+                .await;
+            }
+        }
+
         self.instance
             .log(
                 "Removing IPMI Accounts",
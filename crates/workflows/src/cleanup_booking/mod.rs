@@ -1,18 +1,41 @@
+pub mod bulk;
 mod clean_host;
+pub mod release;
+mod verify_host;
 
-use common::prelude::tracing;
-use dal::{new_client, AsEasyTransaction, FKey, ID};
+use common::prelude::{chrono::Duration, serde_json, tracing};
+use config::settings;
+use dal::{new_client, AsEasyTransaction, EasyTransaction, FKey, ID};
 use models::{
-    allocator::ResourceHandle,
-    dashboard::{Aggregate, LifeCycleState, StatusSentiment},
-    EasyLog,
+    allocator::{AllocationReason, ResourceHandle},
+    dashboard::{
+        Aggregate, AggregateSummary, BookingEvent, BookingEventKind, LeaseOperation,
+        LifeCycleState, PortMirror, StatusSentiment, TrashedBooking,
+    },
+    inventory::{Host, TicketKind},
 };
+use notifications::email::send_to_admins;
 use serde::{self, Deserialize, Serialize};
 use tascii::prelude::*;
 
-use crate::resource_management::{allocator, vpn::SyncVPN};
+use crate::{
+    deploy_booking::maintenance_aggregate,
+    resource_management::{
+        allocator,
+        external::DetachExternalNetworks,
+        firewall::RemoveFirewallPolicy,
+        lease::{self, TEARDOWN_WAIT},
+        mirror::EndPortMirror,
+        tickets::report_host_failure,
+        vpn::SyncVPN,
+    },
+    utils::status_feed::PublishedLog,
+};
 
-use self::clean_host::CleanupHost;
+use self::{
+    clean_host::CleanupHost,
+    verify_host::{verification_timeout, VerifyHost},
+};
 
 #[derive(Debug, Clone, Hash, Serialize, Deserialize)]
 pub struct CleanupAggregate {
@@ -34,15 +57,57 @@ impl AsyncRunnable for CleanupAggregate {
         &mut self,
         context: &tascii::prelude::Context,
     ) -> Result<Self::Output, tascii::prelude::TaskError> {
+        // whatever else is being done to the booking is let finish first
+        let lease =
+            lease::acquire(self.agg_id, LeaseOperation::Teardown, None, TEARDOWN_WAIT).await?;
+        lease::holding(lease, self.cleanup(context)).await
+    }
+
+    fn identifier() -> tascii::task_trait::TaskIdentifier {
+        TaskIdentifier::named("CleanAggTask").versioned(1)
+    }
+
+    fn timeout() -> std::time::Duration {
+        // the default, on top of how long it can be kept waiting and how long verifying
+        // its hosts can take
+        std::time::Duration::from_secs(120) + TEARDOWN_WAIT + verification_timeout()
+    }
+}
+
+impl CleanupAggregate {
+    /// Tears the booking down, under the lease `run` took
+    async fn cleanup(
+        &mut self,
+        context: &tascii::prelude::Context,
+    ) -> Result<(), tascii::prelude::TaskError> {
         // this just wants to be best effort, so don't
         // worry too much about retry logic *here*
         let mut client = new_client().await.unwrap();
+
+        // shown as tearing down for as long as its hosts are being cleaned up, so committed
+        // on its own
+        let mut transaction = client.easy_transaction().await.unwrap();
+        let mut agg = self.agg_id.get(&mut transaction).await.unwrap();
+        if let LifeCycleState::Active = agg.state {
+            Aggregate::transition(
+                &mut agg,
+                &mut transaction,
+                LifeCycleState::TearingDown,
+                "its hosts are being cleaned up",
+                None,
+            )
+            .await?;
+            AggregateSummary::refresh(&mut transaction, agg.id).await?;
+        }
+        transaction.commit().await.unwrap();
+
         let mut transaction = client.easy_transaction().await.unwrap();
 
         let mut agg = self.agg_id.get(&mut transaction).await.unwrap();
 
-        if let LifeCycleState::Active = agg.state {
-            tracing::info!("Booking was active, so we won't be conflicting with some other task")
+        // a retried teardown picks up where the last one left off
+        if let LifeCycleState::TearingDown = agg.state {
+            tracing::info!("Booking is being torn down, so we won't be conflicting with some other task")
         } else {
             tracing::error!("Booking wasn't active! Tried to deprovision a booking that is either being provisioned still, or is expired. Current state of it was {:?}", agg.state);
             panic!("bad cleanup state");
@@ -76,13 +141,90 @@ impl AsyncRunnable for CleanupAggregate {
                         agg_id: self.agg_id,
                         host_id: host,
                     });
-                    cleanup_handles.push(jh);
+                    cleanup_handles.push((instance.id, host, jh));
                 }
             }
         }
 
-        for handle in cleanup_handles {
+        let mut cleaned = Vec::new();
+        for (instance, host, handle) in cleanup_handles {
             let _ignore = handle.join();
+            cleaned.push((instance, host));
+        }
+
+        // cleanup is only taken at its word once the hosts were checked over
+        let verify_handles = cleaned
+            .iter()
+            .map(|&(instance, host)| {
+                let jh = context.spawn(VerifyHost {
+                    agg_id: self.agg_id,
+                    instance,
+                    host_id: host,
+                });
+                (host, jh)
+            })
+            .collect::<Vec<_>>();
+
+        let mut unverified = Vec::new();
+        for (host, handle) in verify_handles {
+            match handle.join() {
+                Ok(true) => (),
+                Ok(false) => unverified.push(host),
+                Err(e) => {
+                    tracing::error!("Couldn't verify host {host:?}: {e:?}");
+                    unverified.push(host);
+                }
+            }
+        }
+
+        // mirrors are only allowed between hosts of the booking, so none can outlive it
+        for mirror in PortMirror::active_for_aggregate(&mut transaction, self.agg_id).await? {
+            if let Err(e) = context.spawn(EndPortMirror { mirror: mirror.id }).join() {
+                tracing::error!("Couldn't end port mirror {:?}: {e:?}", mirror.id);
+            }
+        }
+
+        // our vlans are about to be handed to other bookings, so they must come off any feeds
+        if let Err(e) = context
+            .spawn(DetachExternalNetworks {
+                agg_id: self.agg_id,
+            })
+            .join()
+        {
+            tracing::error!(
+                "Couldn't detach external networks for {:?}: {e:?}",
+                self.agg_id
+            );
+        }
+
+        // the hosts are gone, so their uplink rules shouldn't outlive them
+        if let Err(e) = context
+            .spawn(RemoveFirewallPolicy {
+                agg_id: self.agg_id,
+            })
+            .join()
+        {
+            tracing::error!(
+                "Couldn't remove firewall policy for {:?}: {e:?}",
+                self.agg_id
+            );
+        }
+
+        // keep a record of what the aggregate looked like in case it was torn down by mistake
+        let snapshot = serde_json::json!({
+            "aggregate": &*agg,
+            "network_assignments": &*agg.vlans.get(&mut transaction).await?,
+            "instances": agg
+                .instances(&mut transaction)
+                .await?
+                .into_iter()
+                .map(|i| i.into_inner())
+                .collect::<Vec<_>>(),
+        });
+        match dal::backup::write_snapshot("aggregates", &agg.id.into_id().to_string(), &snapshot) {
+            Ok(Some(path)) => tracing::info!("Saved pre-teardown snapshot to {path:?}"),
+            Ok(None) => (),
+            Err(e) => tracing::error!("Couldn't save pre-teardown snapshot: {e:?}"),
         }
 
         // now, deallocate the aggregate
@@ -91,19 +233,72 @@ impl AsyncRunnable for CleanupAggregate {
             .await
             .expect("couldn't dealloc agg");
 
+        if !unverified.is_empty() {
+            if let Err(e) = hold_back(&mut transaction, self.agg_id, agg.lab, &unverified).await {
+                tracing::error!(
+                    "Couldn't hold back hosts {unverified:?} that failed verification: {e:?}"
+                );
+            }
+        }
+
         for instance in agg.instances(&mut transaction).await.unwrap().iter() {
-            instance
-                .id
-                .log(
-                    "Cleanup Finished",
-                    "host has been deprovisioned and returned to the free pool",
-                    StatusSentiment::Succeeded,
-                )
-                .await;
+            if instance
+                .linked_host
+                .is_some_and(|h| unverified.contains(&h))
+            {
+                instance
+                    .id
+                    .log(
+                        "Cleanup Finished",
+                        "host has been deprovisioned, but failed verification and was held back for maintenance",
+                        StatusSentiment::Succeeded,
+                    )
+                    .await;
+            } else {
+                instance
+                    .id
+                    .log(
+                        "Cleanup Finished",
+                        "host has been deprovisioned and returned to the free pool",
+                        StatusSentiment::Succeeded,
+                    )
+                    .await;
+            }
         }
 
-        agg.state = LifeCycleState::Done;
-        agg.update(&mut transaction).await.unwrap();
+        Aggregate::transition(
+            &mut agg,
+            &mut transaction,
+            LifeCycleState::Done,
+            "its hosts were cleaned up and released",
+            None,
+        )
+        .await
+        .unwrap();
+        AggregateSummary::refresh(&mut transaction, agg.id)
+            .await
+            .unwrap();
+        if let Err(e) = BookingEvent::record(
+            &mut transaction,
+            agg.id,
+            BookingEventKind::Ended,
+            "its hosts were cleaned up and released",
+            None,
+        )
+        .await
+        {
+            tracing::warn!("Couldn't record end of booking {:?}: {e:?}", agg.id);
+        }
+        // its instances and logs are kept for a while in case anything has to be looked up
+        if let Err(e) = TrashedBooking::record(
+            &mut transaction,
+            agg.id,
+            Duration::days(settings().trash.retention_days),
+        )
+        .await
+        {
+            tracing::warn!("Couldn't put booking {:?} in the trash: {e:?}", agg.id);
+        }
         transaction.commit().await.unwrap();
 
         // LifeCycleState is now Done, sync vpn and remove groups from user if needed
@@ -115,8 +310,54 @@ impl AsyncRunnable for CleanupAggregate {
 
         Ok(())
     }
+}
 
-    fn identifier() -> tascii::task_trait::TaskIdentifier {
-        TaskIdentifier::named("CleanAggTask").versioned(1)
+/// Moves `hosts`, which failed verification, from the free pool to a maintenance
+/// booking and opens a ticket for each
+async fn hold_back(
+    transaction: &mut EasyTransaction<'_>,
+    agg_id: FKey<Aggregate>,
+    lab: FKey<models::inventory::Lab>,
+    hosts: &[FKey<Host>],
+) -> Result<(), anyhow::Error> {
+    let maintenance = maintenance_aggregate(transaction, lab, "unverified hosts").await?;
+
+    let mut names = Vec::new();
+    for &host in hosts {
+        allocator::Allocator::instance()
+            .allocate_specific_host(
+                transaction,
+                host,
+                maintenance.id,
+                AllocationReason::ForMaintenance,
+            )
+            .await?;
+        names.push(host.get(transaction).await?.server_name.clone());
     }
+
+    send_to_admins(format!(
+        "Host(s) {} failed verification after the cleanup of booking {:?}. \
+        Added to maintenance booking {:?}.",
+        names.join(", "),
+        agg_id.into_id(),
+        maintenance.id.into_id()
+    ))
+    .await;
+
+    for &host in hosts {
+        report_host_failure(
+            host,
+            TicketKind::FailedVerification,
+            format!(
+                "This host failed verification after the cleanup of booking {:?}, \
+                see its latest verification for what was found. \
+                It has been moved to maintenance booking {:?}.",
+                agg_id.into_id(),
+                maintenance.id.into_id()
+            ),
+        )
+        .await;
+    }
+
+    Ok(())
 }
@@ -0,0 +1,209 @@
+//! Gives a single host of a running booking back, while the rest of the booking keeps going
+//!
+//! The host is cleaned up and verified just like at the end of a booking, so it comes off
+//! the booking networks and any mirror of its ports ends. Its instance is then deleted,
+//! along with its provision log and everything else recorded about it, since nothing of
+//! the booking refers to it anymore.
+
+use common::prelude::tracing;
+use dal::{new_client, AsEasyTransaction, FKey, ID};
+use models::{
+    allocator::ResourceHandle,
+    dashboard::{
+        Aggregate, AggregateSummary, BookingEvent, BookingEventKind, Instance, LeaseOperation,
+        LifeCycleState, PortMirror,
+    },
+};
+use serde::{Deserialize, Serialize};
+use tascii::prelude::*;
+
+use super::{
+    clean_host::CleanupHost,
+    hold_back,
+    verify_host::{verification_timeout, VerifyHost},
+};
+use crate::resource_management::{
+    allocator::Allocator,
+    lease::{self, TEARDOWN_WAIT},
+    mirror::EndPortMirror,
+};
+
+#[derive(Debug, Clone, Hash, Serialize, Deserialize)]
+pub struct ReleaseInstance {
+    pub agg_id: FKey<Aggregate>,
+    pub instance: FKey<Instance>,
+    /// Who asked for the host to be released, None for LibLaaS itself
+    pub released_by: Option<String>,
+}
+
+tascii::mark_task!(ReleaseInstance);
+impl AsyncRunnable for ReleaseInstance {
+    type Output = ();
+
+    fn summarize(&self, id: ID) -> String {
+        format!(
+            "ReleaseInstance task with id {id}, releasing instance {:?} of agg {:?}",
+            self.instance, self.agg_id
+        )
+    }
+
+    async fn run(&mut self, context: &Context) -> Result<Self::Output, TaskError> {
+        let lease =
+            lease::acquire(self.agg_id, LeaseOperation::Teardown, None, TEARDOWN_WAIT).await?;
+        lease::holding(lease, self.release(context)).await
+    }
+
+    fn identifier() -> TaskIdentifier {
+        TaskIdentifier::named("ReleaseInstanceTask").versioned(1)
+    }
+
+    fn timeout() -> std::time::Duration {
+        std::time::Duration::from_secs(120) + TEARDOWN_WAIT + verification_timeout()
+    }
+}
+
+impl ReleaseInstance {
+    /// Tears the host of the instance down, under the lease `run` took
+    async fn release(&self, context: &Context) -> Result<(), TaskError> {
+        let mut client = new_client().await?;
+        let mut transaction = client.easy_transaction().await?;
+
+        let agg = self.agg_id.get(&mut transaction).await?;
+        if agg.state != LifeCycleState::Active {
+            return Err(TaskError::Reason(format!(
+                "booking {:?} isn't active, it is {:?}",
+                self.agg_id, agg.state
+            )));
+        }
+
+        let instance = self.instance.get(&mut transaction).await?.into_inner();
+        if instance.aggregate != self.agg_id {
+            return Err(TaskError::Reason(format!(
+                "instance {:?} isn't part of booking {:?}",
+                self.instance, self.agg_id
+            )));
+        }
+
+        // an instance that never got a host only has its records to clean up
+        let host = match instance.linked_host {
+            Some(host) => {
+                let handle = ResourceHandle::handle_for_host(&mut transaction, host).await?;
+                if handle
+                    .currently_owned_by(&mut transaction, self.agg_id)
+                    .await?
+                {
+                    Some((host, handle))
+                } else {
+                    tracing::warn!(
+                        "Host {host:?} of instance {:?} wasn't allocated to agg {:?}, \
+                        so only the instance is released",
+                        self.instance,
+                        self.agg_id
+                    );
+                    None
+                }
+            }
+            None => None,
+        };
+
+        // a mirror can't outlive either of its ports being in the booking
+        let mut mirrors = Vec::new();
+        if let Some((host, _)) = host {
+            for mirror in PortMirror::active_for_aggregate(&mut transaction, self.agg_id).await? {
+                let source = mirror.source.get(&mut transaction).await?.on_host;
+                let destination = mirror.destination.get(&mut transaction).await?.on_host;
+                if source == host || destination == host {
+                    mirrors.push(mirror.id);
+                }
+            }
+        }
+        transaction.commit().await?;
+
+        for mirror in mirrors {
+            if let Err(e) = context.spawn(EndPortMirror { mirror }).join() {
+                tracing::error!("Couldn't end port mirror {mirror:?}: {e:?}");
+            }
+        }
+
+        let mut verified = true;
+        if let Some((host, _)) = host {
+            // takes the host off the booking networks, among the rest of cleaning it up
+            let _ignore = context
+                .spawn(CleanupHost {
+                    agg_id: self.agg_id,
+                    instance: self.instance,
+                    host_id: host,
+                })
+                .join();
+
+            verified = match context
+                .spawn(VerifyHost {
+                    agg_id: self.agg_id,
+                    instance: self.instance,
+                    host_id: host,
+                })
+                .join()
+            {
+                Ok(passed) => passed,
+                Err(e) => {
+                    tracing::error!("Couldn't verify host {host:?}: {e:?}");
+                    false
+                }
+            };
+        }
+
+        let mut client = new_client().await?;
+        let mut transaction = client.easy_transaction().await?;
+
+        let mut details = format!("{} was released", instance.config.hostname);
+        if let Some((host, handle)) = host {
+            Allocator::instance()
+                .deallocate_host(&mut transaction, handle, self.agg_id)
+                .await?;
+
+            if !verified {
+                if let Err(e) = hold_back(&mut transaction, self.agg_id, agg.lab, &[host]).await {
+                    tracing::error!(
+                        "Couldn't hold back host {host:?} that failed verification: {e:?}"
+                    );
+                }
+            }
+
+            let server_name = host.get(&mut transaction).await?.server_name.clone();
+            details = match verified {
+                true => format!("{details}, and {server_name} returned to the free pool"),
+                false => format!(
+                    "{details}, and {server_name} held back for maintenance after failing verification"
+                ),
+            };
+        }
+
+        // its logs, checkpoints and the like go with it
+        self.instance
+            .get(&mut transaction)
+            .await?
+            .delete(&mut transaction)
+            .await?;
+
+        AggregateSummary::refresh(&mut transaction, self.agg_id).await?;
+        if let Err(e) = BookingEvent::record(
+            &mut transaction,
+            self.agg_id,
+            BookingEventKind::InstanceReleased,
+            details,
+            self.released_by.clone(),
+        )
+        .await
+        {
+            tracing::warn!(
+                "Couldn't record release of {:?} from booking {:?}: {e:?}",
+                self.instance,
+                self.agg_id
+            );
+        }
+
+        transaction.commit().await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,231 @@
+//! Checks a host is fit for its next booking before it goes back to the free pool
+//!
+//! The host is netbooted into the verification ramdisk, which makes sure nothing from the
+//! last booking is left on its disks and that its BMC is set up like the lab's baseline,
+//! then reports back over the mailbox. A host that fails, or that never reports, is kept
+//! out of the free pool by [`CleanupAggregate`](super::CleanupAggregate).
+
+use common::prelude::{
+    serde_json,
+    tokio::time::{sleep, Duration},
+    tracing,
+};
+use config::settings;
+use dal::{new_client, AsEasyTransaction, FKey, NewRow, ID};
+use models::{
+    dashboard::{Aggregate, Instance, ProvEvent, StatusSentiment},
+    inventory::{BootTo, Host, HostVerification, VerificationReport},
+};
+use serde::{Deserialize, Serialize};
+use tascii::prelude::*;
+
+use crate::{
+    deploy_booking::{
+        cobbler_set_config::CobblerSetConfiguration,
+        configure_networking::ConfigureNetworking,
+        net_config::{empty_network_config, mgmt_network_config},
+        set_boot::SetBoot,
+        set_host_power_state::SetPower,
+    },
+    resource_management::{cobbler::CobblerConfig, mailbox::Mailbox},
+    retry_for,
+    utils::status_feed,
+};
+
+pub(super) fn verification_timeout() -> Duration {
+    let minutes = settings()
+        .verification
+        .as_ref()
+        .map(|v| v.timeout_minutes)
+        .unwrap_or(45);
+
+    Duration::from_secs(minutes * 60)
+}
+
+async fn log(instance: FKey<Instance>, headline: &str, details: &str, sentiment: StatusSentiment) {
+    if let Err(e) =
+        status_feed::log_committing(instance, ProvEvent::new(headline, details), Some(sentiment))
+            .await
+    {
+        tracing::error!("Couldn't log verification status of {instance:?}: {e:?}");
+    }
+}
+
+#[derive(Debug, Clone, Hash, Serialize, Deserialize)]
+pub struct VerifyHost {
+    pub agg_id: FKey<Aggregate>,
+    pub instance: FKey<Instance>,
+    pub host_id: FKey<Host>,
+}
+
+impl VerifyHost {
+    /// Netboots the host into the verification ramdisk and waits for its report
+    async fn verify(
+        &self,
+        context: &Context,
+        verification: &config::VerificationConfig,
+        dynamic: bool,
+    ) -> Result<VerificationReport, TaskError> {
+        let mut waiter = Mailbox::set_endpoint_hook(self.instance, "verification").await?;
+
+        // cleanup took the host off every network, but it needs mgmt to netboot
+        if dynamic {
+            let mut client = new_client().await?;
+            let mut transaction = client.easy_transaction().await?;
+            let net_config = mgmt_network_config(self.host_id, &mut transaction).await;
+            transaction.commit().await?;
+
+            context.spawn(ConfigureNetworking { net_config }).join()?;
+        }
+
+        let cobbler_config_jh = context.spawn(CobblerSetConfiguration {
+            host_id: self.host_id,
+            config: CobblerConfig::new_verification_config(verification, waiter.endpoint()),
+            endpoint: waiter.endpoint(),
+        });
+
+        retry_for(
+            SetBoot {
+                host_id: self.host_id,
+                persistent: true,
+                boot_to: BootTo::Network,
+            },
+            context,
+            5,
+            10,
+        )?;
+        sleep(Duration::from_secs(2)).await;
+        retry_for(SetPower::off(self.host_id), context, 5, 10)?;
+        cobbler_config_jh.join()?;
+        retry_for(SetPower::on(self.host_id), context, 5, 10)?;
+
+        let reply = waiter
+            .wait_next(verification_timeout())
+            .map_err(|e| TaskError::Reason(format!("no word from the ramdisk: {e:?}")))?;
+
+        serde_json::from_str(reply.msg.message.trim()).map_err(|e| {
+            TaskError::Reason(format!(
+                "the ramdisk sent a report that couldn't be read ({e}): {}",
+                reply.msg.message
+            ))
+        })
+    }
+
+    /// Powers the host back down and takes it off mgmt again, however verifying it went
+    async fn settle(&self, context: &Context, dynamic: bool) -> Result<(), TaskError> {
+        retry_for(SetPower::off(self.host_id), context, 5, 10)?;
+
+        if dynamic {
+            let mut client = new_client().await?;
+            let mut transaction = client.easy_transaction().await?;
+            let net_config = empty_network_config(self.host_id, &mut transaction).await;
+            transaction.commit().await?;
+
+            context.spawn(ConfigureNetworking { net_config }).join()?;
+        }
+
+        Ok(())
+    }
+}
+
+tascii::mark_task!(VerifyHost);
+impl AsyncRunnable for VerifyHost {
+    /// Whether the host passed, and so can go back to the free pool
+    type Output = bool;
+
+    async fn run(&mut self, context: &Context) -> Result<Self::Output, TaskError> {
+        let Some(verification) = settings().verification.clone() else {
+            // nothing to check hosts with, so cleanup is taken at its word
+            return Ok(true);
+        };
+
+        let mut client = new_client().await?;
+        let mut transaction = client.easy_transaction().await?;
+        let dynamic = self
+            .agg_id
+            .get(&mut transaction)
+            .await?
+            .lab
+            .get(&mut transaction)
+            .await?
+            .is_dynamic;
+        transaction.commit().await?;
+
+        log(
+            self.instance,
+            "Verifying Host",
+            "host is being checked for leftover data and BMC settings before it's released",
+            StatusSentiment::InProgress,
+        )
+        .await;
+
+        let verified = self.verify(context, &verification, dynamic).await;
+        let settled = self.settle(context, dynamic).await;
+
+        // a host that couldn't be looked over isn't known to be fit either
+        let report = match (verified, settled) {
+            (Ok(report), Ok(())) => report,
+            (Ok(report), Err(e)) => VerificationReport {
+                findings: [
+                    report.findings,
+                    vec![format!("couldn't be powered down after: {e:?}")],
+                ]
+                .concat(),
+                ..report
+            },
+            (Err(e), _) => VerificationReport {
+                disks_wiped: false,
+                bmc_matches_baseline: false,
+                findings: vec![format!("couldn't be verified: {e:?}")],
+            },
+        };
+
+        let verification = HostVerification::new(self.host_id, self.agg_id, report);
+        let passed = verification.passed;
+        let findings = verification.findings.join("; ");
+
+        let mut transaction = client.easy_transaction().await?;
+        NewRow::new(verification).insert(&mut transaction).await?;
+        transaction.commit().await?;
+
+        if passed {
+            log(
+                self.instance,
+                "Host Verified",
+                "host was wiped and its BMC matches the lab baseline",
+                StatusSentiment::Succeeded,
+            )
+            .await;
+        } else {
+            log(
+                self.instance,
+                "Host Failed Verification",
+                &format!("host will be held back from the free pool: {findings}"),
+                StatusSentiment::Degraded,
+            )
+            .await;
+        }
+
+        Ok(passed)
+    }
+
+    fn summarize(&self, id: ID) -> String {
+        format!(
+            "VerifyHost task with id {id} verifying {:?} after cleanup of {:?}",
+            self.host_id, self.agg_id
+        )
+    }
+
+    fn identifier() -> TaskIdentifier {
+        TaskIdentifier::named("VerifyHostTask").versioned(1)
+    }
+
+    fn timeout() -> std::time::Duration {
+        verification_timeout() + std::time::Duration::from_secs(15 * 60)
+    }
+
+    fn retry_count(&self) -> usize {
+        // a failed verification is recorded rather than raised, so retrying only repeats it
+        0
+    }
+}
@@ -0,0 +1,255 @@
+//! Verifies after network provisioning that none of a booking's networks
+//! are shared with, or reachable from, any other live booking
+//!
+//! Besides what the database says, the vlans each switch port of a live booking's
+//! hosts really carries are read off the switches. A port of the booking may not carry
+//! a vlan of another booking, and no port of another booking may carry one of its vlans.
+//! If any switch can't be read nothing is attested, since the audit would be incomplete.
+
+use std::collections::HashMap;
+
+use common::prelude::{chrono::Utc, tracing};
+use dal::{new_client, AsEasyTransaction, DBTable, EasyTransaction, FKey, NewRow};
+use models::{
+    allocator::{Allocation, ResourceHandle},
+    dashboard::{Aggregate, IsolationAttestation, IsolationFinding, NetworkAssignmentMap},
+    inventory::Switch,
+};
+use serde::{Deserialize, Serialize};
+use tascii::prelude::*;
+
+use crate::resource_management::reconciler::switch_port_vlans;
+
+/// A switch port that one of the hosts of a live booking is cabled to
+struct BookedPort {
+    aggregate: FKey<Aggregate>,
+    hostname: String,
+    switch_port: String,
+}
+
+/// The switch ports of the hosts of every given booking, by switch
+async fn booked_ports(
+    t: &mut EasyTransaction<'_>,
+    aggregates: &[FKey<Aggregate>],
+) -> Result<HashMap<FKey<Switch>, Vec<BookedPort>>, anyhow::Error> {
+    let mut ports: HashMap<FKey<Switch>, Vec<BookedPort>> = HashMap::new();
+
+    for &agg in aggregates {
+        for instance in agg.get(t).await?.instances(t).await? {
+            let Some(host) = instance.linked_host else {
+                continue;
+            };
+
+            for port in host.get(t).await?.ports(t).await? {
+                let Some(sp) = port.switchport else {
+                    continue;
+                };
+                let sp = sp.get(t).await?;
+
+                ports.entry(sp.for_switch).or_default().push(BookedPort {
+                    aggregate: agg,
+                    hostname: instance.config.hostname.clone(),
+                    switch_port: sp.name.clone(),
+                });
+            }
+        }
+    }
+
+    Ok(ports)
+}
+
+#[derive(Debug, Clone, Hash, Serialize, Deserialize)]
+pub struct AuditNetworkIsolation {
+    pub aggregate: FKey<Aggregate>,
+}
+
+tascii::mark_task!(AuditNetworkIsolation);
+impl AsyncRunnable for AuditNetworkIsolation {
+    /// The findings that were recorded in the attestation, empty if the booking is isolated
+    type Output = Vec<IsolationFinding>;
+
+    async fn run(&mut self, _context: &Context) -> Result<Self::Output, TaskError> {
+        let mut client = new_client().await.unwrap();
+        let mut transaction = client.easy_transaction().await.unwrap();
+
+        let agg = self.aggregate.get(&mut transaction).await?;
+        let netmap = agg.vlans.get(&mut transaction).await?;
+
+        // every other booking that is still holding onto its networks
+        let mut others: Vec<(FKey<Aggregate>, NetworkAssignmentMap)> = Vec::new();
+        for other in Aggregate::select()
+            .where_field("deleted")
+            .equals(false)
+            .run(&mut transaction)
+            .await?
+        {
+            if other.id == agg.id || other.state.ended() {
+                continue;
+            }
+
+            if let Ok(map) = other.vlans.get(&mut transaction).await {
+                others.push((other.id, map.into_inner()));
+            }
+        }
+
+        let mut findings = Vec::new();
+
+        // the network each vlan is assigned to, ours and those of every other booking
+        let mut ours: HashMap<i16, String> = HashMap::new();
+        let mut theirs: HashMap<i16, (FKey<Aggregate>, String)> = HashMap::new();
+        for (other, map) in others.iter() {
+            for (network, vlan) in map.networks.iter() {
+                let network = network.get(&mut transaction).await?.name.clone();
+                theirs.insert(vlan.get(&mut transaction).await?.vlan_id, (*other, network));
+            }
+        }
+
+        for (network, vlan) in netmap.networks.iter() {
+            let network = network.get(&mut transaction).await?;
+            let vlan = vlan.get(&mut transaction).await?;
+            ours.insert(vlan.vlan_id, network.name.clone());
+
+            // the vlan must not be allocated to anyone but us
+            let handle = ResourceHandle::handle_for_vlan(&mut transaction, vlan.id).await?;
+            for allocation in Allocation::find(&mut transaction, handle.id, false).await? {
+                if allocation.for_aggregate != Some(agg.id) {
+                    findings.push(IsolationFinding {
+                        network: network.name.clone(),
+                        vlan_id: Some(vlan.vlan_id),
+                        detail: format!(
+                            "vlan is also allocated to aggregate {:?}",
+                            allocation.for_aggregate.map(|a| a.into_id())
+                        ),
+                    });
+                }
+            }
+
+            // and no other booking may have the vlan assigned to one of its networks
+            for (other, map) in others.iter() {
+                if map.networks.values().any(|v| *v == vlan.id) {
+                    findings.push(IsolationFinding {
+                        network: network.name.clone(),
+                        vlan_id: Some(vlan.vlan_id),
+                        detail: format!(
+                            "vlan is also assigned to a network of aggregate {:?}",
+                            other.into_id()
+                        ),
+                    });
+                }
+            }
+        }
+
+        // the other way around, none of our hosts may be connected to a network we don't own
+        for instance in agg.instances(&mut transaction).await? {
+            for bondgroup in instance.config.connections.iter() {
+                for connection in bondgroup.connects_to.iter() {
+                    if !netmap.networks.contains_key(&connection.network) {
+                        let network = connection.network.get(&mut transaction).await?;
+                        findings.push(IsolationFinding {
+                            network: network.name.clone(),
+                            vlan_id: None,
+                            detail: format!(
+                                "host {} connects to a network that is not assigned to this booking",
+                                instance.config.hostname
+                            ),
+                        });
+                    }
+                }
+            }
+        }
+
+        let live: Vec<FKey<Aggregate>> = std::iter::once(agg.id)
+            .chain(others.iter().map(|(other, _)| *other))
+            .collect();
+        let mut switches = Vec::new();
+        for (switch, ports) in booked_ports(&mut transaction, &live).await? {
+            let switch = switch.get(&mut transaction).await?.into_inner();
+            let os = match switch.switch_os {
+                Some(os) => os.get(&mut transaction).await?.os_type.clone(),
+                None => String::new(),
+            };
+            switches.push((switch, os, ports));
+        }
+
+        transaction.commit().await?;
+
+        // what the switches really carry, which is what keeps bookings apart
+        for (switch, os, ports) in switches {
+            let names = ports.iter().map(|p| p.switch_port.clone()).collect();
+            let actual = switch_port_vlans(&switch, &os, names).await.map_err(|e| {
+                TaskError::Reason(format!(
+                    "couldn't read the vlans of switch {}, so isolation can't be attested: {e}",
+                    switch.name
+                ))
+            })?;
+
+            for port in ports {
+                let Some(carried) = actual.get(&port.switch_port) else {
+                    continue;
+                };
+
+                for vlan_id in carried.native.iter().chain(carried.tagged.iter()) {
+                    if port.aggregate == agg.id {
+                        if let Some((other, network)) = theirs.get(vlan_id) {
+                            findings.push(IsolationFinding {
+                                network: network.clone(),
+                                vlan_id: Some(*vlan_id),
+                                detail: format!(
+                                    "{} {} of host {} carries a vlan of aggregate {:?}",
+                                    switch.name,
+                                    port.switch_port,
+                                    port.hostname,
+                                    other.into_id()
+                                ),
+                            });
+                        }
+                    } else if let Some(network) = ours.get(vlan_id) {
+                        findings.push(IsolationFinding {
+                            network: network.clone(),
+                            vlan_id: Some(*vlan_id),
+                            detail: format!(
+                                "{} {} of host {} of aggregate {:?} carries the vlan",
+                                switch.name,
+                                port.switch_port,
+                                port.hostname,
+                                port.aggregate.into_id()
+                            ),
+                        });
+                    }
+                }
+            }
+        }
+
+        tracing::info!(
+            "Isolation audit of aggregate {:?} made {} finding(s)",
+            agg.id,
+            findings.len()
+        );
+
+        let mut client = new_client().await?;
+        let mut transaction = client.easy_transaction().await?;
+
+        NewRow::new(IsolationAttestation {
+            id: FKey::new_id_dangling(),
+            aggregate: agg.id,
+            time: Utc::now(),
+            passed: findings.is_empty(),
+            findings: findings.clone(),
+        })
+        .insert(&mut transaction)
+        .await?;
+
+        transaction.commit().await?;
+
+        Ok(findings)
+    }
+
+    fn identifier() -> TaskIdentifier {
+        TaskIdentifier::named("AuditNetworkIsolationTask").versioned(1)
+    }
+
+    /// Every switch with a booked host on it is read, one after the other
+    fn timeout() -> std::time::Duration {
+        std::time::Duration::from_secs_f64(600.0)
+    }
+}
@@ -0,0 +1,256 @@
+//! BMCs managed with `ipmitool` over IPMI's lanplus interface
+
+use common::prelude::tracing::{debug, error, info, warn};
+use models::inventory::BootTo;
+use std::{process::Output, str};
+use tokio::{process::Command, time::Duration};
+
+use super::{Bmc, EventLogSummary, SensorKind, SensorReading, SensorStatus};
+use crate::{
+    deploy_booking::set_host_power_state::{HostConfig, PowerAction, PowerState, PowerStateError},
+    utils::resilience::{self, Device},
+};
+
+pub struct Ipmi<'a>(pub &'a HostConfig);
+
+impl Ipmi<'_> {
+    pub(crate) fn command(&self) -> Command {
+        let config = self.0;
+
+        let mut command = Command::new("ipmitool");
+        command
+            .args([
+                "-I",
+                "lanplus",
+                "-C",
+                "3",
+                "-H",
+                &config.fqdn,
+                "-U",
+                &config.user,
+                "-P",
+                &config.password,
+            ])
+            .kill_on_drop(true);
+
+        command
+    }
+
+    /// Runs `ipmitool` with `args`, failing if it exits with an error
+    async fn run(&self, args: &[&str]) -> Result<Output, PowerStateError> {
+        resilience::call(Device::bmc(&self.0.fqdn), || self.exec(args)).await
+    }
+
+    /// Like [`Self::run`], for commands that aren't safe to send twice
+    async fn run_once(&self, args: &[&str]) -> Result<Output, PowerStateError> {
+        resilience::call_once(Device::bmc(&self.0.fqdn), || self.exec(args)).await
+    }
+
+    async fn exec(&self, args: &[&str]) -> Result<Output, PowerStateError> {
+        let output = self
+            .command()
+            .args(args)
+            .output()
+            .await
+            .map_err(|e| PowerStateError::CommandExecutionFailed(e.to_string()))?;
+
+        if !output.status.success() {
+            let stderr = str::from_utf8(&output.stderr)
+                .map_err(|e| PowerStateError::Utf8Error(e.to_string()))?;
+            error!("IPMI command failed to execute properly");
+            return Err(PowerStateError::CommandNonZeroExitStatus(
+                output.status.code().expect("Expected exit code"),
+                stderr.into(),
+            ));
+        }
+
+        Ok(output)
+    }
+}
+
+impl Bmc for Ipmi<'_> {
+    async fn power_state(&self) -> Result<PowerState, PowerStateError> {
+        let output = self.run(&["chassis", "power", "status"]).await?;
+
+        let output_str = str::from_utf8(&output.stdout)
+            .map_err(|e| PowerStateError::Utf8Error(e.to_string()))?;
+
+        debug!("Successfully got chassis power status: {}", output_str);
+
+        match output_str.trim() {
+            "Chassis Power is on" => Ok(PowerState::On),
+            "Chassis Power is off" => Ok(PowerState::Off),
+            _ => {
+                warn!(
+                    "IPMI get host power status command had unexpected output: {}",
+                    output_str
+                );
+                Err(PowerStateError::UnknownPowerState(output_str.into()))
+            }
+        }
+    }
+
+    async fn power(&self, action: PowerAction) -> Result<(), PowerStateError> {
+        let args = ["chassis", "power", action.ipmi_command()];
+        match action.repeatable() {
+            true => self.run(&args).await?,
+            false => self.run_once(&args).await?,
+        };
+
+        Ok(())
+    }
+
+    async fn set_boot_device(
+        &self,
+        boot_to: BootTo,
+        persistent: bool,
+    ) -> Result<(), PowerStateError> {
+        let bdev = match boot_to {
+            BootTo::Network => "pxe",
+            BootTo::Disk | BootTo::SpecificDisk => "disk",
+        };
+
+        info!("note: going to set bootdev in ipmi multiple times so it really sticks");
+        for _i in 0..2 {
+            let mut ipmi_cmd = self.command();
+            ipmi_cmd.args(["chassis", "bootdev", bdev]);
+
+            if let BootTo::Network = boot_to {
+                ipmi_cmd.arg("set").arg("force_pxe").arg("true");
+            } else if let BootTo::Disk = boot_to {
+                ipmi_cmd.arg("set").arg("force_disk").arg("true");
+            }
+
+            ipmi_cmd.arg(if persistent {
+                "options=efiboot,persistent"
+            } else {
+                "options=efiboot"
+            });
+
+            let output = ipmi_cmd
+                .output()
+                .await
+                .map_err(|e| PowerStateError::CommandExecutionFailed(e.to_string()))?;
+            tokio::time::sleep(Duration::from_secs(10)).await;
+            info!(
+                "IPMI set bootdev returns output: {}",
+                String::from_utf8_lossy(&output.stdout)
+            );
+        }
+
+        // todo - extract error code from output. If error, then fail task
+
+        Ok(())
+    }
+
+    async fn boot_into_setup(&self) -> Result<(), PowerStateError> {
+        self.run(&["chassis", "bootdev", "bios", "options=efiboot"])
+            .await?;
+
+        Ok(())
+    }
+
+    async fn sensors(&self) -> Result<Vec<SensorReading>, PowerStateError> {
+        let output = self.run(&["sdr", "list"]).await?;
+
+        Ok(parse_sdr(&String::from_utf8_lossy(&output.stdout)))
+    }
+
+    async fn event_log(&self) -> Result<EventLogSummary, PowerStateError> {
+        let output = self.run(&["sel", "list"]).await?;
+
+        Ok(parse_sel(&String::from_utf8_lossy(&output.stdout)))
+    }
+}
+
+/// The sensors in the output of `ipmitool sdr list`
+pub fn parse_sdr(sdr: &str) -> Vec<SensorReading> {
+    sdr.lines()
+        .filter_map(|line| {
+            let mut columns = line.split('|').map(str::trim);
+            let (name, reading, status) = (columns.next()?, columns.next()?, columns.next()?);
+
+            let status = match status {
+                "ok" => SensorStatus::Ok,
+                "nc" => SensorStatus::Warning,
+                "cr" => SensorStatus::Critical,
+                "nr" => SensorStatus::NonRecoverable,
+                _ => SensorStatus::Unavailable,
+            };
+
+            Some(SensorReading {
+                name: name.to_owned(),
+                kind: SensorKind::of_sdr(name, reading),
+                reading: reading.to_owned(),
+                status,
+            })
+        })
+        .collect()
+}
+
+/// The entries in the output of `ipmitool sel list`, like
+/// `1 | 04/01/2024 | 10:00:00 | Power Supply #0x51 | Failure detected | Asserted`
+pub fn parse_sel(sel: &str) -> EventLogSummary {
+    let mut summary = EventLogSummary::default();
+
+    for line in sel.lines() {
+        let Some(sensor) = line.split('|').nth(3) else {
+            continue;
+        };
+        // the sensor is its type followed by its number, like `Power Supply #0x51`
+        let sensor_type = sensor.split('#').next().unwrap_or_default().trim();
+
+        summary.entries += 1;
+        *summary
+            .by_sensor_type
+            .entry(sensor_type.to_owned())
+            .or_default() += 1;
+    }
+
+    summary
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sensor_kinds() {
+        let sdr = "CPU1 Temp        | 45 degrees C      | ok\n\
+                   Fan3             | 4200 RPM          | ok\n\
+                   PS1 Input Volts  | 230 Volts         | ok\n\
+                   PS2 Status       | 0x00              | nr\n\
+                   Fan Redundancy   | 0x01              | ok\n\
+                   Intrusion        | 0x00              | ok\n";
+
+        let kinds: Vec<_> = parse_sdr(sdr).iter().map(|r| r.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                SensorKind::Temperature,
+                SensorKind::Fan,
+                SensorKind::Voltage,
+                SensorKind::PowerSupply,
+                SensorKind::Fan,
+                SensorKind::Other,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_sel() {
+        let sel = "   1 | 04/01/2024 | 10:00:00 | Power Supply #0x51 | Failure detected | Asserted\n\
+                      2 | 04/01/2024 | 10:02:13 | Temperature #0x30 | Upper Critical going high | Asserted\n\
+                      3 | 04/02/2024 | 08:15:40 | Power Supply #0x52 | Power Supply AC lost | Asserted\n";
+
+        let summary = parse_sel(sel);
+        assert_eq!(summary.entries, 3);
+        assert_eq!(summary.by_sensor_type["Power Supply"], 2);
+        assert_eq!(summary.by_sensor_type["Temperature"], 1);
+
+        assert_eq!(
+            parse_sel("SEL has no entries\n"),
+            EventLogSummary::default()
+        );
+    }
+}
@@ -0,0 +1,272 @@
+//! Talking to the BMCs of hosts, over IPMI or over Redfish for the newer hosts that only
+//! handle Redfish reliably
+//!
+//! Which one a host is managed over is its `bmc_protocol` in the inventory. Powering hosts,
+//! choosing what they boot from and reading their sensors all go through [`Bmc`], so
+//! the rest of LibLaaS doesn't have to care which one a host uses.
+
+use common::prelude::{
+    chrono::{DateTime, Duration, Utc},
+    dashmap::DashMap,
+    lazy_static::lazy_static,
+    tracing,
+};
+use models::inventory::{BmcProtocol, BootTo};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::deploy_booking::set_host_power_state::{
+    HostConfig, PowerAction, PowerState, PowerStateError,
+};
+
+pub mod ipmi;
+pub mod redfish;
+
+pub use ipmi::Ipmi;
+pub use redfish::Redfish;
+
+#[allow(async_fn_in_trait)]
+pub trait Bmc {
+    async fn power_state(&self) -> Result<PowerState, PowerStateError>;
+
+    /// Sends the action to the BMC, without waiting for the host to get to the state
+    /// the action settles to
+    async fn power(&self, action: PowerAction) -> Result<(), PowerStateError>;
+
+    /// What the host boots from, on every boot from now on if `persistent` and only on
+    /// the next one otherwise
+    async fn set_boot_device(
+        &self,
+        boot_to: BootTo,
+        persistent: bool,
+    ) -> Result<(), PowerStateError>;
+
+    /// Has the host go into its BIOS or UEFI setup on its next boot only
+    async fn boot_into_setup(&self) -> Result<(), PowerStateError>;
+
+    async fn sensors(&self) -> Result<Vec<SensorReading>, PowerStateError>;
+
+    async fn event_log(&self) -> Result<EventLogSummary, PowerStateError>;
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, PartialEq)]
+pub struct SensorReading {
+    pub name: String,
+    pub kind: SensorKind,
+    /// The reading with its unit, as the BMC gave it
+    pub reading: String,
+    pub status: SensorStatus,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, JsonSchema, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SensorStatus {
+    Ok,
+    /// Past a non-critical threshold
+    Warning,
+    Critical,
+    NonRecoverable,
+    /// The sensor gave no reading, like for an empty PSU bay
+    Unavailable,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, JsonSchema, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SensorKind {
+    Temperature,
+    Fan,
+    Voltage,
+    PowerSupply,
+    Other,
+}
+
+impl SensorKind {
+    /// The kind of an IPMI sensor, which `ipmitool sdr list` only gives away through the
+    /// unit of its reading and its name
+    pub fn of_sdr(name: &str, reading: &str) -> Self {
+        let name = name.to_lowercase();
+
+        if reading.ends_with("degrees C") {
+            Self::Temperature
+        } else if reading.ends_with("RPM") {
+            Self::Fan
+        } else if reading.ends_with("Volts") {
+            Self::Voltage
+        } else if name.starts_with("ps") || name.contains("psu") || name.contains("power supply") {
+            Self::PowerSupply
+        } else if name.contains("temp") {
+            Self::Temperature
+        } else if name.contains("fan") {
+            Self::Fan
+        } else {
+            Self::Other
+        }
+    }
+}
+
+/// The entries in a BMC's system event log, counted by the type of sensor that logged them
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema, PartialEq, Eq)]
+pub struct EventLogSummary {
+    pub entries: usize,
+    pub by_sensor_type: BTreeMap<String, usize>,
+}
+
+/// Everything the BMC of a host reports about its hardware, for telling a host that is
+/// overheating or losing a PSU apart from one that was misprovisioned
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct SensorReport {
+    #[schemars(with = "String")]
+    pub collected: DateTime<Utc>,
+    pub temperatures: Vec<SensorReading>,
+    pub fans: Vec<SensorReading>,
+    pub power_supplies: Vec<SensorReading>,
+    /// Voltages and whatever else the BMC has sensors for
+    pub other: Vec<SensorReading>,
+    /// None if the BMC's event log couldn't be read
+    pub event_log: Option<EventLogSummary>,
+}
+
+impl SensorReport {
+    pub fn new(
+        collected: DateTime<Utc>,
+        readings: Vec<SensorReading>,
+        event_log: Option<EventLogSummary>,
+    ) -> Self {
+        let mut report = Self {
+            collected,
+            temperatures: Vec::new(),
+            fans: Vec::new(),
+            power_supplies: Vec::new(),
+            other: Vec::new(),
+            event_log,
+        };
+
+        for reading in readings {
+            match reading.kind {
+                SensorKind::Temperature => report.temperatures.push(reading),
+                SensorKind::Fan => report.fans.push(reading),
+                SensorKind::PowerSupply => report.power_supplies.push(reading),
+                SensorKind::Voltage | SensorKind::Other => report.other.push(reading),
+            }
+        }
+
+        report
+    }
+}
+
+/// How long a sensor report is handed out again for, since BMCs take a while to read out
+/// every sensor and someone chasing a flaky host tends to refresh a lot
+pub const SENSOR_REPORT_TTL_SECONDS: i64 = 30;
+
+lazy_static! {
+    static ref SENSOR_REPORTS: DashMap<String, SensorReport> = DashMap::new();
+}
+
+/// The sensors and event log of the host, read from its BMC unless they were read less than
+/// [`SENSOR_REPORT_TTL_SECONDS`] ago
+pub async fn sensor_report(config: &HostConfig) -> Result<SensorReport, PowerStateError> {
+    let cached = SENSOR_REPORTS.get(&config.fqdn).map(|r| r.clone());
+    if let Some(report) =
+        cached.filter(|r| Utc::now() - r.collected < Duration::seconds(SENSOR_REPORT_TTL_SECONDS))
+    {
+        return Ok(report);
+    }
+
+    let bmc = config.bmc();
+    let readings = bmc.sensors().await?;
+    // plenty of BMCs keep no event log worth reading, the sensors are what matter most
+    let event_log = match bmc.event_log().await {
+        Ok(log) => Some(log),
+        Err(e) => {
+            tracing::debug!("Couldn't read the event log of {}: {e:?}", config.fqdn);
+            None
+        }
+    };
+
+    let report = SensorReport::new(Utc::now(), readings, event_log);
+    SENSOR_REPORTS.insert(config.fqdn.clone(), report.clone());
+
+    Ok(report)
+}
+
+impl SensorStatus {
+    /// Whether the sensor is past a threshold the host shouldn't be run past
+    pub fn is_alarm(&self) -> bool {
+        matches!(self, Self::Critical | Self::NonRecoverable)
+    }
+
+    /// The short form `ipmitool sdr list` gives the status in
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::Ok => "ok",
+            Self::Warning => "nc",
+            Self::Critical => "cr",
+            Self::NonRecoverable => "nr",
+            Self::Unavailable => "ns",
+        }
+    }
+}
+
+/// The BMC of a host, over whichever protocol the host is managed with
+pub enum HostBmc<'a> {
+    Ipmi(Ipmi<'a>),
+    Redfish(Redfish<'a>),
+}
+
+impl<'a> HostBmc<'a> {
+    pub fn of(config: &'a HostConfig) -> Self {
+        match config.protocol {
+            BmcProtocol::Ipmi => Self::Ipmi(Ipmi(config)),
+            BmcProtocol::Redfish => Self::Redfish(Redfish(config)),
+        }
+    }
+}
+
+impl Bmc for HostBmc<'_> {
+    async fn power_state(&self) -> Result<PowerState, PowerStateError> {
+        match self {
+            Self::Ipmi(b) => b.power_state().await,
+            Self::Redfish(b) => b.power_state().await,
+        }
+    }
+
+    async fn power(&self, action: PowerAction) -> Result<(), PowerStateError> {
+        match self {
+            Self::Ipmi(b) => b.power(action).await,
+            Self::Redfish(b) => b.power(action).await,
+        }
+    }
+
+    async fn set_boot_device(
+        &self,
+        boot_to: BootTo,
+        persistent: bool,
+    ) -> Result<(), PowerStateError> {
+        match self {
+            Self::Ipmi(b) => b.set_boot_device(boot_to, persistent).await,
+            Self::Redfish(b) => b.set_boot_device(boot_to, persistent).await,
+        }
+    }
+
+    async fn boot_into_setup(&self) -> Result<(), PowerStateError> {
+        match self {
+            Self::Ipmi(b) => b.boot_into_setup().await,
+            Self::Redfish(b) => b.boot_into_setup().await,
+        }
+    }
+
+    async fn sensors(&self) -> Result<Vec<SensorReading>, PowerStateError> {
+        match self {
+            Self::Ipmi(b) => b.sensors().await,
+            Self::Redfish(b) => b.sensors().await,
+        }
+    }
+
+    async fn event_log(&self) -> Result<EventLogSummary, PowerStateError> {
+        match self {
+            Self::Ipmi(b) => b.event_log().await,
+            Self::Redfish(b) => b.event_log().await,
+        }
+    }
+}
@@ -0,0 +1,512 @@
+//! BMCs managed over Redfish, the REST API newer BMCs come with
+//!
+//! Hosts are expected to have a single system behind their BMC, which is the first member
+//! of `/redfish/v1/Systems`. BMCs come with self-signed certificates, so they aren't checked.
+
+use common::prelude::{
+    reqwest::{Client, Method},
+    serde_json::{self, json, Value},
+    tracing,
+};
+use models::inventory::BootTo;
+use std::time::Duration;
+
+use super::{Bmc, EventLogSummary, SensorKind, SensorReading, SensorStatus};
+use crate::{
+    deploy_booking::set_host_power_state::{HostConfig, PowerAction, PowerState, PowerStateError},
+    utils::resilience::{self, Device},
+};
+
+pub struct Redfish<'a>(pub &'a HostConfig);
+
+impl Redfish<'_> {
+    async fn request(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<&Value>,
+    ) -> Result<Value, PowerStateError> {
+        let config = self.0;
+        let url = format!("https://{}{path}", config.fqdn);
+
+        let send = || {
+            let (method, url) = (method.clone(), url.clone());
+            async move {
+                let client = Client::builder()
+                    .danger_accept_invalid_certs(true)
+                    .timeout(Duration::from_secs(30))
+                    .build()
+                    .map_err(|e| PowerStateError::RequestFailed(e.to_string()))?;
+
+                let mut request = client
+                    .request(method.clone(), &url)
+                    .basic_auth(&config.user, Some(&config.password));
+                if let Some(body) = body {
+                    request = request.json(body);
+                }
+
+                let response = request.send().await.map_err(|e| match e.is_connect() {
+                    true => PowerStateError::HostUnreachable(config.fqdn.clone()),
+                    false => PowerStateError::RequestFailed(format!("{method} {url}: {e}")),
+                })?;
+
+                let status = response.status();
+                let text = response
+                    .text()
+                    .await
+                    .map_err(|e| PowerStateError::RequestFailed(e.to_string()))?;
+                if !status.is_success() {
+                    return Err(PowerStateError::RequestFailed(format!(
+                        "{method} {url} returned {status}: {text}"
+                    )));
+                }
+
+                // actions and patches often answer with no body at all
+                Ok(serde_json::from_str(&text).unwrap_or(Value::Null))
+            }
+        };
+
+        // actions like ComputerSystem.Reset are posted, and may have gone through even
+        // if the BMC didn't answer, while reads and patches setting a value are safe to repeat
+        let device = Device::bmc(&config.fqdn);
+        match method == Method::POST {
+            true => resilience::call_once(device, send).await,
+            false => resilience::call(device, send).await,
+        }
+    }
+
+    async fn get(&self, path: &str) -> Result<Value, PowerStateError> {
+        self.request(Method::GET, path, None).await
+    }
+
+    /// The path of the system the BMC manages
+    async fn system(&self) -> Result<String, PowerStateError> {
+        let systems = self.get("/redfish/v1/Systems").await?;
+
+        first_member(&systems).ok_or(PowerStateError::RequestFailed(format!(
+            "{} doesn't manage any systems",
+            self.0.fqdn
+        )))
+    }
+
+    /// The path of the chassis the system sits in, which the sensors are under
+    async fn chassis(&self, system: &Value) -> Result<String, PowerStateError> {
+        if let Some(chassis) = system
+            .pointer("/Links/Chassis/0/@odata.id")
+            .and_then(Value::as_str)
+        {
+            return Ok(chassis.to_owned());
+        }
+
+        let chassis = self.get("/redfish/v1/Chassis").await?;
+        first_member(&chassis).ok_or(PowerStateError::RequestFailed(format!(
+            "{} doesn't have any chassis",
+            self.0.fqdn
+        )))
+    }
+}
+
+impl Bmc for Redfish<'_> {
+    async fn power_state(&self) -> Result<PowerState, PowerStateError> {
+        let system = self.get(&self.system().await?).await?;
+
+        parse_power_state(&system)
+    }
+
+    async fn power(&self, action: PowerAction) -> Result<(), PowerStateError> {
+        let path = format!("{}/Actions/ComputerSystem.Reset", self.system().await?);
+        let body = json!({ "ResetType": reset_type(action) });
+
+        self.request(Method::POST, &path, Some(&body)).await?;
+
+        Ok(())
+    }
+
+    async fn set_boot_device(
+        &self,
+        boot_to: BootTo,
+        persistent: bool,
+    ) -> Result<(), PowerStateError> {
+        let body = boot_override(boot_to, persistent);
+        tracing::info!("Setting Redfish boot override of {} to {body}", self.0.fqdn);
+
+        self.request(Method::PATCH, &self.system().await?, Some(&body))
+            .await?;
+
+        Ok(())
+    }
+
+    async fn boot_into_setup(&self) -> Result<(), PowerStateError> {
+        let body = override_to("BiosSetup", false);
+
+        self.request(Method::PATCH, &self.system().await?, Some(&body))
+            .await?;
+
+        Ok(())
+    }
+
+    async fn sensors(&self) -> Result<Vec<SensorReading>, PowerStateError> {
+        let system = self.get(&self.system().await?).await?;
+        let chassis = self.chassis(&system).await?;
+
+        let mut readings = parse_thermal(&self.get(&format!("{chassis}/Thermal")).await?);
+
+        // not every BMC reports power, the thermal sensors are what matter most
+        match self.get(&format!("{chassis}/Power")).await {
+            Ok(power) => readings.extend(parse_power(&power)),
+            Err(e) => tracing::debug!("{} reports no power sensors: {e}", self.0.fqdn),
+        }
+
+        Ok(readings)
+    }
+
+    async fn event_log(&self) -> Result<EventLogSummary, PowerStateError> {
+        let system = self.get(&self.system().await?).await?;
+
+        // some BMCs keep the event log under the system, others under the manager
+        let mut holders = vec![system.clone()];
+        if let Some(manager) = system
+            .pointer("/Links/ManagedBy/0/@odata.id")
+            .and_then(Value::as_str)
+        {
+            holders.push(self.get(manager).await?);
+        }
+
+        for holder in holders {
+            let Some(services) = holder
+                .pointer("/LogServices/@odata.id")
+                .and_then(Value::as_str)
+            else {
+                continue;
+            };
+
+            for path in members(&self.get(services).await?) {
+                let service = self.get(&path).await?;
+                if !is_sel(&path, &service) {
+                    continue;
+                }
+
+                let entries = service
+                    .pointer("/Entries/@odata.id")
+                    .and_then(Value::as_str)
+                    .map(str::to_owned)
+                    .unwrap_or(format!("{path}/Entries"));
+                return Ok(parse_log_entries(&self.get(&entries).await?));
+            }
+        }
+
+        Err(PowerStateError::RequestFailed(format!(
+            "{} has no system event log",
+            self.0.fqdn
+        )))
+    }
+}
+
+fn members(collection: &Value) -> Vec<String> {
+    collection
+        .get("Members")
+        .and_then(Value::as_array)
+        .map(|members| {
+            members
+                .iter()
+                .filter_map(|m| m.get("@odata.id").and_then(Value::as_str))
+                .map(str::to_owned)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn first_member(collection: &Value) -> Option<String> {
+    members(collection).into_iter().next()
+}
+
+fn is_sel(path: &str, service: &Value) -> bool {
+    service.get("LogEntryType").and_then(Value::as_str) == Some("SEL")
+        || path
+            .trim_end_matches('/')
+            .rsplit('/')
+            .next()
+            .is_some_and(|id| id.eq_ignore_ascii_case("sel"))
+}
+
+/// The entries of a log service's `Entries` collection
+///
+/// The count is taken from the collection, so it covers every entry, while the sensor
+/// types only cover the entries on the page that was read.
+pub fn parse_log_entries(entries: &Value) -> EventLogSummary {
+    let members = entries
+        .get("Members")
+        .and_then(Value::as_array)
+        .cloned()
+        .unwrap_or_default();
+
+    let mut summary = EventLogSummary {
+        entries: entries
+            .get("Members@odata.count")
+            .and_then(Value::as_u64)
+            .map_or(members.len(), |c| c as usize),
+        ..Default::default()
+    };
+    for entry in &members {
+        let sensor_type = entry
+            .get("SensorType")
+            .and_then(Value::as_str)
+            .unwrap_or("Other");
+        *summary
+            .by_sensor_type
+            .entry(sensor_type.to_owned())
+            .or_default() += 1;
+    }
+
+    summary
+}
+
+pub fn parse_power_state(system: &Value) -> Result<PowerState, PowerStateError> {
+    match system.get("PowerState").and_then(Value::as_str) {
+        Some("On") => Ok(PowerState::On),
+        Some("Off") => Ok(PowerState::Off),
+        // still getting there, so confirming a power action keeps waiting
+        Some("PoweringOn" | "PoweringOff") => Ok(PowerState::Unknown),
+        other => Err(PowerStateError::UnknownPowerState(format!("{other:?}"))),
+    }
+}
+
+/// The `ResetType` of `ComputerSystem.Reset` that does the action
+pub fn reset_type(action: PowerAction) -> &'static str {
+    match action {
+        PowerAction::On => "On",
+        PowerAction::Off => "ForceOff",
+        PowerAction::Cycle => "PowerCycle",
+        PowerAction::SoftShutdown => "GracefulShutdown",
+        PowerAction::Reset => "ForceRestart",
+    }
+}
+
+/// The patch of the system that boots it from `boot_to`
+///
+/// Redfish can't pick out one disk by its description like RIBCL can, so a specific disk
+/// boots whichever disk comes first in the boot order.
+pub fn boot_override(boot_to: BootTo, persistent: bool) -> Value {
+    let target = match boot_to {
+        BootTo::Network => "Pxe",
+        BootTo::Disk | BootTo::SpecificDisk => "Hdd",
+    };
+
+    override_to(target, persistent)
+}
+
+/// The patch of the system that boots it from the `BootSourceOverrideTarget` `target`
+fn override_to(target: &str, persistent: bool) -> Value {
+    json!({
+        "Boot": {
+            "BootSourceOverrideTarget": target,
+            "BootSourceOverrideEnabled": if persistent { "Continuous" } else { "Once" },
+            "BootSourceOverrideMode": "UEFI",
+        }
+    })
+}
+
+fn status_of(sensor: &Value, reading: Option<f64>) -> SensorStatus {
+    let state = sensor.pointer("/Status/State").and_then(Value::as_str);
+    if matches!(state, Some("Absent" | "Disabled" | "UnavailableOffline")) {
+        return SensorStatus::Unavailable;
+    }
+
+    let fatal = |threshold: &str| sensor.get(threshold).and_then(Value::as_f64);
+    let past_fatal = reading.is_some_and(|r| {
+        fatal("UpperThresholdFatal").is_some_and(|t| r >= t)
+            || fatal("LowerThresholdFatal").is_some_and(|t| r <= t)
+    });
+
+    // a failed PSU gives no reading, but still reports its health
+    match sensor.pointer("/Status/Health").and_then(Value::as_str) {
+        _ if past_fatal => SensorStatus::NonRecoverable,
+        Some("Critical") => SensorStatus::Critical,
+        Some("Warning") => SensorStatus::Warning,
+        _ if reading.is_none() => SensorStatus::Unavailable,
+        _ => SensorStatus::Ok,
+    }
+}
+
+fn readings_of(
+    resource: &Value,
+    list: &str,
+    kind: SensorKind,
+    reading_field: &str,
+    unit: impl Fn(&Value) -> String,
+) -> Vec<SensorReading> {
+    let Some(sensors) = resource.get(list).and_then(Value::as_array) else {
+        return Vec::new();
+    };
+
+    sensors
+        .iter()
+        .map(|sensor| {
+            let reading = sensor.get(reading_field).and_then(Value::as_f64);
+            SensorReading {
+                name: sensor
+                    .get("Name")
+                    .and_then(Value::as_str)
+                    .unwrap_or(list)
+                    .to_owned(),
+                kind,
+                reading: match reading {
+                    Some(r) => format!("{r} {}", unit(sensor)).trim_end().to_owned(),
+                    None => "no reading".to_owned(),
+                },
+                status: status_of(sensor, reading),
+            }
+        })
+        .collect()
+}
+
+/// The temperatures and fans of a chassis' `Thermal` resource
+pub fn parse_thermal(thermal: &Value) -> Vec<SensorReading> {
+    let mut readings = readings_of(
+        thermal,
+        "Temperatures",
+        SensorKind::Temperature,
+        "ReadingCelsius",
+        |_| "degrees C".to_owned(),
+    );
+    readings.extend(readings_of(
+        thermal,
+        "Fans",
+        SensorKind::Fan,
+        "Reading",
+        |fan| {
+            fan.get("ReadingUnits")
+                .and_then(Value::as_str)
+                .unwrap_or_default()
+                .to_owned()
+        },
+    ));
+
+    readings
+}
+
+/// The voltages and power supplies of a chassis' `Power` resource
+pub fn parse_power(power: &Value) -> Vec<SensorReading> {
+    let mut readings = readings_of(
+        power,
+        "Voltages",
+        SensorKind::Voltage,
+        "ReadingVolts",
+        |_| "Volts".to_owned(),
+    );
+    readings.extend(readings_of(
+        power,
+        "PowerSupplies",
+        SensorKind::PowerSupply,
+        "LastPowerOutputWatts",
+        |_| "Watts".to_owned(),
+    ));
+
+    readings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_power_state() {
+        let state = |s: &str| parse_power_state(&json!({ "PowerState": s }));
+
+        assert_eq!(state("On").unwrap(), PowerState::On);
+        assert_eq!(state("Off").unwrap(), PowerState::Off);
+        assert_eq!(state("PoweringOn").unwrap(), PowerState::Unknown);
+        assert!(parse_power_state(&json!({})).is_err());
+    }
+
+    #[test]
+    fn test_boot_override() {
+        assert_eq!(
+            boot_override(BootTo::Network, false),
+            json!({
+                "Boot": {
+                    "BootSourceOverrideTarget": "Pxe",
+                    "BootSourceOverrideEnabled": "Once",
+                    "BootSourceOverrideMode": "UEFI",
+                }
+            })
+        );
+        assert_eq!(
+            boot_override(BootTo::SpecificDisk, true)["Boot"]["BootSourceOverrideEnabled"],
+            "Continuous"
+        );
+    }
+
+    #[test]
+    fn test_parse_sensors() {
+        let thermal = json!({
+            "Temperatures": [
+                { "Name": "CPU1 Temp", "ReadingCelsius": 45.0, "Status": { "State": "Enabled", "Health": "OK" } },
+                { "Name": "CPU2 Temp", "ReadingCelsius": 98.0, "UpperThresholdFatal": 95.0, "Status": { "State": "Enabled", "Health": "Critical" } },
+                { "Name": "Inlet Temp", "ReadingCelsius": 41.0, "Status": { "State": "Enabled", "Health": "Critical" } },
+            ],
+            "Fans": [
+                { "Name": "Fan3", "Reading": 4200, "ReadingUnits": "RPM", "Status": { "State": "Enabled", "Health": "OK" } },
+                { "Name": "Fan4", "Status": { "State": "Absent" } },
+            ],
+        });
+        let power = json!({
+            "PowerSupplies": [
+                { "Name": "PS2", "Status": { "State": "Enabled", "Health": "Critical" } },
+            ],
+        });
+
+        let readings = parse_thermal(&thermal);
+        let summary: Vec<_> = readings
+            .iter()
+            .map(|r| (r.name.as_str(), r.reading.as_str(), r.status))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("CPU1 Temp", "45 degrees C", SensorStatus::Ok),
+                ("CPU2 Temp", "98 degrees C", SensorStatus::NonRecoverable),
+                ("Inlet Temp", "41 degrees C", SensorStatus::Critical),
+                ("Fan3", "4200 RPM", SensorStatus::Ok),
+                ("Fan4", "no reading", SensorStatus::Unavailable),
+            ]
+        );
+
+        let psu = &parse_power(&power)[0];
+        assert_eq!(psu.reading, "no reading");
+        assert_eq!(psu.kind, SensorKind::PowerSupply);
+        assert!(psu.status.is_alarm());
+    }
+
+    #[test]
+    fn test_parse_log_entries() {
+        let entries = json!({
+            "Members@odata.count": 57,
+            "Members": [
+                { "SensorType": "Power Supply / Converter", "Message": "Power supply input lost" },
+                { "SensorType": "Temperature", "Message": "Upper critical going high" },
+                { "SensorType": "Power Supply / Converter", "Message": "Power supply redundancy lost" },
+                { "Message": "Log cleared" },
+            ],
+        });
+
+        let summary = parse_log_entries(&entries);
+        assert_eq!(summary.entries, 57);
+        assert_eq!(summary.by_sensor_type["Power Supply / Converter"], 2);
+        assert_eq!(summary.by_sensor_type["Other"], 1);
+
+        assert_eq!(parse_log_entries(&json!({ "Members": [] })).entries, 0);
+        assert!(is_sel(
+            "/redfish/v1/Managers/iDRAC.Embedded.1/LogServices/Sel",
+            &json!({})
+        ));
+        assert!(is_sel(
+            "/redfish/v1/Managers/1/LogServices/Log1",
+            &json!({ "LogEntryType": "SEL" })
+        ));
+        assert!(!is_sel(
+            "/redfish/v1/Managers/1/LogServices/Lclog",
+            &json!({})
+        ));
+    }
+}
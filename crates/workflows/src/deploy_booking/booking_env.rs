@@ -0,0 +1,157 @@
+//! Settings a booking hands to the cloud-init and recipes of its hosts
+//!
+//! Each booking can carry a set of variables in its configuration. They are written to
+//! [`ENV_FILE`] on every host of the booking, ready to be sourced by recipes, and any
+//! `{{ env.NAME }}` in the cloud-init files of the booking is replaced by the value of
+//! `NAME`, so one recipe can serve every site that books it.
+
+use std::{collections::BTreeMap, fmt::Write};
+
+use thiserror::Error;
+
+/// Where the variables end up on each host, as `NAME='value'` lines
+pub const ENV_FILE: &str = "/etc/laas/booking.env";
+
+pub const MAX_VARS: usize = 64;
+pub const MAX_VALUE_LEN: usize = 4096;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum EnvError {
+    #[error("a booking can have at most {MAX_VARS} variables")]
+    TooMany,
+    #[error("{0:?} isn't a valid variable name, names are letters, digits and underscores and don't start with a digit")]
+    BadName(String),
+    #[error("the value of {0} is longer than {MAX_VALUE_LEN} characters")]
+    TooLong(String),
+    #[error("the value of {0} contains a null character")]
+    NullByte(String),
+}
+
+fn valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+pub fn validate(env: &BTreeMap<String, String>) -> Result<(), EnvError> {
+    if env.len() > MAX_VARS {
+        return Err(EnvError::TooMany);
+    }
+
+    for (name, value) in env {
+        if !valid_name(name) {
+            return Err(EnvError::BadName(name.clone()));
+        }
+        if value.chars().count() > MAX_VALUE_LEN {
+            return Err(EnvError::TooLong(name.clone()));
+        }
+        if value.contains('\0') {
+            return Err(EnvError::NullByte(name.clone()));
+        }
+    }
+
+    Ok(())
+}
+
+/// The contents of [`ENV_FILE`], with every value quoted so sourcing it can't run anything
+pub fn env_file(env: &BTreeMap<String, String>) -> String {
+    let mut file = String::new();
+    for (name, value) in env {
+        let _ = writeln!(file, "{name}='{}'", value.replace('\'', r"'\''"));
+    }
+
+    file
+}
+
+/// Replaces each `{{ env.NAME }}` in `data` by the value of `NAME`
+///
+/// Placeholders for variables the booking doesn't have are left as they are.
+pub fn substitute(data: &str, env: &BTreeMap<String, String>) -> String {
+    let mut out = String::with_capacity(data.len());
+    let mut rest = data;
+
+    while let Some(start) = rest.find("{{") {
+        let Some(len) = rest[start..].find("}}") else {
+            break;
+        };
+        let inner = rest[start + 2..start + len].trim();
+
+        out.push_str(&rest[..start]);
+        match inner.strip_prefix("env.").and_then(|name| env.get(name)) {
+            Some(value) => out.push_str(value),
+            None => out.push_str(&rest[start..start + len + 2]),
+        }
+        rest = &rest[start + len + 2..];
+    }
+    out.push_str(rest);
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn env(vars: &[(&str, &str)]) -> BTreeMap<String, String> {
+        vars.iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_validate() {
+        assert_eq!(validate(&env(&[("SITE", "unh"), ("_x1", "")])), Ok(()));
+        assert_eq!(
+            validate(&env(&[("1SITE", "unh")])),
+            Err(EnvError::BadName("1SITE".to_owned()))
+        );
+        assert_eq!(
+            validate(&env(&[("SITE-NAME", "unh")])),
+            Err(EnvError::BadName("SITE-NAME".to_owned()))
+        );
+        assert_eq!(
+            validate(&env(&[("", "unh")])),
+            Err(EnvError::BadName(String::new()))
+        );
+        assert_eq!(
+            validate(&env(&[("SITE", "a\0b")])),
+            Err(EnvError::NullByte("SITE".to_owned()))
+        );
+
+        let long = "x".repeat(MAX_VALUE_LEN + 1);
+        assert_eq!(
+            validate(&env(&[("SITE", &long)])),
+            Err(EnvError::TooLong("SITE".to_owned()))
+        );
+
+        let many = (0..=MAX_VARS)
+            .map(|i| (format!("V{i}"), String::new()))
+            .collect();
+        assert_eq!(validate(&many), Err(EnvError::TooMany));
+    }
+
+    #[test]
+    fn test_env_file() {
+        assert_eq!(
+            env_file(&env(&[("SITE", "unh"), ("MOTD", "it's $(here)")])),
+            "MOTD='it'\\''s $(here)'\nSITE='unh'\n"
+        );
+    }
+
+    #[test]
+    fn test_substitute() {
+        let vars = env(&[("SITE", "unh"), ("PROXY", "http://proxy:3128")]);
+
+        assert_eq!(
+            substitute(
+                "site: {{ env.SITE }}\nproxy: {{env.PROXY}}\nother: {{ env.NOPE }} {{ jinja }}",
+                &vars
+            ),
+            "site: unh\nproxy: http://proxy:3128\nother: {{ env.NOPE }} {{ jinja }}"
+        );
+        assert_eq!(substitute("open {{ env.SITE", &vars), "open {{ env.SITE");
+        assert_eq!(substitute("", &vars), "");
+    }
+}
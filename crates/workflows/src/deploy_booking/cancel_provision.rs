@@ -0,0 +1,149 @@
+//! Cleans up after a deploy of an instance that was canceled while it was underway
+//!
+//! Canceling the deploy task only stops it at the next checkpoint it reaches, so the
+//! host is powered off and taken out of netboot here to keep it from installing on
+//! its own in the meantime.
+
+use common::prelude::{anyhow, tracing};
+use dal::{new_client, AsEasyTransaction, FKey, ID};
+use maplit::hashmap;
+use models::{
+    dashboard::{Aggregate, BookingEvent, BookingEventKind, Instance, StatusSentiment},
+    inventory::Host,
+};
+use pyo3::{
+    types::{IntoPyDict, PyModule},
+    IntoPy, Python,
+};
+use serde::{Deserialize, Serialize};
+use tascii::{prelude::*, task_trait::AsyncRunnable};
+
+use super::set_host_power_state::SetPower;
+use crate::{retry_for, utils::status_feed::PublishedLog};
+
+#[derive(Debug, Clone, Hash, Serialize, Deserialize)]
+pub struct CancelProvision {
+    pub host_id: FKey<Host>,
+    pub inst_id: FKey<Instance>,
+    pub agg_id: FKey<Aggregate>,
+    pub canceled_by: Option<String>,
+}
+
+tascii::mark_task!(CancelProvision);
+impl AsyncRunnable for CancelProvision {
+    type Output = ();
+
+    async fn run(&mut self, context: &Context) -> Result<Self::Output, TaskError> {
+        self.inst_id
+            .log(
+                "Canceling Deploy",
+                "the host is being powered off and taken out of netboot",
+                StatusSentiment::InProgress,
+            )
+            .await;
+
+        let mut client = new_client().await.unwrap();
+        let mut transaction = client.easy_transaction().await.unwrap();
+        let host_name = self
+            .host_id
+            .get(&mut transaction)
+            .await
+            .map_err(|e| TaskError::Reason(format!("couldn't find the host: {e:?}")))?
+            .server_name
+            .clone();
+        transaction.commit().await.unwrap();
+
+        // the rest of the cleanup is still worth doing if the host won't power off
+        if let Err(e) = retry_for(SetPower::off(self.host_id), context, 5, 10) {
+            tracing::error!("Couldn't power off {host_name} after canceling its deploy: {e:?}");
+        }
+
+        if let Err(e) = clear_netboot(&host_name) {
+            tracing::error!(
+                "Couldn't take {host_name} out of netboot after canceling its deploy: {e:?}"
+            );
+        }
+
+        let by = self
+            .canceled_by
+            .as_ref()
+            .map_or(String::new(), |user| format!(" by {user}"));
+
+        self.inst_id
+            .log(
+                "Deploy Canceled",
+                &format!("the deploy was canceled{by}, reimage the host to deploy it again"),
+                StatusSentiment::Failed,
+            )
+            .await;
+
+        if let Err(e) = BookingEvent::record_committing(
+            self.agg_id,
+            BookingEventKind::ProvisioningFailed,
+            format!("The deploy of {host_name} was canceled{by}"),
+            self.canceled_by.clone(),
+        )
+        .await
+        {
+            tracing::error!("Couldn't record the canceled deploy of {host_name}: {e:?}");
+        }
+
+        Ok(())
+    }
+
+    fn summarize(&self, id: ID) -> String {
+        format!(
+            "CancelProvision task with id {id} for host {:?} of agg {:?}",
+            self.host_id, self.agg_id
+        )
+    }
+
+    fn identifier() -> TaskIdentifier {
+        TaskIdentifier::named("CancelProvisionTask").versioned(1)
+    }
+
+    fn retry_count(&self) -> usize {
+        0
+    }
+}
+
+/// Stops cobbler from handing the host its installer the next time it boots
+fn clear_netboot(host_name: &str) -> Result<(), anyhow::Error> {
+    Python::with_gil(|py| {
+        PyModule::from_code(
+            py,
+            include_str!("../utils/cobbler.py"),
+            "cobbler.py",
+            "cobbler",
+        )?;
+
+        let config::CobblerConfig {
+            url,
+            api_username,
+            api_password,
+            ..
+        } = config::settings().cobbler.clone();
+
+        let locals = hashmap! {
+            "config" => hashmap! {
+                "url" => url,
+                "user" => api_username,
+                "password" => api_password,
+            }.into_py(py),
+            "host_name" => host_name.into_py(py),
+        }
+        .into_py_dict(py);
+
+        py.run(
+            r#"
+from cobbler import CobblerAction
+
+CobblerAction(config).unset_netboot(host_name)
+"#,
+            None,
+            Some(locals),
+        )?;
+
+        Ok(())
+    })
+}
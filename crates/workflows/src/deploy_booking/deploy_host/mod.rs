@@ -1,17 +1,19 @@
 use common::prelude::{
     anyhow,
+    chrono::{DateTime, Utc},
     tokio::time::{sleep, Duration},
     tracing::{self, error, info, trace, warn},
 };
 
 use config::{self, settings};
-use dal::{new_client, AsEasyTransaction, FKey, ID};
+use dal::{new_client, AsEasyTransaction, FKey, NewRow, ID};
 use metrics::prelude::*;
 
 use models::{
-    dashboard::{Aggregate, StatusSentiment},
-    inventory::{BootTo, Host, Lab},
-    EasyLog,
+    dashboard::{
+        instance::PROVISION_CANCELED, Aggregate, Checkpoint, DeployCheckpoint, StatusSentiment,
+    },
+    inventory::{self, BootTo, Host, Lab, TicketKind},
 };
 use notifications::email::send_to_admins;
 use serde::{Deserialize, Serialize};
@@ -31,15 +33,21 @@ use super::{
 };
 use crate::{
     deploy_booking::{
-        cobbler_set_config::*, configure_networking::ConfigureNetworking,
-        net_config::mgmt_network_config_with_public, wait_host_os_reachable::WaitHostOSReachable,
+        cobbler_set_config::*, configure_networking::ConfigureNetworking, failure_bundle,
+        net_config::mgmt_network_config_with_public, nic_inventory, telemetry,
+        wait_host_os_reachable::WaitHostOSReachable,
     },
     resource_management::{
+        bmc_isolation::VerifyBmcIsolation,
         cobbler::*,
+        composability,
         ipmi_accounts::CreateIPMIAccount,
         mailbox::{Endpoint, Mailbox, MailboxMessageReceiver},
+        post_times,
+        tickets::report_host_failure,
     },
     retry_for,
+    utils::status_feed::PublishedLog,
 };
 
 /// A WorkflowDistro is used for path branching within a workflow.
@@ -74,6 +82,10 @@ pub struct DeployHost {
     pub aggregate_id: FKey<Aggregate>,
     pub using_instance: FKey<models::dashboard::Instance>,
     pub distribution: Option<WorkflowDistro>,
+    /// Pick up an earlier deploy of the instance after this checkpoint, instead of
+    /// starting over, if the earlier deploy got that far on the same host
+    #[serde(default)]
+    pub resume_from: Option<Checkpoint>,
 }
 
 tascii::mark_task!(DeployHost);
@@ -89,10 +101,16 @@ impl AsyncRunnable for DeployHost {
         // start time of the provision
         let start_time = Timestamp::now();
 
+        let task = context.id();
+        self.track(task).await;
+
+        // only the first attempt picks up where an earlier deploy left off, retries start over
+        let mut resume = self.resume_point().await;
+
         let mut err: TaskError = TaskError::Reason(String::from("Host failed to attempt deploy."));
         for _task_retry_no in 0..(self.retry_count() + 1) {
             let result = self
-                .deploy_host(context, (&aggregate, &host_name, &lab))
+                .deploy_host(context, (&aggregate, &host_name, &lab), resume.take())
                 .await;
 
             let provisioning_time_seconds = start_time.elapsed();
@@ -105,14 +123,27 @@ impl AsyncRunnable for DeployHost {
             .await;
 
             match result {
-                Ok(_) => return result,
+                Ok(_) => {
+                    self.untrack(task).await;
+                    return result;
+                }
                 Err(e) => {
                     err = e;
+                    if self.canceled().await {
+                        break;
+                    }
                     continue;
                 }
             }
         }
 
+        self.untrack(task).await;
+
+        // whoever canceled the deploy cleans up after it, it didn't fail on its own
+        if self.canceled().await {
+            return Err(err);
+        }
+
         let mut client = new_client().await.unwrap();
         let mut transaction = client.easy_transaction().await.unwrap();
         let profile = self
@@ -129,14 +160,20 @@ impl AsyncRunnable for DeployHost {
             .into_inner()
             .name;
 
-        send_to_admins(format!(
-            "Failure to provision a host for instance {:?}, this is of profile {profile}",
-            self.using_instance
-        ))
-        .await;
-
         transaction.commit().await.unwrap();
 
+        failure_bundle::report(
+            self.using_instance,
+            self.aggregate_id,
+            self.host_id,
+            format!("{err:?}"),
+            format!(
+                "Failure to provision a host for instance {:?}, this is of profile {profile}",
+                self.using_instance
+            ),
+        )
+        .await;
+
         Err(err)
     }
 
@@ -157,6 +194,25 @@ impl AsyncRunnable for DeployHost {
     }
 }
 
+/// Where a deploy asked to resume after `requested` can pick up, given the checkpoints
+/// recorded so far
+///
+/// Only phases that were done on `host` can be skipped, and the last steps are always
+/// run again, since they are quick and nothing would be left to resume otherwise.
+fn resume_point(
+    requested: Option<Checkpoint>,
+    reached: &[DeployCheckpoint],
+    host: FKey<Host>,
+) -> Option<Checkpoint> {
+    let latest = reached
+        .iter()
+        .filter(|c| c.host == host)
+        .map(|c| c.checkpoint)
+        .max()?;
+
+    Some(requested?.min(latest).min(Checkpoint::PostConfigDone))
+}
+
 pub enum MockInjectionResult {
     Success,
     Abort(TaskError),
@@ -194,56 +250,127 @@ impl DeployHost {
         }
     }
 
+    /// Deploys the host, skipping the phases up to and including `resume` if given, as
+    /// those were already done by an earlier deploy
     async fn deploy_host(
         &mut self,
         context: &Context,
         (aggregate, host_name, lab): (&Aggregate, &String, &Lab),
+        resume: Option<Checkpoint>,
     ) -> Result<(), TaskError> {
-        match self.wait_for_mock_injection().await {
-            MockInjectionResult::Success => return Ok(()),
-            MockInjectionResult::Abort(err) => return Err(err),
-            MockInjectionResult::Continue => {}
+        let done = |checkpoint: Checkpoint| resume.is_some_and(|after| checkpoint <= after);
+
+        if resume.is_none() {
+            match self.wait_for_mock_injection().await {
+                MockInjectionResult::Success => return Ok(()),
+                MockInjectionResult::Abort(err) => return Err(err),
+                MockInjectionResult::Continue => {}
+            }
         }
 
-        self.log(
-            "Provision Start",
-            "a task has started running to provision the host",
-            StatusSentiment::InProgress,
-        )
-        .await;
+        self.clear_checkpoints(resume).await;
+
+        match resume {
+            None => {
+                self.log(
+                    "Provision Start",
+                    "a task has started running to provision the host",
+                    StatusSentiment::InProgress,
+                )
+                .await
+            }
+            Some(after) => {
+                self.log(
+                    "Provision Resumed",
+                    &format!("picking the provision of the host back up after {after}"),
+                    StatusSentiment::InProgress,
+                )
+                .await
+            }
+        }
 
         let (preimage_waiter, imaging_waiter, mut post_boot_waiter, mut post_provision_waiter) =
-            self.generate_endpoints().await;
+            match resume {
+                None => self.generate_endpoints().await,
+                // the host was already handed the endpoints of the deploy being resumed
+                Some(_) => self.reattach_endpoints().await?,
+            };
+
+        // a host that never reports its nics is still provisioned fine, so this is best effort
+        let nic_waiter = match resume {
+            None => self
+                .set_endpoint_hook(nic_inventory::REPORT_HOOK)
+                .await
+                .ok(),
+            Some(_) => self.reattach_hook(nic_inventory::REPORT_HOOK).await.ok(),
+        };
 
-        self.configure_cobbler_and_set_boot(
-            context,
-            preimage_waiter.endpoint(),
-            imaging_waiter.endpoint(),
-            host_name,
-        )
-        .await?;
+        // the agent reports straight to its own route rather than to a waiter
+        if resume.is_none() && aggregate.metadata.telemetry {
+            if let Err(e) = self.set_endpoint_hook(telemetry::REPORT_HOOK).await {
+                tracing::warn!("Couldn't set up telemetry for {host_name}: {e:?}");
+            }
+        }
 
-        sleep(Duration::from_secs(2)).await;
+        self.compose_host(host_name).await?;
+
+        let mut powered_on = None;
+        if !done(Checkpoint::NetbootConfigured) {
+            self.configure_cobbler_and_set_boot(
+                context,
+                preimage_waiter.endpoint(),
+                imaging_waiter.endpoint(),
+                host_name,
+            )
+            .await?;
 
-        self.set_power_on(context, host_name).await?;
+            sleep(Duration::from_secs(2)).await;
 
-        self.configure_mgmt_networking(context, lab.clone()).await?;
+            powered_on = Some(self.set_power_on(context, host_name).await?);
 
-        self.install_os(preimage_waiter, imaging_waiter).await?;
+            self.configure_mgmt_networking(context, lab.clone()).await?;
 
-        self.set_power_off(context, host_name).await?;
+            self.checkpoint(Checkpoint::NetbootConfigured).await?;
+        } else if !done(Checkpoint::ImageWritten) {
+            // whatever the installer got through before is lost, so boot it again
+            powered_on = Some(self.restart_installer(context, host_name).await?);
+        }
 
-        self.boot_from_disk(context, host_name).await?;
+        if !done(Checkpoint::ImageWritten) {
+            self.install_os(preimage_waiter, imaging_waiter, powered_on)
+                .await?;
 
-        self.configure_postprovision_networking(context, lab.clone(), &mut post_boot_waiter)
-            .await?;
+            self.checkpoint(Checkpoint::ImageWritten).await?;
+        }
 
-        self.verify_host_provisioned(context, host_name, &mut post_provision_waiter)
-            .await?;
+        if !done(Checkpoint::FirstBootSeen) {
+            self.set_power_off(context, host_name).await?;
+
+            self.boot_from_disk(context, host_name).await?;
+
+            self.wait_first_boot(&mut post_boot_waiter).await?;
+
+            self.checkpoint(Checkpoint::FirstBootSeen).await?;
+        }
+
+        if !done(Checkpoint::PostConfigDone) {
+            self.configure_prod_networking(context, lab.clone()).await?;
+
+            self.verify_host_provisioned(context, host_name, &mut post_provision_waiter)
+                .await?;
+
+            self.checkpoint(Checkpoint::PostConfigDone).await?;
+        }
+
+        if let Some(waiter) = nic_waiter {
+            self.collect_nic_inventory(waiter, host_name).await;
+        }
 
         self.setup_ipmi_accounts(context, aggregate.clone(), host_name)
             .await?;
 
+        self.checkpoint(Checkpoint::Finished).await?;
+
         self.log(
             "Successfully Provisioned",
             &format!("{} has provisioned according to configuration", host_name),
@@ -284,6 +411,27 @@ impl DeployHost {
             post_provision_waiter,
         )
     }
+
+    /// Waits on the endpoints handed to the host by the deploy being resumed
+    async fn reattach_endpoints(
+        &mut self,
+    ) -> Result<
+        (
+            MailboxMessageReceiver,
+            MailboxMessageReceiver,
+            MailboxMessageReceiver,
+            MailboxMessageReceiver,
+        ),
+        anyhow::Error,
+    > {
+        Ok((
+            self.reattach_hook("pre_image").await?,
+            self.reattach_hook("post_image").await?,
+            self.reattach_hook("post_boot").await?,
+            self.reattach_hook("post_provision").await?,
+        ))
+    }
+
     async fn wait_for_mock_injection(&mut self) -> MockInjectionResult {
         let mut mock_waiter = self.set_endpoint_hook("mock").await.unwrap();
 
@@ -479,7 +627,49 @@ impl DeployHost {
         Ok(())
     }
 
-    async fn set_power_on(&mut self, context: &Context, host_name: &str) -> Result<(), TaskError> {
+    /// Attaches the devices the flavor is composed with from the host's fabric, before the
+    /// host boots so that the installer and the OS both see them
+    async fn compose_host(&mut self, host_name: &str) -> Result<(), TaskError> {
+        match composability::compose(self.using_instance, self.host_id).await {
+            Ok(None) => Ok(()),
+            Ok(Some(composition)) => {
+                let devices = composition
+                    .devices
+                    .iter()
+                    .map(|d| format!("{:?} {}", d.kind, d.model))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                self.log(
+                    "Host Composed",
+                    &format!("attached {devices} from fabric {}", composition.fabric),
+                    StatusSentiment::InProgress,
+                )
+                .await;
+
+                Ok(())
+            }
+            Err(e) => {
+                error!("Failed to compose {host_name}: {e:?}");
+                self.log(
+                    "Failed to Compose Host",
+                    "the devices this host is composed with couldn't be attached",
+                    StatusSentiment::Degraded,
+                )
+                .await;
+
+                Err(TaskError::Reason(format!(
+                    "Failed to compose {host_name}: {e}"
+                )))
+            }
+        }
+    }
+
+    /// Gives when the host was powered on, which is where its time to netboot is counted from
+    async fn set_power_on(
+        &mut self,
+        context: &Context,
+        host_name: &str,
+    ) -> Result<DateTime<Utc>, TaskError> {
         warn!("setting host {} power on", host_name);
 
         self.log(
@@ -490,12 +680,34 @@ impl DeployHost {
         .await;
 
         retry_for(SetPower::on(self.host_id), context, 5, 10)?;
+        let powered_on = Utc::now();
 
         info!(
             "set power on, now adding pxe nets so host can pxe (in time it takes for host to post)"
         );
 
-        Ok(())
+        Ok(powered_on)
+    }
+
+    /// Power cycles a host that was already set to netboot, so that it runs the installer anew
+    async fn restart_installer(
+        &mut self,
+        context: &Context,
+        host_name: &str,
+    ) -> Result<DateTime<Utc>, TaskError> {
+        self.log(
+            "Restarting Installer",
+            "power cycling the host to boot the netinstall image again",
+            StatusSentiment::InProgress,
+        )
+        .await;
+
+        warn!("power cycling {host_name} to restart its install");
+        retry_for(SetPower::off(self.host_id), context, 5, 10)?;
+
+        sleep(Duration::from_secs(2)).await;
+
+        self.set_power_on(context, host_name).await
     }
 
     async fn set_power_off(&mut self, context: &Context, host_name: &str) -> Result<(), TaskError> {
@@ -614,6 +826,7 @@ impl DeployHost {
         &mut self,
         mut preimage_waiter: MailboxMessageReceiver,
         mut imaging_waiter: MailboxMessageReceiver,
+        powered_on: Option<DateTime<Utc>>,
     ) -> Result<(), TaskError> {
         self.log(
             "Installing OS",
@@ -631,6 +844,7 @@ impl DeployHost {
             }
             _ => {
                 let inst_id = self.using_instance;
+                let host_id = self.host_id;
                 let finished_imaging = Arc::new(AtomicBool::new(false));
                 let fcopy = finished_imaging.clone();
                 std::thread::spawn(move || {
@@ -641,10 +855,21 @@ impl DeployHost {
                             warn!("Host didn't phone home before imaging!");
                         }
                         (false, Ok(_)) => {
+                            let netbooted = Utc::now();
                             tascii::executors::spawn_on_tascii_tokio(
                                 "laas_notifications",
                                 async move {
                                     inst_id.log("Installing OS", "host has booted into the installer, and is now installing the base OS", StatusSentiment::InProgress).await;
+
+                                    if let Some(powered_on) = powered_on {
+                                        if let Err(e) = post_times::record(
+                                            host_id, inst_id, powered_on, netbooted,
+                                        )
+                                        .await
+                                        {
+                                            warn!("Couldn't record how long {host_id:?} took to netboot: {e:?}");
+                                        }
+                                    }
                                 },
                             );
                         }
@@ -699,10 +924,8 @@ impl DeployHost {
         Ok(())
     }
 
-    async fn configure_postprovision_networking(
+    async fn wait_first_boot(
         &mut self,
-        context: &Context,
-        lab: Lab,
         post_boot_waiter: &mut MailboxMessageReceiver,
     ) -> Result<(), TaskError> {
         let wfd = self.get_workflow_distro().await?;
@@ -735,6 +958,14 @@ impl DeployHost {
             }
         }
 
+        Ok(())
+    }
+
+    async fn configure_prod_networking(
+        &mut self,
+        context: &Context,
+        lab: Lab,
+    ) -> Result<(), TaskError> {
         if lab.is_dynamic {
             self.log(
                 "Host Configure",
@@ -833,6 +1064,23 @@ impl DeployHost {
         Ok(())
     }
 
+    /// Keeps the nic report the host sent while setting itself up, which it
+    /// does just before its final phone home, so it should already be waiting
+    async fn collect_nic_inventory(&mut self, mut waiter: MailboxMessageReceiver, host_name: &str) {
+        let report = match waiter.wait_next(Duration::from_secs(60 * 2)) {
+            Ok(v) => v.msg.message,
+            Err(e) => {
+                warn!("{host_name} didn't report its nics: {}", e.failure_reason);
+                return;
+            }
+        };
+
+        match nic_inventory::record(self.using_instance, &report).await {
+            Ok(inventory) => info!("Recorded {} nics for {host_name}", inventory.nics.len()),
+            Err(e) => error!("Couldn't record the nics of {host_name}: {e:?}"),
+        }
+    }
+
     async fn setup_ipmi_accounts(
         &mut self,
         context: &Context,
@@ -846,6 +1094,34 @@ impl DeployHost {
         )
         .await;
 
+        // only hand out IPMI access to a BMC that nobody else can reach
+        let findings = match context
+            .spawn(VerifyBmcIsolation { host: self.host_id })
+            .join()
+        {
+            Ok(findings) => findings,
+            Err(e) => vec![format!("the BMC couldn't be checked: {e:?}")],
+        };
+        if !findings.is_empty() {
+            report_host_failure(self.host_id, TicketKind::BmcExposed, findings.join("\n")).await;
+            send_to_admins(format!(
+                "Withheld IPMI access to {host_name}, its BMC may not be isolated: {}",
+                findings.join("; ")
+            ))
+            .await;
+
+            self.log(
+                "IPMI Access Withheld",
+                &format!(
+                    "The BMC of {host_name} couldn't be verified as isolated, \
+                    the administrators have been notified and will set up IPMI access once it is"
+                ),
+                StatusSentiment::Degraded,
+            )
+            .await;
+            return Ok(());
+        }
+
         let ipmi_res = retry_for(
             CreateIPMIAccount {
                 host: self.host_id,
@@ -888,6 +1164,158 @@ impl DeployHost {
     ) -> Result<MailboxMessageReceiver, anyhow::Error> {
         Mailbox::set_endpoint_hook(self.using_instance, usage).await
     }
+
+    /// Waits again on the endpoint an earlier deploy set for `usage`
+    async fn reattach_hook(
+        &mut self,
+        usage: &str,
+    ) -> Result<MailboxMessageReceiver, anyhow::Error> {
+        Ok(Mailbox::waiter_for(
+            Mailbox::get_endpoint_hook(self.using_instance, usage).await?,
+        ))
+    }
+
+    /// Where this deploy will pick up, if it was asked to resume an earlier one and can
+    async fn resume_point(&self) -> Option<Checkpoint> {
+        self.resume_from?;
+
+        let reached = async {
+            let mut client = new_client().await?;
+            let mut transaction = client.easy_transaction().await?;
+            let reached = DeployCheckpoint::for_instance(&mut transaction, self.using_instance)
+                .await?
+                .into_iter()
+                .map(|c| c.into_inner())
+                .collect::<Vec<_>>();
+            transaction.commit().await?;
+
+            Ok::<_, anyhow::Error>(reached)
+        }
+        .await;
+
+        match reached {
+            Ok(reached) => resume_point(self.resume_from, &reached, self.host_id),
+            Err(e) => {
+                warn!(
+                    "Couldn't look up the checkpoints of {:?}, starting over: {e:?}",
+                    self.using_instance
+                );
+                None
+            }
+        }
+    }
+
+    /// Records that the deploy got through `checkpoint`, then stops it if it was canceled
+    /// in the meantime
+    async fn checkpoint(&mut self, checkpoint: Checkpoint) -> Result<(), TaskError> {
+        let record = async {
+            let mut client = new_client().await?;
+            let mut transaction = client.easy_transaction().await?;
+            NewRow::new(DeployCheckpoint {
+                id: FKey::new_id_dangling(),
+                instance: self.using_instance,
+                host: self.host_id,
+                checkpoint,
+                reached: Utc::now(),
+            })
+            .insert(&mut transaction)
+            .await?;
+            transaction.commit().await?;
+
+            Ok::<_, anyhow::Error>(())
+        };
+
+        // the deploy itself is fine without it, it just can't be resumed from here
+        if let Err(e) = record.await {
+            error!(
+                "Couldn't record that {:?} reached {checkpoint}: {e:?}",
+                self.using_instance
+            );
+        }
+
+        match self.canceled().await {
+            true => Err(TaskError::Reason(format!(
+                "the deploy was canceled after {checkpoint}"
+            ))),
+            false => Ok(()),
+        }
+    }
+
+    /// Records that this deploy is underway on the host so that it can be found to be
+    /// canceled, forgetting that an earlier deploy of the instance was
+    async fn track(&mut self, task: ID) {
+        let track = async {
+            let mut client = new_client().await?;
+            let mut transaction = client.easy_transaction().await?;
+            let mut instance = self.using_instance.get(&mut transaction).await?;
+            if instance.metadata.remove(PROVISION_CANCELED).is_some() {
+                instance.update(&mut transaction).await?;
+            }
+            inventory::Action::add_for_host(&mut transaction, self.host_id, false, task).await?;
+            transaction.commit().await?;
+
+            Ok::<_, anyhow::Error>(())
+        };
+
+        // the deploy goes on fine, it just can't be canceled
+        if let Err(e) = track.await {
+            error!("Couldn't track the deploy of {:?}: {e:?}", self.host_id);
+        }
+    }
+
+    async fn untrack(&mut self, task: ID) {
+        let untrack = async {
+            let mut client = new_client().await?;
+            let mut transaction = client.easy_transaction().await?;
+            inventory::Action::complete(&mut transaction, task).await?;
+            transaction.commit().await?;
+
+            Ok::<_, anyhow::Error>(())
+        };
+
+        if let Err(e) = untrack.await {
+            error!("Couldn't mark the deploy of {:?} done: {e:?}", self.host_id);
+        }
+    }
+
+    /// Whether the deploy was canceled and should stop doing anything to the host
+    async fn canceled(&self) -> bool {
+        let canceled = async {
+            let mut client = new_client().await?;
+            let mut transaction = client.easy_transaction().await?;
+            let instance = self.using_instance.get(&mut transaction).await?;
+            transaction.commit().await?;
+
+            Ok::<_, anyhow::Error>(instance.metadata.contains_key(PROVISION_CANCELED))
+        };
+
+        canceled.await.unwrap_or_else(|e| {
+            warn!(
+                "Couldn't check whether the deploy of {:?} was canceled: {e:?}",
+                self.using_instance
+            );
+            false
+        })
+    }
+
+    /// Forgets the checkpoints this deploy is about to go through again
+    async fn clear_checkpoints(&mut self, resume: Option<Checkpoint>) {
+        let clear = async {
+            let mut client = new_client().await?;
+            let mut transaction = client.easy_transaction().await?;
+            DeployCheckpoint::clear_after(&mut transaction, self.using_instance, resume).await?;
+            transaction.commit().await?;
+
+            Ok::<_, anyhow::Error>(())
+        };
+
+        if let Err(e) = clear.await {
+            error!(
+                "Couldn't clear the checkpoints of {:?}: {e:?}",
+                self.using_instance
+            );
+        }
+    }
     async fn log(&mut self, msg: &str, desc: &str, sentiment: StatusSentiment) {
         self.using_instance.log(msg, desc, sentiment).await;
     }
@@ -933,3 +1361,49 @@ impl DeployHost {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reached(host: FKey<Host>, checkpoint: Checkpoint) -> DeployCheckpoint {
+        DeployCheckpoint {
+            id: FKey::new_id_dangling(),
+            instance: FKey::new_id_dangling(),
+            host,
+            checkpoint,
+            reached: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn test_resume_point() {
+        let (host, other) = (FKey::new_id_dangling(), FKey::new_id_dangling());
+        let checkpoints = [
+            reached(other, Checkpoint::PostConfigDone),
+            reached(host, Checkpoint::NetbootConfigured),
+            reached(host, Checkpoint::ImageWritten),
+        ];
+
+        assert_eq!(resume_point(None, &checkpoints, host), None);
+        assert_eq!(
+            resume_point(Some(Checkpoint::NetbootConfigured), &checkpoints, host),
+            Some(Checkpoint::NetbootConfigured)
+        );
+        // can't skip past what this host actually got through
+        assert_eq!(
+            resume_point(Some(Checkpoint::PostConfigDone), &checkpoints, host),
+            Some(Checkpoint::ImageWritten)
+        );
+        assert_eq!(
+            resume_point(Some(Checkpoint::ImageWritten), &checkpoints[..1], host),
+            None
+        );
+
+        let finished = [reached(host, Checkpoint::Finished)];
+        assert_eq!(
+            resume_point(Some(Checkpoint::Finished), &finished, host),
+            Some(Checkpoint::PostConfigDone)
+        );
+    }
+}
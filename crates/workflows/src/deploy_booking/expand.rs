@@ -0,0 +1,105 @@
+//! Deploys hosts added to a booking that is already running
+//!
+//! The new instances were created on the booking's existing network assignments, so each is
+//! deployed just like a host of a fresh booking. The hosts the booking already has are left
+//! alone, and a host that fails to provision only takes its own instance down with it.
+
+use dal::{new_client, AsEasyTransaction, FKey, ID};
+use models::dashboard::{
+    Aggregate, AggregateSummary, BookingEvent, BookingEventKind, Instance, LeaseOperation,
+};
+use notifications::email::send_to_admins;
+use serde::{Deserialize, Serialize};
+use tascii::prelude::*;
+
+use super::SingleHostDeploy;
+use crate::resource_management::lease;
+
+#[derive(Debug, Clone, Hash, Serialize, Deserialize)]
+pub struct ExpandBooking {
+    pub aggregate: FKey<Aggregate>,
+    pub instances: Vec<FKey<Instance>>,
+}
+
+tascii::mark_task!(ExpandBooking);
+impl AsyncRunnable for ExpandBooking {
+    type Output = ();
+
+    async fn run(&mut self, context: &Context) -> Result<Self::Output, TaskError> {
+        let lease = lease::try_acquire(self.aggregate, LeaseOperation::Expansion, None).await?;
+        lease::holding(lease, self.expand(context)).await
+    }
+
+    fn summarize(&self, id: ID) -> String {
+        format!(
+            "ExpandBooking task with id {id} adding {} host(s) to {:?}",
+            self.instances.len(),
+            self.aggregate
+        )
+    }
+
+    fn identifier() -> TaskIdentifier {
+        TaskIdentifier::named("ExpandBookingTask").versioned(1)
+    }
+
+    fn timeout() -> std::time::Duration {
+        SingleHostDeploy::timeout() + std::time::Duration::from_secs(120)
+    }
+}
+
+impl ExpandBooking {
+    /// Deploys the new instances, under the lease `run` took
+    async fn expand(&mut self, context: &Context) -> Result<(), TaskError> {
+        let handles = self
+            .instances
+            .iter()
+            .map(|&instance| {
+                context.spawn(SingleHostDeploy {
+                    instance,
+                    for_aggregate: self.aggregate,
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let mut failed = 0;
+        for (instance, handle) in self.instances.iter().zip(handles) {
+            if let Err(e) = handle.join() {
+                failed += 1;
+                send_to_admins(format!(
+                    "Failed to provision instance {:?} added to booking {:?}, error: {e:?}",
+                    instance.into_id(),
+                    self.aggregate.into_id()
+                ))
+                .await;
+            }
+        }
+
+        let mut client = new_client().await?;
+        let mut transaction = client.easy_transaction().await?;
+
+        let (kind, details) = match failed {
+            0 => (
+                BookingEventKind::Provisioned,
+                format!("all {} added host(s) are up", self.instances.len()),
+            ),
+            _ => (
+                BookingEventKind::ProvisioningFailed,
+                format!(
+                    "{failed} of {} added host(s) failed to provision",
+                    self.instances.len()
+                ),
+            ),
+        };
+        BookingEvent::record(&mut transaction, self.aggregate, kind, details, None).await?;
+        AggregateSummary::refresh(&mut transaction, self.aggregate).await?;
+
+        transaction.commit().await?;
+
+        match failed {
+            0 => Ok(()),
+            _ => Err(TaskError::Reason(format!(
+                "{failed} added host(s) failed to provision"
+            ))),
+        }
+    }
+}
@@ -0,0 +1,317 @@
+//! Diagnostics gathered when a host fails to provision, sent along to the admins and kept
+//! so that they can be downloaded again while triaging
+//!
+//! Every source is best effort. A host that couldn't be provisioned may well have a switch
+//! or lease that can't be looked at either, and the bundle says so instead of leaving it out.
+
+use std::{collections::HashMap, fmt::Write, io::Read, net::TcpStream};
+
+use common::prelude::{anyhow, chrono::Utc, tokio, tracing};
+use config::settings;
+use dal::{new_client, AsEasyTransaction, FKey, NewRow};
+use models::{
+    dashboard::{Aggregate, BundleEvent, FailureBundle, Instance, PortState, ProvisionLogEvent},
+    inventory::{Host, Switch},
+};
+use notifications::email::{send_to_admins, send_to_admins_with_attachment};
+use ssh2::Session;
+
+use crate::resource_management::reconciler::switch_port_vlans;
+
+/// How many of the latest dhcpd lines about the host to keep
+const DHCP_LINES: usize = 50;
+
+/// The last `lines` lines of `text`
+fn tail(text: &str, lines: usize) -> String {
+    let all = text.lines().collect::<Vec<_>>();
+
+    all[all.len().saturating_sub(lines)..].join("\n")
+}
+
+/// The latest lines of a dhcpd log that are about any of `macs`
+fn dhcp_attempts(log: &str, macs: &[String]) -> Vec<String> {
+    let lines = log
+        .lines()
+        .filter(|l| l.contains("DHCP"))
+        .filter(|l| {
+            let l = l.to_lowercase();
+            macs.iter().any(|m| l.contains(m.as_str()))
+        })
+        .map(str::to_owned)
+        .collect::<Vec<_>>();
+
+    lines[lines.len().saturating_sub(DHCP_LINES)..].to_vec()
+}
+
+/// What the host printed to its serial console, if it was being captured
+async fn console_excerpt(server_name: &str) -> Option<String> {
+    // where StashSOLOutput keeps it
+    let output = tokio::fs::read(format!("{server_name}_sol_output.txt"))
+        .await
+        .ok()?;
+
+    Some(tail(
+        &String::from_utf8_lossy(&output),
+        settings().failure_bundles.console_lines,
+    ))
+}
+
+/// What dhcpd logged on the cobbler server about any of `macs`
+fn dhcp_log(macs: Vec<String>) -> Result<Vec<String>, anyhow::Error> {
+    if macs.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let cobbler = settings().cobbler.clone();
+
+    let mut session = Session::new()?;
+    session.set_tcp_stream(TcpStream::connect(format!("{}:22", cobbler.address))?);
+    session.handshake()?;
+    session.userauth_password(&cobbler.username, &cobbler.password)?;
+
+    let mut channel = session.channel_session()?;
+    let mut output = String::new();
+
+    // MACs are only hex digits and colons, so they can be handed to the shell as they are
+    channel.exec(&format!(
+        "sudo grep -i -E '{}' {}",
+        macs.join("|"),
+        settings().failure_bundles.dhcp_log
+    ))?;
+    channel.read_to_string(&mut output)?;
+    channel.wait_close()?;
+
+    Ok(dhcp_attempts(&output, &macs))
+}
+
+/// Gathers and saves a bundle for the failed deploy of `instance` onto `host`
+pub async fn collect(
+    instance: FKey<Instance>,
+    aggregate: FKey<Aggregate>,
+    host: FKey<Host>,
+    error: String,
+) -> Result<FailureBundle, anyhow::Error> {
+    let mut client = new_client().await?;
+    let mut transaction = client.easy_transaction().await?;
+
+    let host = host.get(&mut transaction).await?.into_inner();
+
+    let mut events = ProvisionLogEvent::all_for_instance(&mut transaction, instance)
+        .await?
+        .into_iter()
+        .map(|e| BundleEvent {
+            time: e.time,
+            sentiment: e.sentiment,
+            event: e.prov_status.event.clone(),
+            details: e.prov_status.details.clone(),
+        })
+        .collect::<Vec<_>>();
+    events.sort_by_key(|e| e.time);
+
+    let ports = host.ports(&mut transaction).await?;
+    let macs = ports
+        .iter()
+        .map(|p| p.mac.to_string().to_lowercase())
+        .collect::<Vec<_>>();
+
+    let mut cabled: HashMap<FKey<Switch>, Vec<String>> = HashMap::new();
+    for port in ports {
+        if let Some(sp) = port.switchport {
+            let sp = sp.get(&mut transaction).await?;
+            cabled
+                .entry(sp.for_switch)
+                .or_default()
+                .push(sp.name.clone());
+        }
+    }
+
+    let mut switches = Vec::new();
+    for (switch, ports) in cabled {
+        let switch = switch.get(&mut transaction).await?.into_inner();
+        let os = match switch.switch_os {
+            Some(os) => os.get(&mut transaction).await?.os_type.clone(),
+            None => String::new(),
+        };
+        switches.push((switch, os, ports));
+    }
+
+    transaction.commit().await?;
+
+    let mut switch_ports = Vec::new();
+    for (switch, os, ports) in switches {
+        let vlans = switch_port_vlans(&switch, &os, ports.clone()).await;
+        for port in ports {
+            let state = match &vlans {
+                Ok(vlans) => vlans
+                    .get(&port)
+                    .map_or("not reported by the switch".to_owned(), |v| v.to_string()),
+                Err(e) => format!("couldn't be read: {e}"),
+            };
+
+            switch_ports.push(PortState {
+                switch: switch.name.clone(),
+                port,
+                state,
+            });
+        }
+    }
+    switch_ports.sort_by(|a, b| (&a.switch, &a.port).cmp(&(&b.switch, &b.port)));
+
+    let dhcp = match tokio::task::spawn_blocking(move || dhcp_log(macs)).await {
+        Ok(Ok(lines)) => lines,
+        Ok(Err(e)) => vec![format!("couldn't read the dhcp log: {e}")],
+        Err(e) => vec![format!("couldn't read the dhcp log: {e}")],
+    };
+
+    let bundle = FailureBundle {
+        id: FKey::new_id_dangling(),
+        instance,
+        aggregate,
+        host: host.id,
+        created: Utc::now(),
+        error,
+        events,
+        console: console_excerpt(&host.server_name).await,
+        dhcp,
+        switch_ports,
+    };
+
+    let mut transaction = client.easy_transaction().await?;
+    NewRow::new(bundle.clone()).insert(&mut transaction).await?;
+    transaction.commit().await?;
+
+    Ok(bundle)
+}
+
+/// The bundle as a text file, for mailing and downloading
+pub fn render(bundle: &FailureBundle, server_name: &str) -> String {
+    let mut out = String::new();
+
+    let _ = writeln!(
+        out,
+        "Provisioning failure of {server_name} for instance {:?} of booking {:?}",
+        bundle.instance.into_id(),
+        bundle.aggregate.into_id()
+    );
+    let _ = writeln!(out, "Gathered {}", bundle.created.to_rfc2822());
+    let _ = writeln!(out, "Error: {}", bundle.error);
+
+    let _ = writeln!(out, "\n== Provision log ==");
+    for e in bundle.events.iter() {
+        let _ = writeln!(
+            out,
+            "{} [{:?}] {} -- {}",
+            e.time.to_rfc3339(),
+            e.sentiment,
+            e.event,
+            e.details
+        );
+    }
+
+    let _ = writeln!(out, "\n== Switch ports ==");
+    if bundle.switch_ports.is_empty() {
+        let _ = writeln!(out, "the host isn't cabled to any known switch port");
+    }
+    for p in bundle.switch_ports.iter() {
+        let _ = writeln!(out, "{} {}: {}", p.switch, p.port, p.state);
+    }
+
+    let _ = writeln!(out, "\n== DHCP ==");
+    if bundle.dhcp.is_empty() {
+        let _ = writeln!(out, "dhcpd logged nothing about the host");
+    }
+    for line in bundle.dhcp.iter() {
+        let _ = writeln!(out, "{line}");
+    }
+
+    let _ = writeln!(out, "\n== Console ==");
+    let _ = writeln!(
+        out,
+        "{}",
+        bundle
+            .console
+            .as_deref()
+            .unwrap_or("the console of the host wasn't being captured")
+    );
+
+    let _ = writeln!(out, "\n{}", settings().branding.text_footer());
+
+    out
+}
+
+/// Tells the admins that the deploy of `instance` failed, attaching a bundle for it
+pub async fn report(
+    instance: FKey<Instance>,
+    aggregate: FKey<Aggregate>,
+    host: FKey<Host>,
+    error: String,
+    message: String,
+) {
+    let rendered = async {
+        let bundle = collect(instance, aggregate, host, error).await?;
+
+        let mut client = new_client().await?;
+        let mut transaction = client.easy_transaction().await?;
+        let server_name = host.get(&mut transaction).await?.server_name.clone();
+        transaction.commit().await?;
+
+        Ok::<_, anyhow::Error>((
+            bundle.id,
+            server_name.clone(),
+            render(&bundle, &server_name),
+        ))
+    }
+    .await;
+
+    match rendered {
+        Ok((id, server_name, rendered)) => {
+            send_to_admins_with_attachment(
+                format!(
+                    "{message}. Diagnostics are attached, and can be downloaded again as failure bundle {:?}",
+                    id.into_id()
+                ),
+                format!("{server_name}-failure.txt"),
+                rendered,
+            )
+            .await
+        }
+        Err(e) => {
+            tracing::error!("Couldn't put together a failure bundle for {instance:?}: {e:?}");
+            send_to_admins(format!("{message}. No diagnostics could be gathered: {e:?}")).await
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dhcp_attempts() {
+        let log = "\
+Oct 14 10:00:01 cobbler dhcpd[812]: DHCPDISCOVER from 0c:42:a1:00:00:01 via eth1
+Oct 14 10:00:01 cobbler dhcpd[812]: DHCPOFFER on 10.0.0.5 to 0c:42:a1:00:00:01 via eth1
+Oct 14 10:00:02 cobbler dhcpd[812]: DHCPDISCOVER from 0c:42:a1:00:00:99 via eth1
+Oct 14 10:00:03 cobbler in.tftpd[900]: RRQ from 10.0.0.5 filename pxelinux.0
+Oct 14 10:00:04 cobbler dhcpd[812]: DHCPREQUEST for 10.0.0.5 from 0C:42:A1:00:00:01 via eth1";
+
+        let attempts = dhcp_attempts(log, &["0c:42:a1:00:00:01".to_owned()]);
+
+        assert_eq!(attempts.len(), 3);
+        assert!(attempts[0].contains("DHCPDISCOVER"));
+        assert!(attempts[2].contains("DHCPREQUEST"));
+
+        let many = "DHCPDISCOVER from 0c:42:a1:00:00:01\n".repeat(DHCP_LINES + 10);
+        assert_eq!(
+            dhcp_attempts(&many, &["0c:42:a1:00:00:01".to_owned()]).len(),
+            DHCP_LINES
+        );
+    }
+
+    #[test]
+    fn test_tail() {
+        assert_eq!(tail("a\nb\nc\n", 2), "b\nc");
+        assert_eq!(tail("a\nb", 5), "a\nb");
+        assert_eq!(tail("", 5), "");
+    }
+}
@@ -0,0 +1,232 @@
+//! Names for the hosts of a booking that the user didn't name themselves
+//!
+//! Names come from the project's `hostname_template`, filling in `{project}`, `{lab}`,
+//! `{owner}`, `{booking_id}`, `{booking_short}` (the first 8 characters of the aggregate
+//! id), `{role}` (the flavor of the host) and `{index}` (counting from 1 among hosts
+//! of the same role). The result is always a valid DNS label, and never the same as
+//! another host in the booking.
+
+use std::collections::{HashMap, HashSet};
+
+use common::prelude::{anyhow, itertools::Itertools};
+
+pub const DEFAULT_TEMPLATE: &str = "{project}-{booking_short}-{role}{index}";
+
+/// The longest a single DNS label may be
+const MAX_LABEL_LEN: usize = 63;
+
+/// Everything a template can refer to that is the same for every host in the booking
+#[derive(Debug, Clone, Default)]
+pub struct HostnameVars {
+    pub project: String,
+    pub lab: String,
+    pub owner: String,
+    pub booking_id: String,
+    pub booking_short: String,
+}
+
+/// Lowercases `s` and replaces anything that can't appear in a hostname with dashes
+pub fn dns_label(s: &str) -> String {
+    let mut label = String::new();
+
+    for c in s.chars() {
+        let c = c.to_ascii_lowercase();
+        if c.is_ascii_alphanumeric() {
+            label.push(c);
+        } else if !label.ends_with('-') {
+            label.push('-');
+        }
+    }
+
+    label.truncate(MAX_LABEL_LEN);
+    label.trim_matches('-').to_owned()
+}
+
+fn render(
+    template: &str,
+    vars: &HostnameVars,
+    role: &str,
+    index: usize,
+) -> Result<String, anyhow::Error> {
+    let mut rendered = String::new();
+    let mut rest = template;
+
+    while let Some(open) = rest.find('{') {
+        rendered.push_str(&rest[..open]);
+
+        let close = rest[open..].find('}').ok_or(anyhow::Error::msg(format!(
+            "unclosed placeholder in hostname template {template:?}"
+        )))? + open;
+
+        let value = match &rest[open + 1..close] {
+            "project" => vars.project.clone(),
+            "lab" => vars.lab.clone(),
+            "owner" => vars.owner.clone(),
+            "booking_id" => vars.booking_id.clone(),
+            "booking_short" => vars.booking_short.clone(),
+            "role" => role.to_owned(),
+            "index" => index.to_string(),
+            other => {
+                return Err(anyhow::Error::msg(format!(
+                    "unknown placeholder {{{other}}} in hostname template {template:?}"
+                )))
+            }
+        };
+        rendered.push_str(&value);
+
+        rest = &rest[close + 1..];
+    }
+    rendered.push_str(rest);
+
+    Ok(rendered)
+}
+
+/// Picks hostnames for a booking's hosts, given as `(hostname, role)` in template order
+///
+/// Hosts that already have a hostname keep it, the rest are named from `template`
+pub fn assign(
+    template: &str,
+    vars: &HostnameVars,
+    hosts: &[(String, String)],
+) -> Result<Vec<String>, anyhow::Error> {
+    assign_after(template, vars, &[], hosts)
+}
+
+/// Like [`assign`], for hosts added to a booking that already has the named hosts
+/// `existing`, also given as `(hostname, role)`
+///
+/// Indices carry on from the hosts of the same role the booking already has.
+pub fn assign_after(
+    template: &str,
+    vars: &HostnameVars,
+    existing: &[(String, String)],
+    hosts: &[(String, String)],
+) -> Result<Vec<String>, anyhow::Error> {
+    let mut taken: HashSet<String> = existing
+        .iter()
+        .chain(hosts)
+        .map(|(hostname, _)| hostname.trim().to_lowercase())
+        .filter(|h| !h.is_empty())
+        .collect();
+    let mut indices: HashMap<&str, usize> = existing.iter().map(|(_, role)| role.as_str()).counts();
+
+    let mut names = Vec::new();
+    for (hostname, role) in hosts {
+        if !hostname.trim().is_empty() {
+            names.push(hostname.clone());
+            continue;
+        }
+
+        let index = indices.entry(role.as_str()).or_insert(0);
+        *index += 1;
+
+        let mut base = dns_label(&render(template, vars, &dns_label(role), *index)?);
+        if base.is_empty() {
+            base = "host".to_owned();
+        }
+
+        let mut name = base.clone();
+        let mut n = 2;
+        while taken.contains(&name) {
+            let suffix = format!("-{n}");
+            let mut prefix = base.clone();
+            prefix.truncate(MAX_LABEL_LEN - suffix.len());
+            name = format!("{}{suffix}", prefix.trim_end_matches('-'));
+            n += 1;
+        }
+
+        taken.insert(name.clone());
+        names.push(name);
+    }
+
+    Ok(names)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars() -> HostnameVars {
+        HostnameVars {
+            project: "Anuket".to_owned(),
+            lab: "UNH IOL".to_owned(),
+            owner: "jdoe".to_owned(),
+            booking_id: "1234".to_owned(),
+            booking_short: "9f86d081".to_owned(),
+        }
+    }
+
+    #[test]
+    fn test_dns_label() {
+        assert_eq!(dns_label("Hello, World!"), "hello-world");
+        assert_eq!(dns_label("--a__b--"), "a-b");
+        assert_eq!(dns_label(&"x".repeat(100)).len(), MAX_LABEL_LEN);
+    }
+
+    #[test]
+    fn test_assign() {
+        let hosts = [
+            (String::new(), "HPE Gen10".to_owned()),
+            ("controller".to_owned(), "arm".to_owned()),
+            (String::new(), "HPE Gen10".to_owned()),
+            (String::new(), "arm".to_owned()),
+        ];
+
+        assert_eq!(
+            assign(DEFAULT_TEMPLATE, &vars(), &hosts).unwrap(),
+            vec![
+                "anuket-9f86d081-hpe-gen101",
+                "controller",
+                "anuket-9f86d081-hpe-gen102",
+                "anuket-9f86d081-arm1",
+            ]
+        );
+
+        // a template without an index can't tell hosts apart, so they get suffixed
+        assert_eq!(
+            assign("{lab}-{role}", &vars(), &hosts).unwrap(),
+            vec![
+                "unh-iol-hpe-gen10",
+                "controller",
+                "unh-iol-hpe-gen10-2",
+                "unh-iol-arm",
+            ]
+        );
+
+        assert!(assign("{project}-{nope}", &vars(), &hosts).is_err());
+        assert!(assign("{project", &vars(), &hosts).is_err());
+    }
+
+    #[test]
+    fn test_assign_after() {
+        let existing = [
+            (
+                "anuket-9f86d081-hpe-gen101".to_owned(),
+                "HPE Gen10".to_owned(),
+            ),
+            ("controller".to_owned(), "arm".to_owned()),
+        ];
+        let hosts = [
+            (String::new(), "HPE Gen10".to_owned()),
+            (String::new(), "arm".to_owned()),
+            ("worker".to_owned(), "arm".to_owned()),
+        ];
+
+        assert_eq!(
+            assign_after(DEFAULT_TEMPLATE, &vars(), &existing, &hosts).unwrap(),
+            vec![
+                "anuket-9f86d081-hpe-gen102",
+                "anuket-9f86d081-arm2",
+                "worker",
+            ]
+        );
+        assert_eq!(
+            assign_after("{lab}-{role}", &vars(), &existing, &hosts[..1]).unwrap(),
+            vec!["unh-iol-hpe-gen10"]
+        );
+        assert_eq!(
+            assign_after("controller", &vars(), &existing, &hosts[1..2]).unwrap(),
+            vec!["controller-2"]
+        );
+    }
+}
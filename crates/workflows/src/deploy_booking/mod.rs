@@ -1,6 +1,6 @@
 use std::{
     cell::RefCell,
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, HashMap, HashSet},
     mem::swap,
     sync::atomic::{AtomicBool, AtomicU32},
     time::Duration,
@@ -8,17 +8,32 @@ use std::{
 
 use common::prelude::{itertools::Itertools, parking_lot::Mutex, *};
 
+pub mod audit_isolation;
+pub mod bmc;
+pub mod booking_env;
+pub mod cancel_provision;
 pub mod cobbler_set_config;
 pub mod cobbler_start_provision;
 pub mod configure_networking;
 pub mod deploy_host;
+pub mod expand;
+pub mod failure_bundle;
+pub mod hostnames;
 pub mod manage_eve_nodes;
 pub mod net_config;
+pub mod nic_inventory;
 pub mod notify;
+pub mod parameters;
 pub mod reachable;
+pub mod reboot;
+pub mod reimage;
+pub mod replace_host;
+pub mod rolling_reimage;
 pub mod set_boot;
 pub mod set_host_power_state;
+pub mod snapshot;
 pub mod sol;
+pub mod telemetry;
 pub mod wait_host_os_reachable;
 
 use config::Situation;
@@ -30,11 +45,11 @@ use maplit::hashmap;
 use models::{
     allocator::{AllocationReason, ResourceHandle, ResourceHandleInner},
     dashboard::{
-        self, Aggregate, BondGroupConfig, BookingMetadata, HostConfig, Instance, LifeCycleState,
-        Network, NetworkAssignmentMap, StatusSentiment, Template, VlanConnectionConfig,
+        self, Aggregate, AggregateSummary, BondGroupConfig, BookingEvent, BookingEventKind,
+        BookingMetadata, HostConfig, Instance, LifeCycleState, Network, NetworkAssignmentMap,
+        StatusSentiment, Template, VlanConnectionConfig,
     },
-    inventory::{Flavor, Host, IPInfo, IPNetwork, Vlan},
-    EasyLog,
+    inventory::{Flavor, Host, HostIssue, IPInfo, IPNetwork, Lab, TicketKind, Vlan},
 };
 use notifications::email::send_to_admins;
 use serde_yaml::{to_value, Mapping, Value};
@@ -42,7 +57,10 @@ use tracing::info;
 
 use crate::{
     deploy_booking::deploy_host::DeployHost,
-    resource_management::{allocator::*, mailbox::Mailbox, vpn::SyncVPN},
+    resource_management::{
+        allocator::*, mailbox::Mailbox, tickets::report_host_failure, vpn::SyncVPN,
+    },
+    utils::status_feed::PublishedLog,
 };
 use serde::{Deserialize, Serialize};
 
@@ -52,7 +70,10 @@ use users::ipa;
 
 use crate::resource_management::allocator;
 
-use self::notify::Notify;
+use self::{
+    audit_isolation::AuditNetworkIsolation,
+    notify::{Notify, NotifyContext},
+};
 
 tascii::mark_task!(BookingTask);
 #[derive(Debug, Clone, Hash, Serialize, Deserialize)]
@@ -74,6 +95,20 @@ impl AsyncRunnable for BookingTask {
 
         let agg = self.aggregate_id.get(&mut transaction).await.unwrap();
 
+        if let Err(e) = BookingEvent::record_committing(
+            agg.id,
+            BookingEventKind::ProvisioningStarted,
+            "hosts are being deployed",
+            None,
+        )
+        .await
+        {
+            tracing::warn!(
+                "Couldn't record start of provisioning for {:?}: {e:?}",
+                agg.id
+            );
+        }
+
         let mut handles = Vec::new();
 
         for config in agg.instances(&mut transaction).await.unwrap().into_iter() {
@@ -133,11 +168,37 @@ impl AsyncRunnable for BookingTask {
         tracing::info!("VPN config succeeded, hosts have all provisioned, now notify users their booking is done");
 
         if !results.iter().any(|one| one.is_err()) {
+            // networks are all up now, so check that they really are isolated
+            match context
+                .spawn(AuditNetworkIsolation {
+                    aggregate: self.aggregate_id,
+                })
+                .join()
+            {
+                Ok(findings) if findings.is_empty() => {
+                    tracing::info!("Booking {:?} passed isolation audit", self.aggregate_id)
+                }
+                Ok(findings) => {
+                    send_to_admins(format!(
+                        "Booking {:?} failed its network isolation audit: {findings:?}",
+                        self.aggregate_id
+                    ))
+                    .await
+                }
+                Err(e) => {
+                    send_to_admins(format!(
+                        "Couldn't audit network isolation of booking {:?}, error: {e:?}",
+                        self.aggregate_id
+                    ))
+                    .await
+                }
+            }
+
             // notify booking done, since everything is committed and saved
             let notify = context.spawn(Notify {
                 aggregate: self.aggregate_id,
                 situation: Situation::BookingCreated,
-                extra_context: vec![],
+                context: NotifyContext::Booking,
             });
 
             // make sure it finishes before we return
@@ -149,8 +210,23 @@ impl AsyncRunnable for BookingTask {
 
             // mark the aggregate as done provisioning
             let mut agg = self.aggregate_id.get(&mut transaction).await?;
-            agg.state = LifeCycleState::Active; // finished provisioning
-            agg.update(&mut transaction).await?;
+            Aggregate::transition(
+                &mut agg,
+                &mut transaction,
+                LifeCycleState::Active,
+                "every host finished provisioning",
+                None,
+            )
+            .await?;
+            AggregateSummary::refresh(&mut transaction, agg.id).await?;
+            BookingEvent::record(
+                &mut transaction,
+                agg.id,
+                BookingEventKind::Provisioned,
+                format!("all {} host(s) are up", results.len()),
+                None,
+            )
+            .await?;
 
             transaction.commit().await.unwrap();
 
@@ -166,14 +242,36 @@ impl AsyncRunnable for BookingTask {
                 }
             }
 
+            let failed = results.iter().filter(|one| one.is_err()).count();
+            BookingEvent::record(
+                &mut transaction,
+                self.aggregate_id,
+                BookingEventKind::ProvisioningFailed,
+                match failed == results.len() {
+                    true => {
+                        "every host failed to provision, so the booking was released".to_owned()
+                    }
+                    false => format!("{failed} of {} host(s) failed to provision", results.len()),
+                },
+                None,
+            )
+            .await?;
+
             if results.iter().all(|one| one.is_err()) {
                 tracing::info!("All hosts failed to provision, deallocating the aggregate");
                 Allocator::instance()
                     .deallocate_aggregate(&mut transaction, self.aggregate_id)
                     .await?;
                 let mut agg = self.aggregate_id.get(&mut transaction).await?;
-                agg.state = LifeCycleState::Done;
-                agg.update(&mut transaction).await?;
+                Aggregate::transition(
+                    &mut agg,
+                    &mut transaction,
+                    LifeCycleState::Failed,
+                    "every host failed to provision, so its hosts were released",
+                    None,
+                )
+                .await?;
+                AggregateSummary::refresh(&mut transaction, agg.id).await?;
             }
 
             transaction.commit().await.unwrap();
@@ -225,6 +323,9 @@ impl AsyncRunnable for AllocateHostTask {
         match res {
             Ok(v) => {
                 let host = v.0.get(&mut transaction).await.unwrap();
+                let issues = HostIssue::active_for_host(&mut transaction, host.id)
+                    .await
+                    .unwrap_or_default();
 
                 transaction
                     .commit()
@@ -242,6 +343,20 @@ impl AsyncRunnable for AllocateHostTask {
                     )
                     .await;
 
+                if !issues.is_empty() {
+                    self.instance
+                        .log(
+                            "Known Issues",
+                            format!(
+                                "{} has known issues: {}",
+                                host.server_name,
+                                issues.iter().map(|i| i.summary.as_str()).join("; ")
+                            ),
+                            StatusSentiment::InProgress,
+                        )
+                        .await;
+                }
+
                 tracing::info!("Allocation task allocated host {}", host.server_name);
 
                 Ok(v)
@@ -345,6 +460,7 @@ impl AsyncRunnable for SingleHostDeploy {
                             aggregate_id: self.for_aggregate,
                             using_instance: self.instance,
                             distribution: None,
+                            resume_from: None,
                         })
                         .join()
                     {
@@ -477,6 +593,61 @@ async fn free_hosts(hosts: Vec<ResourceHandle>, agg: FKey<Aggregate>) {
         .expect("Couldn't commit freeing of hosts");
 }
 
+/// Makes an empty booking in `lab` to hold `name`, hosts that were taken out of service
+pub(crate) async fn maintenance_aggregate(
+    t: &mut EasyTransaction<'_>,
+    lab: FKey<Lab>,
+    name: &str,
+) -> Result<Aggregate, anyhow::Error> {
+    let mut agg = Aggregate {
+        id: FKey::new_id_dangling(),
+        deleted: false,
+        users: vec![],
+        vlans: NewRow::new(NetworkAssignmentMap::empty()).insert(t).await?,
+        template: NewRow::new(Template {
+            id: FKey::new_id_dangling(),
+            name: name.to_owned(),
+            deleted: false,
+            description: name.to_owned(),
+            owner: None,
+            public: false,
+            networks: vec![],
+            hosts: vec![],
+            lab,
+            parameters: vec![],
+        })
+        .insert(t)
+        .await?,
+        metadata: BookingMetadata {
+            booking_id: None,
+            owner: None,
+            lab: None,
+            purpose: Some(format!("Hold {name}")),
+            project: None,
+            start: None,
+            end: None,
+            performance_tolerance: None,
+            spread: None,
+            display_name: None,
+            favorited_by: Vec::new(),
+            telemetry: false,
+            exempt_from_expiry: false,
+            ownership_override: None,
+        },
+        state: LifeCycleState::Active,
+        configuration: dashboard::AggregateConfiguration {
+            ipmi_username: String::new(),
+            ipmi_password: String::new(),
+            env: Default::default(),
+        },
+        lab,
+    };
+
+    agg.id = NewRow::new(agg.clone()).insert(t).await?;
+
+    Ok(agg)
+}
+
 /// Call this in the event that provisioning succeeded with one
 /// host but failed with others, passing the hosts it failed on
 ///
@@ -493,51 +664,13 @@ async fn mark_not_working(hosts: Vec<ResourceHandle>, original_agg: FKey<Aggrega
         .lab;
 
     if !hosts.is_empty() {
-        let agg = Aggregate {
-            id: FKey::new_id_dangling(),
-            deleted: false,
-            users: vec![],
-            vlans: NewRow::new(NetworkAssignmentMap::empty())
-                .insert(&mut transaction)
-                .await
-                .unwrap(),
-            template: NewRow::new(Template {
-                id: FKey::new_id_dangling(),
-                name: String::from("bad hosts"),
-                deleted: false,
-                description: String::from("bad hosts"),
-                owner: None,
-                public: false,
-                networks: vec![],
-                hosts: vec![],
-                lab,
-            })
-            .insert(&mut transaction)
-            .await
-            .unwrap(),
-            metadata: BookingMetadata {
-                booking_id: None,
-                owner: None,
-                lab: None,
-                purpose: Some(String::from("Hold bad hosts")),
-                project: None,
-                start: None,
-                end: None,
-            },
-            state: LifeCycleState::Active,
-            configuration: dashboard::AggregateConfiguration {
-                ipmi_username: String::new(),
-                ipmi_password: String::new(),
-            },
-            lab,
-        };
-
-        let agg_id = NewRow::new(agg.clone())
-            .insert(&mut transaction)
+        let agg = maintenance_aggregate(&mut transaction, lab, "bad hosts")
             .await
             .unwrap();
+        let agg_id = agg.id;
 
         let mut host_names = Vec::new();
+        let mut bad_hosts = Vec::new();
 
         for handle in hosts {
             match allocator
@@ -550,6 +683,7 @@ async fn mark_not_working(hosts: Vec<ResourceHandle>, original_agg: FKey<Aggrega
                         let host = h.get(&mut transaction).await.unwrap();
 
                         host_names.push(host.server_name.clone());
+                        bad_hosts.push(h);
 
                         let res = allocator
                             .allocate_specific_host(
@@ -581,6 +715,21 @@ async fn mark_not_working(hosts: Vec<ResourceHandle>, original_agg: FKey<Aggrega
             agg,
         ))
         .await;
+
+        for host in bad_hosts {
+            report_host_failure(
+                host,
+                TicketKind::DeployFailures,
+                format!(
+                    "Deploying for booking {:?} failed on this host after repeated attempts, \
+                    while another host deployed the same configuration fine. \
+                    It has been moved to maintenance booking {:?}.",
+                    original_agg.into_id(),
+                    agg_id.into_id()
+                ),
+            )
+            .await;
+        }
     }
 }
 
@@ -617,6 +766,16 @@ pub async fn generate_cloud_config(
         ci_serialize_sysinfo(transaction, conf.clone(), host_id, aggregate_id),
     );
 
+    let env = aggregate_id
+        .get(transaction)
+        .await?
+        .configuration
+        .env
+        .clone();
+    if !env.is_empty() {
+        cloud_config.insert("write_files".into(), ci_serialize_env(&env));
+    }
+
     // Serialize to a YAML String
     let yaml = serde_yaml::to_string(&cloud_config).expect("Expected to convert to string.");
     tracing::info!("Made cloud config cloud-config:\n{yaml}");
@@ -687,6 +846,19 @@ async fn ci_serialize_users(
         user_list.push(user_dict.into());
     }
 
+    // lets LibLaaS reboot the host from its OS, and add and remove collaborators, once it is up
+    if let Some(access) = config::settings().host_access.as_ref() {
+        let mut user_dict: Mapping = Mapping::new();
+        user_dict.insert("name".into(), Value::String(access.username.clone()));
+        user_dict.insert("lock_passwd".into(), true.into());
+        user_dict.insert("sudo".into(), "ALL=(ALL) NOPASSWD:ALL".into());
+        user_dict.insert(
+            "ssh_authorized_keys".into(),
+            vec![access.public_key.clone()].into(),
+        );
+        user_list.push(user_dict.into());
+    }
+
     // Value to be returned should be a list that contains "default" and then a dict for each user
     user_list.into()
 }
@@ -1324,6 +1496,7 @@ async fn ci_serialize_runcmds(
         command(val("echo 'Running apt -y install curl'".to_string()));
         command(val("sleep 2".to_string()));
         command(val("sudo apt -y install curl || true"));
+        command(val("sudo apt -y install ethtool || true"));
     }
 
     // we've installed the packages we need to configure before going dark,
@@ -1424,6 +1597,29 @@ async fn ci_serialize_runcmds(
         command(val(format!("while ! ping -c 1 -W 1 {v}; do echo 'waiting for networking to come up after configuring production networks' && sleep 10; done || true")));
     }
 
+    // report what the nics came up as, now that the production links are up
+    if let Ok(ep) = Mailbox::get_endpoint_hook(instance_id, nic_inventory::REPORT_HOOK).await {
+        let interfaces = host
+            .ports(transaction)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|p| p.name.clone())
+            .collect_vec();
+
+        command(val(nic_inventory::collect_command(
+            &interfaces,
+            &ep.to_url(),
+        )));
+    }
+
+    // the booking asked for usage telemetry if it has a hook to report to
+    if let Ok(ep) = Mailbox::get_endpoint_hook(instance_id, telemetry::REPORT_HOOK).await {
+        for cmd in telemetry::install_commands(&ep.to_url()) {
+            command(val(cmd));
+        }
+    }
+
     // do final phone home
     if let Ok(ep) = Mailbox::get_endpoint_hook(instance_id, "post_provision").await {
         let url = ep.to_url();
@@ -1451,6 +1647,16 @@ async fn ci_serialize_runcmds(
     to_value(commands).unwrap()
 }
 
+/// Writes the variables of the booking to where its recipes expect them
+fn ci_serialize_env(env: &BTreeMap<String, String>) -> Value {
+    let mut file = Mapping::new();
+    file.insert("path".into(), booking_env::ENV_FILE.into());
+    file.insert("permissions".into(), "0600".into());
+    file.insert("content".into(), booking_env::env_file(env).into());
+
+    Value::Sequence(vec![Value::Mapping(file)])
+}
+
 fn ci_serialize_sysinfo(
     _transaction: &mut EasyTransaction<'_>,
     _conf: HostConfig,
@@ -0,0 +1,181 @@
+//! The NIC firmware and driver versions and link speeds a host ended up with once deployed
+//!
+//! Dataplane performance problems are often down to a particular firmware, so cloud-init
+//! has the host report what `ethtool` and sysfs say about each of its interfaces back
+//! through the mailbox once production networking is up. The report is kept in the
+//! instance metadata, where booking status picks it up.
+
+use common::prelude::{
+    anyhow,
+    chrono::{DateTime, Utc},
+    serde_json,
+};
+use dal::{new_client, AsEasyTransaction, FKey};
+use models::dashboard::Instance;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// The mailbox hook that the host posts its report to
+pub const REPORT_HOOK: &str = "nic_report";
+
+/// Key in the instance metadata holding the last report
+const INVENTORY_KEY: &str = "nic_inventory";
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema, PartialEq, Eq)]
+pub struct NicInfo {
+    pub interface: String,
+    pub driver: Option<String>,
+    pub driver_version: Option<String>,
+    pub firmware_version: Option<String>,
+    /// PCI address of the NIC
+    pub bus_info: Option<String>,
+    /// None if the link is down
+    pub speed_mbps: Option<u32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, PartialEq)]
+pub struct NicInventory {
+    #[schemars(with = "String")]
+    pub collected: DateTime<Utc>,
+    pub nics: Vec<NicInfo>,
+}
+
+/// The shell command that reports on `interfaces` to the mailbox endpoint at `url`
+///
+/// Never fails, since a missing report shouldn't hold up provisioning
+pub fn collect_command(interfaces: &[String], url: &str) -> String {
+    // port names come from the inventory, but they end up in a shell command all the same
+    let interfaces: Vec<String> = interfaces
+        .iter()
+        .map(|i| {
+            i.chars()
+                .filter(|c| c.is_ascii_alphanumeric() || "._-".contains(*c))
+                .collect()
+        })
+        .filter(|i: &String| !i.is_empty())
+        .collect();
+
+    format!(
+        r#"(for i in {}; do echo "interface: $i"; echo "driver: $(basename "$(readlink /sys/class/net/$i/device/driver)")"; ethtool -i $i 2>/dev/null; echo "speed: $(cat /sys/class/net/$i/speed 2>/dev/null)"; done) | curl -X POST -H 'Content-Type: text/plain' --data-binary @- {url}/push || true"#,
+        interfaces.join(" ")
+    )
+}
+
+/// Reads a report made by [`collect_command`]
+pub fn parse(report: &str) -> Vec<NicInfo> {
+    let mut nics: Vec<NicInfo> = Vec::new();
+
+    for line in report.lines() {
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        let given = (!value.is_empty()).then(|| value.to_owned());
+
+        if key.trim() == "interface" {
+            nics.push(NicInfo {
+                interface: value.to_owned(),
+                ..Default::default()
+            });
+            continue;
+        }
+
+        let Some(nic) = nics.last_mut() else {
+            continue;
+        };
+
+        match key.trim() {
+            // sysfs gives the driver first, ethtool knows better if it ran
+            "driver" if given.is_some() => nic.driver = given,
+            "version" => nic.driver_version = given,
+            "firmware-version" => nic.firmware_version = given,
+            "bus-info" => nic.bus_info = given,
+            // the kernel reports -1 for links that are down
+            "speed" => nic.speed_mbps = value.parse().ok(),
+            _ => {}
+        }
+    }
+
+    nics
+}
+
+/// Keeps the report the host sent for `instance`, replacing any earlier one
+pub async fn record(instance: FKey<Instance>, report: &str) -> Result<NicInventory, anyhow::Error> {
+    let inventory = NicInventory {
+        collected: Utc::now(),
+        nics: parse(report),
+    };
+
+    let mut client = new_client().await?;
+    let mut transaction = client.easy_transaction().await?;
+
+    let mut instance = instance.get(&mut transaction).await?;
+    instance
+        .metadata
+        .insert(INVENTORY_KEY.to_owned(), serde_json::to_value(&inventory)?);
+    instance.update(&mut transaction).await?;
+
+    transaction.commit().await?;
+
+    Ok(inventory)
+}
+
+/// The last report kept for `instance`, if its host has sent one
+pub fn recorded(instance: &Instance) -> Option<NicInventory> {
+    instance
+        .metadata
+        .get(INVENTORY_KEY)
+        .and_then(|v| serde_json::from_value(v.clone()).ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let report = "\
+interface: ens1f0
+driver: ice
+driver: ice
+version: 6.5.0-14-generic
+firmware-version: 4.20 0x8001778b 1.3346.0
+expansion-rom-version:
+bus-info: 0000:31:00.0
+supports-statistics: yes
+speed: 25000
+interface: eno1
+driver:
+speed: -1
+";
+
+        assert_eq!(
+            parse(report),
+            vec![
+                NicInfo {
+                    interface: "ens1f0".to_owned(),
+                    driver: Some("ice".to_owned()),
+                    driver_version: Some("6.5.0-14-generic".to_owned()),
+                    firmware_version: Some("4.20 0x8001778b 1.3346.0".to_owned()),
+                    bus_info: Some("0000:31:00.0".to_owned()),
+                    speed_mbps: Some(25000),
+                },
+                NicInfo {
+                    interface: "eno1".to_owned(),
+                    ..Default::default()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_collect_command() {
+        let cmd = collect_command(
+            &["eno1".to_owned(), "ens1f0; rm -rf /".to_owned()],
+            "http://mailbox/1/2",
+        );
+
+        assert!(cmd.starts_with("(for i in eno1 ens1f0rm-rf; do"));
+        assert!(cmd.ends_with("http://mailbox/1/2/push || true"));
+    }
+}
@@ -1,21 +1,65 @@
-use std::collections::HashMap;
-
-use common::prelude::{chrono, serde_json::json, tracing};
+use common::prelude::{
+    anyhow,
+    chrono::{DateTime, Utc},
+    serde_json::json,
+    tracing,
+};
 use config::{settings, Situation};
 use dal::{new_client, AsEasyTransaction, DBTable, FKey};
 
 use models::dashboard::{Aggregate, Instance};
 use notifications::{
-    booking_ended, booking_ending, booking_started, collaborator_added, request_booking_extension,
-    BookingInfo, Env,
+    booking_ended, booking_ending, booking_started, collaborator_added, contacts,
+    email::send_to_admins, request_booking_extension, BookingInfo, Env,
 };
+use schemars::JsonSchema;
 use tascii::{prelude::*, task_trait::AsyncRunnable};
 
 #[derive(Debug, Clone, Hash, Serialize, Deserialize)]
 pub struct Notify {
     pub aggregate: FKey<Aggregate>,
     pub situation: Situation,
-    pub extra_context: Vec<(String, String)>,
+    pub context: NotifyContext,
+}
+
+/// What a notification needs to know beyond the booking it's about
+#[derive(Debug, Clone, Hash, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum NotifyContext {
+    /// Everything comes from the booking
+    Booking,
+    /// The booking is ending, at `ending` instead of its set end if given
+    Expiring {
+        #[schemars(with = "Option<String>")]
+        ending: Option<DateTime<Utc>>,
+    },
+    /// The owner asked for the booking to run until `date`
+    ExtensionRequest { date: String, reason: String },
+}
+
+impl NotifyContext {
+    /// Checks that this is the context notifications of `situation` are sent with
+    pub fn validate(&self, situation: &Situation) -> Result<(), anyhow::Error> {
+        match (situation, self) {
+            (Situation::RequestBookingExtension, Self::ExtensionRequest { date, reason }) => {
+                if date.trim().is_empty() {
+                    return Err(anyhow::Error::msg("an extension request needs a date"));
+                }
+                if reason.trim().is_empty() {
+                    return Err(anyhow::Error::msg("an extension request needs a reason"));
+                }
+                Ok(())
+            }
+            (Situation::RequestBookingExtension, _) => Err(anyhow::Error::msg(
+                "an extension request notification needs the request's date and reason",
+            )),
+            (Situation::BookingExpiring, Self::Booking | Self::Expiring { .. }) => Ok(()),
+            (_, Self::Booking) => Ok(()),
+            (situation, other) => Err(anyhow::Error::msg(format!(
+                "a {situation} notification can't be sent with {other:?}"
+            ))),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy, Hash, Serialize, Deserialize)]
@@ -28,6 +72,11 @@ impl AsyncRunnable for Notify {
     type Output = ();
 
     async fn run(&mut self, _context: &Context) -> Result<Self::Output, TaskError> {
+        // everything dispatched is validated up front, but tasks enrolled directly may not be
+        self.context
+            .validate(&self.situation)
+            .map_err(|e| TaskError::Reason(e.to_string()))?;
+
         let mut client = new_client().await.expect("Expected to connect to db");
         let mut transaction = client
             .easy_transaction()
@@ -58,8 +107,6 @@ impl AsyncRunnable for Notify {
             //project: agg.metadata.project.clone().unwrap_or("None".to_owned()),
         };
 
-        let context_map: HashMap<String, String> =
-            HashMap::from_iter(self.extra_context.clone().into_iter());
         let info = BookingInfo {
             owner: agg.metadata.owner.clone().unwrap_or("None".to_owned()),
             collaborators: agg
@@ -80,12 +127,11 @@ impl AsyncRunnable for Notify {
             purpose: agg.metadata.purpose.clone().unwrap_or("None".to_owned()),
             project: agg.metadata.project.clone().unwrap_or("None".to_owned()),
             start_date: agg.metadata.start,
-            end_date: match context_map.get("ending_override") {
-                Some(o) => match chrono::DateTime::parse_from_rfc2822(&o.to_string()) {
-                    Ok(parsed) => Some(parsed.with_timezone(&chrono::Utc)),
-                    Err(_) => agg.metadata.end,
-                },
-                None => agg.metadata.end,
+            end_date: match self.context {
+                NotifyContext::Expiring {
+                    ending: Some(ending),
+                } => Some(ending),
+                _ => agg.metadata.end,
             },
             dashboard_url: match Some(agg.lab) {
                 Some(p) => settings()
@@ -106,6 +152,29 @@ impl AsyncRunnable for Notify {
             configuration: agg.configuration.clone(),
         };
 
+        if !matches!(self.situation, Situation::RequestBookingExtension) {
+            match contacts::unreachable(&mut transaction, &[info.owner.clone()]).await {
+                Ok(unreachable) => {
+                    for contact in unreachable {
+                        send_to_admins(format!(
+                            "Booking {} is being sent a {} notice, but mail to its owner {} at {} \
+                            has been bouncing ({}). Please reach out to them another way.",
+                            info.id,
+                            self.situation,
+                            contact.username,
+                            contact.email,
+                            contact.last_error.as_deref().unwrap_or("no reason given"),
+                        ))
+                        .await;
+                    }
+                }
+                Err(e) => tracing::error!(
+                    "Couldn't check whether {} can be emailed: {e:?}",
+                    info.owner
+                ),
+            }
+        }
+
         transaction.commit().await.unwrap();
 
         match self.situation.clone() {
@@ -148,16 +217,15 @@ impl AsyncRunnable for Notify {
             Situation::CollaboratorAdded(users) => collaborator_added(&env, &info, users)
                 .await
                 .expect("couldn't notify users"),
-            Situation::RequestBookingExtension => request_booking_extension(
-                &env,
-                &info,
-                context_map.get("extension_date").unwrap_or(&format!("N/A")),
-                context_map
-                    .get("extension_reason")
-                    .unwrap_or(&format!("N/A")),
-            )
-            .await
-            .expect("couldn't notify admins"),
+            Situation::RequestBookingExtension => {
+                let NotifyContext::ExtensionRequest { date, reason } = &self.context else {
+                    unreachable!("context was validated to be an extension request")
+                };
+
+                request_booking_extension(&env, &info, date, reason)
+                    .await
+                    .expect("couldn't notify admins")
+            }
             _ => todo!(),
         }
 
@@ -165,10 +233,68 @@ impl AsyncRunnable for Notify {
     }
 
     fn identifier() -> TaskIdentifier {
-        TaskIdentifier::named("send_notifications").versioned(1)
+        TaskIdentifier::named("send_notifications").versioned(2)
     }
 
     fn retry_count(&self) -> usize {
         0
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::prelude::serde_json;
+
+    #[test]
+    fn test_validate() {
+        let extension = NotifyContext::ExtensionRequest {
+            date: "2024-05-01".to_owned(),
+            reason: "still benchmarking".to_owned(),
+        };
+
+        assert!(extension
+            .validate(&Situation::RequestBookingExtension)
+            .is_ok());
+        assert!(NotifyContext::Booking
+            .validate(&Situation::RequestBookingExtension)
+            .is_err());
+        assert!(NotifyContext::ExtensionRequest {
+            date: "2024-05-01".to_owned(),
+            reason: " ".to_owned(),
+        }
+        .validate(&Situation::RequestBookingExtension)
+        .is_err());
+
+        let expiring = NotifyContext::Expiring {
+            ending: Some(Utc::now()),
+        };
+        assert!(expiring.validate(&Situation::BookingExpiring).is_ok());
+        assert!(expiring.validate(&Situation::BookingCreated).is_err());
+        assert!(NotifyContext::Booking
+            .validate(&Situation::BookingCreated)
+            .is_ok());
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for context in [
+            NotifyContext::Booking,
+            NotifyContext::Expiring {
+                ending: Some(Utc::now()),
+            },
+            NotifyContext::ExtensionRequest {
+                date: "2024-05-01".to_owned(),
+                reason: "still benchmarking".to_owned(),
+            },
+        ] {
+            let json = serde_json::to_string(&context).unwrap();
+            assert_eq!(
+                serde_json::from_str::<NotifyContext>(&json).unwrap(),
+                context
+            );
+        }
+
+        assert!(serde_json::from_str::<NotifyContext>(r#"{"kind": "extension_request"}"#).is_err());
+    }
+}
@@ -0,0 +1,581 @@
+//! Templates that leave some of their shape to each booking
+//!
+//! A template can declare how many copies of a host or network a booking gets, and which
+//! image its hosts run, as parameters with bounds. Bookings give values for any of them,
+//! and [`expand`] turns the template and those values into the hosts and networks the
+//! booking is made of, so one template can stand in for a family of near-duplicates.
+
+use std::collections::{HashMap, HashSet};
+
+use common::prelude::anyhow;
+use dal::{EasyTransaction, FKey, NewRow};
+use models::dashboard::{
+    HostConfig, Image, Network, ParameterKind, ParameterValue, Template, TemplateParameter,
+    VlanConnectionConfig,
+};
+use thiserror::Error;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum ParameterError {
+    #[error("the template has no parameter named {0}")]
+    Unknown(String),
+    #[error("parameters need a name")]
+    Unnamed,
+    #[error("parameter {0} is declared more than once")]
+    Duplicate(String),
+    #[error("parameter {name} refers to {target}, which isn't part of the template")]
+    NoTarget { name: String, target: String },
+    #[error("{0} is controlled by more than one parameter")]
+    Conflict(String),
+    #[error("parameter {name} must allow its default")]
+    BadDefault { name: String },
+    #[error("parameter {name} takes {expected}")]
+    WrongType {
+        name: String,
+        expected: &'static str,
+    },
+    #[error("parameter {name} must be between {min} and {max}, not {value}")]
+    OutOfBounds {
+        name: String,
+        value: u32,
+        min: u32,
+        max: u32,
+    },
+    #[error("parameter {name} doesn't allow that image")]
+    NotAChoice { name: String },
+    #[error("the booking would have no hosts")]
+    NoHosts,
+}
+
+/// The hosts and networks a booking of a parameterized template is made of
+#[derive(Debug, Clone)]
+pub struct Expanded {
+    pub hosts: Vec<HostConfig>,
+    pub networks: Vec<FKey<Network>>,
+}
+
+/// What every parameter of a template came out as for one booking
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Resolved {
+    host_counts: HashMap<String, u32>,
+    vlan_counts: HashMap<String, u32>,
+    /// Images for hosts by their name in the template
+    images: HashMap<String, FKey<Image>>,
+    /// The image for every host that isn't in `images`
+    all_images: Option<FKey<Image>>,
+}
+
+impl Resolved {
+    fn image_for(&self, host: &HostConfig) -> FKey<Image> {
+        self.images
+            .get(&host.hostname)
+            .copied()
+            .or(self.all_images)
+            .unwrap_or(host.image)
+    }
+}
+
+/// Makes sure the parameters of a template only refer to what is in it, and that their defaults fit
+pub fn check(
+    parameters: &[TemplateParameter],
+    hosts: &[HostConfig],
+    networks: &[String],
+) -> Result<(), ParameterError> {
+    let hostnames: HashSet<&str> = hosts.iter().map(|h| h.hostname.as_str()).collect();
+
+    let mut names = HashSet::new();
+    let mut counted = HashSet::new();
+    let mut imaged = HashSet::new();
+    let mut images_all = false;
+
+    let no_target = |name: &str, target: &str| ParameterError::NoTarget {
+        name: name.to_owned(),
+        target: target.to_owned(),
+    };
+
+    for param in parameters {
+        let name = param.name.as_str();
+        if name.trim().is_empty() {
+            return Err(ParameterError::Unnamed);
+        }
+        if !names.insert(name) {
+            return Err(ParameterError::Duplicate(name.to_owned()));
+        }
+
+        match &param.kind {
+            ParameterKind::HostCount {
+                hostname,
+                min,
+                max,
+                default,
+            } => {
+                if !hostnames.contains(hostname.as_str()) {
+                    return Err(no_target(name, hostname));
+                }
+                if !counted.insert(format!("host {hostname}")) {
+                    return Err(ParameterError::Conflict(format!("host {hostname}")));
+                }
+                if min > max || !(min..=max).contains(&default) {
+                    return Err(ParameterError::BadDefault {
+                        name: name.to_owned(),
+                    });
+                }
+            }
+            ParameterKind::VlanCount {
+                network,
+                min,
+                max,
+                default,
+            } => {
+                if !networks.contains(network) {
+                    return Err(no_target(name, network));
+                }
+                if !counted.insert(format!("network {network}")) {
+                    return Err(ParameterError::Conflict(format!("network {network}")));
+                }
+                if min > max || !(min..=max).contains(&default) {
+                    return Err(ParameterError::BadDefault {
+                        name: name.to_owned(),
+                    });
+                }
+            }
+            ParameterKind::Image {
+                hostnames: targets,
+                choices,
+                default,
+            } => {
+                if !choices.contains(default) {
+                    return Err(ParameterError::BadDefault {
+                        name: name.to_owned(),
+                    });
+                }
+
+                if targets.is_empty() {
+                    if images_all || !imaged.is_empty() {
+                        return Err(ParameterError::Conflict(
+                            "the image of every host".to_owned(),
+                        ));
+                    }
+                    images_all = true;
+                }
+
+                for hostname in targets {
+                    if !hostnames.contains(hostname.as_str()) {
+                        return Err(no_target(name, hostname));
+                    }
+                    if images_all || !imaged.insert(hostname.as_str()) {
+                        return Err(ParameterError::Conflict(format!(
+                            "the image of host {hostname}"
+                        )));
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Makes sure the values a booking gives fit the parameters of its template
+pub fn validate(
+    parameters: &[TemplateParameter],
+    values: &HashMap<String, ParameterValue>,
+) -> Result<(), ParameterError> {
+    resolve(parameters, values).map(|_| ())
+}
+
+/// The images the hosts of a booking of `template` would run, given the values it gives
+pub fn images(
+    template: &Template,
+    values: &HashMap<String, ParameterValue>,
+) -> Result<Vec<FKey<Image>>, ParameterError> {
+    let resolved = resolve(&template.parameters, values)?;
+
+    Ok(template
+        .hosts
+        .iter()
+        .map(|h| resolved.image_for(h))
+        .collect())
+}
+
+fn resolve(
+    parameters: &[TemplateParameter],
+    values: &HashMap<String, ParameterValue>,
+) -> Result<Resolved, ParameterError> {
+    if let Some(unknown) = values
+        .keys()
+        .find(|k| !parameters.iter().any(|p| &p.name == *k))
+    {
+        return Err(ParameterError::Unknown(unknown.clone()));
+    }
+
+    let mut resolved = Resolved::default();
+
+    for param in parameters {
+        let name = param.name.clone();
+        let value = values.get(&param.name).copied();
+
+        let count = |min: u32, max: u32, default: u32| match value {
+            None => Ok(default),
+            Some(ParameterValue::Count(value)) if (min..=max).contains(&value) => Ok(value),
+            Some(ParameterValue::Count(value)) => Err(ParameterError::OutOfBounds {
+                name: name.clone(),
+                value,
+                min,
+                max,
+            }),
+            Some(ParameterValue::Image(_)) => Err(ParameterError::WrongType {
+                name: name.clone(),
+                expected: "a count",
+            }),
+        };
+
+        match &param.kind {
+            ParameterKind::HostCount {
+                hostname,
+                min,
+                max,
+                default,
+            } => {
+                resolved
+                    .host_counts
+                    .insert(hostname.clone(), count(*min, *max, *default)?);
+            }
+            ParameterKind::VlanCount {
+                network,
+                min,
+                max,
+                default,
+            } => {
+                resolved
+                    .vlan_counts
+                    .insert(network.clone(), count(*min, *max, *default)?);
+            }
+            ParameterKind::Image {
+                hostnames,
+                choices,
+                default,
+            } => {
+                let image = match value {
+                    None => *default,
+                    Some(ParameterValue::Image(image)) if choices.contains(&image) => image,
+                    Some(ParameterValue::Image(_)) => {
+                        return Err(ParameterError::NotAChoice { name })
+                    }
+                    Some(ParameterValue::Count(_)) => {
+                        return Err(ParameterError::WrongType {
+                            name,
+                            expected: "an image",
+                        })
+                    }
+                };
+
+                match hostnames.is_empty() {
+                    true => resolved.all_images = Some(image),
+                    false => {
+                        for hostname in hostnames {
+                            resolved.images.insert(hostname.clone(), image);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(resolved)
+}
+
+/// Copies hosts and rewires their connections, given which networks each template network became
+///
+/// Extra copies of a network are always tagged, since a bond can only carry one untagged network
+fn expand_hosts(
+    hosts: &[HostConfig],
+    resolved: &Resolved,
+    networks: &HashMap<FKey<Network>, Vec<FKey<Network>>>,
+) -> Vec<HostConfig> {
+    let mut expanded = Vec::new();
+
+    for host in hosts {
+        let mut host = host.clone();
+
+        host.image = resolved.image_for(&host);
+
+        for bond in host.connections.iter_mut() {
+            bond.connects_to = bond
+                .connects_to
+                .iter()
+                .flat_map(|vcc| match networks.get(&vcc.network) {
+                    Some(copies) => copies
+                        .iter()
+                        .enumerate()
+                        .map(|(i, network)| VlanConnectionConfig {
+                            network: *network,
+                            tagged: vcc.tagged || i > 0,
+                        })
+                        .collect(),
+                    None => vec![vcc.clone()],
+                })
+                .collect();
+        }
+
+        match resolved.host_counts.get(&host.hostname) {
+            None => expanded.push(host),
+            Some(count) => {
+                for i in 1..=*count {
+                    let mut copy = host.clone();
+                    if !copy.hostname.trim().is_empty() {
+                        copy.hostname = format!("{}-{i}", host.hostname);
+                    }
+                    expanded.push(copy);
+                }
+            }
+        }
+    }
+
+    expanded
+}
+
+/// The hosts and networks of a booking of `template`, creating any extra copies of networks it asks for
+pub async fn expand(
+    t: &mut EasyTransaction<'_>,
+    template: &Template,
+    values: &HashMap<String, ParameterValue>,
+) -> Result<Expanded, anyhow::Error> {
+    if template.parameters.is_empty() && values.is_empty() {
+        return Ok(Expanded {
+            hosts: template.hosts.clone(),
+            networks: template.networks.clone(),
+        });
+    }
+
+    let resolved = resolve(&template.parameters, values)?;
+
+    let mut networks = Vec::new();
+    let mut copies = HashMap::new();
+    for id in template.networks.iter().copied() {
+        let network = id.get(t).await?.into_inner();
+
+        let count = match resolved.vlan_counts.get(&network.name) {
+            Some(count) => *count,
+            None => {
+                networks.push(id);
+                continue;
+            }
+        };
+
+        let mut made = Vec::new();
+        if count > 0 {
+            made.push(id);
+        }
+        for i in 2..=count {
+            made.push(
+                NewRow::new(Network {
+                    id: FKey::new_id_dangling(),
+                    name: format!("{}-{i}", network.name),
+                    public: network.public,
+                })
+                .insert(t)
+                .await?,
+            );
+        }
+
+        networks.extend(made.iter().copied());
+        copies.insert(id, made);
+    }
+
+    let hosts = expand_hosts(&template.hosts, &resolved, &copies);
+    if hosts.is_empty() && !template.hosts.is_empty() {
+        return Err(ParameterError::NoHosts.into());
+    }
+
+    Ok(Expanded { hosts, networks })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use models::dashboard::BondGroupConfig;
+
+    fn host(hostname: &str, network: FKey<Network>, image: FKey<Image>) -> HostConfig {
+        HostConfig {
+            hostname: hostname.to_owned(),
+            flavor: FKey::new_id_dangling(),
+            image,
+            cifile: vec![],
+            connections: vec![BondGroupConfig {
+                connects_to: [VlanConnectionConfig {
+                    network,
+                    tagged: false,
+                }]
+                .into_iter()
+                .collect(),
+                member_interfaces: ["eno1".to_owned()].into_iter().collect(),
+            }],
+            constraints: None,
+            kernel_args: vec![],
+        }
+    }
+
+    fn param(name: &str, kind: ParameterKind) -> TemplateParameter {
+        TemplateParameter {
+            name: name.to_owned(),
+            description: String::new(),
+            kind,
+        }
+    }
+
+    fn workers(min: u32, max: u32, default: u32) -> TemplateParameter {
+        param(
+            "workers",
+            ParameterKind::HostCount {
+                hostname: "worker".to_owned(),
+                min,
+                max,
+                default,
+            },
+        )
+    }
+
+    #[test]
+    fn test_check() {
+        let network = FKey::new_id_dangling();
+        let image = FKey::new_id_dangling();
+        let hosts = vec![
+            host("control", network, image),
+            host("worker", network, image),
+        ];
+        let networks = vec!["public".to_owned()];
+
+        assert_eq!(check(&[workers(1, 5, 3)], &hosts, &networks), Ok(()));
+        assert_eq!(
+            check(&[workers(1, 5, 7)], &hosts, &networks),
+            Err(ParameterError::BadDefault {
+                name: "workers".to_owned()
+            })
+        );
+        assert_eq!(
+            check(&[workers(1, 5, 3), workers(1, 5, 3)], &hosts, &networks),
+            Err(ParameterError::Duplicate("workers".to_owned()))
+        );
+
+        let vlans = param(
+            "vlans",
+            ParameterKind::VlanCount {
+                network: "private".to_owned(),
+                min: 1,
+                max: 4,
+                default: 1,
+            },
+        );
+        assert!(matches!(
+            check(&[vlans], &hosts, &networks),
+            Err(ParameterError::NoTarget { .. })
+        ));
+
+        let os = |name: &str, hostnames: Vec<String>| {
+            param(
+                name,
+                ParameterKind::Image {
+                    hostnames,
+                    choices: vec![image],
+                    default: image,
+                },
+            )
+        };
+        assert_eq!(
+            check(
+                &[os("os", vec![]), os("worker_os", vec!["worker".to_owned()])],
+                &hosts,
+                &networks
+            ),
+            Err(ParameterError::Conflict(
+                "the image of host worker".to_owned()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_expand_hosts() {
+        let network = FKey::new_id_dangling();
+        let copy = FKey::new_id_dangling();
+        let ubuntu = FKey::new_id_dangling();
+        let fedora = FKey::new_id_dangling();
+        let hosts = vec![
+            host("control", network, ubuntu),
+            host("worker", network, ubuntu),
+        ];
+
+        let parameters = vec![
+            workers(1, 5, 2),
+            param(
+                "worker_os",
+                ParameterKind::Image {
+                    hostnames: vec!["worker".to_owned()],
+                    choices: vec![ubuntu, fedora],
+                    default: ubuntu,
+                },
+            ),
+        ];
+
+        let values = [
+            ("workers".to_owned(), ParameterValue::Count(3)),
+            ("worker_os".to_owned(), ParameterValue::Image(fedora)),
+        ]
+        .into_iter()
+        .collect();
+        let resolved = resolve(&parameters, &values).unwrap();
+        let copies = [(network, vec![network, copy])].into_iter().collect();
+
+        let expanded = expand_hosts(&hosts, &resolved, &copies);
+
+        assert_eq!(
+            expanded
+                .iter()
+                .map(|h| h.hostname.as_str())
+                .collect::<Vec<_>>(),
+            vec!["control", "worker-1", "worker-2", "worker-3"]
+        );
+        assert!(expanded[0].image == ubuntu);
+        assert!(expanded[1..].iter().all(|h| h.image == fedora));
+
+        let connections = &expanded[0].connections[0].connects_to;
+        assert_eq!(connections.len(), 2);
+        assert!(connections.contains(&VlanConnectionConfig {
+            network,
+            tagged: false,
+        }));
+        assert!(connections.contains(&VlanConnectionConfig {
+            network: copy,
+            tagged: true,
+        }));
+
+        // defaults stand in for anything the booking leaves out
+        let resolved = resolve(&parameters, &HashMap::new()).unwrap();
+        assert_eq!(resolved.host_counts.get("worker"), Some(&2));
+        assert_eq!(resolved.images.get("worker"), Some(&ubuntu));
+
+        let bad = [("workers".to_owned(), ParameterValue::Count(9))]
+            .into_iter()
+            .collect();
+        assert!(matches!(
+            resolve(&parameters, &bad),
+            Err(ParameterError::OutOfBounds { value: 9, .. })
+        ));
+
+        let bad = [("workers".to_owned(), ParameterValue::Image(fedora))]
+            .into_iter()
+            .collect();
+        assert!(matches!(
+            resolve(&parameters, &bad),
+            Err(ParameterError::WrongType { .. })
+        ));
+
+        let bad = [("nodes".to_owned(), ParameterValue::Count(1))]
+            .into_iter()
+            .collect();
+        assert_eq!(
+            resolve(&parameters, &bad),
+            Err(ParameterError::Unknown("nodes".to_owned()))
+        );
+    }
+}
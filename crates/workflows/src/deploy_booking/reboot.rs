@@ -0,0 +1,174 @@
+//! Rebooting the host of an instance, rather than only changing its power state
+//!
+//! A graceful reboot asks the OS to reboot itself over ssh as the `host_access` account, an
+//! ACPI reboot has the BMC ask the OS to shut down and powers the host back on once it has,
+//! and a hard reboot has the BMC reset the host. However it was asked for, a reboot is only
+//! confirmed once ssh on the host answers again.
+
+use std::time::Duration;
+
+use common::prelude::tokio::{
+    self,
+    net::TcpStream,
+    time::{timeout, Instant},
+};
+use config::settings;
+use models::inventory::Host;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use super::set_host_power_state::{
+    apply_power_action, execute_power_command, HostConfig, PowerAction, PowerStateError,
+};
+use crate::users::keys::run_on_host;
+
+/// How long a reboot is given to bring ssh back when the request doesn't say
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// How long a host that was asked to reboot is watched for going down
+const GOING_DOWN: Duration = Duration::from_secs(120);
+
+/// Tells the OS to reboot once the session that asked has had the time to close
+const REBOOT_SCRIPT: &str =
+    "nohup sudo sh -c 'sleep 2; systemctl reboot || reboot' >/dev/null 2>&1 &";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum RebootMode {
+    /// The OS is told to reboot over ssh, so it shuts down like it would for its users
+    Graceful,
+    /// The BMC asks the OS to shut down, and powers the host back on once it has
+    Acpi,
+    /// The BMC resets the host, for when the OS doesn't respond to anything else
+    Hard,
+}
+
+/// What came of a reboot
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+pub struct RebootResult {
+    pub mode: RebootMode,
+    /// Whether ssh on the host was seen to stop answering, which a host that reboots
+    /// quickly enough may never be
+    pub went_down: bool,
+    /// True if ssh on the host answered again within the timeout
+    pub confirmed: bool,
+    pub elapsed_seconds: u64,
+    pub message: String,
+}
+
+/// Whether ssh on the host at `address` takes connections right now
+async fn ssh_answers(address: &str) -> bool {
+    matches!(
+        timeout(
+            Duration::from_secs(3),
+            TcpStream::connect(format!("{address}:22"))
+        )
+        .await,
+        Ok(Ok(_))
+    )
+}
+
+/// Waits until ssh on the host answers, or stops answering if not `up`, giving up at
+/// `deadline`
+async fn wait_for_ssh(address: &str, up: bool, deadline: Instant) -> bool {
+    loop {
+        if ssh_answers(address).await == up {
+            return true;
+        }
+        if Instant::now() >= deadline {
+            return false;
+        }
+        tokio::time::sleep(Duration::from_secs(5)).await;
+    }
+}
+
+/// Reboots `host` the way `mode` says, waiting at most `wait` for it to come back
+///
+/// # Errors
+///
+/// - [`PowerStateError::InvalidInputParameter`] if a graceful reboot is asked for with no
+///   `host_access` account configured to ask the OS with.
+///
+/// - [`PowerStateError::CommandExecutionFailed`] if the OS couldn't be asked to reboot over ssh.
+///
+/// - The errors of [`execute_power_command()`] and [`apply_power_action()`].
+pub async fn reboot(
+    host: &Host,
+    mode: RebootMode,
+    wait: Duration,
+) -> Result<RebootResult, PowerStateError> {
+    let config = HostConfig::try_from(host)?;
+    let address = host.fqdn.clone();
+    let started = Instant::now();
+    let deadline = started + wait;
+
+    let went_down = match mode {
+        RebootMode::Graceful => {
+            if settings().host_access.is_none() {
+                return Err(PowerStateError::InvalidInputParameter(
+                    "graceful reboots need a host_access account, which isn't configured, \
+                    reboot through ACPI instead"
+                        .to_owned(),
+                ));
+            }
+
+            let target = address.clone();
+            tokio::task::spawn_blocking(move || run_on_host(&target, REBOOT_SCRIPT))
+                .await
+                .map_err(|e| PowerStateError::CommandExecutionFailed(e.to_string()))?
+                .map_err(|e| {
+                    PowerStateError::CommandExecutionFailed(format!(
+                        "couldn't ask the OS to reboot, {e}"
+                    ))
+                })?;
+
+            wait_for_ssh(&address, false, deadline.min(started + GOING_DOWN)).await
+        }
+        RebootMode::Acpi => {
+            let action = PowerAction::SoftShutdown;
+            let shutdown = apply_power_action(&config, action.default_timeout(), action).await?;
+            if !shutdown.confirmed {
+                return Ok(RebootResult {
+                    mode,
+                    went_down: false,
+                    confirmed: false,
+                    elapsed_seconds: started.elapsed().as_secs(),
+                    message: shutdown.message,
+                });
+            }
+
+            execute_power_command(&config, PowerAction::On).await?;
+            true
+        }
+        RebootMode::Hard => {
+            execute_power_command(&config, PowerAction::Reset).await?;
+            wait_for_ssh(&address, false, deadline.min(started + GOING_DOWN)).await
+        }
+    };
+
+    let confirmed = wait_for_ssh(&address, true, deadline).await;
+    let elapsed_seconds = started.elapsed().as_secs();
+
+    let message = match (confirmed, went_down) {
+        (true, true) => format!("the host rebooted and was back after {elapsed_seconds} seconds"),
+        (true, false) => "ssh on the host never stopped answering, so it may not have rebooted, \
+            or rebooted too quickly to notice"
+            .to_owned(),
+        (false, true) => format!(
+            "the host went down but wasn't back within {} seconds",
+            wait.as_secs()
+        ),
+        (false, false) => format!(
+            "ssh on the host didn't answer within {} seconds",
+            wait.as_secs()
+        ),
+    };
+
+    Ok(RebootResult {
+        mode,
+        went_down,
+        confirmed,
+        elapsed_seconds,
+        message,
+    })
+}
@@ -0,0 +1,62 @@
+//! Reimages a single host of a booking, holding the booking's lease while it does, or
+//! picks an earlier deploy of it back up
+
+use dal::{FKey, ID};
+use models::{
+    dashboard::{Aggregate, Checkpoint, Instance, LeaseOperation},
+    inventory::Host,
+};
+use serde::{Deserialize, Serialize};
+use tascii::prelude::*;
+
+use super::deploy_host::DeployHost;
+use crate::resource_management::lease;
+
+#[derive(Debug, Clone, Hash, Serialize, Deserialize)]
+pub struct Reimage {
+    pub host_id: FKey<Host>,
+    pub agg_id: FKey<Aggregate>,
+    pub inst_id: FKey<Instance>,
+    /// Pick the last deploy back up after this checkpoint instead of starting over
+    #[serde(default)]
+    pub resume_from: Option<Checkpoint>,
+}
+
+tascii::mark_task!(Reimage);
+impl AsyncRunnable for Reimage {
+    type Output = ();
+
+    async fn run(&mut self, context: &Context) -> Result<Self::Output, TaskError> {
+        let lease = lease::try_acquire(self.agg_id, LeaseOperation::Reimage, None).await?;
+
+        let deploy = DeployHost {
+            host_id: self.host_id,
+            aggregate_id: self.agg_id,
+            using_instance: self.inst_id,
+            distribution: None,
+            resume_from: self.resume_from,
+        };
+
+        lease::holding(lease, async { context.spawn(deploy).join().map(|_| ()) }).await
+    }
+
+    fn summarize(&self, id: ID) -> String {
+        format!(
+            "Reimage task with id {id} for host {:?} of agg {:?}",
+            self.host_id, self.agg_id
+        )
+    }
+
+    fn identifier() -> TaskIdentifier {
+        TaskIdentifier::named("ReimageTask").versioned(1)
+    }
+
+    fn timeout() -> std::time::Duration {
+        // every try of the deploy, with room to spare
+        DeployHost::timeout() * 4
+    }
+
+    fn retry_count(&self) -> usize {
+        0
+    }
+}
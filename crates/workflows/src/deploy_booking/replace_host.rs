@@ -0,0 +1,300 @@
+//! Swaps a faulty host of a running booking out for another of the same flavor
+//!
+//! The instance keeps its identity, so its hostname, image and network assignments
+//! carry over to the replacement, which is deployed just like a fresh host of the booking.
+//! Only once the replacement is up is the faulty host torn down and moved into a
+//! maintenance booking for admins to look at. If the replacement can't be deployed, it is
+//! given back and the instance is left on the faulty host.
+
+use common::prelude::{anyhow, chrono::Utc, tracing};
+use dal::{new_client, AsEasyTransaction, FKey, ID};
+use models::{
+    allocator::{AllocationReason, ResourceHandle},
+    dashboard::{HostReplacement, ReplacementState, StatusSentiment},
+    inventory::{Host, TicketKind},
+};
+use notifications::email::send_to_admins;
+use serde::{Deserialize, Serialize};
+use tascii::prelude::*;
+
+use crate::{
+    deploy_booking::{
+        configure_networking::ConfigureNetworking, deploy_host::DeployHost, maintenance_aggregate,
+        net_config::empty_network_config, set_host_power_state::SetPower,
+    },
+    resource_management::{
+        allocator::Allocator,
+        health::{excuse_instance, REIMAGE_GRACE},
+        ipmi_accounts::DeleteIPMIAccount,
+        tickets::report_host_failure,
+    },
+    utils::status_feed::PublishedLog,
+};
+
+/// Carries out an approved [`HostReplacement`]
+#[derive(Debug, Clone, Hash, Serialize, Deserialize)]
+pub struct ReplaceHost {
+    pub replacement: FKey<HostReplacement>,
+}
+
+async fn set_state(
+    replacement: FKey<HostReplacement>,
+    state: ReplacementState,
+    replacement_host: Option<FKey<Host>>,
+) -> Result<HostReplacement, anyhow::Error> {
+    let mut client = new_client().await?;
+    let mut transaction = client.easy_transaction().await?;
+
+    let mut row = replacement.get(&mut transaction).await?;
+    row.state = state;
+    row.replacement_host = replacement_host.or(row.replacement_host);
+    row.updated = Utc::now();
+    row.update(&mut transaction).await?;
+
+    transaction.commit().await?;
+
+    Ok(row.into_inner())
+}
+
+impl ReplaceHost {
+    /// Allocates a host of the same flavor to the booking and moves the instance onto it
+    async fn allocate(&self, replacement: &HostReplacement) -> Result<FKey<Host>, anyhow::Error> {
+        let mut client = new_client().await?;
+        let mut transaction = client.easy_transaction().await?;
+
+        let mut instance = replacement.instance.get(&mut transaction).await?;
+
+        let (host, _) = Allocator::instance()
+            .allocate_host(
+                &mut transaction,
+                instance.config.flavor,
+                replacement.aggregate,
+                AllocationReason::ForBooking,
+                false,
+            )
+            .await?;
+
+        instance.linked_host = Some(host);
+        instance.update(&mut transaction).await?;
+        excuse_instance(&mut transaction, instance.id, REIMAGE_GRACE).await?;
+
+        transaction.commit().await?;
+
+        Ok(host)
+    }
+
+    /// Puts the instance back on the faulty host and gives the replacement back
+    async fn roll_back(
+        &self,
+        replacement: &HostReplacement,
+        host: FKey<Host>,
+    ) -> Result<(), anyhow::Error> {
+        let mut client = new_client().await?;
+        let mut transaction = client.easy_transaction().await?;
+
+        let mut instance = replacement.instance.get(&mut transaction).await?;
+        instance.linked_host = Some(replacement.faulty_host);
+        instance.update(&mut transaction).await?;
+
+        let handle = ResourceHandle::handle_for_host(&mut transaction, host).await?;
+        Allocator::instance()
+            .deallocate_host(&mut transaction, handle, replacement.aggregate)
+            .await?;
+
+        transaction.commit().await?;
+
+        Ok(())
+    }
+
+    /// Cuts the faulty host off from the booking and holds it for maintenance
+    async fn retire_faulty(
+        &self,
+        context: &Context,
+        replacement: &HostReplacement,
+    ) -> Result<(), anyhow::Error> {
+        let host = replacement.faulty_host;
+
+        // the host is broken, so doing any of this may well fail
+        if let Err(e) = context.spawn(SetPower::off(host)).join() {
+            tracing::warn!("Couldn't power off replaced host {host:?}: {e:?}");
+        }
+        let _ignore = context
+            .spawn(DeleteIPMIAccount {
+                host,
+                userid: "4".to_owned(),
+            })
+            .join();
+
+        let mut client = new_client().await?;
+        let mut transaction = client.easy_transaction().await?;
+
+        let net_config = empty_network_config(host, &mut transaction).await;
+        context
+            .spawn(ConfigureNetworking { net_config })
+            .join()
+            .map_err(|e| {
+                anyhow::Error::msg(format!(
+                    "couldn't take the host off of the booking networks: {e:?}"
+                ))
+            })?;
+
+        let lab = replacement.aggregate.get(&mut transaction).await?.lab;
+        let handle = ResourceHandle::handle_for_host(&mut transaction, host).await?;
+        let maintenance = maintenance_aggregate(&mut transaction, lab, "replaced hosts").await?;
+
+        let allocator = Allocator::instance();
+        allocator
+            .deallocate_host(&mut transaction, handle, replacement.aggregate)
+            .await?;
+        allocator
+            .allocate_specific_host(
+                &mut transaction,
+                host,
+                maintenance.id,
+                AllocationReason::ForMaintenance,
+            )
+            .await?;
+
+        let server_name = host.get(&mut transaction).await?.server_name.clone();
+
+        transaction.commit().await?;
+
+        report_host_failure(
+            host,
+            TicketKind::ReportedFaulty,
+            format!(
+                "{} had this host replaced in booking {:?}, because: {}. \
+                It has been moved to maintenance booking {:?}.",
+                replacement.requested_by,
+                replacement.aggregate.into_id(),
+                replacement.reason,
+                maintenance.id.into_id()
+            ),
+        )
+        .await;
+
+        tracing::info!("Moved replaced host {server_name} to maintenance");
+
+        Ok(())
+    }
+}
+
+tascii::mark_task!(ReplaceHost);
+impl AsyncRunnable for ReplaceHost {
+    type Output = ();
+
+    async fn run(&mut self, context: &Context) -> Result<Self::Output, TaskError> {
+        let replacement = set_state(self.replacement, ReplacementState::Replacing, None).await?;
+        let instance = replacement.instance;
+
+        instance
+            .log(
+                "Replacing Host",
+                format!(
+                    "a new host is being allocated because: {}",
+                    replacement.reason
+                ),
+                StatusSentiment::InProgress,
+            )
+            .await;
+
+        let host = match self.allocate(&replacement).await {
+            Ok(host) => host,
+            Err(e) => {
+                instance
+                    .log(
+                        "Replacement Failed",
+                        "no host was available to replace this one with",
+                        StatusSentiment::Degraded,
+                    )
+                    .await;
+                set_state(self.replacement, ReplacementState::Failed, None).await?;
+                send_to_admins(format!(
+                    "Couldn't allocate a replacement for instance {:?}, error: {e:?}",
+                    instance.into_id()
+                ))
+                .await;
+
+                return Err(TaskError::Reason(format!(
+                    "couldn't allocate a replacement host: {e:?}"
+                )));
+            }
+        };
+
+        set_state(self.replacement, ReplacementState::Replacing, Some(host)).await?;
+
+        let deployed = context
+            .spawn(DeployHost {
+                host_id: host,
+                aggregate_id: replacement.aggregate,
+                using_instance: instance,
+                distribution: None,
+                resume_from: None,
+            })
+            .join();
+
+        if let Err(e) = deployed {
+            if let Err(rollback) = self.roll_back(&replacement, host).await {
+                tracing::error!("Couldn't roll back replacement of {instance:?}: {rollback:?}");
+            }
+
+            instance
+                .log(
+                    "Replacement Failed",
+                    "the replacement host failed to provision, so this instance stays on its old host",
+                    StatusSentiment::Degraded,
+                )
+                .await;
+            set_state(self.replacement, ReplacementState::Failed, None).await?;
+            send_to_admins(format!(
+                "Replacement host {host:?} failed to provision for instance {:?}, \
+                the instance was left on its old host. Error: {e:?}",
+                instance.into_id()
+            ))
+            .await;
+
+            return Err(TaskError::Reason(format!(
+                "replacement host failed to provision: {e:?}"
+            )));
+        }
+
+        // the booking already runs on the replacement, so the old host only needs cleaning up
+        if let Err(e) = self.retire_faulty(context, &replacement).await {
+            send_to_admins(format!(
+                "Instance {:?} was moved to a replacement host, but its old host {:?} \
+                couldn't be moved to maintenance and may still be on the booking networks. \
+                Error: {e:?}",
+                instance.into_id(),
+                replacement.faulty_host
+            ))
+            .await;
+        }
+
+        set_state(self.replacement, ReplacementState::Replaced, Some(host)).await?;
+
+        instance
+            .log(
+                "Host Replaced",
+                "this instance now runs on a replacement host",
+                StatusSentiment::Succeeded,
+            )
+            .await;
+
+        Ok(())
+    }
+
+    fn summarize(&self, id: ID) -> String {
+        format!(
+            "ReplaceHost task with id {id} for replacement {:?}",
+            self.replacement
+        )
+    }
+
+    fn identifier() -> TaskIdentifier {
+        TaskIdentifier::named("ReplaceHostTask").versioned(1)
+    }
+
+    fn timeout() -> std::time::Duration {
+        DeployHost::timeout() * 4 + std::time::Duration::from_secs(10 * 60)
+    }
+}
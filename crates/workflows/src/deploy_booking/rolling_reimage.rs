@@ -0,0 +1,407 @@
+//! Reimages the hosts of a booking a few at a time, so a cluster running on the
+//! booking keeps enough members up to stay live through an OS upgrade
+//!
+//! Each batch is deployed in parallel, and the next batch only starts once every
+//! host of the current one has passed post-deploy verification and is up (powered
+//! on and answering pings). The first host that fails stops the whole reimage,
+//! leaving every host that hasn't been reached yet on its old image.
+//!
+//! Admins can also reimage many instances across bookings at once as a job, see [`bulk_reimage`].
+
+use common::prelude::{
+    anyhow,
+    chrono::{DateTime, Utc},
+    tokio::time::{sleep, Duration, Instant},
+    tracing,
+};
+use dal::{new_client, AsEasyTransaction, FKey};
+use models::dashboard::{
+    Aggregate, Image, Instance, LeaseOperation, ProvEvent, ProvisionLogEvent, StatusSentiment,
+};
+use notifications::email::send_to_admins;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tascii::{prelude::*, task_trait::AsyncRunnable};
+
+use crate::{
+    deploy_booking::deploy_host::DeployHost,
+    entry::{Action, DISPATCH},
+    resource_management::{
+        health::{excuse_instance, host_is_up, REIMAGE_GRACE},
+        jobs::{JobHandle, JobReport},
+        lease,
+    },
+    utils::status_feed,
+};
+
+/// How many times to check that a freshly deployed host is up before giving up on it
+const UP_CHECKS: usize = 10;
+
+/// The task timeout only leaves room for this many batches
+pub const MAX_BATCHES: usize = 8;
+
+#[derive(Debug, Clone, Hash, Serialize, Deserialize)]
+pub struct RollingReimage {
+    pub aggregate: FKey<Aggregate>,
+    /// In the order they should be reimaged
+    pub instances: Vec<FKey<Instance>>,
+    /// The image to switch every instance to, keeps their current image if not given
+    pub image: Option<FKey<Image>>,
+    pub batch_size: usize,
+}
+
+async fn log(
+    instance: FKey<Instance>,
+    headline: &str,
+    details: String,
+    sentiment: StatusSentiment,
+) {
+    if let Err(e) = status_feed::log_committing(
+        instance,
+        ProvEvent::new(headline, &details),
+        Some(sentiment),
+    )
+    .await
+    {
+        tracing::error!("Couldn't log rolling reimage status of {instance:?}: {e:?}");
+    }
+}
+
+impl RollingReimage {
+    /// Switches the batch to the new image, and returns the deploy of each of its hosts
+    async fn prepare_batch(
+        &self,
+        batch: &[FKey<Instance>],
+    ) -> Result<Vec<(FKey<Instance>, DeployHost)>, anyhow::Error> {
+        let mut client = new_client().await?;
+        let mut transaction = client.easy_transaction().await?;
+
+        let mut deploys = Vec::new();
+        for &instance in batch {
+            let mut inst = instance.get(&mut transaction).await?;
+            if let Some(image) = self.image {
+                inst.config.image = image;
+                inst.update(&mut transaction).await?;
+            }
+            excuse_instance(&mut transaction, instance, REIMAGE_GRACE).await?;
+
+            let host_id = inst.linked_host.ok_or(anyhow::Error::msg(format!(
+                "instance {instance:?} has no host to reimage"
+            )))?;
+
+            deploys.push((
+                instance,
+                DeployHost {
+                    host_id,
+                    aggregate_id: self.aggregate,
+                    using_instance: instance,
+                    distribution: None,
+                    resume_from: None,
+                },
+            ));
+        }
+
+        transaction.commit().await?;
+
+        Ok(deploys)
+    }
+
+    /// Waits for the host of a freshly deployed instance to be powered on and reachable
+    async fn wait_up(&self, instance: FKey<Instance>) -> Result<(), anyhow::Error> {
+        let mut client = new_client().await?;
+        let mut transaction = client.easy_transaction().await?;
+        let host = match instance.get(&mut transaction).await?.linked_host {
+            Some(h) => h.get(&mut transaction).await?.into_inner(),
+            None => return Err(anyhow::Error::msg("instance lost its host")),
+        };
+        transaction.commit().await?;
+
+        for _ in 0..UP_CHECKS {
+            match host_is_up(&host).await {
+                Ok(true) => return Ok(()),
+                Ok(false) => (),
+                Err(e) => {
+                    tracing::warn!("Couldn't check whether {} is up: {e:?}", host.server_name)
+                }
+            }
+            sleep(Duration::from_secs(30)).await;
+        }
+
+        Err(anyhow::Error::msg(format!(
+            "{} didn't come back up after being reimaged",
+            host.server_name
+        )))
+    }
+}
+
+tascii::mark_task!(RollingReimage);
+impl AsyncRunnable for RollingReimage {
+    type Output = ();
+
+    async fn run(&mut self, context: &Context) -> Result<Self::Output, TaskError> {
+        let lease =
+            lease::try_acquire(self.aggregate, LeaseOperation::RollingReimage, None).await?;
+        lease::holding(lease, self.reimage(context)).await
+    }
+
+    fn identifier() -> TaskIdentifier {
+        TaskIdentifier::named("RollingReimageTask").versioned(1)
+    }
+
+    fn summarize(&self, id: dal::ID) -> String {
+        format!(
+            "RollingReimage of {} instances in batches of {} with id {id}",
+            self.instances.len(),
+            self.batch_size
+        )
+    }
+
+    fn timeout() -> std::time::Duration {
+        // any one batch could take every retry of a deploy, and then some to come up
+        (DeployHost::timeout() * 4 + Duration::from_secs(30 * UP_CHECKS as u64))
+            * MAX_BATCHES as u32
+    }
+
+    fn retry_count(&self) -> usize {
+        0
+    }
+}
+
+impl RollingReimage {
+    /// Reimages each batch in turn, under the lease `run` took
+    async fn reimage(&mut self, context: &Context) -> Result<(), TaskError> {
+        let batches: Vec<&[FKey<Instance>]> =
+            self.instances.chunks(self.batch_size.max(1)).collect();
+        let count = batches.len();
+
+        for (i, instance) in self.instances.iter().enumerate() {
+            log(
+                *instance,
+                "Rolling Reimage Queued",
+                format!(
+                    "will be reimaged in batch {} of {count}",
+                    i / self.batch_size.max(1) + 1
+                ),
+                StatusSentiment::InProgress,
+            )
+            .await;
+        }
+
+        for (n, batch) in batches.iter().enumerate() {
+            tracing::info!(
+                "Starting batch {} of {count} of rolling reimage of {:?}",
+                n + 1,
+                self.aggregate
+            );
+
+            let failure = match self.prepare_batch(batch).await {
+                Err(e) => Some(format!("couldn't start batch {}: {e}", n + 1)),
+                Ok(deploys) => {
+                    let handles: Vec<_> = deploys
+                        .into_iter()
+                        .map(|(instance, deploy)| (instance, context.spawn(deploy)))
+                        .collect();
+                    let mut failure = None;
+
+                    // always join every deploy of the batch, a host shouldn't be left mid provision
+                    for (instance, handle) in handles {
+                        let result = match handle.join() {
+                            Ok(()) => self.wait_up(instance).await.map_err(|e| e.to_string()),
+                            Err(e) => Err(format!("failed to deploy: {e:?}")),
+                        };
+
+                        if let Err(e) = result {
+                            failure.get_or_insert(format!("instance {instance:?} {e}"));
+                        }
+                    }
+
+                    failure
+                }
+            };
+
+            if let Some(reason) = failure {
+                for instance in batches[n + 1..].iter().flat_map(|b| b.iter()) {
+                    log(
+                        *instance,
+                        "Rolling Reimage Aborted",
+                        format!("not reimaged, an earlier batch failed: {reason}"),
+                        StatusSentiment::Degraded,
+                    )
+                    .await;
+                }
+
+                send_to_admins(format!(
+                    "Rolling reimage of booking {:?} stopped at batch {} of {count}: {reason}",
+                    self.aggregate,
+                    n + 1
+                ))
+                .await;
+
+                return Err(TaskError::Reason(reason));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct BulkReimageReport {
+    /// Instances whose host finished provisioning on the new image
+    pub reimaged: Vec<FKey<Instance>>,
+    pub failed: Vec<ReimageFailure>,
+    /// Instances that weren't reimaged because the job was cancelled first
+    pub skipped: Vec<FKey<Instance>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ReimageFailure {
+    pub instance: FKey<Instance>,
+    pub reason: String,
+}
+
+impl JobReport for BulkReimageReport {
+    fn succeeded(&self) -> bool {
+        self.failed.is_empty() && self.skipped.is_empty()
+    }
+}
+
+/// Switches `instance` to `image` and starts the deploy of its host
+async fn start_reimage(
+    instance: FKey<Instance>,
+    image: Option<FKey<Image>>,
+) -> Result<(), anyhow::Error> {
+    let mut client = new_client().await?;
+    let mut transaction = client.easy_transaction().await?;
+
+    let mut inst = instance.get(&mut transaction).await?;
+    if let Some(busy) =
+        lease::conflicting(&mut transaction, inst.aggregate, LeaseOperation::Reimage).await?
+    {
+        return Err(busy.into());
+    }
+    if let Some(image) = image {
+        inst.config.image = image;
+        inst.update(&mut transaction).await?;
+    }
+    excuse_instance(&mut transaction, instance, REIMAGE_GRACE).await?;
+
+    let host_id = inst
+        .linked_host
+        .ok_or(anyhow::Error::msg("the instance has no host to reimage"))?;
+
+    transaction.commit().await?;
+
+    DISPATCH
+        .get()
+        .ok_or(anyhow::Error::msg("the dispatcher isn't running"))?
+        .send(Action::Reimage {
+            host_id,
+            inst_id: instance,
+            agg_id: inst.aggregate,
+        })
+        .map_err(|_| anyhow::Error::msg("couldn't dispatch the reimage"))?;
+
+    Ok(())
+}
+
+/// Whether the deploy of `instance` started at `since` has finished, and how it went
+async fn reimage_outcome(
+    instance: FKey<Instance>,
+    since: DateTime<Utc>,
+) -> Result<Option<Result<(), String>>, anyhow::Error> {
+    let mut client = new_client().await?;
+    let mut transaction = client.easy_transaction().await?;
+
+    let latest = ProvisionLogEvent::latest_for_instance(&mut transaction, instance).await?;
+
+    transaction.commit().await?;
+
+    Ok(match latest {
+        Some(event) if event.time > since => match event.sentiment {
+            StatusSentiment::Succeeded => Some(Ok(())),
+            StatusSentiment::Failed => Some(Err(event.prov_status.details.clone())),
+            _ => None,
+        },
+        _ => None,
+    })
+}
+
+/// Reimages `instances`, which can be spread across bookings, `batch_size` at a time
+///
+/// Unlike a rolling reimage a failed host doesn't stop the rest, every instance is tried
+/// and the report says how each went. Cancelling the job lets the current batch finish.
+pub async fn bulk_reimage(
+    job: JobHandle,
+    instances: Vec<FKey<Instance>>,
+    image: Option<FKey<Image>>,
+    batch_size: usize,
+) -> Result<BulkReimageReport, anyhow::Error> {
+    let mut report = BulkReimageReport::default();
+    let batches: Vec<&[FKey<Instance>]> = instances.chunks(batch_size.max(1)).collect();
+    let count = batches.len();
+
+    for (n, batch) in batches.iter().enumerate() {
+        let done = report.reimaged.len() + report.failed.len();
+        if !report.skipped.is_empty()
+            || !job
+                .step(done, format!("reimaging batch {} of {count}", n + 1))
+                .await?
+        {
+            report.skipped.extend(batch.iter());
+            continue;
+        }
+
+        let since = Utc::now();
+        let mut pending = Vec::new();
+        for &instance in batch.iter() {
+            match start_reimage(instance, image).await {
+                Ok(()) => pending.push(instance),
+                Err(e) => report.failed.push(ReimageFailure {
+                    instance,
+                    reason: format!("couldn't start the reimage: {e}"),
+                }),
+            }
+        }
+
+        // the deploy task could use every one of its retries
+        let deadline = Instant::now() + DeployHost::timeout() * 4;
+        while !pending.is_empty() {
+            if Instant::now() > deadline {
+                for instance in pending.drain(..) {
+                    report.failed.push(ReimageFailure {
+                        instance,
+                        reason: "didn't finish provisioning in time".to_owned(),
+                    });
+                }
+                break;
+            }
+
+            sleep(Duration::from_secs(30)).await;
+
+            let mut still_pending = Vec::new();
+            for instance in pending {
+                match reimage_outcome(instance, since).await {
+                    Ok(None) => still_pending.push(instance),
+                    Ok(Some(Ok(()))) => report.reimaged.push(instance),
+                    Ok(Some(Err(reason))) => {
+                        report.failed.push(ReimageFailure { instance, reason })
+                    }
+                    Err(e) => {
+                        tracing::warn!("Couldn't check on the reimage of {instance:?}: {e:?}");
+                        still_pending.push(instance);
+                    }
+                }
+            }
+            pending = still_pending;
+        }
+    }
+
+    job.step(
+        report.reimaged.len() + report.failed.len(),
+        "done".to_owned(),
+    )
+    .await?;
+
+    Ok(report)
+}
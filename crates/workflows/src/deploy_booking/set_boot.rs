@@ -3,16 +3,20 @@
 
 #![allow(non_snake_case, non_camel_case_types)]
 
-use common::prelude::{anyhow, reqwest, tokio, tracing};
+use common::prelude::{anyhow, reqwest, tracing};
 use models::inventory::*;
 use serde::{Deserialize, Serialize};
 use serde_xml_rs::from_str;
-use std::{cmp::Ordering, fmt::Display, process::Command, time::Duration, *};
+use std::{cmp::Ordering, fmt::Display, time::Duration, *};
 use tascii::{prelude::*, task_trait::AsyncRunnable};
 
 use dal::{new_client, AsEasyTransaction, FKey, ID};
 
-use crate::deploy_booking::reachable::WaitReachable;
+use crate::deploy_booking::{
+    bmc::{Bmc, Ipmi},
+    reachable::WaitReachable,
+    set_host_power_state::HostConfig,
+};
 type BootDevice = (String, String);
 
 #[derive(Debug)]
@@ -96,10 +100,15 @@ impl AsyncRunnable for SetBoot {
 
         let arch = host.arch;
 
-        let result = match arch {
-            Arch::Aarch64 => set_ipmi_boot(&host_url, host, self.persistent, self.boot_to).await,
-            Arch::X86 => set_hpe_boot(&host_url, host, self.persistent, self.boot_to).await,
-            Arch::X86_64 => set_hpe_boot(&host_url, host, self.persistent, self.boot_to).await,
+        let result = match (host.bmc_protocol, arch) {
+            (BmcProtocol::Redfish, _) => {
+                set_redfish_boot(host, self.persistent, self.boot_to).await
+            }
+            (_, Arch::Aarch64) => {
+                set_ipmi_boot(&host_url, host, self.persistent, self.boot_to).await
+            }
+            (_, Arch::X86) => set_hpe_boot(&host_url, host, self.persistent, self.boot_to).await,
+            (_, Arch::X86_64) => set_hpe_boot(&host_url, host, self.persistent, self.boot_to).await,
         };
 
         match result {
@@ -255,58 +264,31 @@ async fn set_ipmi_boot(
     tracing::info!(
         "Setting IPMI boot for {host:?} with persistent {persistent} and boot_to {boot_to}"
     );
-    let bdev = match boot_to {
-        BootTo::Network => "pxe",
-        BootTo::Disk | BootTo::SpecificDisk => "disk",
-    };
 
-    let mut opts = String::from("options=efiboot");
-
-    if persistent {
-        opts.push_str(", persistent");
-    }
+    let config = HostConfig {
+        fqdn: host_url.to_owned(),
+        user: host.ipmi_user,
+        password: host.ipmi_pass,
+        protocol: BmcProtocol::Ipmi,
+    };
+    Ipmi(&config).set_boot_device(boot_to, persistent).await?;
 
-    tracing::info!("note: going to set bootdev in ipmi multiple times so it really sticks");
-    for _i in 0..2 {
-        let mut ipmi_cmd = Command::new("ipmitool");
-
-        let mut ipmi_cmd = ipmi_cmd.args([
-            "-I",
-            "lanplus",
-            "-C",
-            "3",
-            "-H",
-            &host_url,
-            "-U",
-            &host.ipmi_user,
-            "-P",
-            &host.ipmi_pass,
-            "chassis",
-            "bootdev",
-            &bdev,
-        ]);
-
-        if let BootTo::Network = boot_to {
-            ipmi_cmd = ipmi_cmd.arg("set").arg("force_pxe").arg("true");
-        } else if let BootTo::Disk = boot_to {
-            ipmi_cmd = ipmi_cmd.arg("set").arg("force_disk").arg("true");
-        }
+    Ok(())
+}
 
-        let ipmi_cmd = ipmi_cmd.arg(if persistent {
-            "options=efiboot,persistent"
-        } else {
-            "options=efiboot"
-        });
-        let ipmi_cmd = ipmi_cmd
-            .output()
-            .expect("Failed to execute ipmitool command");
-        let output2 = str::from_utf8(&ipmi_cmd.stdout).unwrap();
-        tokio::time::sleep(Duration::from_secs(10)).await;
-        tracing::info!("IPMI set bootdev returns output: {output2}");
-    }
+/// Redfish BMCs are told what to boot from the same way whatever vendor they're from
+async fn set_redfish_boot(
+    host: Host,
+    persistent: bool,
+    boot_to: BootTo,
+) -> Result<(), anyhow::Error> {
+    tracing::info!(
+        "Setting Redfish boot for {} with persistent {persistent} and boot_to {boot_to}",
+        host.server_name
+    );
 
-    // todo - extract error code from output. If error, then fail task
-    // if output1.error_code != 0 return
+    let config = HostConfig::try_from(host)?;
+    config.bmc().set_boot_device(boot_to, persistent).await?;
 
     Ok(())
 }
@@ -477,4 +459,3 @@ fn sort_device_to_top(a: &(String, String), b: &(String, String), device: String
 
     f(&a_d, &b_d)
 }
-
@@ -4,20 +4,25 @@
 use common::prelude::{strum_macros::Display, tracing};
 use dal::{new_client, AsEasyTransaction, FKey, ID};
 
-use models::inventory::Host;
+use models::inventory::{BmcProtocol, Host};
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use tascii::{prelude::*, task_trait::AsyncRunnable};
 
 use std::str::{self, Utf8Error};
 use thiserror::Error;
-use tokio::process::Command;
 use tokio::time::{sleep, timeout, Duration};
-use tracing::{debug, error, info, warn};
+use tracing::{error, info, warn};
 
 use crate::{
-    deploy_booking::reachable::WaitReachable,
-    utils::net::{validate_fqdn, validate_ip},
+    deploy_booking::{
+        bmc::{Bmc, HostBmc},
+        reachable::WaitReachable,
+    },
+    utils::{
+        net::{validate_fqdn, validate_ip},
+        resilience::CircuitOpen,
+    },
 };
 
 #[derive(Serialize, Deserialize, Debug, Hash, Clone, Eq, PartialEq)]
@@ -78,12 +83,10 @@ impl AsyncRunnable for SetPower {
         transaction.commit().await.unwrap();
 
         let ipmi_fqdn = &host.ipmi_fqdn;
-        let ipmi_admin_user = &host.ipmi_user;
-        let ipmi_admin_password = &host.ipmi_pass;
 
-        // make sure we can reach the IPMI endpoint
-        tracing::info!("Checking that we can reach the IPMI endpoint");
-        let ipmi_url = context
+        // make sure we can reach the BMC
+        tracing::info!("Checking that we can reach the BMC");
+        context
             .spawn(WaitReachable {
                 endpoint: ipmi_fqdn.clone(),
                 timeout: Duration::from_secs(120),
@@ -91,58 +94,35 @@ impl AsyncRunnable for SetPower {
             .join()?;
 
         tracing::info!(
-            "about to run ipmi power on {:?} to set power to {:?}",
+            "about to have the BMC at {:?} set power to {:?}",
             ipmi_fqdn,
             self.pstate
         );
 
-        let ipmitool = std::process::Command::new("ipmitool")
-            .args([
-                "-I",
-                "lanplus",
-                "-C",
-                "3",
-                "-H",
-                &ipmi_url,
-                "-U",
-                &ipmi_admin_user,
-                "-P",
-                &ipmi_admin_password,
-                "chassis",
-                "power",
-                match self.pstate {
-                    PowerState::Off => "off",
-                    PowerState::On => "on",
-                    PowerState::Reset => "reset",
-                    PowerState::Unknown => panic!("bad instruction"),
-                },
-            ])
-            .output()
-            .expect("Failed to execute ipmitool command");
-        let stdout = String::from_utf8(ipmitool.stdout).expect("no stdout?");
-        let stderr = String::from_utf8(ipmitool.stderr).expect("no stderr?");
-
-        tracing::info!("ran ipmitool, output was: {stdout}, with stderr: {stderr}");
-
-        if stderr.contains("Unable to establish IPMI") {
-            return Err(TaskError::Reason(format!(
-                "IPMItool could not reach the host, host was: {}, fqdn was: {ipmi_fqdn}",
+        let action = match self.pstate {
+            PowerState::Off => PowerAction::Off,
+            PowerState::On => PowerAction::On,
+            PowerState::Reset => PowerAction::Reset,
+            PowerState::Unknown => panic!("bad instruction"),
+        };
+        let bmc = HostConfig::try_from(&host.clone().into_inner()).map_err(|e| {
+            error!("Invalid parameters or fqdn! {e}");
+            TaskError::Reason("Invalid parameters or fqdn!".to_string())
+        })?;
+        execute_power_command(&bmc, action).await.map_err(|e| {
+            TaskError::Reason(format!(
+                "The BMC could not be reached, host was: {}, fqdn was: {ipmi_fqdn}: {e}",
                 host.server_name
-            )));
-        }
+            ))
+        })?;
+
+        tracing::info!("sent {action:?} to the BMC of {}", host.server_name);
 
         for _ in 0..50 {
             tracing::info!("about to check host power state");
             std::thread::sleep(Duration::from_secs_f64(5.0));
             tracing::info!("checking host power state");
-            let current_state = get_host_power_state(
-                &HostConfig::try_from(&host.clone().into_inner()).map_err(|e| {
-                    error!("Invalid parameters or fqdn! {e}");
-                    TaskError::Reason("Invalid parameters or fqdn!".to_string())
-                })?,
-            )
-            .await
-            .map_err(|e| {
+            let current_state = get_host_power_state(&bmc).await.map_err(|e| {
                 tracing::error!("Error getting host power state: {:?}", e);
                 TaskError::Reason(format!("Error getting host power state: {:?}", e))
             })?;
@@ -203,6 +183,16 @@ pub enum PowerStateError {
     UnknownPowerState(String),
     #[error("Host {0} is unreachable")]
     HostUnreachable(String),
+    #[error("{0}")]
+    CircuitOpen(String),
+    #[error("Redfish request failed: {0}")]
+    RequestFailed(String),
+}
+
+impl From<CircuitOpen> for PowerStateError {
+    fn from(value: CircuitOpen) -> Self {
+        Self::CircuitOpen(value.to_string())
+    }
 }
 
 /// Configuration parameters for interacting with a host using IPMI.
@@ -213,12 +203,14 @@ pub enum PowerStateError {
 /// # Examples
 ///
 /// ```
+/// use models::inventory::BmcProtocol;
 /// use workflows::deploy_booking::set_host_power_state::HostConfig;
 ///
 /// let config = HostConfig {
 ///     fqdn: "example.domain.local".to_string(),
 ///     user: "admin".to_string(),
 ///     password: "password123".to_string(),
+///     protocol: BmcProtocol::Ipmi,
 /// };
 /// ```
 pub struct HostConfig {
@@ -228,6 +220,15 @@ pub struct HostConfig {
     pub user: String,
     /// The IPMI password of the host.
     pub password: String,
+    /// What the BMC is managed over, see [`HostBmc`].
+    pub protocol: BmcProtocol,
+}
+
+impl HostConfig {
+    /// The BMC of the host, over whichever protocol it is managed with.
+    pub fn bmc(&self) -> HostBmc<'_> {
+        HostBmc::of(self)
+    }
 }
 
 /// Configuration for timeouts and retries in IPMI power functions.
@@ -373,6 +374,7 @@ impl TryFrom<Host> for HostConfig {
                 fqdn: host.ipmi_fqdn,
                 user: host.ipmi_user,
                 password: host.ipmi_pass,
+                protocol: host.bmc_protocol,
             })
         } else {
             Err(Self::Error::InvalidInputParameter(format!(
@@ -451,6 +453,7 @@ impl TryFrom<&Host> for HostConfig {
                 fqdn: host.ipmi_fqdn.clone(),
                 user: host.ipmi_user.clone(),
                 password: host.ipmi_pass.clone(),
+                protocol: host.bmc_protocol,
             })
         } else {
             Err(Self::Error::InvalidInputParameter(format!(
@@ -581,6 +584,7 @@ pub fn validate_input(input: &str) -> Result<(), PowerStateError> {
 ///        fqdn: "example.domain.local".to_string(),
 ///        user: "admin".to_string(),
 ///        password: "password123".to_string(),
+///        protocol: BmcProtocol::Ipmi,
 ///    }
 ///    let timeout_config = TimeoutConfig::default
 ///    let result = set_host_power_state(&config, timeout_config, PowerState::On).await;
@@ -617,14 +621,14 @@ pub async fn set_host_power_state(
     timeout_config: TimeoutConfig,
     desired_state: PowerState,
 ) -> Result<PowerState, PowerStateError> {
-    let power_command = match desired_state {
-        PowerState::On => "on",
-        PowerState::Off => "off",
-        PowerState::Reset => "reset",
+    let action = match desired_state {
+        PowerState::On => PowerAction::On,
+        PowerState::Off => PowerAction::Off,
+        PowerState::Reset => PowerAction::Reset,
         PowerState::Unknown => return Err(PowerStateError::SetUnknown),
     };
 
-    execute_power_command(config, power_command).await?;
+    execute_power_command(config, action).await?;
     confirm_power_state(
         config,
         &timeout_config,
@@ -634,13 +638,148 @@ pub async fn set_host_power_state(
     .await
 }
 
-/// Executes an IPMI power control command on a host.
+/// Power actions users can take on the hosts of their booking.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum PowerAction {
+    On,
+    Off,
+    /// Powers the host off and back on, it has to be on to begin with.
+    Cycle,
+    /// Asks the OS to shut down through ACPI, which it may take a while to do or ignore.
+    SoftShutdown,
+    /// A hard reset, without the host ever losing power.
+    Reset,
+}
+
+impl PowerAction {
+    /// The `ipmitool chassis power` subcommand for the action.
+    pub fn ipmi_command(&self) -> &'static str {
+        match self {
+            PowerAction::On => "on",
+            PowerAction::Off => "off",
+            PowerAction::Cycle => "cycle",
+            PowerAction::SoftShutdown => "soft",
+            PowerAction::Reset => "reset",
+        }
+    }
+
+    /// Whether doing the action twice is the same as doing it once, so it can be retried
+    /// when it isn't known whether it went through.
+    pub fn repeatable(&self) -> bool {
+        matches!(self, PowerAction::On | PowerAction::Off)
+    }
+
+    /// The power state the host should be in once the action is done.
+    pub fn settles_to(&self) -> PowerState {
+        match self {
+            PowerAction::On | PowerAction::Cycle | PowerAction::Reset => PowerState::On,
+            PowerAction::Off | PowerAction::SoftShutdown => PowerState::Off,
+        }
+    }
+
+    /// How long the action is given to settle when the request doesn't say.
+    ///
+    /// An OS shutting down cleanly takes far longer than the BMC cutting power.
+    pub fn default_timeout(&self) -> TimeoutConfig {
+        match self {
+            PowerAction::On | PowerAction::Off => TimeoutConfig::default(),
+            PowerAction::Cycle | PowerAction::Reset => TimeoutConfig::new(12, 5, Some(60)),
+            PowerAction::SoftShutdown => TimeoutConfig::new(60, 5, Some(300)),
+        }
+    }
+}
+
+/// What came of a power action.
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+pub struct PowerActionResult {
+    pub action: PowerAction,
+    /// The power state before the action, Unknown if it couldn't be read.
+    pub previous_state: PowerState,
+    /// The power state the host was last seen in.
+    pub power_state: PowerState,
+    /// True if the host reached the state the action settles to within the timeout.
+    pub confirmed: bool,
+    pub elapsed_seconds: u64,
+    pub message: String,
+}
+
+/// Takes `action` on a host and waits for it to settle, reporting how it went.
+///
+/// A host that doesn't settle in time isn't an error, as an OS is free to take its time
+/// with or ignore a soft shutdown. The result says so instead.
+///
+/// # Errors
+///
+/// - [`PowerStateError::CommandNonZeroExitStatus`] if the BMC refuses the action, like cycling a host that is off.
+///
+/// - The errors of [`execute_power_command()`].
+pub async fn apply_power_action(
+    config: &HostConfig,
+    timeout_config: TimeoutConfig,
+    action: PowerAction,
+) -> Result<PowerActionResult, PowerStateError> {
+    let started = tokio::time::Instant::now();
+    let previous_state = get_host_power_state(config)
+        .await
+        .unwrap_or(PowerState::Unknown);
+
+    if action == PowerAction::Cycle && previous_state == PowerState::Off {
+        return Err(PowerStateError::InvalidInputParameter(
+            "the host is off, so it can't be power cycled, power it on instead".to_owned(),
+        ));
+    }
+
+    execute_power_command(config, action).await?;
+
+    let desired = action.settles_to();
+    let confirmed = confirm_power_state(
+        config,
+        &timeout_config,
+        Some(Duration::from_secs(5)),
+        desired.clone(),
+    )
+    .await;
+
+    let (power_state, confirmed) = match confirmed {
+        Ok(state) => (state, true),
+        Err(_) => (
+            get_host_power_state(config)
+                .await
+                .unwrap_or(PowerState::Unknown),
+            false,
+        ),
+    };
+    let elapsed_seconds = started.elapsed().as_secs();
+
+    let message = match (confirmed, action) {
+        (true, _) => format!("the host is {power_state} after {elapsed_seconds} seconds"),
+        (false, PowerAction::SoftShutdown) => format!(
+            "the OS didn't shut down within {} seconds, it may still be shutting down, or power the host off instead",
+            timeout_config.timeout_duration
+        ),
+        (false, _) => format!(
+            "the host didn't reach {desired} within {} seconds and is {power_state}",
+            timeout_config.timeout_duration
+        ),
+    };
+
+    Ok(PowerActionResult {
+        action,
+        previous_state,
+        power_state,
+        confirmed,
+        elapsed_seconds,
+        message,
+    })
+}
+
+/// Sends a power action to the BMC of a host, over IPMI or Redfish.
 ///
 /// # Arguments
 ///
 /// * `config` - A reference to [`HostConfig`] containing the host's connection details.
-/// * `power_command` - A string slice representing the power command (`"on"`, `"off"`, or `"reset"`).
-/// technically, other commands are possible, but we shouldn't need them.
+/// * `action` - The [`PowerAction`] to send.
 ///
 /// # Returns
 ///
@@ -656,42 +795,13 @@ pub async fn set_host_power_state(
 ///
 /// - [`PowerStateError::Utf8Error`] if the standard error output of the command is not valid UTF-8.
 ///
+/// - [`PowerStateError::RequestFailed`] if the host is managed over Redfish and the BMC turns the action down.
+///
 pub async fn execute_power_command(
     config: &HostConfig,
-    power_command: &str,
+    action: PowerAction,
 ) -> Result<(), PowerStateError> {
-    let output = Command::new("ipmitool")
-        .args([
-            "-I",
-            "lanplus",
-            "-C",
-            "3",
-            "-H",
-            &config.fqdn,
-            "-U",
-            &config.user,
-            "-P",
-            &config.password,
-            "chassis",
-            "power",
-            power_command,
-        ])
-        .kill_on_drop(true)
-        .output()
-        .await
-        .map_err(|e| PowerStateError::CommandExecutionFailed(e.to_string()))?;
-
-    if !output.status.success() {
-        let stderr = str::from_utf8(&output.stderr)
-            .map_err(|e| PowerStateError::Utf8Error(e.to_string()))?;
-        error!("IPMI command failed to execute properly");
-        return Err(PowerStateError::CommandNonZeroExitStatus(
-            output.status.code().expect("Expected exit code"),
-            stderr.into(),
-        ));
-    }
-
-    Ok(())
+    config.bmc().power(action).await
 }
 
 /// Confirms the power state of a host matches the desired state.
@@ -762,11 +872,11 @@ pub async fn confirm_power_state(
     Err(PowerStateError::TimeoutReached)
 }
 
-/// Retrieves the current power state of a host using IPMI.
+/// Retrieves the current power state of a host from its BMC.
 ///
-/// This asynchronous function uses [`tokio::process::Command`] and `ipmitool`to query the power
-/// status of a host via IPMI. It uses the host's configuration details to
-/// send the command and interprets the response to determine the current power state of the host.
+/// This asynchronous function asks the BMC of the host for its power status, with `ipmitool`
+/// or over Redfish depending on [`HostConfig::protocol`], and interprets the response to
+/// determine the current power state of the host.
 ///
 /// # Arguments
 ///
@@ -791,6 +901,7 @@ pub async fn confirm_power_state(
 ///         fqdn: "example.domain.local".to_string(),
 ///         user: "admin".to_string(),
 ///         password: "password123".to_string(),
+///         protocol: BmcProtocol::Ipmi,
 ///     };
 ///
 ///     match get_host_power_state(&config, Duration::from_secs(5)).await {
@@ -812,52 +923,8 @@ pub async fn confirm_power_state(
 ///
 /// - [`PowerStateError::UnknownPowerState`] if the output of the command is not recognized as a valid power state.
 ///
+/// - [`PowerStateError::RequestFailed`] if the host is managed over Redfish and the request fails.
+///
 pub async fn get_host_power_state(config: &HostConfig) -> Result<PowerState, PowerStateError> {
-    let output = Command::new("ipmitool")
-        .args([
-            "-I",
-            "lanplus",
-            "-C",
-            "3",
-            "-H",
-            &config.fqdn,
-            "-U",
-            &config.user,
-            "-P",
-            &config.password,
-            "chassis",
-            "power",
-            "status",
-        ])
-        .kill_on_drop(true)
-        .output()
-        .await
-        .map_err(|e| PowerStateError::CommandExecutionFailed(e.to_string()))?;
-
-    if !output.status.success() {
-        let stderr = str::from_utf8(&output.stderr)
-            .map_err(|e| PowerStateError::Utf8Error(e.to_string()))?;
-        error!("IPMI command failed to execute properly");
-        return Err(PowerStateError::CommandNonZeroExitStatus(
-            output.status.code().expect("Expected exit code"),
-            stderr.into(),
-        ));
-    }
-
-    let output_str =
-        str::from_utf8(&output.stdout).map_err(|e| PowerStateError::Utf8Error(e.to_string()))?;
-
-    debug!("Successfully got chassis power status: {}", output_str);
-
-    match output_str.trim() {
-        "Chassis Power is on" => Ok(PowerState::On),
-        "Chassis Power is off" => Ok(PowerState::Off),
-        _ => {
-            warn!(
-                "IPMI get host power status command had unexpected output: {}",
-                output_str
-            );
-            Err(PowerStateError::UnknownPowerState(output_str.into()))
-        }
-    }
+    config.bmc().power_state().await
 }
@@ -0,0 +1,406 @@
+//! Captures the disks of a booking's hosts for a [`BookingSnapshot`]
+//!
+//! Each host is netbooted into the capture profile, which copies its whole disk to the
+//! image store and reports back over the mailbox. The host is then booted off its disk
+//! again and put back on the booking's networks, so the booking carries on as it was.
+//! Restoring happens as part of deploying a fresh booking, see [`CobblerConfig::new`].
+
+use common::prelude::{
+    chrono::Utc,
+    tokio::time::{sleep, Duration},
+    tracing,
+};
+use config::settings;
+use dal::{new_client, AsEasyTransaction, FKey, ID};
+use models::{
+    dashboard::{
+        BookingEvent, BookingEventKind, BookingSnapshot, Instance, LeaseOperation, ProvEvent,
+        SnapshotState, StatusSentiment,
+    },
+    inventory::{BootTo, Host},
+};
+use notifications::email::send_to_admins;
+use serde::{Deserialize, Serialize};
+use tascii::prelude::*;
+
+use super::{
+    cobbler_set_config::CobblerSetConfiguration,
+    configure_networking::ConfigureNetworking,
+    net_config::{mgmt_network_config, prod_network_config},
+    set_boot::SetBoot,
+    set_host_power_state::SetPower,
+};
+use crate::{
+    resource_management::{cobbler::CobblerConfig, lease, mailbox::Mailbox},
+    retry_for,
+    utils::status_feed,
+};
+
+/// The name a captured disk is kept under in the image store
+pub fn disk_image_name(snapshot: ID, hostname: &str) -> String {
+    let host: String = hostname
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+
+    format!("{snapshot}-{host}.img")
+}
+
+/// Where `image` is, or is to be written, in the image store at `store_url`
+pub fn image_url(store_url: &str, image: &str) -> String {
+    format!("{}/{image}", store_url.trim_end_matches('/'))
+}
+
+fn capture_timeout() -> Duration {
+    let minutes = settings()
+        .snapshots
+        .as_ref()
+        .map(|s| s.capture_timeout_minutes)
+        .unwrap_or(120);
+
+    Duration::from_secs(minutes * 60)
+}
+
+async fn log(instance: FKey<Instance>, headline: &str, details: &str, sentiment: StatusSentiment) {
+    if let Err(e) =
+        status_feed::log_committing(instance, ProvEvent::new(headline, details), Some(sentiment))
+            .await
+    {
+        tracing::error!("Couldn't log snapshot status of {instance:?}: {e:?}");
+    }
+}
+
+#[derive(Debug, Clone, Hash, Serialize, Deserialize)]
+pub struct CaptureSnapshot {
+    pub snapshot: FKey<BookingSnapshot>,
+    /// The instance each disk of the snapshot is captured from, in the order of its hosts
+    pub disks: Vec<(FKey<Instance>, String)>,
+}
+
+tascii::mark_task!(CaptureSnapshot);
+impl AsyncRunnable for CaptureSnapshot {
+    type Output = ();
+
+    async fn run(&mut self, context: &Context) -> Result<Self::Output, TaskError> {
+        let aggregate = {
+            let mut client = new_client().await?;
+            let mut transaction = client.easy_transaction().await?;
+            let snapshot = self.snapshot.get(&mut transaction).await?;
+            transaction.commit().await?;
+
+            snapshot.aggregate.ok_or(TaskError::Reason(
+                "the snapshot isn't of a booking".to_owned(),
+            ))?
+        };
+
+        let lease = lease::try_acquire(aggregate, LeaseOperation::Snapshot, None).await?;
+        lease::holding(lease, self.capture(context)).await
+    }
+
+    fn summarize(&self, id: ID) -> String {
+        format!(
+            "CaptureSnapshot task with id {id} capturing {} disk(s) for {:?}",
+            self.disks.len(),
+            self.snapshot
+        )
+    }
+
+    fn identifier() -> TaskIdentifier {
+        TaskIdentifier::named("CaptureSnapshotTask").versioned(1)
+    }
+
+    fn timeout() -> std::time::Duration {
+        CaptureDisk::timeout() + std::time::Duration::from_secs(120)
+    }
+}
+
+impl CaptureSnapshot {
+    /// Captures every disk, under the lease `run` took
+    async fn capture(&mut self, context: &Context) -> Result<(), TaskError> {
+        let mut client = new_client().await?;
+        let mut transaction = client.easy_transaction().await?;
+
+        let mut handles = Vec::new();
+        for (instance, disk_image) in self.disks.iter() {
+            let host_id =
+                instance
+                    .get(&mut transaction)
+                    .await?
+                    .linked_host
+                    .ok_or(TaskError::Reason(format!(
+                        "instance {instance:?} has no host to capture"
+                    )))?;
+
+            handles.push(context.spawn(CaptureDisk {
+                instance: *instance,
+                host_id,
+                disk_image: disk_image.clone(),
+            }));
+        }
+
+        transaction.commit().await?;
+
+        let mut errors = Vec::new();
+        let mut captured = Vec::new();
+        for ((instance, _), handle) in self.disks.iter().zip(handles) {
+            match handle.join() {
+                Ok(()) => captured.push(true),
+                Err(e) => {
+                    captured.push(false);
+                    errors.push(format!("{e:?}"));
+                    send_to_admins(format!(
+                        "Failed to capture the disk of instance {:?} for snapshot {:?}, error: {e:?}",
+                        instance.into_id(),
+                        self.snapshot.into_id()
+                    ))
+                    .await;
+                }
+            }
+        }
+
+        let mut client = new_client().await?;
+        let mut transaction = client.easy_transaction().await?;
+
+        let mut snapshot = self.snapshot.get(&mut transaction).await?;
+        for (host, captured) in snapshot.hosts.iter_mut().zip(captured) {
+            host.captured = captured;
+        }
+        snapshot.captured = Some(Utc::now());
+        let details = match errors.is_empty() {
+            true => {
+                snapshot.state = SnapshotState::Ready;
+                format!(
+                    "captured all {} disk(s) to snapshot {}",
+                    self.disks.len(),
+                    snapshot.name
+                )
+            }
+            false => {
+                snapshot.state = SnapshotState::Failed;
+                snapshot.error = Some(errors.join("; "));
+                format!(
+                    "{} of {} disk(s) couldn't be captured to snapshot {}",
+                    errors.len(),
+                    self.disks.len(),
+                    snapshot.name
+                )
+            }
+        };
+        snapshot.update(&mut transaction).await?;
+
+        if let Some(agg) = snapshot.aggregate {
+            BookingEvent::record(
+                &mut transaction,
+                agg,
+                BookingEventKind::Snapshotted,
+                details,
+                Some(snapshot.owner.clone()),
+            )
+            .await?;
+        }
+
+        transaction.commit().await?;
+
+        match errors.len() {
+            0 => Ok(()),
+            n => Err(TaskError::Reason(format!(
+                "{n} disk(s) couldn't be captured"
+            ))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Hash, Serialize, Deserialize)]
+pub struct CaptureDisk {
+    pub instance: FKey<Instance>,
+    pub host_id: FKey<Host>,
+    pub disk_image: String,
+}
+
+impl CaptureDisk {
+    /// Netboots the host into the capture profile and waits for it to say how it went
+    async fn capture(&self, context: &Context, dynamic: bool) -> Result<(), TaskError> {
+        let snapshots = settings()
+            .snapshots
+            .clone()
+            .ok_or(TaskError::Reason("snapshots aren't configured".to_owned()))?;
+
+        let mut waiter = Mailbox::set_endpoint_hook(self.instance, "snapshot").await?;
+
+        if dynamic {
+            let mut client = new_client().await?;
+            let mut transaction = client.easy_transaction().await?;
+            let net_config = mgmt_network_config(self.host_id, &mut transaction).await;
+            transaction.commit().await?;
+
+            context.spawn(ConfigureNetworking { net_config }).join()?;
+        }
+
+        let cobbler_config_jh = context.spawn(CobblerSetConfiguration {
+            host_id: self.host_id,
+            config: CobblerConfig::new_capture_config(
+                &snapshots,
+                &self.disk_image,
+                waiter.endpoint(),
+            ),
+            endpoint: waiter.endpoint(),
+        });
+
+        retry_for(
+            SetBoot {
+                host_id: self.host_id,
+                persistent: true,
+                boot_to: BootTo::Network,
+            },
+            context,
+            5,
+            10,
+        )?;
+        sleep(Duration::from_secs(2)).await;
+        retry_for(SetPower::off(self.host_id), context, 5, 10)?;
+        cobbler_config_jh.join()?;
+        retry_for(SetPower::on(self.host_id), context, 5, 10)?;
+
+        log(
+            self.instance,
+            "Capturing Disk",
+            &format!("copying the disk of the host to {}", self.disk_image),
+            StatusSentiment::InProgress,
+        )
+        .await;
+
+        let reply = waiter
+            .wait_next(capture_timeout())
+            .map_err(|e| TaskError::Reason(format!("no word from the capture: {e:?}")))?;
+
+        match reply.msg.message.trim() {
+            "ok" => Ok(()),
+            other => Err(TaskError::Reason(format!("the capture failed: {other}"))),
+        }
+    }
+
+    /// Boots the host back into its own OS, on the networks of its booking
+    async fn resume(&self, context: &Context, dynamic: bool) -> Result<(), TaskError> {
+        retry_for(
+            SetBoot {
+                host_id: self.host_id,
+                persistent: true,
+                boot_to: BootTo::Disk,
+            },
+            context,
+            5,
+            10,
+        )?;
+        retry_for(SetPower::off(self.host_id), context, 5, 10)?;
+        sleep(Duration::from_secs(2)).await;
+        retry_for(SetPower::on(self.host_id), context, 5, 10)?;
+
+        if dynamic {
+            let mut client = new_client().await?;
+            let mut transaction = client.easy_transaction().await?;
+            let net_config =
+                prod_network_config(self.host_id, self.instance, &mut transaction).await;
+            transaction.commit().await?;
+
+            context.spawn(ConfigureNetworking { net_config }).join()?;
+        }
+
+        Ok(())
+    }
+}
+
+tascii::mark_task!(CaptureDisk);
+impl AsyncRunnable for CaptureDisk {
+    type Output = ();
+
+    async fn run(&mut self, context: &Context) -> Result<Self::Output, TaskError> {
+        let mut client = new_client().await?;
+        let mut transaction = client.easy_transaction().await?;
+        let agg = self.instance.get(&mut transaction).await?.aggregate;
+        let dynamic = agg
+            .get(&mut transaction)
+            .await?
+            .lab
+            .get(&mut transaction)
+            .await?
+            .is_dynamic;
+        transaction.commit().await?;
+
+        let captured = self.capture(context, dynamic).await;
+
+        // the host goes back to the booking whether or not its disk could be captured
+        let resumed = self.resume(context, dynamic).await;
+
+        match (&captured, &resumed) {
+            (Ok(()), Ok(())) => {
+                log(
+                    self.instance,
+                    "Disk Captured",
+                    &format!("the disk of the host was saved as {}", self.disk_image),
+                    StatusSentiment::Succeeded,
+                )
+                .await
+            }
+            (Err(e), _) | (_, Err(e)) => {
+                log(
+                    self.instance,
+                    "Disk Capture Failed",
+                    &format!("{e:?}"),
+                    StatusSentiment::Failed,
+                )
+                .await
+            }
+        }
+
+        captured.and(resumed)
+    }
+
+    fn summarize(&self, id: ID) -> String {
+        format!(
+            "CaptureDisk task with id {id} capturing {:?} to {}",
+            self.host_id, self.disk_image
+        )
+    }
+
+    fn identifier() -> TaskIdentifier {
+        TaskIdentifier::named("CaptureDiskTask").versioned(1)
+    }
+
+    fn timeout() -> std::time::Duration {
+        capture_timeout() + std::time::Duration::from_secs(15 * 60)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_disk_image_name() {
+        let id = ID::new();
+
+        assert_eq!(disk_image_name(id, "node-1"), format!("{id}-node-1.img"));
+        assert_eq!(
+            disk_image_name(id, "../etc/passwd"),
+            format!("{id}-___etc_passwd.img")
+        );
+    }
+
+    #[test]
+    fn test_image_url() {
+        assert_eq!(
+            image_url("http://images/snapshots/", "a.img"),
+            "http://images/snapshots/a.img"
+        );
+        assert_eq!(
+            image_url("http://images/snapshots", "a.img"),
+            "http://images/snapshots/a.img"
+        );
+    }
+}
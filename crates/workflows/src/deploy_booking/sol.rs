@@ -1,10 +1,16 @@
-use common::prelude::tracing;
+use common::prelude::{lazy_static::lazy_static, parking_lot::Mutex, tracing};
 use std::{
+    collections::HashMap,
     process::{Command, Stdio},
     time::Duration,
 };
 
-use common::prelude::tokio::{self, fs::OpenOptions, io::AsyncWriteExt};
+use common::prelude::tokio::{
+    self,
+    fs::OpenOptions,
+    io::{AsyncReadExt, AsyncWriteExt},
+    sync::broadcast,
+};
 use dal::{new_client, AsEasyTransaction, FKey};
 
 use models::{
@@ -13,6 +19,110 @@ use models::{
 };
 use tascii::prelude::*;
 
+use super::{
+    bmc::ipmi::Ipmi,
+    set_host_power_state::{HostConfig, PowerStateError},
+};
+
+/// How many chunks of output a watcher can fall behind by before it misses some
+const CONSOLE_BACKLOG: usize = 256;
+
+lazy_static! {
+    /// The console sessions being watched, by the BMC they're on. A BMC only allows one
+    /// SOL session at a time, so everyone watching a host shares it.
+    static ref CONSOLES: Mutex<HashMap<String, broadcast::Sender<Vec<u8>>>> =
+        Mutex::new(HashMap::new());
+}
+
+/// Follows what the host prints to its serial console from now on, until the SOL session
+/// ends
+///
+/// The session is opened for the first watcher, taking over any other session on the BMC,
+/// and closed once the last one stops watching.
+pub async fn watch_console(
+    config: HostConfig,
+) -> Result<broadcast::Receiver<Vec<u8>>, PowerStateError> {
+    if let Some(session) = CONSOLES.lock().get(&config.fqdn) {
+        return Ok(session.subscribe());
+    }
+
+    let ipmi = Ipmi(&config);
+    // a session left over from anything else would keep ours from activating
+    let _ = ipmi.command().args(["sol", "deactivate"]).output().await;
+
+    let mut child = ipmi
+        .command()
+        .args(["sol", "activate"])
+        // ipmitool ends the session once its input closes
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| PowerStateError::CommandExecutionFailed(e.to_string()))?;
+    let mut stdout = child
+        .stdout
+        .take()
+        .ok_or(PowerStateError::CommandExecutionFailed(
+            "ipmitool gave no output to read".to_owned(),
+        ))?;
+
+    let (session, watcher) = {
+        let mut consoles = CONSOLES.lock();
+        // someone else may have opened one in the meantime
+        if let Some(session) = consoles.get(&config.fqdn) {
+            return Ok(session.subscribe());
+        }
+
+        let (session, watcher) = broadcast::channel(CONSOLE_BACKLOG);
+        consoles.insert(config.fqdn.clone(), session.clone());
+        (session, watcher)
+    };
+
+    let fqdn = config.fqdn.clone();
+    tokio::spawn(async move {
+        tracing::info!("Opened a console session on {fqdn}");
+
+        let mut buf = [0u8; 4096];
+        // a quiet console is still closed once nobody watches it
+        let mut idle = tokio::time::interval(Duration::from_secs(10));
+        loop {
+            tokio::select! {
+                read = stdout.read(&mut buf) => match read {
+                    Ok(0) | Err(_) => break,
+                    Ok(read) => {
+                        let _ = session.send(buf[..read].to_vec());
+                    }
+                },
+                _ = idle.tick() => {}
+            }
+
+            if session.receiver_count() == 0 {
+                // checked again under the lock, so nobody can start watching as it closes
+                let mut consoles = CONSOLES.lock();
+                if session.receiver_count() == 0 {
+                    consoles.remove(&fqdn);
+                    break;
+                }
+            }
+        }
+
+        {
+            let mut consoles = CONSOLES.lock();
+            if consoles
+                .get(&fqdn)
+                .is_some_and(|s| s.same_channel(&session))
+            {
+                consoles.remove(&fqdn);
+            }
+        }
+
+        let _ = child.kill().await;
+        tracing::info!("Closed the console session on {fqdn}");
+    });
+
+    Ok(watcher)
+}
+
 tascii::mark_task!(StashSOLOutput);
 #[derive(Debug, Clone, Hash, Serialize, Deserialize)]
 pub struct StashSOLOutput {
@@ -0,0 +1,227 @@
+//! A lightweight agent reporting how busy booked hosts are, for bookings that ask for it
+//!
+//! Cloud-init installs a small shell script and a systemd timer that runs it every few
+//! minutes. Each run samples `/proc` for a few seconds and posts CPU, memory, disk and
+//! network utilization to the telemetry route of the mailbox, which keeps it as a
+//! [`TelemetrySample`] for the booking to show.
+
+use axum::{
+    extract::{Json, Path},
+    http::StatusCode,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use common::prelude::{axum, chrono::Utc, schemars, tracing};
+use dal::{new_client, web::*, AsEasyTransaction, FKey, NewRow, ID};
+use models::dashboard::{Instance, TelemetrySample};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::resource_management::mailbox::Mailbox;
+
+/// The mailbox hook the agent reports to
+pub const REPORT_HOOK: &str = "telemetry";
+
+const AGENT_PATH: &str = "/usr/local/bin/laas-telemetry";
+
+/// Samples for a few seconds and posts what it saw to the url it is given
+const AGENT: &str = r#"#!/bin/sh
+# Reports how busy this host is to LaaS, installed because the booking asked for it
+url="$1"
+secs=5
+
+sample() {
+    awk '/^cpu /{print $2 + $3 + $4 + $7 + $8 + $9, $5 + $6}' /proc/stat
+    awk 'NR > 2 {sub(/^ +/, ""); split($0, a, ":"); if (a[1] != "lo") {split(a[2], f, " "); rx += f[1]; tx += f[9]}} END {printf "%d %d\n", rx, tx}' /proc/net/dev
+}
+
+set -- $(sample)
+busy=$1 idle=$2 rx=$3 tx=$4
+sleep $secs
+set -- $(sample)
+
+cpu=$(awk -v b=$(($1 - busy)) -v i=$(($2 - idle)) 'BEGIN {print (b + i > 0 ? 100 * b / (b + i) : 0)}')
+memory=$(awk '/^MemTotal:/ {t = $2} /^MemAvailable:/ {a = $2} END {print (t > 0 ? 100 * (t - a) / t : 0)}' /proc/meminfo)
+disk=$(df -P / | awk 'NR == 2 {sub(/%/, "", $5); print $5}')
+
+curl -s -X POST -H 'Content-Type: application/json' "$url/telemetry" -d "{
+    \"cpu_percent\": $cpu,
+    \"memory_percent\": $memory,
+    \"disk_percent\": ${disk:-0},
+    \"network_rx_bytes_per_sec\": $((($3 - rx) / secs)),
+    \"network_tx_bytes_per_sec\": $((($4 - tx) / secs))
+}"
+"#;
+
+const TIMER: &str = "[Unit]
+Description=Report usage telemetry to LaaS every few minutes
+
+[Timer]
+OnBootSec=2min
+OnUnitActiveSec=5min
+
+[Install]
+WantedBy=timers.target
+";
+
+fn service(url: &str) -> String {
+    format!(
+        "[Unit]
+Description=Report usage telemetry to LaaS
+After=network-online.target
+
+[Service]
+Type=oneshot
+ExecStart={AGENT_PATH} {url}
+"
+    )
+}
+
+/// The shell commands that install the agent, reporting to the mailbox endpoint at `url`
+///
+/// Never fail, since a host without telemetry is still provisioned fine
+pub fn install_commands(url: &str) -> Vec<String> {
+    let write = |content: &str, path: &str| {
+        format!(
+            "echo '{}' | base64 -d > {path} || true",
+            STANDARD.encode(content)
+        )
+    };
+
+    vec![
+        write(AGENT, AGENT_PATH),
+        format!("chmod +x {AGENT_PATH} || true"),
+        write(&service(url), "/etc/systemd/system/laas-telemetry.service"),
+        write(TIMER, "/etc/systemd/system/laas-telemetry.timer"),
+        "systemctl daemon-reload || true".to_owned(),
+        "systemctl enable --now laas-telemetry.timer || true".to_owned(),
+    ]
+}
+
+/// What the agent posts each time it runs
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct TelemetryReport {
+    pub cpu_percent: f64,
+    pub memory_percent: f64,
+    pub disk_percent: f64,
+    pub network_rx_bytes_per_sec: f64,
+    pub network_tx_bytes_per_sec: f64,
+}
+
+impl TelemetryReport {
+    /// The report with anything out of range brought back in, None if it isn't usable at all
+    ///
+    /// Interface counters reset when links bounce, which shows up as a negative rate
+    fn sanitized(self) -> Option<Self> {
+        let values = [
+            self.cpu_percent,
+            self.memory_percent,
+            self.disk_percent,
+            self.network_rx_bytes_per_sec,
+            self.network_tx_bytes_per_sec,
+        ];
+        if values.iter().any(|v| !v.is_finite()) {
+            return None;
+        }
+
+        Some(Self {
+            cpu_percent: self.cpu_percent.clamp(0.0, 100.0),
+            memory_percent: self.memory_percent.clamp(0.0, 100.0),
+            disk_percent: self.disk_percent.clamp(0.0, 100.0),
+            network_rx_bytes_per_sec: self.network_rx_bytes_per_sec.max(0.0),
+            network_tx_bytes_per_sec: self.network_tx_bytes_per_sec.max(0.0),
+        })
+    }
+}
+
+/// Keeps a report the agent on `instance` posted to its telemetry endpoint
+pub async fn receive(
+    Path((instance, unique)): Path<(FKey<Instance>, ID)>,
+    Json(report): Json<TelemetryReport>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    // only the agent that was handed the endpoint may report for the instance
+    let hook = Mailbox::get_endpoint_hook(instance, REPORT_HOOK).await;
+    if !hook.is_ok_and(|h| h.unique == unique) {
+        return Err((
+            StatusCode::NOT_FOUND,
+            "No telemetry is expected at this endpoint".to_owned(),
+        ));
+    }
+
+    let report = report.sanitized().ok_or((
+        StatusCode::UNPROCESSABLE_ENTITY,
+        "Telemetry values must be numbers".to_owned(),
+    ))?;
+
+    let mut client = new_client().await.log_db_client_error()?;
+    let mut transaction = client.easy_transaction().await.log_db_client_error()?;
+
+    let aggregate = instance
+        .get(&mut transaction)
+        .await
+        .log_db_client_error()?
+        .aggregate;
+
+    NewRow::new(TelemetrySample {
+        id: FKey::new_id_dangling(),
+        instance,
+        aggregate,
+        time: Utc::now(),
+        cpu_percent: report.cpu_percent,
+        memory_percent: report.memory_percent,
+        disk_percent: report.disk_percent,
+        network_rx_bytes_per_sec: report.network_rx_bytes_per_sec,
+        network_tx_bytes_per_sec: report.network_tx_bytes_per_sec,
+    })
+    .insert(&mut transaction)
+    .await
+    .log_db_client_error()?;
+
+    transaction.commit().await.log_db_client_error()?;
+
+    tracing::debug!("Kept a telemetry sample for {instance:?}");
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(cpu: f64, rx: f64) -> TelemetryReport {
+        TelemetryReport {
+            cpu_percent: cpu,
+            memory_percent: 50.0,
+            disk_percent: 10.0,
+            network_rx_bytes_per_sec: rx,
+            network_tx_bytes_per_sec: 0.0,
+        }
+    }
+
+    #[test]
+    fn test_sanitized() {
+        let r = report(100.4, -1024.0).sanitized().unwrap();
+        assert_eq!(r.cpu_percent, 100.0);
+        assert_eq!(r.network_rx_bytes_per_sec, 0.0);
+
+        let r = report(12.5, 2048.0).sanitized().unwrap();
+        assert_eq!(r.cpu_percent, 12.5);
+        assert_eq!(r.network_rx_bytes_per_sec, 2048.0);
+
+        assert!(report(f64::NAN, 0.0).sanitized().is_none());
+    }
+
+    #[test]
+    fn test_install_commands() {
+        let commands = install_commands("http://mailbox/1/2");
+
+        let service = commands
+            .iter()
+            .find(|c| c.ends_with("laas-telemetry.service || true"))
+            .unwrap();
+        let encoded = service.split('\'').nth(1).unwrap();
+        let decoded = String::from_utf8(STANDARD.decode(encoded).unwrap()).unwrap();
+
+        assert!(decoded.contains("ExecStart=/usr/local/bin/laas-telemetry http://mailbox/1/2"));
+        assert!(commands.iter().all(|c| c.ends_with("|| true")));
+    }
+}
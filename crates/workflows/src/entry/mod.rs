@@ -14,7 +14,9 @@ use std::sync::Mutex;
 use config::Situation;
 use dal::{new_client, web::*, AsEasyTransaction, FKey, ID};
 use models::{
-    dashboard::{Aggregate, Instance},
+    dashboard::{
+        Aggregate, BookingSnapshot, Checkpoint, HostReplacement, Image, Instance, PortMirror,
+    },
     inventory::Host,
 };
 
@@ -26,7 +28,15 @@ use crossbeam_channel::{Receiver, Sender};
 
 use tascii::prelude::*;
 
-use crate::deploy_booking::{deploy_host::DeployHost, notify::Notify};
+use crate::deploy_booking::{
+    cancel_provision::CancelProvision,
+    expand::ExpandBooking,
+    notify::{Notify, NotifyContext},
+    reimage::Reimage,
+    replace_host::ReplaceHost,
+    rolling_reimage::RollingReimage,
+    snapshot::CaptureSnapshot,
+};
 
 //use crate::actions::{Action, ActionID, StatusHandle};
 
@@ -41,31 +51,106 @@ pub enum Action {
     CleanupBooking {
         agg_id: FKey<Aggregate>,
     },
+    /// Tears down a single host of a running booking, keeping the rest of it
+    ReleaseInstance {
+        agg_id: FKey<Aggregate>,
+        instance: FKey<Instance>,
+        released_by: Option<String>,
+    },
     AddUsers {
         agg_id: FKey<Aggregate>,
         users: Vec<String>,
     },
+    /// Carries a change of who the users of a running booking are through to its hosts
+    ChangeCollaborators {
+        agg_id: FKey<Aggregate>,
+        added: Vec<String>,
+        removed: Vec<String>,
+    },
     Reimage {
         host_id: FKey<Host>,
         inst_id: FKey<Instance>,
         agg_id: FKey<Aggregate>,
     },
+    /// Picks an earlier deploy of the instance back up after `after`, holding the lease
+    /// of the booking like `Reimage` does
+    ResumeDeploy {
+        host_id: FKey<Host>,
+        inst_id: FKey<Instance>,
+        agg_id: FKey<Aggregate>,
+        after: Checkpoint,
+    },
+    /// Cancels the deploy tasks in `tasks`, then powers off the host and takes it out
+    /// of netboot
+    CancelProvision {
+        host_id: FKey<Host>,
+        inst_id: FKey<Instance>,
+        agg_id: FKey<Aggregate>,
+        tasks: Vec<ID>,
+        canceled_by: Option<String>,
+    },
+    RollingReimage {
+        agg_id: FKey<Aggregate>,
+        instances: Vec<FKey<Instance>>,
+        image: Option<FKey<Image>>,
+        batch_size: usize,
+    },
+    ReplaceHost {
+        replacement: FKey<HostReplacement>,
+    },
+    /// Deploys instances just added to a running booking
+    ExpandBooking {
+        agg_id: FKey<Aggregate>,
+        instances: Vec<FKey<Instance>>,
+    },
+    /// Captures the disks of a booking for a snapshot, paired with the instances they come from
+    CaptureSnapshot {
+        snapshot: FKey<BookingSnapshot>,
+        disks: Vec<(FKey<Instance>, String)>,
+    },
     NotifyTask {
         agg_id: FKey<Aggregate>,
         situation: Situation,
-
-        // List of (key, value) for extra items to be rendered in the template
-        // Needs to be a Vec and not a map because task fields need to derive Hash
-        // This was done in an attempt to generify notifications into a single task
-        // Check the Notify task's run method to see expected fields for a template
-        context: Vec<(String, String)>,
+        /// Build with [`Action::notify`], so that it's checked against the situation
+        context: NotifyContext,
+    },
+    ApplyFirewall {
+        agg_id: FKey<Aggregate>,
     },
+    StartPortMirror {
+        mirror: FKey<PortMirror>,
+    },
+    EndPortMirror {
+        mirror: FKey<PortMirror>,
+    },
+    CollectOrphans {
+        dry_run: bool,
+    },
+    ExpireBookings,
+    PurgeTrash,
     // UpdateUser { agg_id: LLID, user: dashboard::UserData },
     // RemoveUser { agg_id: LLID, user: i64 },
     // AddInstance { agg_id: LLID, instance: dashboard::InstanceData },
     // RemoveInstance { agg_id: LLID, instance: dashboard::InstanceData },
 }
 
+impl Action {
+    /// A notification about the aggregate, failing if `context` isn't what `situation` needs
+    pub fn notify(
+        agg_id: FKey<Aggregate>,
+        situation: Situation,
+        context: NotifyContext,
+    ) -> Result<Self, anyhow::Error> {
+        context.validate(&situation)?;
+
+        Ok(Self::NotifyTask {
+            agg_id,
+            situation,
+            context,
+        })
+    }
+}
+
 pub struct Dispatcher {
     rt: &'static Runtime,
 }
@@ -98,20 +183,108 @@ impl Dispatcher {
                 Action::CleanupBooking { agg_id } => {
                     crate::cleanup_booking::CleanupAggregate { agg_id }.into()
                 }
+                Action::ReleaseInstance {
+                    agg_id,
+                    instance,
+                    released_by,
+                } => crate::cleanup_booking::release::ReleaseInstance {
+                    agg_id,
+                    instance,
+                    released_by,
+                }
+                .into(),
                 Action::AddUsers { agg_id, users } => {
                     crate::users::AddUsers { agg_id, users }.into()
                 }
+                Action::ChangeCollaborators {
+                    agg_id,
+                    added,
+                    removed,
+                } => crate::users::ChangeCollaborators {
+                    agg_id,
+                    added,
+                    removed,
+                }
+                .into(),
+                Action::ApplyFirewall { agg_id } => {
+                    crate::resource_management::firewall::ApplyFirewallPolicy { agg_id }.into()
+                }
+                Action::StartPortMirror { mirror } => {
+                    crate::resource_management::mirror::StartPortMirror { mirror }.into()
+                }
+                Action::EndPortMirror { mirror } => {
+                    crate::resource_management::mirror::EndPortMirror { mirror }.into()
+                }
+                Action::CollectOrphans { dry_run } => {
+                    crate::resource_management::orphans::CollectOrphans { dry_run }.into()
+                }
+                Action::ExpireBookings => {
+                    crate::resource_management::expiry::ExpireBookings {}.into()
+                }
+                Action::PurgeTrash => crate::resource_management::trash::PurgeTrash {}.into(),
                 Action::Reimage {
                     agg_id,
                     inst_id,
                     host_id,
-                } => DeployHost {
+                } => Reimage {
                     host_id,
-                    aggregate_id: agg_id,
-                    using_instance: inst_id,
-                    distribution: None,
+                    agg_id,
+                    inst_id,
+                    resume_from: None,
                 }
                 .into(),
+                Action::ResumeDeploy {
+                    agg_id,
+                    inst_id,
+                    host_id,
+                    after,
+                } => Reimage {
+                    host_id,
+                    agg_id,
+                    inst_id,
+                    resume_from: Some(after),
+                }
+                .into(),
+                Action::CancelProvision {
+                    host_id,
+                    inst_id,
+                    agg_id,
+                    tasks,
+                    canceled_by,
+                } => {
+                    for task in tasks {
+                        self.rt.cancel(task);
+                    }
+
+                    CancelProvision {
+                        host_id,
+                        inst_id,
+                        agg_id,
+                        canceled_by,
+                    }
+                    .into()
+                }
+                Action::RollingReimage {
+                    agg_id,
+                    instances,
+                    image,
+                    batch_size,
+                } => RollingReimage {
+                    aggregate: agg_id,
+                    instances,
+                    image,
+                    batch_size,
+                }
+                .into(),
+                Action::ReplaceHost { replacement } => ReplaceHost { replacement }.into(),
+                Action::ExpandBooking { agg_id, instances } => ExpandBooking {
+                    aggregate: agg_id,
+                    instances,
+                }
+                .into(),
+                Action::CaptureSnapshot { snapshot, disks } => {
+                    CaptureSnapshot { snapshot, disks }.into()
+                }
                 Action::NotifyTask {
                     agg_id,
                     situation,
@@ -119,7 +292,7 @@ impl Dispatcher {
                 } => Notify {
                     aggregate: agg_id,
                     situation,
-                    extra_context: context,
+                    context,
                 }
                 .into(), // Action::UpdateUser { agg_id, user } => {
                          //     // TODO: Create task
@@ -0,0 +1,120 @@
+//! Runs the standard benchmark suite against a host and records the score,
+//! enrolled by host inspection as part of burn-in or from the CLI as an opt-in re-run
+
+use common::prelude::chrono::Utc;
+use dal::{new_client, AsEasyTransaction, FKey, NewRow};
+use models::inventory::{host::benchmark::STANDARD_BENCHMARK, Host, HostBenchmark, TicketKind};
+use serde::{Deserialize, Serialize};
+use ssh2::Session;
+use std::{io::Read, net::TcpStream, time::Duration};
+use tascii::{prelude::*, task_trait::AsyncRunnable};
+
+use crate::resource_management::tickets::report_host_failure;
+
+/// How long the cpu benchmark runs for on the host
+const BENCHMARK_SECONDS: u32 = 30;
+
+#[derive(Debug, Clone, Hash, Serialize, Deserialize)]
+pub struct BenchmarkHost {
+    pub host_id: FKey<Host>,
+    pub host_address: String,
+    pub username: String,
+    pub passwd: String,
+}
+
+tascii::mark_task!(BenchmarkHost);
+impl AsyncRunnable for BenchmarkHost {
+    type Output = f64;
+
+    async fn run(&mut self, _context: &Context) -> Result<Self::Output, TaskError> {
+        let res = self.benchmark().await;
+
+        if let Err(e) = &res {
+            report_host_failure(
+                self.host_id,
+                TicketKind::BurnInFailure,
+                format!("The {STANDARD_BENCHMARK} benchmark failed to run: {e:?}"),
+            )
+            .await;
+        }
+
+        res
+    }
+
+    fn identifier() -> TaskIdentifier {
+        TaskIdentifier::named("BenchmarkHostTask").versioned(1)
+    }
+
+    fn timeout() -> Duration {
+        Duration::from_secs_f64(BENCHMARK_SECONDS as f64 + 120.0)
+    }
+
+    fn retry_count(&self) -> usize {
+        1
+    }
+}
+
+impl BenchmarkHost {
+    async fn benchmark(&self) -> Result<f64, TaskError> {
+        let mut session = Session::new()
+            .map_err(|e| TaskError::Reason(format!("couldn't create ssh session: {e}")))?;
+        let connection = TcpStream::connect(format!("{}:22", self.host_address))
+            .map_err(|e| TaskError::Reason(format!("couldn't connect to host: {e}")))?;
+
+        session.set_tcp_stream(connection);
+        session
+            .handshake()
+            .map_err(|e| TaskError::Reason(format!("ssh handshake failed: {e}")))?;
+        session
+            .userauth_password(self.username.as_str(), self.passwd.as_str())
+            .map_err(|e| TaskError::Reason(format!("ssh authentication failed: {e}")))?;
+
+        let output = {
+            let mut channel = session
+                .channel_session()
+                .map_err(|e| TaskError::Reason(format!("couldn't open ssh channel: {e}")))?;
+            let mut res = String::new();
+
+            channel
+                .exec(&format!(
+                    "sysbench cpu --threads=$(nproc) --time={BENCHMARK_SECONDS} run"
+                ))
+                .map_err(|e| TaskError::Reason(format!("couldn't run benchmark: {e}")))?;
+            channel
+                .read_to_string(&mut res)
+                .map_err(|e| TaskError::Reason(format!("couldn't read benchmark output: {e}")))?;
+
+            res
+        };
+
+        let score = parse_sysbench_score(&output).ok_or(TaskError::Reason(format!(
+            "benchmark output did not contain a score: {output}"
+        )))?;
+
+        let mut client = new_client().await.unwrap();
+        let mut transaction = client.easy_transaction().await.unwrap();
+
+        NewRow::new(HostBenchmark {
+            id: FKey::new_id_dangling(),
+            host: self.host_id,
+            suite: STANDARD_BENCHMARK.to_owned(),
+            score,
+            recorded: Utc::now(),
+        })
+        .insert(&mut transaction)
+        .await
+        .map_err(|e| TaskError::Reason(format!("couldn't record benchmark: {e:?}")))?;
+
+        transaction.commit().await.unwrap();
+
+        Ok(score)
+    }
+}
+
+/// Pulls the `events per second` figure out of sysbench cpu output
+fn parse_sysbench_score(output: &str) -> Option<f64> {
+    output
+        .lines()
+        .find_map(|l| l.trim().strip_prefix("events per second:"))
+        .and_then(|v| v.trim().parse().ok())
+}
@@ -1,8 +1,11 @@
+pub mod benchmark;
+
+use common::prelude::tracing;
 use dal::{new_client, AsEasyTransaction, FKey, Lookup, ID};
 use eui48::MacAddress;
 
 use models::inventory::{
-    Arch, DataUnit, DataValue, Flavor, HostPort, ImportFlavor, ImportHost, Lab,
+    Arch, DataUnit, DataValue, Flavor, Host, HostPort, ImportFlavor, ImportHost, Lab,
 };
 use serde::{Deserialize, Serialize};
 
@@ -17,6 +20,8 @@ use std::{
 };
 use tascii::{prelude::*, task_trait::AsyncRunnable};
 
+use self::benchmark::BenchmarkHost;
+
 #[derive(Debug, Clone, Hash, Serialize, Deserialize)]
 pub struct Disks {
     blockdevices: Vec<Disk>,
@@ -115,6 +120,9 @@ impl AsyncRunnable for InspectHost {
                 "" => None,
                 s => Some(s.to_owned()),
             },
+            failure_domain: Default::default(),
+            bmc_protocol: Default::default(),
+            funded_by: None,
         };
 
         let conn_info = {
@@ -272,6 +280,7 @@ impl AsyncRunnable for InspectHost {
                 swap_size,
                 brand: self.brand.clone(),
                 model: self.model.clone(),
+                capabilities: Default::default(),
             };
 
             // write flavor to file too
@@ -334,7 +343,28 @@ impl AsyncRunnable for InspectHost {
         }
         .expect("Expected to write host data");
 
-        todo!()
+        // burn-in: the host is benchmarked as soon as it's in the inventory, so
+        // the allocator has a score to compare it against the rest of its flavor with
+        match Host::get_by_name(&mut transaction, self.host_name.clone()).await {
+            Ok(host) => {
+                context
+                    .spawn(BenchmarkHost {
+                        host_id: host.id,
+                        host_address: self.host_address.clone(),
+                        username: self.username.clone(),
+                        passwd: self.passwd.clone(),
+                    })
+                    .join()?;
+            }
+            Err(_) => tracing::warn!(
+                "{} isn't in the inventory yet, so benchmark it once it's been imported",
+                self.host_name
+            ),
+        }
+
+        transaction.commit().await?;
+
+        Ok(())
     }
 
     fn identifier() -> TaskIdentifier {
@@ -351,7 +381,7 @@ impl AsyncRunnable for InspectHost {
     }
 
     fn timeout() -> Duration {
-        Duration::from_secs_f64(240.0)
+        Duration::from_secs_f64(240.0) + BenchmarkHost::timeout()
     }
 
     fn retry_count(&self) -> usize {
@@ -361,7 +391,7 @@ impl AsyncRunnable for InspectHost {
 
 async fn install_deps(session: &Session) {
     let mut channel = session.channel_session().unwrap();
-    let deps = "lldpd lldpctl awk lscpu free killall systemctl ethtool ip";
+    let deps = "lldpd lldpctl awk lscpu free killall systemctl ethtool ip sysbench";
 
     if {
         let mut res = String::new();
@@ -1,3 +1,4 @@
+use common::prelude::{chrono, itertools::Itertools};
 use dal::{web::*, *};
 use dashmap::DashMap;
 use models::allocator::*;
@@ -5,13 +6,105 @@ use models::{dashboard::*, inventory::Lab};
 
 use models::{
     dashboard::Aggregate,
-    inventory::{Flavor, Host, Vlan},
+    inventory::{
+        host::benchmark, FailureDomainKind, Flavor, FlavorCapabilities, Host, HostBenchmark,
+        HostIssue, IssueArea, Vlan,
+    },
 };
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
 
 // TODO: better tracing in this module
 use tracing::warn;
 
+/// The parts of a host of a flavor with `capabilities` that a booking can't do without,
+/// so hosts with known issues in them are passed over
+///
+/// Issues anywhere else are only shown to the booking.
+pub fn needed_areas(
+    performance_tolerance: Option<f64>,
+    capabilities: &FlavorCapabilities,
+) -> Vec<IssueArea> {
+    let mut areas = vec![IssueArea::Management];
+    if performance_tolerance.is_some() {
+        areas.push(IssueArea::Performance);
+    }
+    if !capabilities.accelerators.is_empty() {
+        areas.push(IssueArea::Accelerator);
+    }
+
+    areas
+}
+
+/// When enough of the hosts in use, which free up at `ends`, will have been released
+/// to make up for being `short` hosts short
+pub fn earliest_enough(
+    short: usize,
+    mut ends: Vec<chrono::DateTime<chrono::Utc>>,
+) -> Option<chrono::DateTime<chrono::Utc>> {
+    if short == 0 {
+        return None;
+    }
+
+    ends.sort();
+    ends.get(short - 1).copied()
+}
+
+/// Key in the metadata of an instance of the [`ConstraintMatch`] its host was picked by
+pub const MATCH_KEY: &str = "constraint_match";
+
+/// A flavor that a host request with constraints wasn't filled from, and why
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema, PartialEq)]
+pub struct PassedOver {
+    pub flavor: String,
+    /// The constraints hosts of the flavor don't meet, or why none of them could be had
+    pub reasons: Vec<String>,
+}
+
+/// How the flavor of a host requested by constraints rather than by flavor was picked
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema, PartialEq)]
+pub struct ConstraintMatch {
+    pub flavor: FKey<Flavor>,
+    pub flavor_name: String,
+    pub hourly_cost: Option<f64>,
+    /// Every constraint the request had
+    pub constraints: Vec<String>,
+    /// The constraints that ruled out a cheaper flavor, so made the host cost what it does
+    pub driven_by: Vec<String>,
+    /// The cheaper flavors that were passed over, cheapest first
+    pub passed_over: Vec<PassedOver>,
+}
+
+/// Orders `flavors`, with their hourly cost, cheapest first
+///
+/// Flavors without a cost go after those with one, and between flavors costing the same,
+/// the one with less hardware goes first so bigger hosts are left for those who need them.
+pub fn cheapest_first(flavors: &mut [(Flavor, Option<f64>)]) {
+    flavors.sort_by(|(a, a_cost), (b, b_cost)| {
+        let by_cost = match (a_cost, b_cost) {
+            (Some(a), Some(b)) => a.total_cmp(b),
+            (Some(_), None) => std::cmp::Ordering::Less,
+            (None, Some(_)) => std::cmp::Ordering::Greater,
+            (None, None) => std::cmp::Ordering::Equal,
+        };
+
+        by_cost
+            .then(a.cpu_count.cmp(&b.cpu_count))
+            .then(a.ram.bytes().cmp(&b.ram.bytes()))
+            .then(a.name.cmp(&b.name))
+    });
+}
+
+/// Whether a host funded by `funded_by` can be given to a booking made for `project`,
+/// which hosts nobody funded always can be
+pub fn funding_allows(funded_by: Option<&str>, project: Option<&str>) -> bool {
+    match funded_by {
+        None => true,
+        Some(funder) => project == Some(funder),
+    }
+}
+
 pub struct Allocator {
     token: AllocatorToken,
 
@@ -93,6 +186,7 @@ impl Allocator {
         agg: FKey<Aggregate>,
     ) -> Result<(), anyhow::Error> {
         tracing::info!("Deallocating aggregate {agg:?}");
+        self.cancel_reservations(t, agg).await?;
         ResourceHandle::deallocate_all(&self.token, t, agg).await
     }
 
@@ -122,38 +216,126 @@ impl Allocator {
         let _lock = self.lock.lock().await;
         let mut t = t.easy_transaction().await?;
 
-        let lab = match for_aggregate.get(&mut t).await {
-            Ok(a) => match a.metadata.lab.clone() {
-                Some(lab_name) => match Lab::get_by_name(&mut t, lab_name).await {
-                    Ok(lab_res) => match lab_res {
-                        Some(l) => l,
-                        None => {
-                            return Err(anyhow::Error::msg(
-                                "Lab does not exist, unable to allocate",
-                            ))
-                        }
-                    },
-                    Err(_e) => {
-                        return Err(anyhow::Error::msg("Error finding lab, unable to allocate"))
+        let agg = for_aggregate.get(&mut t).await?;
+        let until = agg.metadata.end;
+
+        let lab = match agg.metadata.lab.clone() {
+            Some(lab_name) => match Lab::get_by_name(&mut t, lab_name).await {
+                Ok(lab_res) => match lab_res {
+                    Some(l) => l,
+                    None => {
+                        return Err(anyhow::Error::msg("Lab does not exist, unable to allocate"))
                     }
                 },
-                None => return Err(anyhow::Error::msg("No lab provided, unable to allocate")),
+                Err(_e) => return Err(anyhow::Error::msg("Error finding lab, unable to allocate")),
             },
-            Err(e) => return Err(e),
+            None => return Err(anyhow::Error::msg("No lab provided, unable to allocate")),
         };
 
-        let res = ResourceHandle::allocate_one(
-            &self.token,
-            &mut t,
-            ResourceRequestInner::HostByFlavor {
-                flavor,
-                lab: lab.id,
-            },
-            Some(for_aggregate),
-            reason,
-            &self.except_resources(),
-        )
-        .await
+        // hosts that are reserved by a scheduled booking starting before this one
+        // ends can't be handed out, even though they are currently free
+        let mut except = self.except_resources();
+        except.extend(
+            Reservation::conflicting(&mut t, until, Some(for_aggregate))
+                .await?
+                .into_iter()
+                .map(|r| r.for_resource),
+        );
+
+        if let Some(kind) = agg.metadata.spread {
+            except.extend(
+                self.spread_conflicts(&mut t, for_aggregate, flavor, lab.id, kind)
+                    .await?,
+            );
+        }
+
+        let areas = needed_areas(
+            agg.metadata.performance_tolerance,
+            &flavor.get(&mut t).await?.capabilities,
+        );
+        except.extend(self.issue_conflicts(&mut t, &areas).await?);
+
+        if agg.metadata.ownership_override.is_none() {
+            except.extend(
+                self.funding_conflicts(&mut t, flavor, lab.id, agg.metadata.project.as_deref())
+                    .await?,
+            );
+        }
+
+        // a booking that was scheduled ahead is given the hosts it reserved while they are free
+        for reservation in Reservation::all_for_aggregate(&mut t, for_aggregate).await? {
+            if reservation.cancelled || except.contains(&reservation.for_resource) {
+                continue;
+            }
+            let ResourceHandleInner::Host(host) =
+                reservation.for_resource.get(&mut t).await?.tracks
+            else {
+                continue;
+            };
+            if host.get(&mut t).await?.flavor != flavor {
+                continue;
+            }
+
+            let mut st = t.easy_transaction().await?;
+            let reserved = ResourceHandle::allocate_one(
+                &self.token,
+                &mut st,
+                ResourceRequestInner::SpecificHost { host, lab: lab.id },
+                Some(for_aggregate),
+                reason,
+                &except,
+            )
+            .await;
+            match reserved {
+                Ok(handle) => {
+                    st.commit().await?;
+                    t.commit().await?;
+                    return Ok((host, handle.into_inner()));
+                }
+                Err(_) => st.rollback().await?,
+            }
+        }
+
+        let request = ResourceRequestInner::HostByFlavor {
+            flavor,
+            lab: lab.id,
+        };
+
+        // performance sensitive bookings first try only hosts that benchmark close
+        // to the flavor median, falling back to any host of the flavor
+        let preferred = match agg.metadata.performance_tolerance {
+            Some(percent) => {
+                let mut preferred_except = except.clone();
+                preferred_except.extend(self.benchmark_outliers(&mut t, flavor, percent).await?);
+
+                ResourceHandle::allocate_one(
+                    &self.token,
+                    &mut t,
+                    request.clone(),
+                    Some(for_aggregate),
+                    reason,
+                    &preferred_except,
+                )
+                .await
+                .ok()
+            }
+            None => None,
+        };
+
+        let res = match preferred {
+            Some(handle) => Ok(handle),
+            None => {
+                ResourceHandle::allocate_one(
+                    &self.token,
+                    &mut t,
+                    request,
+                    Some(for_aggregate),
+                    reason,
+                    &except,
+                )
+                .await
+            }
+        }
         .map(|v| v.into_inner());
 
         match res {
@@ -182,14 +364,427 @@ impl Allocator {
                 )))
             }
             Err(e) => {
+                let conflicts = self
+                    .schedule_conflicts(&mut t, flavor, lab.id, until, Some(for_aggregate))
+                    .await
+                    .unwrap_or_default();
+
                 let _ = t.rollback(); // let go of the resource, on failure this automatically happens at "some point" anyway
-                Err(e)
+
+                if conflicts.is_empty() {
+                    Err(e)
+                } else {
+                    Err(anyhow::Error::msg(format!(
+                        "{e}, matching hosts are reserved by upcoming bookings: {}",
+                        conflicts.iter().map(|c| c.to_string()).join("; ")
+                    )))
+                }
             }
         }
 
         //Self::instance().allocate_host_internal()
     }
 
+    /// Allocates the cheapest host that meets `constraints` and that `image` can be
+    /// installed on, for a host requested by what it has to have rather than by flavor
+    ///
+    /// Flavors are tried cheapest first, by the hourly costs configured for accounting,
+    /// until one has a host free for the booking.
+    pub async fn allocate_matching(
+        &self,
+        t: &mut EasyTransaction<'_>,
+        constraints: &HostConstraints,
+        image: &Image,
+        for_aggregate: FKey<Aggregate>,
+        reason: AllocationReason,
+        fake: bool,
+    ) -> Result<(FKey<Host>, ResourceHandle, ConstraintMatch), anyhow::Error> {
+        let costs = &config::settings().accounting.flavor_hourly_costs;
+
+        let mut flavors = Vec::new();
+        for flavor in image.flavors.iter() {
+            let flavor = flavor.get(t).await?.into_inner();
+            let cost = costs.get(&flavor.name).copied();
+            flavors.push((flavor, cost));
+        }
+        cheapest_first(&mut flavors);
+
+        let mut passed_over = Vec::new();
+        let mut driven_by: Vec<String> = Vec::new();
+        for (flavor, hourly_cost) in flavors {
+            let nics = flavor.ports(t).await?.len();
+            let unmet = constraints.unmet_by(&flavor, nics);
+            if !unmet.is_empty() {
+                for constraint in unmet.iter() {
+                    if !driven_by.contains(constraint) {
+                        driven_by.push(constraint.clone());
+                    }
+                }
+                passed_over.push(PassedOver {
+                    flavor: flavor.name,
+                    reasons: unmet,
+                });
+                continue;
+            }
+
+            match self
+                .allocate_host(t, flavor.id, for_aggregate, reason, fake)
+                .await
+            {
+                Ok((host, handle)) => {
+                    let matched = ConstraintMatch {
+                        flavor: flavor.id,
+                        flavor_name: flavor.name,
+                        hourly_cost,
+                        constraints: constraints.describe(),
+                        driven_by,
+                        passed_over,
+                    };
+
+                    return Ok((host, handle, matched));
+                }
+                Err(e) => passed_over.push(PassedOver {
+                    flavor: flavor.name,
+                    reasons: vec![format!("no host was free: {e}")],
+                }),
+            }
+        }
+
+        let tried = passed_over
+            .iter()
+            .map(|p| format!("{} ({})", p.flavor, p.reasons.join(", ")))
+            .join("; ");
+        Err(anyhow::Error::msg(format!(
+            "no host with {} that {} can be installed on is free, passed over {}",
+            constraints.describe().join(", "),
+            image.name,
+            match tried.is_empty() {
+                true => "nothing, as the image has no flavors".to_owned(),
+                false => tried,
+            }
+        )))
+    }
+
+    /// Handles of the hosts of `flavor` whose latest benchmark score is
+    /// not within `percent` percent of the flavor median
+    pub async fn benchmark_outliers(
+        &self,
+        t: &mut EasyTransaction<'_>,
+        flavor: FKey<Flavor>,
+        percent: f64,
+    ) -> Result<Vec<FKey<ResourceHandle>>, anyhow::Error> {
+        let results = HostBenchmark::latest_for_flavor(t, flavor).await?;
+        let scores = results.iter().map(|r| r.score).collect_vec();
+
+        let median = match benchmark::median(&scores) {
+            Some(m) => m,
+            None => return Ok(vec![]),
+        };
+
+        let mut outliers = Vec::new();
+        for result in results {
+            if !benchmark::within_percent_of(result.score, median, percent) {
+                outliers.push(ResourceHandle::handle_for_host(t, result.host).await?.id);
+            }
+        }
+
+        Ok(outliers)
+    }
+
+    /// Handles of the hosts with a known issue in any of `areas`
+    pub async fn issue_conflicts(
+        &self,
+        t: &mut EasyTransaction<'_>,
+        areas: &[IssueArea],
+    ) -> Result<Vec<FKey<ResourceHandle>>, anyhow::Error> {
+        let mut flagged = Vec::new();
+        for issue in HostIssue::active(t).await? {
+            if areas.contains(&issue.area) {
+                flagged.push(ResourceHandle::handle_for_host(t, issue.host).await?.id);
+            }
+        }
+
+        Ok(flagged)
+    }
+
+    /// Handles of the free hosts of `flavor` funded by a project other than `project`, so
+    /// that only bookings of that project can be given them
+    pub async fn funding_conflicts(
+        &self,
+        t: &mut EasyTransaction<'_>,
+        flavor: FKey<Flavor>,
+        lab: FKey<Lab>,
+        project: Option<&str>,
+    ) -> Result<Vec<FKey<ResourceHandle>>, anyhow::Error> {
+        let host_tn = Host::table_name();
+        let free = ResourceHandle::query_free::<Host>(
+            t,
+            lab,
+            Some(format!("{host_tn}.flavor = $1")),
+            None,
+            &[&flavor],
+            &[],
+        )
+        .await?;
+
+        let mut conflicts = Vec::new();
+        for (host, handle) in free {
+            if !funding_allows(host.get(t).await?.funded_by.as_deref(), project) {
+                conflicts.push(handle);
+            }
+        }
+
+        Ok(conflicts)
+    }
+
+    /// Handles of the free hosts of `flavor` that would put a second host of
+    /// `for_aggregate` into one of its already occupied failure domains of the given
+    /// kind, along with any whose failure domain is unknown (as placement
+    /// can't be guaranteed for those)
+    pub async fn spread_conflicts(
+        &self,
+        t: &mut EasyTransaction<'_>,
+        for_aggregate: FKey<Aggregate>,
+        flavor: FKey<Flavor>,
+        lab: FKey<Lab>,
+        kind: FailureDomainKind,
+    ) -> Result<Vec<FKey<ResourceHandle>>, anyhow::Error> {
+        let mut occupied = HashSet::new();
+        for allocation in Allocation::all_for_aggregate(t, for_aggregate).await? {
+            if allocation.ended.is_some() {
+                continue;
+            }
+
+            if let ResourceHandleInner::Host(h) = allocation.for_resource.get(t).await?.tracks {
+                if let Some(domain) = h.get(t).await?.failure_domain.get(kind) {
+                    occupied.insert(domain.to_owned());
+                }
+            }
+        }
+
+        let host_tn = Host::table_name();
+        let free = ResourceHandle::query_free::<Host>(
+            t,
+            lab,
+            Some(format!("{host_tn}.flavor = $1")),
+            None,
+            &[&flavor],
+            &[],
+        )
+        .await?;
+
+        let mut conflicts = Vec::new();
+        for (host, handle) in free {
+            match host.get(t).await?.failure_domain.get(kind) {
+                Some(domain) if !occupied.contains(domain) => (),
+                _ => conflicts.push(handle),
+            }
+        }
+
+        Ok(conflicts)
+    }
+
+    /// Where every host of `flavor` in `lab` stands for `for_aggregate`, to explain
+    /// why `needed` of them couldn't be allocated to it. Each host is only counted under
+    /// the first thing that keeps it from being handed out
+    pub async fn availability(
+        &self,
+        t: &mut EasyTransaction<'_>,
+        flavor: FKey<Flavor>,
+        lab: FKey<Lab>,
+        for_aggregate: FKey<Aggregate>,
+        needed: usize,
+    ) -> Result<HostAvailability, anyhow::Error> {
+        let agg = for_aggregate.get(t).await?;
+        let host_tn = Host::table_name();
+        let filter = Some(format!("{host_tn}.flavor = $1"));
+
+        let mut availability = HostAvailability::default();
+
+        let mut ends = Vec::new();
+        for (_, handle) in
+            ResourceHandle::query_allocated::<Host>(t, lab, filter.clone(), None, &[&flavor], &[])
+                .await?
+        {
+            let Some(allocation) = Allocation::find(t, handle, false).await?.pop() else {
+                continue;
+            };
+
+            match (allocation.reason_started, allocation.for_aggregate) {
+                (AllocationReason::ForBooking, Some(holder)) => {
+                    availability.in_use += 1;
+                    ends.extend(holder.get(t).await?.metadata.end);
+                }
+                (AllocationReason::ForBooking, None) => availability.in_use += 1,
+                (AllocationReason::ForMaintenance | AllocationReason::ForRetiry, _) => {
+                    availability.maintenance += 1
+                }
+            }
+        }
+
+        let reserved: HashSet<_> = Reservation::conflicting(t, agg.metadata.end, Some(agg.id))
+            .await?
+            .into_iter()
+            .map(|r| r.for_resource)
+            .collect();
+        let areas = needed_areas(
+            agg.metadata.performance_tolerance,
+            &flavor.get(t).await?.capabilities,
+        );
+        let flagged: HashSet<_> = self.issue_conflicts(t, &areas).await?.into_iter().collect();
+        let funded: HashSet<_> = match agg.metadata.ownership_override {
+            Some(_) => HashSet::new(),
+            None => self
+                .funding_conflicts(t, flavor, lab, agg.metadata.project.as_deref())
+                .await?
+                .into_iter()
+                .collect(),
+        };
+        let spread: HashSet<_> = match agg.metadata.spread {
+            Some(kind) => self
+                .spread_conflicts(t, for_aggregate, flavor, lab, kind)
+                .await?
+                .into_iter()
+                .collect(),
+            None => HashSet::new(),
+        };
+
+        for (_, handle) in
+            ResourceHandle::query_free::<Host>(t, lab, filter, None, &[&flavor], &[]).await?
+        {
+            if reserved.contains(&handle) {
+                availability.reserved += 1;
+            } else if flagged.contains(&handle) {
+                availability.known_issues += 1;
+            } else if funded.contains(&handle) {
+                availability.funded_elsewhere += 1;
+            } else if spread.contains(&handle) {
+                availability.spread += 1;
+            } else {
+                availability.available += 1;
+            }
+        }
+
+        availability.earliest_available =
+            earliest_enough(needed.saturating_sub(availability.available), ends);
+
+        Ok(availability)
+    }
+
+    /// Lists the currently free hosts of `flavor` that can not be used until `until`
+    /// because a scheduled booking (other than `except_aggregate`) has reserved them
+    pub async fn schedule_conflicts(
+        &self,
+        t: &mut EasyTransaction<'_>,
+        flavor: FKey<Flavor>,
+        lab: FKey<Lab>,
+        until: Option<chrono::DateTime<chrono::Utc>>,
+        except_aggregate: Option<FKey<Aggregate>>,
+    ) -> Result<Vec<ScheduleConflict>, anyhow::Error> {
+        let host_tn = Host::table_name();
+        let free = ResourceHandle::query_free::<Host>(
+            t,
+            lab,
+            Some(format!("{host_tn}.flavor = $1")),
+            None,
+            &[&flavor],
+            &[],
+        )
+        .await?;
+
+        let reservations = Reservation::conflicting(t, until, except_aggregate).await?;
+
+        let mut conflicts = Vec::new();
+        for (host, handle) in free {
+            for r in reservations.iter().filter(|r| r.for_resource == handle) {
+                conflicts.push(ScheduleConflict {
+                    host,
+                    server_name: host.get(t).await?.server_name.clone(),
+                    reserved_for: r.for_aggregate,
+                    starts: r.starts,
+                    ends: r.ends,
+                });
+            }
+        }
+
+        Ok(conflicts)
+    }
+
+    /// Reserves a host for `for_aggregate` over the given window, so that
+    /// bookings that would still hold it by `starts` are not given it
+    pub async fn reserve_host(
+        &self,
+        t: &mut EasyTransaction<'_>,
+        host: FKey<Host>,
+        for_aggregate: FKey<Aggregate>,
+        starts: chrono::DateTime<chrono::Utc>,
+        ends: Option<chrono::DateTime<chrono::Utc>>,
+    ) -> Result<FKey<Reservation>, anyhow::Error> {
+        let _lock = self.lock.lock().await;
+        let mut t = t.easy_transaction().await?;
+
+        let handle = ResourceHandle::handle_for_host(&mut t, host).await?;
+
+        let reservation = Reservation {
+            id: FKey::new_id_dangling(),
+            for_resource: handle.id,
+            for_aggregate,
+            starts,
+            ends,
+            cancelled: false,
+        };
+
+        let now = chrono::Utc::now();
+        if let Some(other) = Reservation::upcoming(&mut t)
+            .await?
+            .into_iter()
+            .find(|r| r.for_resource == handle.id && r.overlaps(starts.max(now), ends))
+        {
+            return Err(anyhow::Error::msg(format!(
+                "host is already reserved by aggregate {:?} starting {}",
+                other.for_aggregate, other.starts
+            )));
+        }
+
+        let id = NewRow::new(reservation).insert(&mut t).await?;
+
+        // a booking reserves each of its hosts in turn, but is only scheduled once
+        let scheduled = BookingEvent::for_aggregate(&mut t, for_aggregate)
+            .await?
+            .iter()
+            .any(|e| e.kind == BookingEventKind::Scheduled);
+        if !scheduled {
+            BookingEvent::record(
+                &mut t,
+                for_aggregate,
+                BookingEventKind::Scheduled,
+                format!("hosts were reserved from {}", starts.to_rfc2822()),
+                None,
+            )
+            .await?;
+        }
+
+        t.commit().await?;
+
+        Ok(id)
+    }
+
+    /// Cancels every reservation held by the given aggregate
+    pub async fn cancel_reservations(
+        &self,
+        t: &mut EasyTransaction<'_>,
+        agg: FKey<Aggregate>,
+    ) -> Result<(), anyhow::Error> {
+        for mut reservation in Reservation::all_for_aggregate(t, agg).await? {
+            if !reservation.cancelled {
+                reservation.cancelled = true;
+                reservation.update(t).await?;
+            }
+        }
+
+        Ok(())
+    }
+
     pub async fn allocate_specific_host(
         &self,
         t: &mut EasyTransaction<'_>,
@@ -248,6 +843,32 @@ impl Allocator {
         }
     }
 
+    /// Takes a free host out of commission for good, outside of any booking
+    pub async fn retire_host(
+        &self,
+        t: &mut EasyTransaction<'_>,
+        host: FKey<Host>,
+    ) -> Result<(), anyhow::Error> {
+        let _lock = self.lock.lock().await;
+
+        let lab = ResourceHandle::handle_for_host(t, host)
+            .await?
+            .lab
+            .ok_or(anyhow::Error::msg(format!("host {host:?} isn't in a lab")))?;
+
+        ResourceHandle::allocate_one(
+            &self.token,
+            t,
+            ResourceRequestInner::SpecificHost { host, lab },
+            None,
+            AllocationReason::ForRetiry,
+            &self.except_resources(),
+        )
+        .await?;
+
+        Ok(())
+    }
+
     /// Should never panic, as it is called with an exclusive allocator lock held
     pub async fn allocate_vlans_for(
         &self,
@@ -507,9 +1128,148 @@ pub struct ResourceResponse {
     pub handle: Result<ResourceHandle, AllocationFailure>,
 }
 
+/// How many hosts of a flavor can be allocated to a booking, and what keeps the rest
+#[derive(Clone, Debug, Default, Serialize, Deserialize, JsonSchema)]
+pub struct HostAvailability {
+    /// Free hosts that could be given to the booking
+    pub available: usize,
+    /// Hosts in other bookings
+    pub in_use: usize,
+    /// Hosts taken out for maintenance or retired
+    pub maintenance: usize,
+    /// Free hosts held for scheduled bookings that start before this one would end
+    pub reserved: usize,
+    /// Free hosts with known issues in parts the booking needs
+    pub known_issues: usize,
+    /// Free hosts funded by other projects, which only their bookings can be given
+    pub funded_elsewhere: usize,
+    /// Free hosts that would break the booking's failure domain spread
+    pub spread: usize,
+    /// When enough hosts in use will have been released to fill the booking, if the
+    /// bookings holding them end on time (RFC 3339)
+    #[schemars(with = "Option<String>")]
+    pub earliest_available: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+/// A free host that could not be allocated because it is reserved by a scheduled booking
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ScheduleConflict {
+    pub host: FKey<Host>,
+    pub server_name: String,
+    pub reserved_for: FKey<Aggregate>,
+    pub starts: chrono::DateTime<chrono::Utc>,
+    pub ends: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+impl std::fmt::Display for ScheduleConflict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} is reserved for aggregate {:?} starting {}",
+            self.server_name,
+            self.reserved_for.into_id(),
+            self.starts
+        )
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize, Hash)]
 pub enum AllocationFailure {
     NoneAvailable,
     PermissionsDenied,
     DatabaseFailure,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use models::inventory::{Accelerator, AcceleratorKind};
+
+    #[test]
+    fn test_needed_areas() {
+        let plain = FlavorCapabilities::default();
+        assert_eq!(needed_areas(None, &plain), vec![IssueArea::Management]);
+        assert_eq!(
+            needed_areas(Some(5.0), &plain),
+            vec![IssueArea::Management, IssueArea::Performance]
+        );
+
+        let gpu = FlavorCapabilities {
+            accelerators: vec![Accelerator {
+                kind: AcceleratorKind::Gpu,
+                model: "A100 40GB".to_owned(),
+                count: 2,
+            }],
+            ..Default::default()
+        };
+        let areas = needed_areas(None, &gpu);
+        assert!(areas.contains(&IssueArea::Accelerator));
+        assert!(!areas.contains(&IssueArea::Network));
+    }
+
+    fn flavor(name: &str, cpu_count: usize, ram_gb: u64) -> Flavor {
+        let gb = |value| models::inventory::DataValue {
+            value,
+            unit: models::inventory::DataUnit::GigaBytes,
+        };
+
+        Flavor {
+            id: FKey::new_id_dangling(),
+            arch: models::inventory::Arch::X86_64,
+            name: name.to_owned(),
+            public: true,
+            cpu_count,
+            ram: gb(ram_gb),
+            root_size: gb(100),
+            disk_size: gb(500),
+            swap_size: gb(0),
+            brand: String::new(),
+            model: String::new(),
+            capabilities: FlavorCapabilities::default(),
+        }
+    }
+
+    #[test]
+    fn test_cheapest_first() {
+        let mut flavors = vec![
+            (flavor("uncosted", 8, 32), None),
+            (flavor("big", 64, 512), Some(1.0)),
+            (flavor("pricey", 8, 32), Some(3.0)),
+            (flavor("small", 16, 64), Some(1.0)),
+        ];
+        cheapest_first(&mut flavors);
+
+        let order = flavors.iter().map(|(f, _)| f.name.as_str()).collect_vec();
+        assert_eq!(order, vec!["small", "big", "pricey", "uncosted"]);
+    }
+
+    #[test]
+    fn test_earliest_enough() {
+        let now = chrono::Utc::now();
+        let ends = vec![
+            now + chrono::Duration::days(3),
+            now + chrono::Duration::days(1),
+            now + chrono::Duration::days(2),
+        ];
+
+        assert_eq!(earliest_enough(0, ends.clone()), None);
+        assert_eq!(
+            earliest_enough(1, ends.clone()),
+            Some(now + chrono::Duration::days(1))
+        );
+        assert_eq!(
+            earliest_enough(3, ends.clone()),
+            Some(now + chrono::Duration::days(3))
+        );
+        assert_eq!(earliest_enough(4, ends), None);
+    }
+
+    #[test]
+    fn test_funding_allows() {
+        assert!(funding_allows(None, None));
+        assert!(funding_allows(None, Some("anuket")));
+        assert!(funding_allows(Some("anuket"), Some("anuket")));
+        assert!(!funding_allows(Some("anuket"), Some("onap")));
+        assert!(!funding_allows(Some("anuket"), None));
+    }
+}
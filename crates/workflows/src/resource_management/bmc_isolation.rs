@@ -0,0 +1,398 @@
+//! Makes sure a host's BMC can only be reached from the management network
+//!
+//! A BMC that is cabled into, or shares a NIC with, the dataplane is reachable by whoever
+//! books the hosts on that dataplane. Before a booking's users get IPMI access the BMC is
+//! checked against what the inventory expects of it, and the BMCs of every booked host are
+//! audited again periodically, opening a ticket and alerting the admins about any that aren't
+//! isolated.
+
+use std::collections::{HashMap, HashSet};
+
+use common::prelude::{anyhow, tracing};
+use config::settings;
+use dal::{new_client, AsEasyTransaction, DBTable, EasyTransaction, FKey};
+use models::{
+    dashboard::{Aggregate, LifeCycleState},
+    inventory::{Host, TicketKind},
+};
+use notifications::email::send_to_admins;
+use tascii::prelude::*;
+
+use crate::{
+    deploy_booking::set_host_power_state::HostConfig,
+    resource_management::tickets::report_host_failure,
+};
+
+/// What the BMC says about its own network configuration, from `ipmitool lan print`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LanConfig {
+    pub ip: Option<String>,
+    pub mac: Option<String>,
+    /// None if the BMC doesn't tag its traffic
+    pub vlan: Option<i16>,
+}
+
+/// Where the inventory says the BMC of a host should be
+#[derive(Debug, Clone, Default)]
+pub struct Expectation {
+    pub ipmi_mac: String,
+    /// The IPMI vlans of the switches the host is cabled to
+    pub management_vlans: HashSet<i16>,
+    /// Vlans that the dataplane of any booking can reach, and which booking holds them
+    pub tenant_vlans: HashMap<i16, FKey<Aggregate>>,
+    /// Dataplane ports of the host, by name
+    pub dataplane_macs: Vec<(String, String)>,
+}
+
+/// Lowercase hex digits only, so MACs written either way compare equal
+fn normalize_mac(mac: &str) -> String {
+    mac.chars()
+        .filter(|c| c.is_ascii_hexdigit())
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+pub fn parse_lan_print(output: &str) -> LanConfig {
+    let mut lan = LanConfig::default();
+
+    for line in output.lines() {
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+
+        match key.trim() {
+            "IP Address" => lan.ip = Some(value.to_owned()),
+            "MAC Address" => lan.mac = Some(value.to_owned()),
+            "802.1q VLAN ID" => lan.vlan = value.parse().ok(),
+            _ => {}
+        }
+    }
+
+    lan
+}
+
+/// Everything about how the BMC is connected that could expose it to a booking, empty if it is isolated
+pub fn check(lan: &LanConfig, expected: &Expectation) -> Vec<String> {
+    let mut findings = Vec::new();
+
+    match &lan.mac {
+        Some(mac) if normalize_mac(mac) != normalize_mac(&expected.ipmi_mac) => {
+            findings.push(format!(
+                "the BMC answering has MAC {mac}, but the inventory expects {}, it may be cabled to another host",
+                expected.ipmi_mac
+            ))
+        }
+        Some(_) => {}
+        None => findings.push("the BMC didn't report its MAC".to_owned()),
+    }
+
+    if let Some(mac) = &lan.mac {
+        for (port, port_mac) in expected.dataplane_macs.iter() {
+            if normalize_mac(mac) == normalize_mac(port_mac) {
+                findings.push(format!(
+                    "the BMC shares dataplane port {port}, so it is reachable from booking networks"
+                ));
+            }
+        }
+    }
+
+    if let Some(vlan) = lan.vlan {
+        if !expected.management_vlans.contains(&vlan) {
+            findings.push(format!(
+                "the BMC tags its traffic with vlan {vlan}, which isn't the IPMI vlan of its switches"
+            ));
+        }
+    }
+
+    let mut vlans: Vec<i16> = expected
+        .management_vlans
+        .iter()
+        .chain(lan.vlan.iter())
+        .copied()
+        .collect();
+    vlans.sort();
+    vlans.dedup();
+    for vlan in vlans {
+        if let Some(agg) = expected.tenant_vlans.get(&vlan) {
+            findings.push(format!(
+                "vlan {vlan} that the BMC is on is assigned to a network of aggregate {:?}",
+                agg.into_id()
+            ));
+        }
+    }
+
+    findings
+}
+
+async fn lan_print(config: &HostConfig) -> Result<LanConfig, anyhow::Error> {
+    let output = tokio::process::Command::new("ipmitool")
+        .args([
+            "-I",
+            "lanplus",
+            "-C",
+            "3",
+            "-H",
+            &config.fqdn,
+            "-U",
+            &config.user,
+            "-P",
+            &config.password,
+            "lan",
+            "print",
+        ])
+        .kill_on_drop(true)
+        .output()
+        .await?;
+
+    if !output.status.success() {
+        return Err(anyhow::Error::msg(format!(
+            "ipmitool lan print failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        )));
+    }
+
+    Ok(parse_lan_print(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// Every vlan that is assigned to a network of a booking that still holds its networks
+async fn tenant_vlans(
+    t: &mut EasyTransaction<'_>,
+) -> Result<HashMap<i16, FKey<Aggregate>>, anyhow::Error> {
+    let mut vlans = HashMap::new();
+
+    for agg in Aggregate::select()
+        .where_field("deleted")
+        .equals(false)
+        .run(t)
+        .await?
+    {
+        if agg.state.ended() {
+            continue;
+        }
+
+        let Ok(map) = agg.vlans.get(t).await else {
+            continue;
+        };
+        for vlan in map.networks.values() {
+            vlans.insert(vlan.get(t).await?.vlan_id, agg.id);
+        }
+    }
+
+    Ok(vlans)
+}
+
+async fn expectation(
+    t: &mut EasyTransaction<'_>,
+    host: &Host,
+    tenant_vlans: HashMap<i16, FKey<Aggregate>>,
+) -> Result<Expectation, anyhow::Error> {
+    let mut expected = Expectation {
+        ipmi_mac: host.ipmi_mac.to_hex_string(),
+        tenant_vlans,
+        ..Default::default()
+    };
+
+    for port in host.ports(t).await? {
+        expected
+            .dataplane_macs
+            .push((port.name.clone(), port.mac.to_string()));
+
+        if let Some(switchport) = port.switchport {
+            let switch = switchport.get(t).await?.for_switch.get(t).await?;
+            expected.management_vlans.insert(switch.ipmi_vlan);
+        }
+    }
+
+    Ok(expected)
+}
+
+/// Why the BMC of `host` isn't isolated, empty if it is
+pub async fn findings(
+    t: &mut EasyTransaction<'_>,
+    host: &Host,
+) -> Result<Vec<String>, anyhow::Error> {
+    let tenant_vlans = tenant_vlans(t).await?;
+    let expected = expectation(t, host, tenant_vlans).await?;
+    let lan = lan_print(&HostConfig::try_from(host)?).await?;
+
+    Ok(check(&lan, &expected))
+}
+
+#[derive(Debug, Clone, Hash, Serialize, Deserialize)]
+pub struct VerifyBmcIsolation {
+    pub host: FKey<Host>,
+}
+
+tascii::mark_task!(VerifyBmcIsolation);
+impl AsyncRunnable for VerifyBmcIsolation {
+    /// Why the BMC isn't isolated, empty if it is
+    type Output = Vec<String>;
+
+    async fn run(&mut self, _context: &Context) -> Result<Self::Output, TaskError> {
+        let mut client = new_client().await?;
+        let mut transaction = client.easy_transaction().await?;
+
+        let host = self.host.get(&mut transaction).await?.into_inner();
+        let findings = findings(&mut transaction, &host).await?;
+
+        transaction.commit().await?;
+
+        Ok(findings)
+    }
+
+    fn identifier() -> TaskIdentifier {
+        TaskIdentifier::named("VerifyBmcIsolationTask").versioned(1)
+    }
+}
+
+/// Checks the BMC of every host in an active booking once, returning what was found on each host that isn't isolated
+pub async fn audit_booked_hosts() -> Result<HashMap<FKey<Host>, Vec<String>>, anyhow::Error> {
+    let mut client = new_client().await?;
+    let mut transaction = client.easy_transaction().await?;
+
+    let tenant_vlans = tenant_vlans(&mut transaction).await?;
+    let mut exposed = HashMap::new();
+
+    let aggregates = Aggregate::select()
+        .where_field("lifecycle_state")
+        .equals(LifeCycleState::Active)
+        .run(&mut transaction)
+        .await?;
+
+    for agg in aggregates {
+        for instance in agg.instances(&mut transaction).await? {
+            let Some(host) = instance.linked_host else {
+                continue;
+            };
+            let host = host.get(&mut transaction).await?.into_inner();
+
+            let expected = expectation(&mut transaction, &host, tenant_vlans.clone()).await?;
+            let lan = match lan_print(&HostConfig::try_from(&host)?).await {
+                Ok(lan) => lan,
+                Err(e) => {
+                    tracing::warn!("Couldn't audit the BMC of {}: {e:?}", host.server_name);
+                    continue;
+                }
+            };
+
+            let findings = check(&lan, &expected);
+            if !findings.is_empty() {
+                exposed.insert(host.id, findings);
+            }
+        }
+    }
+
+    transaction.commit().await?;
+
+    Ok(exposed)
+}
+
+/// Audits BMCs forever, unless the audit has been disabled
+pub async fn entry() {
+    let config = settings().bmc_audit.clone();
+    if !config.enabled {
+        tracing::info!("BMC isolation audit is disabled");
+        return;
+    }
+
+    let mut interval =
+        tokio::time::interval(std::time::Duration::from_secs(config.interval_minutes * 60));
+    // admins are only told again when what is wrong with a host changes
+    let mut alerted: HashMap<FKey<Host>, Vec<String>> = HashMap::new();
+
+    loop {
+        interval.tick().await;
+
+        let exposed = match audit_booked_hosts().await {
+            Ok(exposed) => exposed,
+            Err(e) => {
+                tracing::error!("Couldn't audit BMC isolation: {e:?}");
+                continue;
+            }
+        };
+
+        for (host, findings) in exposed.iter() {
+            if alerted.get(host) == Some(findings) {
+                continue;
+            }
+
+            tracing::warn!("BMC of host {host:?} isn't isolated: {findings:?}");
+            report_host_failure(*host, TicketKind::BmcExposed, findings.join("\n")).await;
+            send_to_admins(format!(
+                "The BMC of host {:?} may be reachable by bookings: {}",
+                host.into_id(),
+                findings.join("; ")
+            ))
+            .await;
+        }
+
+        alerted = exposed;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_lan_print() {
+        let output = "\
+Set in Progress         : Set Complete
+IP Address Source       : DHCP Address
+IP Address              : 10.200.1.17
+Subnet Mask             : 255.255.0.0
+MAC Address             : 3c:ec:ef:4a:10:02
+802.1q VLAN ID          : Disabled
+";
+        assert_eq!(
+            parse_lan_print(output),
+            LanConfig {
+                ip: Some("10.200.1.17".to_owned()),
+                mac: Some("3c:ec:ef:4a:10:02".to_owned()),
+                vlan: None,
+            }
+        );
+
+        let tagged = parse_lan_print("802.1q VLAN ID          : 3000\n");
+        assert_eq!(tagged.vlan, Some(3000));
+    }
+
+    #[test]
+    fn test_check() {
+        let agg = FKey::new_id_dangling();
+        let expected = Expectation {
+            ipmi_mac: "3C-EC-EF-4A-10-02".to_owned(),
+            management_vlans: [3000].into_iter().collect(),
+            tenant_vlans: [(101, agg)].into_iter().collect(),
+            dataplane_macs: vec![("eno1".to_owned(), "3c:ec:ef:4a:10:00".to_owned())],
+        };
+
+        let isolated = LanConfig {
+            ip: None,
+            mac: Some("3c:ec:ef:4a:10:02".to_owned()),
+            vlan: None,
+        };
+        assert!(check(&isolated, &expected).is_empty());
+
+        let tagged = LanConfig {
+            vlan: Some(3000),
+            ..isolated.clone()
+        };
+        assert!(check(&tagged, &expected).is_empty());
+
+        // a shared LOM answers with the MAC of a dataplane port, on a tenant's vlan
+        let shared = LanConfig {
+            ip: None,
+            mac: Some("3c:ec:ef:4a:10:00".to_owned()),
+            vlan: Some(101),
+        };
+        assert_eq!(check(&shared, &expected).len(), 4);
+
+        let handed_out = Expectation {
+            tenant_vlans: [(3000, agg)].into_iter().collect(),
+            ..expected
+        };
+        assert_eq!(check(&isolated, &handed_out).len(), 1);
+    }
+}
@@ -0,0 +1,255 @@
+//! Telling the admins when a flavor is running out of hosts, before users start being turned
+//! away
+//!
+//! Every run looks at how many hosts of each flavor are free over the next `horizon_days`,
+//! after the reservations of scheduled bookings. A flavor is alerted on if fewer of its hosts
+//! are free for the rest of the day than its `min_free`, or if the hosts queued bookings are
+//! waiting for won't fit in what is free on some day ahead. Each alert goes out at most once
+//! a day per flavor, counted from when LibLaaS started.
+
+use std::collections::HashMap;
+
+use common::prelude::{
+    anyhow,
+    chrono::{Duration, NaiveDate, Utc},
+    tokio, tracing,
+};
+use config::{settings, CapacityAlertConfig};
+use dal::{new_client, AsEasyTransaction, DBTable, EasyTransaction, FKey};
+use models::{
+    dashboard::{Aggregate, LifeCycleState},
+    inventory::Flavor,
+};
+use notifications::email::send_to_admins;
+
+use super::schedule::{self, FlavorAvailability};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AlertKind {
+    /// Fewer hosts are free than the flavor should have
+    LowFree,
+    /// Queued bookings need more hosts than will be free
+    Shortfall,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CapacityAlert {
+    pub flavor: FKey<Flavor>,
+    pub kind: AlertKind,
+    pub message: String,
+}
+
+/// The alerts already sent, and on which day
+#[derive(Debug, Clone, Default)]
+pub struct SentAlerts(HashMap<(FKey<Flavor>, AlertKind), NaiveDate>);
+
+impl SentAlerts {
+    /// Whether `alert` hasn't been sent yet on `today`, marking it sent if so
+    pub fn first_today(&mut self, alert: &CapacityAlert, today: NaiveDate) -> bool {
+        match self.0.insert((alert.flavor, alert.kind), today) {
+            Some(sent) => sent != today,
+            None => true,
+        }
+    }
+}
+
+/// What is wrong with the capacity of one flavor, given its `availability` over the horizon,
+/// how many of its hosts queued bookings are waiting for, and the fewest it should have free
+pub fn alerts_for(
+    availability: &FlavorAvailability,
+    queued: usize,
+    min_free: Option<usize>,
+) -> Vec<CapacityAlert> {
+    let mut alerts = Vec::new();
+    // a flavor nothing can be booked on has nothing to run out of
+    if availability.hosts == 0 {
+        return alerts;
+    }
+
+    if let (Some(min_free), Some(today)) = (min_free, availability.days.first()) {
+        if today.free < min_free {
+            alerts.push(CapacityAlert {
+                flavor: availability.flavor,
+                kind: AlertKind::LowFree,
+                message: format!(
+                    "Only {} of the {} hosts of {} are free for the rest of the day, fewer than the {min_free} it should have",
+                    today.free, availability.hosts, availability.name
+                ),
+            });
+        }
+    }
+
+    if queued > 0 {
+        if let Some(day) = availability.days.iter().find(|d| d.free < queued) {
+            alerts.push(CapacityAlert {
+                flavor: availability.flavor,
+                kind: AlertKind::Shortfall,
+                message: format!(
+                    "Queued bookings are waiting for {queued} hosts of {}, but only {} will be free on {}",
+                    availability.name, day.free, day.date
+                ),
+            });
+        }
+    }
+
+    alerts
+}
+
+/// How many hosts of each flavor queued bookings are waiting for
+async fn queued_demand(
+    t: &mut EasyTransaction<'_>,
+) -> Result<HashMap<FKey<Flavor>, usize>, anyhow::Error> {
+    let queued = Aggregate::select()
+        .where_field("lifecycle_state")
+        .equals(LifeCycleState::Queued)
+        .run(t)
+        .await?;
+
+    let mut demand = HashMap::new();
+    for agg in queued {
+        for instance in agg.instances(t).await? {
+            *demand.entry(instance.config.flavor).or_default() += 1;
+        }
+    }
+
+    Ok(demand)
+}
+
+/// Tells the admins about every flavor that is running out of hosts, unless they were
+/// already told today
+pub async fn check_capacity(
+    config: &CapacityAlertConfig,
+    sent: &mut SentAlerts,
+) -> Result<(), anyhow::Error> {
+    let mut client = new_client().await?;
+    let mut transaction = client.easy_transaction().await?;
+
+    let now = Utc::now();
+    let schedule = schedule::schedule(
+        &mut transaction,
+        None,
+        now,
+        now + Duration::days(config.horizon_days.max(1)),
+    )
+    .await?;
+    let demand = queued_demand(&mut transaction).await?;
+
+    transaction.commit().await?;
+
+    let today = now.date_naive();
+    let alerts = schedule::availability(&schedule)
+        .iter()
+        .flat_map(|flavor| {
+            let min_free = config
+                .min_free
+                .get(&flavor.name)
+                .copied()
+                .or(config.default_min_free);
+            alerts_for(
+                flavor,
+                demand.get(&flavor.flavor).copied().unwrap_or(0),
+                min_free,
+            )
+        })
+        .filter(|alert| sent.first_today(alert, today))
+        .map(|alert| alert.message)
+        .collect::<Vec<_>>();
+
+    if !alerts.is_empty() {
+        tracing::warn!("{} flavors are running out of hosts", alerts.len());
+        send_to_admins(format!(
+            "Lab capacity is running low:<br><br>{}",
+            alerts.join("<br>")
+        ))
+        .await;
+    }
+
+    Ok(())
+}
+
+/// Watches the capacity of every flavor forever, unless capacity alerts are disabled
+pub async fn entry() {
+    let config = settings().capacity_alerts.clone();
+    if !config.enabled {
+        tracing::info!("Capacity alerts are disabled");
+        return;
+    }
+
+    let mut sent = SentAlerts::default();
+    let mut interval =
+        tokio::time::interval(std::time::Duration::from_secs(config.interval_minutes * 60));
+
+    loop {
+        interval.tick().await;
+
+        if let Err(e) = check_capacity(&config, &mut sent).await {
+            tracing::error!("Couldn't check lab capacity: {e:?}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resource_management::schedule::DayAvailability;
+
+    fn availability(hosts: usize, free: &[usize]) -> FlavorAvailability {
+        FlavorAvailability {
+            flavor: FKey::new_id_dangling(),
+            name: "flavor".to_owned(),
+            hosts,
+            days: free
+                .iter()
+                .enumerate()
+                .map(|(i, free)| DayAvailability {
+                    date: format!("2025-04-0{}", i + 1),
+                    free: *free,
+                })
+                .collect(),
+        }
+    }
+
+    fn kinds(alerts: Vec<CapacityAlert>) -> Vec<AlertKind> {
+        alerts.into_iter().map(|a| a.kind).collect()
+    }
+
+    #[test]
+    fn test_alerts_for() {
+        let flavor = availability(4, &[1, 3, 0]);
+
+        assert!(alerts_for(&flavor, 0, None).is_empty());
+        assert!(alerts_for(&flavor, 0, Some(1)).is_empty());
+        assert_eq!(kinds(alerts_for(&flavor, 0, Some(2))), [AlertKind::LowFree]);
+
+        // one queued host fits today, but not on the third day
+        let shortfall = alerts_for(&flavor, 1, None);
+        assert_eq!(kinds(shortfall.clone()), [AlertKind::Shortfall]);
+        assert!(shortfall[0].message.contains("2025-04-03"));
+        assert!(alerts_for(&availability(4, &[1, 3]), 1, None).is_empty());
+
+        assert_eq!(
+            kinds(alerts_for(&flavor, 2, Some(2))),
+            [AlertKind::LowFree, AlertKind::Shortfall]
+        );
+
+        // a flavor with no hosts at all isn't running out
+        assert!(alerts_for(&availability(0, &[0, 0]), 3, Some(1)).is_empty());
+    }
+
+    #[test]
+    fn test_sent_alerts() {
+        let day = |d| NaiveDate::from_ymd_opt(2025, 4, d).unwrap();
+        let low = alerts_for(&availability(2, &[0]), 0, Some(1)).remove(0);
+        let short = CapacityAlert {
+            kind: AlertKind::Shortfall,
+            ..low.clone()
+        };
+
+        let mut sent = SentAlerts::default();
+        assert!(sent.first_today(&low, day(1)));
+        assert!(!sent.first_today(&low, day(1)));
+        assert!(sent.first_today(&short, day(1)));
+        assert!(sent.first_today(&low, day(2)));
+        assert!(!sent.first_today(&low, day(2)));
+    }
+}
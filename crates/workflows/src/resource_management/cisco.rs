@@ -1,15 +1,18 @@
 use std::{collections::HashMap, sync::Arc};
 
-use common::prelude::{dashmap::DashMap, lazy_static, parking_lot, tracing};
+use common::prelude::{anyhow, dashmap::DashMap, lazy_static, parking_lot, serde_json, tracing};
 use lazy_static::lazy_static;
 
 use super::network::NetworkConfig;
+use crate::utils::resilience::{self, Device};
 use dal::{new_client, AsEasyTransaction};
+use models::inventory::Switch;
 
 #[derive(Clone)]
 pub struct NXCommand {
     inputs: Vec<String>,
 
+    address: String,
     url: String,
 
     user: String,
@@ -28,6 +31,7 @@ impl std::fmt::Debug for NXCommand {
 }
 
 pub struct NXCommandWithoutAuth {
+    address: String,
     url: String,
 }
 
@@ -35,6 +39,7 @@ impl NXCommandWithoutAuth {
     pub fn with_credentials(self, username: String, password: String) -> NXCommand {
         NXCommand {
             inputs: vec![],
+            address: self.address,
             url: self.url,
             user: username,
             password,
@@ -46,6 +51,7 @@ impl NXCommand {
     pub fn for_switch(dn: String) -> NXCommandWithoutAuth {
         NXCommandWithoutAuth {
             url: format!("http://{dn}/ins"),
+            address: dn,
         }
     }
 
@@ -91,11 +97,14 @@ impl NXCommand {
         #[allow(deprecated)]
         let basic_auth_header = format!("Basic {}", base64::encode(basic_auth_header_v.as_bytes()));
 
-        let resp = ureq::post(&self.url)
-            .set("Authorization", &basic_auth_header)
-            .set("content-type", "text/json")
-            .send_json(j)
-            .expect("couldn't send request to switch");
+        let resp = resilience::call_blocking(Device::switch(&self.address), || {
+            ureq::post(&self.url)
+                .set("Authorization", &basic_auth_header)
+                .set("content-type", "text/json")
+                .send_json(j.clone())
+                .map_err(anyhow::Error::from)
+        })
+        .expect("couldn't send request to switch");
 
         tracing::info!("Releases exclusive access to switch {}", self.url);
         std::mem::drop(g);
@@ -108,6 +117,57 @@ impl NXCommand {
     }
 }
 
+impl NXCommand {
+    /// Runs the commands as `show` commands, returning the body of the switch's reply
+    pub fn show(self) -> Result<serde_json::Value, anyhow::Error> {
+        let lock = SWITCH_LOCK
+            .entry(self.url.clone())
+            .or_insert_with(|| Arc::new(parking_lot::Mutex::new(())))
+            .value()
+            .clone();
+        let g = lock.lock();
+
+        let j = ureq::json!({
+            "ins_api": {
+                "version": "1.0",
+                "type": "cli_show",
+                "chunk": "0",
+                "sid": "1",
+                "output_format": "json",
+                "input": self.inputs.join(" ;"),
+            }
+        });
+
+        #[allow(deprecated)]
+        let basic_auth_header = format!(
+            "Basic {}",
+            base64::encode(format!("{}:{}", self.user, self.password).as_bytes())
+        );
+
+        let resp: serde_json::Value =
+            resilience::call_blocking(Device::switch(&self.address), || {
+                ureq::post(&self.url)
+                    .set("Authorization", &basic_auth_header)
+                    .set("content-type", "text/json")
+                    .send_json(j.clone())
+                    .map_err(anyhow::Error::from)
+            })?
+            .into_json()?;
+
+        std::mem::drop(g);
+
+        let output = &resp["ins_api"]["outputs"]["output"];
+        if output["code"] != "200" {
+            return Err(anyhow::Error::msg(format!(
+                "switch {} refused show command: {}",
+                self.url, output["msg"]
+            )));
+        }
+
+        Ok(output["body"].clone())
+    }
+}
+
 lazy_static! {
     static ref SWITCH_LOCK: DashMap<String, Arc<common::prelude::parking_lot::Mutex<()>>> =
         DashMap::new();
@@ -238,3 +298,38 @@ pub async fn nx_run_network_task(nc: NetworkConfig) {
 
     transaction.commit().await.unwrap();
 }
+
+/// Mirrors all traffic on `source` out of `destination` as monitor session `session`
+pub fn nx_start_port_mirror(switch: Switch, session: i16, source: &str, destination: &str) {
+    NXCommand::for_switch(switch.ip)
+        .with_credentials(switch.user, switch.pass)
+        .and_then(format!("interface {destination}"))
+        .and_then("switchport monitor")
+        .and_then(format!("monitor session {session}"))
+        .and_then(format!("source interface {source} both"))
+        .and_then(format!("destination interface {destination}"))
+        .and_then("no shut")
+        .execute();
+}
+
+pub fn nx_stop_port_mirror(switch: Switch, session: i16, destination: &str) {
+    NXCommand::for_switch(switch.ip)
+        .with_credentials(switch.user, switch.pass)
+        .and_then(format!("no monitor session {session}"))
+        .and_then(format!("interface {destination}"))
+        .and_then("no switchport monitor")
+        .execute();
+}
+
+/// Adds (or removes) `vlan` as a tagged vlan on the trunk of `port`, leaving its other vlans alone
+pub fn nx_trunk_vlan(switch: Switch, port: &str, vlan: i16, add: bool) {
+    let op = if add { "add" } else { "remove" };
+
+    NXCommand::for_switch(switch.ip)
+        .with_credentials(switch.user, switch.pass)
+        .and_then(format!("interface {port}"))
+        .and_then("switchport mode trunk")
+        .and_then(format!("switchport trunk allowed vlan {op} {vlan}"))
+        .and_then("copy run start")
+        .execute();
+}
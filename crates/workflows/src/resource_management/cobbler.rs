@@ -7,7 +7,9 @@ use pyo3::{prelude::*, types::PyAny};
 use serde::{Deserialize, Serialize};
 use serde_json;
 
-use crate::{resource_management::mailbox::Endpoint, utils::python::*};
+use crate::{
+    deploy_booking::snapshot::image_url, resource_management::mailbox::Endpoint, utils::python::*,
+};
 use common::prelude::{
     rand::{self, seq::SliceRandom, Rng},
     tracing,
@@ -53,21 +55,90 @@ impl CobblerConfig {
         let msg_url = format!("{}/push", mailbox_endpoint.to_url());
         let preimage_url = format!("{}/push", preimage_endpoint.to_url());
 
-        let kargs: Vec<(String, String)> = vec![
+        let mut kargs: Vec<(String, String)> = vec![
             ("post-install-cinit".to_owned(), ci_url),
             ("provision_id".to_owned(), ID::new().to_string()),
             ("inbox_target".to_owned(), msg_url),
             ("pre_image_target".to_owned(), preimage_url),
         ];
+        kargs.extend(instance.config.kernel_args.iter().cloned());
 
         transaction.commit().await.unwrap();
 
+        // an instance restored from a snapshot gets its captured disk written back
+        // instead of a fresh install of its image
+        let restore_from = instance
+            .metadata
+            .get(dashboard::booking_snapshot::RESTORE_FROM)
+            .and_then(|v| v.as_str());
+        if let (Some(disk_image), Some(snapshots)) = (restore_from, &config::settings().snapshots) {
+            kargs.push((
+                "restore_source".to_owned(),
+                image_url(&snapshots.store_url, disk_image),
+            ));
+
+            return CobblerConfig {
+                kernel_args: kargs,
+                image: snapshots.restore_profile.clone(),
+            };
+        }
+
         CobblerConfig {
             kernel_args: kargs,
             image: image.cobbler_name,
         }
     }
 
+    /// Boots the capture profile, which copies the disk of the host to `disk_image` in
+    /// the image store and then posts how it went to `inbox`
+    pub fn new_capture_config(
+        snapshots: &config::SnapshotConfig,
+        disk_image: &str,
+        inbox: Endpoint,
+    ) -> CobblerConfig {
+        let kargs: Vec<(String, String)> = vec![
+            ("provision_id".to_owned(), ID::new().to_string()),
+            (
+                "capture_target".to_owned(),
+                image_url(&snapshots.store_url, disk_image),
+            ),
+            (
+                "inbox_target".to_owned(),
+                format!("{}/push", inbox.to_url()),
+            ),
+        ];
+
+        CobblerConfig {
+            kernel_args: kargs,
+            image: snapshots.capture_profile.clone(),
+        }
+    }
+
+    /// Boots the verification ramdisk, which looks the host over after its booking was
+    /// cleaned up and posts a [`VerificationReport`](models::inventory::VerificationReport)
+    /// to `inbox`
+    pub fn new_verification_config(
+        verification: &config::VerificationConfig,
+        inbox: Endpoint,
+    ) -> CobblerConfig {
+        let kargs: Vec<(String, String)> = vec![
+            ("provision_id".to_owned(), ID::new().to_string()),
+            (
+                "bmc_baseline".to_owned(),
+                verification.bmc_baseline_url.clone(),
+            ),
+            (
+                "inbox_target".to_owned(),
+                format!("{}/push", inbox.to_url()),
+            ),
+        ];
+
+        CobblerConfig {
+            kernel_args: kargs,
+            image: verification.profile.clone(),
+        }
+    }
+
     pub async fn new_eve_config(
         instance: dashboard::Instance,
         _host: FKey<inventory::Host>,
@@ -0,0 +1,357 @@
+//! Composing hosts on disaggregated PCIe or CXL fabrics, where GPUs, NVMe drives and
+//! memory sit in pooled chassis and are attached to compute sleds as they are needed
+//!
+//! A flavor lists the devices its hosts get composed with in its capabilities. Deploying
+//! a host of such a flavor attaches free devices from the fabric the host is on before the
+//! host is powered on, and cleaning it up detaches them once it is powered off. What was
+//! attached is kept in the instance metadata, where cleanup and booking status pick it up.
+
+use common::prelude::{
+    anyhow,
+    chrono::{DateTime, Utc},
+    lazy_static::lazy_static,
+    serde_json,
+    tokio::sync::Mutex,
+    tracing,
+};
+use config::{settings, FabricConfig, FabricDriver};
+use dal::{new_client, AsEasyTransaction, FKey};
+use models::{
+    dashboard::Instance,
+    inventory::{ComposableKind, ComposedDevices, Host},
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+pub mod redfish;
+
+pub use redfish::RedfishFabric;
+
+/// Key in the instance metadata holding the composition of its host
+const COMPOSITION_KEY: &str = "composition";
+
+lazy_static! {
+    /// Held from listing free devices until they're attached, so that two deploys can't
+    /// both pick the same device
+    static ref COMPOSING: Mutex<()> = Mutex::new(());
+}
+
+#[allow(async_fn_in_trait)]
+pub trait Fabric {
+    /// The devices on the fabric that aren't attached to any machine
+    async fn free_devices(&self) -> Result<Vec<FabricDevice>, anyhow::Error>;
+
+    /// Attaches the devices to `machine`, on top of whatever it already has attached
+    async fn attach(&self, machine: &str, devices: &[FabricDevice]) -> Result<(), anyhow::Error>;
+
+    /// Detaches the devices from `machine`, leaving whatever else it has attached
+    async fn detach(&self, machine: &str, devices: &[FabricDevice]) -> Result<(), anyhow::Error>;
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, PartialEq, Eq)]
+pub struct FabricDevice {
+    /// What the fabric manager knows the device by
+    pub id: String,
+    pub kind: ComposableKind,
+    pub model: String,
+}
+
+/// What the host of an instance was composed with
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, PartialEq)]
+pub struct Composition {
+    pub fabric: String,
+    /// What the fabric manager knows the host as
+    pub machine: String,
+    pub host: FKey<Host>,
+    pub devices: Vec<FabricDevice>,
+    #[schemars(with = "String")]
+    pub composed: DateTime<Utc>,
+    /// None while the devices are still attached
+    #[schemars(with = "Option<String>")]
+    pub decomposed: Option<DateTime<Utc>>,
+}
+
+/// The fabric manager of a fabric, over whichever driver it is configured with
+pub enum FabricManager<'a> {
+    Redfish(RedfishFabric<'a>),
+}
+
+impl<'a> FabricManager<'a> {
+    pub fn of(config: &'a FabricConfig) -> Self {
+        match config.driver {
+            FabricDriver::Redfish => Self::Redfish(RedfishFabric(config)),
+        }
+    }
+}
+
+impl Fabric for FabricManager<'_> {
+    async fn free_devices(&self) -> Result<Vec<FabricDevice>, anyhow::Error> {
+        match self {
+            Self::Redfish(f) => f.free_devices().await,
+        }
+    }
+
+    async fn attach(&self, machine: &str, devices: &[FabricDevice]) -> Result<(), anyhow::Error> {
+        match self {
+            Self::Redfish(f) => f.attach(machine, devices).await,
+        }
+    }
+
+    async fn detach(&self, machine: &str, devices: &[FabricDevice]) -> Result<(), anyhow::Error> {
+        match self {
+            Self::Redfish(f) => f.detach(machine, devices).await,
+        }
+    }
+}
+
+/// The name and config of the fabric `server_name` is on, and what the fabric knows it as
+pub fn fabric_of(server_name: &str) -> Option<(&'static str, &'static FabricConfig, &'static str)> {
+    settings().fabrics.iter().find_map(|(name, fabric)| {
+        fabric
+            .machines
+            .get(server_name)
+            .map(|machine| (name.as_str(), fabric, machine.as_str()))
+    })
+}
+
+/// The devices out of `free` that make up `wanted`, or why they can't
+pub fn pick(
+    free: &[FabricDevice],
+    wanted: &[ComposedDevices],
+) -> Result<Vec<FabricDevice>, String> {
+    let mut picked: Vec<FabricDevice> = Vec::new();
+
+    for want in wanted {
+        let found: Vec<FabricDevice> = free
+            .iter()
+            .filter(|d| {
+                d.kind == want.kind
+                    && want
+                        .model
+                        .as_ref()
+                        .map_or(true, |m| d.model.eq_ignore_ascii_case(m))
+                    && !picked.contains(d)
+            })
+            .take(want.count as usize)
+            .cloned()
+            .collect();
+        if found.len() < want.count as usize {
+            let model = want
+                .model
+                .as_ref()
+                .map(|m| format!(" {m}"))
+                .unwrap_or_default();
+            return Err(format!(
+                "{} {:?}{model} wanted, but only {} free",
+                want.count,
+                want.kind,
+                found.len()
+            ));
+        }
+
+        picked.extend(found);
+    }
+
+    Ok(picked)
+}
+
+/// The composition kept for `instance`, if its host was ever composed
+pub fn recorded(instance: &Instance) -> Option<Composition> {
+    instance
+        .metadata
+        .get(COMPOSITION_KEY)
+        .and_then(|v| serde_json::from_value(v.clone()).ok())
+}
+
+async fn save(instance: FKey<Instance>, composition: &Composition) -> Result<(), anyhow::Error> {
+    let mut client = new_client().await?;
+    let mut transaction = client.easy_transaction().await?;
+
+    let mut instance = instance.get(&mut transaction).await?;
+    instance.metadata.insert(
+        COMPOSITION_KEY.to_owned(),
+        serde_json::to_value(composition)?,
+    );
+    instance.update(&mut transaction).await?;
+
+    transaction.commit().await?;
+
+    Ok(())
+}
+
+/// Attaches the devices the flavor of `instance` is composed with to `host`
+///
+/// Does nothing for flavors that aren't composed, or if `host` was already composed for
+/// the instance, like when a deploy is retried. Devices attached to another host for the
+/// instance, one that was since replaced, are detached first.
+pub async fn compose(
+    instance: FKey<Instance>,
+    host: FKey<Host>,
+) -> Result<Option<Composition>, anyhow::Error> {
+    let mut client = new_client().await?;
+    let mut transaction = client.easy_transaction().await?;
+
+    let inst = instance.get(&mut transaction).await?.into_inner();
+    let wanted = inst
+        .config
+        .flavor
+        .get(&mut transaction)
+        .await?
+        .capabilities
+        .composed
+        .clone();
+    let server_name = host.get(&mut transaction).await?.server_name.clone();
+
+    transaction.commit().await?;
+
+    if let Some(existing) = recorded(&inst).filter(|c| c.decomposed.is_none()) {
+        if existing.host == host {
+            return Ok(Some(existing));
+        }
+
+        decompose(instance).await?;
+    }
+
+    if wanted.iter().all(|w| w.count == 0) {
+        return Ok(None);
+    }
+
+    let (fabric, config, machine) = fabric_of(&server_name).ok_or(anyhow::Error::msg(format!(
+        "{server_name} is of a composed flavor, but isn't on any fabric"
+    )))?;
+    let manager = FabricManager::of(config);
+
+    let _composing = COMPOSING.lock().await;
+
+    let free = manager.free_devices().await?;
+    let devices = pick(&free, &wanted).map_err(|e| {
+        anyhow::Error::msg(format!("Can't compose {server_name} from {fabric}: {e}"))
+    })?;
+
+    let mut composition = Composition {
+        fabric: fabric.to_owned(),
+        machine: machine.to_owned(),
+        host,
+        devices,
+        composed: Utc::now(),
+        decomposed: None,
+    };
+
+    // recorded before attaching, so that devices are never attached without the instance
+    // knowing to detach them again
+    save(instance, &composition).await?;
+
+    tracing::info!(
+        "Composing {server_name} on {fabric} with {:?}",
+        composition.devices
+    );
+    if let Err(e) = manager.attach(machine, &composition.devices).await {
+        // some of them may have been attached before it failed
+        if let Err(d) = manager.detach(machine, &composition.devices).await {
+            tracing::warn!(
+                "Couldn't detach what was attached to {server_name}, leaving it for cleanup: {d:?}"
+            );
+            return Err(e);
+        }
+
+        composition.decomposed = Some(Utc::now());
+        save(instance, &composition).await?;
+
+        return Err(e);
+    }
+
+    Ok(Some(composition))
+}
+
+/// Detaches whatever was attached to the host of `instance`, giving what was detached
+pub async fn decompose(instance: FKey<Instance>) -> Result<Option<Composition>, anyhow::Error> {
+    let mut client = new_client().await?;
+    let mut transaction = client.easy_transaction().await?;
+    let inst = instance.get(&mut transaction).await?.into_inner();
+    transaction.commit().await?;
+
+    let Some(mut composition) = recorded(&inst).filter(|c| c.decomposed.is_none()) else {
+        return Ok(None);
+    };
+
+    let config = settings()
+        .fabrics
+        .get(&composition.fabric)
+        .ok_or(anyhow::Error::msg(format!(
+            "Fabric {} is no longer configured, so {} can't be decomposed",
+            composition.fabric, composition.machine
+        )))?;
+
+    tracing::info!(
+        "Decomposing {} on {}, detaching {:?}",
+        composition.machine,
+        composition.fabric,
+        composition.devices
+    );
+    FabricManager::of(config)
+        .detach(&composition.machine, &composition.devices)
+        .await?;
+
+    composition.decomposed = Some(Utc::now());
+    save(instance, &composition).await?;
+
+    Ok(Some(composition))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn device(id: &str, kind: ComposableKind, model: &str) -> FabricDevice {
+        FabricDevice {
+            id: id.to_owned(),
+            kind,
+            model: model.to_owned(),
+        }
+    }
+
+    #[test]
+    fn test_pick() {
+        let free = vec![
+            device("gpu1", ComposableKind::Gpu, "A100 40GB"),
+            device("gpu2", ComposableKind::Gpu, "L40S"),
+            device("gpu3", ComposableKind::Gpu, "A100 40GB"),
+            device("nvme1", ComposableKind::Nvme, "PM1733"),
+        ];
+        let want = |kind, model: Option<&str>, count| ComposedDevices {
+            kind,
+            model: model.map(str::to_owned),
+            count,
+        };
+
+        let picked = pick(
+            &free,
+            &[
+                want(ComposableKind::Gpu, Some("a100 40gb"), 2),
+                want(ComposableKind::Nvme, None, 1),
+            ],
+        )
+        .unwrap();
+        let ids: Vec<_> = picked.iter().map(|d| d.id.as_str()).collect();
+        assert_eq!(ids, vec!["gpu1", "gpu3", "nvme1"]);
+
+        // a device is never picked twice, even when two of the wants match it
+        let picked = pick(
+            &free,
+            &[
+                want(ComposableKind::Gpu, Some("L40S"), 1),
+                want(ComposableKind::Gpu, None, 2),
+            ],
+        )
+        .unwrap();
+        let ids: Vec<_> = picked.iter().map(|d| d.id.as_str()).collect();
+        assert_eq!(ids, vec!["gpu2", "gpu1", "gpu3"]);
+
+        let short = pick(&free, &[want(ComposableKind::Gpu, Some("A100 40GB"), 3)]);
+        assert_eq!(
+            short,
+            Err("3 Gpu A100 40GB wanted, but only 2 free".to_owned())
+        );
+        assert!(pick(&free, &[want(ComposableKind::Memory, None, 1)]).is_err());
+    }
+}
@@ -0,0 +1,251 @@
+//! Fabric managers serving the Redfish composition service
+//!
+//! The devices of the fabric are the resource blocks of `/redfish/v1/CompositionService`,
+//! and a machine is composed by linking resource blocks to its system. Liqid and GigaIO
+//! both put each device in a block of its own, so a block is taken to be the one device
+//! it holds.
+
+use common::prelude::{
+    anyhow,
+    reqwest::{Client, Method},
+    serde_json::{self, json, Value},
+};
+use config::FabricConfig;
+use models::inventory::ComposableKind;
+use std::time::Duration;
+
+use super::{Fabric, FabricDevice};
+
+const RESOURCE_BLOCKS: &str = "/redfish/v1/CompositionService/ResourceBlocks";
+
+pub struct RedfishFabric<'a>(pub &'a FabricConfig);
+
+impl RedfishFabric<'_> {
+    async fn request(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<&Value>,
+    ) -> Result<Value, anyhow::Error> {
+        let config = self.0;
+        let url = format!("https://{}{path}", config.address);
+
+        // fabric managers come with self-signed certificates, like BMCs
+        let client = Client::builder()
+            .danger_accept_invalid_certs(true)
+            .timeout(Duration::from_secs(60))
+            .build()?;
+
+        let mut request = client
+            .request(method.clone(), &url)
+            .basic_auth(&config.username, Some(&config.password));
+        if let Some(body) = body {
+            request = request.json(body);
+        }
+
+        let response = request.send().await?;
+        let status = response.status();
+        let text = response.text().await?;
+        if !status.is_success() {
+            return Err(anyhow::Error::msg(format!(
+                "{method} {url} returned {status}: {text}"
+            )));
+        }
+
+        Ok(serde_json::from_str(&text).unwrap_or(Value::Null))
+    }
+
+    async fn get(&self, path: &str) -> Result<Value, anyhow::Error> {
+        self.request(Method::GET, path, None).await
+    }
+
+    /// Links `machine` to exactly `blocks`
+    async fn link(&self, machine: &str, blocks: Vec<String>) -> Result<(), anyhow::Error> {
+        self.request(Method::PATCH, machine, Some(&blocks_patch(&blocks)))
+            .await?;
+
+        Ok(())
+    }
+}
+
+impl Fabric for RedfishFabric<'_> {
+    async fn free_devices(&self) -> Result<Vec<FabricDevice>, anyhow::Error> {
+        let mut devices = Vec::new();
+
+        for path in members(&self.get(RESOURCE_BLOCKS).await?) {
+            let block = self.get(&path).await?;
+            if !is_free(&block) {
+                continue;
+            }
+
+            for (collection, device) in device_links(&block) {
+                if let Some(device) = device_of(&path, collection, &self.get(&device).await?) {
+                    devices.push(device);
+                    break;
+                }
+            }
+        }
+
+        Ok(devices)
+    }
+
+    async fn attach(&self, machine: &str, devices: &[FabricDevice]) -> Result<(), anyhow::Error> {
+        let mut blocks = linked_blocks(&self.get(machine).await?);
+        for device in devices {
+            if !blocks.contains(&device.id) {
+                blocks.push(device.id.clone());
+            }
+        }
+
+        self.link(machine, blocks).await
+    }
+
+    async fn detach(&self, machine: &str, devices: &[FabricDevice]) -> Result<(), anyhow::Error> {
+        let mut blocks = linked_blocks(&self.get(machine).await?);
+        blocks.retain(|b| !devices.iter().any(|d| &d.id == b));
+
+        self.link(machine, blocks).await
+    }
+}
+
+fn ids(list: Option<&Value>) -> Vec<String> {
+    list.and_then(Value::as_array)
+        .map(|l| {
+            l.iter()
+                .filter_map(|m| m.get("@odata.id").and_then(Value::as_str))
+                .map(str::to_owned)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn members(collection: &Value) -> Vec<String> {
+    ids(collection.get("Members"))
+}
+
+/// Whether the block is neither composed into a machine nor held back by the fabric
+pub fn is_free(block: &Value) -> bool {
+    let status = block.get("CompositionStatus");
+    let state = status
+        .and_then(|s| s.get("CompositionState"))
+        .and_then(Value::as_str);
+    let reserved = status
+        .and_then(|s| s.get("Reserved"))
+        .and_then(Value::as_bool)
+        .unwrap_or(false);
+
+    state == Some("Unused") && !reserved
+}
+
+/// The paths of the devices in a block, with the collection each is listed under
+fn device_links(block: &Value) -> Vec<(&'static str, String)> {
+    ["Processors", "Drives", "Memory"]
+        .into_iter()
+        .flat_map(|collection| {
+            ids(block.get(collection))
+                .into_iter()
+                .map(move |d| (collection, d))
+        })
+        .collect()
+}
+
+/// The device of the block at `block_path`, from one of its devices listed under
+/// `collection`, if it is a kind that can be composed
+pub fn device_of(block_path: &str, collection: &str, device: &Value) -> Option<FabricDevice> {
+    let field = |name: &str| device.get(name).and_then(Value::as_str);
+
+    let kind = match (collection, field("ProcessorType"), field("Protocol")) {
+        ("Processors", Some("GPU"), _) => ComposableKind::Gpu,
+        ("Processors", Some("FPGA"), _) => ComposableKind::Fpga,
+        ("Drives", _, Some("NVMe")) => ComposableKind::Nvme,
+        ("Memory", _, _) => ComposableKind::Memory,
+        _ => return None,
+    };
+
+    Some(FabricDevice {
+        id: block_path.to_owned(),
+        kind,
+        model: field("Model")
+            .or(field("Name"))
+            .unwrap_or_default()
+            .trim()
+            .to_owned(),
+    })
+}
+
+/// The blocks a system is composed of
+pub fn linked_blocks(system: &Value) -> Vec<String> {
+    ids(system.pointer("/Links/ResourceBlocks"))
+}
+
+/// The patch of a system that composes it of exactly `blocks`
+pub fn blocks_patch(blocks: &[String]) -> Value {
+    let blocks: Vec<Value> = blocks.iter().map(|b| json!({ "@odata.id": b })).collect();
+
+    json!({ "Links": { "ResourceBlocks": blocks } })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_free_blocks() {
+        let block = |state: &str, reserved: bool| json!({ "CompositionStatus": { "CompositionState": state, "Reserved": reserved } });
+
+        assert!(is_free(&block("Unused", false)));
+        assert!(!is_free(&block("Unused", true)));
+        assert!(!is_free(&block("Composed", false)));
+        assert!(!is_free(&json!({})));
+    }
+
+    #[test]
+    fn test_device_of() {
+        let block = "/redfish/v1/CompositionService/ResourceBlocks/12";
+
+        let gpu = device_of(
+            block,
+            "Processors",
+            &json!({ "ProcessorType": "GPU", "Model": "NVIDIA A100 40GB " }),
+        )
+        .unwrap();
+        assert_eq!(gpu.id, block);
+        assert_eq!(gpu.kind, ComposableKind::Gpu);
+        assert_eq!(gpu.model, "NVIDIA A100 40GB");
+
+        let nvme = device_of(
+            block,
+            "Drives",
+            &json!({ "Protocol": "NVMe", "Model": "PM1733" }),
+        );
+        assert_eq!(nvme.unwrap().kind, ComposableKind::Nvme);
+
+        // only NVMe drives and accelerators are composed, not SAS drives or CPUs
+        assert!(device_of(block, "Drives", &json!({ "Protocol": "SAS" })).is_none());
+        assert!(device_of(block, "Processors", &json!({ "ProcessorType": "CPU" })).is_none());
+    }
+
+    #[test]
+    fn test_blocks_patch() {
+        let system = json!({
+            "Links": {
+                "ResourceBlocks": [
+                    { "@odata.id": "/redfish/v1/CompositionService/ResourceBlocks/compute" },
+                ]
+            }
+        });
+
+        let mut blocks = linked_blocks(&system);
+        assert_eq!(
+            blocks,
+            vec!["/redfish/v1/CompositionService/ResourceBlocks/compute"]
+        );
+
+        blocks.push("/redfish/v1/CompositionService/ResourceBlocks/12".to_owned());
+        assert_eq!(
+            blocks_patch(&blocks)["Links"]["ResourceBlocks"][1]["@odata.id"],
+            "/redfish/v1/CompositionService/ResourceBlocks/12"
+        );
+        assert_eq!(linked_blocks(&json!({})), Vec::<String>::new());
+    }
+}
@@ -0,0 +1,129 @@
+//! What a booking can be expected to take before it is ready and what it comes to, shown
+//! when it is validated so users can weigh flavors and lengths against each other
+//!
+//! Time-to-ready comes from how long recent deploys of each flavor took according to their
+//! provision logs. Hosts deploy side by side, so the booking is ready once its slowest
+//! flavor is. Node-hours and cost are the hosts times the length of the booking, at the
+//! hourly costs configured for accounting.
+
+use std::collections::HashMap;
+
+use common::prelude::{anyhow, chrono::Duration};
+use config::settings;
+use dal::{EasyTransaction, FKey};
+use models::{dashboard::ProvisionLogEvent, inventory::Flavor};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// How many of the most recent deploys of a flavor its time-to-ready is estimated from
+const HISTORY: i64 = 50;
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, PartialEq)]
+pub struct FlavorEstimate {
+    pub flavor: String,
+    pub hosts: usize,
+    /// How many past deploys the times are from, the times are None if there were none
+    pub deploys: usize,
+    pub median_minutes: Option<f64>,
+    /// Nine in ten past deploys were ready within this
+    pub p90_minutes: Option<f64>,
+    pub hourly_cost: Option<f64>,
+    pub node_hours: Option<f64>,
+    pub cost: Option<f64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, PartialEq, Default)]
+pub struct BookingEstimate {
+    /// When the booking would typically be ready, None if no flavor in it was deployed before
+    pub time_to_ready_minutes: Option<f64>,
+    /// When it would be ready at worst, going by nine in ten past deploys
+    pub time_to_ready_p90_minutes: Option<f64>,
+    /// None for bookings without a length
+    pub node_hours: Option<f64>,
+    /// None if the cost of any flavor isn't configured, or the booking has no length
+    pub cost: Option<f64>,
+    pub flavors: Vec<FlavorEstimate>,
+}
+
+/// The value `percent` percent of `values` are at or below, by nearest rank
+pub fn percentile(mut values: Vec<f64>, percent: f64) -> Option<f64> {
+    if values.is_empty() {
+        return None;
+    }
+    values.sort_by(f64::total_cmp);
+
+    let rank = ((percent / 100.0) * values.len() as f64).ceil() as usize;
+    values.get(rank.clamp(1, values.len()) - 1).copied()
+}
+
+fn minutes(duration: Duration) -> f64 {
+    duration.num_seconds() as f64 / 60.0
+}
+
+/// Estimates a booking of `length_days` of hosts of `flavors`, one entry per host
+pub async fn estimate(
+    t: &mut EasyTransaction<'_>,
+    flavors: &[FKey<Flavor>],
+    length_days: Option<u64>,
+) -> Result<BookingEstimate, anyhow::Error> {
+    let mut hosts: HashMap<FKey<Flavor>, usize> = HashMap::new();
+    for flavor in flavors {
+        *hosts.entry(*flavor).or_default() += 1;
+    }
+
+    let costs = &settings().accounting.flavor_hourly_costs;
+    let hours = length_days.map(|d| d as f64 * 24.0);
+
+    let mut estimates = Vec::new();
+    for (flavor, count) in hosts {
+        let name = flavor.get(t).await?.name.clone();
+        let times: Vec<f64> = ProvisionLogEvent::provisioning_times(t, flavor, HISTORY)
+            .await?
+            .into_iter()
+            .map(minutes)
+            .collect();
+
+        let hourly_cost = costs.get(&name).copied();
+        let node_hours = hours.map(|h| h * count as f64);
+
+        estimates.push(FlavorEstimate {
+            flavor: name,
+            hosts: count,
+            deploys: times.len(),
+            median_minutes: percentile(times.clone(), 50.0),
+            p90_minutes: percentile(times, 90.0),
+            hourly_cost,
+            node_hours,
+            cost: node_hours.zip(hourly_cost).map(|(h, c)| h * c),
+        });
+    }
+    estimates.sort_by(|a, b| a.flavor.cmp(&b.flavor));
+
+    let slowest = |by: fn(&FlavorEstimate) -> Option<f64>| {
+        estimates.iter().filter_map(by).max_by(f64::total_cmp)
+    };
+
+    Ok(BookingEstimate {
+        time_to_ready_minutes: slowest(|e| e.median_minutes),
+        time_to_ready_p90_minutes: slowest(|e| e.p90_minutes),
+        node_hours: estimates.iter().map(|e| e.node_hours).sum(),
+        cost: estimates.iter().map(|e| e.cost).sum(),
+        flavors: estimates,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_percentile() {
+        assert_eq!(percentile(vec![], 50.0), None);
+        assert_eq!(percentile(vec![7.0], 90.0), Some(7.0));
+
+        let times = vec![30.0, 10.0, 20.0, 50.0, 40.0];
+        assert_eq!(percentile(times.clone(), 50.0), Some(30.0));
+        assert_eq!(percentile(times.clone(), 90.0), Some(50.0));
+        assert_eq!(percentile(times, 0.0), Some(10.0));
+    }
+}
@@ -0,0 +1,243 @@
+//! Ends bookings that are past their end
+//!
+//! Owners are told ahead of time that their booking is about to end, but nothing held
+//! them to it. Every run looks for active bookings that ended more than the grace period
+//! ago, starts their teardown, and tells their users it happened. Admins can exempt a
+//! booking, which is then left running however long it is past its end. Bookings of a
+//! project outside of its [working hours](super::working_hours) are left for a later run.
+
+use std::collections::HashMap;
+
+use common::prelude::{
+    chrono::{DateTime, Duration, Utc},
+    tokio, tracing,
+};
+use config::{settings, Situation};
+use dal::{new_client, AsEasyTransaction, ID};
+use models::dashboard::{
+    Aggregate, AggregateCursor, AggregateFilter, BookingEvent, BookingEventKind, BookingMetadata,
+    LifeCycleState,
+};
+use notifications::email::send_to_admins;
+use serde::{Deserialize, Serialize};
+use tascii::prelude::*;
+
+use super::working_hours;
+use crate::{
+    deploy_booking::notify::NotifyContext,
+    entry::{Action, DISPATCH},
+};
+
+/// Whether a booking with `metadata` should be ended at `now`
+fn is_due(metadata: &BookingMetadata, grace: Duration, now: DateTime<Utc>) -> bool {
+    !metadata.exempt_from_expiry && metadata.end.is_some_and(|end| end + grace < now)
+}
+
+#[derive(Debug, Clone, Hash, Serialize, Deserialize)]
+pub struct ExpireBookings {}
+
+tascii::mark_task!(ExpireBookings);
+impl AsyncRunnable for ExpireBookings {
+    type Output = ();
+
+    async fn run(&mut self, _context: &Context) -> Result<Self::Output, TaskError> {
+        let grace = Duration::hours(settings().booking_expiry.grace_hours);
+        let now = Utc::now();
+
+        let mut client = new_client().await?;
+        let mut transaction = client.easy_transaction().await?;
+
+        let dispatch = DISPATCH
+            .get()
+            .ok_or(TaskError::Reason("dispatcher isn't running".to_owned()))?;
+
+        let filter = AggregateFilter {
+            state: Some(LifeCycleState::Active),
+            ..Default::default()
+        };
+
+        let mut aggregates = Vec::new();
+        let mut after = None;
+        loop {
+            let page = Aggregate::page(&mut transaction, &filter, after, 500).await?;
+            let Some(last) = page.last() else {
+                break;
+            };
+            after = Some(AggregateCursor {
+                start: last.metadata.start,
+                id: last.id,
+            });
+            aggregates.extend(page);
+        }
+
+        let mut due = Vec::new();
+        let mut open = HashMap::new();
+        let mut held = 0;
+        for agg in aggregates {
+            if agg.deleted || !is_due(&agg.metadata, grace, now) {
+                continue;
+            }
+
+            let is_open = match open.get(&agg.lab) {
+                Some(&is_open) => is_open,
+                None => {
+                    let project = agg.lab.get(&mut transaction).await?.name.clone();
+                    let is_open = working_hours::is_open(&project, now);
+                    open.insert(agg.lab, is_open);
+                    is_open
+                }
+            };
+            if !is_open {
+                held += 1;
+                continue;
+            }
+
+            due.push(agg.id);
+        }
+
+        transaction.commit().await?;
+
+        let mut expired = Vec::new();
+        for agg_id in due {
+            // each booking is recorded as expired and committed on its own before its end is
+            // queued, so an end is never queued for an expiry that didn't stick
+            let mut transaction = client.easy_transaction().await?;
+
+            // an admin may have exempted or extended it since it was looked at
+            let agg = agg_id.get(&mut transaction).await?.into_inner();
+            if agg.deleted || !is_due(&agg.metadata, grace, now) {
+                continue;
+            }
+
+            // the teardown of a booking expired by an earlier run may still be going
+            if BookingEvent::for_aggregate(&mut transaction, agg.id)
+                .await?
+                .iter()
+                .any(|e| e.kind == BookingEventKind::Expired)
+            {
+                continue;
+            }
+
+            let end = agg.metadata.end.unwrap_or(now);
+            BookingEvent::record(
+                &mut transaction,
+                agg.id,
+                BookingEventKind::Expired,
+                format!(
+                    "it was due to end {}, so it is being ended",
+                    end.to_rfc2822()
+                ),
+                None,
+            )
+            .await?;
+            transaction.commit().await?;
+
+            if let Err(e) = dispatch.send(Action::CleanupBooking { agg_id: agg.id }) {
+                tracing::error!(
+                    "Couldn't queue the end of expired booking {:?}: {e:?}",
+                    agg.id
+                );
+                continue;
+            }
+
+            match Action::notify(agg.id, Situation::BookingExpired, NotifyContext::Booking) {
+                Ok(action) => {
+                    if let Err(e) = dispatch.send(action) {
+                        tracing::error!("Couldn't tell users of {:?} it expired: {e:?}", agg.id);
+                    }
+                }
+                Err(e) => tracing::error!("Couldn't build expiry notice for {:?}: {e:?}", agg.id),
+            }
+
+            expired.push(format!(
+                "{} (aggregate {}) of {}, due to end {}",
+                agg.metadata
+                    .display_name
+                    .clone()
+                    .or(agg.metadata.booking_id.clone())
+                    .unwrap_or_default(),
+                agg.id.into_id(),
+                agg.metadata.owner.clone().unwrap_or_default(),
+                end.to_rfc2822()
+            ));
+        }
+
+        if held > 0 {
+            tracing::info!("Left {held} expired booking(s) running until their lab is staffed");
+        }
+
+        if !expired.is_empty() {
+            send_to_admins(format!(
+                "Ended {} booking(s) that were past their end:<br>{}",
+                expired.len(),
+                expired.join("<br>")
+            ))
+            .await;
+        }
+
+        Ok(())
+    }
+
+    fn identifier() -> TaskIdentifier {
+        TaskIdentifier::named("ExpireBookingsTask").versioned(1)
+    }
+
+    fn summarize(&self, id: ID) -> String {
+        format!("ExpireBookings with id {id}")
+    }
+
+    fn retry_count(&self) -> usize {
+        0
+    }
+}
+
+/// Periodically queues up a look for expired bookings, unless that has been disabled
+pub async fn entry() {
+    let config = settings().booking_expiry.clone();
+    if !config.enabled {
+        tracing::info!("Booking expiry is disabled, bookings are left running past their end");
+        return;
+    }
+
+    let mut interval =
+        tokio::time::interval(std::time::Duration::from_secs(config.interval_minutes * 60));
+
+    loop {
+        interval.tick().await;
+
+        match DISPATCH.get() {
+            Some(dispatch) => {
+                if let Err(e) = dispatch.send(Action::ExpireBookings) {
+                    tracing::error!("Couldn't queue booking expiry: {e:?}");
+                }
+            }
+            None => tracing::warn!("Dispatcher isn't running yet, skipping booking expiry"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_due() {
+        let now = Utc::now();
+        let grace = Duration::hours(2);
+        let ending = |end| BookingMetadata {
+            end,
+            ..Default::default()
+        };
+
+        assert!(is_due(&ending(Some(now - Duration::hours(3))), grace, now));
+        assert!(!is_due(&ending(Some(now - Duration::hours(1))), grace, now));
+        assert!(!is_due(&ending(Some(now + Duration::hours(1))), grace, now));
+        assert!(!is_due(&ending(None), grace, now));
+
+        let exempt = BookingMetadata {
+            exempt_from_expiry: true,
+            ..ending(Some(now - Duration::days(30)))
+        };
+        assert!(!is_due(&exempt, grace, now));
+    }
+}
@@ -0,0 +1,169 @@
+//! Trunks booking networks onto the uplinks of external carrier/partner feeds,
+//! once an admin has approved the attachment, and takes them off again at booking end
+
+use common::prelude::{chrono::Utc, tracing};
+use config::settings;
+use dal::{new_client, AsEasyTransaction, EasyTransaction, FKey, ID};
+use models::{
+    dashboard::{Aggregate, AttachmentState, ExternalAttachment},
+    inventory::Switch,
+};
+use serde::{Deserialize, Serialize};
+use tascii::prelude::*;
+
+use super::{cisco, sonic};
+
+/// Looks up the vlan the booking network was assigned, and programs it onto the feed uplink
+async fn program_attachment(
+    t: &mut EasyTransaction<'_>,
+    attachment: &ExternalAttachment,
+    add: bool,
+) -> Result<(), anyhow::Error> {
+    let feed = settings()
+        .external_feeds
+        .get(&attachment.feed)
+        .ok_or(anyhow::Error::msg(format!(
+            "no external feed named {} is configured",
+            attachment.feed
+        )))?;
+
+    let netmap = attachment.aggregate.get(t).await?.vlans.get(t).await?;
+    let vlan = netmap
+        .networks
+        .get(&attachment.network)
+        .ok_or(anyhow::Error::msg("network was not assigned a vlan"))?
+        .get(t)
+        .await?
+        .vlan_id;
+
+    let switch: Switch = Switch::get_by_name(t, feed.switch.clone())
+        .await?
+        .ok_or(anyhow::Error::msg(format!(
+            "feed switch {} does not exist",
+            feed.switch
+        )))?
+        .into_inner();
+
+    let os = switch
+        .switch_os
+        .ok_or(anyhow::Error::msg("switch has no os set"))?
+        .get(t)
+        .await?
+        .os_type
+        .clone();
+
+    tracing::info!(
+        "{} vlan {vlan} on feed {} uplink {} {}",
+        if add { "Trunking" } else { "Removing" },
+        attachment.feed,
+        feed.switch,
+        feed.port
+    );
+
+    match os.as_str() {
+        "NXOS" => cisco::nx_trunk_vlan(switch, &feed.port, vlan, add),
+        "SONiC" => sonic::sonic_trunk_vlan(&switch, &feed.port, vlan, add),
+        other => {
+            return Err(anyhow::Error::msg(format!(
+                "external feeds are not supported on {other} switches"
+            )))
+        }
+    }
+
+    Ok(())
+}
+
+/// Programs an approved attachment onto its feed uplink
+#[derive(Debug, Clone, Hash, Serialize, Deserialize)]
+pub struct AttachExternalNetwork {
+    pub attachment: FKey<ExternalAttachment>,
+}
+
+tascii::mark_task!(AttachExternalNetwork);
+impl AsyncRunnable for AttachExternalNetwork {
+    type Output = ();
+
+    async fn run(&mut self, _context: &Context) -> Result<Self::Output, TaskError> {
+        let mut client = new_client().await?;
+        let mut transaction = client.easy_transaction().await?;
+
+        let mut attachment = self.attachment.get(&mut transaction).await?;
+
+        let res = program_attachment(&mut transaction, &attachment, true).await;
+
+        attachment.state = match res {
+            Ok(_) => AttachmentState::Active,
+            Err(_) => AttachmentState::Failed,
+        };
+        attachment.updated = Utc::now();
+        attachment.update(&mut transaction).await?;
+
+        transaction.commit().await?;
+
+        Ok(res?)
+    }
+
+    fn summarize(&self, id: ID) -> String {
+        format!(
+            "AttachExternalNetwork task with id {id} for attachment {:?}",
+            self.attachment
+        )
+    }
+
+    fn identifier() -> TaskIdentifier {
+        TaskIdentifier::named("AttachExternalNetworkTask").versioned(1)
+    }
+}
+
+/// Takes every active attachment of the booking back off of its feed uplink
+#[derive(Debug, Clone, Hash, Serialize, Deserialize)]
+pub struct DetachExternalNetworks {
+    pub agg_id: FKey<Aggregate>,
+}
+
+tascii::mark_task!(DetachExternalNetworks);
+impl AsyncRunnable for DetachExternalNetworks {
+    type Output = ();
+
+    async fn run(&mut self, _context: &Context) -> Result<Self::Output, TaskError> {
+        let mut client = new_client().await?;
+        let mut transaction = client.easy_transaction().await?;
+
+        for mut attachment in
+            ExternalAttachment::all_for_aggregate(&mut transaction, self.agg_id).await?
+        {
+            match attachment.state {
+                // a failed attachment may still have been partially programmed
+                AttachmentState::Active | AttachmentState::Failed => {
+                    program_attachment(&mut transaction, &attachment, false).await?;
+                    attachment.state = AttachmentState::Removed;
+                }
+                // nobody is going to review it anymore
+                AttachmentState::Requested => attachment.state = AttachmentState::Denied,
+                AttachmentState::Denied | AttachmentState::Removed => continue,
+            }
+
+            attachment.updated = Utc::now();
+            attachment.update(&mut transaction).await?;
+        }
+
+        transaction.commit().await?;
+
+        Ok(())
+    }
+
+    fn summarize(&self, id: ID) -> String {
+        format!(
+            "DetachExternalNetworks task with id {id} for agg {:?}",
+            self.agg_id
+        )
+    }
+
+    fn identifier() -> TaskIdentifier {
+        TaskIdentifier::named("DetachExternalNetworksTask").versioned(1)
+    }
+
+    fn retry_count(&self) -> usize {
+        2
+    }
+}
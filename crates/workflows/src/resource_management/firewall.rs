@@ -0,0 +1,479 @@
+//! Programs per-booking inbound firewall policies onto the lab edge
+//!
+//! Each booking gets its own nftables chain on the edge that is jumped to
+//! for traffic headed to any of the booking's public subnets. The chain
+//! accepts established traffic and anything matching an allow rule, and
+//! drops everything else. A changed policy gets a new chain, which the jumps
+//! are moved over to all at once before the old chain is deleted.
+
+use common::prelude::{chrono::Utc, tracing};
+use config::{settings, FirewallConfig, FirewallGuardrails};
+use dal::{new_client, AsEasyTransaction, FKey, ID};
+use models::dashboard::{
+    Aggregate, FirewallPolicy, FirewallProtocol, FirewallRule, LeaseOperation,
+};
+use serde::{Deserialize, Serialize};
+use ssh2::Session;
+use std::{io::Read, net::IpAddr, net::TcpStream};
+use tascii::prelude::*;
+
+use super::lease;
+
+const TABLE: &str = "inet laas";
+
+/// Checks the given rules against the lab guardrails,
+/// returning every reason they are not acceptable
+pub fn validate_rules(
+    rules: &[FirewallRule],
+    guardrails: &FirewallGuardrails,
+) -> Result<(), Vec<String>> {
+    let mut problems = Vec::new();
+
+    if let Some(max) = guardrails.max_rules {
+        if rules.len() > max {
+            problems.push(format!(
+                "at most {max} rules are allowed, {} were given",
+                rules.len()
+            ));
+        }
+    }
+
+    for (i, rule) in rules.iter().enumerate() {
+        match parse_cidr(&rule.source) {
+            None => problems.push(format!(
+                "rule {i}: source {} is not a valid CIDR",
+                rule.source
+            )),
+            Some((_, 0)) if !guardrails.allow_any_source => problems.push(format!(
+                "rule {i}: allowing traffic from any source is not permitted in this lab"
+            )),
+            Some(_) => (),
+        }
+
+        match (rule.protocol, rule.ports) {
+            (FirewallProtocol::Icmp, Some(_)) => {
+                problems.push(format!("rule {i}: icmp rules can not specify ports"))
+            }
+            (_, Some(range)) if range.start > range.end => problems.push(format!(
+                "rule {i}: port range {}-{} is backwards",
+                range.start, range.end
+            )),
+            (_, Some(range)) => {
+                for blocked in guardrails.blocked_ports.iter() {
+                    if (range.start..=range.end).contains(blocked) {
+                        problems.push(format!("rule {i}: port {blocked} may not be opened"));
+                    }
+                }
+            }
+            (FirewallProtocol::Icmp, None) => (),
+            (_, None) => {
+                if !guardrails.blocked_ports.is_empty() {
+                    problems.push(format!(
+                        "rule {i}: opening every port would open blocked ports {:?}",
+                        guardrails.blocked_ports
+                    ));
+                }
+            }
+        }
+    }
+
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(problems)
+    }
+}
+
+fn parse_cidr(cidr: &str) -> Option<(IpAddr, u8)> {
+    let (addr, prefix) = cidr.split_once('/')?;
+    let addr: IpAddr = addr.parse().ok()?;
+    let prefix: u8 = prefix.parse().ok()?;
+
+    let max = match addr {
+        IpAddr::V4(_) => 32,
+        IpAddr::V6(_) => 128,
+    };
+
+    (prefix <= max).then_some((addr, prefix))
+}
+
+fn chain_name(agg: FKey<Aggregate>) -> String {
+    format!("booking_{}", agg.into_id().to_string().replace('-', "_"))
+}
+
+fn family_of(cidr: &str) -> &'static str {
+    match parse_cidr(cidr) {
+        Some((IpAddr::V6(_), _)) => "ip6",
+        _ => "ip",
+    }
+}
+
+/// Prints `lines` one after the other, to be fed to `nft -f`, which applies everything
+/// it reads as one transaction so either all of it takes effect or none of it does
+fn print_lines(lines: &[String]) -> String {
+    let quoted: Vec<String> = lines.iter().map(|l| format!("'{l}'")).collect();
+
+    format!("printf '%s\\n' {}", quoted.join(" "))
+}
+
+/// Deletes every chain of the booking other than `keep`, which must no longer be jumped to
+fn render_stale_chains(agg: FKey<Aggregate>, keep: Option<&str>) -> String {
+    let chain = chain_name(agg);
+    let keep = keep.unwrap_or("");
+
+    format!(
+        "sudo nft list table {TABLE} | awk '$1 == \"chain\" && $2 ~ /^{chain}(_[0-9]+)?$/ && $2 != \"{keep}\" {{print $2}}' \
+        | xargs -r -I{{}} sudo nft 'flush chain {TABLE} {{}}; delete chain {TABLE} {{}}'"
+    )
+}
+
+/// Removes every jump into the booking chains, then the chains themselves
+fn render_removal(agg: FKey<Aggregate>) -> Vec<String> {
+    let chain = chain_name(agg);
+
+    vec![
+        format!(
+            "sudo nft -a list chain {TABLE} forward | grep 'comment \"{chain}\"' \
+            | awk '{{print $NF}}' | xargs -r -n1 sudo nft delete rule {TABLE} forward handle"
+        ),
+        render_stale_chains(agg, None),
+    ]
+}
+
+/// Renders the commands that replace the policy of the booking on the edge
+/// so that it matches `rules`, for traffic to `destinations`
+///
+/// The new policy is built in a chain of its own, named after `generation`, and the jumps
+/// into the old chain are swapped for jumps into the new one in a single transaction. The
+/// traffic of the booking is filtered by one or the other the whole time, and if anything
+/// fails before the swap the old policy stays in place.
+fn render_policy(
+    agg: FKey<Aggregate>,
+    generation: i64,
+    destinations: &[String],
+    rules: &[FirewallRule],
+) -> Vec<String> {
+    let jump_comment = chain_name(agg);
+    let chain = format!("{jump_comment}_{generation}");
+
+    let mut commands = vec![
+        format!("sudo nft add table {TABLE}"),
+        format!("sudo nft 'add chain {TABLE} forward {{ type filter hook forward priority 0 ; }}'"),
+    ];
+
+    let mut build = vec![
+        format!("add chain {TABLE} {chain}"),
+        format!("add rule {TABLE} {chain} ct state established,related accept"),
+    ];
+
+    for rule in rules {
+        let family = family_of(&rule.source);
+        let source = &rule.source;
+        let matcher = match (rule.protocol, rule.ports) {
+            (FirewallProtocol::Icmp, _) if family == "ip6" => "meta l4proto ipv6-icmp".to_owned(),
+            (FirewallProtocol::Icmp, _) => "meta l4proto icmp".to_owned(),
+            (FirewallProtocol::Tcp, Some(p)) => format!("tcp dport {}-{}", p.start, p.end),
+            (FirewallProtocol::Udp, Some(p)) => format!("udp dport {}-{}", p.start, p.end),
+            (FirewallProtocol::Tcp, None) => "meta l4proto tcp".to_owned(),
+            (FirewallProtocol::Udp, None) => "meta l4proto udp".to_owned(),
+        };
+
+        build.push(format!(
+            "add rule {TABLE} {chain} {family} saddr {source} {matcher} accept"
+        ));
+    }
+
+    build.push(format!("add rule {TABLE} {chain} drop"));
+    commands.push(format!("{} | sudo nft -f -", print_lines(&build)));
+
+    // nft reads the whole batch before applying any of it, so the old jumps are listed
+    // before the new ones exist
+    let jumps: Vec<String> = destinations
+        .iter()
+        .map(|destination| {
+            let family = family_of(destination);
+            format!("add rule {TABLE} forward {family} daddr {destination} jump {chain} comment \"{jump_comment}\"")
+        })
+        .collect();
+    let old_jumps = format!(
+        "sudo nft -a list chain {TABLE} forward | grep 'comment \"{jump_comment}\"' \
+        | awk '{{print \"delete rule {TABLE} forward handle \" $NF}}'"
+    );
+    commands.push(format!(
+        "{{ {}; {old_jumps}; }} | sudo nft -f -",
+        print_lines(&jumps)
+    ));
+
+    commands.push(render_stale_chains(agg, Some(&chain)));
+
+    commands
+}
+
+fn run_on_edge(config: &FirewallConfig, commands: &[String]) -> Result<(), anyhow::Error> {
+    let mut session = Session::new()?;
+    let connection = TcpStream::connect(format!("{}:22", config.edge_address))?;
+
+    session.set_tcp_stream(connection);
+    session.handshake()?;
+    session.userauth_password(&config.username, &config.password)?;
+
+    for command in commands {
+        tracing::info!("Running on lab edge: {command}");
+
+        let mut channel = session.channel_session()?;
+        let mut output = String::new();
+
+        channel.exec(command)?;
+        channel.read_to_string(&mut output)?;
+        channel.wait_close()?;
+
+        if channel.exit_status()? != 0 {
+            return Err(anyhow::Error::msg(format!(
+                "edge command `{command}` failed with output: {output}"
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+/// The public subnets that the booking's uplinks serve
+async fn destinations_for(
+    t: &mut dal::EasyTransaction<'_>,
+    agg: FKey<Aggregate>,
+) -> Result<Vec<String>, anyhow::Error> {
+    let agg = agg.get(t).await?;
+    let netmap = agg.vlans.get(t).await?;
+
+    let mut destinations = Vec::new();
+    for vlan in netmap.networks.values() {
+        let vlan = vlan.get(t).await?;
+
+        if let Some(pc) = vlan.public_config.as_ref() {
+            if let Some(v4) = pc.v4.as_ref() {
+                destinations.push(format!("{}/{}", v4.subnet, v4.netmask));
+            }
+            if let Some(v6) = pc.v6.as_ref() {
+                destinations.push(format!("{}/{}", v6.subnet, v6.netmask));
+            }
+        }
+    }
+
+    Ok(destinations)
+}
+
+/// Programs the stored firewall policy of a booking onto the lab edge
+#[derive(Debug, Clone, Hash, Serialize, Deserialize)]
+pub struct ApplyFirewallPolicy {
+    pub agg_id: FKey<Aggregate>,
+}
+
+tascii::mark_task!(ApplyFirewallPolicy);
+impl AsyncRunnable for ApplyFirewallPolicy {
+    type Output = ();
+
+    async fn run(&mut self, _context: &Context) -> Result<Self::Output, TaskError> {
+        let lease = lease::try_acquire(self.agg_id, LeaseOperation::Network, None).await?;
+        lease::holding(lease, self.apply()).await
+    }
+
+    fn summarize(&self, id: ID) -> String {
+        format!(
+            "ApplyFirewallPolicy task with id {id} for agg {:?}",
+            self.agg_id
+        )
+    }
+
+    fn identifier() -> TaskIdentifier {
+        TaskIdentifier::named("ApplyFirewallPolicyTask").versioned(1)
+    }
+
+    fn retry_count(&self) -> usize {
+        2
+    }
+}
+
+impl ApplyFirewallPolicy {
+    /// Programs the policy, under the lease `run` took
+    async fn apply(&mut self) -> Result<(), TaskError> {
+        let config = settings().firewall.as_ref().ok_or(TaskError::Reason(
+            "no lab edge firewall is configured".to_owned(),
+        ))?;
+
+        let mut client = new_client().await?;
+        let mut transaction = client.easy_transaction().await?;
+
+        let mut policy = FirewallPolicy::for_aggregate(&mut transaction, self.agg_id)
+            .await?
+            .ok_or(TaskError::Reason(
+                "booking has no firewall policy to apply".to_owned(),
+            ))?;
+
+        validate_rules(&policy.rules, &config.guardrails)
+            .map_err(|problems| TaskError::Reason(problems.join(", ")))?;
+
+        let destinations = destinations_for(&mut transaction, self.agg_id).await?;
+        let commands = render_policy(
+            self.agg_id,
+            Utc::now().timestamp(),
+            &destinations,
+            &policy.rules,
+        );
+
+        run_on_edge(config, &commands)?;
+
+        policy.applied = true;
+        policy.updated = Utc::now();
+        policy.update(&mut transaction).await?;
+
+        transaction.commit().await?;
+
+        Ok(())
+    }
+}
+
+/// Removes the policy of a booking from the lab edge, closing all of its inbound access
+#[derive(Debug, Clone, Hash, Serialize, Deserialize)]
+pub struct RemoveFirewallPolicy {
+    pub agg_id: FKey<Aggregate>,
+}
+
+tascii::mark_task!(RemoveFirewallPolicy);
+impl AsyncRunnable for RemoveFirewallPolicy {
+    type Output = ();
+
+    async fn run(&mut self, _context: &Context) -> Result<Self::Output, TaskError> {
+        let config = match settings().firewall.as_ref() {
+            Some(c) => c,
+            None => return Ok(()),
+        };
+
+        let mut client = new_client().await?;
+        let mut transaction = client.easy_transaction().await?;
+
+        let policy = FirewallPolicy::for_aggregate(&mut transaction, self.agg_id).await?;
+
+        if let Some(policy) = policy {
+            run_on_edge(config, &render_removal(self.agg_id))?;
+
+            policy.delete(&mut transaction).await?;
+        }
+
+        transaction.commit().await?;
+
+        Ok(())
+    }
+
+    fn summarize(&self, id: ID) -> String {
+        format!(
+            "RemoveFirewallPolicy task with id {id} for agg {:?}",
+            self.agg_id
+        )
+    }
+
+    fn identifier() -> TaskIdentifier {
+        TaskIdentifier::named("RemoveFirewallPolicyTask").versioned(1)
+    }
+
+    fn retry_count(&self) -> usize {
+        2
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use models::dashboard::PortRange;
+
+    fn rule(protocol: FirewallProtocol, ports: Option<(u16, u16)>, source: &str) -> FirewallRule {
+        FirewallRule {
+            protocol,
+            ports: ports.map(|(start, end)| PortRange { start, end }),
+            source: source.to_owned(),
+            description: None,
+        }
+    }
+
+    #[test]
+    fn test_validate_rules() {
+        let guardrails = FirewallGuardrails {
+            max_rules: Some(2),
+            blocked_ports: vec![623],
+            allow_any_source: false,
+        };
+
+        assert!(validate_rules(
+            &[rule(FirewallProtocol::Tcp, Some((443, 443)), "10.0.0.0/8")],
+            &guardrails
+        )
+        .is_ok());
+        assert!(validate_rules(
+            &[rule(FirewallProtocol::Icmp, None, "::1/128")],
+            &guardrails
+        )
+        .is_ok());
+
+        // bad source, any source, blocked port, icmp with ports, all ports
+        assert!(validate_rules(
+            &[rule(FirewallProtocol::Tcp, Some((1, 1)), "10.0.0.0/33")],
+            &guardrails
+        )
+        .is_err());
+        assert!(validate_rules(
+            &[rule(FirewallProtocol::Tcp, Some((1, 1)), "0.0.0.0/0")],
+            &guardrails
+        )
+        .is_err());
+        assert!(validate_rules(
+            &[rule(FirewallProtocol::Udp, Some((600, 700)), "10.0.0.0/8")],
+            &guardrails
+        )
+        .is_err());
+        assert!(validate_rules(
+            &[rule(FirewallProtocol::Icmp, Some((1, 1)), "10.0.0.0/8")],
+            &guardrails
+        )
+        .is_err());
+        assert!(validate_rules(
+            &[rule(FirewallProtocol::Tcp, None, "10.0.0.0/8")],
+            &guardrails
+        )
+        .is_err());
+
+        // too many rules
+        let r = rule(FirewallProtocol::Icmp, None, "10.0.0.0/8");
+        assert!(validate_rules(&[r.clone(), r.clone(), r], &guardrails).is_err());
+    }
+
+    #[test]
+    fn test_render_policy_swaps_before_removing() {
+        let agg = FKey::new_id_dangling();
+        let chain = format!("{}_42", chain_name(agg));
+        let commands = render_policy(
+            agg,
+            42,
+            &["192.0.2.0/24".to_owned()],
+            &[rule(FirewallProtocol::Tcp, Some((22, 22)), "10.0.0.0/8")],
+        );
+
+        let built = commands
+            .iter()
+            .position(|c| c.contains(&format!("add chain {TABLE} {chain}")))
+            .unwrap();
+        let swapped = commands
+            .iter()
+            .position(|c| c.contains(&format!("jump {chain}")))
+            .unwrap();
+        let removed = commands
+            .iter()
+            .position(|c| c.contains("delete chain"))
+            .unwrap();
+
+        assert!(built < swapped && swapped < removed);
+        // the new jumps go in and the old ones go out in the same nft transaction
+        assert!(commands[swapped].contains("delete rule"));
+        assert_eq!(commands[swapped].matches("nft -f").count(), 1);
+        // the chain that was just built is kept
+        assert!(commands[removed].contains(&format!("!= \"{chain}\"")));
+    }
+}
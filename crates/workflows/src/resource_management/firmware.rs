@@ -0,0 +1,256 @@
+//! How much of the fleet is running the firmware it should be, for planning update campaigns
+//!
+//! Firmware versions are what the NIC inventory of the most recent deployment of each host
+//! collected, compared against the baseline configured for the flavor of the host. Hosts
+//! that were never deployed with an inventory, or whose flavor or NICs have no baseline,
+//! can't be told to be up to date or not and are counted as unknown.
+
+use std::collections::HashMap;
+
+use common::prelude::anyhow;
+use config::{settings, FirmwareBaseline};
+use dal::{DBTable, EasyTransaction, FKey};
+use models::{
+    dashboard::Instance,
+    inventory::{Flavor, Host},
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    deploy_booking::nic_inventory::{self, NicInfo, NicInventory},
+    utils::csv,
+};
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, JsonSchema, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Compliance {
+    Compliant,
+    Outdated,
+    Unknown,
+}
+
+impl Compliance {
+    fn as_str(self) -> &'static str {
+        match self {
+            Compliance::Compliant => "compliant",
+            Compliance::Outdated => "outdated",
+            Compliance::Unknown => "unknown",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, PartialEq)]
+pub struct NicCompliance {
+    pub interface: String,
+    pub driver: Option<String>,
+    pub firmware_version: Option<String>,
+    pub status: Compliance,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, PartialEq)]
+pub struct HostCompliance {
+    pub host: FKey<Host>,
+    pub server_name: String,
+    pub flavor: String,
+    /// Outdated if any NIC is, compliant if every NIC that could be checked is
+    pub status: Compliance,
+    /// When the firmware versions were collected, None if they never were
+    pub collected: Option<String>,
+    pub nics: Vec<NicCompliance>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, PartialEq)]
+pub struct FirmwareReport {
+    pub compliant: usize,
+    pub outdated: usize,
+    pub unknown: usize,
+    pub hosts: Vec<HostCompliance>,
+}
+
+/// Checks every NIC in `nics` against `baseline`, and the host they're in as a whole
+pub fn classify(
+    baseline: Option<&FirmwareBaseline>,
+    nics: &[NicInfo],
+) -> (Compliance, Vec<NicCompliance>) {
+    let checked: Vec<NicCompliance> = nics
+        .iter()
+        .map(|nic| {
+            let accepted = baseline
+                .zip(nic.driver.as_ref())
+                .and_then(|(b, d)| b.nics.get(d));
+            let status = match (accepted, &nic.firmware_version) {
+                (Some(accepted), Some(version)) if accepted.contains(version) => {
+                    Compliance::Compliant
+                }
+                (Some(_), Some(_)) => Compliance::Outdated,
+                _ => Compliance::Unknown,
+            };
+
+            NicCompliance {
+                interface: nic.interface.clone(),
+                driver: nic.driver.clone(),
+                firmware_version: nic.firmware_version.clone(),
+                status,
+            }
+        })
+        .collect();
+
+    let status = if checked.iter().any(|n| n.status == Compliance::Outdated) {
+        Compliance::Outdated
+    } else if checked.iter().any(|n| n.status == Compliance::Compliant) {
+        Compliance::Compliant
+    } else {
+        Compliance::Unknown
+    };
+
+    (status, checked)
+}
+
+/// Compares the firmware of every host against the baseline for its flavor
+pub async fn compliance_report(
+    t: &mut EasyTransaction<'_>,
+) -> Result<FirmwareReport, anyhow::Error> {
+    // the most recently collected inventory of each host
+    let mut inventories: HashMap<FKey<Host>, NicInventory> = HashMap::new();
+    for instance in Instance::select().run(t).await? {
+        let (Some(host), Some(inventory)) =
+            (instance.linked_host, nic_inventory::recorded(&instance))
+        else {
+            continue;
+        };
+
+        match inventories.get(&host) {
+            Some(known) if known.collected >= inventory.collected => {}
+            _ => {
+                inventories.insert(host, inventory);
+            }
+        }
+    }
+
+    let baselines = &settings().firmware.baselines;
+    let mut flavors: HashMap<FKey<Flavor>, String> = HashMap::new();
+    let mut hosts = Vec::new();
+    for host in Host::select().run(t).await? {
+        let flavor = match flavors.get(&host.flavor) {
+            Some(name) => name.clone(),
+            None => {
+                let name = host.flavor.get(t).await?.name.clone();
+                flavors.insert(host.flavor, name.clone());
+                name
+            }
+        };
+
+        let inventory = inventories.remove(&host.id);
+        let (status, nics) = classify(
+            baselines.get(&flavor),
+            inventory
+                .as_ref()
+                .map(|i| i.nics.as_slice())
+                .unwrap_or_default(),
+        );
+
+        hosts.push(HostCompliance {
+            host: host.id,
+            server_name: host.server_name.clone(),
+            flavor,
+            status,
+            collected: inventory.map(|i| i.collected.to_rfc2822()),
+            nics,
+        });
+    }
+    hosts.sort_by(|a, b| a.server_name.cmp(&b.server_name));
+
+    let count = |status| hosts.iter().filter(|h| h.status == status).count();
+    Ok(FirmwareReport {
+        compliant: count(Compliance::Compliant),
+        outdated: count(Compliance::Outdated),
+        unknown: count(Compliance::Unknown),
+        hosts,
+    })
+}
+
+/// The report as CSV, one row per NIC of each host, or one row for hosts without any
+pub fn to_csv(report: &FirmwareReport) -> String {
+    let mut out = String::from(
+        "host,flavor,host_status,interface,driver,firmware_version,nic_status,collected\n",
+    );
+
+    for host in &report.hosts {
+        let collected = host.collected.as_deref().unwrap_or_default();
+        let nics: Vec<[&str; 4]> = match host.nics.is_empty() {
+            true => vec![[""; 4]],
+            false => host
+                .nics
+                .iter()
+                .map(|n| {
+                    [
+                        n.interface.as_str(),
+                        n.driver.as_deref().unwrap_or_default(),
+                        n.firmware_version.as_deref().unwrap_or_default(),
+                        n.status.as_str(),
+                    ]
+                })
+                .collect(),
+        };
+
+        for [interface, driver, version, nic_status] in nics {
+            let row = [
+                host.server_name.as_str(),
+                host.flavor.as_str(),
+                host.status.as_str(),
+                interface,
+                driver,
+                version,
+                nic_status,
+                collected,
+            ];
+            out.push_str(&row.map(csv::field).join(","));
+            out.push('\n');
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn nic(driver: &str, version: Option<&str>) -> NicInfo {
+        NicInfo {
+            interface: format!("{driver}0"),
+            driver: Some(driver.to_owned()),
+            firmware_version: version.map(str::to_owned),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_classify() {
+        let baseline = FirmwareBaseline {
+            nics: HashMap::from([("ice".to_owned(), vec!["4.40".to_owned()])]),
+        };
+
+        let (status, nics) = classify(
+            Some(&baseline),
+            &[nic("ice", Some("4.40")), nic("mlx5_core", Some("22.1"))],
+        );
+        assert_eq!(status, Compliance::Compliant);
+        assert_eq!(nics[1].status, Compliance::Unknown);
+
+        let (status, _) = classify(
+            Some(&baseline),
+            &[nic("ice", Some("4.40")), nic("ice", Some("4.20"))],
+        );
+        assert_eq!(status, Compliance::Outdated);
+
+        // nothing to compare against
+        let (status, _) = classify(None, &[nic("ice", Some("4.40"))]);
+        assert_eq!(status, Compliance::Unknown);
+        let (status, _) = classify(Some(&baseline), &[nic("ice", None)]);
+        assert_eq!(status, Compliance::Unknown);
+        let (status, _) = classify(Some(&baseline), &[]);
+        assert_eq!(status, Compliance::Unknown);
+    }
+}
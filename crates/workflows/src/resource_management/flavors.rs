@@ -0,0 +1,342 @@
+//! How flavors are used, and which images run on them
+//!
+//! Usage is worked out from the allocation history of the hosts of a flavor, so that
+//! admins deciding whether to retire a flavor can see how busy it has really been.
+
+use std::collections::HashSet;
+
+use common::prelude::{
+    anyhow,
+    chrono::{DateTime, Duration, Utc},
+};
+use dal::{DBTable, EasyTransaction, FKey};
+use models::{
+    allocator::{Allocation, AllocationReason, ResourceHandle},
+    dashboard::Image,
+    inventory::{Flavor, Host},
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// How far back usage is looked at
+pub const USAGE_WINDOW_DAYS: i64 = 90;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema, PartialEq)]
+pub struct FlavorUsage {
+    pub hosts: usize,
+    pub free: usize,
+    pub booked: usize,
+    pub maintenance: usize,
+    pub retired: usize,
+    pub window_days: i64,
+    /// Bookings that held a host of the flavor at some point within the window
+    pub bookings: usize,
+    pub booked_hours: f64,
+    /// The share of the window that hosts which aren't retired spent booked
+    pub utilization: f64,
+    #[schemars(with = "Option<String>")]
+    pub last_booked: Option<DateTime<Utc>>,
+}
+
+/// Usage of a flavor, given the full allocation history of each of its hosts
+fn summarize(now: DateTime<Utc>, window: Duration, hosts: &[Vec<Allocation>]) -> FlavorUsage {
+    let since = now - window;
+    let mut usage = FlavorUsage {
+        hosts: hosts.len(),
+        window_days: window.num_days(),
+        ..Default::default()
+    };
+    let mut aggregates = HashSet::new();
+
+    for allocations in hosts {
+        match allocations
+            .iter()
+            .find(|a| a.ended.is_none())
+            .map(|a| a.reason_started)
+        {
+            None => usage.free += 1,
+            Some(AllocationReason::ForBooking) => usage.booked += 1,
+            Some(AllocationReason::ForMaintenance) => usage.maintenance += 1,
+            Some(AllocationReason::ForRetiry) => usage.retired += 1,
+        }
+
+        for allocation in allocations
+            .iter()
+            .filter(|a| matches!(a.reason_started, AllocationReason::ForBooking))
+        {
+            usage.last_booked = usage.last_booked.max(Some(allocation.started));
+
+            let start = allocation.started.max(since);
+            let end = allocation.ended.unwrap_or(now).min(now);
+            if end <= start {
+                continue;
+            }
+
+            usage.booked_hours += (end - start).num_seconds() as f64 / 3600.0;
+            if let Some(agg) = allocation.for_aggregate {
+                aggregates.insert(agg);
+            }
+        }
+    }
+
+    usage.bookings = aggregates.len();
+
+    let usable_hours = (usage.hosts - usage.retired) as f64 * window.num_seconds() as f64 / 3600.0;
+    if usable_hours > 0.0 {
+        usage.utilization = (usage.booked_hours / usable_hours).min(1.0);
+    }
+
+    usage
+}
+
+pub async fn usage(
+    t: &mut EasyTransaction<'_>,
+    flavor: FKey<Flavor>,
+) -> Result<FlavorUsage, anyhow::Error> {
+    let hosts = Host::select()
+        .where_field("flavor")
+        .equals(flavor)
+        .run(t)
+        .await?;
+
+    let mut history = Vec::new();
+    for host in hosts {
+        let handle = ResourceHandle::handle_for_host(t, host.id).await?;
+
+        let mut allocations = Allocation::find(t, handle.id, false).await?;
+        allocations.extend(Allocation::find(t, handle.id, true).await?);
+
+        history.push(allocations.into_iter().map(|a| a.into_inner()).collect());
+    }
+
+    Ok(summarize(
+        Utc::now(),
+        Duration::days(USAGE_WINDOW_DAYS),
+        &history,
+    ))
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, JsonSchema, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Compatibility {
+    Supported,
+    /// Supported, but admins no longer recommend the image
+    Deprecated,
+    /// The image used to be supported, but can't be booked anymore
+    Sunset,
+    Unsupported,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct MatrixFlavor {
+    pub id: FKey<Flavor>,
+    pub name: String,
+    pub arch: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct MatrixImage {
+    pub id: FKey<Image>,
+    pub name: String,
+    pub public: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct CompatibilityMatrix {
+    pub flavors: Vec<MatrixFlavor>,
+    pub images: Vec<MatrixImage>,
+    /// `cells[f][i]` is how image `images[i]` runs on flavor `flavors[f]`
+    pub cells: Vec<Vec<Compatibility>>,
+}
+
+fn build_matrix(now: DateTime<Utc>, flavors: &[Flavor], images: &[Image]) -> CompatibilityMatrix {
+    let images: Vec<&Image> = images.iter().filter(|i| !i.deleted).collect();
+
+    let cells = flavors
+        .iter()
+        .map(|flavor| {
+            images
+                .iter()
+                .map(|image| {
+                    if !image.flavors.contains(&flavor.id) {
+                        Compatibility::Unsupported
+                    } else if image.sunset_passed(now) {
+                        Compatibility::Sunset
+                    } else if image.deprecated.is_some_and(|d| d <= now) {
+                        Compatibility::Deprecated
+                    } else {
+                        Compatibility::Supported
+                    }
+                })
+                .collect()
+        })
+        .collect();
+
+    CompatibilityMatrix {
+        flavors: flavors
+            .iter()
+            .map(|f| MatrixFlavor {
+                id: f.id,
+                name: f.name.clone(),
+                arch: f.arch.to_string(),
+            })
+            .collect(),
+        images: images
+            .iter()
+            .map(|i| MatrixImage {
+                id: i.id,
+                name: i.name.clone(),
+                public: i.public,
+            })
+            .collect(),
+        cells,
+    }
+}
+
+pub async fn compatibility(
+    t: &mut EasyTransaction<'_>,
+) -> Result<CompatibilityMatrix, anyhow::Error> {
+    let mut flavors: Vec<Flavor> = Flavor::select()
+        .run(t)
+        .await?
+        .into_iter()
+        .map(|f| f.into_inner())
+        .collect();
+    flavors.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let mut images: Vec<Image> = Image::select()
+        .run(t)
+        .await?
+        .into_iter()
+        .map(|i| i.into_inner())
+        .collect();
+    images.sort_by(|a, b| a.name.cmp(&b.name));
+
+    Ok(build_matrix(Utc::now(), &flavors, &images))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use models::{dashboard::Aggregate, inventory::Arch};
+
+    fn allocation(
+        reason: AllocationReason,
+        agg: Option<FKey<Aggregate>>,
+        started: DateTime<Utc>,
+        ended: Option<DateTime<Utc>>,
+    ) -> Allocation {
+        Allocation {
+            id: FKey::new_id_dangling(),
+            for_resource: FKey::new_id_dangling(),
+            for_aggregate: agg,
+            started,
+            ended,
+            reason_started: reason,
+            reason_ended: None,
+        }
+    }
+
+    #[test]
+    fn test_summarize() {
+        let now = Utc::now();
+        let window = Duration::days(10);
+        let aggs: Vec<FKey<Aggregate>> = (0..3).map(|_| FKey::new_id_dangling()).collect();
+
+        let hosts = vec![
+            // booked for the last two days, and once more long before the window
+            vec![
+                allocation(
+                    AllocationReason::ForBooking,
+                    Some(aggs[0]),
+                    now - Duration::days(2),
+                    None,
+                ),
+                allocation(
+                    AllocationReason::ForBooking,
+                    Some(aggs[1]),
+                    now - Duration::days(40),
+                    Some(now - Duration::days(30)),
+                ),
+            ],
+            // a booking that started before the window only counts from its start
+            vec![allocation(
+                AllocationReason::ForBooking,
+                Some(aggs[2]),
+                now - Duration::days(12),
+                Some(now - Duration::days(7)),
+            )],
+            vec![allocation(
+                AllocationReason::ForRetiry,
+                None,
+                now - Duration::days(100),
+                None,
+            )],
+            vec![],
+        ];
+
+        let usage = summarize(now, window, &hosts);
+
+        assert_eq!(usage.hosts, 4);
+        assert_eq!(
+            (usage.free, usage.booked, usage.maintenance, usage.retired),
+            (2, 1, 0, 1)
+        );
+        assert_eq!(usage.bookings, 2);
+        assert!((usage.booked_hours - 5.0 * 24.0).abs() < 0.01);
+        assert!((usage.utilization - 5.0 / 30.0).abs() < 0.001);
+        assert_eq!(usage.last_booked, Some(now - Duration::days(2)));
+    }
+
+    #[test]
+    fn test_matrix() {
+        let now = Utc::now();
+        let flavor = |name: &str| Flavor {
+            id: FKey::new_id_dangling(),
+            arch: Arch::X86_64,
+            name: name.to_owned(),
+            public: true,
+            cpu_count: 0,
+            ram: Default::default(),
+            root_size: Default::default(),
+            disk_size: Default::default(),
+            swap_size: Default::default(),
+            brand: String::new(),
+            model: String::new(),
+            capabilities: Default::default(),
+        };
+        let flavors = vec![flavor("hpe"), flavor("arm")];
+
+        let image = |name: &str, flavors: Vec<FKey<Flavor>>| Image {
+            id: FKey::new_id_dangling(),
+            owner: "admin".to_owned(),
+            name: name.to_owned(),
+            deleted: false,
+            cobbler_name: name.to_owned(),
+            public: true,
+            flavors,
+            deprecated: None,
+            sunset: None,
+            replacement: None,
+            changelog: vec![],
+        };
+
+        let current = image("ubuntu", vec![flavors[0].id, flavors[1].id]);
+        let mut old = image("centos", vec![flavors[0].id]);
+        old.deprecated = Some(now - Duration::days(30));
+        old.sunset = Some(now - Duration::days(1));
+        let mut deleted = image("fedora", vec![flavors[0].id]);
+        deleted.deleted = true;
+
+        let matrix = build_matrix(now, &flavors, &[current, old, deleted]);
+
+        assert_eq!(matrix.images.len(), 2);
+        assert_eq!(
+            matrix.cells,
+            vec![
+                vec![Compatibility::Supported, Compatibility::Sunset],
+                vec![Compatibility::Supported, Compatibility::Unsupported],
+            ]
+        );
+    }
+}
@@ -0,0 +1,336 @@
+//! Periodic health checks of booked hosts during the active phase of their booking,
+//! and the availability (SLA) summaries built from them
+//!
+//! A check passes when the BMC reports the host as powered on and the host answers
+//! a ping. Checks shortly after a power action or reimage requested through LaaS are
+//! excused, since the host is expected to be down for a bit. Each check also reads the
+//! BMC sensors, and opens a ticket for the host if any are past a critical threshold.
+
+use common::prelude::{
+    anyhow,
+    chrono::{DateTime, Duration, Utc},
+    serde_json, tokio, tracing,
+};
+use config::settings;
+use dal::{new_client, AsEasyTransaction, DBTable, EasyTransaction, FKey, NewRow};
+use models::{
+    dashboard::{Aggregate, Instance, InstanceHealthCheck, LifeCycleState},
+    inventory::{Host, TicketKind},
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    deploy_booking::{
+        bmc::{Bmc, SensorReading},
+        set_host_power_state::{get_host_power_state, HostConfig, PowerState},
+    },
+    resource_management::tickets::report_host_failure,
+};
+
+/// Key in the instance metadata holding when checks stop being excused
+const EXCUSED_UNTIL: &str = "health_excused_until";
+
+/// A reimage takes far longer than a power action, so failing checks are excused for longer
+pub const REIMAGE_GRACE: Duration = Duration::hours(3);
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, PartialEq)]
+pub struct SlaSummary {
+    pub checks: usize,
+    /// Checks that weren't excused by a requested power action or reimage
+    pub counted_checks: usize,
+    pub passed_checks: usize,
+    /// Fraction of counted checks that passed, if any were counted
+    pub availability: Option<f64>,
+    pub unplanned_reboots: usize,
+    pub first_check: Option<String>,
+    pub last_check: Option<String>,
+}
+
+pub fn summarize<'a>(checks: impl IntoIterator<Item = &'a InstanceHealthCheck>) -> SlaSummary {
+    let mut summary = SlaSummary {
+        checks: 0,
+        counted_checks: 0,
+        passed_checks: 0,
+        availability: None,
+        unplanned_reboots: 0,
+        first_check: None,
+        last_check: None,
+    };
+    let (mut first, mut last): (Option<DateTime<Utc>>, Option<DateTime<Utc>>) = (None, None);
+
+    for check in checks {
+        summary.checks += 1;
+        first = Some(first.map_or(check.time, |f| f.min(check.time)));
+        last = Some(last.map_or(check.time, |l| l.max(check.time)));
+
+        if check.unplanned_reboot {
+            summary.unplanned_reboots += 1;
+        }
+
+        // an excused check that passed anyway still shows the host was up
+        if !check.excused || check.passed() {
+            summary.counted_checks += 1;
+            if check.passed() {
+                summary.passed_checks += 1;
+            }
+        }
+    }
+
+    if summary.counted_checks > 0 {
+        summary.availability = Some(summary.passed_checks as f64 / summary.counted_checks as f64);
+    }
+    summary.first_check = first.map(|t| t.to_rfc2822());
+    summary.last_check = last.map(|t| t.to_rfc2822());
+
+    summary
+}
+
+/// Keeps failing checks of the instance from counting against availability for `grace`
+pub async fn excuse_instance(
+    t: &mut EasyTransaction<'_>,
+    instance: FKey<Instance>,
+    grace: Duration,
+) -> Result<(), anyhow::Error> {
+    let mut instance = instance.get(t).await?;
+    let until = Utc::now() + grace;
+
+    let current = excused_until(&instance);
+    if current.is_some_and(|c| c > until) {
+        return Ok(());
+    }
+
+    instance
+        .metadata
+        .insert(EXCUSED_UNTIL.to_owned(), serde_json::to_value(until)?);
+    instance.update(t).await?;
+
+    Ok(())
+}
+
+/// Excuses the instance for the configured grace period after a power action
+pub async fn excuse_power_action(
+    t: &mut EasyTransaction<'_>,
+    instance: FKey<Instance>,
+) -> Result<(), anyhow::Error> {
+    let grace = Duration::minutes(settings().health_checks.power_action_grace_minutes);
+
+    excuse_instance(t, instance, grace).await
+}
+
+fn excused_until(instance: &Instance) -> Option<DateTime<Utc>> {
+    instance
+        .metadata
+        .get(EXCUSED_UNTIL)
+        .and_then(|v| serde_json::from_value(v.clone()).ok())
+}
+
+async fn ping(fqdn: &str) -> bool {
+    tokio::process::Command::new("ping")
+        .args(["-c", "1", "-W", "5", "-n", "-q", fqdn])
+        .kill_on_drop(true)
+        .output()
+        .await
+        .is_ok_and(|o| o.status.success())
+}
+
+/// Whether the BMC reports the host as powered on and it answers a ping
+pub async fn host_is_up(host: &Host) -> Result<bool, anyhow::Error> {
+    let bmc = HostConfig::try_from(host)?;
+    let powered_on = get_host_power_state(&bmc).await? == PowerState::On;
+
+    Ok(powered_on && ping(&host.fqdn).await)
+}
+
+/// Sensors the BMC reports as past their critical or non-recoverable thresholds
+fn alarms(readings: &[SensorReading]) -> Vec<String> {
+    readings
+        .iter()
+        .filter(|r| r.status.is_alarm())
+        .map(|r| format!("{} reads {} ({})", r.name, r.reading, r.status.code()))
+        .collect()
+}
+
+async fn sensor_alarms(config: &HostConfig) -> Result<Vec<String>, anyhow::Error> {
+    Ok(alarms(&config.bmc().sensors().await?))
+}
+
+/// Checks the host of one instance, only holding a transaction while reading and recording,
+/// not while waiting on the BMC or the ping
+async fn check_instance(instance: &Instance) -> Result<(), anyhow::Error> {
+    let Some(host) = instance.linked_host else {
+        return Ok(());
+    };
+
+    let mut client = new_client().await?;
+    let mut transaction = client.easy_transaction().await?;
+    let host = host.get(&mut transaction).await?.into_inner();
+    transaction.commit().await?;
+
+    let bmc = HostConfig::try_from(&host)?;
+
+    match sensor_alarms(&bmc).await {
+        Ok(alarms) if !alarms.is_empty() => {
+            report_host_failure(
+                host.id,
+                TicketKind::SensorThreshold,
+                format!("The BMC reported: {}", alarms.join(", ")),
+            )
+            .await
+        }
+        Ok(_) => (),
+        Err(e) => tracing::warn!("Couldn't read sensors of {}: {e:?}", host.server_name),
+    }
+
+    let powered_on = match get_host_power_state(&bmc).await {
+        Ok(state) => state == PowerState::On,
+        Err(e) => {
+            // the BMC being unreachable says nothing about whether the host itself is up
+            tracing::warn!(
+                "Couldn't get power state of {} for health check: {e:?}",
+                host.server_name
+            );
+            return Ok(());
+        }
+    };
+    let reachable = powered_on && ping(&host.fqdn).await;
+
+    let mut transaction = client.easy_transaction().await?;
+    let now = Utc::now();
+    let excused = excused_until(instance).is_some_and(|until| until > now);
+    let previous = InstanceHealthCheck::latest_for_instance(&mut transaction, instance.id).await?;
+
+    let mut check = InstanceHealthCheck {
+        id: FKey::new_id_dangling(),
+        instance: instance.id,
+        aggregate: instance.aggregate,
+        time: now,
+        powered_on,
+        reachable,
+        excused,
+        unplanned_reboot: false,
+    };
+    check.unplanned_reboot = !excused && !check.passed() && previous.is_some_and(|p| p.passed());
+
+    if check.unplanned_reboot {
+        tracing::warn!(
+            "Host {} of instance {:?} went down without a requested power action",
+            host.server_name,
+            instance.id
+        );
+    }
+
+    NewRow::new(check).insert(&mut transaction).await?;
+    transaction.commit().await?;
+
+    Ok(())
+}
+
+/// Checks every host of every active booking once
+pub async fn check_active_instances() -> Result<(), anyhow::Error> {
+    let mut client = new_client().await?;
+    let mut transaction = client.easy_transaction().await?;
+
+    let aggregates = Aggregate::select()
+        .where_field("lifecycle_state")
+        .equals(LifeCycleState::Active)
+        .run(&mut transaction)
+        .await?;
+
+    let mut instances = Vec::new();
+    for agg in aggregates {
+        instances.extend(agg.instances(&mut transaction).await?);
+    }
+
+    transaction.commit().await?;
+
+    // each instance is checked on its own, so one that fails doesn't undo the checks of the others
+    for instance in instances {
+        if let Err(e) = check_instance(&instance).await {
+            tracing::error!("Health check of instance {:?} failed: {e:?}", instance.id);
+        }
+    }
+
+    Ok(())
+}
+
+/// Runs health checks forever, unless they've been disabled
+pub async fn entry() {
+    let config = settings().health_checks.clone();
+    if !config.enabled {
+        tracing::info!("Health checks are disabled, not tracking instance availability");
+        return;
+    }
+
+    let mut interval =
+        tokio::time::interval(std::time::Duration::from_secs(config.interval_minutes * 60));
+
+    loop {
+        interval.tick().await;
+
+        if let Err(e) = check_active_instances().await {
+            tracing::error!("Couldn't run health checks: {e:?}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::deploy_booking::bmc::ipmi::parse_sdr;
+
+    fn check(
+        minute: i64,
+        passed: bool,
+        excused: bool,
+        unplanned_reboot: bool,
+    ) -> InstanceHealthCheck {
+        InstanceHealthCheck {
+            id: FKey::new_id_dangling(),
+            instance: FKey::new_id_dangling(),
+            aggregate: FKey::new_id_dangling(),
+            time: DateTime::<Utc>::UNIX_EPOCH + Duration::minutes(minute),
+            powered_on: true,
+            reachable: passed,
+            excused,
+            unplanned_reboot,
+        }
+    }
+
+    #[test]
+    fn test_summarize() {
+        let checks = [
+            check(0, true, false, false),
+            check(10, false, false, true),
+            check(20, false, true, false),
+            check(30, true, true, false),
+            check(40, true, false, false),
+        ];
+
+        let summary = summarize(&checks);
+
+        assert_eq!(summary.checks, 5);
+        assert_eq!(summary.counted_checks, 4);
+        assert_eq!(summary.passed_checks, 3);
+        assert_eq!(summary.availability, Some(0.75));
+        assert_eq!(summary.unplanned_reboots, 1);
+
+        assert_eq!(summarize(&[]).availability, None);
+    }
+
+    #[test]
+    fn test_parse_sensor_alarms() {
+        let sdr = "CPU1 Temp        | 45 degrees C      | ok\n\
+                   CPU2 Temp        | 98 degrees C      | cr\n\
+                   PS2 Status       | 0x00              | nr\n\
+                   Fan3             | no reading        | ns\n";
+
+        assert_eq!(
+            alarms(&parse_sdr(sdr)),
+            vec![
+                "CPU2 Temp reads 98 degrees C (cr)".to_owned(),
+                "PS2 Status reads 0x00 (nr)".to_owned(),
+            ]
+        );
+    }
+}
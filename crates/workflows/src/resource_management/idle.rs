@@ -0,0 +1,403 @@
+//! Finding bookings whose hosts sit unused, and asking their owners to keep or release them
+//!
+//! Idle hardware is the biggest drain on what the lab can offer, so every few hours each
+//! active booking older than the configured number of days is looked at over that many
+//! days. A host counts as idle if every health check found it powered off, or if it runs
+//! the telemetry agent and every sample showed next to no CPU use or network traffic.
+//! Hosts that are powered on without telemetry can't be told apart from busy ones, so they
+//! are never counted as idle.
+//!
+//! The owner of a booking whose hosts are all idle is mailed links to keep or release it,
+//! which carry a token rather than needing a login. Bookings that are still idle after
+//! several asks in a row are flagged to the admins.
+
+use common::prelude::{
+    anyhow,
+    chrono::{DateTime, Duration, Utc},
+    rand::{self, distributions::Alphanumeric, Rng},
+    tokio, tracing,
+};
+use config::{settings, IdleBookingConfig};
+use dal::{new_client, AsEasyTransaction, DBTable, EasyTransaction, FKey, NewRow};
+use models::dashboard::{
+    Aggregate, IdleAnswer, IdleNudge, InstanceHealthCheck, LifeCycleState, TelemetrySample,
+};
+use notifications::{booking_idle, email::send_to_admins, Env, IdleBookingInfo};
+
+const TOKEN_LENGTH: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Activity {
+    Idle,
+    Busy,
+    /// Nothing says either way, like a host that is on but doesn't run the telemetry agent
+    Unknown,
+}
+
+/// How active a host was over the window, from its health checks and telemetry samples
+fn host_activity(
+    checks: &[InstanceHealthCheck],
+    samples: &[TelemetrySample],
+    config: &IdleBookingConfig,
+) -> Activity {
+    if !checks.is_empty() && checks.iter().all(|c| !c.powered_on) {
+        return Activity::Idle;
+    }
+
+    if samples.is_empty() {
+        return Activity::Unknown;
+    }
+
+    let quiet = samples.iter().all(|s| {
+        s.cpu_percent <= config.cpu_percent
+            && s.network_rx_bytes_per_sec + s.network_tx_bytes_per_sec
+                <= config.network_bytes_per_sec
+    });
+
+    match quiet {
+        true => Activity::Idle,
+        false => Activity::Busy,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum NextStep {
+    Wait,
+    Nudge { streak: i32, flag: bool },
+}
+
+/// What to do about a booking that is idle now, given the last time its owner was asked about it
+fn next_step(last: Option<&IdleNudge>, now: DateTime<Utc>, config: &IdleBookingConfig) -> NextStep {
+    let window = Duration::days(config.idle_days);
+
+    let streak = match last {
+        None => 1,
+        Some(last) => {
+            let asked_recently = last.sent > now - window;
+            let kept_recently = last.answer == Some(IdleAnswer::Keep)
+                && last.answered.is_some_and(|a| a > now - window);
+            // a release that didn't end the booking is for the admins to look into, not the owner
+            let released = last.answer == Some(IdleAnswer::Release);
+
+            if asked_recently || kept_recently || released {
+                return NextStep::Wait;
+            }
+
+            // a booking that was busy in between wouldn't have been asked for a while
+            match last.sent > now - window * 2 {
+                true => last.streak + 1,
+                false => 1,
+            }
+        }
+    };
+
+    NextStep::Nudge {
+        streak,
+        flag: streak == config.flag_after_nudges,
+    }
+}
+
+/// How active the hosts of `agg` were over the configured window, Unknown if it has none
+pub async fn booking_activity(
+    t: &mut EasyTransaction<'_>,
+    agg: &Aggregate,
+    config: &IdleBookingConfig,
+) -> Result<Activity, anyhow::Error> {
+    let since = Utc::now() - Duration::days(config.idle_days);
+    let samples = TelemetrySample::for_aggregate(t, agg.id, since).await?;
+
+    let mut activities = Vec::new();
+    for instance in agg.instances(t).await? {
+        let checks = InstanceHealthCheck::for_instance_since(t, instance.id, since)
+            .await?
+            .into_iter()
+            .map(|c| c.into_inner())
+            .collect::<Vec<_>>();
+        let samples = samples
+            .iter()
+            .filter(|s| s.instance == instance.id)
+            .map(|s| (**s).clone())
+            .collect::<Vec<_>>();
+
+        activities.push(host_activity(&checks, &samples, config));
+    }
+
+    Ok(match activities.as_slice() {
+        [] => Activity::Unknown,
+        a if a.iter().all(|a| *a == Activity::Idle) => Activity::Idle,
+        a if a.contains(&Activity::Busy) => Activity::Busy,
+        _ => Activity::Unknown,
+    })
+}
+
+fn answer_url(token: &str, answer: IdleAnswer) -> String {
+    let answer = match answer {
+        IdleAnswer::Keep => "keep",
+        IdleAnswer::Release => "release",
+    };
+
+    format!(
+        "{}/booking/idle/{token}/{answer}",
+        settings().web.external_url.trim_end_matches('/')
+    )
+}
+
+async fn nudge(
+    t: &mut EasyTransaction<'_>,
+    agg: &Aggregate,
+    streak: i32,
+    flag: bool,
+    config: &IdleBookingConfig,
+) -> Result<(), anyhow::Error> {
+    let name = agg
+        .metadata
+        .display_name
+        .clone()
+        .or(agg.metadata.booking_id.clone())
+        .unwrap_or_else(|| agg.id.into_id().to_string());
+
+    let Some(owner) = agg.metadata.owner.clone() else {
+        tracing::warn!("Booking {name} is idle, but has no owner to ask about it");
+        return Ok(());
+    };
+
+    let token: String = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(TOKEN_LENGTH)
+        .map(char::from)
+        .collect();
+
+    NewRow::new(IdleNudge {
+        id: FKey::new_id_dangling(),
+        aggregate: agg.id,
+        token: token.clone(),
+        sent: Utc::now(),
+        streak,
+        flagged: flag,
+        answer: None,
+        answered: None,
+    })
+    .insert(t)
+    .await?;
+
+    let project = agg.lab.get(t).await?.name.clone();
+    let info = IdleBookingInfo {
+        booking: name.clone(),
+        purpose: agg.metadata.purpose.clone().unwrap_or_default(),
+        idle_days: config.idle_days,
+        hosts: agg
+            .instances(t)
+            .await?
+            .into_iter()
+            .map(|i| i.config.hostname.clone())
+            .collect(),
+        keep_url: answer_url(&token, IdleAnswer::Keep),
+        release_url: answer_url(&token, IdleAnswer::Release),
+        dashboard_url: settings()
+            .projects
+            .get(project.as_str())
+            .map(|p| p.dashboard_url.clone())
+            .unwrap_or_default(),
+    };
+
+    if let Err(errors) = booking_idle(&Env { project }, &owner, &info).await {
+        tracing::error!("Couldn't ask {owner} about idle booking {name}: {errors:?}");
+    }
+
+    if flag {
+        send_to_admins(format!(
+            "Booking {name} (aggregate {}) of {owner} has been idle through {streak} asks in a row \
+            and may need to be reclaimed",
+            agg.id.into_id()
+        ))
+        .await;
+    }
+
+    Ok(())
+}
+
+/// Looks at every active booking once, asking the owners of idle ones about them
+pub async fn check_idle_bookings(config: &IdleBookingConfig) -> Result<(), anyhow::Error> {
+    let mut client = new_client().await?;
+    let mut transaction = client.easy_transaction().await?;
+
+    let now = Utc::now();
+    let aggregates = Aggregate::select()
+        .where_field("lifecycle_state")
+        .equals(LifeCycleState::Active)
+        .run(&mut transaction)
+        .await?;
+
+    for agg in aggregates {
+        // a booking that just started hasn't had the chance to be idle for long enough
+        if agg
+            .metadata
+            .start
+            .map_or(true, |s| s > now - Duration::days(config.idle_days))
+        {
+            continue;
+        }
+
+        match booking_activity(&mut transaction, &agg, config).await {
+            Ok(Activity::Idle) => (),
+            Ok(_) => continue,
+            Err(e) => {
+                tracing::error!("Couldn't work out whether {:?} is idle: {e:?}", agg.id);
+                continue;
+            }
+        }
+
+        let last = IdleNudge::latest_for_aggregate(&mut transaction, agg.id).await?;
+        if let NextStep::Nudge { streak, flag } = next_step(last.as_deref(), now, config) {
+            nudge(&mut transaction, &agg, streak, flag, config).await?;
+        }
+    }
+
+    transaction.commit().await?;
+
+    Ok(())
+}
+
+/// Looks for idle bookings forever, unless that has been disabled
+pub async fn entry() {
+    let config = settings().idle_bookings.clone();
+    if !config.enabled {
+        tracing::info!("Idle booking detection is disabled");
+        return;
+    }
+
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(
+        config.interval_hours * 60 * 60,
+    ));
+
+    loop {
+        interval.tick().await;
+
+        if let Err(e) = check_idle_bookings(&config).await {
+            tracing::error!("Couldn't check for idle bookings: {e:?}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(powered_on: bool) -> InstanceHealthCheck {
+        InstanceHealthCheck {
+            id: FKey::new_id_dangling(),
+            instance: FKey::new_id_dangling(),
+            aggregate: FKey::new_id_dangling(),
+            time: Utc::now(),
+            powered_on,
+            reachable: powered_on,
+            excused: false,
+            unplanned_reboot: false,
+        }
+    }
+
+    fn sample(cpu_percent: f64, network: f64) -> TelemetrySample {
+        TelemetrySample {
+            id: FKey::new_id_dangling(),
+            instance: FKey::new_id_dangling(),
+            aggregate: FKey::new_id_dangling(),
+            time: Utc::now(),
+            cpu_percent,
+            memory_percent: 20.0,
+            disk_percent: 20.0,
+            network_rx_bytes_per_sec: network,
+            network_tx_bytes_per_sec: network,
+        }
+    }
+
+    fn nudge(days_ago: i64, streak: i32, answer: Option<IdleAnswer>) -> IdleNudge {
+        let sent = Utc::now() - Duration::days(days_ago);
+        IdleNudge {
+            id: FKey::new_id_dangling(),
+            aggregate: FKey::new_id_dangling(),
+            token: String::new(),
+            sent,
+            streak,
+            flagged: false,
+            answer,
+            answered: answer.map(|_| sent),
+        }
+    }
+
+    #[test]
+    fn test_host_activity() {
+        let config = IdleBookingConfig::default();
+
+        assert_eq!(
+            host_activity(&[check(false), check(false)], &[], &config),
+            Activity::Idle
+        );
+        assert_eq!(
+            host_activity(&[check(false), check(true)], &[], &config),
+            Activity::Unknown
+        );
+        assert_eq!(
+            host_activity(&[check(true)], &[sample(1.0, 100.0)], &config),
+            Activity::Idle
+        );
+        assert_eq!(
+            host_activity(
+                &[check(true)],
+                &[sample(1.0, 100.0), sample(80.0, 100.0)],
+                &config
+            ),
+            Activity::Busy
+        );
+        // quiet CPU doesn't make up for a busy network
+        assert_eq!(
+            host_activity(&[], &[sample(1.0, 1_000_000.0)], &config),
+            Activity::Busy
+        );
+        assert_eq!(host_activity(&[], &[], &config), Activity::Unknown);
+    }
+
+    #[test]
+    fn test_next_step() {
+        let config = IdleBookingConfig::default();
+        let now = Utc::now();
+
+        assert_eq!(
+            next_step(None, now, &config),
+            NextStep::Nudge {
+                streak: 1,
+                flag: false
+            }
+        );
+        assert_eq!(
+            next_step(Some(&nudge(1, 1, None)), now, &config),
+            NextStep::Wait
+        );
+        assert_eq!(
+            next_step(Some(&nudge(4, 2, None)), now, &config),
+            NextStep::Nudge {
+                streak: 3,
+                flag: true
+            }
+        );
+        // keeping it still counts towards the streak once the keep runs out
+        assert_eq!(
+            next_step(Some(&nudge(4, 1, Some(IdleAnswer::Keep))), now, &config),
+            NextStep::Nudge {
+                streak: 2,
+                flag: false
+            }
+        );
+        assert_eq!(
+            next_step(Some(&nudge(4, 1, Some(IdleAnswer::Release))), now, &config),
+            NextStep::Wait
+        );
+        // busy in between starts the streak over
+        assert_eq!(
+            next_step(Some(&nudge(30, 2, None)), now, &config),
+            NextStep::Nudge {
+                streak: 1,
+                flag: false
+            }
+        );
+    }
+}
@@ -0,0 +1,371 @@
+//! Retiring images that bookings shouldn't use anymore
+//!
+//! An admin deprecates an image, optionally naming a replacement and a sunset date.
+//! Owners of templates using it, and users of bookings with hosts running it, are
+//! told right away. Once the sunset date passes, new bookings of templates that
+//! still use the image are refused, pointing at the replacement.
+//!
+//! Projects can also limit which images their members book with, and pick the image
+//! each flavor gets by default, through `image_policy` in their config.
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use common::prelude::{
+    anyhow,
+    chrono::{DateTime, Utc},
+    tracing,
+};
+use config::ImagePolicyConfig;
+use dal::{new_client, AsEasyTransaction, DBTable, EasyTransaction, FKey};
+use models::{
+    dashboard::{Aggregate, Image, ImageChange, Template},
+    inventory::{Flavor, Lab},
+};
+use notifications::{email::send_to_admins, image_deprecated, Env, ImageDeprecationInfo};
+
+#[derive(Debug, Clone)]
+pub struct Deprecation {
+    pub image: FKey<Image>,
+    pub sunset: Option<DateTime<Utc>>,
+    pub replacement: Option<FKey<Image>>,
+    pub note: String,
+    pub author: String,
+}
+
+/// Where one user, within one lab, uses an image
+#[derive(Debug, Default)]
+struct Usage {
+    templates: BTreeSet<String>,
+    bookings: BTreeSet<String>,
+}
+
+/// Why a new booking of `template` can't go ahead, one reason per retired image it uses
+pub async fn sunset_violations(
+    t: &mut EasyTransaction<'_>,
+    template: &Template,
+) -> Result<Vec<String>, anyhow::Error> {
+    let now = Utc::now();
+    let mut seen = HashSet::new();
+    let mut violations = Vec::new();
+
+    for host in template.hosts.iter() {
+        if !seen.insert(host.image.into_id()) {
+            continue;
+        }
+
+        let image = host.image.get(t).await?;
+        if !image.sunset_passed(now) {
+            continue;
+        }
+
+        let mut reason = format!(
+            "image {} ({}) was retired on {}",
+            image.name,
+            image.id.into_id(),
+            image.sunset.unwrap().to_rfc2822()
+        );
+        match image.replacement {
+            Some(r) => {
+                let replacement = r.get(t).await?;
+                reason.push_str(&format!(
+                    ", use image {} ({}) instead",
+                    replacement.name,
+                    replacement.id.into_id()
+                ));
+            }
+            None => reason.push_str(", pick another image for the template"),
+        }
+
+        violations.push(reason);
+    }
+
+    Ok(violations)
+}
+
+fn policy(project: &str) -> ImagePolicyConfig {
+    config::settings()
+        .projects
+        .get(project)
+        .map(|p| p.image_policy.clone())
+        .unwrap_or_default()
+}
+
+/// Narrows `images` down to what `policy` allows, along with the one it picks for the flavor named `flavor`
+fn apply_policy(
+    policy: &ImagePolicyConfig,
+    flavor: &str,
+    images: Vec<Image>,
+) -> (Vec<Image>, Option<FKey<Image>>) {
+    let images: Vec<Image> = images
+        .into_iter()
+        .filter(|i| policy.allows(&i.name))
+        .collect();
+
+    let default = policy
+        .defaults
+        .get(flavor)
+        .and_then(|name| images.iter().find(|i| &i.name == name))
+        .map(|i| i.id);
+
+    (images, default)
+}
+
+/// The public images members of `project` may book hosts of `flavor` with,
+/// and the one they get by default if the project picks one
+pub async fn project_images(
+    t: &mut EasyTransaction<'_>,
+    project: &str,
+    flavor: &Flavor,
+) -> Result<(Vec<Image>, Option<FKey<Image>>), anyhow::Error> {
+    let images = Image::images_for_flavor(t, flavor.id, None).await?;
+
+    Ok(apply_policy(&policy(project), &flavor.name, images))
+}
+
+/// Every public image the policy of `project` lets its members book with, by name
+pub async fn allowed_images(
+    t: &mut EasyTransaction<'_>,
+    project: &str,
+) -> Result<Vec<Image>, anyhow::Error> {
+    let policy = policy(project);
+
+    Ok(Image::select()
+        .where_field("deleted")
+        .equals(false)
+        .where_field("public")
+        .equals(true)
+        .order_by("name", false)
+        .run(t)
+        .await?
+        .into_iter()
+        .map(|i| i.into_inner())
+        .filter(|i| policy.allows(&i.name))
+        .collect())
+}
+
+/// Why members of `project` can't book hosts running `images`, one reason per image its policy doesn't allow
+pub async fn policy_violations(
+    t: &mut EasyTransaction<'_>,
+    project: &str,
+    images: &[FKey<Image>],
+) -> Result<Vec<String>, anyhow::Error> {
+    let policy = policy(project);
+    let mut seen = HashSet::new();
+    let mut violations = Vec::new();
+
+    for id in images {
+        if !seen.insert(id.into_id()) {
+            continue;
+        }
+
+        let image = id.get(t).await?;
+        if !policy.allows(&image.name) {
+            violations.push(format!(
+                "project {project} doesn't allow image {} ({})",
+                image.name,
+                image.id.into_id()
+            ));
+        }
+    }
+
+    Ok(violations)
+}
+
+/// Marks an image deprecated and tells everyone using it, returning who was told
+pub async fn deprecate(deprecation: Deprecation) -> Result<Vec<String>, anyhow::Error> {
+    let mut client = new_client().await?;
+    let mut transaction = client.easy_transaction().await?;
+    let now = Utc::now();
+
+    let mut image = deprecation.image.get(&mut transaction).await?;
+
+    let replacement = match deprecation.replacement {
+        Some(r) if r == deprecation.image => {
+            return Err(anyhow::Error::msg("an image can't replace itself"))
+        }
+        Some(r) => {
+            let replacement = r.get(&mut transaction).await?.into_inner();
+            if replacement.deleted || replacement.sunset_passed(now) {
+                return Err(anyhow::Error::msg(format!(
+                    "replacement image {} is itself retired",
+                    replacement.name
+                )));
+            }
+            Some(replacement)
+        }
+        None => None,
+    };
+
+    let mut change = String::from("Deprecated");
+    if let Some(sunset) = deprecation.sunset {
+        change.push_str(&format!(", sunset on {}", sunset.to_rfc2822()));
+    }
+    if let Some(r) = &replacement {
+        change.push_str(&format!(", replaced by {}", r.name));
+    }
+    if !deprecation.note.trim().is_empty() {
+        change.push_str(&format!(": {}", deprecation.note.trim()));
+    }
+
+    image.deprecated = image.deprecated.or(Some(now));
+    image.sunset = deprecation.sunset;
+    image.replacement = deprecation.replacement;
+    image.changelog.push(ImageChange {
+        time: now,
+        author: deprecation.author.clone(),
+        note: change.clone(),
+    });
+    image.update(&mut transaction).await?;
+
+    // keyed by (username, lab)
+    let mut usages: HashMap<(String, FKey<Lab>), Usage> = HashMap::new();
+
+    for template in Template::get_all(&mut transaction).await? {
+        if !template.hosts.iter().any(|h| h.image == deprecation.image) {
+            continue;
+        }
+
+        if let Some(owner) = template.owner.clone() {
+            usages
+                .entry((owner, template.lab))
+                .or_default()
+                .templates
+                .insert(template.name.clone());
+        }
+    }
+
+    let aggregates = Aggregate::select()
+        .where_field("deleted")
+        .equals(false)
+        .run(&mut transaction)
+        .await?;
+    for agg in aggregates {
+        if agg.state.ended() {
+            continue;
+        }
+
+        let uses_image = agg
+            .instances(&mut transaction)
+            .await?
+            .iter()
+            .any(|i| i.config.image == deprecation.image);
+        if !uses_image {
+            continue;
+        }
+
+        let booking = agg
+            .metadata
+            .booking_id
+            .clone()
+            .unwrap_or(agg.id.into_id().to_string());
+        let users: BTreeSet<String> = agg
+            .metadata
+            .owner
+            .iter()
+            .chain(agg.users.iter())
+            .cloned()
+            .collect();
+        for username in users {
+            usages
+                .entry((username, agg.lab))
+                .or_default()
+                .bookings
+                .insert(booking.clone());
+        }
+    }
+
+    let mut notices = Vec::new();
+    for ((username, lab), usage) in usages {
+        let project = lab.get(&mut transaction).await?.name.clone();
+        notices.push((username, project, usage));
+    }
+
+    transaction.commit().await?;
+
+    let mut notified = Vec::new();
+    for (username, project, usage) in notices {
+        let info = ImageDeprecationInfo {
+            image: image.name.clone(),
+            image_id: image.id.into_id().to_string(),
+            note: deprecation.note.clone(),
+            sunset: deprecation.sunset,
+            replacement: replacement
+                .as_ref()
+                .map(|r| (r.name.clone(), r.id.into_id().to_string())),
+            templates: usage.templates.into_iter().collect(),
+            bookings: usage.bookings.into_iter().collect(),
+            dashboard_url: config::settings()
+                .projects
+                .get(project.as_str())
+                .map(|p| p.dashboard_url.clone())
+                .unwrap_or_default(),
+        };
+
+        match image_deprecated(&Env { project }, &username, &info).await {
+            Ok(()) => notified.push(username),
+            Err(errors) => {
+                tracing::error!("Couldn't tell {username} about deprecated image: {errors:?}")
+            }
+        }
+    }
+
+    send_to_admins(format!(
+        "Image {} was deprecated by {} ({change}), {} users were notified",
+        image.name,
+        deprecation.author,
+        notified.len()
+    ))
+    .await;
+
+    Ok(notified)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(name: &str) -> Image {
+        Image {
+            id: FKey::new_id_dangling(),
+            owner: "admin".to_owned(),
+            name: name.to_owned(),
+            deleted: false,
+            cobbler_name: name.to_owned(),
+            public: true,
+            flavors: vec![],
+            deprecated: None,
+            sunset: None,
+            replacement: None,
+            changelog: vec![],
+        }
+    }
+
+    #[test]
+    fn test_apply_policy() {
+        let images = vec![image("ubuntu"), image("fedora"), image("windows")];
+        let names = |images: &[Image]| images.iter().map(|i| i.name.clone()).collect::<Vec<_>>();
+
+        let open = ImagePolicyConfig::default();
+        let (allowed, default) = apply_policy(&open, "x86", images.clone());
+        assert_eq!(names(&allowed), vec!["ubuntu", "fedora", "windows"]);
+        assert_eq!(default, None);
+
+        let policy = ImagePolicyConfig {
+            allow: Some(vec!["ubuntu".to_owned(), "fedora".to_owned()]),
+            deny: vec!["fedora".to_owned()],
+            defaults: [
+                ("x86".to_owned(), "ubuntu".to_owned()),
+                ("arm".to_owned(), "windows".to_owned()),
+            ]
+            .into_iter()
+            .collect(),
+        };
+        let (allowed, default) = apply_policy(&policy, "x86", images.clone());
+        assert_eq!(names(&allowed), vec!["ubuntu"]);
+        assert_eq!(default, Some(images[0].id));
+
+        // a default the policy doesn't allow is no default at all
+        let (_, default) = apply_policy(&policy, "arm", images);
+        assert_eq!(default, None);
+    }
+}
@@ -0,0 +1,543 @@
+//! Edits to the host inventory that are applied all together or not at all
+//!
+//! A rack refresh touches many hosts at once. Every operation in a batch is checked and
+//! applied in order within one transaction, so later operations see the effects of earlier
+//! ones, and the batch is only committed if every operation in it went through. Otherwise
+//! nothing is changed and the problems with each operation are reported back.
+//!
+//! Imports too large to wait on can be run as a job with [`import`] instead.
+
+use common::prelude::anyhow;
+use dal::{new_client, AsEasyTransaction, DBTable, EasyTransaction, FKey, NewRow};
+use eui48::MacAddress;
+use models::{
+    allocator::{Allocation, AllocationReason, ResourceHandle, ResourceHandleInner},
+    inventory::{Arch, BmcProtocol, FailureDomain, Flavor, Host, Lab},
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+use crate::{
+    deploy_booking::set_host_power_state::validate_input,
+    resource_management::{
+        allocator::Allocator,
+        jobs::{JobHandle, JobReport},
+    },
+    utils::net::{validate_fqdn, validate_ip},
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct BulkHostRequest {
+    pub operations: Vec<HostOperation>,
+    /// Check the operations without keeping any of their changes
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum HostOperation {
+    Create(NewHost),
+    Update {
+        server_name: String,
+        changes: HostChanges,
+    },
+    /// Takes a host that isn't in use out of commission
+    Retire {
+        server_name: String,
+    },
+}
+
+impl HostOperation {
+    pub fn server_name(&self) -> &str {
+        match self {
+            Self::Create(host) => &host.server_name,
+            Self::Update { server_name, .. } | Self::Retire { server_name } => server_name,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct NewHost {
+    pub server_name: String,
+    pub lab: String,
+    pub flavor: String,
+    pub arch: String,
+    pub serial: String,
+    pub ipmi_fqdn: String,
+    pub ipmi_mac: String,
+    pub ipmi_user: String,
+    pub ipmi_pass: String,
+    pub fqdn: String,
+    #[serde(default)]
+    pub iol_id: String,
+    #[serde(default)]
+    pub projects: Vec<String>,
+    #[serde(default)]
+    pub sda_uefi_device: Option<String>,
+    #[serde(default)]
+    pub failure_domain: FailureDomain,
+    #[serde(default)]
+    pub bmc_protocol: BmcProtocol,
+    #[serde(default)]
+    pub funded_by: Option<String>,
+}
+
+/// The fields to change on a host, leaving out any that should stay as they are
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct HostChanges {
+    pub server_name: Option<String>,
+    pub flavor: Option<String>,
+    pub arch: Option<String>,
+    pub serial: Option<String>,
+    pub ipmi_fqdn: Option<String>,
+    pub ipmi_mac: Option<String>,
+    pub ipmi_user: Option<String>,
+    pub ipmi_pass: Option<String>,
+    pub fqdn: Option<String>,
+    pub iol_id: Option<String>,
+    pub projects: Option<Vec<String>>,
+    pub sda_uefi_device: Option<String>,
+    pub failure_domain: Option<FailureDomain>,
+    pub bmc_protocol: Option<BmcProtocol>,
+    /// An empty project leaves the host bookable by anyone
+    pub funded_by: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct BulkHostReport {
+    /// Whether the changes were kept, which only happens when every operation went through
+    pub applied: bool,
+    pub results: Vec<OperationResult>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct OperationResult {
+    /// Position of the operation in the request
+    pub index: usize,
+    pub server_name: String,
+    /// The host the operation went through for
+    pub host: Option<FKey<Host>>,
+    pub errors: Vec<String>,
+}
+
+impl BulkHostReport {
+    pub fn valid(&self) -> bool {
+        self.results.iter().all(|r| r.errors.is_empty())
+    }
+}
+
+impl JobReport for BulkHostReport {
+    fn succeeded(&self) -> bool {
+        self.valid()
+    }
+}
+
+/// Runs every operation in order, committing to `t` only if all of them went through
+///
+/// When run as part of `job` each operation is a step of it, and cancelling the job
+/// leaves the inventory as it was
+pub async fn apply(
+    t: &mut EasyTransaction<'_>,
+    request: BulkHostRequest,
+    job: Option<JobHandle>,
+) -> Result<BulkHostReport, anyhow::Error> {
+    let mut transaction = t.easy_transaction().await?;
+    let mut results = Vec::new();
+    let mut cancelled = false;
+
+    for (index, operation) in request.operations.into_iter().enumerate() {
+        let server_name = operation.server_name().to_owned();
+
+        if let Some(job) = job {
+            if !job.step(index, format!("applying {server_name}")).await? {
+                cancelled = true;
+                break;
+            }
+        }
+
+        // each operation gets its own savepoint so that a failed one doesn't poison the rest
+        let mut step = transaction.easy_transaction().await?;
+        let result = match operation {
+            HostOperation::Create(new) => create(&mut step, new).await,
+            HostOperation::Update {
+                server_name,
+                changes,
+            } => update(&mut step, server_name, changes).await,
+            HostOperation::Retire { server_name } => retire(&mut step, server_name).await,
+        };
+
+        results.push(match result {
+            Ok(host) => {
+                step.commit().await?;
+                OperationResult {
+                    index,
+                    server_name,
+                    host: Some(host),
+                    errors: vec![],
+                }
+            }
+            Err(errors) => {
+                step.rollback().await?;
+                OperationResult {
+                    index,
+                    server_name,
+                    host: None,
+                    errors,
+                }
+            }
+        });
+    }
+
+    let mut report = BulkHostReport {
+        applied: false,
+        results,
+    };
+
+    if report.valid() && !request.dry_run && !cancelled {
+        transaction.commit().await?;
+        report.applied = true;
+    } else {
+        transaction.rollback().await?;
+    }
+
+    Ok(report)
+}
+
+/// Applies `request` as `job`, for imports too large to wait on
+pub async fn import(
+    job: JobHandle,
+    request: BulkHostRequest,
+) -> Result<BulkHostReport, anyhow::Error> {
+    let total = request.operations.len();
+
+    let mut client = new_client().await?;
+    let mut transaction = client.easy_transaction().await?;
+
+    let report = apply(&mut transaction, request, Some(job)).await?;
+
+    transaction.commit().await?;
+
+    job.step(total, "done".to_owned()).await?;
+
+    Ok(report)
+}
+
+async fn create(t: &mut EasyTransaction<'_>, new: NewHost) -> Result<FKey<Host>, Vec<String>> {
+    let mut errors = Vec::new();
+
+    let lab = match Lab::get_by_name(t, new.lab.clone()).await {
+        Ok(Some(lab)) => Some(lab.id),
+        Ok(None) => {
+            errors.push(format!("no lab is named {}", new.lab));
+            None
+        }
+        Err(e) => {
+            errors.push(format!("couldn't look up lab {}: {e}", new.lab));
+            None
+        }
+    };
+
+    if Host::get_by_name(t, new.server_name.clone()).await.is_ok() {
+        errors.push(format!("a host named {} already exists", new.server_name));
+    }
+
+    let flavor = find_flavor(t, &new.flavor, &mut errors).await;
+    let arch = parse_arch(&new.arch, &mut errors);
+    let ipmi_mac = parse_mac(&new.ipmi_mac, &mut errors);
+
+    let (Some(lab), Some(flavor), Some(arch), Some(ipmi_mac)) = (lab, flavor, arch, ipmi_mac)
+    else {
+        return Err(errors);
+    };
+
+    let host = Host {
+        id: FKey::new_id_dangling(),
+        server_name: new.server_name,
+        arch,
+        flavor,
+        serial: new.serial,
+        ipmi_fqdn: new.ipmi_fqdn,
+        iol_id: new.iol_id,
+        ipmi_mac,
+        ipmi_user: new.ipmi_user,
+        ipmi_pass: new.ipmi_pass,
+        fqdn: new.fqdn,
+        projects: new.projects,
+        sda_uefi_device: new.sda_uefi_device,
+        failure_domain: new.failure_domain,
+        bmc_protocol: new.bmc_protocol,
+        funded_by: new.funded_by,
+    };
+
+    errors.extend(check_host(&host));
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    let id = NewRow::new(host)
+        .insert(t)
+        .await
+        .map_err(|e| vec![format!("couldn't save the host: {e}")])?;
+
+    ResourceHandle::add_resource(t, ResourceHandleInner::Host(id), lab)
+        .await
+        .map_err(|e| vec![format!("couldn't start tracking the host: {e}")])?;
+
+    Ok(id)
+}
+
+async fn update(
+    t: &mut EasyTransaction<'_>,
+    server_name: String,
+    changes: HostChanges,
+) -> Result<FKey<Host>, Vec<String>> {
+    let mut host = Host::get_by_name(t, server_name.clone())
+        .await
+        .map_err(|_| vec![format!("no host is named {server_name}")])?;
+
+    let mut errors = Vec::new();
+
+    if let Some(name) = changes.server_name {
+        if name != host.server_name && Host::get_by_name(t, name.clone()).await.is_ok() {
+            errors.push(format!(
+                "can't rename to {name}, a host by that name already exists"
+            ));
+        }
+        host.server_name = name;
+    }
+    if let Some(flavor) = changes.flavor {
+        if let Some(flavor) = find_flavor(t, &flavor, &mut errors).await {
+            host.flavor = flavor;
+        }
+    }
+    if let Some(arch) = changes.arch.and_then(|a| parse_arch(&a, &mut errors)) {
+        host.arch = arch;
+    }
+    if let Some(mac) = changes.ipmi_mac.and_then(|m| parse_mac(&m, &mut errors)) {
+        host.ipmi_mac = mac;
+    }
+    if let Some(serial) = changes.serial {
+        host.serial = serial;
+    }
+    if let Some(ipmi_fqdn) = changes.ipmi_fqdn {
+        host.ipmi_fqdn = ipmi_fqdn;
+    }
+    if let Some(ipmi_user) = changes.ipmi_user {
+        host.ipmi_user = ipmi_user;
+    }
+    if let Some(ipmi_pass) = changes.ipmi_pass {
+        host.ipmi_pass = ipmi_pass;
+    }
+    if let Some(fqdn) = changes.fqdn {
+        host.fqdn = fqdn;
+    }
+    if let Some(iol_id) = changes.iol_id {
+        host.iol_id = iol_id;
+    }
+    if let Some(projects) = changes.projects {
+        host.projects = projects;
+    }
+    if let Some(device) = changes.sda_uefi_device {
+        host.sda_uefi_device = Some(device);
+    }
+    if let Some(failure_domain) = changes.failure_domain {
+        host.failure_domain = failure_domain;
+    }
+    if let Some(bmc_protocol) = changes.bmc_protocol {
+        host.bmc_protocol = bmc_protocol;
+    }
+    if let Some(funded_by) = changes.funded_by {
+        host.funded_by = Some(funded_by).filter(|p| !p.is_empty());
+    }
+
+    errors.extend(check_host(&host));
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    host.update(t)
+        .await
+        .map_err(|e| vec![format!("couldn't save the host: {e}")])?;
+
+    Ok(host.id)
+}
+
+async fn retire(
+    t: &mut EasyTransaction<'_>,
+    server_name: String,
+) -> Result<FKey<Host>, Vec<String>> {
+    let host = Host::get_by_name(t, server_name.clone())
+        .await
+        .map_err(|_| vec![format!("no host is named {server_name}")])?;
+
+    let handle = ResourceHandle::handle_for_host(t, host.id)
+        .await
+        .map_err(|e| vec![format!("couldn't find how {server_name} is tracked: {e}")])?;
+
+    let live = Allocation::find(t, handle.id, false).await.map_err(|e| {
+        vec![format!(
+            "couldn't check whether {server_name} is in use: {e}"
+        )]
+    })?;
+
+    if let Some(allocation) = live.first() {
+        return Err(vec![match allocation.reason_started {
+            AllocationReason::ForRetiry => format!("{server_name} is already retired"),
+            AllocationReason::ForMaintenance => format!("{server_name} is in maintenance"),
+            AllocationReason::ForBooking => format!("{server_name} is in use by a booking"),
+        }]);
+    }
+
+    Allocator::instance()
+        .retire_host(t, host.id)
+        .await
+        .map_err(|e| vec![format!("couldn't retire the host: {e}")])?;
+
+    Ok(host.id)
+}
+
+async fn find_flavor(
+    t: &mut EasyTransaction<'_>,
+    name: &str,
+    errors: &mut Vec<String>,
+) -> Option<FKey<Flavor>> {
+    match Flavor::select()
+        .where_field("name")
+        .equals(name.to_owned())
+        .run(t)
+        .await
+    {
+        Ok(flavors) if !flavors.is_empty() => Some(flavors[0].id),
+        Ok(_) => {
+            errors.push(format!("no flavor is named {name}"));
+            None
+        }
+        Err(e) => {
+            errors.push(format!("couldn't look up flavor {name}: {e}"));
+            None
+        }
+    }
+}
+
+fn parse_arch(arch: &str, errors: &mut Vec<String>) -> Option<Arch> {
+    Arch::from_str(arch)
+        .map_err(|_| errors.push(format!("{arch} is not an architecture")))
+        .ok()
+}
+
+fn parse_mac(mac: &str, errors: &mut Vec<String>) -> Option<MacAddress> {
+    MacAddress::parse_str(mac)
+        .map_err(|e| errors.push(format!("{mac} is not a mac address: {e}")))
+        .ok()
+}
+
+/// Problems with the host's own fields, regardless of the rest of the inventory
+fn check_host(host: &Host) -> Vec<String> {
+    let mut errors = Vec::new();
+
+    if host.server_name.trim().is_empty() {
+        errors.push("the host needs a name".to_owned());
+    }
+    if host.fqdn.trim().is_empty() {
+        errors.push("the host needs an fqdn".to_owned());
+    }
+
+    for (field, value) in [
+        ("ipmi_fqdn", &host.ipmi_fqdn),
+        ("ipmi_user", &host.ipmi_user),
+        ("ipmi_pass", &host.ipmi_pass),
+    ] {
+        if validate_input(value).is_err() {
+            errors.push(format!(
+                "{field} can't be empty or contain control characters"
+            ));
+        }
+    }
+
+    if let Err(e) = validate_fqdn(&host.ipmi_fqdn) {
+        if !validate_ip(&host.ipmi_fqdn) {
+            errors.push(format!("ipmi_fqdn {} is invalid: {e}", host.ipmi_fqdn));
+        }
+    }
+
+    errors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::prelude::serde_json;
+
+    fn host() -> Host {
+        Host {
+            id: FKey::new_id_dangling(),
+            server_name: "hpe-1".to_owned(),
+            arch: Arch::X86_64,
+            flavor: FKey::new_id_dangling(),
+            serial: "CZ1234".to_owned(),
+            ipmi_fqdn: "hpe-1-ipmi.lab.example.com".to_owned(),
+            iol_id: String::new(),
+            ipmi_mac: MacAddress::parse_str("00:11:22:33:44:55").unwrap(),
+            ipmi_user: "admin".to_owned(),
+            ipmi_pass: "password".to_owned(),
+            fqdn: "hpe-1.lab.example.com".to_owned(),
+            projects: vec!["anuket".to_owned()],
+            sda_uefi_device: None,
+            failure_domain: FailureDomain::default(),
+            bmc_protocol: BmcProtocol::Ipmi,
+            funded_by: None,
+        }
+    }
+
+    #[test]
+    fn test_check_host() {
+        assert!(check_host(&host()).is_empty());
+
+        let mut by_ip = host();
+        by_ip.ipmi_fqdn = "10.0.0.5".to_owned();
+        assert!(check_host(&by_ip).is_empty());
+
+        let mut bad = host();
+        bad.server_name = " ".to_owned();
+        bad.ipmi_pass = "pass\nword".to_owned();
+        assert_eq!(check_host(&bad).len(), 2);
+    }
+
+    #[test]
+    fn test_parse() {
+        let mut errors = Vec::new();
+        assert!(parse_arch("aarch64", &mut errors).is_some());
+        assert!(parse_mac("00:11:22:33:44:55", &mut errors).is_some());
+        assert!(errors.is_empty());
+
+        assert!(parse_arch("sparc", &mut errors).is_none());
+        assert!(parse_mac("not-a-mac", &mut errors).is_none());
+        assert_eq!(errors.len(), 2);
+    }
+
+    #[test]
+    fn test_operations() {
+        let request: BulkHostRequest = serde_json::from_str(
+            r#"{"operations": [
+                {"op": "update", "server_name": "hpe-1", "changes": {"serial": "CZ9999"}},
+                {"op": "retire", "server_name": "hpe-2"}
+            ]}"#,
+        )
+        .unwrap();
+
+        assert!(!request.dry_run);
+        assert_eq!(request.operations[0].server_name(), "hpe-1");
+        assert!(matches!(
+            &request.operations[0],
+            HostOperation::Update { changes, .. }
+                if changes.serial.as_deref() == Some("CZ9999") && changes.fqdn.is_none()
+        ));
+        assert!(matches!(
+            &request.operations[1],
+            HostOperation::Retire { server_name } if server_name == "hpe-2"
+        ));
+    }
+}
@@ -1,11 +1,19 @@
-use common::prelude::rand::{self, seq::SliceRandom, Rng};
+use common::prelude::{
+    anyhow,
+    rand::{self, seq::SliceRandom, Rng},
+    tracing,
+};
 use dal::{new_client, AsEasyTransaction, FKey, ID};
 use models::inventory::Host;
+use schemars::JsonSchema;
 use tascii::prelude::*;
 
 use std::{process::Command, time::Duration};
 
-use crate::deploy_booking::reachable::WaitReachable;
+use crate::{
+    deploy_booking::{reachable::WaitReachable, set_host_power_state::HostConfig},
+    resource_management::jobs::{JobHandle, JobReport},
+};
 
 #[derive(Debug, Clone, Hash, Serialize, Deserialize)]
 pub struct CreateIPMIAccount {
@@ -227,3 +235,173 @@ pub fn generate_password(length: usize) -> String {
 
     s[0..length].to_owned()
 }
+
+/// How long generated BMC admin passwords are, which every BMC in the fleet accepts
+const ADMIN_PASSWORD_LENGTH: usize = 15;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct CredentialRotationReport {
+    /// Hosts whose BMC admin password was changed and saved
+    pub rotated: Vec<String>,
+    pub failed: Vec<RotationFailure>,
+    /// Hosts left alone because the job was cancelled first
+    pub skipped: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct RotationFailure {
+    pub host: String,
+    pub reason: String,
+}
+
+impl JobReport for CredentialRotationReport {
+    fn succeeded(&self) -> bool {
+        self.failed.is_empty() && self.skipped.is_empty()
+    }
+}
+
+/// The id of the user named `name`, from `ipmitool user list`
+///
+/// ipmitool lays the list out in fixed width columns, the name taking the 17 after the 4 of
+/// the id, which is the only way to tell a blank name from one like `true`
+pub fn find_user_id(user_list: &str, name: &str) -> Option<String> {
+    user_list.lines().skip(1).find_map(|line| {
+        let id = line.get(..4)?.trim();
+        let user = line.get(4..line.len().min(21))?.trim();
+        (user == name && id.parse::<u8>().is_ok()).then(|| id.to_owned())
+    })
+}
+
+async fn ipmitool(config: &HostConfig, args: &[&str]) -> Result<String, anyhow::Error> {
+    let output = tokio::process::Command::new("ipmitool")
+        .args([
+            "-I",
+            "lanplus",
+            "-C",
+            "3",
+            "-H",
+            &config.fqdn,
+            "-U",
+            &config.user,
+            "-P",
+            &config.password,
+        ])
+        .args(args)
+        .kill_on_drop(true)
+        .output()
+        .await?;
+
+    if !output.status.success() {
+        return Err(anyhow::Error::msg(format!(
+            "ipmitool {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Gives the admin account of the BMC of `host` a new password, returning the password the
+/// BMC now has, along with why the rotation failed if it did
+///
+/// A password is only returned if the BMC may have taken it, so that it can be
+/// saved rather than losing track of how to get into the BMC
+async fn rotate(host: &Host) -> (Option<String>, Result<(), String>) {
+    let old = match HostConfig::try_from(host) {
+        Ok(config) => config,
+        Err(e) => return (None, Err(format!("{e}"))),
+    };
+
+    let user_id = match ipmitool(&old, &["user", "list", "1"]).await {
+        Ok(list) => match find_user_id(&list, &old.user) {
+            Some(id) => id,
+            None => return (None, Err(format!("the BMC has no user named {}", old.user))),
+        },
+        Err(e) => return (None, Err(e.to_string())),
+    };
+
+    let password = generate_password(ADMIN_PASSWORD_LENGTH);
+    if let Err(e) = ipmitool(&old, &["user", "set", "password", &user_id, &password]).await {
+        return (None, Err(e.to_string()));
+    }
+
+    let new = HostConfig {
+        fqdn: old.fqdn.clone(),
+        user: old.user.clone(),
+        password: password.clone(),
+        protocol: old.protocol,
+    };
+    match ipmitool(&new, &["chassis", "power", "status"]).await {
+        Ok(_) => (Some(password), Ok(())),
+        Err(e) => match ipmitool(&old, &["chassis", "power", "status"]).await {
+            Ok(_) => (None, Err(format!("the BMC kept its old password: {e}"))),
+            Err(_) => (
+                Some(password),
+                Err(format!(
+                    "neither password works anymore, the new one was saved since the BMC most likely took it: {e}"
+                )),
+            ),
+        },
+    }
+}
+
+/// Rotates the BMC admin password of each of `hosts` in turn, saving each new password as soon as it is set
+pub async fn rotate_admin_credentials(
+    job: JobHandle,
+    hosts: Vec<FKey<Host>>,
+) -> Result<CredentialRotationReport, anyhow::Error> {
+    let mut report = CredentialRotationReport::default();
+
+    for (done, host) in hosts.iter().enumerate() {
+        let mut client = new_client().await?;
+        let mut transaction = client.easy_transaction().await?;
+        let mut host = host.get(&mut transaction).await?;
+
+        let name = host.server_name.clone();
+        if !report.skipped.is_empty() || !job.step(done, format!("rotating {name}")).await? {
+            report.skipped.push(name);
+            continue;
+        }
+
+        let (password, result) = rotate(&host).await;
+        if let Some(password) = password {
+            host.ipmi_pass = password;
+            host.update(&mut transaction).await?;
+        }
+        transaction.commit().await?;
+
+        match result {
+            Ok(()) => report.rotated.push(name),
+            Err(reason) => {
+                tracing::warn!("Couldn't rotate the BMC password of {name}: {reason}");
+                report.failed.push(RotationFailure { host: name, reason });
+            }
+        }
+    }
+
+    job.step(hosts.len(), "done".to_owned()).await?;
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_user_id() {
+        let list = "\
+ID  Name	     Callin  Link Auth	IPMI Msg   Channel Priv Limit
+1                    true    false      false      Unknown (0x00)
+2   ADMIN            false   false      true       ADMINISTRATOR
+3   laas_tmp         true    false      true       OPERATOR
+";
+
+        assert_eq!(find_user_id(list, "ADMIN"), Some("2".to_owned()));
+        assert_eq!(find_user_id(list, "laas_tmp"), Some("3".to_owned()));
+        assert_eq!(find_user_id(list, "root"), None);
+        // the unnamed user's first column isn't a name
+        assert_eq!(find_user_id(list, "true"), None);
+    }
+}
@@ -0,0 +1,193 @@
+//! Long running admin operations that answer with a job id right away
+//!
+//! Rotating every BMC password or reimaging a rack takes far longer than a request should.
+//! Endpoints for operations like that start a job and answer with its id, and the operation
+//! runs in the background, recording its progress on the job as it goes. Anyone can ask for
+//! a job to be cancelled, which the operation notices at its next step, and once it finishes
+//! the job holds the report it came back with.
+
+use std::future::Future;
+
+use common::prelude::{anyhow, chrono::Utc, serde_json, tracing};
+use dal::{new_client, AsEasyTransaction, FKey, NewRow};
+use models::dashboard::{Job, JobStatus};
+use serde::Serialize;
+
+/// What an operation reports back once it finishes
+pub trait JobReport: Serialize {
+    /// Whether everything the job was asked to do went through
+    fn succeeded(&self) -> bool;
+}
+
+/// What operations record their progress through
+#[derive(Debug, Clone, Copy)]
+pub struct JobHandle {
+    id: FKey<Job>,
+}
+
+impl JobHandle {
+    pub fn id(&self) -> FKey<Job> {
+        self.id
+    }
+
+    /// Records that `done` steps of the job are finished and what it is doing now,
+    /// returning false if the job was asked to stop
+    ///
+    /// Operations call this before each step, and stop with what they have when it says to
+    pub async fn step(&self, done: usize, message: String) -> Result<bool, anyhow::Error> {
+        let mut client = new_client().await?;
+        let mut transaction = client.easy_transaction().await?;
+
+        let mut job = Job::lock(&mut transaction, self.id).await?;
+        job.done = done as i32;
+        job.message = Some(message);
+        job.updated = Utc::now();
+        let keep_going = !job.cancel_requested;
+        job.update(&mut transaction).await?;
+
+        transaction.commit().await?;
+
+        Ok(keep_going)
+    }
+}
+
+/// How a job ended, given whether its operation reported success (None if it errored)
+/// and whether it was asked to stop
+fn final_status(succeeded: Option<bool>, cancel_requested: bool) -> JobStatus {
+    match (succeeded, cancel_requested) {
+        (_, true) => JobStatus::Cancelled,
+        (Some(true), false) => JobStatus::Succeeded,
+        _ => JobStatus::Failed,
+    }
+}
+
+/// Records a new job of `kind` with `total` steps and runs `operation` for it in the background
+pub async fn start<R, F, Fut>(
+    kind: &str,
+    requested_by: &str,
+    total: usize,
+    operation: F,
+) -> Result<FKey<Job>, anyhow::Error>
+where
+    R: JobReport + Send + 'static,
+    F: FnOnce(JobHandle) -> Fut + Send + 'static,
+    Fut: Future<Output = Result<R, anyhow::Error>> + Send + 'static,
+{
+    let mut client = new_client().await?;
+    let mut transaction = client.easy_transaction().await?;
+
+    let id = NewRow::new(Job::new(kind, requested_by, total))
+        .insert(&mut transaction)
+        .await?;
+
+    transaction.commit().await?;
+
+    tracing::info!("Starting {kind} job {id:?} for {requested_by}");
+
+    tokio::spawn(async move {
+        if let Err(e) = run(JobHandle { id }, operation).await {
+            tracing::error!("Couldn't record the outcome of job {id:?}: {e:?}");
+        }
+    });
+
+    Ok(id)
+}
+
+async fn run<R, F, Fut>(handle: JobHandle, operation: F) -> Result<(), anyhow::Error>
+where
+    R: JobReport + Send + 'static,
+    F: FnOnce(JobHandle) -> Fut,
+    Fut: Future<Output = Result<R, anyhow::Error>> + Send + 'static,
+{
+    {
+        let mut client = new_client().await?;
+        let mut transaction = client.easy_transaction().await?;
+
+        let mut job = Job::lock(&mut transaction, handle.id).await?;
+        let cancelled_early = job.cancel_requested;
+        job.status = match cancelled_early {
+            true => JobStatus::Cancelled,
+            false => JobStatus::Running,
+        };
+        job.started = Some(Utc::now());
+        job.updated = Utc::now();
+        if cancelled_early {
+            job.finished = job.started;
+        }
+        job.update(&mut transaction).await?;
+
+        transaction.commit().await?;
+
+        if cancelled_early {
+            return Ok(());
+        }
+    }
+
+    // run on its own task so that the job is still finished if the operation panics
+    let outcome = match tokio::spawn(operation(handle)).await {
+        Ok(outcome) => outcome,
+        Err(e) => Err(anyhow::Error::msg(format!(
+            "the operation stopped unexpectedly: {e}"
+        ))),
+    };
+
+    // the operation can run for hours, so it doesn't hold on to a connection meanwhile
+    let mut client = new_client().await?;
+    let mut transaction = client.easy_transaction().await?;
+    let mut job = Job::lock(&mut transaction, handle.id).await?;
+
+    job.status = final_status(
+        outcome.as_ref().ok().map(|r| r.succeeded()),
+        job.cancel_requested,
+    );
+    match outcome {
+        Ok(report) => job.result = Some(serde_json::to_value(report)?),
+        Err(e) => {
+            tracing::warn!("{} job {:?} failed: {e:?}", job.kind, handle.id);
+            job.error = Some(e.to_string());
+        }
+    }
+    job.finished = Some(Utc::now());
+    job.updated = Utc::now();
+    job.update(&mut transaction).await?;
+
+    transaction.commit().await?;
+
+    Ok(())
+}
+
+/// Fails jobs that were left unfinished by the last run of LibLaaS, since nothing is running them anymore
+pub async fn fail_interrupted() -> Result<(), anyhow::Error> {
+    let mut client = new_client().await?;
+    let mut transaction = client.easy_transaction().await?;
+
+    for mut job in Job::unfinished(&mut transaction).await? {
+        tracing::warn!("Job {:?} was interrupted by a restart", job.id);
+
+        job.status = JobStatus::Failed;
+        job.error = Some("LibLaaS restarted before the job finished".to_owned());
+        job.finished = Some(Utc::now());
+        job.updated = Utc::now();
+        job.update(&mut transaction).await?;
+    }
+
+    transaction.commit().await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_final_status() {
+        assert_eq!(final_status(Some(true), false), JobStatus::Succeeded);
+        assert_eq!(final_status(Some(false), false), JobStatus::Failed);
+        assert_eq!(final_status(None, false), JobStatus::Failed);
+
+        // whatever the operation managed before stopping, the job was cancelled
+        assert_eq!(final_status(Some(true), true), JobStatus::Cancelled);
+        assert_eq!(final_status(None, true), JobStatus::Cancelled);
+    }
+}
@@ -0,0 +1,295 @@
+//! Leases that keep operations on the same booking from interleaving
+//!
+//! Operations take a lease on the booking for as long as they run, and give it back when
+//! they're done. An operation that conflicts with a lease someone else holds either waits
+//! for it to be given back, like the teardown of a booking waiting out a reimage, or is
+//! turned away, like endpoints that answer with a conflict instead. Leases are renewed
+//! every so often while they are held, so the lease of an operation that went away without
+//! giving it back stops counting soon after, and none is held for longer than its operation
+//! [`lasts`](LeaseOperation::lasts) at most.
+
+use std::future::Future;
+
+use common::prelude::{
+    anyhow,
+    chrono::{self, DateTime, Utc},
+    tokio::{
+        self,
+        time::{sleep, Duration, Instant},
+    },
+    tracing,
+};
+use dal::{new_client, AsEasyTransaction, EasyTransaction, FKey, NewRow};
+use models::dashboard::{Aggregate, AggregateLease, LeaseOperation};
+use tascii::executors::{get_tokio_runtime, RtOptions};
+
+/// How often a waiting operation checks whether it can have its lease yet
+const POLL: Duration = Duration::from_secs(15);
+
+/// How long the teardown of a booking waits for whatever else is being done to it, which
+/// is as long as the longest lease, that of a rolling reimage, can be held
+pub const TEARDOWN_WAIT: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// How long a lease counts for after it was taken or last renewed
+const TTL_MINUTES: i64 = 10;
+
+/// How often held leases are renewed
+const HEARTBEAT: Duration = Duration::from_secs(2 * 60);
+
+/// When `lease` should stop counting if it is renewed at `now`
+fn expiry(lease: &AggregateLease, now: DateTime<Utc>) -> DateTime<Utc> {
+    (now + chrono::Duration::minutes(TTL_MINUTES)).min(lease.acquired + lease.operation.lasts())
+}
+
+/// Whether `a` and `b` can't be done to the same booking at once
+fn conflicts(a: LeaseOperation, b: LeaseOperation) -> bool {
+    use LeaseOperation::*;
+
+    match (a, b) {
+        // nothing else can happen to a booking while it is being torn down
+        (Teardown, _) | (_, Teardown) => true,
+        // a later end doesn't get in the way of what's running on the hosts
+        (Extension, _) | (_, Extension) => false,
+        // separate hosts of a booking can be reimaged side by side
+        (Reimage, Reimage) => false,
+        // everything else changes the hosts or their networking
+        _ => true,
+    }
+}
+
+/// A lease on a booking that stood in the way of another
+#[derive(Debug, Clone)]
+pub struct Busy {
+    pub operation: LeaseOperation,
+    pub holder: Option<String>,
+    pub since: DateTime<Utc>,
+}
+
+impl std::fmt::Display for Busy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let operation = serde_json::to_value(self.operation)
+            .ok()
+            .and_then(|v| v.as_str().map(|s| s.replace('_', " ")))
+            .unwrap_or_default();
+        write!(
+            f,
+            "the booking is busy with a {operation} since {}",
+            self.since.to_rfc2822()
+        )?;
+        match &self.holder {
+            Some(holder) => write!(f, ", started by {holder}"),
+            None => Ok(()),
+        }
+    }
+}
+
+impl std::error::Error for Busy {}
+
+/// The lease that keeps `operation` from being done to `aggregate` right now, if any
+///
+/// Like [`AggregateLease::held_on`], this keeps any other lease from being taken on the
+/// booking until `transaction` ends.
+pub async fn conflicting(
+    transaction: &mut EasyTransaction<'_>,
+    aggregate: FKey<Aggregate>,
+    operation: LeaseOperation,
+) -> Result<Option<Busy>, anyhow::Error> {
+    Ok(AggregateLease::held_on(transaction, aggregate)
+        .await?
+        .into_iter()
+        .find(|l| conflicts(l.operation, operation))
+        .map(|l| Busy {
+            operation: l.operation,
+            holder: l.holder.clone(),
+            since: l.acquired,
+        }))
+}
+
+/// The lease that keeps `operation` from being done to `aggregate` right now, if any
+pub async fn busy(
+    aggregate: FKey<Aggregate>,
+    operation: LeaseOperation,
+) -> Result<Option<Busy>, anyhow::Error> {
+    let mut client = new_client().await?;
+    let mut transaction = client.easy_transaction().await?;
+
+    let busy = conflicting(&mut transaction, aggregate, operation).await?;
+    transaction.commit().await?;
+
+    Ok(busy)
+}
+
+/// Takes a lease on `aggregate` for `operation`, failing with [`Busy`] if another
+/// operation holds one that conflicts with it
+pub async fn try_acquire(
+    aggregate: FKey<Aggregate>,
+    operation: LeaseOperation,
+    holder: Option<String>,
+) -> Result<FKey<AggregateLease>, anyhow::Error> {
+    let mut client = new_client().await?;
+    let mut transaction = client.easy_transaction().await?;
+
+    if let Some(busy) = conflicting(&mut transaction, aggregate, operation).await? {
+        return Err(busy.into());
+    }
+
+    let now = Utc::now();
+    let mut lease = AggregateLease {
+        id: FKey::new_id_dangling(),
+        aggregate,
+        operation,
+        holder,
+        acquired: now,
+        expires: now,
+    };
+    lease.expires = expiry(&lease, now);
+    let lease = NewRow::new(lease).insert(&mut transaction).await?;
+
+    transaction.commit().await?;
+
+    Ok(lease)
+}
+
+/// Takes a lease on `aggregate` for `operation`, waiting up to `wait` for conflicting
+/// leases to be given back first
+pub async fn acquire(
+    aggregate: FKey<Aggregate>,
+    operation: LeaseOperation,
+    holder: Option<String>,
+    wait: Duration,
+) -> Result<FKey<AggregateLease>, anyhow::Error> {
+    let deadline = Instant::now() + wait;
+    loop {
+        match try_acquire(aggregate, operation, holder.clone()).await {
+            Err(e) if e.is::<Busy>() && Instant::now() < deadline => {
+                tracing::info!("Waiting to start a {operation:?} of {aggregate:?}, {e}");
+                sleep(POLL).await;
+            }
+            res => return res,
+        }
+    }
+}
+
+/// Keeps the lease counting for a while longer, unless its operation has held it as long as
+/// it can
+async fn renew(lease: FKey<AggregateLease>) -> Result<(), anyhow::Error> {
+    let mut client = new_client().await?;
+    let mut transaction = client.easy_transaction().await?;
+
+    let mut held = lease.get(&mut transaction).await?;
+    let expires = expiry(&held, Utc::now());
+    if expires > held.expires {
+        held.expires = expires;
+        held.update(&mut transaction).await?;
+    }
+
+    transaction.commit().await?;
+
+    Ok(())
+}
+
+/// Runs `work` while holding `lease`, renewing it as it goes, then gives it back
+///
+/// The renewals run on a runtime of their own, so they keep going while `work` blocks the
+/// thread of the task it is done in.
+pub async fn holding<T>(lease: FKey<AggregateLease>, work: impl Future<Output = T>) -> T {
+    let runtime = get_tokio_runtime("leases".to_owned(), RtOptions { threads: Some(1) });
+    let heartbeat = runtime.spawn(async move {
+        let mut interval = tokio::time::interval(HEARTBEAT);
+        // the first tick is right away, and the lease was only just taken
+        interval.tick().await;
+
+        loop {
+            interval.tick().await;
+            if let Err(e) = renew(lease).await {
+                tracing::warn!("Couldn't renew lease {lease:?}: {e:?}");
+            }
+        }
+    });
+
+    let res = work.await;
+
+    heartbeat.abort();
+    release(lease).await;
+
+    res
+}
+
+/// Gives the lease back, so operations waiting on it can go ahead
+pub async fn release(lease: FKey<AggregateLease>) {
+    let res = async {
+        let mut client = new_client().await?;
+        let mut transaction = client.easy_transaction().await?;
+
+        lease
+            .get(&mut transaction)
+            .await?
+            .delete(&mut transaction)
+            .await?;
+
+        transaction.commit().await?;
+
+        Ok::<(), anyhow::Error>(())
+    }
+    .await;
+
+    if let Err(e) = res {
+        tracing::error!("Couldn't give back lease {lease:?}, it will expire on its own: {e:?}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use LeaseOperation::*;
+
+    #[test]
+    fn test_conflicts() {
+        assert!(conflicts(Teardown, Extension));
+        assert!(conflicts(Reimage, Teardown));
+        assert!(conflicts(Reimage, Network));
+        assert!(conflicts(RollingReimage, Reimage));
+        assert!(!conflicts(Extension, Reimage));
+        assert!(!conflicts(Snapshot, Extension));
+        assert!(!conflicts(Extension, Extension));
+        assert!(!conflicts(Reimage, Reimage));
+    }
+
+    #[test]
+    fn test_teardown_outwaits_every_lease() {
+        for operation in [
+            Reimage,
+            RollingReimage,
+            Network,
+            Extension,
+            Expansion,
+            Snapshot,
+            Teardown,
+        ] {
+            assert!(operation.lasts().to_std().unwrap() <= TEARDOWN_WAIT);
+        }
+    }
+
+    #[test]
+    fn test_expiry() {
+        let acquired = Utc::now();
+        let lease = AggregateLease {
+            id: FKey::new_id_dangling(),
+            aggregate: FKey::new_id_dangling(),
+            operation: Network,
+            holder: None,
+            acquired,
+            expires: acquired,
+        };
+
+        assert_eq!(
+            expiry(&lease, acquired),
+            acquired + chrono::Duration::minutes(TTL_MINUTES)
+        );
+        // renewals never take it past how long the operation can hold it
+        assert_eq!(
+            expiry(&lease, acquired + chrono::Duration::minutes(14)),
+            acquired + Network.lasts()
+        );
+    }
+}
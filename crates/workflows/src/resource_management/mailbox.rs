@@ -432,10 +432,19 @@ async fn get_ci_file(
     let host = instance.linked_host.expect("no host for ci file");
     let agg = instance.aggregate;
 
+    let env = agg
+        .get(&mut transaction)
+        .await
+        .log_db_client_error()?
+        .configuration
+        .env
+        .clone();
+
     for fk in instance.config.cifile.clone() {
-        let ci = fk.get(&mut transaction).await.unwrap();
+        let mut ci = fk.get(&mut transaction).await.unwrap().into_inner();
+        ci.data = crate::deploy_booking::booking_env::substitute(&ci.data, &env);
         tracing::info!("Returning additional ci file from list:\n{}", ci.data);
-        ci_files.push(ci.into_inner());
+        ci_files.push(ci);
     }
 
     ci_files.sort_by_key(|c| c.priority);
@@ -487,6 +496,10 @@ pub async fn entry(_rt: &'static Runtime) {
         .route("/:instance/:unique/push", post(Mailbox::push))
         .route("/:instance/:unique/peek", post(Mailbox::peek))
         .route("/:instance/:unique/pop", post(Mailbox::pop))
+        .route(
+            "/:instance/:unique/telemetry",
+            post(crate::deploy_booking::telemetry::receive),
+        )
         //.route("/:instance/:aggregate/cloud_init.tar", get(get_ci_file))
         .route("/:instance/user-data", get(get_ci_file))
         .route("/cloud_init.py", get(get_ci_injector))
@@ -0,0 +1,267 @@
+//! Temporary port mirroring between hosts of the same booking, so that
+//! users can capture the traffic of an interface while debugging
+//!
+//! A mirror is programmed onto the switch both ports are connected to, and
+//! is torn down again once it expires, is ended early, or the booking ends
+
+use common::prelude::{chrono::Utc, tokio, tracing};
+use dal::{new_client, AsEasyTransaction, EasyTransaction, ExistingRow, FKey, ID};
+use models::{
+    dashboard::{Aggregate, Instance, PortMirror, StatusSentiment},
+    inventory::{HostPort, Switch},
+};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use tascii::prelude::*;
+
+use super::{cisco, sonic};
+use crate::utils::status_feed::PublishedLog;
+
+/// The longest that a single mirror may be requested for
+pub const MAX_MIRROR_MINUTES: u32 = 4 * 60;
+
+/// How many monitor sessions we are willing to use on a single switch,
+/// most switches only support a handful of concurrent ones
+pub const MAX_SESSIONS_PER_SWITCH: i16 = 4;
+
+/// How often a running mirror checks whether it was ended early
+const POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Finds the lowest monitor session number that is not in use on the switch
+pub async fn free_session(
+    t: &mut EasyTransaction<'_>,
+    switch: FKey<Switch>,
+) -> Result<Option<i16>, anyhow::Error> {
+    let used: Vec<i16> = PortMirror::active_on_switch(t, switch)
+        .await?
+        .iter()
+        .map(|m| m.session)
+        .collect();
+
+    Ok((1..=MAX_SESSIONS_PER_SWITCH).find(|s| !used.contains(s)))
+}
+
+/// The instance of the aggregate that is running on the host the port belongs to
+async fn instance_for_port(
+    t: &mut EasyTransaction<'_>,
+    aggregate: FKey<Aggregate>,
+    port: FKey<HostPort>,
+) -> Result<Option<FKey<Instance>>, anyhow::Error> {
+    let host = port.get(t).await?.on_host;
+    let agg = aggregate.get(t).await?;
+
+    Ok(agg
+        .instances(t)
+        .await?
+        .into_iter()
+        .find(|i| i.linked_host == Some(host))
+        .map(|i| i.id))
+}
+
+/// Programs (or tears down) the mirror on its switch, using the driver for the switch os
+async fn program_mirror(
+    t: &mut EasyTransaction<'_>,
+    mirror: &PortMirror,
+    enable: bool,
+) -> Result<(), anyhow::Error> {
+    let switch = mirror.switch.get(t).await?.into_inner();
+    let os = switch
+        .switch_os
+        .ok_or(anyhow::Error::msg("switch has no os set"))?
+        .get(t)
+        .await?
+        .os_type
+        .clone();
+
+    let port_name = |p: HostPort| p.switchport.ok_or(anyhow::Error::msg("port is not cabled"));
+    let source = port_name(mirror.source.get(t).await?.into_inner())?
+        .get(t)
+        .await?
+        .name
+        .clone();
+    let destination = port_name(mirror.destination.get(t).await?.into_inner())?
+        .get(t)
+        .await?
+        .name
+        .clone();
+
+    match (os.as_str(), enable) {
+        ("NXOS", true) => {
+            cisco::nx_start_port_mirror(switch, mirror.session, &source, &destination)
+        }
+        ("NXOS", false) => cisco::nx_stop_port_mirror(switch, mirror.session, &destination),
+        ("SONiC", true) => {
+            sonic::sonic_start_port_mirror(&switch, mirror.session, &source, &destination)
+        }
+        ("SONiC", false) => sonic::sonic_stop_port_mirror(&switch, mirror.session),
+        (other, _) => {
+            return Err(anyhow::Error::msg(format!(
+                "port mirroring is not supported on {other} switches"
+            )))
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes an audit entry for the mirror to the logs of both instances involved
+async fn log_mirror(
+    t: &mut EasyTransaction<'_>,
+    mirror: &PortMirror,
+    header: &str,
+    sentiment: StatusSentiment,
+) -> Result<(), anyhow::Error> {
+    let source = mirror.source.get(t).await?.name.clone();
+    let destination = mirror.destination.get(t).await?.name.clone();
+
+    let detail = format!(
+        "traffic on {source} mirrored to {destination}, requested by {} until {}",
+        mirror.requested_by,
+        mirror.expires.to_rfc2822()
+    );
+
+    tracing::info!("{header} for aggregate {:?}: {detail}", mirror.aggregate);
+
+    for port in [mirror.source, mirror.destination] {
+        if let Some(instance) = instance_for_port(t, mirror.aggregate, port).await? {
+            instance.log(header, detail.clone(), sentiment).await;
+        }
+    }
+
+    Ok(())
+}
+
+/// Tears the mirror down if it is still running, returning the now ended mirror
+async fn end_mirror(
+    t: &mut EasyTransaction<'_>,
+    mut mirror: ExistingRow<PortMirror>,
+) -> Result<ExistingRow<PortMirror>, anyhow::Error> {
+    if mirror.ended.is_none() {
+        program_mirror(t, &mirror, false).await?;
+
+        mirror.ended = Some(Utc::now());
+        mirror.update(t).await?;
+
+        log_mirror(t, &mirror, "Port Mirror Ended", StatusSentiment::Succeeded).await?;
+    }
+
+    Ok(mirror)
+}
+
+/// Starts the mirror on the switch, and keeps it up until it expires or is ended
+#[derive(Debug, Clone, Hash, Serialize, Deserialize)]
+pub struct StartPortMirror {
+    pub mirror: FKey<PortMirror>,
+}
+
+tascii::mark_task!(StartPortMirror);
+impl AsyncRunnable for StartPortMirror {
+    type Output = ();
+
+    async fn run(&mut self, _context: &Context) -> Result<Self::Output, TaskError> {
+        let mut client = new_client().await?;
+        let mut transaction = client.easy_transaction().await?;
+
+        let mirror = self.mirror.get(&mut transaction).await?;
+        if let Err(e) = program_mirror(&mut transaction, &mirror, true).await {
+            log_mirror(
+                &mut transaction,
+                &mirror,
+                "Port Mirror Failed",
+                StatusSentiment::Failed,
+            )
+            .await?;
+
+            // never leave a half programmed or unaccounted for session behind
+            let mut mirror = mirror;
+            mirror.ended = Some(Utc::now());
+            mirror.update(&mut transaction).await?;
+            transaction.commit().await?;
+
+            return Err(e.into());
+        }
+
+        log_mirror(
+            &mut transaction,
+            &mirror,
+            "Port Mirror Started",
+            StatusSentiment::InProgress,
+        )
+        .await?;
+        transaction.commit().await?;
+
+        loop {
+            tokio::time::sleep(POLL_INTERVAL).await;
+
+            let mut transaction = client.easy_transaction().await?;
+            let mirror = self.mirror.get(&mut transaction).await?;
+
+            if mirror.ended.is_some() {
+                // someone ended it early, they did the teardown
+                transaction.commit().await?;
+                return Ok(());
+            }
+
+            if mirror.expires <= Utc::now() {
+                end_mirror(&mut transaction, mirror).await?;
+                transaction.commit().await?;
+                return Ok(());
+            }
+
+            transaction.commit().await?;
+        }
+    }
+
+    fn summarize(&self, id: ID) -> String {
+        format!(
+            "StartPortMirror task with id {id} for mirror {:?}",
+            self.mirror
+        )
+    }
+
+    fn identifier() -> TaskIdentifier {
+        TaskIdentifier::named("StartPortMirrorTask").versioned(1)
+    }
+
+    fn variable_timeout(&self) -> Duration {
+        Duration::from_secs(MAX_MIRROR_MINUTES as u64 * 60) + Duration::from_secs(60 * 10)
+    }
+}
+
+/// Tears down a mirror before it expires
+#[derive(Debug, Clone, Hash, Serialize, Deserialize)]
+pub struct EndPortMirror {
+    pub mirror: FKey<PortMirror>,
+}
+
+tascii::mark_task!(EndPortMirror);
+impl AsyncRunnable for EndPortMirror {
+    type Output = ();
+
+    async fn run(&mut self, _context: &Context) -> Result<Self::Output, TaskError> {
+        let mut client = new_client().await?;
+        let mut transaction = client.easy_transaction().await?;
+
+        let mirror = self.mirror.get(&mut transaction).await?;
+        end_mirror(&mut transaction, mirror).await?;
+
+        transaction.commit().await?;
+
+        Ok(())
+    }
+
+    fn summarize(&self, id: ID) -> String {
+        format!(
+            "EndPortMirror task with id {id} for mirror {:?}",
+            self.mirror
+        )
+    }
+
+    fn identifier() -> TaskIdentifier {
+        TaskIdentifier::named("EndPortMirrorTask").versioned(1)
+    }
+
+    fn retry_count(&self) -> usize {
+        2
+    }
+}
@@ -3,10 +3,37 @@
 
 //pub mod allocation;
 pub mod allocator;
+pub mod bmc_isolation;
+pub mod capacity;
 pub mod cisco;
 pub mod cobbler;
+pub mod composability;
+pub mod estimate;
+pub mod expiry;
+pub mod external;
+pub mod firewall;
+pub mod firmware;
+pub mod flavors;
+pub mod health;
+pub mod idle;
+pub mod images;
+pub mod inventory;
 pub mod ipmi_accounts;
+pub mod jobs;
+pub mod lease;
 pub mod mailbox;
+pub mod mirror;
 pub mod network;
-pub mod vpn;
+pub mod orphans;
+pub mod post_times;
+pub mod queue;
+pub mod reconciler;
+pub mod schedule;
+pub mod scheduled_start;
+pub mod simulator;
 pub mod sonic;
+pub mod tickets;
+pub mod trash;
+pub mod usage;
+pub mod vpn;
+pub mod working_hours;
@@ -0,0 +1,492 @@
+//! Finds and releases resources that are still held by bookings that have ended
+//!
+//! A teardown that fails partway leaves behind whatever it didn't get to: hosts still
+//! powered on and on the booking's vlans, vlans and vpn access still allocated, port
+//! mirrors and external attachments still programmed, and so on. Cobbler systems for
+//! hosts that have since been removed from inventory are collected here too, since
+//! they keep serving DHCP leases to MACs nothing tracks anymore.
+//!
+//! Every run reports what it found to the admins and sends a metric per kind of
+//! orphan, so leaks can be tracked over time. Dry runs stop there.
+//!
+//! Allocations of a booking are only released once all of its hosts were cleaned,
+//! so a host is never handed to another booking while still on the old vlans.
+
+use std::collections::{HashMap, HashSet};
+
+use common::prelude::{tokio, tracing};
+use config::settings;
+use dal::{new_client, AsEasyTransaction, DBTable, EasyTransaction, FKey, ID};
+use metrics::prelude::*;
+use models::{
+    allocator::{Allocation, Reservation, ResourceHandle, ResourceHandleInner},
+    dashboard::{Aggregate, AttachmentState, ExternalAttachment, FirewallPolicy, PortMirror},
+    inventory::Host,
+};
+use notifications::email::send_to_admins;
+use serde::{Deserialize, Serialize};
+use tascii::prelude::*;
+
+use crate::{
+    deploy_booking::{
+        configure_networking::ConfigureNetworking, net_config::empty_network_config,
+        set_host_power_state::SetPower,
+    },
+    entry::{Action, DISPATCH},
+    resource_management::{
+        allocator::Allocator, cobbler::CobblerActions, external::DetachExternalNetworks,
+        firewall::RemoveFirewallPolicy, ipmi_accounts::DeleteIPMIAccount, mirror::EndPortMirror,
+        vpn::SyncVPN,
+    },
+    retry_for,
+    utils::python::PythonBuilder,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OrphanKind {
+    HostAllocation(FKey<Host>),
+    VlanAllocation,
+    VpnAllocation,
+    Reservation,
+    PortMirror(FKey<PortMirror>),
+    ExternalAttachment,
+    FirewallPolicy,
+    /// A cobbler system for a host that isn't in inventory, by the host's name
+    DhcpReservation,
+}
+
+impl OrphanKind {
+    /// Every kind, as named in metrics
+    const NAMES: [&'static str; 8] = [
+        "host_allocation",
+        "vlan_allocation",
+        "vpn_allocation",
+        "reservation",
+        "port_mirror",
+        "external_attachment",
+        "firewall_policy",
+        "dhcp_reservation",
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::HostAllocation(_) => Self::NAMES[0],
+            Self::VlanAllocation => Self::NAMES[1],
+            Self::VpnAllocation => Self::NAMES[2],
+            Self::Reservation => Self::NAMES[3],
+            Self::PortMirror(_) => Self::NAMES[4],
+            Self::ExternalAttachment => Self::NAMES[5],
+            Self::FirewallPolicy => Self::NAMES[6],
+            Self::DhcpReservation => Self::NAMES[7],
+        }
+    }
+
+    /// Whether ending the aggregate's allocations takes care of it
+    fn released_with_allocations(&self) -> bool {
+        matches!(
+            self,
+            Self::HostAllocation(_)
+                | Self::VlanAllocation
+                | Self::VpnAllocation
+                | Self::Reservation
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Orphan {
+    pub kind: OrphanKind,
+    pub resource: String,
+    /// The ended booking holding it, if any
+    pub aggregate: Option<FKey<Aggregate>>,
+    pub cleaned: bool,
+}
+
+impl std::fmt::Display for Orphan {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} ({})",
+            self.resource,
+            self.kind.name().replace('_', " ")
+        )?;
+        if let Some(agg) = self.aggregate {
+            write!(f, " held by ended aggregate {}", agg.into_id())?;
+        }
+        if self.cleaned {
+            write!(f, ", cleaned up")?;
+        }
+
+        Ok(())
+    }
+}
+
+async fn describe(
+    t: &mut EasyTransaction<'_>,
+    handle: FKey<ResourceHandle>,
+) -> Result<(OrphanKind, String), anyhow::Error> {
+    Ok(match handle.get(t).await?.tracks {
+        ResourceHandleInner::Host(h) => (
+            OrphanKind::HostAllocation(h),
+            h.get(t).await?.server_name.clone(),
+        ),
+        ResourceHandleInner::PrivateVlan(v) | ResourceHandleInner::PublicVlan(v) => (
+            OrphanKind::VlanAllocation,
+            format!("vlan {}", v.get(t).await?.vlan_id),
+        ),
+        ResourceHandleInner::VPNAccess(token) => {
+            let token = token.get(t).await?;
+            (
+                OrphanKind::VpnAllocation,
+                format!("vpn access of {} to {}", token.username, token.project),
+            )
+        }
+    })
+}
+
+/// Everything still held by ended bookings, and cobbler systems of hosts that are gone
+pub async fn find_orphans(t: &mut EasyTransaction<'_>) -> Result<Vec<Orphan>, anyhow::Error> {
+    let ended: HashSet<FKey<Aggregate>> = Aggregate::select()
+        .run(t)
+        .await?
+        .into_iter()
+        .filter(|a| a.deleted || a.state.ended())
+        .map(|a| a.id)
+        .collect();
+    let is_ended = |agg: Option<FKey<Aggregate>>| agg.is_some_and(|a| ended.contains(&a));
+
+    let mut orphans = Vec::new();
+    let mut orphan = |kind, resource, aggregate| {
+        orphans.push(Orphan {
+            kind,
+            resource,
+            aggregate,
+            cleaned: false,
+        })
+    };
+
+    for allocation in Allocation::all_live(t).await? {
+        if is_ended(allocation.for_aggregate) {
+            let (kind, resource) = describe(t, allocation.for_resource).await?;
+            orphan(kind, resource, allocation.for_aggregate);
+        }
+    }
+
+    for reservation in Reservation::upcoming(t).await? {
+        if is_ended(Some(reservation.for_aggregate)) {
+            let (_, resource) = describe(t, reservation.for_resource).await?;
+            orphan(
+                OrphanKind::Reservation,
+                format!("reservation of {resource}"),
+                Some(reservation.for_aggregate),
+            );
+        }
+    }
+
+    for mirror in PortMirror::select().run(t).await? {
+        if mirror.ended.is_none() && is_ended(Some(mirror.aggregate)) {
+            let switch = mirror.switch.get(t).await?;
+            orphan(
+                OrphanKind::PortMirror(mirror.id),
+                format!("mirror session {} on {}", mirror.session, switch.name),
+                Some(mirror.aggregate),
+            );
+        }
+    }
+
+    for attachment in ExternalAttachment::select().run(t).await? {
+        let programmed = matches!(
+            attachment.state,
+            AttachmentState::Active | AttachmentState::Failed
+        );
+        if programmed && is_ended(Some(attachment.aggregate)) {
+            orphan(
+                OrphanKind::ExternalAttachment,
+                format!("attachment to feed {}", attachment.feed),
+                Some(attachment.aggregate),
+            );
+        }
+    }
+
+    for policy in FirewallPolicy::select().run(t).await? {
+        if policy.applied && is_ended(Some(policy.aggregate)) {
+            orphan(
+                OrphanKind::FirewallPolicy,
+                "uplink firewall policy".to_owned(),
+                Some(policy.aggregate),
+            );
+        }
+    }
+
+    let hosts: HashSet<String> = Host::select()
+        .run(t)
+        .await?
+        .into_iter()
+        .map(|h| h.server_name.clone())
+        .collect();
+    match PythonBuilder::<CobblerActions>::command("system_names")
+        .run_and(|o| o.extract::<Vec<String>>())
+    {
+        Ok(Ok(systems)) => {
+            for system in systems.into_iter().filter(|s| !hosts.contains(s)) {
+                orphan(OrphanKind::DhcpReservation, system, None);
+            }
+        }
+        Ok(Err(e)) | Err(e) => tracing::warn!("Couldn't list cobbler systems: {e:?}"),
+    }
+
+    Ok(orphans)
+}
+
+#[derive(Debug, Clone, Hash, Serialize, Deserialize)]
+pub struct CollectOrphans {
+    /// Only report orphans, without cleaning any up
+    pub dry_run: bool,
+}
+
+impl CollectOrphans {
+    /// Powers the host off and takes it off every vlan, returning whether both worked
+    async fn clean_host(&self, context: &Context, host: FKey<Host>) -> bool {
+        let powered_off = retry_for(SetPower::off(host), context, 3, 10)
+            .inspect_err(|e| tracing::error!("Couldn't power off orphaned {host:?}: {e:?}"))
+            .is_ok();
+
+        let net_config = match new_client().await {
+            Ok(mut client) => match client.easy_transaction().await {
+                Ok(mut transaction) => {
+                    let config = empty_network_config(host, &mut transaction).await;
+                    let _ = transaction.commit().await;
+                    config
+                }
+                Err(_) => return false,
+            },
+            Err(_) => return false,
+        };
+        let unnetworked = context
+            .spawn(ConfigureNetworking { net_config })
+            .join()
+            .inspect_err(|e| tracing::error!("Couldn't reset network of orphaned {host:?}: {e:?}"))
+            .is_ok();
+
+        // leave the booking's BMC account behind, it shouldn't outlive the booking
+        let _ = context
+            .spawn(DeleteIPMIAccount {
+                host,
+                userid: "4".to_owned(),
+            })
+            .join();
+
+        powered_off && unnetworked
+    }
+
+    async fn clean_aggregate(
+        &self,
+        context: &Context,
+        agg: FKey<Aggregate>,
+        orphans: &mut [&mut Orphan],
+    ) {
+        let mut hosts_clean = true;
+        for orphan in orphans.iter_mut() {
+            if let OrphanKind::HostAllocation(host) = orphan.kind {
+                hosts_clean &= self.clean_host(context, host).await;
+            }
+        }
+
+        for orphan in orphans.iter_mut() {
+            if let OrphanKind::PortMirror(mirror) = orphan.kind {
+                orphan.cleaned = context.spawn(EndPortMirror { mirror }).join().is_ok();
+            }
+        }
+
+        if orphans
+            .iter()
+            .any(|o| o.kind == OrphanKind::ExternalAttachment)
+        {
+            let detached = context
+                .spawn(DetachExternalNetworks { agg_id: agg })
+                .join()
+                .is_ok();
+            for orphan in orphans.iter_mut() {
+                if orphan.kind == OrphanKind::ExternalAttachment {
+                    orphan.cleaned = detached;
+                }
+            }
+        }
+
+        if orphans.iter().any(|o| o.kind == OrphanKind::FirewallPolicy) {
+            let removed = context
+                .spawn(RemoveFirewallPolicy { agg_id: agg })
+                .join()
+                .is_ok();
+            for orphan in orphans.iter_mut() {
+                if orphan.kind == OrphanKind::FirewallPolicy {
+                    orphan.cleaned = removed;
+                }
+            }
+        }
+
+        if !hosts_clean {
+            tracing::warn!(
+                "Not releasing allocations of {agg:?}, some of its hosts couldn't be cleaned"
+            );
+            return;
+        }
+
+        let released = async {
+            let mut client = new_client().await?;
+            let mut transaction = client.easy_transaction().await?;
+            Allocator::instance()
+                .deallocate_aggregate(&mut transaction, agg)
+                .await?;
+            let users = agg.get(&mut transaction).await?.users.clone();
+            transaction.commit().await?;
+
+            Ok::<_, anyhow::Error>(users)
+        }
+        .await;
+
+        match released {
+            Ok(users) => {
+                for orphan in orphans.iter_mut() {
+                    if orphan.kind.released_with_allocations() {
+                        orphan.cleaned = true;
+                    }
+                }
+
+                // the vpn groups of the booking only go away once nothing grants them
+                if orphans.iter().any(|o| o.kind == OrphanKind::VpnAllocation) {
+                    let _ = context.spawn(SyncVPN { users }).join();
+                }
+            }
+            Err(e) => tracing::error!("Couldn't release allocations of {agg:?}: {e:?}"),
+        }
+    }
+
+    async fn clean(&self, context: &Context, orphans: &mut [Orphan]) {
+        let mut by_aggregate: HashMap<FKey<Aggregate>, Vec<&mut Orphan>> = HashMap::new();
+        let mut systems = Vec::new();
+        for orphan in orphans.iter_mut() {
+            match orphan.aggregate {
+                Some(agg) => by_aggregate.entry(agg).or_default().push(orphan),
+                None => systems.push(orphan),
+            }
+        }
+
+        for (agg, mut held) in by_aggregate {
+            self.clean_aggregate(context, agg, &mut held).await;
+        }
+
+        for system in systems.iter_mut() {
+            system.cleaned = PythonBuilder::<CobblerActions>::command("remove_system")
+                .arg(system.resource.clone())
+                .run()
+                .inspect_err(|e| {
+                    tracing::error!("Couldn't remove cobbler system {}: {e:?}", system.resource)
+                })
+                .is_ok();
+        }
+        if systems.iter().any(|s| s.cleaned) {
+            if let Err(e) = PythonBuilder::<CobblerActions>::command("sync").run() {
+                tracing::error!("Couldn't sync cobbler after removing systems: {e:?}");
+            }
+        }
+    }
+
+    fn send_metrics(&self, orphans: &[Orphan]) {
+        for kind in OrphanKind::NAMES {
+            let of_kind = orphans.iter().filter(|o| o.kind.name() == kind);
+            let metric = OrphanMetric {
+                kind: kind.to_owned(),
+                dry_run: self.dry_run,
+                found: of_kind.clone().count() as i32,
+                cleaned: of_kind.filter(|o| o.cleaned).count() as i32,
+                ..Default::default()
+            };
+
+            if let Err(e) = MetricHandler::send(metric) {
+                tracing::error!("Failed to send orphan metric: {e:?}");
+            }
+        }
+    }
+}
+
+tascii::mark_task!(CollectOrphans);
+impl AsyncRunnable for CollectOrphans {
+    type Output = ();
+
+    async fn run(&mut self, context: &Context) -> Result<Self::Output, TaskError> {
+        let mut client = new_client().await?;
+        let mut transaction = client.easy_transaction().await?;
+        let mut orphans = find_orphans(&mut transaction).await?;
+        transaction.commit().await?;
+
+        if !self.dry_run {
+            self.clean(context, &mut orphans).await;
+        }
+
+        self.send_metrics(&orphans);
+
+        if !orphans.is_empty() {
+            let lines: Vec<String> = orphans.iter().map(|o| o.to_string()).collect();
+            let heading = match self.dry_run {
+                true => "Dry run of the garbage collector found",
+                false => "Garbage collector found",
+            };
+
+            send_to_admins(format!(
+                "{heading} {} orphaned resources, {} were cleaned up:<br>{}",
+                orphans.len(),
+                orphans.iter().filter(|o| o.cleaned).count(),
+                lines.join("<br>")
+            ))
+            .await;
+        }
+
+        Ok(())
+    }
+
+    fn identifier() -> TaskIdentifier {
+        TaskIdentifier::named("CollectOrphansTask").versioned(1)
+    }
+
+    fn summarize(&self, id: ID) -> String {
+        format!("CollectOrphans (dry run: {}) with id {id}", self.dry_run)
+    }
+
+    fn timeout() -> std::time::Duration {
+        // every leaked host may need powering off and reconfiguring
+        std::time::Duration::from_secs(60 * 60)
+    }
+
+    fn retry_count(&self) -> usize {
+        0
+    }
+}
+
+/// Periodically queues up a garbage collection, unless it's been disabled
+pub async fn entry() {
+    let config = settings().garbage_collector.clone();
+    if !config.enabled {
+        tracing::info!("Garbage collector is disabled, not looking for orphaned resources");
+        return;
+    }
+
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(
+        config.interval_hours * 60 * 60,
+    ));
+
+    loop {
+        interval.tick().await;
+
+        match DISPATCH.get() {
+            Some(dispatch) => {
+                if let Err(e) = dispatch.send(Action::CollectOrphans {
+                    dry_run: config.dry_run,
+                }) {
+                    tracing::error!("Couldn't queue garbage collection: {e:?}");
+                }
+            }
+            None => tracing::warn!("Dispatcher isn't running yet, skipping garbage collection"),
+        }
+    }
+}
@@ -0,0 +1,204 @@
+//! Tracking how long hosts take from power on to netboot, and flagging hosts that drift
+//! from their usual
+//!
+//! Every deploy records when the host was powered on and when it phoned home from the
+//! installer. Most of that is POST, which hardly changes from deploy to deploy until
+//! something like a DIMM or RAID controller starts failing, so a host taking far longer
+//! (or shorter) than its usual is worth a ticket before it fails outright. The usual is
+//! the median of the host's latest deploys that weren't flagged themselves, so a failing
+//! host doesn't drag its own baseline along with it.
+
+use common::prelude::{
+    anyhow,
+    chrono::{DateTime, Utc},
+    tracing,
+};
+use config::{settings, PostTimeConfig};
+use dal::{new_client, AsEasyTransaction, EasyTransaction, FKey, NewRow};
+use models::{
+    dashboard::Instance,
+    inventory::{host::benchmark::median, Host, HostPostTime, TicketKind},
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::resource_management::tickets::report_host_failure;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, JsonSchema, PartialEq)]
+pub struct PostDrift {
+    pub baseline_seconds: f64,
+    /// How far off the baseline the time is, negative if it was quicker
+    pub drift_percent: f64,
+    pub anomalous: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct PostTimeSample {
+    pub seconds: f64,
+    pub netbooted: String,
+    pub anomalous: bool,
+}
+
+/// How the host's latest POST compares to its usual
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct PostTimeReport {
+    pub latest_seconds: Option<f64>,
+    /// None until the host has enough deploys behind it to have a usual
+    pub drift: Option<PostDrift>,
+    /// Newest first
+    pub recent: Vec<PostTimeSample>,
+}
+
+/// How `seconds` compares to the usual of `history`, the host's earlier times, if there
+/// are enough of them to tell
+pub fn assess(history: &[f64], seconds: f64, config: &PostTimeConfig) -> Option<PostDrift> {
+    if history.len() < config.min_baseline.max(1) {
+        return None;
+    }
+
+    let baseline = median(history)?;
+    if baseline <= 0.0 {
+        return None;
+    }
+
+    let drift = seconds - baseline;
+    let drift_percent = drift / baseline * 100.0;
+
+    Some(PostDrift {
+        baseline_seconds: baseline,
+        drift_percent,
+        anomalous: drift.abs() >= config.min_drift_seconds
+            && drift_percent.abs() >= config.drift_percent,
+    })
+}
+
+/// The times of `recorded` the usual is taken from
+fn history(recorded: &[HostPostTime]) -> Vec<f64> {
+    recorded
+        .iter()
+        .filter(|p| !p.anomalous)
+        .map(|p| p.seconds)
+        .collect()
+}
+
+/// Records one deploy's time from power on to netboot, and opens a ticket for the host
+/// if it is far off its usual
+pub async fn record(
+    host: FKey<Host>,
+    instance: FKey<Instance>,
+    powered_on: DateTime<Utc>,
+    netbooted: DateTime<Utc>,
+) -> Result<(), anyhow::Error> {
+    let config = settings().post_times.clone();
+    let seconds = (netbooted - powered_on).num_milliseconds() as f64 / 1000.0;
+
+    let mut client = new_client().await?;
+    let mut transaction = client.easy_transaction().await?;
+
+    let recorded = HostPostTime::latest_for_host(&mut transaction, host, config.baseline_deploys)
+        .await?
+        .into_iter()
+        .map(|p| p.into_inner())
+        .collect::<Vec<_>>();
+    let drift = assess(&history(&recorded), seconds, &config);
+    let anomalous = drift.is_some_and(|d| d.anomalous);
+
+    NewRow::new(HostPostTime {
+        id: FKey::new_id_dangling(),
+        host,
+        instance: Some(instance),
+        powered_on,
+        netbooted,
+        seconds,
+        anomalous,
+    })
+    .insert(&mut transaction)
+    .await?;
+
+    let server_name = host.get(&mut transaction).await?.server_name.clone();
+    transaction.commit().await?;
+
+    if let Some(drift) = drift.filter(|d| d.anomalous) {
+        tracing::warn!("{server_name} took {seconds:.0}s to netboot, {drift:?}");
+
+        report_host_failure(
+            host,
+            TicketKind::PostTimeDrift,
+            format!(
+                "{server_name} took {seconds:.0} seconds from power on to netboot, where it usually takes {:.0} ({:+.0}%). \
+                 POST times drifting like this are often an early sign of failing DIMMs or a failing RAID controller.",
+                drift.baseline_seconds, drift.drift_percent
+            ),
+        )
+        .await;
+    }
+
+    Ok(())
+}
+
+/// How the host has been doing at POST lately
+pub async fn report(
+    t: &mut EasyTransaction<'_>,
+    host: FKey<Host>,
+) -> Result<PostTimeReport, anyhow::Error> {
+    let config = &settings().post_times;
+
+    let recorded = HostPostTime::latest_for_host(t, host, config.baseline_deploys + 1)
+        .await?
+        .into_iter()
+        .map(|p| p.into_inner())
+        .collect::<Vec<_>>();
+
+    let drift = recorded
+        .split_first()
+        .and_then(|(latest, earlier)| assess(&history(earlier), latest.seconds, config));
+
+    Ok(PostTimeReport {
+        latest_seconds: recorded.first().map(|p| p.seconds),
+        drift,
+        recent: recorded
+            .iter()
+            .map(|p| PostTimeSample {
+                seconds: p.seconds,
+                netbooted: p.netbooted.to_rfc2822(),
+                anomalous: p.anomalous,
+            })
+            .collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> PostTimeConfig {
+        PostTimeConfig {
+            baseline_deploys: 20,
+            min_baseline: 3,
+            drift_percent: 40.0,
+            min_drift_seconds: 60.0,
+        }
+    }
+
+    #[test]
+    fn test_assess() {
+        let history = [200.0, 210.0, 190.0, 205.0];
+
+        let usual = assess(&history, 215.0, &config()).unwrap();
+        assert_eq!(usual.baseline_seconds, 202.5);
+        assert!(!usual.anomalous);
+
+        let slow = assess(&history, 420.0, &config()).unwrap();
+        assert!(slow.anomalous);
+        assert!(slow.drift_percent > 100.0);
+
+        // far off in percent, but only by a few seconds
+        let quick_host = [20.0, 21.0, 19.0];
+        assert!(!assess(&quick_host, 60.0, &config()).unwrap().anomalous);
+
+        // far too quick is as suspect as far too slow, like a host that lost most of its memory
+        assert!(assess(&history, 90.0, &config()).unwrap().anomalous);
+
+        assert_eq!(assess(&history[..2], 420.0, &config()), None);
+    }
+}
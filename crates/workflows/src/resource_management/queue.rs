@@ -0,0 +1,262 @@
+//! Starting bookings that asked to wait for hosts instead of failing when none were free
+//!
+//! Queued bookings are written out with their instances and networks like any other, but
+//! hold no hosts. Every run goes through them oldest first and tries to allocate hosts for
+//! each, so a booking that fits goes ahead of older ones that are still waiting for a
+//! flavor nobody has released yet. The booking starts over from when it got its hosts,
+//! keeping the length it was booked for, and its owner is told either way. Bookings that
+//! waited longer than the configured limit are taken out of the queue.
+
+use common::prelude::{
+    anyhow,
+    chrono::{Duration, Utc},
+    tokio, tracing,
+};
+use config::{settings, BookingQueueConfig};
+use dal::{new_client, AsEasyTransaction, DBTable, EasyTransaction, FKey};
+use models::{
+    allocator::AllocationReason,
+    dashboard::{Aggregate, AggregateSummary, BookingEvent, BookingEventKind, LifeCycleState},
+};
+use notifications::{booking_queue, Env, QueuedBookingInfo};
+
+use crate::{
+    entry::{Action, DISPATCH},
+    resource_management::allocator::{Allocator, MATCH_KEY},
+};
+
+/// Whether hosts could be allocated for every instance of `agg` right now. Instances
+/// requested by constraints are matched again, and keep the flavor they were matched to
+/// if everything fits
+async fn fits(t: &mut EasyTransaction<'_>, agg: &Aggregate) -> Result<bool, anyhow::Error> {
+    let allocator = Allocator::instance();
+    let instances = agg.instances(t).await?;
+
+    let mut ct = t.easy_transaction().await?;
+    let mut to_free = Vec::new();
+    let mut matched = Vec::new();
+    for instance in instances.iter() {
+        let allocated = match &instance.config.constraints {
+            Some(constraints) => {
+                let image = instance.config.image.get(&mut ct).await?;
+                allocator
+                    .allocate_matching(
+                        &mut ct,
+                        constraints,
+                        &image,
+                        agg.id,
+                        AllocationReason::ForBooking,
+                        true,
+                    )
+                    .await
+                    .map(|(host, handle, m)| (host, handle, Some(m)))
+            }
+            None => allocator
+                .allocate_host(
+                    &mut ct,
+                    instance.config.flavor,
+                    agg.id,
+                    AllocationReason::ForBooking,
+                    true,
+                )
+                .await
+                .map(|(host, handle)| (host, handle, None)),
+        };
+
+        match allocated {
+            Ok((host, handle, m)) => {
+                to_free.push((host, handle));
+                matched.push(m);
+            }
+            Err(e) => {
+                tracing::debug!("Queued booking {:?} doesn't fit yet: {e}", agg.id);
+                ct.rollback().await?;
+                return Ok(false);
+            }
+        }
+    }
+
+    for (_host, handle) in to_free {
+        allocator.deallocate_host(&mut ct, handle, agg.id).await?;
+    }
+    ct.rollback().await?;
+
+    for (mut instance, m) in instances.into_iter().zip(matched) {
+        if let Some(m) = m {
+            instance.config.flavor = m.flavor;
+            instance
+                .metadata
+                .insert(MATCH_KEY.to_owned(), serde_json::to_value(m)?);
+            instance.update(t).await?;
+        }
+    }
+
+    Ok(true)
+}
+
+async fn tell_owner(
+    t: &mut EasyTransaction<'_>,
+    agg: &Aggregate,
+    waited: Duration,
+    started: bool,
+) -> Result<(), anyhow::Error> {
+    let name = agg
+        .metadata
+        .display_name
+        .clone()
+        .or(agg.metadata.booking_id.clone())
+        .unwrap_or_else(|| agg.id.into_id().to_string());
+
+    let Some(owner) = agg.metadata.owner.clone() else {
+        return Ok(());
+    };
+
+    let project = agg.lab.get(t).await?.name.clone();
+    let info = QueuedBookingInfo {
+        booking: name.clone(),
+        purpose: agg.metadata.purpose.clone().unwrap_or_default(),
+        waited_hours: waited.num_hours(),
+        started,
+        dashboard_url: settings()
+            .projects
+            .get(project.as_str())
+            .map(|p| p.dashboard_url.clone())
+            .unwrap_or_default(),
+    };
+
+    if let Err(errors) = booking_queue(&Env { project }, &owner, &info).await {
+        tracing::error!("Couldn't tell {owner} about queued booking {name}: {errors:?}");
+    }
+
+    Ok(())
+}
+
+/// Takes a queued booking out of the queue without it ever getting hosts, letting go of
+/// the networks it was holding
+pub async fn withdraw(
+    t: &mut EasyTransaction<'_>,
+    agg_id: FKey<Aggregate>,
+    details: String,
+    actor: Option<String>,
+) -> Result<(), anyhow::Error> {
+    let mut agg = agg_id.get(t).await?;
+
+    Allocator::instance()
+        .deallocate_aggregate(t, agg_id)
+        .await?;
+
+    agg.metadata.end = Some(Utc::now());
+    Aggregate::transition(&mut agg, t, LifeCycleState::Done, details.clone(), actor.clone())
+        .await?;
+
+    BookingEvent::record(t, agg_id, BookingEventKind::Ended, details, actor).await?;
+    AggregateSummary::refresh(t, agg_id).await?;
+
+    Ok(())
+}
+
+/// Goes through the queue once, starting every booking that hosts are free for
+pub async fn admit_queued(config: &BookingQueueConfig) -> Result<(), anyhow::Error> {
+    let mut client = new_client().await?;
+    let mut transaction = client.easy_transaction().await?;
+
+    let now = Utc::now();
+    let mut aggregates = Aggregate::select()
+        .where_field("lifecycle_state")
+        .equals(LifeCycleState::Queued)
+        .run(&mut transaction)
+        .await?;
+    aggregates.sort_by_key(|a| a.metadata.start);
+
+    let mut admitted = Vec::new();
+    for mut agg in aggregates {
+        let queued_at = agg.metadata.start.unwrap_or(now);
+        let waited = now - queued_at;
+
+        if waited > Duration::hours(config.max_wait_hours) {
+            withdraw(
+                &mut transaction,
+                agg.id,
+                format!(
+                    "no hosts freed up within {} hours, so the booking was taken out of the queue",
+                    config.max_wait_hours
+                ),
+                None,
+            )
+            .await?;
+            tell_owner(&mut transaction, &agg, waited, false).await?;
+            continue;
+        }
+
+        // hosts are allocated until the end of the booking, so it has to be moved up first
+        let length = agg.metadata.end.map(|end| end - queued_at);
+        agg.metadata.start = Some(now);
+        agg.metadata.end = length.map(|l| now + l);
+
+        let mut ct = transaction.easy_transaction().await?;
+        agg.update(&mut ct).await?;
+        if !fits(&mut ct, &agg).await? {
+            ct.rollback().await?;
+            continue;
+        }
+
+        Aggregate::transition(
+            &mut agg,
+            &mut ct,
+            LifeCycleState::New,
+            "hosts freed up, so it left the queue",
+            None,
+        )
+        .await?;
+        BookingEvent::record(
+            &mut ct,
+            agg.id,
+            BookingEventKind::Dequeued,
+            format!(
+                "hosts freed up after {} hours in the queue",
+                waited.num_hours()
+            ),
+            None,
+        )
+        .await?;
+        AggregateSummary::refresh(&mut ct, agg.id).await?;
+        ct.commit().await?;
+
+        tell_owner(&mut transaction, &agg, waited, true).await?;
+        admitted.push(agg.id);
+    }
+
+    transaction.commit().await?;
+
+    // only once the bookings are out of the queue for good
+    let dispatch = DISPATCH
+        .get()
+        .ok_or(anyhow::anyhow!("dispatcher isn't running"))?;
+    for agg_id in admitted {
+        if let Err(e) = dispatch.send(Action::DeployBooking { agg_id }) {
+            tracing::error!("Failed to send deploy task for {agg_id:?} with error {e:#?}");
+        }
+    }
+
+    Ok(())
+}
+
+/// Watches the queue forever, unless queueing has been disabled
+pub async fn entry() {
+    let config = settings().booking_queue.clone();
+    if !config.enabled {
+        tracing::info!("The booking queue is disabled");
+        return;
+    }
+
+    let mut interval =
+        tokio::time::interval(std::time::Duration::from_secs(config.interval_minutes * 60));
+
+    loop {
+        interval.tick().await;
+
+        if let Err(e) = admit_queued(&config).await {
+            tracing::error!("Couldn't go through the booking queue: {e:?}");
+        }
+    }
+}
@@ -0,0 +1,713 @@
+//! Nightly comparison of what the database says the lab looks like against what the
+//! lab hardware reports
+//!
+//! State that drifted (a host someone powered on by hand, a switch port left on a
+//! booking's vlan, a booking that outlived its allocation) gets noticed before it
+//! causes a failed deploy or leaks traffic between bookings.
+//!
+//! Each host is classed as free (no active allocation), booked (allocated to an
+//! active booking that has an instance on it), or in flux (provisioning, under
+//! maintenance, ...). Power and vlans are only checked for free and booked hosts,
+//! since hosts in flux are expected to be changing. DHCP reservations in cobbler are
+//! checked for every host.
+//!
+//! When `remediate` is on, drift on free hosts is fixed: they're powered off and
+//! their ports put back on the empty network config. Nothing a booking could be
+//! relying on is ever changed unattended.
+
+use std::collections::{BTreeSet, HashMap};
+
+use common::prelude::{
+    anyhow,
+    chrono::{DateTime, Duration, Utc},
+    serde_json, tokio, tracing,
+};
+use config::settings;
+use dal::{new_client, AsEasyTransaction, DBTable, EasyTransaction, FKey, NewRow};
+use models::{
+    allocator::{Allocation, AllocationReason, ResourceHandle},
+    dashboard::{Aggregate, Instance, LifeCycleState},
+    inventory::{Drift, DriftKind, DriftReport, Host, Switch},
+};
+use notifications::email::send_to_admins;
+
+use crate::{
+    deploy_booking::{
+        net_config::{empty_network_config, prod_network_config},
+        set_host_power_state::{
+            get_host_power_state, set_host_power_state, HostConfig, PowerState, TimeoutConfig,
+        },
+    },
+    resource_management::{
+        cisco::{self, NXCommand},
+        cobbler::CobblerActions,
+        network::NetworkConfig,
+        sonic::{adams_law, SonicSwitch},
+    },
+    utils::python::PythonBuilder,
+};
+
+/// NX-OS reports this as the native vlan of a trunk that wasn't given one
+const NXOS_DEFAULT_NATIVE_VLAN: i16 = 1;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PortVlans {
+    pub native: Option<i16>,
+    pub tagged: BTreeSet<i16>,
+}
+
+impl std::fmt::Display for PortVlans {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let tagged: Vec<String> = self.tagged.iter().map(|v| v.to_string()).collect();
+        match self.native {
+            Some(n) => write!(f, "native {n}, tagged [{}]", tagged.join(",")),
+            None => write!(f, "no native, tagged [{}]", tagged.join(",")),
+        }
+    }
+}
+
+impl PortVlans {
+    /// Whether a port carrying `actual` is configured like `self` calls for
+    ///
+    /// Booked ports may carry extra tagged vlans, since external networks are trunked
+    /// onto them without being part of the booking's network config.
+    fn matches(&self, actual: &PortVlans, allow_extra_tagged: bool) -> bool {
+        self.native == actual.native
+            && if allow_extra_tagged {
+                self.tagged.is_subset(&actual.tagged)
+            } else {
+                self.tagged == actual.tagged
+            }
+    }
+}
+
+/// Parses an NX-OS vlan list like `1,98-99,101`
+fn parse_vlan_list(list: &str) -> BTreeSet<i16> {
+    let mut vlans = BTreeSet::new();
+
+    for part in list.split(',').map(str::trim) {
+        match part.split_once('-') {
+            Some((start, end)) => {
+                if let (Ok(start), Ok(end)) =
+                    (start.trim().parse::<i16>(), end.trim().parse::<i16>())
+                {
+                    vlans.extend(start..=end);
+                }
+            }
+            None => {
+                if let Ok(v) = part.parse() {
+                    vlans.insert(v);
+                }
+            }
+        }
+    }
+
+    vlans
+}
+
+/// The vlans of each interface in the body of a `show interface switchport` reply
+fn parse_nx_switchports(body: &serde_json::Value) -> HashMap<String, PortVlans> {
+    let rows = match &body["TABLE_interface"]["ROW_interface"] {
+        serde_json::Value::Array(rows) => rows.clone(),
+        row @ serde_json::Value::Object(_) => vec![row.clone()],
+        _ => Vec::new(),
+    };
+
+    let mut ports = HashMap::new();
+    for row in rows {
+        let Some(name) = row["interface"].as_str() else {
+            continue;
+        };
+
+        let native = row["native_vlan"]
+            .as_str()
+            .and_then(|v| v.trim().parse().ok())
+            .filter(|&v| v != NXOS_DEFAULT_NATIVE_VLAN);
+        let mut tagged = parse_vlan_list(row["trunk_vlans"].as_str().unwrap_or_default());
+        if let Some(n) = native {
+            tagged.remove(&n);
+        }
+
+        ports.insert(name.to_owned(), PortVlans { native, tagged });
+    }
+
+    ports
+}
+
+/// How long from `now` until the next run at `hour_utc`
+fn until_next_run(now: DateTime<Utc>, hour_utc: u32) -> Duration {
+    let today = now
+        .date_naive()
+        .and_hms_opt(hour_utc.min(23), 0, 0)
+        .unwrap()
+        .and_utc();
+
+    if today > now {
+        today - now
+    } else {
+        today + Duration::days(1) - now
+    }
+}
+
+fn expected_port_vlans(
+    config: &NetworkConfig,
+    vlan_ids: &HashMap<FKey<models::inventory::Vlan>, i16>,
+) -> HashMap<FKey<models::inventory::HostPort>, PortVlans> {
+    let mut ports = HashMap::new();
+
+    for bg in config.bondgroups.iter() {
+        let mut vlans = PortVlans::default();
+        for connection in bg.vlans.iter() {
+            let Some(&id) = vlan_ids.get(&connection.vlan) else {
+                continue;
+            };
+            if connection.tagged {
+                vlans.tagged.insert(id);
+            } else {
+                vlans.native = Some(id);
+            }
+        }
+        if let Some(n) = vlans.native {
+            vlans.tagged.remove(&n);
+        }
+
+        for &port in bg.member_host_ports.iter() {
+            ports.insert(port, vlans.clone());
+        }
+    }
+
+    ports
+}
+
+enum Expectation {
+    Free,
+    Booked,
+}
+
+/// A switch port whose vlans are checked against what its host's state calls for
+struct PortCheck {
+    host: usize,
+    switch_port: String,
+    expected: PortVlans,
+}
+
+struct HostCheck {
+    host: Host,
+    expectation: Expectation,
+    network: NetworkConfig,
+}
+
+/// Classes every host, recording allocation drift found along the way
+async fn plan(
+    t: &mut EasyTransaction<'_>,
+    findings: &mut Vec<Drift>,
+) -> Result<(Vec<HostCheck>, HashMap<FKey<Switch>, Vec<PortCheck>>), anyhow::Error> {
+    // which active booking instance each host is linked to
+    let mut linked: HashMap<FKey<Host>, (FKey<Aggregate>, FKey<Instance>)> = HashMap::new();
+    let aggregates = Aggregate::select()
+        .where_field("lifecycle_state")
+        .equals(LifeCycleState::Active)
+        .run(t)
+        .await?;
+    for agg in aggregates.iter().filter(|a| !a.deleted) {
+        for instance in agg.instances(t).await? {
+            if let Some(host) = instance.linked_host {
+                linked.insert(host, (agg.id, instance.id));
+            }
+        }
+    }
+
+    let vlan_ids: HashMap<_, _> = models::inventory::Vlan::select()
+        .run(t)
+        .await?
+        .into_iter()
+        .map(|v| (v.id, v.vlan_id))
+        .collect();
+
+    let mut hosts = Vec::new();
+    let mut ports: HashMap<FKey<Switch>, Vec<PortCheck>> = HashMap::new();
+
+    for host in Host::select().run(t).await? {
+        let host = host.into_inner();
+        let handle = match ResourceHandle::handle_for_host(t, host.id).await {
+            Ok(h) => h,
+            Err(e) => {
+                tracing::warn!("Not reconciling {}: {e:?}", host.server_name);
+                continue;
+            }
+        };
+
+        let mut booked_by = None;
+        let mut in_flux = false;
+        let allocations = Allocation::find(t, handle.id, false).await?;
+        for allocation in allocations.iter() {
+            let Some(agg_id) = allocation.for_aggregate else {
+                in_flux = true;
+                continue;
+            };
+            let agg = agg_id.get(t).await?;
+
+            if agg.deleted || agg.state.ended() {
+                findings.push(Drift {
+                    host: host.server_name.clone(),
+                    kind: DriftKind::StaleAllocation,
+                    details: format!(
+                        "still allocated to booking {:?}, which has ended",
+                        agg.metadata.booking_id
+                    ),
+                    remediated: false,
+                });
+                in_flux = true;
+            } else if agg.state == LifeCycleState::Active
+                && matches!(allocation.reason_started, AllocationReason::ForBooking)
+            {
+                match linked.get(&host.id) {
+                    Some((linked_agg, instance)) if *linked_agg == agg.id => {
+                        booked_by = Some(*instance)
+                    }
+                    _ => {
+                        findings.push(Drift {
+                            host: host.server_name.clone(),
+                            kind: DriftKind::StaleAllocation,
+                            details: format!(
+                                "allocated to active booking {:?}, but none of its instances use the host",
+                                agg.metadata.booking_id
+                            ),
+                            remediated: false,
+                        });
+                        in_flux = true;
+                    }
+                }
+            } else {
+                in_flux = true;
+            }
+        }
+
+        if let Some((agg, _)) = linked.get(&host.id) {
+            let held = allocations.iter().any(|a| a.for_aggregate == Some(*agg));
+            if !held {
+                findings.push(Drift {
+                    host: host.server_name.clone(),
+                    kind: DriftKind::MissingAllocation,
+                    details: format!(
+                        "used by an instance of aggregate {:?} without being allocated to it",
+                        agg.into_id()
+                    ),
+                    remediated: false,
+                });
+                continue;
+            }
+        }
+
+        let (expectation, network) = match (booked_by, in_flux) {
+            (_, true) => continue,
+            (Some(instance), false) => (
+                Expectation::Booked,
+                prod_network_config(host.id, instance, t).await,
+            ),
+            (None, false) if allocations.is_empty() => {
+                (Expectation::Free, empty_network_config(host.id, t).await)
+            }
+            (None, false) => continue,
+        };
+
+        let expected = expected_port_vlans(&network, &vlan_ids);
+        for port in host.ports(t).await? {
+            let (Some(sp), Some(vlans)) = (port.switchport, expected.get(&port.id)) else {
+                continue;
+            };
+            let sp = sp.get(t).await?;
+            ports.entry(sp.for_switch).or_default().push(PortCheck {
+                host: hosts.len(),
+                switch_port: sp.name.clone(),
+                expected: vlans.clone(),
+            });
+        }
+
+        hosts.push(HostCheck {
+            host,
+            expectation,
+            network,
+        });
+    }
+
+    Ok((hosts, ports))
+}
+
+/// What each port of the switch carries, keyed by the port names the inventory uses
+pub(crate) async fn switch_port_vlans(
+    switch: &Switch,
+    os: &str,
+    port_names: Vec<String>,
+) -> Result<HashMap<String, PortVlans>, anyhow::Error> {
+    let switch = switch.clone();
+    let os = os.to_owned();
+
+    // both clients block, and panic rather than return some errors
+    tokio::task::spawn_blocking(move || match os.as_str() {
+        "NXOS" => {
+            let body = NXCommand::for_switch(switch.ip)
+                .with_credentials(switch.user, switch.pass)
+                .and_then("show interface switchport")
+                .show()?;
+            Ok(parse_nx_switchports(&body))
+        }
+        "SONiC" => {
+            let sonic = SonicSwitch::with_user_pass_auth(&switch.ip, &switch.user, &switch.pass);
+            let interfaces = sonic.interface_vlans();
+
+            Ok(port_names
+                .into_iter()
+                .map(|name| {
+                    let (native, tagged) = interfaces
+                        .get(&adams_law(name.clone()))
+                        .cloned()
+                        .unwrap_or_default();
+                    let mut vlans = PortVlans {
+                        native,
+                        tagged: tagged.into_iter().collect(),
+                    };
+                    if let Some(n) = native {
+                        vlans.tagged.remove(&n);
+                    }
+                    (name, vlans)
+                })
+                .collect())
+        }
+        other => Err(anyhow::Error::msg(format!(
+            "can't read vlans from a switch running {other}"
+        ))),
+    })
+    .await
+    .map_err(|e| anyhow::Error::msg(format!("switch client crashed: {e}")))?
+}
+
+/// The MACs cobbler serves each system, by system name
+fn cobbler_systems() -> Result<HashMap<String, BTreeSet<String>>, anyhow::Error> {
+    let json = PythonBuilder::<CobblerActions>::command("system_macs")
+        .run_and(|o| o.extract::<String>())
+        .map_err(|e| anyhow::Error::msg(format!("couldn't list cobbler systems: {e}")))?
+        .map_err(|e| anyhow::Error::msg(format!("cobbler returned something odd: {e}")))?;
+
+    let systems: HashMap<String, Vec<String>> = serde_json::from_str(&json)?;
+
+    Ok(systems
+        .into_iter()
+        .map(|(name, macs)| (name, macs.iter().map(|m| m.to_lowercase()).collect()))
+        .collect())
+}
+
+/// Whether the host still has no live allocations, read afresh since a booking
+/// may have claimed it after the plan was made
+async fn still_free(host: FKey<Host>) -> bool {
+    let free = async {
+        let mut client = new_client().await?;
+        let mut transaction = client.easy_transaction().await?;
+        let handle = ResourceHandle::handle_for_host(&mut transaction, host).await?;
+        let live = Allocation::find(&mut transaction, handle.id, false).await?;
+        transaction.commit().await?;
+
+        Ok::<_, anyhow::Error>(live.is_empty())
+    };
+
+    free.await
+        .inspect_err(|e| tracing::warn!("Couldn't recheck allocations of {host:?}: {e:?}"))
+        .unwrap_or(false)
+}
+
+async fn check_power(check: &HostCheck, remediate: bool) -> Option<Drift> {
+    let Expectation::Free = check.expectation else {
+        // users can power their booked hosts however they like
+        return None;
+    };
+
+    let bmc = match HostConfig::try_from(&check.host) {
+        Ok(b) => b,
+        Err(e) => {
+            tracing::warn!("Can't reach BMC of {}: {e:?}", check.host.server_name);
+            return None;
+        }
+    };
+
+    match get_host_power_state(&bmc).await {
+        Ok(PowerState::On) => {
+            if !still_free(check.host.id).await {
+                return None;
+            }
+
+            let remediated = remediate
+                && set_host_power_state(&bmc, TimeoutConfig::default(), PowerState::Off)
+                    .await
+                    .inspect_err(|e| {
+                        tracing::error!("Couldn't power off {}: {e:?}", check.host.server_name)
+                    })
+                    .is_ok();
+
+            Some(Drift {
+                host: check.host.server_name.clone(),
+                kind: DriftKind::UnexpectedPower,
+                details: "powered on while not allocated to anything".to_owned(),
+                remediated,
+            })
+        }
+        Ok(_) => None,
+        Err(e) => {
+            tracing::warn!(
+                "Couldn't get power state of {}: {e:?}",
+                check.host.server_name
+            );
+            None
+        }
+    }
+}
+
+/// Compares the database against the lab, fixing safe drift if `remediate` is set,
+/// and saves what was found
+pub async fn reconcile(remediate: bool) -> Result<DriftReport, anyhow::Error> {
+    let mut client = new_client().await?;
+    let mut transaction = client.easy_transaction().await?;
+
+    let mut findings = Vec::new();
+    let (hosts, ports) = plan(&mut transaction, &mut findings).await?;
+
+    let mut switches = Vec::new();
+    for (switch, checks) in ports {
+        let switch = switch.get(&mut transaction).await?.into_inner();
+        let os = match switch.switch_os {
+            Some(os) => os.get(&mut transaction).await?.os_type.clone(),
+            None => String::new(),
+        };
+        switches.push((switch, os, checks));
+    }
+
+    transaction.commit().await?;
+
+    for check in hosts.iter() {
+        if let Some(drift) = check_power(check, remediate).await {
+            findings.push(drift);
+        }
+    }
+
+    let mut needs_network = BTreeSet::new();
+    for (switch, os, checks) in switches {
+        let names = checks.iter().map(|c| c.switch_port.clone()).collect();
+        let actual = match switch_port_vlans(&switch, &os, names).await {
+            Ok(a) => a,
+            Err(e) => {
+                tracing::warn!("Couldn't read vlans from switch {}: {e:?}", switch.name);
+                continue;
+            }
+        };
+
+        for check in checks {
+            let host = &hosts[check.host];
+            let booked = matches!(host.expectation, Expectation::Booked);
+            let actual = actual.get(&check.switch_port).cloned().unwrap_or_default();
+
+            if !check.expected.matches(&actual, booked) {
+                // the network task only knows how to configure NX-OS switches
+                let fixable = remediate && !booked && os == "NXOS";
+                if fixable {
+                    needs_network.insert(check.host);
+                }
+
+                findings.push(Drift {
+                    host: host.host.server_name.clone(),
+                    kind: DriftKind::VlanMismatch,
+                    details: format!(
+                        "{} {} should have {}, but has {actual}",
+                        switch.name, check.switch_port, check.expected
+                    ),
+                    remediated: fixable,
+                });
+            }
+        }
+    }
+
+    for index in needs_network {
+        let host = &hosts[index];
+        if !still_free(host.host.id).await {
+            // its vlans now belong to whatever claimed it
+            findings
+                .retain(|d| d.host != host.host.server_name || d.kind != DriftKind::VlanMismatch);
+            continue;
+        }
+
+        let fixed = tokio::spawn(cisco::nx_run_network_task(host.network.clone())).await;
+        if let Err(e) = fixed {
+            tracing::error!("Couldn't reset network of {}: {e:?}", host.host.server_name);
+            for drift in findings.iter_mut() {
+                if drift.host == host.host.server_name && drift.kind == DriftKind::VlanMismatch {
+                    drift.remediated = false;
+                }
+            }
+        }
+    }
+
+    match cobbler_systems() {
+        Ok(systems) => {
+            let mut client = new_client().await?;
+            let mut transaction = client.easy_transaction().await?;
+
+            for host in Host::select().run(&mut transaction).await? {
+                let macs: BTreeSet<String> = host
+                    .ports(&mut transaction)
+                    .await?
+                    .iter()
+                    .map(|p| p.mac.to_string().to_lowercase())
+                    .collect();
+
+                match systems.get(&host.server_name) {
+                    None => findings.push(Drift {
+                        host: host.server_name.clone(),
+                        kind: DriftKind::DhcpMissing,
+                        details: "cobbler has no system for the host".to_owned(),
+                        remediated: false,
+                    }),
+                    Some(served) if *served != macs => findings.push(Drift {
+                        host: host.server_name.clone(),
+                        kind: DriftKind::DhcpMismatch,
+                        details: format!(
+                            "cobbler serves {served:?}, but the host's ports are {macs:?}"
+                        ),
+                        remediated: false,
+                    }),
+                    Some(_) => (),
+                }
+            }
+
+            transaction.commit().await?;
+        }
+        Err(e) => tracing::warn!("Not checking dhcp reservations: {e:?}"),
+    }
+
+    let report = DriftReport {
+        id: FKey::new_id_dangling(),
+        time: Utc::now(),
+        findings,
+    };
+
+    let mut client = new_client().await?;
+    let mut transaction = client.easy_transaction().await?;
+    NewRow::new(report.clone()).insert(&mut transaction).await?;
+    transaction.commit().await?;
+
+    Ok(report)
+}
+
+/// Reconciles every night at the configured hour, unless it's been disabled
+pub async fn entry() {
+    let config = settings().reconciler.clone();
+    if !config.enabled {
+        tracing::info!("Reconciler is disabled, not checking for drift");
+        return;
+    }
+
+    loop {
+        let wait = until_next_run(Utc::now(), config.hour_utc);
+        tokio::time::sleep(wait.to_std().unwrap_or_default()).await;
+
+        match reconcile(config.remediate).await {
+            Ok(report) if report.findings.is_empty() => {
+                tracing::info!("Reconciler found no drift")
+            }
+            Ok(report) => {
+                let lines: Vec<String> = report
+                    .findings
+                    .iter()
+                    .map(|d| {
+                        let fixed = if d.remediated { " (fixed)" } else { "" };
+                        format!("{}: {}, {}{fixed}", d.host, d.kind, d.details)
+                    })
+                    .collect();
+
+                send_to_admins(format!(
+                    "Nightly reconciliation found {} drifted resources:<br>{}",
+                    lines.len(),
+                    lines.join("<br>")
+                ))
+                .await;
+            }
+            Err(e) => tracing::error!("Couldn't reconcile lab state: {e:?}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::prelude::chrono::TimeZone;
+
+    #[test]
+    fn test_parse_vlan_list() {
+        assert_eq!(
+            parse_vlan_list("1,98-99, 101"),
+            BTreeSet::from([1, 98, 99, 101])
+        );
+        assert!(parse_vlan_list("none").is_empty());
+    }
+
+    #[test]
+    fn test_parse_nx_switchports() {
+        let body = serde_json::json!({
+            "TABLE_interface": {
+                "ROW_interface": [
+                    { "interface": "Ethernet1/1", "native_vlan": "98", "trunk_vlans": "98-99" },
+                    { "interface": "Ethernet1/2", "native_vlan": "1", "trunk_vlans": "99" },
+                ]
+            }
+        });
+
+        let ports = parse_nx_switchports(&body);
+        assert_eq!(
+            ports["Ethernet1/1"],
+            PortVlans {
+                native: Some(98),
+                tagged: BTreeSet::from([99])
+            }
+        );
+        assert_eq!(
+            ports["Ethernet1/2"],
+            PortVlans {
+                native: None,
+                tagged: BTreeSet::from([99])
+            }
+        );
+
+        // a switch with a single interface doesn't wrap it in a list
+        let body = serde_json::json!({
+            "TABLE_interface": {
+                "ROW_interface": { "interface": "Ethernet1/3", "native_vlan": "1", "trunk_vlans": "1-4094" }
+            }
+        });
+        assert_eq!(
+            parse_nx_switchports(&body)["Ethernet1/3"].tagged.len(),
+            4094
+        );
+    }
+
+    #[test]
+    fn test_matches() {
+        let expected = PortVlans {
+            native: Some(101),
+            tagged: BTreeSet::from([99]),
+        };
+        let extra = PortVlans {
+            native: Some(101),
+            tagged: BTreeSet::from([99, 3001]),
+        };
+
+        assert!(expected.matches(&extra, true));
+        assert!(!expected.matches(&extra, false));
+        assert!(!expected.matches(&PortVlans::default(), true));
+    }
+
+    #[test]
+    fn test_until_next_run() {
+        let at = |h, m| Utc.with_ymd_and_hms(2024, 1, 1, h, m, 0).unwrap();
+
+        assert_eq!(until_next_run(at(1, 30), 4), Duration::minutes(150));
+        assert_eq!(until_next_run(at(4, 0), 4), Duration::hours(24));
+        assert_eq!(until_next_run(at(23, 0), 4), Duration::hours(5));
+    }
+}
@@ -0,0 +1,498 @@
+//! When each host is taken over a window of time, for drawing a calendar of lab occupancy
+//!
+//! Running and past bookings come from the allocations of each host, scheduled bookings
+//! from its reservations. A reservation stops being shown once the booking holding it has
+//! allocated the host, since the allocation is shown in its place.
+
+use std::collections::{hash_map::Entry, HashMap};
+
+use common::prelude::{
+    anyhow,
+    chrono::{DateTime, Duration, Utc},
+};
+use dal::{DBTable, EasyTransaction, FKey};
+use models::{
+    allocator::{Allocation, AllocationReason, Reservation, ResourceHandle},
+    dashboard::Aggregate,
+    inventory::{Flavor, Host, Lab},
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, JsonSchema, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum IntervalKind {
+    /// A booking holds the host
+    Booked,
+    /// A booking that hasn't started yet has reserved the host
+    Scheduled,
+    Maintenance,
+    Retired,
+}
+
+/// What the schedule shows of the booking behind an interval
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema, PartialEq)]
+pub struct IntervalBooking {
+    pub aggregate: FKey<Aggregate>,
+    /// The display name of the booking, or its dashboard id if it has none
+    pub name: Option<String>,
+    pub owner: Option<String>,
+    pub purpose: Option<String>,
+    /// When the booking is due to end
+    #[schemars(with = "Option<String>")]
+    pub end: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, PartialEq)]
+pub struct ScheduleInterval {
+    pub kind: IntervalKind,
+    #[schemars(with = "String")]
+    pub start: DateTime<Utc>,
+    /// None if the interval is open ended
+    #[schemars(with = "Option<String>")]
+    pub end: Option<DateTime<Utc>>,
+    pub booking: Option<IntervalBooking>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct HostSchedule {
+    pub host: FKey<Host>,
+    pub server_name: String,
+    /// Earliest first
+    pub intervals: Vec<ScheduleInterval>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct FlavorSchedule {
+    pub flavor: FKey<Flavor>,
+    pub name: String,
+    pub hosts: Vec<HostSchedule>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Schedule {
+    #[schemars(with = "String")]
+    pub from: DateTime<Utc>,
+    #[schemars(with = "String")]
+    pub to: DateTime<Utc>,
+    pub flavors: Vec<FlavorSchedule>,
+}
+
+/// How many hosts of a flavor are free on one day
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, PartialEq)]
+pub struct DayAvailability {
+    /// Like `2025-04-01`, in UTC
+    pub date: String,
+    /// Hosts that nothing holds or has reserved at any point of the day
+    pub free: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct FlavorAvailability {
+    pub flavor: FKey<Flavor>,
+    pub name: String,
+    /// Hosts of the flavor there are in all
+    pub hosts: usize,
+    /// Earliest first
+    pub days: Vec<DayAvailability>,
+}
+
+fn overlaps(
+    start: DateTime<Utc>,
+    end: Option<DateTime<Utc>>,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> bool {
+    start < to && end.map_or(true, |end| end > from)
+}
+
+/// The intervals of one host that overlap `[from, to)`, earliest first
+///
+/// Bookings still holding the host are shown until they are due to end, as found
+/// in `bookings`, or open ended if that isn't known.
+fn host_intervals(
+    allocations: &[Allocation],
+    reservations: &[Reservation],
+    bookings: &HashMap<FKey<Aggregate>, IntervalBooking>,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> Vec<ScheduleInterval> {
+    let booking = |agg: Option<FKey<Aggregate>>| agg.and_then(|a| bookings.get(&a).cloned());
+
+    let mut intervals = Vec::new();
+    for allocation in allocations {
+        let booking = booking(allocation.for_aggregate);
+        let (kind, end) = match allocation.reason_started {
+            AllocationReason::ForBooking => (
+                IntervalKind::Booked,
+                allocation.ended.or(booking.as_ref().and_then(|b| b.end)),
+            ),
+            AllocationReason::ForMaintenance => (IntervalKind::Maintenance, allocation.ended),
+            AllocationReason::ForRetiry => (IntervalKind::Retired, allocation.ended),
+        };
+
+        intervals.push(ScheduleInterval {
+            kind,
+            start: allocation.started,
+            end,
+            booking,
+        });
+    }
+
+    for reservation in reservations.iter().filter(|r| !r.cancelled) {
+        let started = allocations
+            .iter()
+            .any(|a| a.for_aggregate == Some(reservation.for_aggregate));
+        if started {
+            continue;
+        }
+
+        intervals.push(ScheduleInterval {
+            kind: IntervalKind::Scheduled,
+            start: reservation.starts,
+            end: reservation.ends,
+            booking: booking(Some(reservation.for_aggregate)),
+        });
+    }
+
+    intervals.retain(|i| overlaps(i.start, i.end, from, to));
+    intervals.sort_by_key(|i| i.start);
+
+    intervals
+}
+
+async fn interval_booking(
+    t: &mut EasyTransaction<'_>,
+    agg: FKey<Aggregate>,
+) -> Result<IntervalBooking, anyhow::Error> {
+    let agg = agg.get(t).await?;
+
+    Ok(IntervalBooking {
+        aggregate: agg.id,
+        name: agg
+            .metadata
+            .display_name
+            .clone()
+            .or(agg.metadata.booking_id.clone()),
+        owner: agg.metadata.owner.clone(),
+        purpose: agg.metadata.purpose.clone(),
+        end: agg.metadata.end,
+    })
+}
+
+/// The schedule of every host over `[from, to)`, of only `flavor` if given
+pub async fn schedule(
+    t: &mut EasyTransaction<'_>,
+    flavor: Option<FKey<Flavor>>,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> Result<Schedule, anyhow::Error> {
+    let flavors = match flavor {
+        Some(flavor) => vec![flavor.get(t).await?],
+        None => Flavor::select().run(t).await?,
+    };
+
+    let mut bookings = HashMap::new();
+    let mut schedules = Vec::new();
+    for flavor in flavors {
+        let mut hosts = Vec::new();
+        for host in Host::select()
+            .where_field("flavor")
+            .equals(flavor.id)
+            .run(t)
+            .await?
+        {
+            let handle = ResourceHandle::handle_for_host(t, host.id).await?;
+
+            let mut allocations = Allocation::find(t, handle.id, false).await?;
+            allocations.extend(Allocation::find(t, handle.id, true).await?);
+            let allocations = allocations
+                .into_iter()
+                .map(|a| a.into_inner())
+                .filter(|a| overlaps(a.started, a.ended, from, to))
+                .collect::<Vec<_>>();
+            let reservations = Reservation::select()
+                .where_field("for_resource")
+                .equals(handle.id)
+                .run(t)
+                .await?
+                .into_iter()
+                .map(|r| r.into_inner())
+                .filter(|r| overlaps(r.starts, r.ends, from, to))
+                .collect::<Vec<_>>();
+
+            let aggregates = allocations
+                .iter()
+                .filter_map(|a| a.for_aggregate)
+                .chain(reservations.iter().map(|r| r.for_aggregate));
+            for agg in aggregates.collect::<Vec<_>>() {
+                if let Entry::Vacant(e) = bookings.entry(agg) {
+                    e.insert(interval_booking(t, agg).await?);
+                }
+            }
+
+            hosts.push(HostSchedule {
+                host: host.id,
+                server_name: host.server_name.clone(),
+                intervals: host_intervals(&allocations, &reservations, &bookings, from, to),
+            });
+        }
+
+        hosts.sort_by(|a, b| a.server_name.cmp(&b.server_name));
+
+        schedules.push(FlavorSchedule {
+            flavor: flavor.id,
+            name: flavor.name.clone(),
+            hosts,
+        });
+    }
+
+    schedules.sort_by(|a, b| a.name.cmp(&b.name));
+
+    Ok(Schedule {
+        from,
+        to,
+        flavors: schedules,
+    })
+}
+
+/// How many hosts of each flavor are free on each day of `schedule`, for a calendar of when
+/// bookings can start. The first and last days are cut to the window of the schedule
+pub fn availability(schedule: &Schedule) -> Vec<FlavorAvailability> {
+    let mut days = Vec::new();
+    let mut day = schedule.from;
+    while day < schedule.to {
+        let midnight = (day.date_naive() + Duration::days(1))
+            .and_hms_opt(0, 0, 0)
+            .expect("midnight is a time")
+            .and_utc();
+        days.push((day, midnight.min(schedule.to)));
+        day = midnight;
+    }
+
+    schedule
+        .flavors
+        .iter()
+        .map(|flavor| FlavorAvailability {
+            flavor: flavor.flavor,
+            name: flavor.name.clone(),
+            hosts: flavor.hosts.len(),
+            days: days
+                .iter()
+                .map(|(from, to)| DayAvailability {
+                    date: from.format("%F").to_string(),
+                    free: flavor
+                        .hosts
+                        .iter()
+                        .filter(|h| {
+                            !h.intervals
+                                .iter()
+                                .any(|i| overlaps(i.start, i.end, *from, *to))
+                        })
+                        .count(),
+                })
+                .collect(),
+        })
+        .collect()
+}
+
+/// The hosts of `flavor` in `lab` that nothing holds or has reserved at any point of
+/// `[from, to)`, which a booking over that window could be given
+pub async fn free_hosts(
+    t: &mut EasyTransaction<'_>,
+    flavor: FKey<Flavor>,
+    lab: FKey<Lab>,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> Result<Vec<FKey<Host>>, anyhow::Error> {
+    let schedule = schedule(t, Some(flavor), from, to).await?;
+
+    let mut free = Vec::new();
+    for host in schedule
+        .flavors
+        .iter()
+        .flat_map(|f| f.hosts.iter())
+        .filter(|h| h.intervals.is_empty())
+    {
+        if ResourceHandle::handle_for_host(t, host.host).await?.lab == Some(lab) {
+            free.push(host.host);
+        }
+    }
+
+    Ok(free)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn allocation(
+        agg: Option<FKey<Aggregate>>,
+        reason: AllocationReason,
+        started: DateTime<Utc>,
+        ended: Option<DateTime<Utc>>,
+    ) -> Allocation {
+        Allocation {
+            id: FKey::new_id_dangling(),
+            for_resource: FKey::new_id_dangling(),
+            for_aggregate: agg,
+            started,
+            ended,
+            reason_started: reason,
+            reason_ended: None,
+        }
+    }
+
+    fn reservation(
+        agg: FKey<Aggregate>,
+        starts: DateTime<Utc>,
+        ends: Option<DateTime<Utc>>,
+    ) -> Reservation {
+        Reservation {
+            id: FKey::new_id_dangling(),
+            for_resource: FKey::new_id_dangling(),
+            for_aggregate: agg,
+            starts,
+            ends,
+            cancelled: false,
+        }
+    }
+
+    #[test]
+    fn test_host_intervals() {
+        let now = Utc::now();
+        let days = Duration::days;
+        let (running, scheduled, started, past) = (
+            FKey::new_id_dangling(),
+            FKey::new_id_dangling(),
+            FKey::new_id_dangling(),
+            FKey::new_id_dangling(),
+        );
+
+        let bookings = HashMap::from([(
+            running,
+            IntervalBooking {
+                aggregate: running,
+                end: Some(now + days(2)),
+                ..Default::default()
+            },
+        )]);
+
+        let allocations = [
+            allocation(Some(running), AllocationReason::ForBooking, now, None),
+            allocation(
+                Some(started),
+                AllocationReason::ForBooking,
+                now - days(3),
+                Some(now - days(2)),
+            ),
+            // over before the window starts
+            allocation(
+                Some(past),
+                AllocationReason::ForBooking,
+                now - days(30),
+                Some(now - days(20)),
+            ),
+            allocation(
+                None,
+                AllocationReason::ForMaintenance,
+                now - days(1),
+                Some(now),
+            ),
+        ];
+        let mut cancelled = reservation(scheduled, now + days(6), None);
+        cancelled.cancelled = true;
+        let reservations = [
+            reservation(scheduled, now + days(3), Some(now + days(5))),
+            // the booking already holds the host, so its allocation stands in for it
+            reservation(started, now - days(3), Some(now - days(2))),
+            cancelled,
+        ];
+
+        let intervals = host_intervals(
+            &allocations,
+            &reservations,
+            &bookings,
+            now - days(7),
+            now + days(7),
+        );
+
+        let summary = intervals
+            .iter()
+            .map(|i| (i.kind, i.start, i.end))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            vec![
+                (IntervalKind::Booked, now - days(3), Some(now - days(2))),
+                (IntervalKind::Maintenance, now - days(1), Some(now)),
+                (IntervalKind::Booked, now, Some(now + days(2))),
+                (IntervalKind::Scheduled, now + days(3), Some(now + days(5))),
+            ]
+        );
+        assert_eq!(
+            intervals[2].booking.as_ref().map(|b| b.aggregate),
+            Some(running)
+        );
+
+        // a window entirely in the future still shows what is running into it
+        let later = host_intervals(
+            &allocations,
+            &[],
+            &HashMap::new(),
+            now + days(1),
+            now + days(2),
+        );
+        assert_eq!(later.len(), 1);
+        assert_eq!(later[0].end, None);
+    }
+
+    #[test]
+    fn test_availability() {
+        let from = "2025-04-01T12:00:00Z".parse::<DateTime<Utc>>().unwrap();
+        let to = from + Duration::days(2);
+        let host = |intervals| HostSchedule {
+            host: FKey::new_id_dangling(),
+            server_name: String::new(),
+            intervals,
+        };
+        let interval = |kind, start, end| ScheduleInterval {
+            kind,
+            start,
+            end,
+            booking: None,
+        };
+        let schedule = Schedule {
+            from,
+            to,
+            flavors: vec![FlavorSchedule {
+                flavor: FKey::new_id_dangling(),
+                name: "x86".to_owned(),
+                hosts: vec![
+                    host(vec![]),
+                    // ends just as the second day starts
+                    host(vec![interval(
+                        IntervalKind::Booked,
+                        from - Duration::days(1),
+                        Some("2025-04-02T00:00:00Z".parse().unwrap()),
+                    )]),
+                    host(vec![interval(
+                        IntervalKind::Scheduled,
+                        from + Duration::hours(36),
+                        None,
+                    )]),
+                ],
+            }],
+        };
+        let availability = availability(&schedule);
+        assert_eq!(availability[0].hosts, 3);
+        assert_eq!(
+            availability[0]
+                .days
+                .iter()
+                .map(|d| (d.date.as_str(), d.free))
+                .collect::<Vec<_>>(),
+            vec![("2025-04-01", 2), ("2025-04-02", 3), ("2025-04-03", 2)]
+        );
+    }
+}
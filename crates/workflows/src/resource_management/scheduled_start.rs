@@ -0,0 +1,71 @@
+//! Starting bookings that were made ahead of time, once the time they were booked from comes
+//!
+//! Scheduled bookings are written out with their instances and networks like any other, but
+//! only hold reservations on their hosts. Once a booking is due it goes on to provisioning
+//! like one made just then, and the allocator gives it the hosts it reserved.
+
+use common::prelude::{anyhow, chrono::Utc, tokio, tracing};
+use dal::{new_client, AsEasyTransaction, DBTable};
+use models::dashboard::{Aggregate, AggregateSummary, LifeCycleState};
+
+use crate::entry::{Action, DISPATCH};
+
+/// How often scheduled bookings are checked for being due
+const CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// Starts provisioning every scheduled booking whose start has come
+pub async fn start_due() -> Result<(), anyhow::Error> {
+    let mut client = new_client().await?;
+    let mut transaction = client.easy_transaction().await?;
+
+    let now = Utc::now();
+    let due = Aggregate::select()
+        .where_field("lifecycle_state")
+        .equals(LifeCycleState::Scheduled)
+        .run(&mut transaction)
+        .await?
+        .into_iter()
+        .filter(|a| a.metadata.start.map_or(true, |start| start <= now));
+
+    let mut started = Vec::new();
+    for mut agg in due {
+        Aggregate::transition(
+            &mut agg,
+            &mut transaction,
+            LifeCycleState::New,
+            "its start came, so it is provisioned",
+            None,
+        )
+        .await?;
+        AggregateSummary::refresh(&mut transaction, agg.id).await?;
+
+        started.push(agg.id);
+    }
+
+    transaction.commit().await?;
+
+    let dispatch = DISPATCH
+        .get()
+        .ok_or(anyhow::anyhow!("dispatcher isn't running"))?;
+    for agg_id in started {
+        tracing::info!("Scheduled booking {agg_id:?} is due, starting it");
+        if let Err(e) = dispatch.send(Action::DeployBooking { agg_id }) {
+            tracing::error!("Failed to send deploy task for {agg_id:?} with error {e:#?}");
+        }
+    }
+
+    Ok(())
+}
+
+/// Starts scheduled bookings as they come due, forever
+pub async fn entry() {
+    let mut interval = tokio::time::interval(CHECK_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        if let Err(e) = start_due().await {
+            tracing::error!("Couldn't start the scheduled bookings that are due: {e:?}");
+        }
+    }
+}
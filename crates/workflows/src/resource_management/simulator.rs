@@ -0,0 +1,385 @@
+//! What-if simulation of a booking load against an inventory, for capacity planning
+//!
+//! Bookings arrive at their start time and are placed as soon as every host
+//! they ask for is free, otherwise they wait in line. A booking that waits
+//! longer than the allowed wait, or that asks for more hosts of a flavor than
+//! the inventory has at all, is rejected.
+
+use std::collections::{HashMap, VecDeque};
+
+use common::prelude::{
+    anyhow,
+    chrono::{DateTime, Duration, Utc},
+};
+use dal::{DBTable, EasyTransaction};
+use models::{
+    dashboard::Aggregate,
+    inventory::{Flavor, Host},
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// A booking as the simulator sees it, just when it wants hosts and which ones
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct SimulatedBooking {
+    /// When the booking asks to start (RFC 3339)
+    #[schemars(with = "String")]
+    pub arrival: DateTime<Utc>,
+    pub duration_hours: f64,
+    /// Number of hosts wanted of each flavor, by flavor name
+    pub hosts: HashMap<String, u32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct SimulationInput {
+    /// Number of hosts of each flavor, by flavor name
+    pub inventory: HashMap<String, u32>,
+    pub bookings: Vec<SimulatedBooking>,
+    /// How long a booking will wait for hosts before it is rejected
+    pub max_wait_hours: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, PartialEq)]
+pub struct FlavorUtilization {
+    pub hosts: u32,
+    /// Fraction of the available host-hours that were booked
+    pub utilization: f64,
+    pub peak_in_use: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, PartialEq)]
+pub struct SimulationReport {
+    pub bookings: usize,
+    pub served: usize,
+    /// Bookings that gave up waiting for hosts
+    pub rejected: usize,
+    /// Bookings that asked for more hosts than the inventory has
+    pub unsatisfiable: usize,
+    pub mean_wait_hours: f64,
+    pub p95_wait_hours: f64,
+    pub max_wait_hours: f64,
+    pub window_hours: f64,
+    pub flavors: HashMap<String, FlavorUtilization>,
+}
+
+fn hours(d: Duration) -> f64 {
+    d.num_seconds() as f64 / 3600.0
+}
+
+fn from_hours(h: f64) -> Duration {
+    Duration::seconds((h * 3600.0) as i64)
+}
+
+struct Running {
+    end: DateTime<Utc>,
+    hosts: HashMap<String, u32>,
+}
+
+struct Usage {
+    in_use: u32,
+    peak: u32,
+    host_seconds: i64,
+}
+
+pub fn simulate(input: &SimulationInput) -> SimulationReport {
+    let mut bookings: Vec<&SimulatedBooking> = input.bookings.iter().collect();
+    bookings.sort_by_key(|b| b.arrival);
+
+    let max_wait = from_hours(input.max_wait_hours);
+
+    let mut usage: HashMap<&str, Usage> = input
+        .inventory
+        .keys()
+        .map(|f| {
+            (
+                f.as_str(),
+                Usage {
+                    in_use: 0,
+                    peak: 0,
+                    host_seconds: 0,
+                },
+            )
+        })
+        .collect();
+
+    let fits = |usage: &HashMap<&str, Usage>, b: &SimulatedBooking, limit: bool| {
+        b.hosts.iter().all(|(flavor, count)| {
+            let total = input.inventory.get(flavor).copied().unwrap_or(0);
+            let in_use = match limit {
+                true => usage.get(flavor.as_str()).map(|u| u.in_use).unwrap_or(0),
+                false => 0,
+            };
+            in_use + count <= total
+        })
+    };
+
+    let mut running: Vec<Running> = Vec::new();
+    let mut queue: VecDeque<&SimulatedBooking> = VecDeque::new();
+    let mut waits: Vec<Duration> = Vec::new();
+    let (mut rejected, mut unsatisfiable) = (0, 0);
+
+    let first = bookings.first().map(|b| b.arrival);
+    let mut last = first;
+
+    let mut arrivals = bookings.into_iter().peekable();
+
+    loop {
+        let next_release = running.iter().map(|r| r.end).min();
+        let next_arrival = arrivals.peek().map(|b| b.arrival);
+
+        let now = match (next_arrival, next_release) {
+            (None, None) => break,
+            (Some(a), Some(r)) => a.min(r),
+            (Some(a), None) => a,
+            (None, Some(r)) => r,
+        };
+
+        // hosts are freed before anything new is placed at the same instant
+        running.retain(|r| {
+            if r.end > now {
+                return true;
+            }
+
+            for (flavor, count) in r.hosts.iter() {
+                if let Some(u) = usage.get_mut(flavor.as_str()) {
+                    u.in_use -= count;
+                }
+            }
+
+            false
+        });
+
+        while let Some(b) = arrivals.next_if(|b| b.arrival <= now) {
+            if fits(&usage, b, false) {
+                queue.push_back(b);
+            } else {
+                unsatisfiable += 1;
+            }
+        }
+
+        let mut waiting = VecDeque::new();
+        while let Some(b) = queue.pop_front() {
+            if now - b.arrival > max_wait {
+                rejected += 1;
+                continue;
+            }
+
+            if !fits(&usage, b, true) {
+                waiting.push_back(b);
+                continue;
+            }
+
+            let duration = from_hours(b.duration_hours);
+            for (flavor, count) in b.hosts.iter() {
+                if let Some(u) = usage.get_mut(flavor.as_str()) {
+                    u.in_use += count;
+                    u.peak = u.peak.max(u.in_use);
+                    u.host_seconds += *count as i64 * duration.num_seconds();
+                }
+            }
+
+            waits.push(now - b.arrival);
+            last = last.max(Some(now + duration));
+            running.push(Running {
+                end: now + duration,
+                hosts: b.hosts.clone(),
+            });
+        }
+        queue = waiting;
+    }
+
+    // nothing is left running, so whatever is still in line can never be placed
+    rejected += queue.len();
+
+    let window = match (first, last) {
+        (Some(f), Some(l)) => l - f,
+        _ => Duration::zero(),
+    };
+
+    waits.sort();
+    let mean_wait_hours = match waits.len() {
+        0 => 0.0,
+        n => waits.iter().map(|w| hours(*w)).sum::<f64>() / n as f64,
+    };
+    let p95_wait_hours = match waits.len() {
+        0 => 0.0,
+        n => hours(waits[((n as f64 * 0.95).ceil() as usize).clamp(1, n) - 1]),
+    };
+
+    let flavors = input
+        .inventory
+        .iter()
+        .map(|(flavor, hosts)| {
+            let u = &usage[flavor.as_str()];
+            let available = *hosts as i64 * window.num_seconds();
+            let utilization = match available {
+                0 => 0.0,
+                a => u.host_seconds as f64 / a as f64,
+            };
+
+            (
+                flavor.clone(),
+                FlavorUtilization {
+                    hosts: *hosts,
+                    utilization,
+                    peak_in_use: u.peak,
+                },
+            )
+        })
+        .collect();
+
+    SimulationReport {
+        bookings: input.bookings.len(),
+        served: waits.len(),
+        rejected,
+        unsatisfiable,
+        mean_wait_hours,
+        p95_wait_hours,
+        max_wait_hours: waits.last().copied().map(hours).unwrap_or(0.0),
+        window_hours: hours(window),
+        flavors,
+    }
+}
+
+/// Number of hosts of each flavor currently in the inventory, by flavor name
+pub async fn current_inventory(
+    t: &mut EasyTransaction<'_>,
+) -> Result<HashMap<String, u32>, anyhow::Error> {
+    let mut inventory = HashMap::new();
+
+    for host in Host::select().run(t).await? {
+        let flavor = host.flavor.get(t).await?;
+        *inventory.entry(flavor.name.clone()).or_insert(0) += 1;
+    }
+
+    Ok(inventory)
+}
+
+/// Every past booking that started at or after `since`, as a load to replay
+pub async fn booking_history(
+    t: &mut EasyTransaction<'_>,
+    since: Option<DateTime<Utc>>,
+) -> Result<Vec<SimulatedBooking>, anyhow::Error> {
+    let mut flavor_names: HashMap<_, String> = HashMap::new();
+    let mut history = Vec::new();
+
+    for agg in Aggregate::select().run(t).await? {
+        let (start, end) = match (agg.metadata.start, agg.metadata.end) {
+            (Some(start), Some(end)) if end > start => (start, end),
+            _ => continue,
+        };
+
+        if since.is_some_and(|since| start < since) {
+            continue;
+        }
+
+        let mut hosts = HashMap::new();
+        for instance in agg.instances(t).await? {
+            let flavor = instance.config.flavor;
+            let name = match flavor_names.get(&flavor) {
+                Some(name) => name.clone(),
+                None => {
+                    let name = flavor.get(t).await?.name.clone();
+                    flavor_names.insert(flavor, name.clone());
+                    name
+                }
+            };
+
+            *hosts.entry(name).or_insert(0) += 1;
+        }
+
+        history.push(SimulatedBooking {
+            arrival: start,
+            duration_hours: hours(end - start),
+            hosts,
+        });
+    }
+
+    Ok(history)
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, Default)]
+pub struct SimulationRequest {
+    /// The inventory to simulate against, the current inventory if not given
+    pub inventory: Option<HashMap<String, u32>>,
+    /// Hosts to add to (or remove from, if negative) each flavor of the inventory
+    #[serde(default)]
+    pub add_hosts: HashMap<String, i64>,
+    /// The load to simulate, replays booking history if not given
+    pub bookings: Option<Vec<SimulatedBooking>>,
+    /// When replaying history, only replay bookings that started at or after this (RFC 3339)
+    #[schemars(with = "Option<String>")]
+    pub replay_since: Option<DateTime<Utc>>,
+    /// Defaults to 24 hours
+    pub max_wait_hours: Option<f64>,
+}
+
+/// Fills in anything the request leaves out from the database, then runs the simulation
+pub async fn run_request(
+    t: &mut EasyTransaction<'_>,
+    request: SimulationRequest,
+) -> Result<SimulationReport, anyhow::Error> {
+    let mut inventory = match request.inventory {
+        Some(i) => i,
+        None => current_inventory(t).await?,
+    };
+
+    for (flavor, delta) in request.add_hosts {
+        let count = inventory.entry(flavor).or_insert(0);
+        *count = (*count as i64 + delta).max(0) as u32;
+    }
+
+    let bookings = match request.bookings {
+        Some(b) => b,
+        None => booking_history(t, request.replay_since).await?,
+    };
+
+    // make sure flavors that exist but aren't in the request still get reported on
+    for flavor in Flavor::select().run(t).await? {
+        inventory.entry(flavor.name.clone()).or_insert(0);
+    }
+
+    Ok(simulate(&SimulationInput {
+        inventory,
+        bookings,
+        max_wait_hours: request.max_wait_hours.unwrap_or(24.0),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn booking(arrival_hour: i64, duration_hours: f64, hosts: u32) -> SimulatedBooking {
+        SimulatedBooking {
+            arrival: DateTime::<Utc>::UNIX_EPOCH + Duration::hours(arrival_hour),
+            duration_hours,
+            hosts: [("small".to_owned(), hosts)].into_iter().collect(),
+        }
+    }
+
+    #[test]
+    fn test_simulate() {
+        let report = simulate(&SimulationInput {
+            inventory: [("small".to_owned(), 2)].into_iter().collect(),
+            bookings: vec![
+                booking(0, 10.0, 2),
+                // waits 10 hours for the first booking to end
+                booking(0, 10.0, 1),
+                // only one host frees up before it gives up waiting
+                booking(1, 1.0, 2),
+                // more hosts than the lab has
+                booking(2, 1.0, 3),
+            ],
+            max_wait_hours: 12.0,
+        });
+
+        assert_eq!(report.served, 2);
+        assert_eq!(report.unsatisfiable, 1);
+        assert_eq!(report.rejected, 1);
+        assert_eq!(report.max_wait_hours, 10.0);
+        assert_eq!(report.window_hours, 20.0);
+        assert_eq!(report.flavors["small"].peak_in_use, 2);
+        assert_eq!(report.flavors["small"].utilization, 0.75);
+    }
+}
@@ -1,9 +1,13 @@
-use common::prelude::tracing::{self};
+use common::prelude::{
+    anyhow,
+    tracing::{self},
+};
 use dal::{new_client, AsEasyTransaction, FKey};
 
 use models::inventory::Switch;
 
 use super::network::NetworkConfig;
+use crate::utils::resilience::{self, Device};
 use serde::{Deserialize, Serialize};
 
 use ssh2::Session;
@@ -115,16 +119,10 @@ impl SonicSwitch {
 
     // Userauth-password.
     pub fn with_user_pass_auth(addr: &str, username: &str, password: &str) -> SonicSwitch {
-        let mut session =
-            Session::new().expect("Failed to create a new SSH session for SONiC switch.");
-        let connection = TcpStream::connect(format!("{addr}:22"))
-            .expect("Failed to open TCP stream to SONiC switch.");
-
-        session.set_tcp_stream(connection);
-        session.handshake().unwrap();
-        session
-            .userauth_password(username, password)
-            .expect("SSH basic authentication failed");
+        let mut session = resilience::call_blocking(Device::switch(addr), || {
+            Self::connect(addr, username, password)
+        })
+        .expect("Failed to open an SSH session to SONiC switch.");
 
         SonicSwitch {
             config: Self::pull_config(&mut session),
@@ -133,6 +131,17 @@ impl SonicSwitch {
         }
     }
 
+    fn connect(addr: &str, username: &str, password: &str) -> Result<Session, anyhow::Error> {
+        let mut session = Session::new()?;
+        let connection = TcpStream::connect(format!("{addr}:22"))?;
+
+        session.set_tcp_stream(connection);
+        session.handshake()?;
+        session.userauth_password(username, password)?;
+
+        Ok(session)
+    }
+
     /*
     Get an iterator that contains needed information to manipulate the VLAN Members and VLANs.
     */
@@ -256,6 +265,26 @@ impl SonicSwitch {
         }
     }
 
+    /// The untagged vlan and tagged vlans of each interface that is in any vlan
+    pub fn interface_vlans(&self) -> HashMap<String, (Option<i16>, Vec<i16>)> {
+        let mut vlans: HashMap<String, (Option<i16>, Vec<i16>)> = HashMap::new();
+
+        for (_, vlan_name, interface_name, member) in self.member_list() {
+            let Ok(vlan) = vlan_name.trim_start_matches("Vlan").parse::<i16>() else {
+                continue;
+            };
+
+            let entry = vlans.entry(interface_name).or_default();
+            if member.tagging_mode == "untagged" {
+                entry.0 = Some(vlan);
+            } else {
+                entry.1.push(vlan);
+            }
+        }
+
+        vlans
+    }
+
     // Queue a command to run.
     pub fn queue<S>(&mut self, input: S) -> &mut Self
     where
@@ -379,7 +408,45 @@ pub async fn sonic_run_network_task(ncfg: NetworkConfig) {
     }
 }
 
-fn adams_law(iface: String) -> String {
+/// Mirrors all traffic on `source` out of `destination` as span session `session`
+pub fn sonic_start_port_mirror(switch: &Switch, session: i16, source: &str, destination: &str) {
+    let mut sanic = SonicSwitch::with_user_pass_auth(&switch.ip, &switch.user, &switch.pass);
+
+    sanic.queue(format!(
+        "sudo config mirror_session span add laas_span_{session} {} {} both",
+        adams_law(destination.to_owned()),
+        adams_law(source.to_owned())
+    ));
+
+    // mirrors are always temporary, so they should never survive a reload
+    sanic.run_commands(false).unwrap();
+}
+
+pub fn sonic_stop_port_mirror(switch: &Switch, session: i16) {
+    let mut sanic = SonicSwitch::with_user_pass_auth(&switch.ip, &switch.user, &switch.pass);
+
+    sanic.queue(format!(
+        "sudo config mirror_session remove laas_span_{session}"
+    ));
+
+    sanic.run_commands(false).unwrap();
+}
+
+/// Adds (or removes) `vlan` as a tagged vlan on `port`, leaving its other vlans alone
+pub fn sonic_trunk_vlan(switch: &Switch, port: &str, vlan: i16, add: bool) {
+    let mut sanic = SonicSwitch::with_user_pass_auth(&switch.ip, &switch.user, &switch.pass);
+    let op = if add { "add" } else { "del" };
+
+    sanic.queue(format!(
+        "sudo config vlan member {op} {vlan} {}",
+        adams_law(port.to_owned())
+    ));
+
+    sanic.run_commands(true).unwrap();
+}
+
+/// Translates a port name from the inventory into the name SONiC gives the interface
+pub fn adams_law(iface: String) -> String {
     match iface {
         _ if iface.contains("GigE") && iface.contains('b') => {
             let mut nums = iface.split(&['E', 'b']);
@@ -0,0 +1,271 @@
+//! Opens tickets in the lab's issue tracker (Jira, ServiceNow or GitLab) when a host
+//! has a hardware problem, and records the ticket against the host
+//!
+//! Only one unresolved ticket is kept per host and kind of problem, so a host
+//! that keeps failing doesn't flood the tracker. Users asking for help with a booking
+//! are filed in the same tracker, see [`open_support_ticket`].
+
+use common::prelude::{anyhow, chrono::Utc, reqwest::Client, tracing};
+use config::{settings, TicketSystem, TicketingConfig};
+use dal::{new_client, AsEasyTransaction, EasyTransaction, FKey, NewRow};
+use models::inventory::{Host, HostBenchmark, HostTicket, TicketKind};
+use notifications::email::send_to_admins;
+use serde_json::{json, Value};
+
+pub struct OpenedTicket {
+    pub external_id: String,
+    pub url: Option<String>,
+}
+
+/// What a ticket is opened about, which decides where in the tracker it goes
+#[derive(Debug, Clone, Copy)]
+enum TicketPurpose {
+    HostFailure,
+    Support,
+}
+
+impl TicketPurpose {
+    fn project(self, config: &TicketingConfig) -> &str {
+        match self {
+            TicketPurpose::HostFailure => &config.project,
+            TicketPurpose::Support => config.support_project.as_ref().unwrap_or(&config.project),
+        }
+    }
+
+    fn jira_issue_type(self) -> &'static str {
+        match self {
+            TicketPurpose::HostFailure => "Bug",
+            TicketPurpose::Support => "Task",
+        }
+    }
+
+    fn servicenow_category(self) -> &'static str {
+        match self {
+            TicketPurpose::HostFailure => "hardware",
+            TicketPurpose::Support => "inquiry",
+        }
+    }
+}
+
+/// Everything an admin would want to know about the host before walking over to it
+async fn diagnostic_context(
+    t: &mut EasyTransaction<'_>,
+    host: &Host,
+    details: &str,
+) -> Result<String, anyhow::Error> {
+    let flavor = host.flavor.get(t).await?;
+    let benchmark = HostBenchmark::latest_for_host(t, host.id).await?;
+    let domain = &host.failure_domain;
+
+    Ok(format!(
+        "{details}\n\n\
+        Host: {}\n\
+        Serial: {}\n\
+        Flavor: {} ({} {})\n\
+        IPMI: {}\n\
+        Rack: {}, PDU: {}, Switch: {}\n\
+        Latest benchmark score: {}\n\n\
+        Opened automatically by LaaS.",
+        host.server_name,
+        host.serial,
+        flavor.name,
+        flavor.brand,
+        flavor.model,
+        host.ipmi_fqdn,
+        domain.rack.as_deref().unwrap_or("unknown"),
+        domain.pdu.as_deref().unwrap_or("unknown"),
+        domain.switch.as_deref().unwrap_or("unknown"),
+        benchmark
+            .map(|b| format!("{:.1}", b.score))
+            .unwrap_or("never benchmarked".to_owned()),
+    ))
+}
+
+async fn post(config: &TicketingConfig, url: String, body: Value) -> Result<Value, anyhow::Error> {
+    let request = Client::new().post(url).json(&body);
+    let request = match config.system {
+        TicketSystem::GitLab => request.header("PRIVATE-TOKEN", &config.token),
+        TicketSystem::Jira | TicketSystem::ServiceNow => request.basic_auth(
+            config.username.clone().unwrap_or_default(),
+            Some(&config.token),
+        ),
+    };
+
+    let response = request.send().await?;
+    let status = response.status();
+    if !status.is_success() {
+        return Err(anyhow::Error::msg(format!(
+            "{} returned {status}: {}",
+            config.system,
+            response.text().await.unwrap_or_default()
+        )));
+    }
+
+    Ok(response.json().await?)
+}
+
+fn field(v: &Value, path: &[&str]) -> Result<String, anyhow::Error> {
+    let mut v = v;
+    for key in path {
+        v = &v[key];
+    }
+
+    match v {
+        Value::String(s) => Ok(s.clone()),
+        Value::Number(n) => Ok(n.to_string()),
+        _ => Err(anyhow::Error::msg(format!(
+            "ticket response had no {}",
+            path.join(".")
+        ))),
+    }
+}
+
+async fn open_ticket(
+    config: &TicketingConfig,
+    purpose: TicketPurpose,
+    summary: &str,
+    description: &str,
+) -> Result<OpenedTicket, anyhow::Error> {
+    let base = config.url.trim_end_matches('/');
+    let project = purpose.project(config);
+
+    match config.system {
+        TicketSystem::Jira => {
+            let response = post(
+                config,
+                format!("{base}/rest/api/2/issue"),
+                json!({
+                    "fields": {
+                        "project": { "key": project },
+                        "issuetype": { "name": purpose.jira_issue_type() },
+                        "summary": summary,
+                        "description": description,
+                        "labels": config.labels,
+                    }
+                }),
+            )
+            .await?;
+
+            let key = field(&response, &["key"])?;
+            Ok(OpenedTicket {
+                url: Some(format!("{base}/browse/{key}")),
+                external_id: key,
+            })
+        }
+        TicketSystem::ServiceNow => {
+            let response = post(
+                config,
+                format!("{base}/api/now/table/incident"),
+                json!({
+                    "short_description": summary,
+                    "description": description,
+                    "assignment_group": project,
+                    "category": purpose.servicenow_category(),
+                }),
+            )
+            .await?;
+
+            let sys_id = field(&response, &["result", "sys_id"])?;
+            Ok(OpenedTicket {
+                external_id: field(&response, &["result", "number"])?,
+                url: Some(format!("{base}/nav_to.do?uri=incident.do?sys_id={sys_id}")),
+            })
+        }
+        TicketSystem::GitLab => {
+            // gitlab takes either a numeric id or the url encoded `group/project` path
+            let project = project.replace('/', "%2F");
+
+            let response = post(
+                config,
+                format!("{base}/api/v4/projects/{project}/issues"),
+                json!({
+                    "title": summary,
+                    "description": description,
+                    "labels": config.labels.join(","),
+                }),
+            )
+            .await?;
+
+            Ok(OpenedTicket {
+                external_id: format!("#{}", field(&response, &["iid"])?),
+                url: field(&response, &["web_url"]).ok(),
+            })
+        }
+    }
+}
+
+async fn report(host: FKey<Host>, kind: TicketKind, details: &str) -> Result<(), anyhow::Error> {
+    let config = match settings().ticketing.as_ref() {
+        Some(c) => c,
+        None => return Ok(()),
+    };
+
+    let mut client = new_client().await?;
+    let mut transaction = client.easy_transaction().await?;
+
+    if let Some(open) = HostTicket::all_for_host(&mut transaction, host)
+        .await?
+        .into_iter()
+        .find(|ticket| ticket.kind == kind && ticket.resolved.is_none())
+    {
+        tracing::info!(
+            "Host {host:?} {kind} again, ticket {} is already open for it",
+            open.external_id
+        );
+        return Ok(());
+    }
+
+    let host = host.get(&mut transaction).await?.into_inner();
+    let summary = format!("{} {kind}", host.server_name);
+    let description = diagnostic_context(&mut transaction, &host, details).await?;
+
+    let opened = open_ticket(config, TicketPurpose::HostFailure, &summary, &description).await?;
+    tracing::info!("Opened ticket {} for {summary}", opened.external_id);
+
+    NewRow::new(HostTicket {
+        id: FKey::new_id_dangling(),
+        host: host.id,
+        kind,
+        system: config.system.to_string(),
+        external_id: opened.external_id,
+        url: opened.url,
+        summary,
+        opened: Utc::now(),
+        resolved: None,
+    })
+    .insert(&mut transaction)
+    .await?;
+
+    transaction.commit().await?;
+
+    Ok(())
+}
+
+/// Opens a ticket for the host's problem, unless one is already open for it
+///
+/// This is best effort, if the ticket can't be opened the admins are emailed instead
+pub async fn report_host_failure(host: FKey<Host>, kind: TicketKind, details: String) {
+    if let Err(e) = report(host, kind, &details).await {
+        tracing::error!("Couldn't open a ticket for host {host:?}: {e:?}");
+
+        send_to_admins(format!(
+            "Host {host:?} {kind}, but a ticket couldn't be opened for it ({e}). Details: {details}"
+        ))
+        .await;
+    }
+}
+
+/// Files a user's request for help in the tracker, giving None if no tracker is configured
+pub async fn open_support_ticket(
+    summary: &str,
+    description: &str,
+) -> Result<Option<OpenedTicket>, anyhow::Error> {
+    let Some(config) = settings().ticketing.as_ref() else {
+        return Ok(None);
+    };
+
+    let opened = open_ticket(config, TicketPurpose::Support, summary, description).await?;
+    tracing::info!("Opened support ticket {} for {summary}", opened.external_id);
+
+    Ok(Some(opened))
+}
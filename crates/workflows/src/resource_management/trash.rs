@@ -0,0 +1,99 @@
+//! Purges bookings that have been in the trash for longer than they're kept
+//!
+//! Bookings are put in the trash once their teardown is done, with their hosts already
+//! back in the free pool. Their instances and logs stay around for the retention window so
+//! owners and admins can still look them up, and every run deletes those of bookings whose
+//! window is over.
+
+use common::prelude::{chrono::Utc, tokio, tracing};
+use config::settings;
+use dal::{new_client, AsEasyTransaction, ID};
+use models::dashboard::{AggregateSummary, TrashedBooking};
+use serde::{Deserialize, Serialize};
+use tascii::prelude::*;
+
+use crate::entry::{Action, DISPATCH};
+
+#[derive(Debug, Clone, Hash, Serialize, Deserialize)]
+pub struct PurgeTrash {}
+
+tascii::mark_task!(PurgeTrash);
+impl AsyncRunnable for PurgeTrash {
+    type Output = ();
+
+    async fn run(&mut self, _context: &Context) -> Result<Self::Output, TaskError> {
+        let mut client = new_client().await?;
+        let mut transaction = client.easy_transaction().await?;
+
+        let due = TrashedBooking::due(&mut transaction, Utc::now()).await?;
+        transaction.commit().await?;
+
+        let mut purged = 0;
+        for trashed in due {
+            let agg = trashed.aggregate;
+
+            // each booking is purged on its own, so one that can't be doesn't hold up the rest
+            let mut transaction = client.easy_transaction().await?;
+            let res = async {
+                trashed.into_inner().purge(&mut transaction).await?;
+                AggregateSummary::refresh(&mut transaction, agg).await
+            }
+            .await;
+
+            match res {
+                Ok(()) => {
+                    transaction.commit().await?;
+                    purged += 1;
+                }
+                Err(e) => {
+                    tracing::error!("Couldn't purge booking {agg:?} from the trash: {e:?}");
+                    transaction.rollback().await?;
+                }
+            }
+        }
+
+        if purged > 0 {
+            tracing::info!("Purged {purged} booking(s) from the trash");
+        }
+
+        Ok(())
+    }
+
+    fn identifier() -> TaskIdentifier {
+        TaskIdentifier::named("PurgeTrashTask").versioned(1)
+    }
+
+    fn summarize(&self, id: ID) -> String {
+        format!("PurgeTrash with id {id}")
+    }
+
+    fn retry_count(&self) -> usize {
+        0
+    }
+}
+
+/// Periodically queues up a purge of the trash, unless bookings are kept forever
+pub async fn entry() {
+    let config = settings().trash.clone();
+    if !config.purge {
+        tracing::info!("Trash purging is disabled, ended bookings are kept forever");
+        return;
+    }
+
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(
+        config.interval_hours * 60 * 60,
+    ));
+
+    loop {
+        interval.tick().await;
+
+        match DISPATCH.get() {
+            Some(dispatch) => {
+                if let Err(e) = dispatch.send(Action::PurgeTrash) {
+                    tracing::error!("Couldn't queue trash purge: {e:?}");
+                }
+            }
+            None => tracing::warn!("Dispatcher isn't running yet, skipping trash purge"),
+        }
+    }
+}
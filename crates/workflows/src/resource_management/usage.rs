@@ -0,0 +1,239 @@
+//! What a booking used, so projects can be billed for their bookings or at least reported on
+//!
+//! Host-hours come from the allocations of the booking, and power-on time from the power
+//! actions in its provision logs together with what its health checks saw. Every install of
+//! an image is counted from the logs too, which is multiplied out to the bandwidth it took
+//! for images whose size is configured.
+
+use std::collections::HashMap;
+
+use common::prelude::{
+    anyhow,
+    chrono::{DateTime, Duration, Utc},
+};
+use config::settings;
+use dal::{EasyTransaction, FKey};
+use models::{
+    allocator::{Allocation, ResourceHandleInner},
+    dashboard::{Aggregate, Image, InstanceHealthCheck, ProvisionLogEvent},
+    inventory::{Flavor, Host},
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// What deploying a host logs as it starts writing its image
+pub const INSTALL_EVENT: &str = "Installing OS";
+
+/// Logged as LibLaaS powers a host on
+const POWER_ON_EVENTS: &[&str] = &["Powering Host On"];
+/// Logged as LibLaaS powers a host off
+const POWER_OFF_EVENTS: &[&str] = &["Powering Host Off", "Shutting Down Host"];
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, PartialEq)]
+pub struct FlavorUsage {
+    pub flavor: FKey<Flavor>,
+    pub name: String,
+    pub hosts: usize,
+    pub host_hours: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, PartialEq)]
+pub struct ImageUsage {
+    pub image: FKey<Image>,
+    pub name: String,
+    pub installs: usize,
+    /// None if the size of the image isn't configured
+    pub transferred_gb: Option<f64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, PartialEq)]
+pub struct BookingUsage {
+    pub aggregate: FKey<Aggregate>,
+    pub project: Option<String>,
+    pub flavors: Vec<FlavorUsage>,
+    pub host_hours: f64,
+    pub power_on_hours: f64,
+    pub images: Vec<ImageUsage>,
+    /// None if the size of any image that was installed isn't configured
+    pub transferred_gb: Option<f64>,
+    /// Usage of a booking that is still running is counted up to now
+    pub until: String,
+}
+
+fn hours(duration: Duration) -> f64 {
+    duration.num_seconds() as f64 / 3600.0
+}
+
+/// How long a host was powered on before `until`, given whether it was seen or made to be
+/// powered on at each of `observations`
+///
+/// A host is taken to stay as it was last seen until it is seen again, and nothing is
+/// counted before it was first seen.
+pub fn powered_on_for(
+    mut observations: Vec<(DateTime<Utc>, bool)>,
+    until: DateTime<Utc>,
+) -> Duration {
+    observations.sort_by_key(|&(time, _)| time);
+
+    let mut total = Duration::zero();
+    for (i, &(time, on)) in observations.iter().enumerate() {
+        if !on || time >= until {
+            continue;
+        }
+
+        let next = observations
+            .get(i + 1)
+            .map(|&(t, _)| t)
+            .unwrap_or(until)
+            .min(until);
+        total += next - time;
+    }
+
+    total
+}
+
+/// When the power state of the host was seen or changed, according to its logs and checks
+fn power_observations(
+    logs: &[impl std::ops::Deref<Target = ProvisionLogEvent>],
+    checks: &[impl std::ops::Deref<Target = InstanceHealthCheck>],
+) -> Vec<(DateTime<Utc>, bool)> {
+    let from_logs = logs.iter().filter_map(|l| {
+        let event = l.prov_status.event.as_str();
+        if POWER_ON_EVENTS.contains(&event) {
+            Some((l.time, true))
+        } else if POWER_OFF_EVENTS.contains(&event) {
+            Some((l.time, false))
+        } else {
+            None
+        }
+    });
+    let from_checks = checks.iter().map(|c| (c.time, c.powered_on));
+
+    from_logs.chain(from_checks).collect()
+}
+
+/// Works out what `agg_id` used, up to now if it's still running
+pub async fn booking_usage(
+    t: &mut EasyTransaction<'_>,
+    agg_id: FKey<Aggregate>,
+) -> Result<BookingUsage, anyhow::Error> {
+    let agg = agg_id.get(t).await?;
+    let now = Utc::now();
+
+    // host-hours, and until when each host was the booking's
+    let mut held_until: HashMap<FKey<Host>, DateTime<Utc>> = HashMap::new();
+    let mut by_flavor: HashMap<FKey<Flavor>, (Vec<FKey<Host>>, Duration)> = HashMap::new();
+    for allocation in Allocation::all_for_aggregate(t, agg_id).await? {
+        let ResourceHandleInner::Host(host) = allocation.for_resource.get(t).await?.tracks else {
+            continue;
+        };
+        let ended = allocation.ended.unwrap_or(now);
+
+        let until = held_until.entry(host).or_insert(ended);
+        *until = (*until).max(ended);
+
+        let flavor = host.get(t).await?.flavor;
+        let (hosts, held) = by_flavor
+            .entry(flavor)
+            .or_insert((Vec::new(), Duration::zero()));
+        if !hosts.contains(&host) {
+            hosts.push(host);
+        }
+        *held += (ended - allocation.started).max(Duration::zero());
+    }
+
+    let mut flavors = Vec::new();
+    for (flavor, (hosts, held)) in by_flavor {
+        flavors.push(FlavorUsage {
+            flavor,
+            name: flavor.get(t).await?.name.clone(),
+            hosts: hosts.len(),
+            host_hours: hours(held),
+        });
+    }
+    flavors.sort_by(|a, b| a.name.cmp(&b.name));
+
+    // power-on time and installs, from what was logged and checked for each instance
+    let mut powered_on = Duration::zero();
+    let mut installs: HashMap<FKey<Image>, usize> = HashMap::new();
+    for instance in agg.instances(t).await? {
+        let logs = ProvisionLogEvent::all_for_instance(t, instance.id).await?;
+        let checks = InstanceHealthCheck::all_for_instance(t, instance.id).await?;
+
+        let until = instance
+            .linked_host
+            .and_then(|h| held_until.get(&h).copied())
+            .unwrap_or(now);
+        powered_on += powered_on_for(power_observations(&logs, &checks), until);
+
+        let installed = logs
+            .iter()
+            .filter(|l| l.prov_status.event == INSTALL_EVENT)
+            .count();
+        if installed > 0 {
+            *installs.entry(instance.config.image).or_default() += installed;
+        }
+    }
+
+    let sizes = &settings().accounting.image_sizes_gb;
+    let mut images = Vec::new();
+    for (image, installs) in installs {
+        let name = image.get(t).await?.name.clone();
+        images.push(ImageUsage {
+            image,
+            transferred_gb: sizes.get(&name).map(|gb| gb * installs as f64),
+            name,
+            installs,
+        });
+    }
+    images.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let until = match agg.state.ended() {
+        true => held_until.values().max().copied().unwrap_or(now),
+        false => now,
+    };
+
+    Ok(BookingUsage {
+        aggregate: agg_id,
+        project: agg.metadata.project.clone(),
+        host_hours: flavors.iter().map(|f| f.host_hours).sum(),
+        flavors,
+        power_on_hours: hours(powered_on),
+        transferred_gb: images.iter().map(|i| i.transferred_gb).sum(),
+        images,
+        until: until.to_rfc2822(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(minute: i64) -> DateTime<Utc> {
+        DateTime::<Utc>::UNIX_EPOCH + Duration::minutes(minute)
+    }
+
+    #[test]
+    fn test_powered_on_for() {
+        let observations = vec![
+            (at(30), false),
+            (at(0), true),
+            (at(10), true),
+            (at(50), true),
+        ];
+
+        // on from 0 to 30, and from 50 up to when it was given back
+        assert_eq!(
+            powered_on_for(observations.clone(), at(60)),
+            Duration::minutes(40)
+        );
+        // nothing past when it was given back counts
+        assert_eq!(powered_on_for(observations, at(20)), Duration::minutes(20));
+
+        assert_eq!(powered_on_for(vec![], at(60)), Duration::zero());
+        assert_eq!(
+            powered_on_for(vec![(at(0), false)], at(60)),
+            Duration::zero()
+        );
+    }
+}
@@ -0,0 +1,151 @@
+//! Holding off actions LibLaaS takes on its own that tear hosts down, until someone is
+//! around at the lab to step in if they go wrong
+//!
+//! Each project can set the hours it is worked in with `working_hours`, in the time zone
+//! of the lab and minus its holidays. Outside of them, actions like ending expired bookings
+//! are left for the first run that falls inside them. Projects without working hours are
+//! always open.
+
+use std::collections::HashSet;
+
+use chrono_tz::Tz;
+use common::prelude::{
+    anyhow,
+    chrono::{DateTime, Datelike, NaiveDate, Timelike, Utc, Weekday},
+    tracing,
+};
+use config::{settings, WorkingHoursConfig};
+
+/// The working hours of a project, ready to check times against
+#[derive(Debug, Clone)]
+pub struct WorkingHours {
+    timezone: Tz,
+    days: HashSet<Weekday>,
+    start_hour: u32,
+    end_hour: u32,
+    holidays: HashSet<NaiveDate>,
+}
+
+impl WorkingHours {
+    pub fn from_config(config: &WorkingHoursConfig) -> Result<Self, anyhow::Error> {
+        let timezone = config
+            .timezone
+            .parse::<Tz>()
+            .map_err(|e| anyhow::anyhow!("{} isn't a time zone: {e}", config.timezone))?;
+
+        let days = config
+            .days
+            .iter()
+            .map(|d| {
+                d.parse::<Weekday>()
+                    .map_err(|_| anyhow::anyhow!("{d} isn't a day of the week"))
+            })
+            .collect::<Result<_, _>>()?;
+
+        if config.start_hour >= config.end_hour || config.end_hour > 24 {
+            return Err(anyhow::anyhow!(
+                "working hours have to start before they end, within the day, not {} to {}",
+                config.start_hour,
+                config.end_hour
+            ));
+        }
+
+        let holidays = config
+            .holidays
+            .iter()
+            .map(|h| {
+                h.parse::<NaiveDate>()
+                    .map_err(|e| anyhow::anyhow!("holiday {h} isn't a YYYY-MM-DD date: {e}"))
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self {
+            timezone,
+            days,
+            start_hour: config.start_hour,
+            end_hour: config.end_hour,
+            holidays,
+        })
+    }
+
+    /// Whether `now` falls in the working hours, in the time zone of the lab
+    pub fn is_open(&self, now: DateTime<Utc>) -> bool {
+        let local = now.with_timezone(&self.timezone);
+
+        self.days.contains(&local.weekday())
+            && !self.holidays.contains(&local.date_naive())
+            && (self.start_hour..self.end_hour).contains(&local.hour())
+    }
+}
+
+/// Whether LibLaaS may tear hosts of `project` down on its own at `now`
+///
+/// Working hours that can't be read keep the project closed, so that a typo in them
+/// doesn't have hosts torn down at night.
+pub fn is_open(project: &str, now: DateTime<Utc>) -> bool {
+    let Some(config) = settings()
+        .projects
+        .get(project)
+        .and_then(|p| p.working_hours.as_ref())
+    else {
+        return true;
+    };
+
+    match WorkingHours::from_config(config) {
+        Ok(hours) => hours.is_open(now),
+        Err(e) => {
+            tracing::error!(
+                "The working hours of {project} are misconfigured, so it is treated as closed: {e}"
+            );
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::prelude::chrono::TimeZone;
+
+    #[test]
+    fn test_working_hours() {
+        let hours = WorkingHours::from_config(&WorkingHoursConfig {
+            timezone: "America/New_York".to_owned(),
+            days: vec!["mon".to_owned(), "Tuesday".to_owned(), "wed".to_owned()],
+            start_hour: 9,
+            end_hour: 17,
+            holidays: vec!["2024-07-03".to_owned()],
+        })
+        .unwrap();
+        let at = |m, d, h| Utc.with_ymd_and_hms(2024, m, d, h, 30, 0).unwrap();
+
+        // a Monday, when New York is four hours behind
+        assert!(hours.is_open(at(7, 1, 13)));
+        assert!(hours.is_open(at(7, 1, 20)));
+        assert!(!hours.is_open(at(7, 1, 12)));
+        assert!(!hours.is_open(at(7, 1, 21)));
+        // a holiday, then a Thursday
+        assert!(!hours.is_open(at(7, 3, 15)));
+        assert!(!hours.is_open(at(7, 4, 15)));
+        // a Monday in winter, when it is five hours behind
+        assert!(!hours.is_open(at(1, 8, 13)));
+        assert!(hours.is_open(at(1, 8, 14)));
+
+        let broken = |timezone: &str, days: &[&str], start_hour, end_hour, holiday: &str| {
+            WorkingHours::from_config(&WorkingHoursConfig {
+                timezone: timezone.to_owned(),
+                days: days.iter().map(|d| d.to_string()).collect(),
+                start_hour,
+                end_hour,
+                holidays: vec![holiday.to_owned()],
+            })
+            .is_err()
+        };
+        assert!(broken("Mars/Olympus", &["mon"], 9, 17, "2024-01-01"));
+        assert!(broken("UTC", &["someday"], 9, 17, "2024-01-01"));
+        assert!(broken("UTC", &["mon"], 17, 9, "2024-01-01"));
+        assert!(broken("UTC", &["mon"], 9, 25, "2024-01-01"));
+        assert!(broken("UTC", &["mon"], 9, 17, "01/01/2024"));
+        assert!(!broken("UTC", &["mon"], 0, 24, "2024-01-01"));
+    }
+}
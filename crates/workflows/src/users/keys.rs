@@ -0,0 +1,271 @@
+//! Giving and taking away logins on the hosts of a running booking
+//!
+//! Hosts get the ssh keys of the users of their booking through cloud-init when they're
+//! deployed. Collaborators added or removed after that are pushed to the hosts by logging
+//! in as the `host_access` account every host is given, which needs to be configured.
+
+use std::{io::Read, net::TcpStream, time::Duration};
+
+use config::settings;
+use dal::{new_client, AsEasyTransaction, FKey, ID};
+use models::dashboard::{Aggregate, LifeCycleState, ProvEvent, StatusSentiment};
+use serde::{Deserialize, Serialize};
+use ssh2::Session;
+use tascii::{prelude::*, task_trait::AsyncRunnable};
+use users::ipa;
+
+use crate::utils::status_feed;
+
+/// Whether `name` can be put in a shell command as it is
+fn is_safe_username(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('-')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'))
+}
+
+/// The shell script that gives each of `added` a sudo login with their keys, and takes
+/// the login of each of `removed` away
+fn access_script(added: &[(String, Vec<String>)], removed: &[String]) -> Result<String, String> {
+    let mut script = vec!["set -e".to_owned()];
+
+    for (user, keys) in added {
+        if !is_safe_username(user) {
+            return Err(format!(
+                "{user:?} isn't a username that can be added to a host"
+            ));
+        }
+        if let Some(key) = keys.iter().find(|k| k.contains(['\'', '\n', '\r'])) {
+            return Err(format!("{user} has a malformed ssh key on file: {key:?}"));
+        }
+
+        script.push(format!(
+            "id -u {user} >/dev/null 2>&1 || sudo useradd -m -s /bin/bash {user}"
+        ));
+        script.push(format!("sudo usermod -U {user} 2>/dev/null || true"));
+        script.push(format!(
+            "sudo install -d -m 700 -o {user} -g {user} ~{user}/.ssh"
+        ));
+        script.push(format!(
+            "printf '%s\\n' {} | sudo tee ~{user}/.ssh/authorized_keys >/dev/null",
+            keys.iter()
+                .map(|k| format!("'{k}'"))
+                .collect::<Vec<_>>()
+                .join(" ")
+        ));
+        script.push(format!(
+            "sudo chown {user}:{user} ~{user}/.ssh/authorized_keys && sudo chmod 600 ~{user}/.ssh/authorized_keys"
+        ));
+        script.push(format!(
+            "echo '{user} ALL=(ALL) NOPASSWD:ALL' | sudo tee /etc/sudoers.d/laas-{user} >/dev/null"
+        ));
+    }
+
+    for user in removed {
+        if !is_safe_username(user) {
+            return Err(format!(
+                "{user:?} isn't a username that can be removed from a host"
+            ));
+        }
+
+        script.push(format!("if id -u {user} >/dev/null 2>&1; then"));
+        script.push(format!(
+            "  sudo truncate -s 0 ~{user}/.ssh/authorized_keys 2>/dev/null || true"
+        ));
+        script.push(format!("  sudo usermod -L {user}"));
+        script.push(format!("  sudo rm -f /etc/sudoers.d/laas-{user}"));
+        // cloud-init gives the users it creates sudo here
+        script.push(format!(
+            "  sudo sed -i '/^{user} /d' /etc/sudoers.d/90-cloud-init-users 2>/dev/null || true"
+        ));
+        script.push(format!("  sudo pkill -KILL -u {user} || true"));
+        script.push("fi".to_owned());
+    }
+
+    Ok(script.join("\n"))
+}
+
+/// Runs `script` on the host at `address` as the `host_access` account
+pub(crate) fn run_on_host(address: &str, script: &str) -> Result<(), String> {
+    let access = settings()
+        .host_access
+        .as_ref()
+        .ok_or("no host_access account is configured".to_owned())?;
+
+    let connection = TcpStream::connect(format!("{address}:22"))
+        .map_err(|e| format!("couldn't connect to the host: {e}"))?;
+    connection
+        .set_read_timeout(Some(Duration::from_secs(60)))
+        .map_err(|e| format!("couldn't set a timeout on the connection: {e}"))?;
+
+    let mut session = Session::new().map_err(|e| format!("couldn't create ssh session: {e}"))?;
+    session.set_tcp_stream(connection);
+    session
+        .handshake()
+        .map_err(|e| format!("ssh handshake failed: {e}"))?;
+    session
+        .userauth_pubkey_file(&access.username, None, &access.private_key, None)
+        .map_err(|e| format!("ssh authentication failed: {e}"))?;
+
+    let mut channel = session
+        .channel_session()
+        .map_err(|e| format!("couldn't open ssh channel: {e}"))?;
+    channel
+        .exec("bash -s")
+        .map_err(|e| format!("couldn't start a shell: {e}"))?;
+    std::io::Write::write_all(&mut channel, script.as_bytes())
+        .map_err(|e| format!("couldn't send the changes: {e}"))?;
+    channel
+        .send_eof()
+        .map_err(|e| format!("couldn't send the changes: {e}"))?;
+
+    let mut output = String::new();
+    let _ = channel.stderr().read_to_string(&mut output);
+    channel
+        .wait_close()
+        .map_err(|e| format!("the session didn't close: {e}"))?;
+
+    match channel.exit_status() {
+        Ok(0) => Ok(()),
+        Ok(code) => Err(format!(
+            "the changes failed with exit code {code}: {output}"
+        )),
+        Err(e) => Err(format!(
+            "couldn't tell whether the changes went through: {e}"
+        )),
+    }
+}
+
+/// Pushes the keys of `added` to the hosts of the booking, and takes away the logins of `removed`
+#[derive(Debug, Clone, Hash, Serialize, Deserialize)]
+pub struct PushCollaboratorKeys {
+    pub agg_id: FKey<Aggregate>,
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+}
+
+tascii::mark_task!(PushCollaboratorKeys);
+impl AsyncRunnable for PushCollaboratorKeys {
+    type Output = ();
+
+    async fn run(&mut self, _context: &Context) -> Result<Self::Output, TaskError> {
+        if settings().host_access.is_none() {
+            tracing::warn!(
+                "Not changing the logins on the hosts of {:?}, no host_access account is configured",
+                self.agg_id
+            );
+            return Ok(());
+        }
+
+        let mut client = new_client().await?;
+        let mut transaction = client.easy_transaction().await?;
+
+        let agg = self.agg_id.get(&mut transaction).await?;
+        // hosts still deploying get whoever the users are when their cloud-init is made
+        if agg.state != LifeCycleState::Active {
+            return Ok(());
+        }
+
+        let mut ipa = ipa::IPA::init()
+            .await
+            .map_err(|e| TaskError::Reason(format!("couldn't connect to IPA: {e:?}")))?;
+        let mut added = Vec::new();
+        for username in self.added.iter() {
+            let keys = ipa
+                .find_matching_user(username.clone(), true, false)
+                .await
+                .map_err(|e| TaskError::Reason(format!("couldn't look up {username}: {e:?}")))?
+                .ipasshpubkey
+                .unwrap_or_default();
+            if keys.is_empty() {
+                tracing::warn!("User '{username}' had no ssh public key on file");
+            }
+            added.push((username.clone(), keys));
+        }
+
+        let script = access_script(&added, &self.removed).map_err(TaskError::Reason)?;
+
+        let mut hosts = Vec::new();
+        for instance in agg.instances(&mut transaction).await? {
+            if let Some(host) = instance.linked_host {
+                hosts.push((instance.id, host.get(&mut transaction).await?.fqdn.clone()));
+            }
+        }
+        transaction.commit().await?;
+
+        let mut failed = Vec::new();
+        for (instance, address) in hosts {
+            let (event, sentiment) = match run_on_host(&address, &script) {
+                Ok(()) => (
+                    ProvEvent::new("Collaborators", "Logins on the host were updated"),
+                    StatusSentiment::Succeeded,
+                ),
+                Err(e) => {
+                    failed.push(format!("{address}: {e}"));
+                    (
+                        ProvEvent::new(
+                            "Collaborators",
+                            format!("Logins on the host couldn't be updated: {e}"),
+                        ),
+                        StatusSentiment::Degraded,
+                    )
+                }
+            };
+            let _ = status_feed::log_committing(instance, event, Some(sentiment)).await;
+        }
+
+        match failed.is_empty() {
+            true => Ok(()),
+            false => Err(TaskError::Reason(format!(
+                "couldn't update the logins on {}",
+                failed.join("; ")
+            ))),
+        }
+    }
+
+    fn identifier() -> TaskIdentifier {
+        TaskIdentifier::named("PushCollaboratorKeysTask").versioned(1)
+    }
+
+    fn summarize(&self, id: ID) -> String {
+        format!(
+            "PushCollaboratorKeys with id {id} for {:?}, adding {:?} and removing {:?}",
+            self.agg_id, self.added, self.removed
+        )
+    }
+
+    fn timeout() -> Duration {
+        Duration::from_secs(600)
+    }
+
+    fn retry_count(&self) -> usize {
+        1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_access_script() {
+        let script = access_script(
+            &[(
+                "alice".to_owned(),
+                vec!["ssh-ed25519 AAAA alice@home".to_owned()],
+            )],
+            &["bob".to_owned()],
+        )
+        .unwrap();
+
+        assert!(script.contains("sudo useradd -m -s /bin/bash alice"));
+        assert!(script.contains("'ssh-ed25519 AAAA alice@home'"));
+        assert!(script.contains("sudo usermod -L bob"));
+        assert!(!script.contains("useradd -m -s /bin/bash bob"));
+
+        assert!(access_script(&[], &["bob; rm -rf /".to_owned()]).is_err());
+        assert!(access_script(&[("-o".to_owned(), vec![])], &[]).is_err());
+        assert!(access_script(&[("eve".to_owned(), vec!["ssh-rsa 'x".to_owned()])], &[]).is_err());
+    }
+}
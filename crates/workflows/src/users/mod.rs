@@ -4,7 +4,14 @@ use serde::{Deserialize, Serialize};
 
 use tascii::{prelude::*, task_trait::AsyncRunnable};
 
-use crate::{deploy_booking::notify::Notify, resource_management::vpn::SyncVPN};
+use crate::{
+    deploy_booking::notify::{Notify, NotifyContext},
+    resource_management::vpn::SyncVPN,
+};
+
+pub mod keys;
+
+use keys::PushCollaboratorKeys;
 
 use config::Situation;
 
@@ -30,7 +37,58 @@ impl AsyncRunnable for AddUsers {
         context.spawn(Notify {
             aggregate: self.agg_id,
             situation: Situation::CollaboratorAdded(self.users.clone()),
-            extra_context: vec![],
+            context: NotifyContext::Booking,
+        });
+
+        context.spawn(PushCollaboratorKeys {
+            agg_id: self.agg_id,
+            added: self.users.clone(),
+            removed: Vec::new(),
+        });
+
+        Ok(())
+    }
+}
+
+/// Carries collaborators being added to and removed from a booking through to its VPN
+/// groups and hosts, telling the booking about the ones added
+#[derive(Debug, Clone, Hash, Serialize, Deserialize)]
+pub struct ChangeCollaborators {
+    pub agg_id: FKey<Aggregate>,
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+}
+
+tascii::mark_task!(ChangeCollaborators);
+impl AsyncRunnable for ChangeCollaborators {
+    type Output = ();
+
+    fn identifier() -> TaskIdentifier {
+        TaskIdentifier::named("ChangeCollaborators").versioned(1)
+    }
+
+    async fn run(&mut self, context: &Context) -> Result<Self::Output, TaskError> {
+        context.spawn(SyncVPN {
+            users: self
+                .added
+                .iter()
+                .chain(self.removed.iter())
+                .cloned()
+                .collect(),
+        });
+
+        if !self.added.is_empty() {
+            context.spawn(Notify {
+                aggregate: self.agg_id,
+                situation: Situation::CollaboratorAdded(self.added.clone()),
+                context: NotifyContext::Booking,
+            });
+        }
+
+        context.spawn(PushCollaboratorKeys {
+            agg_id: self.agg_id,
+            added: self.added.clone(),
+            removed: self.removed.clone(),
         });
 
         Ok(())
@@ -46,6 +46,15 @@ class CobblerAction():
         return 'no-profile'
 
 
+    # maps each system to the mac addresses of its interfaces, as json
+    def system_macs(self):
+        return json.dumps({ system['name']: [ iface['mac_address'] for iface in system['interfaces'].values() ] for system in self.cobbler.get_systems() })
+
+
+    def remove_system(self, hostname: str):
+        self.cobbler.remove_system(hostname, self.token)
+
+
     def system_exists(self, hostname: str):
         return hostname in self.system_names()
 
@@ -106,6 +115,12 @@ class CobblerAction():
         self.cobbler.save_system(sys_id, self.token)
         print("set system netboot")
 
+    def unset_netboot(self, hostname: str):
+        sys_id = self.get_system_handle(hostname)
+        self.cobbler.modify_system(sys_id, 'netboot_enabled', False, self.token)
+        self.cobbler.save_system(sys_id, self.token)
+        print("unset system netboot")
+
     # sets the post install kernel args
     def set_system_post_args(self, hostname: str, url):
         sys_id = self.get_system_handle(hostname)
@@ -0,0 +1,76 @@
+//! Just enough CSV for the reports LibLaaS hands out and the rosters it takes in
+//!
+//! Fields are separated by commas and may be quoted, with quotes inside quoted fields
+//! doubled, as spreadsheets and course management systems write them.
+
+/// `field`, quoted if it has to be
+pub fn field(field: &str) -> String {
+    match field.contains([',', '"', '\n']) {
+        true => format!("\"{}\"", field.replace('"', "\"\"")),
+        false => field.to_owned(),
+    }
+}
+
+/// The rows of `text`, each split into its fields, leaving out blank lines
+pub fn parse(text: &str) -> Vec<Vec<String>> {
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut current = String::new();
+    let mut quoted = false;
+
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, quoted) {
+            ('"', true) if chars.peek() == Some(&'"') => {
+                current.push('"');
+                chars.next();
+            }
+            ('"', true) => quoted = false,
+            ('"', false) if current.is_empty() => quoted = true,
+            (',', false) => row.push(std::mem::take(&mut current)),
+            ('\r', false) => {}
+            ('\n', false) => {
+                row.push(std::mem::take(&mut current));
+                rows.push(std::mem::take(&mut row));
+            }
+            (c, _) => current.push(c),
+        }
+    }
+    if !current.is_empty() || !row.is_empty() {
+        row.push(current);
+        rows.push(row);
+    }
+
+    rows.retain(|r: &Vec<String>| r.iter().any(|f| !f.trim().is_empty()));
+    rows
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_field() {
+        assert_eq!(field("4.20 0x8001778b"), "4.20 0x8001778b");
+        assert_eq!(field("a,b"), "\"a,b\"");
+        assert_eq!(field("say \"hi\""), "\"say \"\"hi\"\"\"");
+    }
+
+    #[test]
+    fn test_parse() {
+        let text = "name,username\r\n\"Doe, Jane\",jdoe\n\nBob \"B\",bob\n\"say \"\"hi\"\"\",\n";
+        assert_eq!(
+            parse(text),
+            vec![
+                vec!["name", "username"],
+                vec!["Doe, Jane", "jdoe"],
+                vec!["Bob \"B\"", "bob"],
+                vec!["say \"hi\"", ""],
+            ]
+        );
+
+        // a last line without a newline, and what field() writes comes back as it was
+        let written = ["a,b", "c"].map(field).join(",");
+        assert_eq!(parse(&written), vec![vec!["a,b", "c"]]);
+    }
+}
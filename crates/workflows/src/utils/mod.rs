@@ -1,5 +1,8 @@
 //! Copyright (c) 2023 University of New Hampshire
 //! SPDX-License-Identifier: MIT
 
+pub mod csv;
 pub mod net;
 pub mod python;
+pub mod resilience;
+pub mod status_feed;
@@ -0,0 +1,372 @@
+//! Retries and circuit breaking for calls out to BMCs and switches
+//!
+//! Failed calls are retried with jittered exponential backoff. Every device has a
+//! breaker that trips after `breaker_threshold` failures in a row, after which calls
+//! to the device fail right away instead of piling up behind timeouts. Once the
+//! cooldown has passed a single call is let through to see if the device is back,
+//! closing the breaker if it works and tripping it again for another cooldown if not.
+//! Calls that aren't safe to make twice, like resetting a host, go through the breaker
+//! with [`call_once`] and aren't retried, since a call that timed out may still have
+//! gone through.
+
+use std::{fmt::Display, future::Future};
+
+use common::prelude::{
+    chrono::{DateTime, Duration, Utc},
+    dashmap::DashMap,
+    lazy_static::lazy_static,
+    rand::{self, Rng},
+    tokio, tracing,
+};
+use config::{settings, DeviceCallConfig};
+use metrics::prelude::*;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum DeviceKind {
+    Bmc,
+    Switch,
+}
+
+impl Display for DeviceKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Bmc => write!(f, "bmc"),
+            Self::Switch => write!(f, "switch"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Device {
+    pub kind: DeviceKind,
+    pub address: String,
+}
+
+impl Device {
+    pub fn bmc(address: &str) -> Self {
+        Self {
+            kind: DeviceKind::Bmc,
+            address: address.to_owned(),
+        }
+    }
+
+    pub fn switch(address: &str) -> Self {
+        Self {
+            kind: DeviceKind::Switch,
+            address: address.to_owned(),
+        }
+    }
+}
+
+impl Display for Device {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.kind, self.address)
+    }
+}
+
+/// A call that wasn't made because the device's breaker is open
+#[derive(Debug, Clone, Error)]
+#[error("not calling {device}, it has failed {failures} times in a row (retrying after {until})")]
+pub struct CircuitOpen {
+    pub device: String,
+    pub failures: u32,
+    pub until: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum BreakerState {
+    /// Calls are made as usual
+    Closed,
+    /// Calls fail right away until the cooldown is over
+    Open,
+    /// The cooldown is over, and the next call will test whether the device is back
+    HalfOpen,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct BreakerStatus {
+    pub state: BreakerState,
+    pub consecutive_failures: u32,
+    #[schemars(with = "Option<String>")]
+    pub open_until: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Breaker {
+    failures: u32,
+    open_until: Option<DateTime<Utc>>,
+}
+
+impl Breaker {
+    /// Whether a call may be made now, letting through one trial call once open and cooled down
+    fn allow(
+        &mut self,
+        now: DateTime<Utc>,
+        config: &DeviceCallConfig,
+    ) -> Result<(), DateTime<Utc>> {
+        match self.open_until {
+            Some(until) if now < until => Err(until),
+            Some(_) => {
+                // hold everyone else off for another cooldown while the trial runs
+                self.open_until =
+                    Some(now + Duration::seconds(config.breaker_cooldown_secs as i64));
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Returns whether this closed the breaker
+    fn succeeded(&mut self) -> bool {
+        let was_open = self.open_until.is_some();
+        self.failures = 0;
+        self.open_until = None;
+
+        was_open
+    }
+
+    /// Returns whether this tripped the breaker
+    fn failed(&mut self, now: DateTime<Utc>, config: &DeviceCallConfig) -> bool {
+        self.failures += 1;
+
+        // a failed trial call trips it again regardless of the count
+        if self.failures >= config.breaker_threshold.max(1) || self.open_until.is_some() {
+            self.open_until = Some(now + Duration::seconds(config.breaker_cooldown_secs as i64));
+            true
+        } else {
+            false
+        }
+    }
+
+    fn status(&self, now: DateTime<Utc>) -> BreakerStatus {
+        BreakerStatus {
+            state: match self.open_until {
+                None => BreakerState::Closed,
+                Some(until) if now < until => BreakerState::Open,
+                Some(_) => BreakerState::HalfOpen,
+            },
+            consecutive_failures: self.failures,
+            open_until: self.open_until,
+        }
+    }
+}
+
+lazy_static! {
+    static ref BREAKERS: DashMap<Device, Breaker> = DashMap::new();
+}
+
+/// The longest to wait before retry number `attempt` (counting from 0)
+fn backoff_cap(attempt: u32, config: &DeviceCallConfig) -> std::time::Duration {
+    let delay = config
+        .base_delay_ms
+        .saturating_mul(2u64.saturating_pow(attempt))
+        .min(config.max_delay_ms);
+
+    std::time::Duration::from_millis(delay)
+}
+
+/// Somewhere between half of the cap and all of it, so retries of calls that failed
+/// together don't all land on the device at the same moment again
+fn backoff(attempt: u32, config: &DeviceCallConfig) -> std::time::Duration {
+    let cap = backoff_cap(attempt, config);
+    let half = cap / 2;
+
+    half + half.mul_f64(rand::thread_rng().gen::<f64>())
+}
+
+fn send_metric(device: &Device, open: bool, failures: u32) {
+    if open {
+        tracing::warn!("Breaker for {device} tripped after {failures} failures in a row");
+    } else {
+        tracing::info!("Breaker for {device} closed, the device is answering again");
+    }
+
+    let metric = DeviceBreakerMetric {
+        kind: device.kind.to_string(),
+        device: device.address.clone(),
+        open,
+        consecutive_failures: failures as i32,
+        ..Default::default()
+    };
+
+    if let Err(e) = MetricHandler::send(metric) {
+        tracing::error!("Failed to send device breaker metric: {e:?}");
+    }
+}
+
+fn before_call(device: &Device, config: &DeviceCallConfig) -> Result<(), CircuitOpen> {
+    let mut breaker = BREAKERS.entry(device.clone()).or_default();
+
+    breaker
+        .allow(Utc::now(), config)
+        .map_err(|until| CircuitOpen {
+            device: device.to_string(),
+            failures: breaker.failures,
+            until,
+        })
+}
+
+fn after_call<T, E: Display>(device: &Device, config: &DeviceCallConfig, result: &Result<T, E>) {
+    let mut breaker = BREAKERS.entry(device.clone()).or_default();
+
+    match result {
+        Ok(_) => {
+            if breaker.succeeded() {
+                send_metric(device, false, 0);
+            }
+        }
+        Err(e) => {
+            tracing::warn!("Call to {device} failed: {e}");
+            if breaker.failed(Utc::now(), config) {
+                send_metric(device, true, breaker.failures);
+            }
+        }
+    }
+}
+
+/// Calls the device, retrying failures with backoff unless its breaker trips
+pub async fn call<T, E, F, Fut>(device: Device, op: F) -> Result<T, E>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+    E: From<CircuitOpen> + Display,
+{
+    let retries = settings().device_calls.retries;
+
+    call_retrying(device, retries, op).await
+}
+
+/// Calls the device unless its breaker is open, without retrying if it fails
+pub async fn call_once<T, E, F, Fut>(device: Device, op: F) -> Result<T, E>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+    E: From<CircuitOpen> + Display,
+{
+    call_retrying(device, 0, op).await
+}
+
+async fn call_retrying<T, E, F, Fut>(device: Device, retries: u32, mut op: F) -> Result<T, E>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+    E: From<CircuitOpen> + Display,
+{
+    let config = settings().device_calls.clone();
+
+    let mut attempt = 0;
+    loop {
+        before_call(&device, &config)?;
+
+        let result = op().await;
+        after_call(&device, &config, &result);
+
+        match result {
+            Err(_) if attempt < retries => {
+                tokio::time::sleep(backoff(attempt, &config)).await;
+                attempt += 1;
+            }
+            done => return done,
+        }
+    }
+}
+
+/// Like [`call`], for clients that block
+pub fn call_blocking<T, E, F>(device: Device, mut op: F) -> Result<T, E>
+where
+    F: FnMut() -> Result<T, E>,
+    E: From<CircuitOpen> + Display,
+{
+    let config = settings().device_calls.clone();
+
+    let mut attempt = 0;
+    loop {
+        before_call(&device, &config)?;
+
+        let result = op();
+        after_call(&device, &config, &result);
+
+        match result {
+            Err(_) if attempt < config.retries => {
+                std::thread::sleep(backoff(attempt, &config));
+                attempt += 1;
+            }
+            done => return done,
+        }
+    }
+}
+
+/// How calls to the device have been going
+pub fn status(device: &Device) -> BreakerStatus {
+    BREAKERS
+        .get(device)
+        .map(|b| b.status(Utc::now()))
+        .unwrap_or(BreakerStatus {
+            state: BreakerState::Closed,
+            consecutive_failures: 0,
+            open_until: None,
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> DeviceCallConfig {
+        DeviceCallConfig {
+            retries: 2,
+            base_delay_ms: 500,
+            max_delay_ms: 3000,
+            breaker_threshold: 3,
+            breaker_cooldown_secs: 60,
+        }
+    }
+
+    #[test]
+    fn test_backoff() {
+        let caps: Vec<u64> = (0..5)
+            .map(|a| backoff_cap(a, &config()).as_millis() as u64)
+            .collect();
+        assert_eq!(caps, vec![500, 1000, 2000, 3000, 3000]);
+
+        let delay = backoff(1, &config());
+        assert!(delay >= std::time::Duration::from_millis(500));
+        assert!(delay <= std::time::Duration::from_millis(1000));
+    }
+
+    #[test]
+    fn test_breaker() {
+        let config = config();
+        let now = Utc::now();
+        let mut breaker = Breaker::default();
+
+        assert!(!breaker.failed(now, &config));
+        assert!(!breaker.failed(now, &config));
+        assert!(breaker.failed(now, &config));
+        assert_eq!(breaker.status(now).state, BreakerState::Open);
+        assert!(breaker.allow(now, &config).is_err());
+
+        // once cooled down, exactly one trial call is let through
+        let later = now + Duration::seconds(61);
+        assert_eq!(breaker.status(later).state, BreakerState::HalfOpen);
+        assert!(breaker.allow(later, &config).is_ok());
+        assert!(breaker.allow(later, &config).is_err());
+
+        // and a failed trial trips it again right away
+        assert!(breaker.failed(later, &config));
+        assert!(breaker
+            .allow(later + Duration::seconds(1), &config)
+            .is_err());
+
+        let much_later = later + Duration::seconds(61);
+        assert!(breaker.allow(much_later, &config).is_ok());
+        assert!(breaker.succeeded());
+        assert_eq!(breaker.status(much_later).state, BreakerState::Closed);
+        assert_eq!(breaker.status(much_later).consecutive_failures, 0);
+    }
+}
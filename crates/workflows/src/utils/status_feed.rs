@@ -0,0 +1,74 @@
+//! Live provisioning status
+//!
+//! Tasks publish what they log about instances here on top of writing it to the provision
+//! log, so the status stream of a booking can push it to the dashboard as it happens
+//! instead of the dashboard polling for it. Nothing published is kept: subscribers only
+//! hear what is published while they listen and miss events if they fall far behind, so
+//! the provision log stays what to go by.
+
+use common::prelude::{anyhow, lazy_static::lazy_static, tokio::sync::broadcast, tracing};
+use dal::FKey;
+use models::dashboard::{Instance, ProvEvent, ProvisionLogEvent, StatusSentiment};
+
+/// How many events a subscriber can fall behind by before it starts missing them
+const FEED_CAPACITY: usize = 1024;
+
+lazy_static! {
+    static ref FEED: broadcast::Sender<ProvisionLogEvent> = broadcast::channel(FEED_CAPACITY).0;
+}
+
+/// Hears every event published from now on, for any instance
+pub fn subscribe() -> broadcast::Receiver<ProvisionLogEvent> {
+    FEED.subscribe()
+}
+
+pub fn publish(event: ProvisionLogEvent) {
+    // sending only fails when nobody is listening
+    let _ = FEED.send(event);
+}
+
+/// Like [`Instance::log_committing`], publishing the event once it is logged
+pub async fn log_committing(
+    instance: FKey<Instance>,
+    event: ProvEvent,
+    sentiment: Option<StatusSentiment>,
+) -> Result<ProvisionLogEvent, anyhow::Error> {
+    let logged = Instance::log_committing(instance, event, sentiment).await?;
+    publish(logged.clone());
+
+    Ok(logged)
+}
+
+/// Like [`models::EasyLog`], publishing what is logged to the feed
+#[allow(async_fn_in_trait)]
+pub trait PublishedLog {
+    async fn log<H, D>(&self, header: H, detail: D, status: StatusSentiment)
+    where
+        H: Into<String>,
+        D: Into<String>;
+}
+
+impl PublishedLog for FKey<Instance> {
+    async fn log<H, D>(&self, header: H, detail: D, status: StatusSentiment)
+    where
+        H: Into<String>,
+        D: Into<String>,
+    {
+        let header: String = header.into();
+        let detail: String = detail.into();
+
+        tracing::info!("Dispatching log for an instance, header: {header}, detail: {detail}");
+        if let Err(e) = log_committing(
+            *self,
+            ProvEvent {
+                event: header,
+                details: detail,
+            },
+            Some(status),
+        )
+        .await
+        {
+            tracing::error!("Couldn't log status of {self:?}: {e:?}");
+        }
+    }
+}
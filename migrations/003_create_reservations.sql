@@ -0,0 +1,10 @@
+CREATE TABLE IF NOT EXISTS reservations (
+  id uuid PRIMARY KEY NOT NULL,
+  for_resource uuid NOT NULL,
+  for_aggregate uuid NOT NULL,
+  starts timestamptz NOT NULL,
+  ends timestamptz,
+  cancelled boolean NOT NULL DEFAULT false,
+  CONSTRAINT reservations_for_aggregate_fkey FOREIGN KEY (for_aggregate) REFERENCES aggregates (id) ON DELETE RESTRICT,
+  CONSTRAINT reservations_for_resource_fkey FOREIGN KEY (for_resource) REFERENCES resource_handles (id) ON DELETE RESTRICT
+);
@@ -0,0 +1,10 @@
+CREATE TABLE IF NOT EXISTS host_benchmarks (
+  id uuid PRIMARY KEY NOT NULL,
+  host uuid NOT NULL,
+  suite VARCHAR NOT NULL,
+  score double precision NOT NULL,
+  recorded timestamptz NOT NULL,
+  CONSTRAINT host_benchmarks_host_fkey FOREIGN KEY (host) REFERENCES hosts (id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS host_benchmarks_host_index ON host_benchmarks (host, recorded);
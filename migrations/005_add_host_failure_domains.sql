@@ -0,0 +1 @@
+ALTER TABLE hosts ADD COLUMN IF NOT EXISTS failure_domain jsonb NOT NULL DEFAULT '{}';
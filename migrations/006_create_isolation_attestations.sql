@@ -0,0 +1,8 @@
+CREATE TABLE IF NOT EXISTS isolation_attestations (
+  id uuid PRIMARY KEY NOT NULL,
+  aggregate uuid NOT NULL,
+  time timestamptz NOT NULL,
+  passed boolean NOT NULL,
+  findings jsonb NOT NULL,
+  CONSTRAINT isolation_attestations_aggregate_fkey FOREIGN KEY (aggregate) REFERENCES aggregates (id) ON DELETE CASCADE
+);
@@ -0,0 +1,8 @@
+CREATE TABLE IF NOT EXISTS firewall_policies (
+  id uuid PRIMARY KEY NOT NULL,
+  aggregate uuid UNIQUE NOT NULL,
+  rules jsonb NOT NULL,
+  applied boolean NOT NULL,
+  updated timestamptz NOT NULL,
+  CONSTRAINT firewall_policies_aggregate_fkey FOREIGN KEY (aggregate) REFERENCES aggregates (id) ON DELETE CASCADE
+);
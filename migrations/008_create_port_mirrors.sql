@@ -0,0 +1,16 @@
+CREATE TABLE IF NOT EXISTS port_mirrors (
+  id uuid PRIMARY KEY NOT NULL,
+  aggregate uuid NOT NULL,
+  switch uuid NOT NULL,
+  session smallint NOT NULL,
+  source uuid NOT NULL,
+  destination uuid NOT NULL,
+  requested_by varchar NOT NULL,
+  started timestamptz NOT NULL,
+  expires timestamptz NOT NULL,
+  ended timestamptz,
+  CONSTRAINT port_mirrors_aggregate_fkey FOREIGN KEY (aggregate) REFERENCES aggregates (id) ON DELETE CASCADE,
+  CONSTRAINT port_mirrors_switch_fkey FOREIGN KEY (switch) REFERENCES switches (id),
+  CONSTRAINT port_mirrors_source_fkey FOREIGN KEY (source) REFERENCES host_ports (id),
+  CONSTRAINT port_mirrors_destination_fkey FOREIGN KEY (destination) REFERENCES host_ports (id)
+);
@@ -0,0 +1,14 @@
+CREATE TABLE IF NOT EXISTS external_attachments (
+  id uuid PRIMARY KEY NOT NULL,
+  aggregate uuid NOT NULL,
+  network uuid NOT NULL,
+  feed varchar NOT NULL,
+  state jsonb NOT NULL,
+  requested_by varchar NOT NULL,
+  reason varchar NOT NULL,
+  reviewed_by varchar,
+  requested timestamptz NOT NULL,
+  updated timestamptz NOT NULL,
+  CONSTRAINT external_attachments_aggregate_fkey FOREIGN KEY (aggregate) REFERENCES aggregates (id) ON DELETE CASCADE,
+  CONSTRAINT external_attachments_network_fkey FOREIGN KEY (network) REFERENCES networks (id)
+);
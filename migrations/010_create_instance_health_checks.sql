@@ -0,0 +1,14 @@
+CREATE TABLE IF NOT EXISTS instance_health_checks (
+  id uuid PRIMARY KEY NOT NULL,
+  instance uuid NOT NULL,
+  aggregate uuid NOT NULL,
+  time timestamptz NOT NULL,
+  powered_on boolean NOT NULL,
+  reachable boolean NOT NULL,
+  excused boolean NOT NULL,
+  unplanned_reboot boolean NOT NULL,
+  CONSTRAINT instance_health_checks_instance_fkey FOREIGN KEY (instance) REFERENCES instances (id) ON DELETE CASCADE,
+  CONSTRAINT instance_health_checks_aggregate_fkey FOREIGN KEY (aggregate) REFERENCES aggregates (id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS instance_health_checks_instance_index ON instance_health_checks (instance, time);
@@ -0,0 +1,14 @@
+CREATE TABLE IF NOT EXISTS host_tickets (
+  id uuid PRIMARY KEY NOT NULL,
+  host uuid NOT NULL,
+  kind jsonb NOT NULL,
+  system varchar NOT NULL,
+  external_id varchar NOT NULL,
+  url varchar,
+  summary varchar NOT NULL,
+  opened timestamptz NOT NULL,
+  resolved timestamptz,
+  CONSTRAINT host_tickets_host_fkey FOREIGN KEY (host) REFERENCES hosts (id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS host_tickets_host_index ON host_tickets (host, opened);
@@ -0,0 +1,12 @@
+CREATE TABLE IF NOT EXISTS email_contacts (
+  id uuid PRIMARY KEY NOT NULL,
+  username varchar NOT NULL,
+  email varchar NOT NULL,
+  verified timestamptz,
+  soft_bounces integer NOT NULL,
+  hard_bounced timestamptz,
+  last_error text,
+  CONSTRAINT email_contacts_username_unique UNIQUE (username)
+);
+
+CREATE INDEX IF NOT EXISTS email_contacts_email_index ON email_contacts (email);
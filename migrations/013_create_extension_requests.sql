@@ -0,0 +1,10 @@
+CREATE TABLE IF NOT EXISTS extension_requests (
+  id uuid PRIMARY KEY NOT NULL,
+  aggregate uuid NOT NULL,
+  date varchar NOT NULL,
+  reason text NOT NULL,
+  requested timestamptz NOT NULL,
+  CONSTRAINT extension_requests_aggregate_fkey FOREIGN KEY (aggregate) REFERENCES aggregates (id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS extension_requests_aggregate_index ON extension_requests (aggregate, requested);
@@ -0,0 +1,4 @@
+ALTER TABLE images ADD COLUMN IF NOT EXISTS deprecated timestamptz;
+ALTER TABLE images ADD COLUMN IF NOT EXISTS sunset timestamptz;
+ALTER TABLE images ADD COLUMN IF NOT EXISTS replacement uuid;
+ALTER TABLE images ADD COLUMN IF NOT EXISTS changelog jsonb NOT NULL DEFAULT '[]';
@@ -0,0 +1,7 @@
+CREATE TABLE IF NOT EXISTS drift_reports (
+  id uuid PRIMARY KEY NOT NULL,
+  time timestamptz NOT NULL,
+  findings jsonb NOT NULL DEFAULT '[]'
+);
+
+CREATE INDEX IF NOT EXISTS drift_reports_time_index ON drift_reports (time);
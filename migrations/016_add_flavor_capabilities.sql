@@ -0,0 +1 @@
+ALTER TABLE flavors ADD COLUMN IF NOT EXISTS capabilities jsonb NOT NULL DEFAULT '{}';
@@ -0,0 +1,17 @@
+CREATE TABLE IF NOT EXISTS booking_drafts (
+  id uuid PRIMARY KEY NOT NULL,
+  owner varchar NOT NULL,
+  origin varchar,
+  template uuid,
+  allowed_users varchar[] NOT NULL,
+  global_cifile text,
+  metadata jsonb NOT NULL,
+  parameters jsonb NOT NULL,
+  created timestamptz NOT NULL,
+  updated timestamptz NOT NULL,
+  submitted uuid,
+  CONSTRAINT booking_drafts_template_fkey FOREIGN KEY (template) REFERENCES templates (id) ON DELETE SET NULL,
+  CONSTRAINT booking_drafts_submitted_fkey FOREIGN KEY (submitted) REFERENCES aggregates (id) ON DELETE SET NULL
+);
+
+CREATE INDEX IF NOT EXISTS booking_drafts_owner_index ON booking_drafts (owner, updated);
@@ -0,0 +1,19 @@
+CREATE TABLE IF NOT EXISTS aggregate_summary (
+  id uuid PRIMARY KEY NOT NULL,
+  aggregate uuid NOT NULL UNIQUE,
+  owner varchar,
+  users varchar[] NOT NULL,
+  booking_id varchar,
+  display_name varchar,
+  project varchar,
+  lifecycle_state jsonb NOT NULL,
+  hosts integer NOT NULL,
+  hosts_provisioned integer NOT NULL,
+  hosts_failed integer NOT NULL,
+  latest_status varchar,
+  latest_sentiment jsonb,
+  booking_start timestamptz,
+  booking_end timestamptz,
+  updated timestamptz NOT NULL,
+  CONSTRAINT aggregate_summary_aggregate_fkey FOREIGN KEY (aggregate) REFERENCES aggregates (id) ON DELETE CASCADE
+);
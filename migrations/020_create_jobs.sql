@@ -0,0 +1,18 @@
+CREATE TABLE IF NOT EXISTS jobs (
+  id uuid PRIMARY KEY NOT NULL,
+  kind varchar NOT NULL,
+  requested_by varchar NOT NULL,
+  status jsonb NOT NULL,
+  done integer NOT NULL,
+  total integer NOT NULL,
+  message text,
+  cancel_requested boolean NOT NULL,
+  result jsonb,
+  error text,
+  created timestamptz NOT NULL,
+  started timestamptz,
+  finished timestamptz,
+  updated timestamptz NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS jobs_created_index ON jobs (created);
@@ -0,0 +1,15 @@
+CREATE TABLE IF NOT EXISTS telemetry_samples (
+  id uuid PRIMARY KEY NOT NULL,
+  instance uuid NOT NULL,
+  aggregate uuid NOT NULL,
+  time timestamptz NOT NULL,
+  cpu_percent double precision NOT NULL,
+  memory_percent double precision NOT NULL,
+  disk_percent double precision NOT NULL,
+  network_rx_bytes_per_sec double precision NOT NULL,
+  network_tx_bytes_per_sec double precision NOT NULL,
+  CONSTRAINT telemetry_samples_instance_fkey FOREIGN KEY (instance) REFERENCES instances (id) ON DELETE CASCADE,
+  CONSTRAINT telemetry_samples_aggregate_fkey FOREIGN KEY (aggregate) REFERENCES aggregates (id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS telemetry_samples_aggregate_index ON telemetry_samples (aggregate, time);
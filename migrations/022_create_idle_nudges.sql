@@ -0,0 +1,13 @@
+CREATE TABLE IF NOT EXISTS idle_nudges (
+  id uuid PRIMARY KEY NOT NULL,
+  aggregate uuid NOT NULL,
+  token varchar NOT NULL UNIQUE,
+  sent timestamptz NOT NULL,
+  streak integer NOT NULL,
+  flagged boolean NOT NULL,
+  answer jsonb,
+  answered timestamptz,
+  CONSTRAINT idle_nudges_aggregate_fkey FOREIGN KEY (aggregate) REFERENCES aggregates (id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idle_nudges_aggregate_index ON idle_nudges (aggregate, sent);
@@ -0,0 +1,17 @@
+CREATE TABLE IF NOT EXISTS host_replacements (
+  id uuid PRIMARY KEY NOT NULL,
+  aggregate uuid NOT NULL,
+  instance uuid NOT NULL,
+  faulty_host uuid NOT NULL,
+  replacement_host uuid,
+  state jsonb NOT NULL,
+  requested_by varchar NOT NULL,
+  reason varchar NOT NULL,
+  reviewed_by varchar,
+  requested timestamptz NOT NULL,
+  updated timestamptz NOT NULL,
+  CONSTRAINT host_replacements_aggregate_fkey FOREIGN KEY (aggregate) REFERENCES aggregates (id) ON DELETE CASCADE,
+  CONSTRAINT host_replacements_instance_fkey FOREIGN KEY (instance) REFERENCES instances (id) ON DELETE CASCADE,
+  CONSTRAINT host_replacements_faulty_host_fkey FOREIGN KEY (faulty_host) REFERENCES hosts (id) ON DELETE CASCADE,
+  CONSTRAINT host_replacements_replacement_host_fkey FOREIGN KEY (replacement_host) REFERENCES hosts (id) ON DELETE SET NULL
+);
@@ -0,0 +1,11 @@
+CREATE TABLE IF NOT EXISTS deploy_checkpoints (
+  id uuid PRIMARY KEY NOT NULL,
+  instance uuid NOT NULL,
+  host uuid NOT NULL,
+  checkpoint jsonb NOT NULL,
+  reached timestamptz NOT NULL,
+  CONSTRAINT deploy_checkpoints_instance_fkey FOREIGN KEY (instance) REFERENCES instances (id) ON DELETE CASCADE,
+  CONSTRAINT deploy_checkpoints_host_fkey FOREIGN KEY (host) REFERENCES hosts (id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS deploy_checkpoints_instance_index ON deploy_checkpoints (instance, reached);
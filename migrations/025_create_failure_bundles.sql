@@ -0,0 +1,17 @@
+CREATE TABLE IF NOT EXISTS failure_bundles (
+  id uuid PRIMARY KEY NOT NULL,
+  instance uuid NOT NULL,
+  aggregate uuid NOT NULL,
+  host uuid NOT NULL,
+  created timestamptz NOT NULL,
+  error varchar NOT NULL,
+  events jsonb NOT NULL,
+  console varchar,
+  dhcp jsonb NOT NULL,
+  switch_ports jsonb NOT NULL,
+  CONSTRAINT failure_bundles_instance_fkey FOREIGN KEY (instance) REFERENCES instances (id) ON DELETE CASCADE,
+  CONSTRAINT failure_bundles_aggregate_fkey FOREIGN KEY (aggregate) REFERENCES aggregates (id) ON DELETE CASCADE,
+  CONSTRAINT failure_bundles_host_fkey FOREIGN KEY (host) REFERENCES hosts (id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS failure_bundles_instance_index ON failure_bundles (instance, created);
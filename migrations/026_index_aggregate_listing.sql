@@ -0,0 +1,15 @@
+-- The start and end of a booking as timestamps, open ended where they aren't set.
+--
+-- Casting text to timestamptz is only stable since it can depend on the session time zone,
+-- but booking metadata always carries its offset, so the cast can be indexed.
+CREATE OR REPLACE FUNCTION booking_start(metadata jsonb) RETURNS timestamptz
+  LANGUAGE sql IMMUTABLE AS $$ SELECT COALESCE((metadata->>'start')::timestamptz, '-infinity') $$;
+
+CREATE OR REPLACE FUNCTION booking_end(metadata jsonb) RETURNS timestamptz
+  LANGUAGE sql IMMUTABLE AS $$ SELECT COALESCE((metadata->>'end')::timestamptz, 'infinity') $$;
+
+CREATE INDEX IF NOT EXISTS aggregates_start_index ON aggregates (booking_start(metadata), id) WHERE NOT deleted;
+CREATE INDEX IF NOT EXISTS aggregates_end_index ON aggregates (booking_end(metadata)) WHERE NOT deleted;
+CREATE INDEX IF NOT EXISTS aggregates_owner_index ON aggregates ((metadata->>'owner')) WHERE NOT deleted;
+CREATE INDEX IF NOT EXISTS aggregates_project_index ON aggregates ((metadata->>'project')) WHERE NOT deleted;
+CREATE INDEX IF NOT EXISTS aggregates_lifecycle_state_index ON aggregates (lifecycle_state) WHERE NOT deleted;
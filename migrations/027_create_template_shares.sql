@@ -0,0 +1,12 @@
+CREATE TABLE IF NOT EXISTS template_shares (
+  id uuid PRIMARY KEY NOT NULL,
+  template uuid NOT NULL,
+  username varchar NOT NULL,
+  permission jsonb NOT NULL,
+  shared_by varchar NOT NULL,
+  shared timestamptz NOT NULL,
+  CONSTRAINT template_shares_template_fkey FOREIGN KEY (template) REFERENCES templates (id) ON DELETE CASCADE,
+  CONSTRAINT template_shares_template_username_key UNIQUE (template, username)
+);
+
+CREATE INDEX IF NOT EXISTS template_shares_username_index ON template_shares (username);
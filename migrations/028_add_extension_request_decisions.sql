@@ -0,0 +1,6 @@
+ALTER TABLE extension_requests ADD COLUMN IF NOT EXISTS status jsonb NOT NULL DEFAULT '"Pending"';
+ALTER TABLE extension_requests ADD COLUMN IF NOT EXISTS requested_by varchar;
+ALTER TABLE extension_requests ADD COLUMN IF NOT EXISTS decided_by varchar;
+ALTER TABLE extension_requests ADD COLUMN IF NOT EXISTS decided timestamptz;
+ALTER TABLE extension_requests ADD COLUMN IF NOT EXISTS note text;
+ALTER TABLE extension_requests ADD COLUMN IF NOT EXISTS granted_end timestamptz;
@@ -0,0 +1,12 @@
+CREATE TABLE IF NOT EXISTS booking_events (
+  id uuid PRIMARY KEY NOT NULL,
+  aggregate uuid NOT NULL,
+  kind jsonb NOT NULL,
+  details text NOT NULL,
+  actor varchar,
+  time timestamptz NOT NULL,
+  CONSTRAINT booking_events_aggregate_fkey FOREIGN KEY (aggregate) REFERENCES aggregates (id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS booking_events_aggregate_index ON booking_events (aggregate, time);
+CREATE INDEX IF NOT EXISTS booking_events_time_index ON booking_events (time);
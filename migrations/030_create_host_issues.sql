@@ -0,0 +1,13 @@
+CREATE TABLE IF NOT EXISTS host_issues (
+  id uuid PRIMARY KEY NOT NULL,
+  host uuid NOT NULL,
+  area jsonb NOT NULL,
+  summary varchar NOT NULL,
+  flagged_by varchar NOT NULL,
+  flagged timestamptz NOT NULL,
+  cleared_by varchar,
+  cleared timestamptz,
+  CONSTRAINT host_issues_host_fkey FOREIGN KEY (host) REFERENCES hosts (id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS host_issues_host_index ON host_issues (host, flagged);
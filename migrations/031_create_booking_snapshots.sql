@@ -0,0 +1,20 @@
+CREATE TABLE IF NOT EXISTS booking_snapshots (
+  id uuid PRIMARY KEY NOT NULL,
+  name varchar NOT NULL,
+  owner varchar NOT NULL,
+  aggregate uuid,
+  template uuid NOT NULL,
+  lab uuid NOT NULL,
+  env jsonb NOT NULL,
+  networks jsonb NOT NULL,
+  hosts jsonb NOT NULL,
+  state jsonb NOT NULL,
+  error varchar,
+  created timestamptz NOT NULL,
+  captured timestamptz,
+  CONSTRAINT booking_snapshots_aggregate_fkey FOREIGN KEY (aggregate) REFERENCES aggregates (id) ON DELETE SET NULL,
+  CONSTRAINT booking_snapshots_template_fkey FOREIGN KEY (template) REFERENCES templates (id),
+  CONSTRAINT booking_snapshots_lab_fkey FOREIGN KEY (lab) REFERENCES labs (id)
+);
+
+CREATE INDEX IF NOT EXISTS booking_snapshots_owner_index ON booking_snapshots (owner, created);
@@ -0,0 +1,11 @@
+CREATE TABLE IF NOT EXISTS idempotency_keys (
+  id uuid PRIMARY KEY NOT NULL,
+  key varchar NOT NULL UNIQUE,
+  request jsonb NOT NULL,
+  aggregate uuid,
+  created timestamptz NOT NULL,
+  expires timestamptz NOT NULL,
+  CONSTRAINT idempotency_keys_aggregate_fkey FOREIGN KEY (aggregate) REFERENCES aggregates (id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idempotency_keys_expires_index ON idempotency_keys (expires);
@@ -0,0 +1,14 @@
+CREATE TABLE IF NOT EXISTS support_requests (
+  id uuid PRIMARY KEY NOT NULL,
+  aggregate uuid NOT NULL,
+  requested_by varchar NOT NULL,
+  summary varchar NOT NULL,
+  description text NOT NULL,
+  context jsonb NOT NULL,
+  ticket varchar,
+  ticket_url varchar,
+  created timestamptz NOT NULL,
+  CONSTRAINT support_requests_aggregate_fkey FOREIGN KEY (aggregate) REFERENCES aggregates (id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS support_requests_aggregate_index ON support_requests (aggregate, created);
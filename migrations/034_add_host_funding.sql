@@ -0,0 +1 @@
+ALTER TABLE hosts ADD COLUMN IF NOT EXISTS funded_by varchar;
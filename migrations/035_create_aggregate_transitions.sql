@@ -0,0 +1,13 @@
+CREATE TABLE IF NOT EXISTS aggregate_transitions (
+  id uuid PRIMARY KEY NOT NULL,
+  aggregate uuid NOT NULL,
+  from_state jsonb NOT NULL,
+  to_state jsonb NOT NULL,
+  reason text NOT NULL,
+  actor varchar,
+  forced boolean NOT NULL DEFAULT false,
+  time timestamptz NOT NULL,
+  CONSTRAINT aggregate_transitions_aggregate_fkey FOREIGN KEY (aggregate) REFERENCES aggregates (id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS aggregate_transitions_aggregate_index ON aggregate_transitions (aggregate, time);
@@ -0,0 +1,12 @@
+CREATE TABLE IF NOT EXISTS cohorts (
+  id uuid PRIMARY KEY NOT NULL,
+  name varchar NOT NULL,
+  instructor varchar NOT NULL,
+  origin varchar NOT NULL,
+  template uuid NOT NULL,
+  members jsonb NOT NULL,
+  created timestamptz NOT NULL,
+  CONSTRAINT cohorts_template_fkey FOREIGN KEY (template) REFERENCES templates (id)
+);
+
+CREATE INDEX IF NOT EXISTS cohorts_instructor_index ON cohorts (instructor, created);
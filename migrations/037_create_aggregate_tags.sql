@@ -0,0 +1,11 @@
+CREATE TABLE IF NOT EXISTS aggregate_tags (
+  id uuid PRIMARY KEY NOT NULL,
+  aggregate uuid NOT NULL,
+  tag varchar NOT NULL,
+  tagged_by varchar,
+  tagged timestamptz NOT NULL,
+  CONSTRAINT aggregate_tags_aggregate_fkey FOREIGN KEY (aggregate) REFERENCES aggregates (id) ON DELETE CASCADE,
+  CONSTRAINT aggregate_tags_aggregate_tag_key UNIQUE (aggregate, tag)
+);
+
+CREATE INDEX IF NOT EXISTS aggregate_tags_tag_index ON aggregate_tags (tag);
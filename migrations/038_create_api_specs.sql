@@ -0,0 +1,8 @@
+CREATE TABLE IF NOT EXISTS api_specs (
+  id uuid PRIMARY KEY NOT NULL,
+  version varchar NOT NULL,
+  spec jsonb NOT NULL,
+  recorded timestamptz NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS api_specs_recorded_index ON api_specs (recorded);
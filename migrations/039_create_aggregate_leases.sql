@@ -0,0 +1,11 @@
+CREATE TABLE IF NOT EXISTS aggregate_leases (
+  id uuid PRIMARY KEY NOT NULL,
+  aggregate uuid NOT NULL,
+  operation jsonb NOT NULL,
+  holder varchar,
+  acquired timestamptz NOT NULL,
+  expires timestamptz NOT NULL,
+  CONSTRAINT aggregate_leases_aggregate_fkey FOREIGN KEY (aggregate) REFERENCES aggregates (id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS aggregate_leases_aggregate_index ON aggregate_leases (aggregate, expires);
@@ -0,0 +1,10 @@
+CREATE TABLE IF NOT EXISTS collaborator_permissions (
+  id uuid PRIMARY KEY NOT NULL,
+  aggregate uuid NOT NULL,
+  username varchar NOT NULL,
+  capabilities jsonb NOT NULL,
+  granted_by varchar NOT NULL,
+  updated timestamptz NOT NULL,
+  CONSTRAINT collaborator_permissions_aggregate_fkey FOREIGN KEY (aggregate) REFERENCES aggregates (id) ON DELETE CASCADE,
+  CONSTRAINT collaborator_permissions_aggregate_username_key UNIQUE (aggregate, username)
+);
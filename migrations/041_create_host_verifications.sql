@@ -0,0 +1,13 @@
+CREATE TABLE IF NOT EXISTS host_verifications (
+  id uuid PRIMARY KEY NOT NULL,
+  host uuid NOT NULL,
+  aggregate uuid NOT NULL,
+  passed boolean NOT NULL,
+  disks_wiped boolean NOT NULL,
+  bmc_matches_baseline boolean NOT NULL,
+  findings jsonb NOT NULL,
+  recorded timestamptz NOT NULL,
+  CONSTRAINT host_verifications_host_fkey FOREIGN KEY (host) REFERENCES hosts (id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS host_verifications_host_index ON host_verifications (host, recorded);
@@ -0,0 +1,10 @@
+CREATE TABLE IF NOT EXISTS booking_trash (
+  id uuid PRIMARY KEY NOT NULL,
+  aggregate uuid NOT NULL,
+  ended timestamptz NOT NULL,
+  purge_after timestamptz NOT NULL,
+  CONSTRAINT booking_trash_aggregate_fkey FOREIGN KEY (aggregate) REFERENCES aggregates (id) ON DELETE CASCADE,
+  CONSTRAINT booking_trash_aggregate_key UNIQUE (aggregate)
+);
+
+CREATE INDEX IF NOT EXISTS booking_trash_purge_after_index ON booking_trash (purge_after);
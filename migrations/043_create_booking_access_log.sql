@@ -0,0 +1,15 @@
+CREATE TABLE IF NOT EXISTS booking_access_log (
+  id uuid PRIMARY KEY NOT NULL,
+  aggregate uuid NOT NULL,
+  instance uuid,
+  username varchar,
+  admin boolean NOT NULL,
+  method varchar NOT NULL,
+  route varchar NOT NULL,
+  status integer NOT NULL,
+  time timestamptz NOT NULL,
+  CONSTRAINT booking_access_log_aggregate_fkey FOREIGN KEY (aggregate) REFERENCES aggregates (id) ON DELETE CASCADE,
+  CONSTRAINT booking_access_log_instance_fkey FOREIGN KEY (instance) REFERENCES instances (id) ON DELETE SET NULL
+);
+
+CREATE INDEX IF NOT EXISTS booking_access_log_aggregate_index ON booking_access_log (aggregate, time);
@@ -0,0 +1,15 @@
+CREATE TABLE IF NOT EXISTS quota_overrides (
+  id uuid PRIMARY KEY NOT NULL,
+  origin varchar NOT NULL,
+  username varchar,
+  project varchar,
+  max_hosts integer,
+  max_length_days bigint,
+  granted_by varchar NOT NULL,
+  reason varchar NOT NULL,
+  created timestamptz NOT NULL,
+  expires timestamptz NOT NULL,
+  CONSTRAINT quota_overrides_subject_check CHECK ((username IS NULL) <> (project IS NULL))
+);
+
+CREATE INDEX IF NOT EXISTS quota_overrides_origin_index ON quota_overrides (origin, expires);
@@ -0,0 +1,12 @@
+CREATE TABLE IF NOT EXISTS host_post_times (
+  id uuid PRIMARY KEY NOT NULL,
+  host uuid NOT NULL,
+  instance uuid,
+  powered_on timestamptz NOT NULL,
+  netbooted timestamptz NOT NULL,
+  seconds double precision NOT NULL,
+  anomalous boolean NOT NULL,
+  CONSTRAINT host_post_times_host_fkey FOREIGN KEY (host) REFERENCES hosts (id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS host_post_times_host_index ON host_post_times (host, netbooted);
@@ -0,0 +1 @@
+ALTER TABLE hosts ADD COLUMN IF NOT EXISTS bmc_protocol jsonb NOT NULL DEFAULT '"ipmi"';
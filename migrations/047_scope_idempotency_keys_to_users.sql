@@ -0,0 +1,3 @@
+ALTER TABLE idempotency_keys ADD COLUMN IF NOT EXISTS username varchar NOT NULL DEFAULT '';
+ALTER TABLE idempotency_keys DROP CONSTRAINT IF EXISTS idempotency_keys_key_key;
+CREATE UNIQUE INDEX IF NOT EXISTS idempotency_keys_username_key_index ON idempotency_keys (username, key);
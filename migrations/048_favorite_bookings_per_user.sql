@@ -0,0 +1,11 @@
+-- favorites were shared by everyone on a booking, so hand each one to the booking's owner
+UPDATE aggregates
+SET metadata = (metadata - 'favorite') || jsonb_build_object(
+  'favorited_by',
+  CASE
+    WHEN (metadata->>'favorite')::boolean AND metadata ? 'owner' AND metadata->'owner' <> 'null'::jsonb
+      THEN jsonb_build_array(metadata->'owner')
+    ELSE '[]'::jsonb
+  END
+)
+WHERE metadata ? 'favorite';
@@ -78,6 +78,8 @@ pub async fn allocate_unreserved_hosts() {
             host_list: vec![],
             networks: vec![],
             lab_name: format!("reserved"),
+            parameters: vec![],
+            access: None,
         }),
     )
     .await
@@ -91,6 +93,7 @@ pub async fn allocate_unreserved_hosts() {
         configuration: AggregateConfiguration {
             ipmi_username: String::new(),
             ipmi_password: String::new(),
+            env: Default::default(),
         },
         deleted: false,
         users: vec![],
@@ -107,6 +110,13 @@ pub async fn allocate_unreserved_hosts() {
             project: Some("LibLaaS".to_owned()),
             start: Some(now.clone()),
             end: Some(now + Days::new(1000)),
+            performance_tolerance: None,
+            spread: None,
+            display_name: None,
+            favorited_by: Vec::new(),
+            exempt_from_expiry: false,
+            telemetry: false,
+            ownership_override: None,
         },
     };
     NewRow::new(agg).insert(&mut transaction).await.unwrap();
@@ -230,6 +240,23 @@ async fn main() {
         tracing::info!("Running LibLaaS as prod");
     }
 
+    if dev.status {
+        for injection in dev.failure_injection.iter() {
+            tascii::faults::inject(tascii::faults::InjectedFault {
+                task: injection.task.clone(),
+                mode: match injection.mode {
+                    config::FailureMode::Fail => tascii::faults::FaultMode::Fail,
+                    config::FailureMode::Hang => tascii::faults::FaultMode::Hang,
+                },
+                remaining: injection.times,
+            });
+        }
+    }
+
+    if let Err(e) = workflows::resource_management::jobs::fail_interrupted().await {
+        tracing::error!("Couldn't fail jobs interrupted by the last shutdown: {e:?}");
+    }
+
     tracing::info!("starting tascii runtime");
     let tascii_rt = start_tascii();
 
@@ -251,12 +278,78 @@ async fn main() {
         v
     });
 
+    let bh = tokio::spawn(async {
+        dal::backup::entry().await;
+        tracing::info!("backup scheduler exited");
+    });
+
+    let hh = tokio::spawn(async {
+        workflows::resource_management::health::entry().await;
+        tracing::info!("health checks exited");
+    });
+
+    let ah = tokio::spawn(async {
+        workflows::resource_management::bmc_isolation::entry().await;
+        tracing::info!("BMC audit exited");
+    });
+
+    let rh = tokio::spawn(async {
+        workflows::resource_management::reconciler::entry().await;
+        tracing::info!("reconciler exited");
+    });
+
+    let gh = tokio::spawn(async {
+        workflows::resource_management::orphans::entry().await;
+        tracing::info!("garbage collector exited");
+    });
+
+    let dh = tokio::spawn(async {
+        workflows::resource_management::idle::entry().await;
+        tracing::info!("idle booking detection exited");
+    });
+
+    let xh = tokio::spawn(async {
+        workflows::resource_management::expiry::entry().await;
+        tracing::info!("booking expiry exited");
+    });
+
+    let th = tokio::spawn(async {
+        workflows::resource_management::trash::entry().await;
+        tracing::info!("trash purging exited");
+    });
+
+    let qh = tokio::spawn(async {
+        workflows::resource_management::queue::entry().await;
+        tracing::info!("booking queue exited");
+    });
+
+    let sh = tokio::spawn(async {
+        workflows::resource_management::scheduled_start::entry().await;
+        tracing::info!("scheduled booking starts exited");
+    });
+
+    let ch = tokio::spawn(async {
+        workflows::resource_management::capacity::entry().await;
+        tracing::info!("capacity alerts exited");
+    });
+
     std::thread::sleep(Duration::from_secs(1));
 
     let l = LocalSet::new();
 
     l.spawn_local(async { mh.await });
     l.spawn_local(async { wh.await });
+    l.spawn_local(bh);
+    l.spawn_local(hh);
+    l.spawn_local(ah);
+    l.spawn_local(rh);
+    l.spawn_local(gh);
+    l.spawn_local(dh);
+    l.spawn_local(xh);
+    l.spawn_local(th);
+    l.spawn_local(qh);
+    l.spawn_local(sh);
+    l.spawn_local(ch);
 
     let (liblaas_tx, mut liblaas_rx) = mpsc::channel(5);
 